@@ -0,0 +1,29 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Pull Request descriptions can be edited by anyone, so everything spr reads
+//! from them must cope with any text, without panicking or taking forever.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use spr::message::{parse_message, take_marked_pr_stack, MessageSection};
+use spr::utils::{parse_name_list, parse_pr_stack_list};
+
+fuzz_target!(|body: &str| {
+    let numbers = parse_pr_stack_list(body);
+    assert!(numbers.len() <= spr::utils::MAX_PR_STACK_ENTRIES);
+
+    let (rest, marked) = take_marked_pr_stack(body);
+    if let Some(marked) = marked {
+        parse_pr_stack_list(&marked);
+    }
+    let sections = parse_message(&rest, MessageSection::Summary);
+    if let Some(reviewers) = sections.get(&MessageSection::Reviewers) {
+        parse_name_list(reviewers);
+    }
+});
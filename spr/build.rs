@@ -0,0 +1,66 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Bakes information about the build into the binary, for `spr version
+//! --verbose`.
+
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+fn main() {
+    // Outside of a Git checkout (e.g. when installed from crates.io) there is
+    // no commit to report.
+    let sha = match git(&["rev-parse", "HEAD"]) {
+        Some(sha) => {
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty());
+            if dirty {
+                format!("{}-dirty", sha)
+            } else {
+                sha
+            }
+        }
+        None => "unknown".to_string(),
+    };
+
+    // Honour SOURCE_DATE_EPOCH, for reproducible builds
+    let build_date = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .map(|time| time.date_naive())
+        .unwrap_or_else(|| chrono::Utc::now().date_naive());
+
+    println!("cargo:rustc-env=SPR_GIT_SHA={}", sha);
+    println!(
+        "cargo:rustc-env=SPR_BUILD_DATE={}",
+        build_date.format("%Y-%m-%d")
+    );
+    println!(
+        "cargo:rustc-env=SPR_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/index", git_dir);
+        if let Some(branch) =
+            git(&["rev-parse", "--symbolic-full-name", "HEAD"])
+        {
+            println!("cargo:rerun-if-changed={}/{}", git_dir, branch);
+        }
+    }
+}
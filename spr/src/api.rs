@@ -0,0 +1,343 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! The core operations of spr, for tools that build on spr rather than run
+//! its command line. Each takes the options it needs as plain values, and
+//! returns what it did, or an [`Error`](crate::error::Error) with its
+//! messages. The `spr` command line is a wrapper around the same code.
+//!
+//! The operations report their progress on the terminal like the `spr`
+//! commands do. Call [`set_output_silenced`](crate::output::set_output_silenced)
+//! once to turn that off, and show the results in your own way instead.
+//!
+//! ```no_run
+//! # async fn example(
+//! #     git: &spr::git::Git,
+//! #     gh: &spr::github::GitHub,
+//! #     config: &spr::config::Config,
+//! # ) -> spr::error::Result<()> {
+//! spr::output::set_output_silenced(true);
+//! for entry in spr::api::status(git, gh, config).await? {
+//!     println!("{} {:?}", entry.title, entry.pull_request.map(|pr| pr.number));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use git2::Oid;
+
+use crate::{
+    commands::{diff::DiffOptions, land, list},
+    error::Result,
+    forge::Forge,
+    github::PullRequest,
+    message::MessageSection,
+};
+
+pub use crate::commands::{
+    diff::DiffOutcome,
+    land::{LandOutcome, LandReport, LandStep},
+    list::{ListState, ListedPullRequest, ReviewDecision},
+};
+
+/// A commit of the local branch, and its Pull Request.
+#[derive(Debug, Clone)]
+pub struct StackEntry {
+    pub oid: Oid,
+    pub short_id: String,
+    pub title: String,
+    /// The Pull Request that the commit refers to, as it is on the forge, if
+    /// it refers to one
+    pub pull_request: Option<PullRequest>,
+}
+
+/// The commits of the local branch that are not on master yet (bottom of the
+/// stack first), with their Pull Requests.
+pub async fn status(
+    git: &crate::git::Git,
+    forge: &impl Forge,
+    config: &crate::config::Config,
+) -> Result<Vec<StackEntry>> {
+    let prepared_commits = git.get_prepared_commits(config)?;
+    let pull_requests = forge
+        .get_pull_requests(
+            prepared_commits.iter().map(|pc| pc.pull_request_number),
+        )
+        .await;
+
+    prepared_commits
+        .into_iter()
+        .zip(pull_requests)
+        .map(|(commit, pull_request)| {
+            Ok(StackEntry {
+                oid: commit.oid,
+                title: commit
+                    .message
+                    .get(&MessageSection::Title)
+                    .cloned()
+                    .unwrap_or_default(),
+                short_id: commit.short_id,
+                pull_request: pull_request?,
+            })
+        })
+        .collect()
+}
+
+/// What to submit, and how (see `spr diff --help` for the details).
+#[derive(Debug, Clone, Default)]
+pub struct SubmitOptions {
+    /// Submit the whole branch, not just the HEAD commit
+    pub all: bool,
+    /// Create new Pull Requests as drafts
+    pub draft: bool,
+    /// The message for the commits that update existing Pull Requests
+    pub message: Option<String>,
+    /// Update the title and description of existing Pull Requests from the
+    /// commit messages
+    pub update_message: bool,
+    /// Ask these users (or teams, with a leading '#') to review new Pull
+    /// Requests, in addition to the configured default reviewers
+    pub reviewers: Vec<String>,
+    /// Only prepare the Pull Request branches locally, without pushing them
+    /// or creating or updating any Pull Requests
+    pub no_push: bool,
+}
+
+/// Create or update the Pull Requests of the HEAD commit (or the whole
+/// branch), like `spr diff`. Besides [`SubmitOptions`], this takes all the
+/// options of the command line.
+pub async fn submit(
+    git: &crate::git::Git,
+    forge: &impl Forge,
+    config: &crate::config::Config,
+    options: impl Into<DiffOptions>,
+) -> Result<DiffOutcome> {
+    crate::commands::diff::diff(options.into(), git, forge, config).await
+}
+
+/// What to land (see `spr land --help` for the details).
+#[derive(Debug, Clone, Default)]
+pub struct LandOptions {
+    /// Land the Pull Requests of the whole branch, bottom first
+    pub all: bool,
+    /// Land a Pull Request that was submitted with `spr diff --cherry-pick`
+    pub cherry_pick: bool,
+    /// Land as many Pull Requests of the whole branch as possible, bottom
+    /// first, and report the one that could not be landed instead of failing
+    pub keep_going: bool,
+    /// Wait for the checks of each Pull Request to pass before merging it
+    pub wait_for_checks: bool,
+    /// Only work out what landing would do, and return that as the plan
+    pub plan: bool,
+}
+
+/// Merge the Pull Request of the HEAD commit (or those of the whole branch),
+/// like `spr land`. Besides [`LandOptions`], this takes all the options of
+/// the command line.
+pub async fn land(
+    git: &crate::git::Git,
+    forge: &impl Forge,
+    config: &crate::config::Config,
+    options: impl Into<land::LandOptions>,
+) -> Result<LandOutcome> {
+    land::land(options.into(), git, forge, config).await
+}
+
+/// Your Pull Requests in the repository that are in the given state, like
+/// `spr list`.
+pub async fn list(
+    graphql_client: &reqwest::Client,
+    config: &crate::config::Config,
+    state: ListState,
+) -> Result<Vec<ListedPullRequest>> {
+    list::search_pull_requests(graphql_client, config, state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::tests::config_factory,
+        forge::tests::{Call, MockForge},
+        git::tests::{create_commit, test_repo},
+        github::{tests::pull_request_factory, PullRequestState},
+    };
+
+    /// A branch with two commits on top of master, the first of which has
+    /// Pull Request #7.
+    fn stack() -> (tempfile::TempDir, crate::git::Git, Oid, Oid) {
+        let (dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        git.repo()
+            .reference("refs/remotes/origin/master", root, true, "test")
+            .unwrap();
+        let one = create_commit(
+            &git,
+            "One\n\nTest Plan: none\n\nPull Request: #7",
+            &[root],
+        );
+        let two = create_commit(&git, "Two\n\nTest Plan: none", &[one]);
+        git.repo()
+            .reference("refs/heads/master", two, true, "test")
+            .unwrap();
+
+        (dir, git, one, two)
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let config = config_factory();
+        let (_dir, git, one, two) = stack();
+        let forge = MockForge::new(
+            10,
+            vec![pull_request_factory(&config, 7, "spr/foo/one", "master")],
+        );
+
+        let entries = status(&git, &forge, &config).await.unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (
+                    entry.oid,
+                    &entry.title[..],
+                    entry.pull_request.as_ref().map(|pr| pr.number)
+                ))
+                .collect::<Vec<_>>(),
+            vec![(one, "One", Some(7)), (two, "Two", None)]
+        );
+        assert_eq!(forge.calls(), vec![Call::GetPullRequest(7)]);
+
+        // A Pull Request the forge does not know is an error
+        let forge = MockForge::new(10, Vec::new());
+        assert!(status(&git, &forge, &config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_submit_no_push() {
+        crate::output::set_output_silenced(true);
+        let config = config_factory();
+        let (_dir, git, one, _) = stack();
+        // #7 is up to date already
+        let mut pull_request =
+            pull_request_factory(&config, 7, "spr/foo/one", "master");
+        pull_request.head_oid = one;
+        pull_request.base_oid =
+            git.resolve_reference("refs/remotes/origin/master").unwrap();
+        let forge = MockForge::new(10, vec![pull_request]);
+        let options = SubmitOptions {
+            all: true,
+            no_push: true,
+            ..Default::default()
+        };
+
+        let outcome = submit(&git, &forge, &config, &options).await.unwrap();
+        // Nothing was published, but the branch of the new Pull Request is
+        // there locally
+        assert_eq!(
+            outcome,
+            DiffOutcome::Submitted {
+                pull_requests: Vec::new()
+            }
+        );
+        assert_eq!(forge.calls(), vec![Call::GetPullRequest(7)]);
+        assert!(git.resolve_reference("refs/heads/spr/foo/two").is_ok());
+
+        // With HEAD on master, there is nothing to submit
+        let master =
+            git.resolve_reference("refs/remotes/origin/master").unwrap();
+        git.repo()
+            .reference("refs/heads/master", master, true, "test")
+            .unwrap();
+        assert_eq!(
+            submit(&git, &forge, &config, &options).await.unwrap(),
+            DiffOutcome::NothingToSubmit
+        );
+    }
+
+    #[tokio::test]
+    async fn test_land() {
+        crate::output::set_output_silenced(true);
+        let config = config_factory();
+        let (_dir, git, one, two) = stack();
+        let forge = MockForge::new(
+            10,
+            vec![pull_request_factory(&config, 7, "spr/foo/one", "master")],
+        );
+
+        // The commit on top has no Pull Request to land
+        let options = LandOptions {
+            all: true,
+            plan: true,
+            ..Default::default()
+        };
+        let error = land(&git, &forge, &config, &options).await.unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &[format!(
+                "Commit {} does not refer to a Pull Request.",
+                git.prepare_commit(&config, two).unwrap().short_id
+            )]
+        );
+        assert!(forge.calls().is_empty());
+
+        git.repo()
+            .reference("refs/heads/master", one, true, "test")
+            .unwrap();
+        match land(&git, &forge, &config, &options).await.unwrap() {
+            LandOutcome::Planned(plan) => assert_eq!(
+                plan.iter()
+                    .map(|step| (step.pull_request_number, &step.title[..]))
+                    .collect::<Vec<_>>(),
+                vec![(7, "PR 7")]
+            ),
+            outcome => panic!("Expected a plan, got {:?}", outcome),
+        }
+        assert_eq!(forge.calls(), vec![Call::GetPullRequest(7)]);
+
+        // #7 was closed in the meantime, so it cannot be landed, which the
+        // report says
+        forge.pull_requests.borrow_mut().get_mut(&7).unwrap().state =
+            PullRequestState::Closed;
+        let options = LandOptions {
+            keep_going: true,
+            ..Default::default()
+        };
+        match land(&git, &forge, &config, &options).await.unwrap() {
+            LandOutcome::Landed(report) => {
+                assert!(report.landed.is_empty());
+                let (number, error) = report.blocked.unwrap();
+                assert_eq!(number, 7);
+                assert_eq!(
+                    error.messages(),
+                    &["This Pull Request is already closed!"]
+                );
+                assert!(report.not_attempted.is_empty());
+            }
+            outcome => panic!("Expected a report, got {:?}", outcome),
+        }
+        // Nothing was merged
+        assert_eq!(
+            forge.calls(),
+            vec![
+                Call::GetPullRequest(7),
+                Call::GetPullRequest(7),
+                Call::GetPullRequest(7)
+            ]
+        );
+
+        // On master, there is nothing to land
+        let master =
+            git.resolve_reference("refs/remotes/origin/master").unwrap();
+        git.repo()
+            .reference("refs/heads/master", master, true, "test")
+            .unwrap();
+        assert!(matches!(
+            land(&git, &forge, &config, &options).await.unwrap(),
+            LandOutcome::NothingToLand
+        ));
+    }
+}
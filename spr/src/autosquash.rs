@@ -0,0 +1,185 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Folding `fixup!` and `squash!` commits into the commits they are meant
+//! for before submitting, as `git rebase --autosquash` does, rather than
+//! giving each its own Pull Request (with `spr.autosquash`, or `spr diff
+//! --autosquash`). A fixup commit goes with the commit further down the stack
+//! whose title is the rest of its own title (or starts with it), or whose
+//! commit ID starts with it.
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    git::PreparedCommit,
+    message::MessageSection,
+    utils::run_command,
+};
+
+/// The prefixes of the titles of commits that `git rebase --autosquash`
+/// folds into earlier ones.
+const PREFIXES: [&str; 3] = ["fixup! ", "squash! ", "amend! "];
+
+/// What the title of a fixup commit refers to, with all `fixup!` (and
+/// `squash!`, `amend!`) prefixes taken off, or `None` if it is no fixup.
+pub fn fixup_subject(title: &str) -> Option<&str> {
+    let mut subject = title;
+    while let Some(rest) = PREFIXES
+        .iter()
+        .find_map(|prefix| subject.strip_prefix(prefix))
+    {
+        subject = rest.trim_start();
+    }
+
+    (subject.len() < title.len()).then_some(subject)
+}
+
+fn title(commit: &PreparedCommit) -> &str {
+    commit
+        .message
+        .get(&MessageSection::Title)
+        .map(|title| &title[..])
+        .unwrap_or_default()
+}
+
+/// Whether the commit with the given title and ID is the one that `subject`
+/// refers to.
+fn is_target(subject: &str, title: &str, oid: git2::Oid) -> bool {
+    let is_hex =
+        subject.len() >= 4 && subject.chars().all(|c| c.is_ascii_hexdigit());
+
+    title == subject
+        || (is_hex && oid.to_string().starts_with(&subject.to_lowercase()))
+        || title.starts_with(subject)
+}
+
+/// For each fixup commit among `commits` (bottom of the stack first), its
+/// index and that of the commit it goes into. Fails if a fixup commit has no
+/// such commit below it in the stack.
+pub fn autosquash_targets(
+    commits: &[PreparedCommit],
+) -> Result<Vec<(usize, usize)>> {
+    let mut targets = Vec::new();
+    for (index, commit) in commits.iter().enumerate() {
+        let subject = match fixup_subject(title(commit)) {
+            Some(subject) => subject,
+            None => continue,
+        };
+
+        // An exact match of the title first, as Git does
+        let below = &commits[..index];
+        let target = below
+            .iter()
+            .position(|other| {
+                fixup_subject(title(other)).is_none() && title(other) == subject
+            })
+            .or_else(|| {
+                below.iter().position(|other| {
+                    fixup_subject(title(other)).is_none()
+                        && is_target(subject, title(other), other.oid)
+                })
+            })
+            .ok_or_else(|| {
+                Error::new(format!(
+                    "Commit {} ('{}') is meant to be squashed into a commit \
+                     that is not below it in the stack",
+                    commit.short_id,
+                    title(commit)
+                ))
+            })?;
+        targets.push((index, target));
+    }
+
+    Ok(targets)
+}
+
+/// Fold the fixup commits on top of `base` into their targets, with `git
+/// rebase --autosquash`. If that fails (because of conflicts, say), the
+/// rebase is aborted, leaving the branch as it was.
+pub async fn autosquash(base: git2::Oid) -> Result<()> {
+    let mut cmd = tokio::process::Command::new("git");
+    cmd.env("GIT_SEQUENCE_EDITOR", "true")
+        .env("GIT_EDITOR", "true")
+        .arg("rebase")
+        .arg("--quiet")
+        .arg("--interactive")
+        .arg("--autosquash")
+        .arg(format!("{}", base));
+    if let Err(error) = run_command(&mut cmd).await {
+        run_command(
+            tokio::process::Command::new("git")
+                .arg("rebase")
+                .arg("--abort"),
+        )
+        .await?;
+        return Err(error).reword(
+            "The fixup commits could not be squashed into their targets, \
+             probably because of conflicts with the commits between them. \
+             Nothing was changed."
+                .to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::prepared_commit_factory;
+
+    fn commits(titles: &[&str]) -> Vec<PreparedCommit> {
+        titles
+            .iter()
+            .enumerate()
+            .map(|(index, title)| {
+                let mut commit =
+                    prepared_commit_factory(&index.to_string(), title);
+                commit.oid =
+                    git2::Oid::from_bytes(&[0xa0 + index as u8; 20]).unwrap();
+                commit
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fixup_subject() {
+        assert_eq!(fixup_subject("fixup! Add thing"), Some("Add thing"));
+        assert_eq!(fixup_subject("squash! Add thing"), Some("Add thing"));
+        assert_eq!(fixup_subject("fixup! fixup! Add thing"), Some("Add thing"));
+        assert_eq!(fixup_subject("amend! Add thing"), Some("Add thing"));
+        assert_eq!(fixup_subject("Add thing"), None);
+        assert_eq!(fixup_subject("Fix fixup! handling"), None);
+    }
+
+    #[test]
+    fn test_autosquash_targets() {
+        let stack = commits(&[
+            "Add parser",
+            "Add parser tests",
+            "fixup! Add parser",
+            "squash! Add parser tests",
+            "fixup! Add pars",
+            "fixup! a1a1a1a1",
+            "fixup! fixup! Add parser tests",
+        ]);
+
+        assert_eq!(
+            autosquash_targets(&stack).unwrap(),
+            vec![(2, 0), (3, 1), (4, 0), (5, 1), (6, 1)]
+        );
+        assert!(autosquash_targets(&commits(&["One", "Two"]))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_autosquash_targets_outside_stack() {
+        // The target is not in the stack, or only on top of the fixup
+        assert!(autosquash_targets(&commits(&["One", "fixup! Zero"])).is_err());
+        assert!(autosquash_targets(&commits(&["fixup! One", "One"])).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::error::{Error, Result};
+
+/// Where GitHub looks for the CODEOWNERS file, relative to the root of the
+/// repository, in the order it looks.
+pub const CODEOWNERS_FILES: [&str; 3] =
+    [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// The owners of the files in the repository, going by its CODEOWNERS file.
+pub struct CodeOwners {
+    /// Each line of the file: the paths it is about, and their owners
+    rules: Vec<(Gitignore, Vec<String>)>,
+}
+
+impl CodeOwners {
+    /// Build the code owners from the contents of a CODEOWNERS file.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let pattern = match words.next() {
+                Some(pattern) => pattern,
+                None => continue,
+            };
+            let owners = words
+                .take_while(|word| !word.starts_with('#'))
+                .map(String::from)
+                .collect();
+
+            let mut builder = GitignoreBuilder::new("");
+            builder.add_line(None, pattern).map_err(|error| {
+                Error::new(format!("Invalid line in CODEOWNERS: {}", error))
+            })?;
+            rules.push((builder.build()?, owners));
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Load the CODEOWNERS file from the working directory of the repository,
+    /// if there is one.
+    pub fn load(git: &crate::git::Git) -> Result<Option<Self>> {
+        let repo = git.repo();
+        let workdir = match repo.workdir() {
+            Some(workdir) => workdir,
+            None => return Ok(None),
+        };
+
+        for file in CODEOWNERS_FILES {
+            match std::fs::read_to_string(workdir.join(file)) {
+                Ok(content) => return Self::parse(&content).map(Some),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The owners of the file at the given path (relative to the root of the
+    /// repository). As on GitHub, the last line that matches it counts.
+    pub fn owners_of(&self, path: &str) -> &[String] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(gitignore, _)| {
+                gitignore
+                    .matched_path_or_any_parents(path, false)
+                    .is_ignore()
+            })
+            .map(|(_, owners)| &owners[..])
+            .unwrap_or_default()
+    }
+
+    /// The owners of any of the given files, as reviewers to give spr: users
+    /// by their login, and teams as `#slug`. Owners given by email address are
+    /// left out, since spr cannot request reviews from them.
+    pub fn reviewers<'a>(
+        &self,
+        paths: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<String> {
+        let mut reviewers = Vec::new();
+        for path in paths {
+            for owner in self.owners_of(path) {
+                let reviewer = match owner.strip_prefix('@') {
+                    Some(owner) => match owner.split_once('/') {
+                        Some((_, team)) => format!("#{}", team),
+                        None => owner.to_string(),
+                    },
+                    None => continue,
+                };
+                if !reviewers.contains(&reviewer) {
+                    reviewers.push(reviewer);
+                }
+            }
+        }
+
+        reviewers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_owners() -> CodeOwners {
+        CodeOwners::parse(indoc::indoc!(
+            "
+            # Everything else
+            *           @alice
+            /docs/      @bob @acme/writers   # the docs team
+            *.rs        @carol
+            /vendor/
+            legal.md    lawyer@example.com
+            "
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_owners_of() {
+        let code_owners = code_owners();
+
+        assert_eq!(code_owners.owners_of("README.md"), ["@alice"]);
+        assert_eq!(
+            code_owners.owners_of("docs/guide.md"),
+            ["@bob", "@acme/writers"]
+        );
+        assert_eq!(code_owners.owners_of("docs/example.rs"), ["@carol"]);
+        assert!(code_owners.owners_of("vendor/lib/lib.rs").is_empty());
+    }
+
+    #[test]
+    fn test_reviewers() {
+        let code_owners = code_owners();
+
+        assert_eq!(
+            code_owners.reviewers([
+                "docs/guide.md",
+                "src/main.rs",
+                "docs/faq.md",
+                "legal.md",
+            ]),
+            vec!["bob", "#writers", "carol"]
+        );
+        assert!(code_owners.reviewers([]).is_empty());
+    }
+
+    #[test]
+    fn test_load() {
+        let (dir, git) = crate::git::tests::test_repo();
+        assert!(CodeOwners::load(&git).unwrap().is_none());
+
+        std::fs::write(dir.path().join("CODEOWNERS"), "* @alice\n").unwrap();
+        std::fs::create_dir(dir.path().join(".github")).unwrap();
+        std::fs::write(dir.path().join(".github/CODEOWNERS"), "* @bob\n")
+            .unwrap();
+        let code_owners = CodeOwners::load(&git).unwrap().unwrap();
+        assert_eq!(code_owners.owners_of("src/lib.rs"), ["@bob"]);
+    }
+}
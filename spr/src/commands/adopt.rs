@@ -0,0 +1,218 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use indoc::formatdoc;
+
+use crate::{
+    config::PullRequestTracking,
+    error::{Error, Result},
+    forge::Forge,
+    github::{PullRequestState, PullRequestUpdate},
+    message::{build_github_body, MessageSection, MessageSectionsMap},
+    output::{output, write_commit_title},
+    utils::get_pr_stack,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct AdoptOptions {
+    /// Number of the existing Pull Request to associate with the HEAD commit
+    pull_request: u64,
+}
+
+pub async fn adopt(
+    opts: AdoptOptions,
+    git: &crate::git::Git,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let mut prepared_commits = git.get_prepared_commits(config)?;
+
+    let master_base_oid = match prepared_commits.first() {
+        Some(commit) => commit.parent_oid,
+        None => {
+            output("👋", "Branch is empty - nothing to do. Good bye!")?;
+            return Ok(());
+        }
+    };
+    let prepared_commit = prepared_commits.last_mut().unwrap();
+
+    write_commit_title(prepared_commit)?;
+
+    let pull_request = gh.get_pull_request(opts.pull_request).await?;
+    if pull_request.state != PullRequestState::Open {
+        return Err(Error::new(formatdoc!(
+            "Pull Request #{} is closed, so it cannot be adopted.",
+            pull_request.number
+        )));
+    }
+
+    match config.pull_request_tracking {
+        PullRequestTracking::Trailer => attach_pull_request(
+            &mut prepared_commit.message,
+            config,
+            pull_request.number,
+        )?,
+        PullRequestTracking::Notes => {
+            // The note moves over to the commit when it is rewritten below
+            if let Some(existing) = prepared_commit
+                .pull_request_number
+                .filter(|&existing| existing != pull_request.number)
+            {
+                return Err(Error::new(format!(
+                    "This commit already belongs to Pull Request #{}.",
+                    existing
+                )));
+            }
+            git.set_pull_request_note(
+                config,
+                prepared_commit.oid,
+                pull_request.number,
+            )?;
+        }
+    }
+
+    let pr_stack = get_pr_stack(
+        git,
+        config,
+        pull_request.number,
+        prepared_commit.oid,
+        prepared_commit.parent_oid,
+        false,
+        prepared_commit.parent_oid == master_base_oid,
+    )?;
+    prepared_commit
+        .message
+        .insert(MessageSection::PRStack, pr_stack.clone());
+
+    let body =
+        adopted_pull_request_body(&pull_request.sections, pr_stack, config);
+    if pull_request.body.as_ref() != Some(&body) {
+        gh.update_pull_request(
+            pull_request.number,
+            &PullRequestUpdate {
+                body: Some(body),
+                ..Default::default()
+            },
+        )
+        .await?;
+    }
+
+    git.rewrite_single_commit_message(prepared_commit, None)?;
+
+    output(
+        "🤝",
+        &format!(
+            "Adopted Pull Request #{}: {}",
+            pull_request.number,
+            config.pull_request_url(pull_request.number)
+        ),
+    )?;
+
+    Ok(())
+}
+
+/// Add the "Pull Request" section to a commit message that is not yet linked
+/// to a Pull Request.
+fn attach_pull_request(
+    message: &mut MessageSectionsMap,
+    config: &crate::config::Config,
+    number: u64,
+) -> Result<()> {
+    let existing = message
+        .get(&MessageSection::PullRequest)
+        .and_then(|text| config.parse_pull_request_field(text));
+
+    match existing {
+        Some(existing) if existing != number => Err(Error::new(format!(
+            "This commit already belongs to Pull Request #{}.",
+            existing
+        ))),
+        _ => {
+            message.insert(
+                MessageSection::PullRequest,
+                config.pull_request_url(number),
+            );
+            Ok(())
+        }
+    }
+}
+
+/// The body of an adopted Pull Request: its existing description with the PR
+/// stack section added (or replaced).
+fn adopted_pull_request_body(
+    pull_request_sections: &MessageSectionsMap,
+    pr_stack: String,
+    config: &crate::config::Config,
+) -> String {
+    let mut sections = pull_request_sections.clone();
+    sections.insert(MessageSection::PRStack, pr_stack);
+    build_github_body(&sections, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::message::{build_pr_stack_message, parse_message};
+
+    #[test]
+    fn test_attach_pull_request() {
+        let config = config_factory();
+        let mut message =
+            parse_message("Add feature\n\nIt's great", MessageSection::Title);
+
+        attach_pull_request(&mut message, &config, 42).unwrap();
+
+        assert_eq!(
+            message.get(&MessageSection::PullRequest).unwrap(),
+            "https://github.com/acme/codez/pull/42"
+        );
+        assert_eq!(
+            config.parse_pull_request_field(
+                message.get(&MessageSection::PullRequest).unwrap()
+            ),
+            Some(42)
+        );
+    }
+
+    #[test]
+    fn test_attach_pull_request_already_attached() {
+        let config = config_factory();
+        let mut message = parse_message(
+            "Add feature\n\nPull Request: #7",
+            MessageSection::Title,
+        );
+
+        assert!(attach_pull_request(&mut message, &config, 42).is_err());
+        assert!(attach_pull_request(&mut message, &config, 7).is_ok());
+    }
+
+    #[test]
+    fn test_adopted_pull_request_body() {
+        let sections = parse_message(
+            "Some description\n\nwritten on GitHub",
+            MessageSection::Summary,
+        );
+
+        assert_eq!(
+            adopted_pull_request_body(
+                &sections,
+                build_pr_stack_message(
+                    &[42, 41],
+                    &Default::default(),
+                    &config_factory()
+                ),
+                &config_factory(),
+            ),
+            "Some description\n\nwritten on GitHub\n\
+             \n\
+             PR Stack:\n\
+             https://github.com/acme/codez/pull/42 <-- (current PR)\n\
+             https://github.com/acme/codez/pull/41\n\n"
+        );
+    }
+}
@@ -5,10 +5,12 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+use std::iter::zip;
+
 use crate::{
     error::{Error, Result},
-    git::PreparedCommit,
-    message::validate_commit_message,
+    forge::Forge,
+    message::{restore_stripped_trailers, validate_commit_message},
     output::{output, write_commit_title},
 };
 
@@ -41,25 +43,20 @@ pub async fn amend(
     };
 
     // Request the Pull Request information for each commit (well, those that
-    // declare to have Pull Requests). This list is in reverse order, so that
-    // below we can pop from the vector as we iterate.
-    let mut pull_requests: Vec<_> = slice
-        .iter()
-        .rev()
-        .map(|pc: &PreparedCommit| {
-            pc.pull_request_number
-                .map(|number| tokio::spawn(gh.clone().get_pull_request(number)))
-        })
-        .collect();
+    // declare to have Pull Requests).
+    let pull_requests = gh
+        .get_pull_requests(slice.iter().map(|pc| pc.pull_request_number))
+        .await;
 
     let mut failure = false;
 
-    for commit in slice.iter_mut() {
+    for (commit, pull_request) in zip(slice.iter_mut(), pull_requests) {
         write_commit_title(commit)?;
-        let pull_request = pull_requests.pop().flatten();
-        if let Some(pull_request) = pull_request {
-            let pull_request = pull_request.await??;
-            commit.message = pull_request.sections;
+        if let Some(pull_request) = pull_request? {
+            let mut message = pull_request.sections;
+            restore_stripped_trailers(&commit.message, &mut message, config);
+            crate::pr_config::restore_block(&commit.message, &mut message);
+            commit.message = message;
         }
         failure = validate_commit_message(&commit.message, config).is_err()
             || failure;
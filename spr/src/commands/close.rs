@@ -11,6 +11,7 @@ use indoc::formatdoc;
 
 use crate::{
     error::{add_error, Error, Result},
+    forge::Forge,
     git::PreparedCommit,
     github::{PullRequestState, PullRequestUpdate},
     message::MessageSection,
@@ -56,7 +57,7 @@ pub async fn close(
         // This makes it easier to run the code to update the local commit message
         // with all the changes that the implementation makes at the end, even if
         // the implementation encounters an error or exits early.
-        result = close_impl(gh, config, prepared_commit).await;
+        result = close_impl(git, gh, config, prepared_commit).await;
     }
 
     // This updates the commit message in the local Git repository (if it was
@@ -70,6 +71,7 @@ pub async fn close(
 }
 
 async fn close_impl(
+    git: &crate::git::Git,
     gh: &mut crate::github::GitHub,
     config: &crate::config::Config,
     prepared_commit: &mut PreparedCommit,
@@ -85,7 +87,7 @@ async fn close_impl(
         };
 
     // Load Pull Request information
-    let pull_request = gh.clone().get_pull_request(pull_request_number).await?;
+    let pull_request = gh.get_pull_request(pull_request_number).await?;
 
     if pull_request.state != PullRequestState::Open {
         return Err(Error::new(formatdoc!(
@@ -121,6 +123,7 @@ async fn close_impl(
     // Remove sections from commit that are not relevant after closing.
     prepared_commit.message.remove(&MessageSection::PullRequest);
     prepared_commit.message.remove(&MessageSection::ReviewedBy);
+    git.remove_pull_request_note(prepared_commit.oid)?;
 
     let mut remove_old_branch_child_process =
         tokio::process::Command::new("git")
@@ -0,0 +1,101 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io::Read;
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    github::IssueComment,
+    output::output,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct CommentOptions {
+    /// Number of the Pull Request to comment on
+    pull_request: u64,
+
+    /// Text of the comment, or `-` to read it from standard input
+    body: String,
+}
+
+/// The comment to post, with the text given on the command line, or read from
+/// `stdin` if that is `-`.
+fn build_comment(body: &str, mut stdin: impl Read) -> Result<IssueComment> {
+    let body = if body == "-" {
+        let mut text = String::new();
+        stdin
+            .read_to_string(&mut text)
+            .reword("Could not read the comment from stdin".to_string())?;
+        text
+    } else {
+        body.to_string()
+    };
+
+    if body.trim().is_empty() {
+        return Err(Error::new("The comment is empty - nothing to post"));
+    }
+
+    Ok(IssueComment { body })
+}
+
+pub async fn comment(
+    opts: CommentOptions,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let comment = build_comment(&opts.body, std::io::stdin())?;
+    gh.add_comment(opts.pull_request, &comment).await?;
+
+    output(
+        "💬",
+        &format!(
+            "Commented on Pull Request #{}: {}",
+            opts.pull_request,
+            config.pull_request_url(opts.pull_request)
+        ),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(comment: &IssueComment) -> serde_json::Value {
+        serde_json::to_value(comment).unwrap()
+    }
+
+    #[test]
+    fn test_comment_from_argument() {
+        let comment =
+            build_comment("LGTM, thanks!", "ignored".as_bytes()).unwrap();
+
+        assert_eq!(
+            payload(&comment),
+            serde_json::json!({ "body": "LGTM, thanks!" })
+        );
+    }
+
+    #[test]
+    fn test_comment_from_stdin() {
+        let comment =
+            build_comment("-", "Rebased on main.\n\n- fixed lint\n".as_bytes())
+                .unwrap();
+
+        assert_eq!(
+            payload(&comment),
+            serde_json::json!({ "body": "Rebased on main.\n\n- fixed lint\n" })
+        );
+    }
+
+    #[test]
+    fn test_empty_comment() {
+        assert!(build_comment("", "".as_bytes()).is_err());
+        assert!(build_comment("-", " \n".as_bytes()).is_err());
+    }
+}
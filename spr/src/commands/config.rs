@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::{
+    config::ProfileConfig,
+    error::{Error, Result, ResultExt},
+    output::output,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct ConfigOptions {
+    #[clap(subcommand)]
+    action: ConfigAction,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ConfigAction {
+    /// Print the value of a setting (e.g. `branchPrefix` or
+    /// `spr.branchPrefix`)
+    Get { key: String },
+
+    /// Change a setting, after checking that spr understands the value. For
+    /// settings that can be given multiple times, this adds a value.
+    Set {
+        key: String,
+        value: String,
+
+        /// Write to your global Git configuration, instead of the one of
+        /// the repository
+        #[clap(long)]
+        global: bool,
+    },
+
+    /// List the settings spr knows about, and their values
+    List,
+}
+
+/// What values a setting takes.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Bool,
+    Integer,
+    Text,
+    /// Given multiple times, each value checked with the function
+    Multi(fn(&str) -> Result<()>),
+    /// Checked with the function
+    Checked(fn(&str) -> Result<()>),
+}
+
+fn parses<T>(value: &str) -> Result<()>
+where
+    T: std::str::FromStr<Err = Error>,
+{
+    value.parse::<T>().map(|_| ())
+}
+
+fn regex(value: &str) -> Result<()> {
+    lazy_regex::Regex::new(value)
+        .map(|_| ())
+        .map_err(|error| Error::new(error.to_string()))
+}
+
+fn watch_events(value: &str) -> Result<()> {
+    crate::utils::parse_name_list(value)
+        .iter()
+        .try_for_each(|event| parses::<crate::config::WatchEvent>(event))
+}
+
+/// Git does not send push options with line breaks.
+fn push_option(value: &str) -> Result<()> {
+    if value.contains('\n') {
+        return Err(Error::new("must not contain line breaks"));
+    }
+    Ok(())
+}
+
+fn denylist_pattern(value: &str) -> Result<()> {
+    crate::path_filter::PathFilter::from_patterns([value], "spr.submitDenylist")
+        .map(|_| ())
+}
+
+/// The settings spr reads, without the `spr.` in front, and what values they
+/// take.
+const SETTINGS: &[(&str, Kind)] = &[
+    ("assignSelf", Kind::Bool),
+    ("assignees", Kind::Text),
+    ("autosquash", Kind::Bool),
+    (
+        "baseMismatch",
+        Kind::Checked(parses::<crate::config::BaseMismatch>),
+    ),
+    (
+        "branchDateFormat",
+        Kind::Checked(crate::config::validate_branch_date_format),
+    ),
+    ("branchPrefix", Kind::Text),
+    ("branchSlugKeepDots", Kind::Bool),
+    ("branchSlugStripPattern", Kind::Checked(regex)),
+    ("checksPollInterval", Kind::Integer),
+    ("checksTimeout", Kind::Integer),
+    ("cherryPickCoAuthor", Kind::Bool),
+    ("commitHook", Kind::Text),
+    ("concurrency", Kind::Integer),
+    ("confirmForcePush", Kind::Bool),
+    ("connectTimeout", Kind::Integer),
+    ("defaultReviewers", Kind::Text),
+    ("dependsOnLine", Kind::Bool),
+    (
+        "emptyCommits",
+        Kind::Checked(parses::<crate::config::EmptyCommits>),
+    ),
+    (
+        "emptySlugFallback",
+        Kind::Checked(parses::<crate::config::EmptySlugFallback>),
+    ),
+    ("footerKeepMerged", Kind::Bool),
+    ("githubAppId", Kind::Integer),
+    ("githubAppInstallationId", Kind::Integer),
+    ("githubAppPrivateKey", Kind::Text),
+    ("githubAuthToken", Kind::Text),
+    ("githubHost", Kind::Text),
+    ("githubMasterBranch", Kind::Text),
+    ("githubRemoteName", Kind::Text),
+    ("githubRepository", Kind::Text),
+    ("hideSinglePrFooter", Kind::Bool),
+    (
+        "landAuthor",
+        Kind::Checked(parses::<crate::config::LandAuthor>),
+    ),
+    ("landDeleteLocalBranches", Kind::Bool),
+    ("landDeleteRemoteBranches", Kind::Bool),
+    (
+        "landMessageTemplate",
+        Kind::Checked(crate::commands::land::validate_land_message_template),
+    ),
+    ("landSquashTitleFromPr", Kind::Bool),
+    (
+        "landTrailer",
+        Kind::Multi(crate::commands::land::validate_land_trailer),
+    ),
+    (
+        "minimumGitVersion",
+        Kind::Checked(parses::<crate::git::GitVersion>),
+    ),
+    ("normalizeTypography", Kind::Bool),
+    ("notesRef", Kind::Text),
+    ("prStackCommentMarkers", Kind::Bool),
+    ("prStackCurrentMarker", Kind::Text),
+    ("prStackDiffstat", Kind::Bool),
+    ("prStackHeading", Kind::Text),
+    ("prStackLimit", Kind::Integer),
+    ("profile", Kind::Text),
+    ("project", Kind::Text),
+    (
+        "pullRequestTracking",
+        Kind::Checked(parses::<crate::config::PullRequestTracking>),
+    ),
+    ("pushOption", Kind::Multi(push_option)),
+    ("refreshPrStacks", Kind::Bool),
+    ("requestTimeout", Kind::Integer),
+    ("requireApproval", Kind::Bool),
+    ("requireTestPlan", Kind::Bool),
+    (
+        "reviewerAlias",
+        Kind::Multi(parses::<crate::utils::ReviewerAlias>),
+    ),
+    ("reviewerPool", Kind::Text),
+    ("reviewerPoolCount", Kind::Integer),
+    ("reviewerRequired", Kind::Integer),
+    (
+        "reviewerStrategy",
+        Kind::Checked(parses::<crate::config::ReviewerStrategy>),
+    ),
+    ("setUpstream", Kind::Bool),
+    ("showPlan", Kind::Bool),
+    ("stripTitleEmoji", Kind::Bool),
+    ("stripTrailers", Kind::Text),
+    ("submitDenylist", Kind::Multi(denylist_pattern)),
+    ("syncBranchNames", Kind::Bool),
+    ("titleLengthLimit", Kind::Integer),
+    (
+        "trailerSection",
+        Kind::Multi(parses::<crate::message::TrailerSection>),
+    ),
+    ("userAgent", Kind::Text),
+    ("watchEvents", Kind::Checked(watch_events)),
+    ("watchInterval", Kind::Integer),
+];
+
+/// Settings whose values `spr config list` does not show.
+const SECRETS: [&str; 1] = ["githubAuthToken"];
+
+/// The full name (`spr.xyz`) and the kind of the setting with the given name,
+/// which may leave out the `spr.`, and, like Git, ignores case.
+fn find_setting(key: &str) -> Result<(String, Kind)> {
+    let name = key.strip_prefix("spr.").unwrap_or(key);
+    SETTINGS
+        .iter()
+        .find(|(setting, _)| setting.eq_ignore_ascii_case(name))
+        .map(|(setting, kind)| (format!("spr.{}", setting), *kind))
+        .ok_or_else(|| {
+            Error::new(format!(
+                "Unknown setting '{}' (see `spr config list` for the ones spr \
+                 knows)",
+                key
+            ))
+        })
+}
+
+/// Check that `value` is a valid value for the setting.
+fn validate(name: &str, kind: Kind, value: &str) -> Result<()> {
+    let result = match kind {
+        Kind::Bool => git2::Config::parse_bool(value)
+            .map(|_| ())
+            .map_err(|_| Error::new("must be true or false")),
+        Kind::Integer => git2::Config::parse_i64(value)
+            .map(|_| ())
+            .map_err(|_| Error::new("must be a whole number")),
+        Kind::Text => Ok(()),
+        Kind::Multi(check) | Kind::Checked(check) => check(value),
+    };
+
+    result.context(format!("Invalid value for {}: '{}'", name, value))
+}
+
+/// The values of the setting, as spr reads them (taking the profile into
+/// account). Empty if the setting is not set.
+fn get_values(
+    git_config: &ProfileConfig,
+    name: &str,
+    kind: Kind,
+) -> Result<Vec<String>> {
+    match kind {
+        Kind::Multi(_) => git_config.get_multivar(name),
+        _ => match git_config.get_string(name) {
+            Ok(value) => Ok(vec![value]),
+            Err(error) if error.code() == git2::ErrorCode::NotFound => {
+                Ok(Vec::new())
+            }
+            Err(error) => Err(error.into()),
+        },
+    }
+}
+
+/// Change the setting in `target`, after checking the value.
+fn set_value(
+    target: &mut git2::Config,
+    key: &str,
+    value: &str,
+) -> Result<String> {
+    let (name, kind) = find_setting(key)?;
+    validate(&name, kind, value)?;
+    match kind {
+        // Like `git config --add`: a regular expression that never matches
+        // keeps the existing values
+        Kind::Multi(_) => target.set_multivar(&name, "^$a", value)?,
+        _ => target.set_str(&name, value)?,
+    }
+
+    Ok(name)
+}
+
+/// The lines `spr config list` prints.
+fn list_lines(git_config: &ProfileConfig) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for (setting, kind) in SETTINGS {
+        let name = format!("spr.{}", setting);
+        let values = get_values(git_config, &name, *kind)?;
+        if values.is_empty() {
+            lines.push(format!("{} (not set)", name));
+        } else if SECRETS.contains(setting) {
+            lines.push(format!("{} = (hidden)", name));
+        } else {
+            lines.extend(
+                values.iter().map(|value| format!("{} = {}", name, value)),
+            );
+        }
+    }
+
+    Ok(lines)
+}
+
+pub fn config(
+    opts: ConfigOptions,
+    repo: &git2::Repository,
+    explicit_path: Option<&std::path::Path>,
+    git_config: &ProfileConfig,
+) -> Result<()> {
+    let term = console::Term::stdout();
+    match opts.action {
+        ConfigAction::Get { key } => {
+            let (name, kind) = find_setting(&key)?;
+            let values = get_values(git_config, &name, kind)?;
+            if values.is_empty() {
+                return Err(Error::new(format!("{} is not set", name)));
+            }
+            for value in values {
+                term.write_line(&value)?;
+            }
+        }
+        ConfigAction::Set { key, value, global } => {
+            let mut target = match (explicit_path, global) {
+                (Some(path), _) => git2::Config::open(path)?,
+                (None, true) => {
+                    repo.config()?.open_level(git2::ConfigLevel::Global)?
+                }
+                (None, false) => {
+                    repo.config()?.open_level(git2::ConfigLevel::Local)?
+                }
+            };
+            let name = set_value(&mut target, &key, &value)?;
+            output("✅", &format!("Set {} to '{}'", name, value))?;
+        }
+        ConfigAction::List => {
+            for line in list_lines(git_config)? {
+                term.write_line(&line)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_file() -> (tempfile::TempDir, git2::Config) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        std::fs::write(&path, "").unwrap();
+        let git_config = git2::Config::open(&path).unwrap();
+        (dir, git_config)
+    }
+
+    /// The settings in the file as spr reads them.
+    fn read(dir: &tempfile::TempDir) -> ProfileConfig {
+        let git_config = git2::Config::open(&dir.path().join("config"));
+        ProfileConfig::new(git_config.unwrap(), None).unwrap()
+    }
+
+    #[test]
+    fn test_find_setting() {
+        assert_eq!(find_setting("branchPrefix").unwrap().0, "spr.branchPrefix");
+        assert_eq!(
+            find_setting("spr.BRANCHPREFIX").unwrap().0,
+            "spr.branchPrefix"
+        );
+        assert!(find_setting("branchPrefx").is_err());
+    }
+
+    #[test]
+    fn test_set_validates() {
+        let (dir, mut git_config) = config_file();
+
+        for (key, value) in [
+            ("requireApproval", "maybe"),
+            ("concurrency", "lots"),
+            ("emptyCommits", "sometimes"),
+            ("landAuthor", "me"),
+            ("branchDateFormat", "%H:%M"),
+            ("branchSlugStripPattern", "[unclosed"),
+            ("watchEvents", "approved, teatime"),
+            ("reviewerAlias", "no equals sign"),
+            ("noSuchSetting", "true"),
+        ] {
+            assert!(set_value(&mut git_config, key, value).is_err(), "{}", key);
+        }
+        assert!(list_lines(&read(&dir))
+            .unwrap()
+            .iter()
+            .all(|line| line.ends_with("(not set)")));
+    }
+
+    #[test]
+    fn test_get_reflects_set() {
+        let (dir, mut git_config) = config_file();
+
+        set_value(&mut git_config, "spr.requireApproval", "true").unwrap();
+        set_value(&mut git_config, "emptyCommits", "skip").unwrap();
+        set_value(&mut git_config, "emptyCommits", "error").unwrap();
+        set_value(&mut git_config, "reviewerAlias", "core=alice,bob").unwrap();
+        set_value(&mut git_config, "reviewerAlias", "web=#frontend").unwrap();
+        set_value(&mut git_config, "githubAuthToken", "secret").unwrap();
+
+        let values = |key| {
+            let (name, kind) = find_setting(key).unwrap();
+            get_values(&read(&dir), &name, kind).unwrap()
+        };
+        assert_eq!(values("requireApproval"), vec!["true"]);
+        // A later value replaces the earlier one...
+        assert_eq!(values("emptyCommits"), vec!["error"]);
+        // ...unless the setting can be given multiple times
+        assert_eq!(
+            values("reviewerAlias"),
+            vec!["core=alice,bob", "web=#frontend"]
+        );
+        assert!(values("branchPrefix").is_empty());
+
+        let lines = list_lines(&read(&dir)).unwrap();
+        assert!(lines.contains(&"spr.emptyCommits = error".to_string()));
+        assert!(lines.contains(&"spr.githubAuthToken = (hidden)".to_string()));
+        assert!(lines.contains(&"spr.branchPrefix (not set)".to_string()));
+    }
+}
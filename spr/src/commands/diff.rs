@@ -5,25 +5,54 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use std::iter::zip;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    iter::zip,
+};
 
 use crate::{
-    error::{Error, Result, ResultExt},
-    git::PreparedCommit,
+    autosquash,
+    code_owners::CodeOwners,
+    commands::{
+        fix_footers::refresh_pr_stacks,
+        rename_branches::{rename_branch, retitled_branch, BranchRename},
+    },
+    commit_group,
+    config::{
+        BaseMismatch, EmptyCommits, PullRequestTracking, ReviewerStrategy,
+    },
+    error::{add_error, Error, Result, ResultExt},
+    forge::Forge,
+    git::{find_pull_request_commit, PreparedCommit},
     github::{
-        GitHub, PullRequest, PullRequestRequestReviewers, PullRequestState,
-        PullRequestUpdate,
+        CommitIdentity, GitHub, GitHubBranch, Project, PullRequest,
+        PullRequestRequestReviewers, PullRequestState, PullRequestUpdate,
     },
+    interrupt,
     message::{
-         validate_commit_message, MessageSection,
+        build_message, check_title_length, is_work_in_progress,
+        validate_commit_message, with_description,
+        with_pull_request_description, CleanupMode, MessageCleanup,
+        MessageSection, MessageSectionsMap,
+    },
+    output::{output, output_diff, write_commit_title},
+    path_filter::PathFilter,
+    pr_config,
+    push_cache::PushCache,
+    rate_limit::{check_rate_limit, ApiOperation},
+    reviewer_rotation,
+    timings::{self, Phase},
+    upstream::{self, Upstream},
+    utils::{
+        dedup_names, expand_reviewer_aliases, get_pr_stack, parse_name_list,
+        remove_all_parens, ReviewerAlias,
     },
-    output::{output, write_commit_title},
-    utils::{get_pr_stack, parse_name_list, remove_all_parens, run_command},
 };
 use git2::Oid;
 use indoc::{formatdoc, indoc};
 
-#[derive(Debug, clap::Parser)]
+#[derive(Debug, Default, clap::Parser)]
 pub struct DiffOptions {
     /// Create/update pull requests for the whole branch, not just the HEAD commit
     #[clap(long, short = 'a')]
@@ -34,6 +63,12 @@ pub struct DiffOptions {
     #[clap(long)]
     update_message: bool,
 
+    /// Also request review from the reviewers in the commit message, and add
+    /// the labels of its ```spr block, when updating an existing Pull Request
+    /// (rather than only when creating one)
+    #[clap(long)]
+    update_reviewers_and_labels: bool,
+
     /// Submit any new Pull Request as a draft
     #[clap(long)]
     draft: bool,
@@ -47,668 +82,5601 @@ pub struct DiffOptions {
     /// on any intermediate changes between the master branch and this commit.
     #[clap(long)]
     cherry_pick: bool,
-}
 
-pub async fn diff(
-    opts: DiffOptions,
-    git: &crate::git::Git,
-    gh: &mut crate::github::GitHub,
-    config: &crate::config::Config,
-) -> Result<()> {
-    // Abort right here if the local Git repository is not clean
-    git.check_no_uncommitted_changes()?;
+    /// Submit this commit as a standalone Pull Request: based on master, as
+    /// with --cherry-pick, and without a PR Stack section in its description
+    #[clap(long, conflicts_with = "all")]
+    no_stack: bool,
 
-    let mut result = Ok(());
+    /// Pick the commits to submit from a list of the commits on the branch.
+    /// The selected commits must be the bottom of the stack, without gaps.
+    #[clap(long, short = 'i', conflicts_with_all = &["all", "no-stack"])]
+    interactive: bool,
 
-    // Look up the commits on the local branch
-    let mut prepared_commits = git.get_prepared_commits(config)?;
+    /// Submit the commits on top of this commit (e.g. a tag) up to HEAD as the
+    /// stack, instead of those on top of master. It must be an ancestor of
+    /// HEAD.
+    #[clap(long, value_name = "REF", conflicts_with = "no-stack")]
+    stack_from: Option<String>,
 
-    // The parent of the first commit in the list is the commit on master that
-    // the local branch is based on
-    let master_base_oid = if let Some(first_commit) = prepared_commits.first() {
-        first_commit.parent_oid
-    } else {
-        output("👋", "Branch is empty - nothing to do. Good bye!")?;
-        return result;
-    };
+    /// Use this commit (given by its ID, and in the local repository already)
+    /// as the state of master to submit the commits on top of it to, instead
+    /// of master itself, which is not looked at (e.g. to pin the base in CI)
+    #[clap(long, value_name = "SHA", conflicts_with = "stack-from")]
+    base_commit: Option<String>,
 
-    if !opts.all {
-        // Remove all prepared commits from the vector but the last. So, if
-        // `--all` is not given, we only operate on the HEAD commit.
-        prepared_commits.drain(0..prepared_commits.len() - 1);
-    }
+    /// Request review from these users or teams (teams with a leading '#') on
+    /// new Pull Requests. Can be given multiple times, and each value may be a
+    /// comma-separated list. Replaces the reviewers given in the commit
+    /// message, unless --reviewer-from-commit is given as well.
+    #[clap(long, short = 'r', value_name = "NAMES")]
+    reviewer: Vec<String>,
 
-    #[allow(clippy::needless_collect)]
-    let pull_request_tasks: Vec<_> = prepared_commits
-        .iter()
-        .map(|pc: &PreparedCommit| {
-            pc.pull_request_number
-                .map(|number| tokio::spawn(gh.clone().get_pull_request(number)))
-        })
-        .collect();
+    /// Also request review from the reviewers listed in the commit message
+    /// when reviewers are given with --reviewer
+    #[clap(long)]
+    reviewer_from_commit: bool,
 
-    let mut message_on_prompt = "".to_string();
+    /// Do not request review from the reviewers configured in
+    /// spr.defaultReviewers, or from any in spr.reviewerPool
+    #[clap(long)]
+    no_default_reviewers: bool,
 
-    let mut parent_oid = None;
-    for (prepared_commit, pull_request_task) in
-        zip(prepared_commits.iter_mut(), pull_request_tasks.into_iter())
-    {
-        if result.is_err() {
-            break;
-        }
+    /// How to pick the reviewers of new Pull Requests from spr.reviewerPool:
+    /// everyone in it ('all'), or the next ones in turn ('round-robin').
+    /// Overrides spr.reviewerStrategy.
+    #[clap(
+        long,
+        value_name = "STRATEGY",
+        possible_values = &["all", "round-robin"]
+    )]
+    reviewer_strategy: Option<String>,
+
+    /// Refuse to create a Pull Request with fewer than N distinct
+    /// reviewers, where a team counts as one reviewer. Overrides
+    /// spr.reviewerRequired.
+    #[clap(long, value_name = "N")]
+    reviewer_required: Option<usize>,
+
+    /// Add new Pull Requests to this project of the owner of the repository,
+    /// given by its number or title. Overrides spr.project.
+    #[clap(long, value_name = "PROJECT")]
+    project: Option<String>,
+
+    /// Assign new Pull Requests to these users, in addition to the ones
+    /// configured in spr.assignees. Can be given multiple times, and each
+    /// value may be a comma-separated list.
+    #[clap(long, value_name = "NAMES")]
+    assignee: Vec<String>,
+
+    /// Prepare the Pull Request branches as local branches only. Nothing is
+    /// pushed, and no Pull Requests are created or updated on GitHub.
+    #[clap(long)]
+    no_push: bool,
+
+    /// Push the Pull Request branches, but do not create or update any Pull
+    /// Requests on GitHub (e.g. when some other automation opens them)
+    #[clap(long, conflicts_with = "no-push")]
+    push_only: bool,
+
+    /// Record the base of the stack (master, or the commit given with
+    /// --stack-from) in the Git directory, for commands that rebase the stack
+    /// later on (the default with spr.setUpstream)
+    #[clap(long)]
+    set_upstream: bool,
+
+    /// Squash `fixup!` and `squash!` commits into the commits they are meant
+    /// for (as `git rebase --autosquash` does) before submitting, rather than
+    /// submitting them on their own (the default with spr.autosquash)
+    #[clap(long)]
+    autosquash: bool,
+
+    /// Send this server-side push option when pushing the Pull Request
+    /// branches (`git push --push-option`), in addition to the ones
+    /// configured in spr.pushOption. Can be given multiple times.
+    #[clap(long, value_name = "OPTION")]
+    push_option: Vec<String>,
+
+    /// Overwrite the branches on GitHub even if someone else pushed to them
+    /// since spr last did
+    #[clap(long)]
+    force: bool,
+
+    /// Reopen Pull Requests that were closed (but not merged), instead of
+    /// refusing to update them. Their branches are only pushed if their
+    /// content has changed.
+    #[clap(long)]
+    reopen: bool,
+
+    /// Print the URL of each Pull Request created or updated to stdout, one
+    /// per line and nothing else, for piping into other programs. All other
+    /// output goes to stderr.
+    #[clap(long)]
+    pub print_urls: bool,
+
+    /// Use the contents of this file as the description of new Pull Requests,
+    /// instead of the summary and test plan from the commit message (which
+    /// stays as it is)
+    #[clap(long, value_name = "PATH")]
+    body_file: Option<std::path::PathBuf>,
+
+    /// Edit the description of each new Pull Request in your editor before
+    /// creating it (the commit message stays as it is)
+    #[clap(long, conflicts_with = "body-file")]
+    edit_body: bool,
+
+    /// Fail, instead of just warning, if the title of a Pull Request is
+    /// longer than spr.titleLengthLimit
+    #[clap(long)]
+    strict_title: bool,
+
+    /// Succeed (with exit code 0) when there is nothing to submit, instead of
+    /// exiting with the special exit code 3, e.g. for smoke tests in CI
+    #[clap(long)]
+    allow_empty_stack: bool,
+
+    /// Fail, instead of just warning, if a commit to submit was authored by
+    /// someone else
+    #[clap(long)]
+    strict_authorship: bool,
+
+    /// Submit commits even if they change files that spr.submitDenylist
+    /// matches
+    #[clap(long)]
+    allow_denied: bool,
+
+    /// Before pushing anything, print what is about to happen to each commit:
+    /// which Pull Request it creates or updates, with which branch and base
+    #[clap(long)]
+    show_plan: bool,
+
+    /// Before changing the description of an existing Pull Request, print how
+    /// it changes, as a diff of the description on GitHub and the new one
+    #[clap(long)]
+    show_body_diff: bool,
+
+    /// Like --show-body-diff, and ask whether to go ahead with each change of
+    /// a description (when running in a terminal). Declining keeps the
+    /// description on GitHub as it is.
+    #[clap(long)]
+    confirm_body: bool,
+
+    /// The contents of --body-file
+    #[clap(skip)]
+    body: Option<String>,
 
-        let pull_request = if let Some(task) = pull_request_task {
-            Some(task.await??)
+    /// Only submit the commit of this Pull Request and the ones on top of it
+    #[clap(skip)]
+    from_pull_request: Option<u64>,
+
+    /// The authors (other than the user) of the commits to submit, by the
+    /// commit whose Pull Request credits them with a Co-authored-by trailer,
+    /// as the user agreed to
+    #[clap(skip)]
+    credit_authors: HashMap<Oid, Vec<CommitIdentity>>,
+
+    /// The project that new Pull Requests are added to, as found on GitHub
+    #[clap(skip)]
+    target_project: Option<Project>,
+}
+
+impl DiffOptions {
+    /// Whether to submit commits as if they were cherry-picked on master.
+    fn on_master(&self) -> bool {
+        self.cherry_pick || self.no_stack
+    }
+
+    /// Whether to submit the whole branch (or the selected part of it), rather
+    /// than just the HEAD commit.
+    fn whole_branch(&self) -> bool {
+        self.all || self.interactive || self.stack_from.is_some()
+    }
+
+    /// Whether to create and update Pull Requests on GitHub, rather than only
+    /// preparing (and maybe pushing) their branches.
+    fn updates_pull_requests(&self) -> bool {
+        !self.no_push && !self.push_only
+    }
+
+    /// The configured default reviewers, unless they are turned off.
+    fn default_reviewers<'a>(
+        &self,
+        config: &'a crate::config::Config,
+    ) -> &'a [String] {
+        if self.no_default_reviewers {
+            &[]
         } else {
-            None
-        };
+            &config.default_reviewers
+        }
+    }
 
-        write_commit_title(prepared_commit)?;
+    /// Options for (re-)submitting the commit of the given Pull Request and
+    /// all commits on top of it.
+    pub(crate) fn from_pull_request(
+        number: u64,
+        message: Option<String>,
+    ) -> Self {
+        Self {
+            all: true,
+            message,
+            from_pull_request: Some(number),
+            ..Default::default()
+        }
+    }
+}
 
-        if let Some(parent_oid) = parent_oid {
-            prepared_commit.parent_oid = parent_oid;
+/// Options for submitting as the library asks (see [`crate::api::submit`]).
+impl From<&crate::api::SubmitOptions> for DiffOptions {
+    fn from(options: &crate::api::SubmitOptions) -> Self {
+        Self {
+            all: options.all,
+            draft: options.draft,
+            message: options.message.clone(),
+            update_message: options.update_message,
+            reviewer: options.reviewers.clone(),
+            no_push: options.no_push,
+            ..Default::default()
         }
+    }
+}
 
-        // The further implementation of the diff command is in a separate function.
-        // This makes it easier to run the code to update the local commit message
-        // with all the changes that the implementation makes at the end, even if
-        // the implementation encounters an error or exits early.
-        result = diff_impl(
-            &opts,
-            &mut message_on_prompt,
-            git,
-            gh,
-            config,
-            prepared_commit,
-            master_base_oid,
-            pull_request,
-        )
-        .await;
-        // This updates the commit message in the local Git repository (if it was
-        // changed by the implementation)
-        parent_oid = Some(
-            git.rewrite_single_commit_message(prepared_commit, parent_oid)?,
-        );
+/// What `spr diff` did, when it did not fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffOutcome {
+    /// The commits were submitted (or skipped, as configured), creating or
+    /// updating these Pull Requests (bottom of the stack first)
+    Submitted { pull_requests: Vec<u64> },
+    /// There are no commits between the base of the stack and HEAD
+    NothingToSubmit,
+}
+
+/// The outcome of `spr diff` when there are no commits to submit: with
+/// `--allow-empty-stack`, that counts as submitting all (none) of them.
+fn empty_stack_outcome(opts: &DiffOptions) -> Result<DiffOutcome> {
+    if !opts.allow_empty_stack {
+        return Ok(DiffOutcome::NothingToSubmit);
     }
 
-    result
+    output("✅", "The stack is empty, which --allow-empty-stack allows")?;
+    Ok(DiffOutcome::Submitted {
+        pull_requests: Vec::new(),
+    })
 }
 
-#[allow(clippy::too_many_arguments)]
-async fn diff_impl(
+/// The commits on the local branch to submit (bottom of the stack first), and
+/// the commit on master that they are based on, or `None` if there is nothing
+/// to submit. `base_commit` is the resolved `--base-commit`, if given.
+fn find_stack(
     opts: &DiffOptions,
-    message_on_prompt: &mut String,
     git: &crate::git::Git,
-    gh: &mut crate::github::GitHub,
     config: &crate::config::Config,
-    local_commit: &mut PreparedCommit,
-    master_base_oid: Oid,
-    pull_request: Option<PullRequest>,
-) -> Result<()> {
-    // Parsed commit message of the local commit
-    let message = &mut local_commit.message;
+    base_commit: Option<&str>,
+) -> Result<Option<(Vec<PreparedCommit>, Oid)>> {
+    let bottom = base_commit.or(opts.stack_from.as_deref());
 
-    // Check if the local commit is based directly on the master branch.
-    let directly_based_on_master = local_commit.parent_oid == master_base_oid;
+    // Look up the commits on the local branch
+    let prepared_commits = match bottom {
+        Some(bottom) => git.get_prepared_commits_from(config, bottom)?,
+        None => git.get_prepared_commits(config)?,
+    };
 
-    // Determine the trees the Pull Request branch and the base branch should
-    // have when we're done here.
-    let (new_head_tree, new_base_tree) = if !opts.cherry_pick
-        || directly_based_on_master
-    {
-        // Unless the user tells us to --cherry-pick, these should be the trees
-        // of the current commit and its parent.
-        // If the current commit is directly based on master (i.e.
-        // directly_based_on_master is true), then we can do this here even when
-        // the user tells us to --cherry-pick, because we would cherry pick the
-        // current commit onto its parent, which gives us the same tree as the
-        // current commit has, and the master base is the same as this commit's
-        // parent.
-        let head_tree = git.get_tree_oid_for_commit(local_commit.oid)?;
-        let base_tree = git.get_tree_oid_for_commit(local_commit.parent_oid)?;
+    // HEAD being the base of the stack (or behind it) is not an error, but a
+    // different outcome from submitting something
+    let (base, base_name) = match bottom {
+        Some(bottom) => (bottom, bottom),
+        None => (config.master_ref.local(), config.master_ref.branch_name()),
+    };
+    if git.is_empty_range(base)? {
+        output(
+            "👋",
+            &format!("HEAD is on {} already - nothing to submit", base_name),
+        )?;
+        return Ok(None);
+    }
 
-        (head_tree, base_tree)
-    } else {
-        // Cherry-pick the current commit onto master
-        let index = git.cherrypick(local_commit.oid, master_base_oid)?;
+    // The parent of the first commit in the list is the commit on master that
+    // the local branch is based on. The bottom of a stack given with
+    // --stack-from need not be on master, though: the stack is then based on
+    // where its bottom branched off master. A --base-commit takes the place
+    // of master.
+    let master_base_oid = match prepared_commits.first() {
+        Some(first_commit) if base_commit.is_some() => first_commit.parent_oid,
+        Some(first_commit) if opts.stack_from.is_some() => git.merge_base(
+            first_commit.parent_oid,
+            git.resolve_reference(config.master_ref.local())?,
+        )?,
+        Some(first_commit) => first_commit.parent_oid,
+        None => return Ok(None),
+    };
 
-        if index.has_conflicts() {
-            return Err(Error::new(formatdoc!(
-                "This commit cannot be cherry-picked on {master}.",
-                master = config.master_ref.branch_name(),
-            )));
-        }
+    Ok(Some((prepared_commits, master_base_oid)))
+}
 
-        // This is the tree we are getting from cherrypicking the local commit
-        // on master.
-        let cherry_pick_tree = git.write_index(index)?;
-        let master_tree = git.get_tree_oid_for_commit(master_base_oid)?;
+pub async fn diff(
+    mut opts: DiffOptions,
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    config: &crate::config::Config,
+) -> Result<DiffOutcome> {
+    // Abort right here if the local Git repository is not clean
+    git.check_no_uncommitted_changes()?;
 
-        (cherry_pick_tree, master_tree)
+    // Rather find out about an unreadable body file before pushing anything
+    if let Some(path) = &opts.body_file {
+        opts.body = Some(std::fs::read_to_string(path).reword(format!(
+            "Could not read the Pull Request description from {}",
+            path.display()
+        ))?);
+    }
+    if opts.edit_body && !console::user_attended() {
+        return Err(Error::new(
+            "spr diff --edit-body needs a terminal to run the editor in",
+        ));
+    }
+
+    let mut result = Ok(());
+
+    if let Some(warning) = upstream_moved(git)? {
+        output("⚠️", &warning)?;
+    }
+
+    let base_commit = match &opts.base_commit {
+        Some(sha) => Some(git.find_local_commit(sha)?.to_string()),
+        None => None,
     };
 
-    if let Some(number) = local_commit.pull_request_number {
+    let (mut prepared_commits, mut master_base_oid) =
+        match find_stack(&opts, git, config, base_commit.as_deref())? {
+            Some(stack) => stack,
+            None => return empty_stack_outcome(&opts),
+        };
+
+    // Fixup commits go into the commits they are meant for, rather than
+    // getting Pull Requests of their own
+    if opts.autosquash || config.autosquash {
+        let targets = autosquash::autosquash_targets(&prepared_commits)?;
+        if !targets.is_empty() {
+            timings::time(
+                Phase::Rebase,
+                autosquash::autosquash(prepared_commits[0].parent_oid),
+            )
+            .await?;
+            output(
+                "🧹",
+                &format!(
+                    "Squashed {} fixup commit(s) into the commits they are for",
+                    targets.len()
+                ),
+            )?;
+            (prepared_commits, master_base_oid) =
+                match find_stack(&opts, git, config, base_commit.as_deref())? {
+                    Some(stack) => stack,
+                    None => return empty_stack_outcome(&opts),
+                };
+        }
+    }
+
+    // Commits marked as work in progress are not submitted, and neither is
+    // anything on top of them, since those would be based on an unsubmitted
+    // change.
+    let mut skipped_commits = split_off_work_in_progress(&mut prepared_commits);
+    if let Some(first_skipped) = skipped_commits.first() {
         output(
-            "#️⃣ ",
+            "🚧",
             &format!(
-                "Pull Request #{}: {}",
-                number,
-                config.pull_request_url(number)
+                "Commit {} is marked as work in progress - not submitting it{}",
+                first_skipped.short_id,
+                match skipped_commits.len() - 1 {
+                    0 => String::new(),
+                    1 => " or the commit on top of it".to_string(),
+                    n => format!(" or the {} commits on top of it", n),
+                }
             ),
         )?;
-    }
-
-    if local_commit.pull_request_number.is_none() || opts.update_message {
-        validate_commit_message(message, config)?;
-    }
 
-    if let Some(ref pull_request) = pull_request {
-        if pull_request.state == PullRequestState::Closed {
-            return Err(Error::new(formatdoc!(
-                "Pull request is closed. If you want to open a new one, \
-                 remove the 'Pull Request' section from the commit message."
-            )));
+        if !opts.whole_branch() || prepared_commits.is_empty() {
+            // The HEAD commit is skipped, so there is nothing left to do
+            return result.map(|()| DiffOutcome::Submitted {
+                pull_requests: Vec::new(),
+            });
         }
+    }
 
-        if !opts.update_message {
-            let mut pull_request_updates: PullRequestUpdate =
-                Default::default();
-            pull_request_updates.update_message(pull_request, message);
+    // Commits with a Squash-Into-Previous trailer are submitted together with
+    // the ones below them, so each group counts as one commit from here on.
+    let mut prepared_commits = commit_group::fold(prepared_commits)?;
 
-            if !pull_request_updates.is_empty() {
-                output(
-                    "⚠️",
-                    indoc!(
-                        "The Pull Request's title/message differ from the \
-                         local commit's message.
-                         Use `spr diff --update-message` to overwrite the \
-                         title and message on GitHub with the local message, \
-                         or `spr amend` to go the other way (rewrite the local \
-                         commit message with what is on GitHub)."
-                    ),
-                )?;
-            }
-        }
+    // Commits left out of the interactive selection are put back on top of
+    // the submitted ones, like the skipped ones.
+    if opts.interactive {
+        let selected = pick_commits(&prepared_commits)?;
+        let mut unselected =
+            commit_group::unfold(prepared_commits.split_off(selected));
+        unselected.append(&mut skipped_commits);
+        skipped_commits = unselected;
     }
 
-    // Parse "Reviewers" section, if this is a new Pull Request
-    let mut requested_reviewers = PullRequestRequestReviewers::default();
+    select_commits(&mut prepared_commits, &opts)?;
+    if let Some(hook) = &config.commit_hook {
+        output("🪝", &format!("Running spr.commitHook: {}", hook))?;
+        let mut commits = commit_group::unfold(prepared_commits);
+        git.run_commit_hook(hook, &mut commits, &mut skipped_commits)
+            .await?;
+        prepared_commits = commit_group::fold(commits)?;
+    }
+    check_authorship(&mut opts, git, &prepared_commits)?;
+    check_denied_paths(&opts, git, config, &prepared_commits)?;
+    check_fork_stack(&opts, config, &prepared_commits, master_base_oid)?;
+    let code_owners = if opts.updates_pull_requests() {
+        code_owner_suggestions(git, &prepared_commits)?
+    } else {
+        HashMap::new()
+    };
+    let skip_empty = skip_empty_commits(git, config, &prepared_commits)?;
 
-    if local_commit.pull_request_number.is_none() {
-        if let Some(reviewers) = message.get(&MessageSection::Reviewers) {
-            let reviewers = parse_name_list(reviewers);
-            let mut checked_reviewers = Vec::new();
+    if prepared_commits.len() > 1 {
+        check_rate_limit(config, ApiOperation::Diff, prepared_commits.len())
+            .await?;
+    }
 
-            for reviewer in reviewers {
-                // Teams are indicated with a leading #
-                if let Some(slug) = reviewer.strip_prefix('#') {
-                    if let Ok(team) = GitHub::get_github_team(
-                        (&config.owner).into(),
-                        slug.into(),
-                    )
+    // Rather find out about a project that cannot be found before pushing
+    // anything
+    let project = opts.project.as_ref().or(config.project.as_ref());
+    if let (Some(name), true) = (project, opts.updates_pull_requests()) {
+        if prepared_commits
+            .iter()
+            .any(|commit| commit.pull_request_number.is_none())
+        {
+            opts.target_project = Some(
+                gh.find_project(name)
                     .await
-                    {
-                        requested_reviewers
-                            .team_reviewers
-                            .push(team.slug.to_string());
-
-                        checked_reviewers.push(reviewer);
-                    } else {
-                        return Err(Error::new(format!(
-                            "Reviewers field contains unknown team '{}'",
-                            reviewer
-                        )));
-                    }
-                } else if let Ok(user) =
-                    GitHub::get_github_user(reviewer.clone()).await
-                {
-                    requested_reviewers.reviewers.push(user.login);
-                    if let Some(name) = user.name {
-                        checked_reviewers.push(format!(
-                            "{} ({})",
-                            reviewer.clone(),
-                            remove_all_parens(&name)
-                        ));
-                    } else {
-                        checked_reviewers.push(reviewer);
-                    }
-                } else {
-                    return Err(Error::new(format!(
-                        "Reviewers field contains unknown user '{}'",
-                        reviewer
-                    )));
-                }
-            }
-
-            message.insert(
-                MessageSection::Reviewers,
-                checked_reviewers.join(", "),
+                    .context(format!("Could not find project '{}'", name))?,
             );
         }
     }
 
-    // Get the name of the existing Pull Request branch, or constuct one if
-    // there is none yet.
+    let pull_requests = timings::time(
+        Phase::Fetch,
+        gh.get_pull_requests(
+            prepared_commits.iter().map(|pc| pc.pull_request_number),
+        ),
+    )
+    .await;
 
-    let title = message
-        .get(&MessageSection::Title)
-        .map(|t| &t[..])
-        .unwrap_or("");
+    let mut message_on_prompt = "".to_string();
+    let mut ref_names = git.get_all_ref_names()?;
 
-    let pull_request_branch = match &pull_request {
-        Some(pr) => pr.head.clone(),
-        None => config.new_github_branch(
-            &config.get_new_branch_name(&git.get_all_ref_names()?, title),
-        ),
-    };
+    // First, work out the changes to make for each commit...
+    let mut changes = Vec::new();
+    let mut renames = Vec::new();
+    for ((prepared_commit, pull_request), skip) in
+        zip(zip(prepared_commits.iter_mut(), pull_requests), skip_empty)
+    {
+        let pull_request = match pull_request {
+            Ok(pr) => pr,
+            Err(error) => {
+                result = Err(error);
+                break;
+            }
+        };
 
-    // Get the tree ids of the current head of the Pull Request, as well as the
-    // base, and the commit id of the master commit this PR is currently based
-    // on.
-    // If there is no pre-existing Pull Request, we fill in the equivalent
-    // values.
-    let (pr_head_oid, pr_head_tree, pr_base_oid, pr_base_tree, pr_master_base) =
-        if let Some(pr) = &pull_request {
-            let pr_head_tree = git.get_tree_oid_for_commit(pr.head_oid)?;
+        write_commit_title(prepared_commit)?;
+        if !prepared_commit.squashed.is_empty() {
+            output(
+                "🧩",
+                &format!(
+                    "Submitting {} commits together as one Pull Request",
+                    prepared_commit.squashed.len()
+                ),
+            )?;
+        }
+        if let Some(owners) = code_owners.get(&prepared_commit.oid) {
+            output(
+                "💡",
+                &format!(
+                    "Code owners of the changed files: {}",
+                    owners.join(", ")
+                ),
+            )?;
+        }
 
-            let current_master_oid =
-                git.resolve_reference(config.master_ref.local())?;
-            let pr_base_oid =
-                git.repo().merge_base(pr.head_oid, pr.base_oid)?;
-            let pr_base_tree = git.get_tree_oid_for_commit(pr_base_oid)?;
+        if skip {
+            output("🫥", "Commit is empty - not submitting it")?;
+            changes.push(None);
+            continue;
+        }
 
-            let pr_master_base =
-                git.repo().merge_base(pr.head_oid, current_master_oid)?;
+        let pull_request = match pull_request {
+            Some(mut pull_request)
+                if config.sync_branch_names && opts.updates_pull_requests() =>
+            {
+                // The branch gets renamed before anything is pushed to it
+                match branch_rename(
+                    git,
+                    config,
+                    prepared_commit,
+                    &pull_request,
+                    &mut ref_names,
+                ) {
+                    Ok(Some(rename)) => {
+                        pull_request.head = rename.to.clone();
+                        renames.push(rename);
+                        Some(pull_request)
+                    }
+                    Ok(None) => Some(pull_request),
+                    Err(error) => {
+                        changes.push(None);
+                        result = Err(error);
+                        break;
+                    }
+                }
+            }
+            pull_request => pull_request,
+        };
 
-            (
-                pr.head_oid,
-                pr_head_tree,
-                pr_base_oid,
-                pr_base_tree,
-                pr_master_base,
+        match prepare_diff(
+            &opts,
+            &mut message_on_prompt,
+            git,
+            config,
+            prepared_commit,
+            master_base_oid,
+            pull_request,
+            &mut ref_names,
+        )
+        .await
+        {
+            Ok(commit_changes) => changes.push(commit_changes),
+            Err(error) => {
+                // The commit's message may have been changed before the
+                // error, so it still gets rewritten below
+                changes.push(None);
+                result = Err(error);
+                break;
+            }
+        }
+    }
+    let processed = changes.len();
+
+    if result.is_ok() && (opts.show_plan || config.show_plan) {
+        let plan = submit_plan(
+            &prepared_commits[..processed],
+            &changes,
+            &renames,
+            config,
+        );
+        output("📐", "Plan:")?;
+        for line in plan_lines(&plan) {
+            output("  ", &line)?;
+        }
+    }
+
+    // ...then update the branches of all of them in one go (after renaming
+    // the branches named after old commit titles, and reopening the closed
+    // Pull Requests among them)...
+    let renamed =
+        timings::time(Phase::ApiUpdates, sync_branch_names(git, gh, &renames))
+            .await;
+    let reopened = match renamed {
+        Ok(()) if opts.updates_pull_requests() => {
+            timings::time(
+                Phase::ApiUpdates,
+                reopen_before_push(gh, &mut changes),
             )
-        } else {
-            let master_base_tree =
-                git.get_tree_oid_for_commit(master_base_oid)?;
-            (
-                master_base_oid,
-                master_base_tree,
-                master_base_oid,
-                master_base_tree,
-                master_base_oid,
+            .await
+        }
+        renamed => renamed,
+    };
+    let branch_updates: Vec<_> = changes
+        .iter()
+        .flatten()
+        .flat_map(|changes| {
+            changes
+                .branch_updates
+                .iter()
+                .map(|(oid, branch)| (*oid, branch))
+        })
+        .collect();
+    let fetched = match reopened {
+        Ok(()) if opts.no_push || opts.force => Ok(()),
+        Ok(()) => {
+            timings::time(
+                Phase::Fetch,
+                fetch_uncached_branches(git, config, &branch_updates),
             )
-        };
-    let needs_merging_master = pr_master_base != master_base_oid;
+            .await
+        }
+        Err(error) => Err(error),
+    };
+    let pushed = match fetched {
+        Ok(()) => {
+            push_branch_updates(git, config, &opts, &branch_updates).await
+        }
+        Err(error) => Err(error),
+    };
+    let mut publish = pushed.is_ok() && !opts.no_push;
+    add_error(&mut result, pushed);
 
-    // At this point we can check if we can exit early because no update to the
-    // existing Pull Request is necessary
-    if let Some(ref pull_request) = pull_request {
-        // So there is an existing Pull Request...
-        if !needs_merging_master
-            && pr_head_tree == new_head_tree
-            && pr_base_tree == new_base_tree
-        {
-            // ...and it does not need a rebase, and the trees of both Pull
-            // Request branch and base are all the right ones.
-            output("✅", "No update necessary")?;
+    // ...and finally create or update the Pull Requests, one after the other,
+    // since each one's PR stack refers to the ones below it.
+    let mut parent_oid = None;
+    let mut published_numbers = Vec::new();
+    for (prepared_commit, changes) in zip(prepared_commits.iter_mut(), changes)
+    {
+        if let Some(parent_oid) = parent_oid {
+            prepared_commit.parent_oid = parent_oid;
+        }
 
-            if opts.update_message {
-                // However, the user requested to update the commit message on
-                // GitHub
+        if let (true, Some(changes)) = (publish, changes) {
+            let published = timings::time(
+                Phase::ApiUpdates,
+                publish_or_report(
+                    &opts,
+                    git,
+                    gh,
+                    config,
+                    prepared_commit,
+                    changes,
+                ),
+            )
+            .await;
+            publish = published.is_ok();
+            published_numbers
+                .extend(add_error(&mut result, published).flatten());
+        }
 
-                message.insert(
-                    MessageSection::PRStack,
-                    get_pr_stack(
+        // This updates the commit message in the local Git repository (if it
+        // was changed by the implementation)
+        parent_oid = timings::time_sync(Phase::Rebase, || {
+            rewrite_submitted_commit(git, config, prepared_commit, parent_oid)
+        })?;
+    }
+
+    // Put the commits we did not get to (because of an error) and the ones we
+    // skipped back on top of the (possibly rewritten) commits we submitted.
+    let mut remaining_commits: Vec<_> =
+        commit_group::unfold(prepared_commits.drain(processed..).collect())
+            .into_iter()
+            .chain(skipped_commits)
+            .collect();
+    if let Some(parent_oid) = parent_oid {
+        add_error(
+            &mut result,
+            timings::time_sync(Phase::Rebase, || {
+                git.reparent_commits(&mut remaining_commits, parent_oid)
+            }),
+        );
+    }
+
+    // The Pull Requests of the stack that were not submitted may still have
+    // outdated PR Stacks, e.g. after one below them was closed
+    if result.is_ok()
+        && opts.updates_pull_requests()
+        && config.refresh_pr_stacks
+    {
+        let commits =
+            match base_commit.as_deref().or(opts.stack_from.as_deref()) {
+                Some(bottom) => git.get_prepared_commits_from(config, bottom),
+                None => git.get_prepared_commits(config),
+            };
+        let refreshed = match commits {
+            Ok(commits) => {
+                timings::time(
+                    Phase::ApiUpdates,
+                    refresh_pr_stacks(
                         git,
+                        gh,
                         config,
-                        pull_request.number,
-                        local_commit.parent_oid,
-                        opts.cherry_pick,
-                        directly_based_on_master,
-                    )?,
-                );
+                        &commits,
+                        &published_numbers,
+                    ),
+                )
+                .await
+            }
+            Err(error) => Err(error),
+        };
+        add_error(
+            &mut result,
+            refreshed.context("Could not update the PR Stacks".to_string()),
+        );
+    }
 
-                let mut pull_request_updates: PullRequestUpdate =
-                    Default::default();
-                pull_request_updates.update_message(pull_request, message);
+    if result.is_ok() && (opts.set_upstream || config.set_upstream) {
+        add_error(&mut result, record_upstream(git, config, &opts));
+    }
 
-                if !pull_request_updates.is_empty() {
-                    // ...and there are actual changes to the message
-                    gh.update_pull_request(
-                        pull_request.number,
-                        &pull_request_updates,
-                    )
-                    .await?;
-                    output("✍", "Updated commit message on GitHub")?;
-                }
+    if opts.print_urls {
+        add_error(
+            &mut result,
+            write_urls(&mut std::io::stdout(), config, &published_numbers),
+        );
+    }
+
+    result.map(|()| DiffOutcome::Submitted {
+        pull_requests: published_numbers,
+    })
+}
+
+/// The commits to submit that someone else than the user running spr
+/// authored (going by the email address, in any case): for each, the commit
+/// submitted (the top one of a group), the one authored by someone else, and
+/// its author. If Git does not know who the user is, there is nothing to
+/// compare with.
+fn foreign_authored_commits(
+    git: &crate::git::Git,
+    commits: &[PreparedCommit],
+) -> Result<Vec<(Oid, Oid, CommitIdentity)>> {
+    let user = match git.user_identity() {
+        Ok(user) => user,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut foreign = Vec::new();
+    for commit in commits {
+        let members = if commit.squashed.is_empty() {
+            std::slice::from_ref(commit)
+        } else {
+            &commit.squashed[..]
+        };
+        for member in members {
+            let author = git.commit_author(member.oid)?;
+            if !author.email.eq_ignore_ascii_case(&user.email) {
+                foreign.push((commit.oid, member.oid, author));
             }
+        }
+    }
+
+    Ok(foreign)
+}
+
+/// Warn about commits authored by someone else, which would get submitted
+/// under the user's name, and offer to credit their authors with a
+/// Co-authored-by trailer. With `--strict-authorship`, fail instead.
+fn check_authorship(
+    opts: &mut DiffOptions,
+    git: &crate::git::Git,
+    commits: &[PreparedCommit],
+) -> Result<()> {
+    let foreign = foreign_authored_commits(git, commits)?;
+    if foreign.is_empty() {
+        return Ok(());
+    }
+
+    for (_, oid, author) in &foreign {
+        output(
+            "⚠️",
+            &format!(
+                "{} was authored by {} <{}>, not by you",
+                git.describe_commit(*oid)?,
+                author.name,
+                author.email
+            ),
+        )?;
+    }
+    if opts.strict_authorship {
+        return Err(Error::new(
+            "Not submitting commits authored by someone else \
+             (--strict-authorship)",
+        ));
+    }
 
-            return Ok(());
+    if console::user_attended() && opts.updates_pull_requests() {
+        let credit = dialoguer::Confirm::new()
+            .with_prompt(
+                "Credit the authors with a Co-authored-by trailer in the Pull \
+                 Requests?",
+            )
+            .default(true)
+            .interact()?;
+        if credit {
+            for (submitted, _, author) in foreign {
+                opts.credit_authors
+                    .entry(submitted)
+                    .or_default()
+                    .push(author);
+            }
         }
     }
 
-    // Check if there is a base branch on GitHub already. That's the case when
-    // there is an existing Pull Request, and its base is not the master branch.
-    let base_branch = if let Some(ref pr) = pull_request {
-        if pr.base.is_master_branch() {
-            None
+    Ok(())
+}
+
+/// The files that the commits to submit change and that `denylist` matches:
+/// for each, the commit that changes it (a member of a group, if the commit
+/// submitted is one), and its path.
+fn denied_paths(
+    git: &crate::git::Git,
+    denylist: &PathFilter,
+    commits: &[PreparedCommit],
+) -> Result<Vec<(Oid, String)>> {
+    let mut denied = Vec::new();
+    for commit in commits {
+        let members = if commit.squashed.is_empty() {
+            std::slice::from_ref(commit)
         } else {
-            Some(pr.base.clone())
+            &commit.squashed[..]
+        };
+        for member in members {
+            for path in
+                git.get_changed_files(member.oid, &PathFilter::empty())?
+            {
+                if denylist.is_excluded(&path) {
+                    denied.push((member.oid, path));
+                }
+            }
         }
-    } else {
-        None
+    }
+
+    Ok(denied)
+}
+
+/// The code owners of the files that the commits without a Pull Request yet
+/// change, as reviewers to suggest for their new Pull Requests, by the commit
+/// submitted. Files that `.spr/ignore` lists (e.g. generated or vendored code)
+/// are left out, and so are commits whose files nobody owns.
+fn code_owner_suggestions(
+    git: &crate::git::Git,
+    commits: &[PreparedCommit],
+) -> Result<HashMap<Oid, Vec<String>>> {
+    let mut suggestions = HashMap::new();
+    let code_owners = match CodeOwners::load(git)? {
+        Some(code_owners) => code_owners,
+        None => return Ok(suggestions),
     };
+    let filter = PathFilter::load(git)?;
 
-    // We are going to construct `pr_base_parent: Option<Oid>`.
-    // The value will be the commit we have to merge into the new Pull Request
-    // commit to reflect changes in the parent of the local commit (by rebasing
-    // or changing commits between master and this one, although technically
-    // that's also rebasing).
-    // If it's `None`, then we will not merge anything into the new Pull Request
-    // commit.
-    // If we are updating an existing PR, then there are three cases here:
-    // (1) the parent tree of this commit is unchanged and we do not need to
-    //     merge in master, which means that the local commit was amended, but
-    //     not rebased. We don't need to merge anything into the Pull Request
-    //     branch.
-    // (2) the parent tree has changed, but the parent of the local commit is on
-    //     master (or we are cherry-picking) and we are not already using a base
-    //     branch: in this case we can merge the master commit we are based on
-    //     into the PR branch, without going via a base branch. Thus, we don't
-    //     introduce a base branch here and the PR continues to target the
-    //     master branch.
-    // (3) the parent tree has changed, and we need to use a base branch (either
-    //     because one was already created earlier, or we find that we are not
-    //     directly based on master now): we need to construct a new commit for
-    //     the base branch. That new commit's tree is always that of that local
-    //     commit's parent (thus making sure that the difference between base
-    //     branch and pull request branch are exactly the changes made by the
-    //     local commit, thus the changes we want to have reviewed). The new
-    //     commit may have one or two parents. The previous base is always a
-    //     parent (that's either the current commit on an existing base branch,
-    //     or the previous master commit the PR was based on if there isn't a
-    //     base branch already). In addition, if the master commit this commit
-    //     is based on has changed, (i.e. the local commit got rebased on newer
-    //     master in the meantime) then we have to merge in that master commit,
-    //     which will be the second parent.
-    // If we are creating a new pull request then `pr_base_tree` (the current
-    // base of the PR) was set above to be the tree of the master commit the
-    // local commit is based one, whereas `new_base_tree` is the tree of the
-    // parent of the local commit. So if the local commit for this new PR is on
-    // master, those two are the same (and we want to apply case 1). If the
-    // commit is not directly based on master, we have to create this new PR
-    // with a base branch, so that is case 3.
+    for commit in commits {
+        if commit.pull_request_number.is_some() {
+            continue;
+        }
+        let members = if commit.squashed.is_empty() {
+            std::slice::from_ref(commit)
+        } else {
+            &commit.squashed[..]
+        };
+        let mut changed_files = Vec::new();
+        for member in members {
+            changed_files.extend(git.get_changed_files(member.oid, &filter)?);
+        }
+        let reviewers =
+            code_owners.reviewers(changed_files.iter().map(String::as_str));
+        if !reviewers.is_empty() {
+            suggestions.insert(commit.oid, reviewers);
+        }
+    }
+
+    Ok(suggestions)
+}
+
+/// Refuse to submit commits that change files spr.submitDenylist matches,
+/// before pushing anything, unless `--allow-denied` says otherwise.
+fn check_denied_paths(
+    opts: &DiffOptions,
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    commits: &[PreparedCommit],
+) -> Result<()> {
+    if config.submit_denylist.is_empty() || opts.allow_denied {
+        return Ok(());
+    }
+
+    let denylist = PathFilter::from_patterns(
+        config.submit_denylist.iter().map(String::as_str),
+        "spr.submitDenylist",
+    )?;
+    let denied = denied_paths(git, &denylist, commits)?;
+    if denied.is_empty() {
+        return Ok(());
+    }
+
+    for (oid, path) in &denied {
+        output(
+            "🚫",
+            &format!("{} changes {}", git.describe_commit(*oid)?, path),
+        )?;
+    }
+    Err(Error::new(format!(
+        "Not submitting {} file(s) that spr.submitDenylist matches (pass \
+         --allow-denied to submit them anyway)",
+        denied.len()
+    )))
+}
+
+/// Refuse to submit stacked commits when pushing to a fork, before pushing
+/// anything: the Pull Request of a commit that is not based on master would
+/// need a base branch, which has to be in the target repository, but spr can
+/// only push to the fork.
+fn check_fork_stack(
+    opts: &DiffOptions,
+    config: &crate::config::Config,
+    commits: &[PreparedCommit],
+    master_base_oid: Oid,
+) -> Result<()> {
+    let fork_owner = match &config.fork_owner {
+        Some(fork_owner) if !opts.on_master() => fork_owner,
+        _ => return Ok(()),
+    };
+
+    match commits
+        .iter()
+        .find(|commit| commit.parent_oid != master_base_oid)
+    {
+        Some(commit) => Err(Error::new(format!(
+            "Commit {} is stacked on other commits, but Pull Requests from \
+             the fork of {} cannot be stacked: their base branches would have \
+             to be in {}/{}. Submit it with --cherry-pick to base its Pull \
+             Request on {}, or land the commits below it first.",
+            commit.short_id,
+            fork_owner,
+            config.owner,
+            config.repo,
+            config.master_ref.branch_name()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// The `Co-authored-by` trailer for the author of the commit, unless that is
+/// the user running spr.
+fn co_author_trailer(
+    git: &crate::git::Git,
+    oid: Oid,
+) -> Result<Option<String>> {
+    let author = git.commit_author(oid)?;
+    let is_user = git
+        .user_identity()
+        .map(|user| user.email.eq_ignore_ascii_case(&author.email))
+        .unwrap_or(false);
+
+    Ok((!is_user)
+        .then(|| format!("Co-authored-by: {} <{}>", author.name, author.email)))
+}
+
+/// Rewrite the message of a submitted commit on top of `parent_oid` (or its
+/// parent), and return the new commit. For a group of commits, all of them
+/// are recreated, with the message of the group going to the top one. With
+/// spr.pullRequestTracking=notes, spr never changes commit messages, so
+/// nothing is rewritten and `None` is returned.
+fn rewrite_submitted_commit(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    prepared_commit: &mut PreparedCommit,
+    parent_oid: Option<Oid>,
+) -> Result<Option<Oid>> {
+    if config.pull_request_tracking == PullRequestTracking::Notes {
+        return Ok(None);
+    }
+    if prepared_commit.squashed.is_empty() {
+        return git
+            .rewrite_single_commit_message(prepared_commit, parent_oid)
+            .map(Some);
+    }
+
+    let mut members = commit_group::submitted_members(prepared_commit);
+    let mut parent_oid = parent_oid;
+    for member in &mut members {
+        parent_oid =
+            Some(git.rewrite_single_commit_message(member, parent_oid)?);
+    }
+    prepared_commit.oid = members[members.len() - 1].oid;
+    prepared_commit.squashed = members;
+
+    Ok(Some(prepared_commit.oid))
+}
+
+/// Record the base the stack was submitted on for the checked-out branch, for
+/// `--set-upstream`: the full name of the reference (master, or the one given
+/// with `--stack-from`), and the commit it is at.
+fn record_upstream(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    opts: &DiffOptions,
+) -> Result<()> {
+    let branch = match git.checked_out_branch() {
+        Some(branch) => branch,
+        None => {
+            return output(
+                "⚠️",
+                "Not on a branch - not recording the base of the stack",
+            )
+        }
+    };
+    let base_ref = match (&opts.stack_from, &opts.base_commit) {
+        (Some(name), _) => {
+            match git.repo().resolve_reference_from_short_name(name) {
+                Ok(reference) => reference.name().map(String::from),
+                Err(_) => None,
+            }
+        }
+        (None, Some(_)) => None,
+        (None, None) => Some(config.master_ref.local().to_string()),
+    };
+    let base_ref =
+        match base_ref {
+            Some(base_ref) => base_ref,
+            None => return output(
+                "⚠️",
+                "The base of the stack is a commit rather than a reference - \
+                 not recording it",
+            ),
+        };
+    let upstream = Upstream {
+        oid: git.resolve_reference(&base_ref)?,
+        base_ref,
+    };
+    upstream::record(git.repo().path(), &branch, &upstream)?;
+
+    output(
+        "📌",
+        &format!(
+            "Recorded {} ({}) as the base of branch {}",
+            upstream.base_ref, upstream.oid, branch
+        ),
+    )
+}
+
+/// A warning if the base recorded for the checked-out branch (see
+/// [`record_upstream`]) moved on since, or is gone, while the branch is not on
+/// top of where it is now: the stack is then due for a rebase.
+fn upstream_moved(git: &crate::git::Git) -> Result<Option<String>> {
+    let upstream = match git.checked_out_branch() {
+        Some(branch) => upstream::load(git.repo().path(), &branch)?,
+        None => None,
+    };
+    let upstream = match upstream {
+        Some(upstream) => upstream,
+        None => return Ok(None),
+    };
+
+    let repo = git.repo();
+    let reference = match repo.find_reference(&upstream.base_ref) {
+        Ok(reference) => reference,
+        Err(_) => {
+            return Ok(Some(format!(
+                "{}, the recorded base of the stack, does not exist anymore",
+                upstream.base_ref
+            )))
+        }
+    };
+    let name = reference.shorthand().unwrap_or(&upstream.base_ref);
+    let oid = reference.peel_to_commit()?.id();
+    let head = repo.head()?.peel_to_commit()?.id();
+    if oid == upstream.oid
+        || oid == head
+        || repo.graph_descendant_of(head, oid)?
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(format!(
+        "{} moved from {} to {} since the stack was submitted on it; run `git \
+         rebase {}` to bring the stack up to date",
+        name,
+        &upstream.oid.to_string()[..8],
+        &oid.to_string()[..8],
+        name
+    )))
+}
+
+/// Write the URLs of the given Pull Requests, one per line, for
+/// `--print-urls`.
+fn write_urls(
+    out: &mut impl std::io::Write,
+    config: &crate::config::Config,
+    numbers: &[u64],
+) -> Result<()> {
+    for &number in numbers {
+        writeln!(out, "{}", config.pull_request_url(number))?;
+    }
+
+    Ok(())
+}
+
+/// Remove the commits from `commits` that we are not going to submit.
+fn select_commits(
+    commits: &mut Vec<PreparedCommit>,
+    opts: &DiffOptions,
+) -> Result<()> {
+    if let Some(number) = opts.from_pull_request {
+        let index =
+            find_pull_request_commit(commits, number).ok_or_else(|| {
+                Error::new(format!(
+                    "No commit on this branch belongs to Pull Request #{}",
+                    number
+                ))
+            })?;
+        commits.drain(0..index);
+    } else if !opts.whole_branch() {
+        // Remove all prepared commits from the vector but the last. So, if
+        // `--all` is not given, we only operate on the HEAD commit.
+        commits.drain(0..commits.len() - 1);
+    }
+
+    Ok(())
+}
+
+/// For each of the commits, whether to leave it out because it is empty (has
+/// the same tree as its parent). Fails if there is an empty commit, and
+/// `spr.emptyCommits` says that is an error.
+fn skip_empty_commits(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    commits: &[PreparedCommit],
+) -> Result<Vec<bool>> {
+    if config.empty_commits == EmptyCommits::Allow {
+        return Ok(vec![false; commits.len()]);
+    }
+
+    commits
+        .iter()
+        .map(|commit| {
+            let empty = git.is_empty_commit(commit.oid)?;
+            if empty && config.empty_commits == EmptyCommits::Error {
+                return Err(Error::new(format!(
+                    "Commit {} is empty. Drop it, or set spr.emptyCommits to \
+                     'skip' to leave it out.",
+                    commit.short_id
+                )));
+            }
+
+            Ok(empty)
+        })
+        .collect()
+}
+
+/// Remove the first commit marked as work in progress and all commits after it
+/// from `commits`, and return them.
+fn split_off_work_in_progress(
+    commits: &mut Vec<PreparedCommit>,
+) -> Vec<PreparedCommit> {
+    let index = commits
+        .iter()
+        .position(|commit| is_work_in_progress(&commit.message))
+        .unwrap_or(commits.len());
+
+    commits.split_off(index)
+}
+
+/// The number of commits from the bottom of the stack that the (sorted)
+/// indices in `selection` make up, or `None` if they do not start at the bottom
+/// or have gaps.
+fn selected_prefix_len(selection: &[usize]) -> Option<usize> {
+    selection
+        .iter()
+        .enumerate()
+        .all(|(position, &index)| position == index)
+        .then_some(selection.len())
+}
+
+/// Ask the user which of the commits to submit, until they pick some commits
+/// from the bottom of the stack, and return how many they picked.
+fn pick_commits(commits: &[PreparedCommit]) -> Result<usize> {
+    if !console::user_attended() {
+        return Err(Error::new(
+            "--interactive needs a terminal to pick the commits in",
+        ));
+    }
+
+    let items: Vec<_> = commits
+        .iter()
+        .map(|commit| {
+            format!(
+                "{} {}",
+                commit.short_id,
+                commit
+                    .message
+                    .get(&MessageSection::Title)
+                    .map(|t| &t[..])
+                    .unwrap_or("(untitled)")
+            )
+        })
+        .collect();
+
+    loop {
+        let selection = dialoguer::MultiSelect::new()
+            .with_prompt("Commits to submit (bottom of the stack first)")
+            .items(&items)
+            .defaults(&vec![true; items.len()])
+            .interact()?;
+
+        match selected_prefix_len(&selection) {
+            Some(0) => {
+                return Err(Error::new("No commits selected - nothing to do"))
+            }
+            Some(selected) => return Ok(selected),
+            None => output(
+                "⚠️",
+                "Only commits at the bottom of the stack, without gaps, can \
+                 be submitted, since each one is based on the ones below it",
+            )?,
+        }
+    }
+}
+
+/// What is left to do for the Pull Request of a commit once its branches are
+/// updated.
+struct PullRequestChanges {
+    /// The existing Pull Request, or `None` if a new one is to be created
+    pull_request: Option<PullRequest>,
+    pull_request_branch: GitHubBranch,
+    /// The base branch, unless the Pull Request is based on master
+    base_branch: Option<GitHubBranch>,
+    /// The new commits for the Pull Request branch and the base branch. Empty
+    /// if only the title and message on GitHub need updating.
+    branch_updates: Vec<(Oid, GitHubBranch)>,
+    requested_reviewers: PullRequestRequestReviewers,
+    directly_based_on_master: bool,
+    /// Whether the existing Pull Request is closed and still needs reopening
+    reopen: bool,
+}
+
+/// What `spr diff` is about to do with a commit, as `--show-plan` prints it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PlannedCommit {
+    short_id: String,
+    title: String,
+    /// The branch of the Pull Request to rename first (with
+    /// spr.syncBranchNames), and its new name
+    rename: Option<(String, String)>,
+    action: PlannedAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PlannedAction {
+    Create {
+        head: String,
+        base: String,
+    },
+    Update {
+        number: u64,
+        head: String,
+        base: String,
+        /// Whether new commits get pushed, rather than only the title and
+        /// description updated
+        push: bool,
+        reopen: bool,
+    },
+    /// Nothing to do, for the given Pull Request if there is one
+    Unchanged(Option<u64>),
+}
+
+/// The plan for the given commits (bottom first), from the changes and branch
+/// renames worked out for them.
+fn submit_plan(
+    commits: &[PreparedCommit],
+    changes: &[Option<PullRequestChanges>],
+    renames: &[BranchRename],
+    config: &crate::config::Config,
+) -> Vec<PlannedCommit> {
+    zip(commits, changes)
+        .map(|(commit, changes)| {
+            let action = match changes {
+                None => PlannedAction::Unchanged(commit.pull_request_number),
+                Some(changes) => {
+                    let head =
+                        changes.pull_request_branch.branch_name().to_string();
+                    let base = changes
+                        .base_branch
+                        .as_ref()
+                        .unwrap_or(&config.master_ref)
+                        .branch_name()
+                        .to_string();
+                    match &changes.pull_request {
+                        None => PlannedAction::Create { head, base },
+                        Some(pull_request) => PlannedAction::Update {
+                            number: pull_request.number,
+                            head,
+                            base,
+                            push: !changes.branch_updates.is_empty(),
+                            reopen: changes.reopen,
+                        },
+                    }
+                }
+            };
+
+            let rename = renames
+                .iter()
+                .find(|rename| {
+                    Some(rename.number) == commit.pull_request_number
+                })
+                .map(|rename| {
+                    (
+                        rename.from.branch_name().to_string(),
+                        rename.to.branch_name().to_string(),
+                    )
+                });
+
+            PlannedCommit {
+                short_id: commit.short_id.clone(),
+                title: commit
+                    .message
+                    .get(&MessageSection::Title)
+                    .cloned()
+                    .unwrap_or_default(),
+                rename,
+                action,
+            }
+        })
+        .collect()
+}
+
+/// The plan as a compact graph, top of the stack first like `git log
+/// --graph`: a line for each commit, and one below it for its Pull Request.
+fn plan_lines(plan: &[PlannedCommit]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (index, commit) in plan.iter().enumerate().rev() {
+        let rail = if index == 0 { ' ' } else { '|' };
+        lines.push(format!("* {} {}", commit.short_id, commit.title));
+        if let Some((from, to)) = &commit.rename {
+            lines.push(format!("{}   rename branch {} → {}", rail, from, to));
+        }
+        let detail = match &commit.action {
+            PlannedAction::Create { head, base } => {
+                format!("create a Pull Request: {} → {}", head, base)
+            }
+            PlannedAction::Update {
+                number,
+                head,
+                base,
+                push: true,
+                reopen,
+            } => format!(
+                "{} #{}: {} → {}",
+                if *reopen {
+                    "reopen and update"
+                } else {
+                    "update"
+                },
+                number,
+                head,
+                base
+            ),
+            PlannedAction::Update {
+                number,
+                push: false,
+                reopen,
+                ..
+            } => format!(
+                "{}update the title and description of #{}",
+                if *reopen { "reopen and " } else { "" },
+                number
+            ),
+            PlannedAction::Unchanged(Some(number)) => {
+                format!("#{} is up to date", number)
+            }
+            PlannedAction::Unchanged(None) => "nothing to submit".to_string(),
+        };
+        lines.push(format!("{}   {}", rail, detail));
+    }
+
+    lines
+}
+
+/// Prepare the commits for the Pull Request of `local_commit`, and return what
+/// is left to do once they are pushed, or `None` if there is nothing to do.
+/// The names of new branches are added to `ref_names`, so that those of the
+/// following commits do not clash with them.
+#[allow(clippy::too_many_arguments)]
+async fn prepare_diff(
+    opts: &DiffOptions,
+    message_on_prompt: &mut String,
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    local_commit: &mut PreparedCommit,
+    master_base_oid: Oid,
+    pull_request: Option<PullRequest>,
+    ref_names: &mut HashSet<String>,
+) -> Result<Option<PullRequestChanges>> {
+    // Parsed commit message of the local commit
+    let message = &mut local_commit.message;
+
+    // Check if the local commit is based directly on the master branch.
+    let directly_based_on_master = local_commit.parent_oid == master_base_oid;
+
+    // Determine the trees the Pull Request branch and the base branch should
+    // have when we're done here.
+    let cherry_picked = opts.on_master() && !directly_based_on_master;
+    let (new_head_tree, new_base_tree) = if !cherry_picked {
+        // Unless the user tells us to --cherry-pick, these should be the trees
+        // of the current commit and its parent.
+        // If the current commit is directly based on master (i.e.
+        // directly_based_on_master is true), then we can do this here even when
+        // the user tells us to --cherry-pick, because we would cherry pick the
+        // current commit onto its parent, which gives us the same tree as the
+        // current commit has, and the master base is the same as this commit's
+        // parent.
+        let head_tree = git.get_tree_oid_for_commit(local_commit.oid)?;
+        let base_tree = git.get_tree_oid_for_commit(local_commit.parent_oid)?;
+
+        (head_tree, base_tree)
+    } else {
+        // Cherry-pick the current commit (or group of commits) onto master
+        let index = if local_commit.squashed.is_empty() {
+            git.cherrypick(local_commit.oid, master_base_oid)?
+        } else {
+            git.cherrypick_range(
+                local_commit.parent_oid,
+                local_commit.oid,
+                master_base_oid,
+            )?
+        };
+
+        if index.has_conflicts() {
+            return Err(Error::new(formatdoc!(
+                "This commit cannot be cherry-picked on {master}.",
+                master = config.master_ref.branch_name(),
+            )));
+        }
+
+        // This is the tree we are getting from cherrypicking the local commit
+        // on master.
+        let cherry_pick_tree = git.write_index(index)?;
+        let master_tree = git.get_tree_oid_for_commit(master_base_oid)?;
+
+        (cherry_pick_tree, master_tree)
+    };
+
+    if let Some(number) = local_commit.pull_request_number {
+        output(
+            "#️⃣ ",
+            &format!(
+                "Pull Request #{}: {}",
+                number,
+                config.pull_request_url(number)
+            ),
+        )?;
+    }
+
+    if local_commit.pull_request_number.is_none() || opts.update_message {
+        validate_commit_message(message, config)?;
+        check_title_length(message, config, opts.strict_title)?;
+    }
+
+    let reopen = match &pull_request {
+        Some(pull_request) => check_reopen(pull_request, opts)?,
+        None => false,
+    };
+
+    // A base that spr did not set must not be taken for a base branch spr
+    // made, or spr would push its commits there
+    let base_mismatch = pull_request.as_ref().and_then(|pr| {
+        unexpected_base(pr, config).map(|base| (pr.number, base))
+    });
+    if let Some((number, base)) = &base_mismatch {
+        let discrepancy = format!(
+            "Pull Request #{} is based on {}, but spr expects {} or a base \
+             branch made by spr",
+            number,
+            base,
+            config.master_ref.branch_name()
+        );
+        match config.base_mismatch {
+            BaseMismatch::Correct => {
+                output("🔀", &format!("{} - correcting its base", discrepancy))?
+            }
+            BaseMismatch::Warn => {
+                output(
+                    "⚠️",
+                    &format!(
+                        "{} - leaving it alone (spr.baseMismatch is 'warn')",
+                        discrepancy
+                    ),
+                )?;
+                return Ok(None);
+            }
+        }
+    }
+
+    if let Some(ref pull_request) = pull_request {
+        if !opts.update_message {
+            let mut pull_request_updates: PullRequestUpdate =
+                Default::default();
+            pull_request_updates.update_message(
+                pull_request,
+                &with_pull_request_description(
+                    &with_pr_stack_from(message, pull_request, config),
+                    pull_request.body.as_deref(),
+                ),
+                config,
+            );
+
+            if !pull_request_updates.is_empty() {
+                output(
+                    "⚠️",
+                    indoc!(
+                        "The Pull Request's title/message differ from the \
+                         local commit's message.
+                         Use `spr diff --update-message` to overwrite the \
+                         title and message on GitHub with the local message, \
+                         or `spr amend` to go the other way (rewrite the local \
+                         commit message with what is on GitHub)."
+                    ),
+                )?;
+            }
+        }
+    }
+
+    // Parse "Reviewers" section, if this is a new Pull Request (or if asked
+    // to with --update-reviewers-and-labels). The reviewers of an existing
+    // Pull Request may have been changed on GitHub on purpose.
+    let mut requested_reviewers = PullRequestRequestReviewers::default();
+    let new_pull_request = local_commit.pull_request_number.is_none();
+
+    if (new_pull_request || opts.update_reviewers_and_labels)
+        && opts.updates_pull_requests()
+    {
+        // The reviewers of a ```spr block count as part of the Reviewers
+        // section
+        let pr_config = pr_config::from_message(message)?.unwrap_or_default();
+        let commit_reviewers = message
+            .get(&MessageSection::Reviewers)
+            .into_iter()
+            .cloned()
+            .chain(pr_config.reviewers)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let reviewers = if new_pull_request {
+            let default_reviewers = [
+                opts.default_reviewers(config),
+                &pool_reviewers(opts, git, config)?,
+            ]
+            .concat();
+            let reviewers = collect_reviewers(
+                Some(&commit_reviewers),
+                &opts.reviewer,
+                opts.reviewer_from_commit,
+                &default_reviewers,
+                &config.reviewer_aliases,
+            );
+            check_reviewer_count(
+                &reviewers,
+                opts.reviewer_required.unwrap_or(config.reviewer_required),
+            )?;
+            reviewers
+        } else {
+            // Only the ones from the commit message, for an existing Pull
+            // Request
+            collect_reviewers(
+                Some(&commit_reviewers),
+                &[],
+                false,
+                &[],
+                &config.reviewer_aliases,
+            )
+        };
+        if !reviewers.is_empty()
+            || message.contains_key(&MessageSection::Reviewers)
+        {
+            let mut checked_reviewers = Vec::new();
+
+            for reviewer in reviewers {
+                // Teams are indicated with a leading #
+                if let Some(slug) = reviewer.strip_prefix('#') {
+                    if let Ok(team) = GitHub::get_github_team(
+                        (&config.owner).into(),
+                        slug.into(),
+                    )
+                    .await
+                    {
+                        requested_reviewers
+                            .team_reviewers
+                            .push(team.slug.to_string());
+
+                        checked_reviewers.push(reviewer);
+                    } else {
+                        return Err(Error::new(format!(
+                            "Reviewers field contains unknown team '{}'",
+                            reviewer
+                        )));
+                    }
+                } else if let Ok(user) =
+                    GitHub::get_github_user(reviewer.clone()).await
+                {
+                    requested_reviewers.reviewers.push(user.login);
+                    if let Some(name) = user.name {
+                        checked_reviewers.push(format!(
+                            "{} ({})",
+                            reviewer.clone(),
+                            remove_all_parens(&name)
+                        ));
+                    } else {
+                        checked_reviewers.push(reviewer);
+                    }
+                } else {
+                    return Err(Error::new(format!(
+                        "Reviewers field contains unknown user '{}'",
+                        reviewer
+                    )));
+                }
+            }
+
+            // The commit message keeps the reviewers in the order they were
+            // given, but GitHub always gets them in the same order
+            requested_reviewers.sort();
+
+            if config.pull_request_tracking == PullRequestTracking::Trailer {
+                message.insert(
+                    MessageSection::Reviewers,
+                    checked_reviewers.join(", "),
+                );
+            }
+        }
+    }
+
+    // Get the name of the existing Pull Request branch, or constuct one if
+    // there is none yet.
+
+    let title = message
+        .get(&MessageSection::Title)
+        .map(|t| &t[..])
+        .unwrap_or("");
+
+    // Branch names only need the date of the commit with spr.branchDateFormat
+    let author_date = match &config.branch_date_format {
+        Some(_) => Some(git.author_date(local_commit.oid)?),
+        None => None,
+    };
+
+    let pull_request_branch = match &pull_request {
+        Some(pr) => pr.head.clone(),
+        None => {
+            let branch = config.new_github_branch(&config.get_new_branch_name(
+                ref_names,
+                title,
+                author_date.as_ref(),
+            ));
+            ref_names.insert(branch.local().to_string());
+            branch
+        }
+    };
+
+    // Get the tree ids of the current head of the Pull Request, as well as the
+    // base, and the commit id of the master commit this PR is currently based
+    // on.
+    // If there is no pre-existing Pull Request, we fill in the equivalent
+    // values.
+    let (pr_head_oid, pr_head_tree, pr_base_oid, pr_base_tree, pr_master_base) =
+        if let Some(pr) = &pull_request {
+            let pr_head_tree = git.get_tree_oid_for_commit(pr.head_oid)?;
+
+            let current_master_oid = if opts.base_commit.is_some() {
+                master_base_oid
+            } else {
+                git.resolve_reference(config.master_ref.local())?
+            };
+            let pr_master_base =
+                git.repo().merge_base(pr.head_oid, current_master_oid)?;
+
+            // A Pull Request whose base is being corrected counts as based on
+            // master
+            let pr_base_oid = if base_mismatch.is_some() {
+                pr_master_base
+            } else {
+                git.repo().merge_base(pr.head_oid, pr.base_oid)?
+            };
+            let pr_base_tree = git.get_tree_oid_for_commit(pr_base_oid)?;
+
+            (
+                pr.head_oid,
+                pr_head_tree,
+                pr_base_oid,
+                pr_base_tree,
+                pr_master_base,
+            )
+        } else {
+            let master_base_tree =
+                git.get_tree_oid_for_commit(master_base_oid)?;
+            (
+                master_base_oid,
+                master_base_tree,
+                master_base_oid,
+                master_base_tree,
+                master_base_oid,
+            )
+        };
+    let needs_merging_master = pr_master_base != master_base_oid;
+
+    // Check if there is a base branch on GitHub already. That's the case when
+    // there is an existing Pull Request, and its base is not the master branch
+    // (or one spr did not set).
+    let base_branch = match &pull_request {
+        Some(pr) if !pr.base.is_master_branch() && base_mismatch.is_none() => {
+            Some(pr.base.clone())
+        }
+        _ => None,
+    };
+
+    // At this point we can check if we can exit early because no update to the
+    // existing Pull Request is necessary
+    if let Some(ref pull_request) = pull_request {
+        // So there is an existing Pull Request...
+        if !needs_merging_master
+            && pr_head_tree == new_head_tree
+            && pr_base_tree == new_base_tree
+        {
+            // ...and it does not need a rebase, and the trees of both Pull
+            // Request branch and base are all the right ones.
+            output("✅", "No update necessary")?;
+
+            if (opts.update_message
+                || opts.update_reviewers_and_labels
+                || reopen
+                || base_mismatch.is_some())
+                && opts.updates_pull_requests()
+            {
+                // However, the user requested to update the commit message on
+                // GitHub, or its reviewers and labels, or to reopen the Pull
+                // Request, or its base needs correcting. None of these needs a
+                // push (which would trigger CI for nothing).
+                return Ok(Some(PullRequestChanges {
+                    pull_request: Some(pull_request.clone()),
+                    pull_request_branch,
+                    base_branch,
+                    branch_updates: Vec::new(),
+                    requested_reviewers,
+                    directly_based_on_master,
+                    reopen,
+                }));
+            }
+
+            return Ok(None);
+        }
+    }
+
+    // We are going to construct `pr_base_parent: Option<Oid>`.
+    // The value will be the commit we have to merge into the new Pull Request
+    // commit to reflect changes in the parent of the local commit (by rebasing
+    // or changing commits between master and this one, although technically
+    // that's also rebasing).
+    // If it's `None`, then we will not merge anything into the new Pull Request
+    // commit.
+    // If we are updating an existing PR, then there are three cases here:
+    // (1) the parent tree of this commit is unchanged and we do not need to
+    //     merge in master, which means that the local commit was amended, but
+    //     not rebased. We don't need to merge anything into the Pull Request
+    //     branch.
+    // (2) the parent tree has changed, but the parent of the local commit is on
+    //     master (or we are cherry-picking) and we are not already using a base
+    //     branch: in this case we can merge the master commit we are based on
+    //     into the PR branch, without going via a base branch. Thus, we don't
+    //     introduce a base branch here and the PR continues to target the
+    //     master branch.
+    // (3) the parent tree has changed, and we need to use a base branch (either
+    //     because one was already created earlier, or we find that we are not
+    //     directly based on master now): we need to construct a new commit for
+    //     the base branch. That new commit's tree is always that of that local
+    //     commit's parent (thus making sure that the difference between base
+    //     branch and pull request branch are exactly the changes made by the
+    //     local commit, thus the changes we want to have reviewed). The new
+    //     commit may have one or two parents. The previous base is always a
+    //     parent (that's either the current commit on an existing base branch,
+    //     or the previous master commit the PR was based on if there isn't a
+    //     base branch already). In addition, if the master commit this commit
+    //     is based on has changed, (i.e. the local commit got rebased on newer
+    //     master in the meantime) then we have to merge in that master commit,
+    //     which will be the second parent.
+    // If we are creating a new pull request then `pr_base_tree` (the current
+    // base of the PR) was set above to be the tree of the master commit the
+    // local commit is based one, whereas `new_base_tree` is the tree of the
+    // parent of the local commit. So if the local commit for this new PR is on
+    // master, those two are the same (and we want to apply case 1). If the
+    // commit is not directly based on master, we have to create this new PR
+    // with a base branch, so that is case 3.
+
+    let (pr_base_parent, base_branch) =
+        if pr_base_tree == new_base_tree && !needs_merging_master {
+            // Case 1
+            (None, base_branch)
+        } else if base_branch.is_none()
+            && (directly_based_on_master || opts.on_master())
+        {
+            // Case 2
+            (Some(master_base_oid), None)
+        } else {
+            // Case 3
+
+            // We are constructing a base branch commit.
+            // One parent of the new base branch commit will be the current base
+            // commit, that could be either the top commit of an existing base
+            // branch, or a commit on master.
+            let mut parents = vec![pr_base_oid];
+
+            // If we need to rebase on master, make the master commit also a
+            // parent (except if the first parent is that same commit, we don't
+            // want duplicates in `parents`).
+            if needs_merging_master && pr_base_oid != master_base_oid {
+                parents.push(master_base_oid);
+            }
+
+            let new_base_branch_commit = git.create_derived_commit(
+                local_commit.parent_oid,
+                &format!(
+                    "[𝘀𝗽𝗿] {}\n\nCreated using spr {}\n\n[skip ci]",
+                    if pull_request.is_some() {
+                        "changes introduced through rebase".to_string()
+                    } else {
+                        format!(
+                            "changes to {} this commit is based on",
+                            config.master_ref.branch_name()
+                        )
+                    },
+                    env!("CARGO_PKG_VERSION"),
+                ),
+                new_base_tree,
+                &parents[..],
+            )?;
+
+            // If `base_branch` is `None` (which means a base branch does not exist
+            // yet), then make a `GitHubBranch` with a new name for a base branch
+            let base_branch = if let Some(base_branch) = base_branch {
+                base_branch
+            } else {
+                let name = config.get_base_branch_name(
+                    ref_names,
+                    title,
+                    author_date.as_ref(),
+                );
+                let branch = config.new_github_branch(&name);
+                ref_names.insert(branch.local().to_string());
+                branch
+            };
+
+            (Some(new_base_branch_commit), Some(base_branch))
+        };
+
+    let mut github_commit_message = opts.message.clone();
+    if pull_request.is_some()
+        && github_commit_message.is_none()
+        && !opts.no_push
+    {
+        let input = {
+            let message_on_prompt = message_on_prompt.clone();
+
+            tokio::task::spawn_blocking(move || {
+                dialoguer::Input::<String>::new()
+                    .with_prompt("Message (leave empty to abort)")
+                    .with_initial_text(message_on_prompt)
+                    .allow_empty(true)
+                    .interact_text()
+            })
+            .await??
+        };
+
+        if input.is_empty() {
+            return Err(Error::new("Aborted as per user request".to_string()));
+        }
+
+        *message_on_prompt = input.clone();
+        github_commit_message = Some(input);
+    }
+
+    // Construct the new commit for the Pull Request branch. First parent is the
+    // current head commit of the Pull Request (we set this to the master base
+    // commit earlier if the Pull Request does not yet exist)
+    let mut pr_commit_parents = vec![pr_head_oid];
+
+    // If we prepared a commit earlier that needs merging into the Pull Request
+    // branch, then that commit is a parent of the new Pull Request commit.
+    if let Some(oid) = pr_base_parent {
+        // ...unless if that's the same commit as the one we added to
+        // pr_commit_parents first.
+        if pr_commit_parents.first() != Some(&oid) {
+            pr_commit_parents.push(oid);
+        }
+    }
+
+    // A commit cherry-picked from someone else keeps crediting them, and so
+    // do the commits the user agreed to credit their authors for
+    let mut co_authors = Vec::new();
+    if cherry_picked && config.cherry_pick_co_author {
+        co_authors.extend(co_author_trailer(git, local_commit.oid)?);
+    }
+    for author in opts
+        .credit_authors
+        .get(&local_commit.oid)
+        .into_iter()
+        .flatten()
+    {
+        let trailer =
+            format!("Co-authored-by: {} <{}>", author.name, author.email);
+        if !co_authors.contains(&trailer) {
+            co_authors.push(trailer);
+        }
+    }
+
+    // Create the new commit
+    let pr_commit = git.create_derived_commit(
+        local_commit.oid,
+        &format!(
+            "{}\n\nCreated using spr {}{}",
+            github_commit_message.as_ref().map(|s| &s[..]).unwrap_or(
+                if pull_request.is_some() {
+                    "[𝘀𝗽𝗿] changes prepared locally"
+                } else {
+                    "[𝘀𝗽𝗿] initial version"
+                }
+            ),
+            env!("CARGO_PKG_VERSION"),
+            if co_authors.is_empty() {
+                String::new()
+            } else {
+                format!("\n\n{}", co_authors.join("\n"))
+            },
+        ),
+        new_head_tree,
+        &pr_commit_parents[..],
+    )?;
+
+    // The branches to update: the Pull Request branch, and the base branch
+    // if we prepared a new commit for it.
+    let mut branch_updates = vec![(pr_commit, pull_request_branch.clone())];
+    if let (Some(base_branch), Some(base_branch_commit)) =
+        (&base_branch, pr_base_parent)
+    {
+        branch_updates.push((base_branch_commit, base_branch.clone()));
+    }
+
+    if let (Some(pull_request), false) = (&pull_request, opts.no_push) {
+        if needs_merging_master {
+            output(
+                "⚾",
+                &format!(
+                    "Commit was rebased - updating Pull Request #{}",
+                    pull_request.number
+                ),
+            )?;
+        } else {
+            output(
+                "🔁",
+                &format!(
+                    "Commit was changed - updating Pull Request #{}",
+                    pull_request.number
+                ),
+            )?;
+        }
+    }
+
+    Ok(Some(PullRequestChanges {
+        pull_request,
+        pull_request_branch,
+        base_branch,
+        branch_updates,
+        requested_reviewers,
+        directly_based_on_master,
+        reopen,
+    }))
+}
+
+/// With spr.syncBranchNames, how to rename the branch of the open Pull
+/// Request of `local_commit` if the title of the commit changed since the
+/// branch was named after it, or `None` if there is nothing to rename.
+fn branch_rename(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    local_commit: &PreparedCommit,
+    pull_request: &PullRequest,
+    ref_names: &mut HashSet<String>,
+) -> Result<Option<BranchRename>> {
+    if pull_request.state != PullRequestState::Open {
+        return Ok(None);
+    }
+
+    let title = local_commit
+        .message
+        .get(&MessageSection::Title)
+        .map(String::as_str)
+        .unwrap_or_default();
+    let author_date = match &config.branch_date_format {
+        Some(_) => Some(git.author_date(local_commit.oid)?),
+        None => None,
+    };
+
+    Ok(retitled_branch(
+        config,
+        &pull_request.head,
+        title,
+        author_date.as_ref(),
+        ref_names,
+    )
+    .map(|to| BranchRename {
+        number: pull_request.number,
+        from: pull_request.head.clone(),
+        to,
+    }))
+}
+
+/// Rename the branches that [`branch_rename`] worked out. GitHub moves the
+/// Pull Requests along with their branches, so they keep their reviews and
+/// comments (and the old branches are gone).
+async fn sync_branch_names(
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    renames: &[BranchRename],
+) -> Result<()> {
+    for rename in renames {
+        rename_branch(git, gh, rename).await?;
+        output(
+            "🏷️",
+            &format!(
+                "Commit title changed - renamed branch {} of Pull Request #{} \
+                 to {}",
+                rename.from.branch_name(),
+                rename.number,
+                rename.to.branch_name()
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The base branch of an existing Pull Request, if it is not one spr bases
+/// Pull Requests on: master, or a base branch spr made (named
+/// `<prefix><master>.<slug>`). Then someone changed it outside spr, e.g. in
+/// the GitHub UI, maybe to the branch of the Pull Request below.
+fn unexpected_base(
+    pull_request: &PullRequest,
+    config: &crate::config::Config,
+) -> Option<String> {
+    let base = &pull_request.base;
+    let spr_base_prefix = format!(
+        "{}{}.",
+        config.branch_prefix,
+        config.master_ref.branch_name()
+    );
+    if base.is_master_branch()
+        || base.branch_name().starts_with(&spr_base_prefix)
+    {
+        None
+    } else {
+        Some(base.branch_name().to_string())
+    }
+}
+
+/// The message to compare with the title and description of the Pull Request.
+/// With spr.pullRequestTracking=notes, the PR Stack section is not kept in
+/// the commit message, so the one of the Pull Request goes in.
+fn with_pr_stack_from(
+    message: &MessageSectionsMap,
+    pull_request: &PullRequest,
+    config: &crate::config::Config,
+) -> MessageSectionsMap {
+    let mut message = message.clone();
+    if config.pull_request_tracking == PullRequestTracking::Notes {
+        if let Some(pr_stack) =
+            pull_request.sections.get(&MessageSection::PRStack)
+        {
+            message.insert(MessageSection::PRStack, pr_stack.clone());
+        }
+    }
+    message
+}
+
+/// Whether the existing Pull Request needs reopening, because it is closed and
+/// the user asked for that with `--reopen`. Fails if it is closed otherwise.
+fn check_reopen(
+    pull_request: &PullRequest,
+    opts: &DiffOptions,
+) -> Result<bool> {
+    if pull_request.state != PullRequestState::Closed {
+        return Ok(false);
+    }
+
+    if pull_request.merge_commit.is_some() {
+        return Err(Error::new(formatdoc!(
+            "Pull request has been merged already. If you want to open a new \
+             one, remove the 'Pull Request' section from the commit message."
+        )));
+    }
+
+    if !opts.reopen {
+        return Err(Error::new(formatdoc!(
+            "Pull request is closed. If you want to open a new one, remove \
+             the 'Pull Request' section from the commit message, or run \
+             `spr diff --reopen` to reopen it."
+        )));
+    }
+
+    Ok(true)
+}
+
+/// Reopen the closed Pull Requests whose branches are about to be pushed:
+/// GitHub refuses to reopen a Pull Request whose head branch was force-pushed
+/// while it was closed. The others are reopened along with their other
+/// updates, in `publish_diff`.
+async fn reopen_before_push(
+    gh: &impl Forge,
+    changes: &mut [Option<PullRequestChanges>],
+) -> Result<()> {
+    for changes in changes.iter_mut().flatten() {
+        let number = match &changes.pull_request {
+            Some(pull_request)
+                if changes.reopen && !changes.branch_updates.is_empty() =>
+            {
+                pull_request.number
+            }
+            _ => continue,
+        };
+
+        let update = PullRequestUpdate {
+            state: Some(PullRequestState::Open),
+            ..Default::default()
+        };
+        gh.update_pull_request(number, &update).await?;
+        interrupt::record_completed(format!(
+            "Reopened Pull Request #{}",
+            number
+        ));
+        output("🔓", &format!("Reopened Pull Request #{}", number))?;
+        changes.reopen = false;
+    }
+
+    Ok(())
+}
+
+/// After the branches of `local_commit` were pushed, create or update its Pull
+/// Request, and return its number. With `--push-only`, only tell which
+/// branches were pushed instead, and return `None`.
+async fn publish_or_report(
+    opts: &DiffOptions,
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    config: &crate::config::Config,
+    local_commit: &mut PreparedCommit,
+    changes: PullRequestChanges,
+) -> Result<Option<u64>> {
+    if opts.updates_pull_requests() {
+        return publish_diff(opts, git, gh, config, local_commit, changes)
+            .await
+            .map(Some);
+    }
+
+    for (oid, branch) in &changes.branch_updates {
+        output(
+            "📤",
+            &format!(
+                "Pushed branch {} at {} - no Pull Request created or updated",
+                branch.branch_name(),
+                oid
+            ),
+        )?;
+    }
+
+    Ok(None)
+}
+
+/// Create the Pull Request for `local_commit`, or update the existing one,
+/// after its branches were pushed. Returns the number of the Pull Request.
+async fn publish_diff(
+    opts: &DiffOptions,
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    config: &crate::config::Config,
+    local_commit: &mut PreparedCommit,
+    changes: PullRequestChanges,
+) -> Result<u64> {
+    let PullRequestChanges {
+        pull_request,
+        pull_request_branch,
+        base_branch,
+        branch_updates,
+        requested_reviewers,
+        directly_based_on_master,
+        reopen,
+    } = changes;
+    // With spr.pullRequestTracking=notes, the sections spr adds (like the PR
+    // Stack) only go to GitHub, and the commit message stays as it is
+    let mut github_only_message = local_commit.message.clone();
+    let message = match config.pull_request_tracking {
+        PullRequestTracking::Trailer => &mut local_commit.message,
+        PullRequestTracking::Notes => &mut github_only_message,
+    };
+
+    let number = if let Some(pull_request) = pull_request {
+        // We are updating an existing Pull Request
+
+        // Things we want to update in the Pull Request on GitHub
+        let mut pull_request_updates: PullRequestUpdate = Default::default();
+
+        set_pr_stack(
+            message,
+            opts,
+            git,
+            config,
+            pull_request.number,
+            local_commit.oid,
+            local_commit.parent_oid,
+            directly_based_on_master,
+        )?;
+        // A description of the Pull Request's own (from `--body-file` or
+        // `--edit-body`) is kept, and only the sections spr manages change
+        pull_request_updates.update_message(
+            &pull_request,
+            &with_pull_request_description(
+                message,
+                pull_request.body.as_deref(),
+            ),
+            config,
+        );
+        if let Some(body) = &pull_request_updates.body {
+            if (opts.show_body_diff || opts.confirm_body)
+                && !review_body_update(opts, &pull_request, body)?
+            {
+                pull_request_updates.body = None;
+            }
+        }
+
+        // If we are using a base branch, and the Pull Request's base is not
+        // set to it yet, change that now. Without one, the Pull Request is
+        // based on master, unless someone changed that (see
+        // `unexpected_base`).
+        if let Some(base_branch) = base_branch {
+            if pull_request.base.branch_name() != base_branch.branch_name() {
+                pull_request_updates.base =
+                    Some(base_branch.branch_name().to_string());
+            }
+        } else if !pull_request.base.is_master_branch() {
+            pull_request_updates.base =
+                Some(config.master_ref.branch_name().to_string());
+        }
+
+        let message_updated = pull_request_updates.title.is_some()
+            || pull_request_updates.body.is_some();
+        if reopen {
+            pull_request_updates.state = Some(PullRequestState::Open);
+        }
+
+        if !pull_request_updates.is_empty() {
+            gh.update_pull_request(pull_request.number, &pull_request_updates)
+                .await?;
+            interrupt::record_completed(format!(
+                "Updated Pull Request #{}",
+                pull_request.number
+            ));
+            if reopen {
+                output(
+                    "🔓",
+                    &format!("Reopened Pull Request #{}", pull_request.number),
+                )?;
+            }
+            if message_updated && branch_updates.is_empty() {
+                output("✍", "Updated commit message on GitHub")?;
+            }
+        }
+
+        if opts.update_reviewers_and_labels {
+            if !requested_reviewers.reviewers.is_empty()
+                || !requested_reviewers.team_reviewers.is_empty()
+            {
+                let result = gh
+                    .request_reviewers(pull_request.number, requested_reviewers)
+                    .await;
+                if let Err(error) = result {
+                    output("⚠️", "Requesting reviewers failed")?;
+                    for message in error.messages() {
+                        output("  ", message)?;
+                    }
+                }
+            }
+
+            let labels =
+                pr_config::from_message(message)?.unwrap_or_default().labels;
+            if !labels.is_empty() {
+                let result = gh.add_labels(pull_request.number, labels).await;
+                if let Err(error) = result {
+                    output("⚠️", "Adding labels failed")?;
+                    for message in error.messages() {
+                        output("  ", message)?;
+                    }
+                }
+            }
+        }
+
+        pull_request.number
+    } else {
+        // We are creating a new Pull Request.
+
+        // Call GitHub to create the Pull Request.
+        let base_branch_name = base_branch
+            .as_ref()
+            .unwrap_or(&config.master_ref)
+            .branch_name()
+            .to_string();
+
+        let description = match &opts.body {
+            Some(body) => Some(body.clone()),
+            None if opts.edit_body => Some(
+                edit_description(git, message, &config.message_cleanup).await?,
+            ),
+            None => None,
+        };
+        let github_message = |message: &MessageSectionsMap| match &description {
+            Some(description) => with_description(message, description),
+            None => message.clone(),
+        };
+
+        let pr_config = pr_config::from_message(message)?.unwrap_or_default();
+        let pull_request_number = gh
+            .create_pull_request(
+                &github_message(message),
+                base_branch_name,
+                config.pull_request_head(&pull_request_branch),
+                opts.draft || pr_config.draft == Some(true),
+            )
+            .await?;
+        interrupt::record_completed(format!(
+            "Created Pull Request #{}",
+            pull_request_number
+        ));
+
+        set_pr_stack(
+            message,
+            opts,
+            git,
+            config,
+            pull_request_number,
+            local_commit.oid,
+            local_commit.parent_oid,
+            directly_based_on_master,
+        )?;
+
+        let mut pull_request_updates: PullRequestUpdate = Default::default();
+        let newly_created_pr = gh.get_pull_request(pull_request_number).await?;
+        pull_request_updates.update_message(
+            &newly_created_pr,
+            &github_message(message),
+            config,
+        );
+        if !pull_request_updates.is_empty() {
+            gh.update_pull_request(pull_request_number, &pull_request_updates)
+                .await?;
+        }
+
+        let pull_request_url = config.pull_request_url(pull_request_number);
+
+        output(
+            "✨",
+            &format!(
+                "Created new Pull Request #{}: {}",
+                pull_request_number, &pull_request_url,
+            ),
+        )?;
+
+        match config.pull_request_tracking {
+            PullRequestTracking::Trailer => {
+                message.insert(MessageSection::PullRequest, pull_request_url);
+            }
+            PullRequestTracking::Notes => git.set_pull_request_note(
+                config,
+                local_commit.oid,
+                pull_request_number,
+            )?,
+        }
+
+        let result = gh
+            .request_reviewers(pull_request_number, requested_reviewers)
+            .await;
+        match result {
+            Ok(()) => (),
+            Err(error) => {
+                output("⚠️", "Requesting reviewers failed")?;
+                for message in error.messages() {
+                    output("  ", message)?;
+                }
+            }
+        }
+
+        let result =
+            assign_pull_request(gh, config, opts, pull_request_number).await;
+        match result {
+            Ok(()) => (),
+            Err(error) => {
+                output("⚠️", "Setting assignees failed")?;
+                for message in error.messages() {
+                    output("  ", message)?;
+                }
+            }
+        }
+
+        if !pr_config.labels.is_empty() {
+            let result =
+                gh.add_labels(pull_request_number, pr_config.labels).await;
+            match result {
+                Ok(()) => (),
+                Err(error) => {
+                    output("⚠️", "Adding labels failed")?;
+                    for message in error.messages() {
+                        output("  ", message)?;
+                    }
+                }
+            }
+        }
+
+        if let Some(project) = &opts.target_project {
+            let result = gh.add_to_project(project, &newly_created_pr).await;
+            match result {
+                Ok(()) => output(
+                    "🗂️",
+                    &format!("Added to project '{}'", project.title),
+                )?,
+                Err(error) => {
+                    output("⚠️", "Adding to the project failed")?;
+                    for message in error.messages() {
+                        output("  ", message)?;
+                    }
+                }
+            }
+        }
+
+        pull_request_number
+    };
+
+    Ok(number)
+}
+
+/// Let the user edit the description of a new Pull Request, starting from the
+/// one in the commit message.
+async fn edit_description(
+    git: &crate::git::Git,
+    message: &MessageSectionsMap,
+    cleanup: &MessageCleanup,
+) -> Result<String> {
+    let hint = format!(
+        "Edit the description of the new Pull Request \"{}\". The commit \
+         message stays as it is.",
+        message
+            .get(&MessageSection::Title)
+            .map(String::as_str)
+            .unwrap_or_default()
+    );
+    let ignored = format!(
+        "Lines starting with '{}' are ignored.",
+        cleanup.comment_char
+    );
+    let mut hint = vec![hint.as_str()];
+    if cleanup.mode == CleanupMode::Strip {
+        hint.push(&ignored);
+    }
+    let template = cleanup.template(
+        &build_message(
+            message,
+            &[MessageSection::Summary, MessageSection::TestPlan],
+        ),
+        &hint,
+    );
+    let edited = crate::commands::edit::run_editor(git, &template).await?;
+
+    Ok(cleanup.clean_up(&edited))
+}
+
+/// Set the PR Stack section of the message for the Pull Request with the given
+/// number, or remove it, if the Pull Request is submitted with `--no-stack`.
+#[allow(clippy::too_many_arguments)]
+fn set_pr_stack(
+    message: &mut MessageSectionsMap,
+    opts: &DiffOptions,
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    pull_request_number: u64,
+    oid: Oid,
+    parent_oid: Oid,
+    directly_based_on_master: bool,
+) -> Result<()> {
+    if opts.no_stack {
+        message.remove(&MessageSection::PRStack);
+    } else {
+        message.insert(
+            MessageSection::PRStack,
+            get_pr_stack(
+                git,
+                config,
+                pull_request_number,
+                oid,
+                parent_oid,
+                opts.cherry_pick,
+                directly_based_on_master,
+            )?,
+        );
+    }
+
+    Ok(())
+}
+
+/// Get the given branches to point at the given commits. With `--no-push`, the
+/// branches are created locally and `None` is returned. Otherwise, the
+/// returned `git push` command updates all of them on GitHub at once (or
+/// `None` is returned if there are no branches to update).
+fn prepare_branch_updates(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    opts: &DiffOptions,
+    branch_updates: &[(Oid, &GitHubBranch)],
+) -> Result<Option<tokio::process::Command>> {
+    check_push_targets(config, branch_updates)?;
+
+    if opts.no_push {
+        git.create_local_branches(branch_updates)?;
+        for (oid, branch) in branch_updates {
+            output(
+                "🏠",
+                &format!(
+                    "Prepared local branch {} at {} - not pushed",
+                    branch.branch_name(),
+                    oid
+                ),
+            )?;
+        }
+
+        return Ok(None);
+    }
+
+    if branch_updates.is_empty() {
+        return Ok(None);
+    }
+
+    if config.confirm_force_push {
+        for (_, branch) in branch_updates {
+            confirm_overwrite(git, config, branch)?;
+        }
+    }
+
+    let mut cmd = tokio::process::Command::new("git");
+    cmd.arg("push").arg("--atomic").arg("--no-verify");
+    for push_option in config.push_options.iter().chain(&opts.push_option) {
+        cmd.arg(format!("--push-option={}", push_option));
+    }
+    if opts.force {
+        cmd.arg("--force");
+    } else {
+        // Only overwrite a branch on GitHub if it is still at the commit we
+        // last saw there, or, for a branch we have not seen before, if it does
+        // not exist.
+        let cache = lease_cache(git, config);
+        for (_, branch) in branch_updates {
+            let expected = expected_branch_oid(git, cache.as_ref(), branch)
+                .map(|oid| oid.to_string())
+                .unwrap_or_default();
+            cmd.arg(format!(
+                "--force-with-lease={}:{}",
+                branch.on_github(),
+                expected
+            ));
+        }
+    }
+    cmd.arg("--").arg(&config.remote_name);
+    for (oid, branch) in branch_updates {
+        cmd.arg(format!("{}:{}", oid, branch.on_github()));
+    }
+
+    Ok(Some(cmd))
+}
+
+/// Refuse to update the base branch (master), which spr only ever changes by
+/// landing Pull Requests. It is usually protected on GitHub, so that `git
+/// push` would fail halfway through with a less helpful error.
+fn check_push_targets(
+    config: &crate::config::Config,
+    branch_updates: &[(Oid, &GitHubBranch)],
+) -> Result<()> {
+    let master = config.master_ref.on_github();
+    if branch_updates
+        .iter()
+        .any(|(_, branch)| branch.on_github() == master)
+    {
+        return Err(Error::new(format!(
+            "Refusing to push to {}, the base branch: spr never pushes \
+             commits there directly. Does a Pull Request have {} as its \
+             head branch?",
+            config.master_ref.branch_name(),
+            config.master_ref.branch_name()
+        )));
+    }
+
+    Ok(())
+}
+
+/// The cache of branches to take the expected commits of `--force-with-lease`
+/// from, unless `--refresh` says not to trust it.
+fn lease_cache(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Option<PushCache> {
+    if config.refresh {
+        return None;
+    }
+    // A cache we cannot read is as good as none
+    PushCache::load(git.repo().path()).ok()
+}
+
+/// The commit we last saw the branch at on GitHub: where the cache says we
+/// pushed or fetched it, if it knows the branch, or else where its
+/// remote-tracking branch points. `None` for a branch we have not seen.
+fn expected_branch_oid(
+    git: &crate::git::Git,
+    cache: Option<&PushCache>,
+    branch: &GitHubBranch,
+) -> Option<Oid> {
+    cache
+        .and_then(|cache| cache.get(branch.on_github()))
+        .or_else(|| git.resolve_reference(branch.local()).ok())
+}
+
+/// Fetch the branches the cache does not know (but which we have seen
+/// before), so that their remote-tracking branches are up to date for
+/// `--force-with-lease`.
+async fn fetch_uncached_branches(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    branch_updates: &[(Oid, &GitHubBranch)],
+) -> Result<()> {
+    let cache = lease_cache(git, config);
+    let uncached: Vec<&GitHubBranch> = branch_updates
+        .iter()
+        .map(|(_, branch)| *branch)
+        .filter(|branch| {
+            cache
+                .as_ref()
+                .and_then(|cache| cache.get(branch.on_github()))
+                .is_none()
+                && git.resolve_reference(branch.local()).is_ok()
+        })
+        .collect();
+    if uncached.is_empty() {
+        return Ok(());
+    }
+
+    crate::git::Git::fetch_from_remote(&uncached, &config.remote_name).await
+}
+
+/// How often `spr diff` tries to push when GitHub rejects the push because
+/// branches changed there meanwhile.
+const MAX_PUSH_ATTEMPTS: usize = 3;
+
+/// Why `git push` failed, as far as we can tell from what it printed.
+#[derive(Debug, PartialEq, Eq)]
+enum PushFailure {
+    /// These branches (by their names on GitHub) changed on GitHub since we
+    /// last saw them, e.g. because the same stack is being submitted from
+    /// another machine at the same time
+    Race(Vec<String>),
+    /// Anything else, like failing to authenticate, which trying again does
+    /// not fix
+    Other,
+}
+
+/// What made `git push` fail, given what it wrote to stderr. Only branches
+/// rejected for having changed on GitHub (by `--force-with-lease`, or as
+/// non-fast-forward) count as a race, and not if anything else went wrong.
+fn push_failure(stderr: &str) -> PushFailure {
+    const FATAL: [&str; 6] = [
+        "Authentication failed",
+        "Permission denied",
+        "could not read Username",
+        "terminal prompts disabled",
+        "returned error: 403",
+        "[remote rejected]",
+    ];
+    if FATAL.iter().any(|fatal| stderr.contains(fatal)) {
+        return PushFailure::Other;
+    }
+
+    let mut raced = Vec::new();
+    for line in stderr.lines() {
+        // With `--atomic`, the other branches are rejected with "atomic push
+        // failed", which is just a consequence
+        if let Some((_, branch, reason)) = lazy_regex::regex_captures!(
+            r#"^\s*! \[rejected\]\s+\S+ -> (\S+) \((.+)\)$"#,
+            line
+        ) {
+            match reason {
+                "stale info" | "fetch first" | "non-fast-forward" => {
+                    raced.push(branch.to_string())
+                }
+                "atomic push failed" => {}
+                _ => return PushFailure::Other,
+            }
+        }
+    }
+
+    if raced.is_empty() {
+        PushFailure::Other
+    } else {
+        PushFailure::Race(raced)
+    }
+}
+
+/// Push the branches (see [`prepare_branch_updates`]). If GitHub rejects the
+/// push because some of them changed there since we last saw them, those are
+/// fetched and, if only spr pushed to them (e.g. from another machine), the
+/// push is tried again, expecting them where they are now, up to
+/// `MAX_PUSH_ATTEMPTS` times in all.
+async fn push_branch_updates(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    opts: &DiffOptions,
+    branch_updates: &[(Oid, &GitHubBranch)],
+) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        let mut cmd =
+            match prepare_branch_updates(git, config, opts, branch_updates)? {
+                Some(cmd) => cmd,
+                None => return Ok(()),
+            };
+        let push = timings::time(
+            Phase::Push,
+            cmd.stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::piped())
+                .output(),
+        )
+        .await?;
+        if push.status.success() {
+            for (_, branch) in branch_updates {
+                interrupt::record_completed(format!(
+                    "Pushed branch {}",
+                    branch.branch_name()
+                ));
+            }
+            return record_pushed_branches(git, branch_updates);
+        }
+
+        let raced = match push_failure(&String::from_utf8_lossy(&push.stderr)) {
+            PushFailure::Race(raced) if attempt < MAX_PUSH_ATTEMPTS => raced,
+            _ => {
+                console::Term::stderr().write_all(&push.stderr)?;
+                return Err(Error::new("git push failed"));
+            }
+        };
+        let raced: Vec<&GitHubBranch> = branch_updates
+            .iter()
+            .map(|(_, branch)| *branch)
+            .filter(|branch| raced.iter().any(|r| r == branch.branch_name()))
+            .collect();
+        output(
+            "🔁",
+            &format!(
+                "Branches changed on GitHub while pushing ({}) - fetching \
+                 them and trying again",
+                raced
+                    .iter()
+                    .map(|branch| branch.branch_name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )?;
+        tokio::time::sleep(std::time::Duration::from_secs(attempt as u64))
+            .await;
+        timings::time(
+            Phase::Fetch,
+            crate::git::Git::fetch_from_remote(&raced, &config.remote_name),
+        )
+        .await?;
+        // The next push expects them where they are now, unless someone
+        // (rather than spr elsewhere) added commits, which must not be thrown
+        // away
+        let master = git.resolve_reference(config.master_ref.local())?;
+        let mut fetched = Vec::new();
+        for branch in raced {
+            let head = match git.resolve_reference(branch.local()) {
+                Ok(head) => head,
+                Err(_) => continue,
+            };
+            if !git.foreign_commits(head, master)?.is_empty() {
+                return Err(Error::new(formatdoc!(
+                    "Branch {} on GitHub got commits spr did not create while \
+                     pushing. Not overwriting them (run spr diff --force to \
+                     overwrite them anyway).",
+                    branch.branch_name()
+                )));
+            }
+            fetched.push((branch.on_github(), head));
+        }
+        crate::push_cache::record(git.repo().path(), &fetched)?;
+
+        attempt += 1;
+    }
+}
+
+/// Remember where we pushed the branches, so that the next run does not need
+/// to fetch them to tell that they are unchanged.
+fn record_pushed_branches(
+    git: &crate::git::Git,
+    branch_updates: &[(Oid, &GitHubBranch)],
+) -> Result<()> {
+    let pushed: Vec<_> = branch_updates
+        .iter()
+        .map(|(oid, branch)| (branch.on_github(), *oid))
+        .collect();
+
+    crate::push_cache::record(git.repo().path(), &pushed)
+}
+
+/// The change of the description of a Pull Request on GitHub to `body`, as a
+/// unified diff.
+fn body_diff(pull_request: &PullRequest, body: &str) -> Vec<String> {
+    crate::utils::unified_diff(
+        pull_request.body.as_deref().unwrap_or_default(),
+        body,
+        3,
+    )
+}
+
+/// Show how the description of the Pull Request changes to `body`, and with
+/// `--confirm-body`, ask whether to change it. Returns whether to go ahead.
+fn review_body_update(
+    opts: &DiffOptions,
+    pull_request: &PullRequest,
+    body: &str,
+) -> Result<bool> {
+    let diff = body_diff(pull_request, body);
+    if diff.is_empty() {
+        // Only whitespace changes, which nobody can see
+        return Ok(true);
+    }
+
+    output(
+        "📝",
+        &format!(
+            "Changes to the description of Pull Request #{}:",
+            pull_request.number
+        ),
+    )?;
+    output_diff(&diff)?;
+
+    if !opts.confirm_body || !console::user_attended() {
+        return Ok(true);
+    }
+    let confirmed = dialoguer::Confirm::new()
+        .with_prompt("Update the description?")
+        .default(true)
+        .interact()?;
+    if !confirmed {
+        output("⏭️", "Keeping the description on GitHub as it is")?;
+    }
+
+    Ok(confirmed)
+}
+
+/// Make sure the user is fine with pushing to the given branch, if that
+/// overwrites commits on GitHub that spr did not create. Without a terminal to
+/// ask on, this fails.
+fn confirm_overwrite(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    branch: &GitHubBranch,
+) -> Result<()> {
+    // A branch we have not seen on GitHub has nothing to overwrite
+    let head = match git.resolve_reference(branch.local()) {
+        Ok(head) => head,
+        Err(_) => return Ok(()),
+    };
+    let master = git.resolve_reference(config.master_ref.local())?;
+    let foreign_commits = git.foreign_commits(head, master)?;
+    if foreign_commits.is_empty() {
+        return Ok(());
+    }
+
+    output(
+        "⚠️",
+        &format!(
+            "Branch {} on GitHub has commits spr did not create, whose \
+             changes this push overwrites:",
+            branch.branch_name()
+        ),
+    )?;
+    for oid in &foreign_commits {
+        output("  ", &git.describe_commit(*oid)?)?;
+    }
+
+    if !console::user_attended() {
+        return Err(Error::new(formatdoc!(
+            "Not overwriting branch {} without confirmation. Run spr in a \
+             terminal to confirm, or set spr.confirmForcePush to false.",
+            branch.branch_name()
+        )));
+    }
+
+    let confirmed = dialoguer::Confirm::new()
+        .with_prompt("Overwrite them?")
+        .default(false)
+        .interact()?;
+    if !confirmed {
+        return Err(Error::new("Aborted as per user request".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Determine the reviewers to request for a new Pull Request. Reviewers given
+/// on the command line take precedence over the ones from the commit message,
+/// unless `from_commit` is set, in which case both are combined. The default
+/// reviewers are always added. Aliases are expanded, and each name is only
+/// included once (compared case-insensitively, as GitHub does).
+pub(crate) fn collect_reviewers(
+    commit_reviewers: Option<&str>,
+    cli_reviewers: &[String],
+    from_commit: bool,
+    default_reviewers: &[String],
+    aliases: &[ReviewerAlias],
+) -> Vec<String> {
+    let cli_reviewers: Vec<String> = cli_reviewers
+        .iter()
+        .flat_map(|r| parse_name_list(r))
+        .collect();
+    let commit_reviewers =
+        commit_reviewers.map(parse_name_list).unwrap_or_default();
+
+    let reviewers = if cli_reviewers.is_empty() {
+        commit_reviewers
+    } else if from_commit {
+        [commit_reviewers, cli_reviewers].concat()
+    } else {
+        cli_reviewers
+    };
+
+    dedup_names(expand_reviewer_aliases(
+        [reviewers, default_reviewers.to_vec()].concat(),
+        aliases,
+    ))
+}
+
+/// How many of the given (distinct) reviewers are users, and how many are
+/// teams (written with a leading `#`). A team counts as a single reviewer,
+/// however many members it has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ReviewerCount {
+    users: usize,
+    teams: usize,
+}
+
+impl ReviewerCount {
+    fn of(reviewers: &[String]) -> Self {
+        let teams = reviewers
+            .iter()
+            .filter(|reviewer| reviewer.starts_with('#'))
+            .count();
+
+        ReviewerCount {
+            users: reviewers.len() - teams,
+            teams,
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.users + self.teams
+    }
+}
+
+/// Fail if a new Pull Request would get fewer than `required` reviewers (as
+/// returned by `collect_reviewers`, so without duplicates).
+fn check_reviewer_count(reviewers: &[String], required: usize) -> Result<()> {
+    let count = ReviewerCount::of(reviewers);
+    if count.total() >= required {
+        return Ok(());
+    }
+
+    Err(Error::new(format!(
+        "This Pull Request needs at least {} reviewer(s), but has {} ({} \
+         user(s) and {} team(s), where a team counts as one reviewer) - add \
+         more to the Reviewers section, or with --reviewer",
+        required,
+        count.total(),
+        count.users,
+        count.teams
+    )))
+}
+
+/// The reviewers of a new Pull Request from spr.reviewerPool: everyone in it,
+/// or the next ones in turn, as the strategy says.
+fn pool_reviewers(
+    opts: &DiffOptions,
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Result<Vec<String>> {
+    if opts.no_default_reviewers || config.reviewer_pool.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let strategy = match &opts.reviewer_strategy {
+        Some(strategy) => strategy.parse()?,
+        None => config.reviewer_strategy,
+    };
+    match strategy {
+        ReviewerStrategy::All => Ok(config.reviewer_pool.clone()),
+        ReviewerStrategy::RoundRobin => reviewer_rotation::next_reviewers(
+            git.repo().path(),
+            &config.reviewer_pool,
+            config.reviewer_pool_count,
+        ),
+    }
+}
+
+/// Set the assignees of a newly created Pull Request.
+async fn assign_pull_request(
+    gh: &impl Forge,
+    config: &crate::config::Config,
+    opts: &DiffOptions,
+    pull_request_number: u64,
+) -> Result<()> {
+    let current_user = if config.assign_self {
+        Some(gh.current_user_login().await?)
+    } else {
+        None
+    };
+
+    let assignees = collect_assignees(
+        &config.assignees,
+        &opts.assignee,
+        current_user.as_deref(),
+    );
+    if assignees.is_empty() {
+        return Ok(());
+    }
+
+    gh.add_assignees(pull_request_number, assignees).await
+}
+
+/// Determine the assignees for a new Pull Request: the ones configured, the
+/// ones given on the command line, and the current user if `spr.assignSelf` is
+/// enabled. Each name is only included once.
+fn collect_assignees(
+    config_assignees: &[String],
+    cli_assignees: &[String],
+    current_user: Option<&str>,
+) -> Vec<String> {
+    dedup_names(
+        config_assignees
+            .iter()
+            .cloned()
+            .chain(cli_assignees.iter().flat_map(|a| parse_name_list(a)))
+            .chain(current_user.map(String::from)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::prepared_commit_factory;
+    use clap::Parser;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_collect_reviewers_from_commit_only() {
+        assert_eq!(
+            collect_reviewers(Some("alice, bob (Bob B)"), &[], false, &[], &[]),
+            names(&["alice", "bob"])
+        );
+        assert!(collect_reviewers(None, &[], true, &[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_collect_reviewers_cli_replaces_commit() {
+        assert_eq!(
+            collect_reviewers(
+                Some("alice, bob"),
+                &names(&["carol", "dave,#team"]),
+                false,
+                &[],
+                &[]
+            ),
+            names(&["carol", "dave", "#team"])
+        );
+    }
+
+    #[test]
+    fn test_check_reviewer_count() {
+        // Names given more than once count once, and so does a team
+        let reviewers = collect_reviewers(
+            Some("alice, #core"),
+            &names(&["Alice", "bob, #core"]),
+            true,
+            &[],
+            &[],
+        );
+        assert_eq!(
+            ReviewerCount::of(&reviewers),
+            ReviewerCount { users: 2, teams: 1 }
+        );
+        assert!(check_reviewer_count(&reviewers, 3).is_ok());
+        assert!(check_reviewer_count(&reviewers, 0).is_ok());
+
+        let error = check_reviewer_count(&reviewers, 4).unwrap_err();
+        assert!(error.messages()[0].starts_with(
+            "This Pull Request needs at least 4 reviewer(s), but has 3 (2 \
+             user(s) and 1 team(s), where a team counts as one reviewer)"
+        ));
+        assert!(check_reviewer_count(&names(&["#core", "#infra"]), 2).is_ok());
+        assert!(check_reviewer_count(&[], 1).is_err());
+    }
+
+    #[test]
+    fn test_collect_reviewers_merged_and_deduped() {
+        assert_eq!(
+            collect_reviewers(
+                Some("alice, bob"),
+                &names(&["Bob", "carol, alice"]),
+                true,
+                &[],
+                &[]
+            ),
+            names(&["alice", "bob", "carol"])
+        );
+    }
+
+    #[test]
+    fn test_collect_reviewers_default_reviewers() {
+        let defaults = names(&["#platform", "Carol"]);
+        assert_eq!(
+            collect_reviewers(
+                Some("alice"),
+                &names(&["carol, bob"]),
+                false,
+                &defaults,
+                &[]
+            ),
+            names(&["carol", "bob", "#platform"])
+        );
+        assert_eq!(
+            collect_reviewers(None, &[], false, &defaults, &[]),
+            names(&["#platform", "Carol"])
+        );
+    }
+
+    #[test]
+    fn test_no_default_reviewers_flag() {
+        let mut config = crate::config::tests::config_factory();
+        config.default_reviewers = names(&["#platform"]);
+
+        let opts = DiffOptions::parse_from(["diff"]);
+        assert_eq!(opts.default_reviewers(&config), names(&["#platform"]));
+        let opts = DiffOptions::parse_from(["diff", "--no-default-reviewers"]);
+        assert!(opts.default_reviewers(&config).is_empty());
+    }
+
+    #[test]
+    fn test_pool_reviewers() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let mut config = crate::config::tests::config_factory();
+        let pick = |config: &crate::config::Config, args: &[&str]| {
+            let opts = DiffOptions::parse_from(
+                std::iter::once("diff").chain(args.iter().copied()),
+            );
+            pool_reviewers(&opts, &git, config).unwrap()
+        };
+        assert!(pick(&config, &[]).is_empty());
+
+        config.reviewer_pool = names(&["alice", "bob", "#core"]);
+        assert_eq!(pick(&config, &[]), names(&["alice", "bob", "#core"]));
+        assert!(pick(&config, &["--no-default-reviewers"]).is_empty());
+
+        // One submit after the other goes through the pool
+        let round_robin = ["--reviewer-strategy", "round-robin"];
+        assert_eq!(pick(&config, &round_robin), names(&["alice"]));
+        assert_eq!(pick(&config, &round_robin), names(&["bob"]));
+        config.reviewer_strategy = ReviewerStrategy::RoundRobin;
+        assert_eq!(pick(&config, &[]), names(&["#core"]));
+        assert_eq!(
+            pick(&config, &["--reviewer-strategy", "all"]),
+            names(&["alice", "bob", "#core"])
+        );
+        assert_eq!(pick(&config, &[]), names(&["alice"]));
+    }
+
+    #[test]
+    fn test_collect_reviewers_expands_aliases() {
+        let aliases = vec!["frontend=#frontend, alice".parse().unwrap()];
+        assert_eq!(
+            collect_reviewers(
+                Some("alice"),
+                &names(&["frontend,bob"]),
+                true,
+                &[],
+                &aliases
+            ),
+            names(&["alice", "#frontend", "bob"])
+        );
+    }
+
+    #[test]
+    fn test_select_commits_from_pull_request() {
+        let mut commits = vec![
+            prepared_commit_factory("a", "First"),
+            prepared_commit_factory("b", "Second"),
+            prepared_commit_factory("c", "Third"),
+        ];
+        commits[0].pull_request_number = Some(1);
+        commits[1].pull_request_number = Some(2);
+
+        let opts = DiffOptions::from_pull_request(2, None);
+        select_commits(&mut commits, &opts).unwrap();
+        assert_eq!(
+            commits.iter().map(|c| &c.short_id[..]).collect::<Vec<_>>(),
+            vec!["b", "c"]
+        );
+
+        let opts = DiffOptions::from_pull_request(3, None);
+        assert!(select_commits(&mut commits, &opts).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_submitted_group() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let one = crate::git::tests::create_commit(
+            &git,
+            "One\n\nFirst part",
+            &[master],
+        );
+        let two = crate::git::tests::create_commit(
+            &git,
+            "Two\n\nSquash-Into-Previous: true",
+            &[one],
+        );
+        let three = crate::git::tests::create_commit(&git, "Three", &[two]);
+        let commits = [one, two, three]
+            .iter()
+            .map(|&oid| git.prepare_commit(&config, oid).unwrap())
+            .collect();
+
+        let mut folded = commit_group::fold(commits).unwrap();
+        assert_eq!(folded.len(), 2);
+        assert_eq!(folded[0].message[&MessageSection::Title], "One");
+        // As if the Pull Request of the group had just been created
+        folded[0].pull_request_number = Some(5);
+        folded[0]
+            .message
+            .insert(MessageSection::PullRequest, config.pull_request_url(5));
+
+        let group_oid =
+            rewrite_submitted_commit(&git, &config, &mut folded[0], None)
+                .unwrap()
+                .unwrap();
+        let top_oid = rewrite_submitted_commit(
+            &git,
+            &config,
+            &mut folded[1],
+            Some(group_oid),
+        )
+        .unwrap()
+        .unwrap();
+
+        // The top commit of the group has the Pull Request, and the one
+        // below it keeps its own message
+        let top = git.prepare_commit(&config, group_oid).unwrap();
+        assert_eq!(top.pull_request_number, Some(5));
+        assert_eq!(top.message[&MessageSection::Title], "Two");
+        let bottom = git.prepare_commit(&config, top.parent_oid).unwrap();
+        assert_eq!(bottom.parent_oid, master);
+        assert_eq!(bottom.pull_request_number, None);
+        assert_eq!(bottom.message[&MessageSection::Summary], "First part");
+        let above = git.prepare_commit(&config, top_oid).unwrap();
+        assert_eq!(above.parent_oid, group_oid);
+        assert_eq!(above.pull_request_number, None);
+    }
+
+    #[test]
+    fn test_record_upstream() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+
+        let repo = git.repo();
+        repo.reference("refs/remotes/origin/master", master, true, "test")
+            .unwrap();
+        repo.tag_lightweight(
+            "v1.0",
+            &repo.find_object(master, None).unwrap(),
+            false,
+        )
+        .unwrap();
+        drop(repo);
+
+        let opts = DiffOptions::parse_from(["diff", "--set-upstream"]);
+        record_upstream(&git, &config, &opts).unwrap();
+        assert_eq!(
+            upstream::load(git.repo().path(), "master").unwrap(),
+            Some(Upstream {
+                base_ref: "refs/remotes/origin/master".to_string(),
+                oid: master,
+            })
+        );
+
+        // The name given with --stack-from is recorded in full
+        let opts = DiffOptions::parse_from(["diff", "--stack-from", "v1.0"]);
+        record_upstream(&git, &config, &opts).unwrap();
+        assert_eq!(
+            upstream::load(git.repo().path(), "master").unwrap(),
+            Some(Upstream {
+                base_ref: "refs/tags/v1.0".to_string(),
+                oid: master,
+            })
+        );
+
+        // A commit is not a reference that can move on
+        let opts = DiffOptions::parse_from([
+            "diff",
+            "--stack-from",
+            &master.to_string(),
+        ]);
+        record_upstream(&git, &config, &opts).unwrap();
+        assert_eq!(
+            upstream::load(git.repo().path(), "master")
+                .unwrap()
+                .unwrap()
+                .base_ref,
+            "refs/tags/v1.0"
+        );
+    }
+
+    #[test]
+    fn test_upstream_moved() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        git.repo()
+            .reference("refs/remotes/origin/master", master, true, "test")
+            .unwrap();
+        let head = crate::git::tests::create_commit(&git, "Change", &[master]);
+        git.repo()
+            .reference("refs/heads/master", head, true, "test")
+            .unwrap();
+
+        // Nothing is recorded
+        assert_eq!(upstream_moved(&git).unwrap(), None);
+
+        let opts = DiffOptions::parse_from(["diff", "--set-upstream"]);
+        record_upstream(&git, &config, &opts).unwrap();
+        assert_eq!(upstream_moved(&git).unwrap(), None);
+
+        // Upstream moves on without the stack
+        let other = crate::git::tests::create_commit(
+            &git,
+            "Someone else's change",
+            &[master],
+        );
+        git.repo()
+            .reference("refs/remotes/origin/master", other, true, "test")
+            .unwrap();
+        assert_eq!(
+            upstream_moved(&git).unwrap(),
+            Some(format!(
+                "origin/master moved from {} to {} since the stack was \
+                 submitted on it; run `git rebase origin/master` to bring the \
+                 stack up to date",
+                &master.to_string()[..8],
+                &other.to_string()[..8]
+            ))
+        );
+
+        // Once the stack is rebased, there is nothing to point out
+        let rebased =
+            crate::git::tests::create_commit(&git, "Change", &[other]);
+        git.repo()
+            .reference("refs/heads/master", rebased, true, "test")
+            .unwrap();
+        assert_eq!(upstream_moved(&git).unwrap(), None);
+
+        git.repo()
+            .find_reference("refs/remotes/origin/master")
+            .unwrap()
+            .delete()
+            .unwrap();
+        assert!(upstream_moved(&git)
+            .unwrap()
+            .unwrap()
+            .contains("does not exist anymore"));
+    }
+
+    #[test]
+    fn test_skip_empty_commits() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let mut config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let first = crate::git::tests::create_commit_with_files(
+            &git,
+            "First",
+            &[master],
+            &[("a.txt", "a")],
+        );
+        let empty = crate::git::tests::create_commit_with_files(
+            &git,
+            "Empty",
+            &[first],
+            &[("a.txt", "a")],
+        );
+        let third = crate::git::tests::create_commit_with_files(
+            &git,
+            "Third",
+            &[empty],
+            &[("a.txt", "a"), ("b.txt", "b")],
+        );
+        let commits: Vec<_> = [("a", first), ("b", empty), ("c", third)]
+            .iter()
+            .map(|&(id, oid)| PreparedCommit {
+                oid,
+                ..prepared_commit_factory(id, id)
+            })
+            .collect();
+
+        assert_eq!(
+            skip_empty_commits(&git, &config, &commits).unwrap(),
+            vec![false, false, false]
+        );
+
+        config.empty_commits = EmptyCommits::Skip;
+        assert_eq!(
+            skip_empty_commits(&git, &config, &commits).unwrap(),
+            vec![false, true, false]
+        );
+
+        config.empty_commits = EmptyCommits::Error;
+        assert!(skip_empty_commits(&git, &config, &commits).is_err());
+        assert!(skip_empty_commits(&git, &config, &commits[2..]).is_ok());
+    }
+
+    #[test]
+    fn test_write_urls() {
+        let config = crate::config::tests::config_factory();
+        let mut out = Vec::new();
+
+        write_urls(&mut out, &config, &[12, 13, 20]).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "https://github.com/acme/codez/pull/12\n\
+             https://github.com/acme/codez/pull/13\n\
+             https://github.com/acme/codez/pull/20\n"
+        );
+    }
+
+    #[test]
+    fn test_selected_prefix_len() {
+        assert_eq!(selected_prefix_len(&[]), Some(0));
+        assert_eq!(selected_prefix_len(&[0]), Some(1));
+        assert_eq!(selected_prefix_len(&[0, 1, 2]), Some(3));
+        // Not starting at the bottom
+        assert_eq!(selected_prefix_len(&[1, 2]), None);
+        // With a gap
+        assert_eq!(selected_prefix_len(&[0, 2]), None);
+        assert_eq!(selected_prefix_len(&[0, 1, 3]), None);
+    }
+
+    #[test]
+    fn test_select_commits_interactive_keeps_all() {
+        let mut commits = vec![
+            prepared_commit_factory("a", "First"),
+            prepared_commit_factory("b", "Second"),
+        ];
+
+        let opts = DiffOptions::parse_from(["diff", "--interactive"]);
+        select_commits(&mut commits, &opts).unwrap();
+        assert_eq!(commits.len(), 2);
+    }
+
+    #[test]
+    fn test_stack_from_submits_whole_branch() {
+        let opts = DiffOptions::parse_from(["diff", "--stack-from", "v1.0"]);
+        assert_eq!(opts.stack_from.as_deref(), Some("v1.0"));
+        assert!(opts.whole_branch());
+
+        assert!(DiffOptions::try_parse_from([
+            "diff",
+            "--stack-from",
+            "v1.0",
+            "--no-stack"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_find_stack_base_commit() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        git.repo()
+            .reference("refs/remotes/origin/master", root, true, "test")
+            .unwrap();
+        let one = crate::git::tests::create_commit(&git, "One", &[root]);
+        let two = crate::git::tests::create_commit(&git, "Two", &[one]);
+        git.repo()
+            .reference("refs/heads/master", two, true, "test")
+            .unwrap();
+
+        // The whole branch on top of master
+        let opts = DiffOptions::parse_from(["diff"]);
+        let (commits, base) =
+            find_stack(&opts, &git, &config, None).unwrap().unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(base, root);
+
+        // Only what is on top of the pinned commit
+        let sha = one.to_string();
+        let opts = DiffOptions::parse_from(["diff", "--base-commit", &sha]);
+        let (commits, base) = find_stack(&opts, &git, &config, Some(&sha))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            commits.iter().map(|c| c.oid).collect::<Vec<_>>(),
+            vec![two]
+        );
+        assert_eq!(base, one);
+
+        // Nothing on top of it
+        let sha = two.to_string();
+        assert!(find_stack(&opts, &git, &config, Some(&sha))
+            .unwrap()
+            .is_none());
+
+        assert!(DiffOptions::try_parse_from([
+            "diff",
+            "--base-commit",
+            &sha,
+            "--stack-from",
+            "v1.0"
+        ])
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_allow_empty_stack() {
+        crate::output::set_output_silenced(true);
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        git.repo()
+            .reference("refs/remotes/origin/master", root, true, "test")
+            .unwrap();
+        let forge = crate::forge::tests::MockForge::new(1, Vec::new());
+
+        // HEAD is on master, so there is nothing to submit
+        let opts = DiffOptions::parse_from(["diff"]);
+        assert_eq!(
+            diff(opts, &git, &forge, &config).await.unwrap(),
+            DiffOutcome::NothingToSubmit
+        );
+
+        // Which is just success with the flag
+        let opts = DiffOptions::parse_from(["diff", "--allow-empty-stack"]);
+        assert_eq!(
+            diff(opts, &git, &forge, &config).await.unwrap(),
+            DiffOutcome::Submitted {
+                pull_requests: Vec::new()
+            }
+        );
+        assert!(forge.calls().is_empty());
+    }
+
+    #[test]
+    fn test_split_off_work_in_progress() {
+        let mut commits = vec![
+            prepared_commit_factory("a", "First"),
+            prepared_commit_factory("b", "Second"),
+            prepared_commit_factory("c", "Scratch\n\nSkip-PR: true"),
+            prepared_commit_factory("d", "Fourth"),
+        ];
+
+        let skipped = split_off_work_in_progress(&mut commits);
+
+        assert_eq!(
+            commits.iter().map(|c| &c.short_id[..]).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            skipped.iter().map(|c| &c.short_id[..]).collect::<Vec<_>>(),
+            vec!["c", "d"]
+        );
+    }
+
+    #[test]
+    fn test_split_off_work_in_progress_none() {
+        let mut commits = vec![
+            prepared_commit_factory("a", "First"),
+            prepared_commit_factory("b", "Second"),
+        ];
+
+        assert!(split_off_work_in_progress(&mut commits).is_empty());
+        assert_eq!(commits.len(), 2);
+    }
+
+    #[test]
+    fn test_split_off_work_in_progress_bottom() {
+        let mut commits = vec![
+            prepared_commit_factory("a", "[WIP] First"),
+            prepared_commit_factory("b", "Second"),
+        ];
+
+        assert_eq!(split_off_work_in_progress(&mut commits).len(), 2);
+        assert!(commits.is_empty());
+    }
+
+    #[test]
+    fn test_collect_assignees_merged_and_deduped() {
+        assert_eq!(
+            collect_assignees(
+                &names(&["alice", "bob"]),
+                &names(&["Bob,carol", "dave"]),
+                None
+            ),
+            names(&["alice", "bob", "carol", "dave"])
+        );
+        assert!(collect_assignees(&[], &[], None).is_empty());
+    }
+
+    #[test]
+    fn test_collect_assignees_assign_self() {
+        assert_eq!(collect_assignees(&[], &[], Some("me")), names(&["me"]));
+        assert_eq!(
+            collect_assignees(&names(&["Me", "alice"]), &[], Some("me")),
+            names(&["Me", "alice"])
+        );
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_no_push() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let head = crate::git::tests::create_commit(&git, "Head", &[master]);
+        let base = crate::git::tests::create_commit(&git, "Base", &[master]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+        let base_branch = config.new_github_branch("spr/foo/base");
+        let opts = DiffOptions::parse_from(["diff", "--no-push"]);
+
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch), (base, &base_branch)],
+        )
+        .unwrap();
+
+        assert!(cmd.is_none());
+        assert_eq!(
+            git.resolve_reference("refs/heads/spr/foo/head").unwrap(),
+            head
+        );
+        assert_eq!(
+            git.resolve_reference("refs/heads/spr/foo/base").unwrap(),
+            base
+        );
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_push() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let head = crate::git::tests::create_commit(&git, "Head", &[master]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+        let opts = DiffOptions::parse_from(["diff"]);
+
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch)],
+        )
+        .unwrap()
+        .unwrap();
+
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            args,
+            vec![
+                "push".to_string(),
+                "--atomic".to_string(),
+                "--no-verify".to_string(),
+                "--force-with-lease=refs/heads/spr/foo/head:".to_string(),
+                "--".to_string(),
+                "origin".to_string(),
+                format!("{}:refs/heads/spr/foo/head", head),
+            ]
+        );
+        assert!(git.resolve_reference("refs/heads/spr/foo/head").is_err());
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_push_options() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let mut config = crate::config::tests::config_factory();
+        config.push_options = vec!["ci.skip".to_string()];
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let head = crate::git::tests::create_commit(&git, "Head", &[master]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+        let opts = DiffOptions::parse_from([
+            "diff",
+            "--push-option",
+            "merge_request.label=spr",
+        ]);
+
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch)],
+        )
+        .unwrap()
+        .unwrap();
+
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+        // Before the `--` that ends the options
+        let end_of_options = args.iter().position(|arg| arg == "--").unwrap();
+        assert_eq!(
+            args[3..5],
+            [
+                "--push-option=ci.skip".to_string(),
+                "--push-option=merge_request.label=spr".to_string(),
+            ]
+        );
+        assert!(end_of_options > 5);
+    }
+
+    fn lease_args(cmd: &tokio::process::Command) -> Vec<String> {
+        cmd.as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .filter(|arg| arg.starts_with("--force-with-lease="))
+            .collect()
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_lease_from_cache() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let mut config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let fetched = crate::git::tests::create_commit(&git, "Old", &[master]);
+        let pushed =
+            crate::git::tests::create_commit(&git, "Pushed", &[master]);
+        let head = crate::git::tests::create_commit(&git, "Head", &[pushed]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+        git.repo()
+            .reference(head_branch.local(), fetched, true, "test")
+            .unwrap();
+        let opts = DiffOptions::parse_from(["diff"]);
+
+        // Without a cache entry, the remote-tracking branch is the expectation
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch)],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            lease_args(&cmd),
+            vec![format!(
+                "--force-with-lease=refs/heads/spr/foo/head:{}",
+                fetched
+            )]
+        );
+
+        // The cache knows better, even though we did not fetch since pushing
+        crate::push_cache::record(
+            git.repo().path(),
+            &[(head_branch.on_github(), pushed)],
+        )
+        .unwrap();
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch)],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            lease_args(&cmd),
+            vec![format!(
+                "--force-with-lease=refs/heads/spr/foo/head:{}",
+                pushed
+            )]
+        );
+
+        // ...unless told not to trust it
+        config.refresh = true;
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch)],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            lease_args(&cmd),
+            vec![format!(
+                "--force-with-lease=refs/heads/spr/foo/head:{}",
+                fetched
+            )]
+        );
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_push_batches_commits() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let first = crate::git::tests::create_commit(&git, "First", &[master]);
+        let second = crate::git::tests::create_commit(&git, "Second", &[first]);
+        let second_base =
+            crate::git::tests::create_commit(&git, "Second base", &[master]);
+        let first_branch = config.new_github_branch("spr/foo/first");
+        let second_branch = config.new_github_branch("spr/foo/second");
+        let second_base_branch =
+            config.new_github_branch("spr/foo/master.second");
+        let opts = DiffOptions::parse_from(["diff", "--all"]);
+
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[
+                (first, &first_branch),
+                (second, &second_branch),
+                (second_base, &second_base_branch),
+            ],
+        )
+        .unwrap()
+        .unwrap();
+
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            &args[args.len() - 3..],
+            &[
+                format!("{}:refs/heads/spr/foo/first", first),
+                format!("{}:refs/heads/spr/foo/second", second),
+                format!("{}:refs/heads/spr/foo/master.second", second_base),
+            ]
+        );
+        assert!(args.contains(&"--atomic".to_string()));
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_refuses_base_branch() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let head = crate::git::tests::create_commit(&git, "Head", &[master]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+
+        for args in
+            [&["diff"][..], &["diff", "--no-push"], &["diff", "--force"]]
+        {
+            let opts = DiffOptions::parse_from(args);
+            let result = prepare_branch_updates(
+                &git,
+                &config,
+                &opts,
+                &[(head, &head_branch), (head, &config.master_ref)],
+            );
+            assert!(result.is_err());
+        }
+        // Nothing was touched locally either
+        assert_eq!(git.resolve_reference("refs/heads/master").unwrap(), master);
+        assert!(git.resolve_reference("refs/heads/spr/foo/head").is_err());
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_nothing_to_push() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let opts = DiffOptions::parse_from(["diff"]);
+
+        assert!(prepare_branch_updates(&git, &config, &opts, &[])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_lease_on_last_known_commit() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let pushed =
+            crate::git::tests::create_commit(&git, "Pushed", &[master]);
+        let head = crate::git::tests::create_commit(&git, "Head", &[pushed]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+        git.repo()
+            .reference(head_branch.local(), pushed, true, "test")
+            .unwrap();
+        let opts = DiffOptions::parse_from(["diff"]);
+
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch)],
+        )
+        .unwrap()
+        .unwrap();
+
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+        assert!(args.contains(&format!(
+            "--force-with-lease=refs/heads/spr/foo/head:{}",
+            pushed
+        )));
+        assert!(!args.contains(&"--force".to_string()));
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_confirm_without_foreign_commits() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let mut config = crate::config::tests::config_factory();
+        config.confirm_force_push = true;
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        git.repo()
+            .reference(config.master_ref.local(), master, true, "test")
+            .unwrap();
+        let pushed = crate::git::tests::create_commit(
+            &git,
+            "[spr] initial version\n\nCreated using spr 1.3.5",
+            &[master],
+        );
+        let head = crate::git::tests::create_commit(&git, "Head", &[pushed]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+        let new_branch = config.new_github_branch("spr/foo/new");
+        git.repo()
+            .reference(head_branch.local(), pushed, true, "test")
+            .unwrap();
+
+        // Only spr pushed to the existing branch, and the new one has nothing
+        // to overwrite, so there is nothing to confirm
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &DiffOptions::parse_from(["diff"]),
+            &[(head, &head_branch), (head, &new_branch)],
+        )
+        .unwrap();
+        assert!(cmd.is_some());
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_force() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let head = crate::git::tests::create_commit(&git, "Head", &[master]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+        let opts = DiffOptions::parse_from(["diff", "--force"]);
+
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch)],
+        )
+        .unwrap()
+        .unwrap();
+
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+        assert!(args.contains(&"--force".to_string()));
+        assert!(!args.iter().any(|arg| arg.starts_with("--force-with-lease")));
+    }
+
+    #[test]
+    fn test_set_pr_stack() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let mut message =
+            prepared_commit_factory("a", "Title\n\nSummary").message;
+        let opts = DiffOptions::parse_from(["diff"]);
+
+        set_pr_stack(
+            &mut message,
+            &opts,
+            &git,
+            &config,
+            7,
+            master,
+            master,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            message.get(&MessageSection::PRStack).unwrap(),
+            &crate::message::build_pr_stack_message(
+                &[7],
+                &Default::default(),
+                &config
+            )
+        );
+    }
+
+    #[test]
+    fn test_set_pr_stack_no_stack() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let mut message = prepared_commit_factory(
+            "a",
+            "Title\n\nSummary\n\nPR Stack:\nhttps://github.com/acme/codez/pull/7",
+        )
+        .message;
+        let opts = DiffOptions::parse_from(["diff", "--no-stack"]);
+
+        set_pr_stack(
+            &mut message,
+            &opts,
+            &git,
+            &config,
+            7,
+            master,
+            master,
+            false,
+        )
+        .unwrap();
+
+        assert!(!message.contains_key(&MessageSection::PRStack));
+        let body = crate::message::build_github_body(&message, &config);
+        assert!(!body.contains("PR Stack"));
+        assert!(!body.contains("/pull/7"));
+    }
+
+    #[test]
+    fn test_no_stack_submits_on_master() {
+        let opts = DiffOptions::parse_from(["diff", "--no-stack"]);
+        assert!(opts.on_master());
+        assert!(!DiffOptions::parse_from(["diff"]).on_master());
+        assert!(DiffOptions::try_parse_from(["diff", "--no-stack", "--all"])
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cherry_pick_credits_original_author() {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        let (_dir, git) = test_repo();
+        let mut config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        {
+            let repo = git.repo();
+            let mut repo_config = repo.config().unwrap();
+            repo_config.set_str("user.name", "Me").unwrap();
+            repo_config.set_str("user.email", "me@acme.com").unwrap();
+        }
+
+        // Someone else's commit, on top of one of ours that is not on master
+        let below = create_commit_with_files(
+            &git,
+            "Below",
+            &[master],
+            &[("below.txt", "below")],
+        );
+        let picked = {
+            let tree = git
+                .get_tree_oid_for_commit(create_commit_with_files(
+                    &git,
+                    "Their change",
+                    &[below],
+                    &[("below.txt", "below"), ("theirs.txt", "theirs")],
+                ))
+                .unwrap();
+            let repo = git.repo();
+            let author =
+                git2::Signature::now("Pat Author", "pat@acme.com").unwrap();
+            let tree = repo.find_tree(tree).unwrap();
+            let parent = repo.find_commit(below).unwrap();
+            let oid = repo.commit(
+                None,
+                &author,
+                &author,
+                "Their change\n\nTest Plan: ran it",
+                &tree,
+                &[&parent],
+            );
+            oid.unwrap()
+        };
+
+        for co_author in [true, false] {
+            config.cherry_pick_co_author = co_author;
+            let mut local_commit = git.prepare_commit(&config, picked).unwrap();
+            let changes = prepare_diff(
+                &DiffOptions::parse_from(["diff", "--cherry-pick"]),
+                &mut String::new(),
+                &git,
+                &config,
+                &mut local_commit,
+                master,
+                None,
+                &mut HashSet::new(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+            let pr_commit = changes.branch_updates[0].0;
+            let message = git
+                .repo()
+                .find_commit(pr_commit)
+                .unwrap()
+                .message()
+                .unwrap()
+                .trim_end()
+                .to_string();
+            assert_eq!(
+                message
+                    .ends_with("\n\nCo-authored-by: Pat Author <pat@acme.com>"),
+                co_author
+            );
+            // ...which is where `spr land` finds the trailer
+            assert_eq!(
+                git.co_author_trailers(pr_commit, master)
+                    .unwrap()
+                    .is_empty(),
+                !co_author
+            );
+        }
+
+        // Nobody needs crediting for the user's own commits
+        assert!(co_author_trailer(&git, picked).unwrap().is_some());
+        git.repo()
+            .config()
+            .unwrap()
+            .set_str("user.email", "Pat@acme.com")
+            .unwrap();
+        assert_eq!(co_author_trailer(&git, picked).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_credit_authors() {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        let (_dir, git) = test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let commit = create_commit_with_files(
+            &git,
+            "Their change\n\nTest Plan: ran it",
+            &[master],
+            &[("theirs.txt", "theirs")],
+        );
+        let mut local_commit = git.prepare_commit(&config, commit).unwrap();
+        let mut opts = DiffOptions::parse_from(["diff"]);
+        opts.credit_authors.insert(
+            commit,
+            vec![CommitIdentity {
+                name: "Pat Author".to_string(),
+                email: "pat@acme.com".to_string(),
+            }],
+        );
+
+        let changes = prepare_diff(
+            &opts,
+            &mut String::new(),
+            &git,
+            &config,
+            &mut local_commit,
+            master,
+            None,
+            &mut HashSet::new(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        // The credit goes into the commit of the Pull Request branch, for
+        // `spr land` to put into the landed commit
+        assert_eq!(
+            git.co_author_trailers(changes.branch_updates[0].0, master)
+                .unwrap(),
+            vec!["Co-authored-by: Pat Author <pat@acme.com>"]
+        );
+        // The local commit stays as it is
+        assert!(!local_commit
+            .message
+            .values()
+            .any(|text| text.contains("Pat")));
+    }
+
+    #[tokio::test]
+    async fn test_submit_plan() {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        let (_dir, git) = test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit_with_files(
+            &git,
+            "One\n\nTest Plan: ran it",
+            &[master],
+            &[("a", "1")],
+        );
+        let two = create_commit_with_files(
+            &git,
+            "Two\n\nTest Plan: ran it",
+            &[one],
+            &[("a", "2")],
+        );
+
+        // A stack of two new commits, as `spr diff --all` works it out
+        let opts = DiffOptions::parse_from(["diff", "--all", "--show-plan"]);
+        let mut commits = [one, two]
+            .into_iter()
+            .map(|oid| git.prepare_commit(&config, oid).unwrap())
+            .collect::<Vec<_>>();
+        let mut ref_names = git.get_all_ref_names().unwrap();
+        let mut changes = Vec::new();
+        for commit in commits.iter_mut() {
+            changes.push(
+                prepare_diff(
+                    &opts,
+                    &mut String::new(),
+                    &git,
+                    &config,
+                    commit,
+                    master,
+                    None,
+                    &mut ref_names,
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        commits.push(prepared_commit_factory("Three", "Three"));
+        changes.push(None);
+
+        let plan = submit_plan(&commits, &changes, &[], &config);
+        // The plan names the branches that get pushed
+        for (planned, changes) in zip(&plan, &changes) {
+            if let PlannedAction::Create { head, base } = &planned.action {
+                let pushed = changes
+                    .as_ref()
+                    .unwrap()
+                    .branch_updates
+                    .iter()
+                    .map(|(_, branch)| branch.branch_name())
+                    .collect::<Vec<_>>();
+                assert!(pushed.contains(&head.as_str()));
+                assert!(base == "master" || pushed.contains(&base.as_str()));
+            }
+        }
+        assert_eq!(
+            plan.iter()
+                .map(|planned| planned.action.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                PlannedAction::Create {
+                    head: "spr/foo/one".to_string(),
+                    base: "master".to_string(),
+                },
+                PlannedAction::Create {
+                    head: "spr/foo/two".to_string(),
+                    base: "spr/foo/master.two".to_string(),
+                },
+                PlannedAction::Unchanged(None),
+            ]
+        );
+
+        let lines = plan_lines(&plan);
+        assert_eq!(
+            lines,
+            vec![
+                format!("* {} Three", commits[2].short_id),
+                "|   nothing to submit".to_string(),
+                format!("* {} Two", commits[1].short_id),
+                "|   create a Pull Request: spr/foo/two → spr/foo/master.two"
+                    .to_string(),
+                format!("* {} One", commits[0].short_id),
+                "    create a Pull Request: spr/foo/one → master".to_string(),
+            ]
+        );
+
+        // Updates, with and without new commits
+        let planned = |action| PlannedCommit {
+            short_id: "1234567".to_string(),
+            title: "Title".to_string(),
+            rename: None,
+            action,
+        };
+        assert_eq!(
+            plan_lines(&[
+                planned(PlannedAction::Update {
+                    number: 12,
+                    head: "spr/foo/one".to_string(),
+                    base: "master".to_string(),
+                    push: true,
+                    reopen: false,
+                }),
+                planned(PlannedAction::Update {
+                    number: 13,
+                    head: "spr/foo/two".to_string(),
+                    base: "spr/foo/one".to_string(),
+                    push: false,
+                    reopen: true,
+                }),
+                planned(PlannedAction::Unchanged(Some(14))),
+            ])[1..]
+                .iter()
+                .step_by(2)
+                .collect::<Vec<_>>(),
+            vec![
+                "|   #14 is up to date",
+                "|   reopen and update the title and description of #13",
+                "    update #12: spr/foo/one → master",
+            ]
+        );
+
+        // The plan shows the branch of a Pull Request that gets renamed
+        // before anything else happens to it
+        commits[2].pull_request_number = Some(14);
+        let rename = BranchRename {
+            number: 14,
+            from: config.new_github_branch("spr/foo/old-three"),
+            to: config.new_github_branch("spr/foo/three"),
+        };
+        let plan =
+            submit_plan(&commits[2..], &changes[2..], &[rename], &config);
+        assert_eq!(
+            plan_lines(&plan),
+            vec![
+                format!("* {} Three", commits[2].short_id),
+                "    rename branch spr/foo/old-three → spr/foo/three"
+                    .to_string(),
+                "    #14 is up to date".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_foreign_authored_commits() {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        crate::output::set_output_silenced(true);
+        let (_dir, git) = test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        {
+            let repo = git.repo();
+            let mut repo_config = repo.config().unwrap();
+            repo_config.set_str("user.name", "Me").unwrap();
+            repo_config.set_str("user.email", "me@acme.com").unwrap();
+        }
+        let commit_by = |name: &str, email: &str, parent: Oid| {
+            let tree = git
+                .get_tree_oid_for_commit(create_commit_with_files(
+                    &git,
+                    name,
+                    &[parent],
+                    &[(name, name)],
+                ))
+                .unwrap();
+            let repo = git.repo();
+            let author = git2::Signature::now(name, email).unwrap();
+            let tree = repo.find_tree(tree).unwrap();
+            let parent = repo.find_commit(parent).unwrap();
+            repo.commit(None, &author, &author, name, &tree, &[&parent])
+                .unwrap()
+        };
+
+        // Ours (whatever the case of the email address), theirs, ours
+        let mine = commit_by("Me", "Me@Acme.com", master);
+        let theirs = commit_by("Pat Author", "pat@acme.com", mine);
+        let mine_again = commit_by("Me again", "me@acme.com", theirs);
+        let commits = [mine, theirs, mine_again]
+            .into_iter()
+            .map(|oid| git.prepare_commit(&config, oid).unwrap())
+            .collect::<Vec<_>>();
+
+        let foreign = foreign_authored_commits(&git, &commits).unwrap();
+        assert_eq!(
+            foreign
+                .iter()
+                .map(|(submitted, oid, author)| (
+                    *submitted,
+                    *oid,
+                    &author.email[..]
+                ))
+                .collect::<Vec<_>>(),
+            vec![(theirs, theirs, "pat@acme.com")]
+        );
+
+        // A group is submitted as its top commit, but checked by member
+        let mut group = commits[2].clone();
+        group.squashed = commits.clone();
+        let foreign = foreign_authored_commits(&git, &[group]).unwrap();
+        assert_eq!(
+            foreign
+                .iter()
+                .map(|(submitted, oid, _)| (*submitted, *oid))
+                .collect::<Vec<_>>(),
+            vec![(mine_again, theirs)]
+        );
+
+        // Only a warning by default, but an error with --strict-authorship
+        let mut opts = DiffOptions::parse_from(["diff"]);
+        check_authorship(&mut opts, &git, &commits).unwrap();
+        let mut opts = DiffOptions::parse_from(["diff", "--strict-authorship"]);
+        assert!(check_authorship(&mut opts, &git, &commits).is_err());
+        check_authorship(&mut opts, &git, &commits[..1]).unwrap();
+    }
+
+    #[test]
+    fn test_check_denied_paths() {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        let (_dir, git) = test_repo();
+        let mut config = crate::config::tests::config_factory();
+        config.submit_denylist = vec![".env".to_string(), "*.pem".to_string()];
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let code = create_commit_with_files(
+            &git,
+            "Code",
+            &[master],
+            &[("src/lib.rs", "fn main() {}")],
+        );
+        let secrets = create_commit_with_files(
+            &git,
+            "Secrets",
+            &[code],
+            &[("config/.env", "TOKEN=x"), ("certs/server.pem", "---")],
+        );
+        let commits = [
+            git.prepare_commit(&config, code).unwrap(),
+            git.prepare_commit(&config, secrets).unwrap(),
+        ];
+        let opts = DiffOptions::parse_from(["diff"]);
+
+        let denylist = PathFilter::from_patterns(
+            config.submit_denylist.iter().map(String::as_str),
+            "test",
+        )
+        .unwrap();
+        assert_eq!(
+            denied_paths(&git, &denylist, &commits).unwrap(),
+            vec![
+                (secrets, "certs/server.pem".to_string()),
+                (secrets, "config/.env".to_string()),
+            ]
+        );
+        assert!(check_denied_paths(&opts, &git, &config, &commits).is_err());
+
+        // Allowed files pass
+        check_denied_paths(&opts, &git, &config, &commits[..1]).unwrap();
+        // A group is checked by member
+        let mut group = commits[1].clone();
+        group.squashed = commits.to_vec();
+        assert!(check_denied_paths(&opts, &git, &config, &[group]).is_err());
+
+        let opts = DiffOptions::parse_from(["diff", "--allow-denied"]);
+        check_denied_paths(&opts, &git, &config, &commits).unwrap();
+        config.submit_denylist.clear();
+        let opts = DiffOptions::parse_from(["diff"]);
+        check_denied_paths(&opts, &git, &config, &commits).unwrap();
+    }
+
+    #[test]
+    fn test_check_fork_stack() {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        let (_dir, git) = test_repo();
+        let mut config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let bottom = create_commit_with_files(
+            &git,
+            "Bottom",
+            &[master],
+            &[("bottom.txt", "bottom")],
+        );
+        let top = create_commit_with_files(
+            &git,
+            "Top",
+            &[bottom],
+            &[("bottom.txt", "bottom"), ("top.txt", "top")],
+        );
+        let commits = [
+            git.prepare_commit(&config, bottom).unwrap(),
+            git.prepare_commit(&config, top).unwrap(),
+        ];
+        let opts = DiffOptions::parse_from(["diff", "--all"]);
+
+        // Within the same repository, stacking is fine
+        check_fork_stack(&opts, &config, &commits, master).unwrap();
+
+        config.fork_owner = Some("contributor".to_string());
+        let error =
+            check_fork_stack(&opts, &config, &commits, master).unwrap_err();
+        assert!(error.messages()[0].starts_with(&format!(
+            "Commit {} is stacked on other commits",
+            commits[1].short_id
+        )));
+        // The bottom commit is based on master, so it needs no base branch
+        check_fork_stack(&opts, &config, &commits[..1], master).unwrap();
+        // Cherry-picked, each Pull Request is based on master
+        let opts = DiffOptions::parse_from(["diff", "--all", "--cherry-pick"]);
+        check_fork_stack(&opts, &config, &commits, master).unwrap();
+    }
+
+    #[test]
+    fn test_code_owner_suggestions() {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        let (dir, git) = test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let commit = create_commit_with_files(
+            &git,
+            "Update",
+            &[master],
+            &[("src/lib.rs", "fn main() {}"), ("vendor/dep.rs", "")],
+        );
+        let mut commits = [git.prepare_commit(&config, commit).unwrap()];
+
+        // Without a CODEOWNERS file, there is nobody to suggest
+        assert!(code_owner_suggestions(&git, &commits).unwrap().is_empty());
+
+        std::fs::write(
+            dir.path().join("CODEOWNERS"),
+            "/src/ @alice\n/vendor/ @acme/deps\n",
+        )
+        .unwrap();
+        assert_eq!(
+            code_owner_suggestions(&git, &commits).unwrap()[&commit],
+            vec!["alice", "#deps"]
+        );
+
+        // The owners of ignored files are not asked
+        std::fs::create_dir(dir.path().join(".spr")).unwrap();
+        std::fs::write(dir.path().join(".spr/ignore"), "vendor/\n").unwrap();
+        assert_eq!(
+            code_owner_suggestions(&git, &commits).unwrap()[&commit],
+            vec!["alice"]
+        );
+
+        // Commits that have a Pull Request already get no suggestions
+        commits[0].pull_request_number = Some(1);
+        assert!(code_owner_suggestions(&git, &commits).unwrap().is_empty());
+    }
+
+    /// A repository with a local commit on master, and a Pull Request for it
+    /// that was closed while its branch had the given content.
+    fn closed_pull_request(
+        pr_content: &str,
+    ) -> (
+        tempfile::TempDir,
+        crate::git::Git,
+        PreparedCommit,
+        PullRequest,
+    ) {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        let (dir, git) = test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        git.repo()
+            .reference("refs/remotes/origin/master", master, true, "test")
+            .unwrap();
+
+        let local = create_commit_with_files(
+            &git,
+            "Change\n\nPull Request: #7\n",
+            &[master],
+            &[("file.txt", "new content")],
+        );
+        let pr_head = create_commit_with_files(
+            &git,
+            "[spr] initial version",
+            &[master],
+            &[("file.txt", pr_content)],
+        );
+
+        let mut pull_request = crate::github::tests::pull_request_factory(
+            &config,
+            7,
+            "spr/foo/change",
+            "master",
+        );
+        pull_request.state = PullRequestState::Closed;
+        pull_request.head_oid = pr_head;
+        pull_request.base_oid = master;
+
+        let local_commit = git.prepare_commit(&config, local).unwrap();
+        (dir, git, local_commit, pull_request)
+    }
+
+    async fn prepare(
+        args: &[&str],
+        git: &crate::git::Git,
+        mut local_commit: PreparedCommit,
+        pull_request: PullRequest,
+    ) -> Result<Option<PullRequestChanges>> {
+        let config = crate::config::tests::config_factory();
+        let master_base_oid = local_commit.parent_oid;
+        prepare_diff(
+            &DiffOptions::parse_from(args),
+            &mut String::new(),
+            git,
+            &config,
+            &mut local_commit,
+            master_base_oid,
+            Some(pull_request),
+            &mut HashSet::new(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_reopen_without_push_when_content_matches() {
+        let (_dir, git, local_commit, pull_request) =
+            closed_pull_request("new content");
+
+        let changes =
+            prepare(&["diff", "--reopen"], &git, local_commit, pull_request)
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert!(changes.reopen);
+        assert!(changes.branch_updates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reopen_with_push_when_content_changed() {
+        use crate::forge::tests::{Call, MockForge};
+
+        let (_dir, git, mut local_commit, pull_request) =
+            closed_pull_request("old content");
+        let config = crate::config::tests::config_factory();
+        let opts =
+            DiffOptions::parse_from(["diff", "--reopen", "-m", "update"]);
+
+        let changes = prepare(
+            &["diff", "--reopen", "-m", "update"],
+            &git,
+            local_commit.clone(),
+            pull_request.clone(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert!(changes.reopen);
+        assert_eq!(changes.branch_updates.len(), 1);
+        assert_eq!(changes.branch_updates[0].1.branch_name(), "spr/foo/change");
+
+        // The Pull Request is reopened before its branch is pushed, and not
+        // again when it is updated after the push
+        let forge = MockForge::new(8, vec![pull_request]);
+        let mut changes = vec![Some(changes)];
+        reopen_before_push(&forge, &mut changes).await.unwrap();
+        let reopen = PullRequestUpdate {
+            state: Some(PullRequestState::Open),
+            ..Default::default()
+        };
+        assert_eq!(forge.calls(), vec![Call::UpdatePullRequest(7, reopen)]);
+
+        let changes = changes.pop().flatten().unwrap();
+        assert!(!changes.reopen);
+        publish_diff(&opts, &git, &forge, &config, &mut local_commit, changes)
+            .await
+            .unwrap();
+        assert!(forge.calls()[1..].iter().all(|call| !matches!(
+            call,
+            Call::UpdatePullRequest(_, update) if update.state.is_some()
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_reopen_before_push_only_with_push() {
+        use crate::forge::tests::MockForge;
+
+        let (_dir, git, local_commit, pull_request) =
+            closed_pull_request("new content");
+        let changes =
+            prepare(&["diff", "--reopen"], &git, local_commit, pull_request)
+                .await
+                .unwrap();
+
+        // Without a push, reopening is left to the update of the Pull Request
+        let forge = MockForge::new(8, Vec::new());
+        let mut changes = vec![changes];
+        reopen_before_push(&forge, &mut changes).await.unwrap();
+        assert!(forge.calls().is_empty());
+        assert!(changes[0].as_ref().unwrap().reopen);
+    }
+
+    #[tokio::test]
+    async fn test_closed_pull_request_needs_reopen() {
+        let (_dir, git, local_commit, pull_request) =
+            closed_pull_request("new content");
+        assert!(prepare(&["diff"], &git, local_commit, pull_request)
+            .await
+            .is_err());
+
+        // An open one is up to date, so there is nothing to do
+        let (_dir, git, local_commit, mut pull_request) =
+            closed_pull_request("new content");
+        pull_request.state = PullRequestState::Open;
+        assert!(prepare(
+            &["diff", "--reopen"],
+            &git,
+            local_commit,
+            pull_request
+        )
+        .await
+        .unwrap()
+        .is_none());
+
+        // A merged one cannot be reopened
+        let (_dir, git, local_commit, mut pull_request) =
+            closed_pull_request("new content");
+        pull_request.merge_commit = Some(pull_request.head_oid);
+        assert!(prepare(
+            &["diff", "--reopen"],
+            &git,
+            local_commit,
+            pull_request
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_reviewers_and_labels_without_push() {
+        let (_dir, git, local_commit, mut pull_request) =
+            closed_pull_request("new content");
+        pull_request.state = PullRequestState::Open;
+
+        // The Pull Request is up to date, but its reviewers and labels still
+        // get updated
+        let changes = prepare(
+            &["diff", "--update-reviewers-and-labels"],
+            &git,
+            local_commit,
+            pull_request,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert!(changes.branch_updates.is_empty());
+        assert!(!changes.reopen);
+    }
+
+    #[tokio::test]
+    async fn test_sync_branch_name() {
+        use crate::forge::tests::{Call, MockForge};
+
+        let (_dir, git) = crate::git::tests::test_repo();
+        let mut config = crate::config::tests::config_factory();
+        config.sync_branch_names = true;
+        let local_commit =
+            crate::git::tests::prepared_commit_factory("1", "New title");
+        let mut pull_request = crate::github::tests::pull_request_factory(
+            &config,
+            7,
+            "spr/foo/old-title",
+            "master",
+        );
+
+        let rename = branch_rename(
+            &git,
+            &config,
+            &local_commit,
+            &pull_request,
+            &mut HashSet::new(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(rename.number, 7);
+        assert_eq!(rename.from.branch_name(), "spr/foo/old-title");
+        assert_eq!(rename.to.branch_name(), "spr/foo/new-title");
+
+        // The branch is renamed in place, which takes the Pull Request along
+        let forge = MockForge::new(8, Vec::new());
+        sync_branch_names(&git, &forge, std::slice::from_ref(&rename))
+            .await
+            .unwrap();
+        assert_eq!(
+            forge.calls(),
+            vec![Call::RenameBranch(
+                "spr/foo/old-title".to_string(),
+                "spr/foo/new-title".to_string()
+            )]
+        );
+
+        // Nothing to do when the title fits the branch, or for a closed
+        // Pull Request
+        let mut renamed = pull_request.clone();
+        renamed.head = rename.to;
+        assert!(branch_rename(
+            &git,
+            &config,
+            &local_commit,
+            &renamed,
+            &mut HashSet::new(),
+        )
+        .unwrap()
+        .is_none());
+        pull_request.state = PullRequestState::Closed;
+        assert!(branch_rename(
+            &git,
+            &config,
+            &local_commit,
+            &pull_request,
+            &mut HashSet::new(),
+        )
+        .unwrap()
+        .is_none());
+    }
 
-    let (pr_base_parent, base_branch) = if pr_base_tree == new_base_tree
-        && !needs_merging_master
-    {
-        // Case 1
-        (None, base_branch)
-    } else if base_branch.is_none()
-        && (directly_based_on_master || opts.cherry_pick)
-    {
-        // Case 2
-        (Some(master_base_oid), None)
-    } else {
-        // Case 3
+    #[test]
+    fn test_push_failure() {
+        // Another machine pushed one of the branches meanwhile
+        let stale = indoc!(
+            "
+            error: atomic push failed for ref refs/heads/spr/foo/two. status: 7
+            To github.com:acme/codez.git
+             ! [rejected]        c07fcce0f373 -> spr/foo/two (stale info)
+             ! [rejected]        9a3e2b1c4d5f -> spr/foo/one (atomic push failed)
+            error: failed to push some refs to 'github.com:acme/codez.git'
+            "
+        );
+        assert_eq!(
+            push_failure(stale),
+            PushFailure::Race(vec!["spr/foo/two".to_string()])
+        );
 
-        // We are constructing a base branch commit.
-        // One parent of the new base branch commit will be the current base
-        // commit, that could be either the top commit of an existing base
-        // branch, or a commit on master.
-        let mut parents = vec![pr_base_oid];
+        let non_fast_forward = indoc!(
+            "
+            To github.com:acme/codez.git
+             ! [rejected]        c07fcce0f373 -> spr/foo/one (non-fast-forward)
+             ! [rejected]        9a3e2b1c4d5f -> spr/foo/two (fetch first)
+            error: failed to push some refs to 'github.com:acme/codez.git'
+            "
+        );
+        assert_eq!(
+            push_failure(non_fast_forward),
+            PushFailure::Race(vec![
+                "spr/foo/one".to_string(),
+                "spr/foo/two".to_string()
+            ])
+        );
 
-        // If we need to rebase on master, make the master commit also a
-        // parent (except if the first parent is that same commit, we don't
-        // want duplicates in `parents`).
-        if needs_merging_master && pr_base_oid != master_base_oid {
-            parents.push(master_base_oid);
-        }
+        // Trying again does not help with these
+        let auth = indoc!(
+            "
+            remote: Invalid username or password.
+            fatal: Authentication failed for 'https://github.com/acme/codez.git/'
+            "
+        );
+        assert_eq!(push_failure(auth), PushFailure::Other);
+        assert_eq!(
+            push_failure(
+                "git@github.com: Permission denied (publickey).\n\
+                 fatal: Could not read from remote repository.\n"
+            ),
+            PushFailure::Other
+        );
+        let protected = indoc!(
+            "
+            To github.com:acme/codez.git
+             ! [rejected]        c07fcce0f373 -> spr/foo/one (stale info)
+             ! [remote rejected] 9a3e2b1c4d5f -> spr/foo/two (protected branch hook declined)
+            "
+        );
+        assert_eq!(push_failure(protected), PushFailure::Other);
+        assert_eq!(push_failure(""), PushFailure::Other);
+    }
 
-        let new_base_branch_commit = git.create_derived_commit(
-            local_commit.parent_oid,
-            &format!(
-                "[𝘀𝗽𝗿] {}\n\nCreated using spr {}\n\n[skip ci]",
-                if pull_request.is_some() {
-                    "changes introduced through rebase".to_string()
-                } else {
-                    format!(
-                        "changes to {} this commit is based on",
-                        config.master_ref.branch_name()
-                    )
-                },
-                env!("CARGO_PKG_VERSION"),
+    #[test]
+    fn test_body_diff() {
+        let config = crate::config::tests::config_factory();
+        let mut pull_request = crate::github::tests::pull_request_factory(
+            &config,
+            7,
+            "spr/foo/one",
+            "master",
+        );
+        pull_request.body =
+            Some("Old summary\r\n\r\nTest Plan:\r\nRun it\r\n".to_string());
+
+        assert_eq!(
+            body_diff(
+                &pull_request,
+                "New summary\n\nTest Plan:\nRun it\n\nMore\n"
             ),
-            new_base_tree,
-            &parents[..],
-        )?;
+            vec![
+                "@@ -1,4 +1,6 @@",
+                "-Old summary",
+                "+New summary",
+                " ",
+                " Test Plan:",
+                " Run it",
+                "+",
+                "+More",
+            ]
+        );
+        assert!(body_diff(
+            &pull_request,
+            "Old summary\n\nTest Plan:\nRun it\n"
+        )
+        .is_empty());
+    }
 
-        // If `base_branch` is `None` (which means a base branch does not exist
-        // yet), then make a `GitHubBranch` with a new name for a base branch
-        let base_branch = if let Some(base_branch) = base_branch {
-            base_branch
-        } else {
-            config.new_github_branch(
-                &config.get_base_branch_name(&git.get_all_ref_names()?, title),
-            )
+    #[test]
+    fn test_unexpected_base() {
+        let config = crate::config::tests::config_factory();
+        let based_on = |base: &str| {
+            let pull_request = crate::github::tests::pull_request_factory(
+                &config,
+                7,
+                "spr/foo/change",
+                base,
+            );
+            unexpected_base(&pull_request, &config)
         };
 
-        (Some(new_base_branch_commit), Some(base_branch))
-    };
+        assert_eq!(based_on("master"), None);
+        assert_eq!(based_on("spr/foo/master.change"), None);
+        // Changed on GitHub: to another branch, or to the branch of the Pull
+        // Request below, which spr would push its base branch commits onto
+        assert_eq!(based_on("release"), Some("release".to_string()));
+        assert_eq!(
+            based_on("spr/foo/other-change"),
+            Some("spr/foo/other-change".to_string())
+        );
+        assert_eq!(
+            based_on("old/foo/master.change"),
+            Some("old/foo/master.change".to_string())
+        );
+    }
 
-    let mut github_commit_message = opts.message.clone();
-    if pull_request.is_some() && github_commit_message.is_none() {
-        let input = {
-            let message_on_prompt = message_on_prompt.clone();
+    #[tokio::test]
+    async fn test_unexpected_base_is_corrected() {
+        use crate::forge::tests::{Call, MockForge};
 
-            tokio::task::spawn_blocking(move || {
-                dialoguer::Input::<String>::new()
-                    .with_prompt("Message (leave empty to abort)")
-                    .with_initial_text(message_on_prompt)
-                    .allow_empty(true)
-                    .interact_text()
-            })
-            .await??
-        };
+        let config = crate::config::tests::config_factory();
+        let (_dir, git, mut local_commit, mut pull_request) =
+            closed_pull_request("new content");
+        pull_request.state = PullRequestState::Open;
+        pull_request.base = config.new_github_branch("release");
 
-        if input.is_empty() {
-            return Err(Error::new("Aborted as per user request".to_string()));
-        }
+        // Only the base needs changing, which takes no push
+        let changes = prepare(
+            &["diff"],
+            &git,
+            local_commit.clone(),
+            pull_request.clone(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(changes.base_branch.is_none());
+        assert!(changes.branch_updates.is_empty());
 
-        *message_on_prompt = input.clone();
-        github_commit_message = Some(input);
+        let forge = MockForge::new(8, Vec::new());
+        publish_diff(
+            &DiffOptions::parse_from(["diff"]),
+            &git,
+            &forge,
+            &config,
+            &mut local_commit,
+            changes,
+        )
+        .await
+        .unwrap();
+        assert!(forge.calls().iter().any(|call| matches!(
+            call,
+            Call::UpdatePullRequest(7, update)
+                if update.base.as_deref() == Some("master")
+        )));
+
+        // Nothing is pushed to the unexpected base when the content changed
+        let (_dir, git, local_commit, mut pull_request) =
+            closed_pull_request("old content");
+        pull_request.state = PullRequestState::Open;
+        pull_request.base = config.new_github_branch("release");
+        let changes = prepare(
+            &["diff", "-m", "update"],
+            &git,
+            local_commit,
+            pull_request,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            changes
+                .branch_updates
+                .iter()
+                .map(|(_, branch)| branch.branch_name())
+                .collect::<Vec<_>>(),
+            vec!["spr/foo/change"]
+        );
     }
 
-    // Construct the new commit for the Pull Request branch. First parent is the
-    // current head commit of the Pull Request (we set this to the master base
-    // commit earlier if the Pull Request does not yet exist)
-    let mut pr_commit_parents = vec![pr_head_oid];
+    #[tokio::test]
+    async fn test_unexpected_base_with_warn() {
+        let mut config = crate::config::tests::config_factory();
+        config.base_mismatch = BaseMismatch::Warn;
+        let (_dir, git, mut local_commit, mut pull_request) =
+            closed_pull_request("old content");
+        pull_request.state = PullRequestState::Open;
+        pull_request.base = config.new_github_branch("release");
+        let master_base_oid = local_commit.parent_oid;
 
-    // If we prepared a commit earlier that needs merging into the Pull Request
-    // branch, then that commit is a parent of the new Pull Request commit.
-    if let Some(oid) = pr_base_parent {
-        // ...unless if that's the same commit as the one we added to
-        // pr_commit_parents first.
-        if pr_commit_parents.first() != Some(&oid) {
-            pr_commit_parents.push(oid);
+        let changes = prepare_diff(
+            &DiffOptions::parse_from(["diff", "-m", "update"]),
+            &mut String::new(),
+            &git,
+            &config,
+            &mut local_commit,
+            master_base_oid,
+            Some(pull_request),
+            &mut HashSet::new(),
+        )
+        .await
+        .unwrap();
+        assert!(changes.is_none());
+    }
+
+    fn changes(pull_request: Option<PullRequest>) -> PullRequestChanges {
+        let config = crate::config::tests::config_factory();
+        PullRequestChanges {
+            pull_request,
+            pull_request_branch: config.new_github_branch("spr/foo/change"),
+            base_branch: None,
+            branch_updates: Vec::new(),
+            requested_reviewers: PullRequestRequestReviewers {
+                reviewers: vec!["alice".to_string()],
+                team_reviewers: Vec::new(),
+            },
+            directly_based_on_master: true,
+            reopen: false,
         }
     }
 
-    // Create the new commit
-    let pr_commit = git.create_derived_commit(
-        local_commit.oid,
-        &format!(
-            "{}\n\nCreated using spr {}",
-            github_commit_message
-                .as_ref()
-                .map(|s| &s[..])
-                .unwrap_or("[𝘀𝗽𝗿] initial version"),
-            env!("CARGO_PKG_VERSION"),
-        ),
-        new_head_tree,
-        &pr_commit_parents[..],
-    )?;
+    #[tokio::test]
+    async fn test_publish_diff_creates_pull_request() {
+        use crate::forge::tests::{Call, MockForge};
 
-    let mut cmd = tokio::process::Command::new("git");
-    cmd.arg("push")
-        .arg("--atomic")
-        .arg("--no-verify")
-        .arg("--")
-        .arg(&config.remote_name)
-        .arg(format!("{}:{}", pr_commit, pull_request_branch.on_github()));
-
-    if let Some(pull_request) = pull_request {
-        // We are updating an existing Pull Request
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let forge = MockForge::new(7, Vec::new());
+        let mut local_commit =
+            crate::git::tests::prepared_commit_factory("1", "Change");
 
-        if needs_merging_master {
-            output(
-                "⚾",
-                &format!(
-                    "Commit was rebased - updating Pull Request #{}",
-                    pull_request.number
-                ),
-            )?;
-        } else {
-            output(
-                "🔁",
-                &format!(
-                    "Commit was changed - updating Pull Request #{}",
-                    pull_request.number
-                ),
-            )?;
-        }
+        let number = publish_diff(
+            &DiffOptions::parse_from(["diff", "--draft"]),
+            &git,
+            &forge,
+            &config,
+            &mut local_commit,
+            changes(None),
+        )
+        .await
+        .unwrap();
 
-        // Things we want to update in the Pull Request on GitHub
-        let mut pull_request_updates: PullRequestUpdate = Default::default();
+        assert_eq!(number, 7);
+        let calls = forge.calls();
+        assert_eq!(
+            calls[..2],
+            [
+                Call::CreatePullRequest {
+                    title: Some("Change".to_string()),
+                    base: "master".to_string(),
+                    head: "spr/foo/change".to_string(),
+                    draft: true,
+                },
+                Call::GetPullRequest(7),
+            ]
+        );
+        // The body gets the PR Stack, which needs the number of the new Pull
+        // Request
+        assert!(matches!(
+            &calls[2],
+            Call::UpdatePullRequest(7, update)
+                if update.body.as_ref().unwrap().contains("/pull/7")
+        ));
+        assert_eq!(
+            calls[3..],
+            [Call::RequestReviewers(
+                7,
+                PullRequestRequestReviewers {
+                    reviewers: vec!["alice".to_string()],
+                    team_reviewers: Vec::new(),
+                }
+            )]
+        );
+        assert_eq!(
+            local_commit.message.get(&MessageSection::PullRequest),
+            Some(&config.pull_request_url(7))
+        );
+    }
 
-        message.insert(
-            MessageSection::PRStack,
-            get_pr_stack(
-                git,
-                config,
-                pull_request.number,
-                local_commit.parent_oid,
-                opts.cherry_pick,
-                directly_based_on_master,
-            )?,
+    #[tokio::test]
+    async fn test_publish_diff_with_notes_keeps_commit_message() {
+        use crate::forge::tests::{Call, MockForge};
+
+        let (_dir, git) = crate::git::tests::test_repo();
+        let mut config = crate::config::tests::config_factory();
+        config.pull_request_tracking = PullRequestTracking::Notes;
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        // A message that spr would format differently if it rewrote it
+        let original = "Change\n\nSome summary  \n\nTest Plan:   ran it\n";
+        let oid = crate::git::tests::create_commit(&git, original, &[master]);
+        git.repo()
+            .reference("refs/heads/master", oid, true, "test")
+            .unwrap();
+        let mut local_commit = git.prepare_commit(&config, oid).unwrap();
+        let forge = MockForge::new(7, Vec::new());
+
+        publish_diff(
+            &DiffOptions::parse_from(["diff"]),
+            &git,
+            &forge,
+            &config,
+            &mut local_commit,
+            changes(None),
+        )
+        .await
+        .unwrap();
+
+        // GitHub gets the PR Stack...
+        assert!(forge.calls().iter().any(|call| matches!(
+            call,
+            Call::UpdatePullRequest(7, update)
+                if update.body.as_ref().unwrap().contains("/pull/7")
+        )));
+        // ...but the commit message does not, and the commit stays as it is
+        assert!(!local_commit.message.contains_key(&MessageSection::PRStack));
+        assert_eq!(
+            rewrite_submitted_commit(&git, &config, &mut local_commit, None)
+                .unwrap(),
+            None
         );
-        pull_request_updates.update_message(&pull_request, message);
+        assert_eq!(local_commit.oid, oid);
+        assert_eq!(git.resolve_reference("HEAD").unwrap(), oid);
+        assert_eq!(
+            git.repo().find_commit(oid).unwrap().message(),
+            Some(original)
+        );
+        assert_eq!(git.pull_request_note(&config, oid), Some(7));
+    }
 
-        if let Some(base_branch) = base_branch {
-            // We are using a base branch.
-
-            if let Some(base_branch_commit) = pr_base_parent {
-                // ...and we prepared a new commit for it, so we need to push an
-                // update of the base branch.
-                cmd.arg(format!(
-                    "{}:{}",
-                    base_branch_commit,
-                    base_branch.on_github()
-                ));
-            }
+    #[test]
+    fn test_with_pr_stack_from() {
+        let mut config = crate::config::tests::config_factory();
+        let mut pull_request = crate::github::tests::pull_request_factory(
+            &config,
+            7,
+            "spr/foo/change",
+            "master",
+        );
+        pull_request
+            .sections
+            .insert(MessageSection::PRStack, "* #7".to_string());
+        let message = crate::message::parse_message(
+            "Change\n\nSummary",
+            MessageSection::Title,
+        );
 
-            // Push the new commit onto the Pull Request branch (and also the
-            // new base commit, if we added that to cmd above).
-            run_command(&mut cmd)
-                .await
-                .reword("git push failed".to_string())?;
+        // With trailers, the PR Stack is in the commit message already
+        let compared = with_pr_stack_from(&message, &pull_request, &config);
+        assert!(!compared.contains_key(&MessageSection::PRStack));
 
-            // If the Pull Request's base is not set to the base branch yet,
-            // change that now.
-            if pull_request.base.branch_name() != base_branch.branch_name() {
-                pull_request_updates.base =
-                    Some(base_branch.branch_name().to_string());
+        config.pull_request_tracking = PullRequestTracking::Notes;
+        let compared = with_pr_stack_from(&message, &pull_request, &config);
+        assert_eq!(compared[&MessageSection::PRStack], "* #7");
+        assert_eq!(compared[&MessageSection::Summary], "Summary");
+    }
+
+    #[tokio::test]
+    async fn test_publish_diff_adds_to_project() {
+        use crate::forge::tests::{Call, MockForge};
+
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let mut forge = MockForge::new(7, Vec::new());
+        forge.projects = vec![Project {
+            id: "PVT_3".to_string(),
+            number: 3,
+            title: "Roadmap".to_string(),
+            closed: false,
+        }];
+        let mut opts =
+            DiffOptions::parse_from(["diff", "--project", "roadmap"]);
+        opts.target_project =
+            Some(forge.find_project("roadmap").await.unwrap());
+        let mut local_commit =
+            crate::git::tests::prepared_commit_factory("1", "Change");
+
+        publish_diff(
+            &opts,
+            &git,
+            &forge,
+            &config,
+            &mut local_commit,
+            changes(None),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(forge.calls().last(), Some(&Call::AddToProject(3, 7)));
+        assert!(forge.find_project("Backlog").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_publish_diff_with_body_file() {
+        use crate::forge::tests::{Call, MockForge};
+
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let forge = MockForge::new(7, Vec::new());
+        let mut local_commit = crate::git::tests::prepared_commit_factory(
+            "1",
+            "Change\n\nCommit summary",
+        );
+        let original_summary =
+            local_commit.message.get(&MessageSection::Summary).cloned();
+
+        let body_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(body_file.path(), "The *real* description\n").unwrap();
+        let mut opts = DiffOptions::parse_from([
+            "diff".as_ref(),
+            "--body-file".as_ref(),
+            body_file.path().as_os_str(),
+        ]);
+        opts.body = Some(std::fs::read_to_string(body_file.path()).unwrap());
+
+        publish_diff(
+            &opts,
+            &git,
+            &forge,
+            &config,
+            &mut local_commit,
+            changes(None),
+        )
+        .await
+        .unwrap();
+
+        let calls = forge.calls();
+        let body = match &calls[2] {
+            Call::UpdatePullRequest(7, update) => update.body.clone().unwrap(),
+            call => panic!("unexpected call {:?}", call),
+        };
+        assert_eq!(
+            crate::message::marked_description(&body),
+            Some("The *real* description")
+        );
+        assert!(!body.contains("Commit summary"));
+        // spr still adds its own sections
+        assert!(body.contains("/pull/7"));
+        // The commit message is left alone
+        assert_eq!(
+            local_commit.message.get(&MessageSection::Summary).cloned(),
+            original_summary
+        );
+
+        // Updating the Pull Request later, after the commit message changed,
+        // keeps the description, and updates the sections spr manages
+        let mut pull_request = crate::github::tests::pull_request_factory(
+            &config,
+            7,
+            "spr/foo/change",
+            "master",
+        );
+        pull_request.title = "Change".to_string();
+        pull_request.body = Some(format!(
+            "{}\nThe *real* description\n{}\n",
+            crate::message::DESCRIPTION_START_MARKER,
+            crate::message::DESCRIPTION_END_MARKER
+        ));
+        local_commit
+            .message
+            .insert(MessageSection::Summary, "New summary".to_string());
+        let forge = MockForge::new(8, Vec::new());
+
+        publish_diff(
+            &DiffOptions::parse_from(["diff"]),
+            &git,
+            &forge,
+            &config,
+            &mut local_commit,
+            changes(Some(pull_request)),
+        )
+        .await
+        .unwrap();
+
+        let body = match &forge.calls()[..] {
+            [Call::UpdatePullRequest(7, update)] => {
+                update.body.clone().unwrap()
             }
-        } else {
-            // The Pull Request is against the master branch. In that case we
-            // only need to push the update to the Pull Request branch.
-            run_command(&mut cmd)
-                .await
-                .reword("git push failed".to_string())?;
-        }
+            calls => panic!("unexpected calls {:?}", calls),
+        };
+        assert_eq!(
+            crate::message::marked_description(&body),
+            Some("The *real* description")
+        );
+        assert!(!body.contains("New summary"));
+        assert!(body.contains("/pull/7"));
+    }
 
-        if !pull_request_updates.is_empty() {
-            gh.update_pull_request(pull_request.number, &pull_request_updates)
-                .await?;
-        }
-    } else {
-        // We are creating a new Pull Request.
+    #[tokio::test]
+    async fn test_publish_diff_applies_pr_config_block() {
+        use crate::forge::tests::{Call, MockForge};
 
-        // If there's a base branch, add it to the push
-        if let (Some(base_branch), Some(base_branch_commit)) =
-            (&base_branch, pr_base_parent)
-        {
-            cmd.arg(format!(
-                "{}:{}",
-                base_branch_commit,
-                base_branch.on_github()
-            ));
-        }
-        // Push the pull request branch and the base branch if present
-        run_command(&mut cmd)
-            .await
-            .reword("git push failed".to_string())?;
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let forge = MockForge::new(7, Vec::new());
+        let mut local_commit = crate::git::tests::prepared_commit_factory(
+            "1",
+            "Change\n\nSummary\n\n```spr\nlabels: [bug, ui]\ndraft: true\n```",
+        );
 
-        // Then call GitHub to create the Pull Request.
-        let base_branch_name = base_branch
-            .as_ref()
-            .unwrap_or(&config.master_ref)
-            .branch_name()
-            .to_string();
+        publish_diff(
+            &DiffOptions::parse_from(["diff"]),
+            &git,
+            &forge,
+            &config,
+            &mut local_commit,
+            changes(None),
+        )
+        .await
+        .unwrap();
 
-        let pull_request_number = gh
-            .create_pull_request(
-                message,
-                base_branch_name,
-                pull_request_branch.branch_name().to_string(),
-                opts.draft,
+        let calls = forge.calls();
+        assert!(matches!(
+            &calls[0],
+            Call::CreatePullRequest { draft: true, .. }
+        ));
+        assert!(matches!(
+            &calls[2],
+            Call::UpdatePullRequest(7, update)
+                if !update.body.as_ref().unwrap().contains("```spr")
+        ));
+        assert_eq!(
+            calls.last(),
+            Some(&Call::AddLabels(
+                7,
+                vec!["bug".to_string(), "ui".to_string()]
+            ))
+        );
+        // The commit keeps its block
+        assert!(
+            local_commit.message[&MessageSection::Summary].contains("```spr")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_push_only_does_not_touch_pull_requests() {
+        use crate::forge::tests::MockForge;
+
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let forge = MockForge::new(7, Vec::new());
+        let opts = DiffOptions::parse_from(["diff", "--push-only", "--draft"]);
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let head = crate::git::tests::create_commit(&git, "Head", &[master]);
+
+        for pull_request in [
+            None,
+            Some(crate::github::tests::pull_request_factory(
+                &config,
+                3,
+                "spr/foo/change",
+                "master",
+            )),
+        ] {
+            let mut local_commit =
+                crate::git::tests::prepared_commit_factory("1", "Change");
+            let original_message = local_commit.message.clone();
+            let mut changes = changes(pull_request);
+            changes.branch_updates =
+                vec![(head, config.new_github_branch("spr/foo/change"))];
+
+            let number = publish_or_report(
+                &opts,
+                &git,
+                &forge,
+                &config,
+                &mut local_commit,
+                changes,
             )
-            .await?;
+            .await
+            .unwrap();
 
-        message.insert(
-            MessageSection::PRStack,
-            get_pr_stack(
-                git,
-                config,
-                pull_request_number,
-                local_commit.parent_oid,
-                opts.cherry_pick,
-                directly_based_on_master,
-            )?,
+            assert_eq!(number, None);
+            assert_eq!(local_commit.message, original_message);
+        }
+        assert!(forge.calls().is_empty());
+    }
+
+    #[test]
+    fn test_prepare_branch_updates_push_only() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let head = crate::git::tests::create_commit(&git, "Head", &[master]);
+        let base = crate::git::tests::create_commit(&git, "Base", &[master]);
+        let head_branch = config.new_github_branch("spr/foo/head");
+        let base_branch = config.new_github_branch("spr/foo/master.head");
+        let opts = DiffOptions::parse_from(["diff", "--push-only"]);
+        assert!(!opts.updates_pull_requests());
+
+        let cmd = prepare_branch_updates(
+            &git,
+            &config,
+            &opts,
+            &[(head, &head_branch), (base, &base_branch)],
+        )
+        .unwrap()
+        .unwrap();
+
+        let args: Vec<_> = cmd
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            &args[args.len() - 2..],
+            &[
+                format!("{}:refs/heads/spr/foo/head", head),
+                format!("{}:refs/heads/spr/foo/master.head", base),
+            ]
         );
+        assert!(git.resolve_reference("refs/heads/spr/foo/head").is_err());
+    }
 
-        let mut pull_request_updates: PullRequestUpdate = Default::default();
-        let newly_created_pr =
-            gh.clone().get_pull_request(pull_request_number).await?;
-        pull_request_updates.update_message(&newly_created_pr, message);
-        gh.update_pull_request(pull_request_number, &pull_request_updates)
-            .await?;
+    #[tokio::test]
+    async fn test_publish_diff_reviewers_and_labels_only_on_create() {
+        use crate::forge::tests::{Call, MockForge};
 
-        let pull_request_url = config.pull_request_url(pull_request_number);
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let pull_request = crate::github::tests::pull_request_factory(
+            &config,
+            7,
+            "spr/foo/change",
+            "master",
+        );
+        let publish = |args: &'static [&'static str], pull_request| {
+            let git = &git;
+            let config = &config;
+            async move {
+                let forge = MockForge::new(7, Vec::new());
+                let mut local_commit =
+                    crate::git::tests::prepared_commit_factory(
+                        "1",
+                        "Change\n\nSummary\n\n```spr\nlabels: [bug]\n```",
+                    );
+                publish_diff(
+                    &DiffOptions::parse_from(args),
+                    git,
+                    &forge,
+                    config,
+                    &mut local_commit,
+                    changes(pull_request),
+                )
+                .await
+                .unwrap();
+                forge.calls()
+            }
+        };
+        let applied = |calls: &[Call]| {
+            (
+                calls
+                    .iter()
+                    .any(|call| matches!(call, Call::RequestReviewers(7, _))),
+                calls.contains(&Call::AddLabels(7, vec!["bug".to_string()])),
+            )
+        };
 
-        output(
-            "✨",
-            &format!(
-                "Created new Pull Request #{}: {}",
-                pull_request_number, &pull_request_url,
+        // A new Pull Request gets them
+        assert_eq!(applied(&publish(&["diff"], None).await), (true, true));
+        // An existing one keeps the ones it has on GitHub
+        assert_eq!(
+            applied(&publish(&["diff"], Some(pull_request.clone())).await),
+            (false, false)
+        );
+        // ...unless asked for
+        assert_eq!(
+            applied(
+                &publish(
+                    &["diff", "--update-reviewers-and-labels"],
+                    Some(pull_request)
+                )
+                .await
             ),
-        )?;
+            (true, true)
+        );
+    }
 
-        message.insert(MessageSection::PullRequest, pull_request_url);
+    #[tokio::test]
+    async fn test_prepare_diff_skips_reviewers_of_existing_pull_request() {
+        let (_dir, git, mut local_commit, mut pull_request) =
+            closed_pull_request("old content");
+        pull_request.state = PullRequestState::Open;
+        local_commit
+            .message
+            .insert(MessageSection::Reviewers, "alice".to_string());
 
-        let result = gh
-            .request_reviewers(pull_request_number, requested_reviewers)
-            .await;
-        match result {
-            Ok(()) => (),
-            Err(error) => {
-                output("⚠️", "Requesting reviewers failed")?;
-                for message in error.messages() {
-                    output("  ", message)?;
-                }
-            }
-        }
+        let changes = prepare(
+            &["diff", "-m", "update"],
+            &git,
+            local_commit,
+            pull_request,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            changes.requested_reviewers,
+            PullRequestRequestReviewers::default()
+        );
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_publish_diff_updates_pull_request() {
+        use crate::forge::tests::{Call, MockForge};
+
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = crate::config::tests::config_factory();
+        let forge = MockForge::new(8, Vec::new());
+        let mut local_commit =
+            crate::git::tests::prepared_commit_factory("1", "PR 7");
+        let mut pull_request = crate::github::tests::pull_request_factory(
+            &config,
+            7,
+            "spr/foo/change",
+            "spr/foo/master.change",
+        );
+        pull_request.state = PullRequestState::Closed;
+        let mut changes = changes(Some(pull_request));
+        changes.base_branch =
+            Some(config.new_github_branch("spr/foo/master.other"));
+        changes.reopen = true;
+
+        publish_diff(
+            &DiffOptions::parse_from(["diff"]),
+            &git,
+            &forge,
+            &config,
+            &mut local_commit,
+            changes,
+        )
+        .await
+        .unwrap();
+
+        // Only the existing Pull Request is updated, in a single call, and no
+        // reviewers are requested again
+        let calls = forge.calls();
+        assert_eq!(calls.len(), 1);
+        assert!(matches!(
+            &calls[0],
+            Call::UpdatePullRequest(7, update)
+                if update.title.is_none()
+                    && update.body.is_some()
+                    && update.base.as_deref() == Some("spr/foo/master.other")
+                    && update.state == Some(PullRequestState::Open)
+        ));
+    }
 }
@@ -0,0 +1,325 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::{
+    commands::diff::{diff, DiffOptions},
+    error::{Error, Result, ResultExt},
+    git::find_pull_request_commit,
+    message::{
+        build_commit_message, parse_message, validate_commit_message,
+        CleanupMode, MessageCleanup, MessageSection, MessageSectionsMap,
+    },
+    output::{output, write_commit_title},
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct EditOptions {
+    /// Number of the Pull Request whose commit message to edit
+    pull_request: u64,
+}
+
+/// The text the user edits: the commit message, and a hint about how it is
+/// used.
+fn message_template(
+    message: &MessageSectionsMap,
+    number: u64,
+    cleanup: &MessageCleanup,
+) -> String {
+    let ignored = if cleanup.mode == CleanupMode::Strip {
+        format!(
+            "Lines starting with '{}' are ignored, and an empty message \
+             aborts the edit.",
+            cleanup.comment_char
+        )
+    } else {
+        "An empty message aborts the edit.".to_string()
+    };
+    cleanup.template(
+        &build_commit_message(message),
+        &[
+            &format!(
+                "Edit the commit message of Pull Request #{}, which becomes \
+                 its title and description.",
+                number
+            ),
+            &ignored,
+        ],
+    )
+}
+
+/// The new message of the commit, given its old message and the text the user
+/// saved. The Pull Request the commit belongs to is kept, so that the commit
+/// stays linked to it even if the user removed that line.
+fn edited_message(
+    original: &MessageSectionsMap,
+    edited: &str,
+    cleanup: &MessageCleanup,
+) -> Result<MessageSectionsMap> {
+    let edited = cleanup.clean_up(edited);
+    if edited.is_empty() {
+        return Err(Error::new(
+            "Aborting the edit due to an empty commit message",
+        ));
+    }
+
+    let mut message = parse_message(&edited, MessageSection::Title);
+    if let Some(pull_request) = original.get(&MessageSection::PullRequest) {
+        message.insert(MessageSection::PullRequest, pull_request.clone());
+    }
+
+    Ok(message)
+}
+
+/// The editor used when none is configured, like Git's default.
+const DEFAULT_EDITOR: &str = "vi";
+
+/// The editor to run, given the values of `GIT_EDITOR`, `core.editor`,
+/// `VISUAL` and `EDITOR`, in the order Git tries them. Empty values count as
+/// unset.
+pub(crate) fn resolve_editor(
+    git_editor: Option<&str>,
+    core_editor: Option<&str>,
+    visual: Option<&str>,
+    editor: Option<&str>,
+) -> String {
+    [git_editor, core_editor, visual, editor]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .find(|editor| !editor.is_empty())
+        .unwrap_or(DEFAULT_EDITOR)
+        .to_string()
+}
+
+/// The editor configured for the repository, see [`resolve_editor`].
+pub(crate) fn configured_editor(git: &crate::git::Git) -> String {
+    let core_editor = git
+        .repo()
+        .config()
+        .and_then(|config| config.get_string("core.editor"))
+        .ok();
+    let var = |name: &str| std::env::var(name).ok();
+
+    resolve_editor(
+        var("GIT_EDITOR").as_deref(),
+        core_editor.as_deref(),
+        var("VISUAL").as_deref(),
+        var("EDITOR").as_deref(),
+    )
+}
+
+/// Open `text` in the editor Git uses (see [`configured_editor`]), and return
+/// what the user saved. All commands that let the user edit text go through
+/// here.
+pub(crate) async fn run_editor(
+    git: &crate::git::Git,
+    text: &str,
+) -> Result<String> {
+    let editor = configured_editor(git);
+
+    let path = git.repo().path().join("SPR_EDITMSG");
+    std::fs::write(&path, text)?;
+
+    // Like Git, let the shell interpret the editor, which may come with
+    // arguments
+    let status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(&path)
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(Error::new(format!(
+            "The editor ({}) exited with an error",
+            editor
+        )));
+    }
+
+    let edited = std::fs::read_to_string(&path)
+        .reword("Could not read the edited commit message".to_string())?;
+    std::fs::remove_file(&path).ok();
+
+    Ok(edited)
+}
+
+pub async fn edit(
+    opts: EditOptions,
+    git: &crate::git::Git,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    if !console::user_attended() {
+        return Err(Error::new(
+            "spr edit needs a terminal to run the editor in",
+        ));
+    }
+
+    let mut prepared_commits = git.get_prepared_commits(config)?;
+    let index = find_pull_request_commit(&prepared_commits, opts.pull_request)
+        .ok_or_else(|| {
+            Error::new(format!(
+                "No commit on this branch belongs to Pull Request #{}",
+                opts.pull_request
+            ))
+        })?;
+    let target = &mut prepared_commits[index];
+    write_commit_title(target)?;
+
+    let cleanup = &config.message_cleanup;
+    let edited = run_editor(
+        git,
+        &message_template(&target.message, opts.pull_request, cleanup),
+    )
+    .await?;
+    let message = edited_message(&target.message, &edited, cleanup)?;
+    if message == target.message {
+        output("👍", "The commit message is unchanged - nothing to do")?;
+        return Ok(());
+    }
+    validate_commit_message(&message, config)?;
+    target.message = message;
+
+    // Rewrite the commit, and the ones on top of it onto the new commit
+    git.rewrite_commit_messages(&mut prepared_commits[index..], None)?;
+    output("✍", "Updated the commit message")?;
+
+    // Only the message changed, so the Pull Requests of the commits on top of
+    // the edited one are updated without new commits on their branches
+    diff(
+        DiffOptions::from_pull_request(opts.pull_request, None),
+        git,
+        gh,
+        config,
+    )
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original() -> MessageSectionsMap {
+        parse_message(
+            "Old title\n\nOld summary\n\nPull Request: \
+             https://github.com/acme/codez/pull/7\n",
+            MessageSection::Title,
+        )
+    }
+
+    #[test]
+    fn test_edited_message_keeps_pull_request() {
+        let message = edited_message(
+            &original(),
+            "New title\n\nNew summary\n",
+            &Default::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            message.get(&MessageSection::Title).map(String::as_str),
+            Some("New title")
+        );
+        assert_eq!(
+            message.get(&MessageSection::Summary).map(String::as_str),
+            Some("New summary")
+        );
+        assert_eq!(
+            message.get(&MessageSection::PullRequest),
+            original().get(&MessageSection::PullRequest)
+        );
+    }
+
+    #[test]
+    fn test_edited_message_from_template() {
+        let cleanup = MessageCleanup::default();
+        let template = message_template(&original(), 7, &cleanup);
+        let edited = template.replace("Old title", "New title");
+
+        let message = edited_message(&original(), &edited, &cleanup).unwrap();
+        let mut expected = original();
+        expected.insert(MessageSection::Title, "New title".to_string());
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn test_edited_message_keeps_comments() {
+        // With `commit.cleanup` set to `whitespace`, a Markdown heading is not
+        // a comment, and the message comes back as it was
+        let mut original = original();
+        original.insert(
+            MessageSection::Summary,
+            "## Heading\n\nOld summary".to_string(),
+        );
+        let cleanup = MessageCleanup {
+            mode: CleanupMode::Whitespace,
+            comment_char: '#',
+        };
+        let template = message_template(&original, 7, &cleanup);
+        assert!(template.contains("\n# ------------------------ >8"));
+
+        let message = edited_message(&original, &template, &cleanup).unwrap();
+        assert_eq!(message, original);
+
+        // The hint uses the comment character Git is configured with
+        let cleanup = MessageCleanup {
+            comment_char: ';',
+            ..Default::default()
+        };
+        let template = message_template(&original, 7, &cleanup);
+        assert!(template.contains("\n; Lines starting with ';' are ignored"));
+        let message = edited_message(&original, &template, &cleanup).unwrap();
+        assert_eq!(message, original);
+    }
+
+    #[test]
+    fn test_resolve_editor() {
+        assert_eq!(
+            resolve_editor(
+                Some("nano"),
+                Some("emacs"),
+                Some("code"),
+                Some("ed")
+            ),
+            "nano"
+        );
+        assert_eq!(
+            resolve_editor(None, Some("emacs -nw"), Some("code"), Some("ed")),
+            "emacs -nw"
+        );
+        assert_eq!(
+            resolve_editor(None, None, Some("code"), Some("ed")),
+            "code"
+        );
+        assert_eq!(resolve_editor(None, None, None, Some("ed")), "ed");
+        assert_eq!(resolve_editor(None, None, None, None), DEFAULT_EDITOR);
+        // Empty values are skipped
+        assert_eq!(
+            resolve_editor(Some(""), Some("  "), None, Some("ed")),
+            "ed"
+        );
+        assert_eq!(
+            resolve_editor(Some("nano"), None, None, Some("ed")),
+            "nano"
+        );
+        assert_eq!(
+            resolve_editor(Some(""), None, None, Some("")),
+            DEFAULT_EDITOR
+        );
+    }
+
+    #[test]
+    fn test_edited_message_empty() {
+        let cleanup = MessageCleanup::default();
+        assert!(edited_message(&original(), "", &cleanup).is_err());
+        assert!(
+            edited_message(&original(), "# Just a comment\n\n", &cleanup)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::path::PathBuf;
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    git::PreparedCommit,
+    output::output,
+    utils::slugify,
+};
+
+/// The extra header in each patch that names the Pull Request of the commit.
+pub const PULL_REQUEST_HEADER: &str = "X-Spr-Pull-Request";
+
+/// The name of the file `spr export --format mbox` writes.
+pub const MBOX_FILE_NAME: &str = "stack.mbox";
+
+/// How `spr export` writes the patch series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// A single mbox file with all patches
+    Mbox,
+    /// One file per commit, like `git format-patch`
+    Patches,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct ExportOptions {
+    /// Write the patches into a single mbox file, or one file per commit
+    #[clap(long, value_enum, default_value = "patches")]
+    format: ExportFormat,
+
+    /// Directory to write the patch series to (created if missing)
+    dir: PathBuf,
+}
+
+/// The file name for the patch of the commit at the given (zero-based)
+/// position in the stack, numbered from 1 like `git format-patch` does.
+pub fn patch_file_name(index: usize, title: &str) -> String {
+    let slug = slugify(title);
+    if slug.is_empty() {
+        format!("{:04}.patch", index + 1)
+    } else {
+        format!("{:04}-{}.patch", index + 1, slug)
+    }
+}
+
+/// Add a header to the end of the header block of an email.
+fn add_header(email: &str, name: &str, value: &str) -> String {
+    let end_of_headers = email.find("\n\n").unwrap_or(email.len());
+    format!(
+        "{}\n{}: {}{}",
+        &email[..end_of_headers],
+        name,
+        value,
+        &email[end_of_headers..]
+    )
+}
+
+/// Format the given commits as a series of patch emails, with the Pull
+/// Request of each commit (if it has one) in an extra header.
+pub fn format_patches(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    prepared_commits: &[PreparedCommit],
+) -> Result<Vec<String>> {
+    let repo = git.repo();
+    let mut options = git2::EmailCreateOptions::new();
+
+    prepared_commits
+        .iter()
+        .enumerate()
+        .map(|(index, prepared_commit)| {
+            let commit = repo.find_commit(prepared_commit.oid)?;
+            let parent_tree =
+                repo.find_commit(prepared_commit.parent_oid)?.tree()?;
+            let diff = repo.diff_tree_to_tree(
+                Some(&parent_tree),
+                Some(&commit.tree()?),
+                None,
+            )?;
+            let email = git2::Email::from_diff(
+                &diff,
+                index + 1,
+                prepared_commits.len(),
+                &commit.id(),
+                commit.summary().unwrap_or_default(),
+                commit.body().unwrap_or_default(),
+                &commit.author(),
+                &mut options,
+            )?;
+            let email = String::from_utf8_lossy(email.as_slice()).into_owned();
+
+            Ok(match prepared_commit.pull_request_number {
+                Some(number) => add_header(
+                    &email,
+                    PULL_REQUEST_HEADER,
+                    &config.pull_request_url(number),
+                ),
+                None => email,
+            })
+        })
+        .collect()
+}
+
+pub async fn export(
+    opts: ExportOptions,
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let prepared_commits = git.get_prepared_commits(config)?;
+    if prepared_commits.is_empty() {
+        return Err(Error::new("Branch is empty - there is nothing to export"));
+    }
+
+    let patches = format_patches(git, config, &prepared_commits)?;
+
+    std::fs::create_dir_all(&opts.dir)
+        .reword(format!("Could not create directory {}", opts.dir.display()))?;
+    let write = |name: &str, content: &str| {
+        let path = opts.dir.join(name);
+        std::fs::write(&path, content)
+            .reword(format!("Could not write {}", path.display()))
+    };
+
+    match opts.format {
+        ExportFormat::Patches => {
+            for (index, (commit, patch)) in
+                prepared_commits.iter().zip(&patches).enumerate()
+            {
+                let title = commit
+                    .message
+                    .get(&crate::message::MessageSection::Title)
+                    .map(String::as_str)
+                    .unwrap_or_default();
+                write(&patch_file_name(index, title), patch)?;
+            }
+        }
+        ExportFormat::Mbox => write(MBOX_FILE_NAME, &patches.concat())?,
+    }
+
+    output(
+        "📤",
+        &format!(
+            "Exported {} patch(es) to {}",
+            patches.len(),
+            opts.dir.display()
+        ),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::git::tests::{create_commit_with_files, test_repo};
+
+    #[test]
+    fn test_patch_file_name() {
+        assert_eq!(patch_file_name(0, "Add a thing"), "0001-add-a-thing.patch");
+        assert_eq!(
+            patch_file_name(11, "Fix: the bug!"),
+            "0012-fix-the-bug.patch"
+        );
+        assert_eq!(patch_file_name(2, "!!!"), "0003.patch");
+    }
+
+    #[test]
+    fn test_add_header() {
+        assert_eq!(
+            add_header("From: a\nSubject: b\n\nBody\n", "X-Foo", "bar"),
+            "From: a\nSubject: b\nX-Foo: bar\n\nBody\n"
+        );
+    }
+
+    #[test]
+    fn test_format_patches() {
+        let config = config_factory();
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+
+        let one = create_commit_with_files(
+            &git,
+            "One\n\nPull Request: https://github.com/acme/codez/pull/12\n",
+            &[root],
+            &[("a.txt", "a\n")],
+        );
+        let two = create_commit_with_files(
+            &git,
+            "Two\n",
+            &[one],
+            &[("a.txt", "a\n"), ("b.txt", "b\n")],
+        );
+        let prepared_commits = [one, two]
+            .iter()
+            .map(|&oid| git.prepare_commit(&config, oid).unwrap())
+            .collect::<Vec<_>>();
+
+        let patches = format_patches(&git, &config, &prepared_commits).unwrap();
+
+        assert_eq!(patches.len(), 2);
+        assert!(patches[0].contains("Subject: [PATCH 1/2] One\n"));
+        assert!(patches[0].contains(
+            "\nX-Spr-Pull-Request: https://github.com/acme/codez/pull/12\n\n"
+        ));
+        assert!(patches[0].contains("+++ b/a.txt"));
+        assert!(patches[1].contains("Subject: [PATCH 2/2] Two\n"));
+        assert!(!patches[1].contains(PULL_REQUEST_HEADER));
+        assert!(patches[1].contains("+++ b/b.txt"));
+    }
+}
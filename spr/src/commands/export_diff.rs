@@ -0,0 +1,272 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{io::Write, path::PathBuf};
+
+use git2::Oid;
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    git::PreparedCommit,
+    message::MessageSection,
+    output::output,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct ExportDiffOptions {
+    /// File to write the diff to, instead of standard output
+    #[clap(long, short = 'o', value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// The part of the combined diff for one Pull Request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffSection {
+    pub pull_request_number: Option<u64>,
+    pub title: String,
+    /// The commit the changes are on top of
+    pub parent_oid: Oid,
+    /// The (top) commit of the Pull Request
+    pub oid: Oid,
+}
+
+fn title(commit: &PreparedCommit) -> String {
+    commit
+        .message
+        .get(&MessageSection::Title)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// The sections of the combined diff of `commits` (bottom of the stack
+/// first), in the same order: one for each Pull Request, so a group of
+/// commits submitted as one Pull Request makes one section.
+pub fn diff_sections(commits: &[PreparedCommit]) -> Result<Vec<DiffSection>> {
+    Ok(crate::commit_group::group_ranges(commits)?
+        .into_iter()
+        .map(|range| {
+            let bottom = &commits[range.start];
+            let top = &commits[range.end - 1];
+            DiffSection {
+                pull_request_number: top.pull_request_number,
+                title: title(bottom),
+                parent_oid: bottom.parent_oid,
+                oid: top.oid,
+            }
+        })
+        .collect())
+}
+
+/// The lines above the diff of a section: its position in the stack, its
+/// Pull Request and title, and the URL of the Pull Request.
+pub fn section_header(
+    section: &DiffSection,
+    index: usize,
+    total: usize,
+    config: &crate::config::Config,
+) -> String {
+    match section.pull_request_number {
+        Some(number) => format!(
+            "==== [{}/{}] #{}: {} ====\n{}\n",
+            index + 1,
+            total,
+            number,
+            section.title,
+            config.pull_request_url(number)
+        ),
+        None => format!(
+            "==== [{}/{}] (no Pull Request yet): {} ====\n",
+            index + 1,
+            total,
+            section.title
+        ),
+    }
+}
+
+/// The changes of a section as a patch, like `git diff` shows them.
+fn section_diff(
+    git: &crate::git::Git,
+    section: &DiffSection,
+) -> Result<String> {
+    let repo = git.repo();
+    let parent_tree = repo.find_commit(section.parent_oid)?.tree()?;
+    let tree = repo.find_commit(section.oid)?.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&tree), None)?;
+
+    let mut patch = Vec::new();
+    diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            patch.push(line.origin() as u8);
+        }
+        patch.extend_from_slice(line.content());
+        true
+    })?;
+
+    Ok(String::from_utf8_lossy(&patch).into_owned())
+}
+
+/// The diff of the whole stack, with a section for each Pull Request, bottom
+/// first.
+pub fn combined_diff(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    prepared_commits: &[PreparedCommit],
+) -> Result<String> {
+    let sections = diff_sections(prepared_commits)?;
+
+    let mut result = String::new();
+    for (index, section) in sections.iter().enumerate() {
+        if index > 0 {
+            result.push('\n');
+        }
+        result.push_str(&section_header(
+            section,
+            index,
+            sections.len(),
+            config,
+        ));
+        result.push('\n');
+        result.push_str(&section_diff(git, section)?);
+    }
+
+    Ok(result)
+}
+
+pub async fn export_diff(
+    opts: ExportDiffOptions,
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let prepared_commits = git.get_prepared_commits(config)?;
+    if prepared_commits.is_empty() {
+        return Err(Error::new("Branch is empty - there is nothing to export"));
+    }
+
+    let diff = combined_diff(git, config, &prepared_commits)?;
+
+    match &opts.output {
+        None => std::io::stdout().write_all(diff.as_bytes())?,
+        Some(path) => {
+            std::fs::write(path, &diff)
+                .reword(format!("Could not write {}", path.display()))?;
+            output(
+                "📤",
+                &format!(
+                    "Exported the diff of the stack to {}",
+                    path.display()
+                ),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::git::tests::{
+        create_commit_with_files, prepared_commit_factory, test_repo,
+    };
+
+    #[test]
+    fn test_section_header() {
+        let config = config_factory();
+        let mut section = DiffSection {
+            pull_request_number: Some(12),
+            title: "Add parser".to_string(),
+            parent_oid: Oid::zero(),
+            oid: Oid::zero(),
+        };
+
+        assert_eq!(
+            section_header(&section, 0, 3, &config),
+            "==== [1/3] #12: Add parser ====\n\
+             https://github.com/acme/codez/pull/12\n"
+        );
+
+        section.pull_request_number = None;
+        assert_eq!(
+            section_header(&section, 2, 3, &config),
+            "==== [3/3] (no Pull Request yet): Add parser ====\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_sections() {
+        let commit = |id: &str, message: &str, number: Option<u64>| {
+            let mut commit = prepared_commit_factory(id, message);
+            commit.oid = Oid::from_bytes(&[id.as_bytes()[0]; 20]).unwrap();
+            commit.parent_oid =
+                Oid::from_bytes(&[id.as_bytes()[0] - 1; 20]).unwrap();
+            commit.pull_request_number = number;
+            commit
+        };
+        let commits = [
+            commit("b", "One", Some(1)),
+            commit("c", "Two", None),
+            commit("d", "Two, more\n\nSquash-Into-Previous: true", Some(2)),
+            commit("e", "Three", None),
+        ];
+
+        let sections = diff_sections(&commits).unwrap();
+        assert_eq!(
+            sections
+                .iter()
+                .map(|section| (
+                    section.pull_request_number,
+                    &section.title[..]
+                ))
+                .collect::<Vec<_>>(),
+            vec![(Some(1), "One"), (Some(2), "Two"), (None, "Three")]
+        );
+        // The group spans from the parent of its bottom commit to its top one
+        assert_eq!(sections[1].parent_oid, commits[1].parent_oid);
+        assert_eq!(sections[1].oid, commits[2].oid);
+    }
+
+    #[test]
+    fn test_combined_diff() {
+        let config = config_factory();
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit_with_files(
+            &git,
+            "One\n\nPull Request: #12",
+            &[root],
+            &[("a.txt", "a\n")],
+        );
+        let two = create_commit_with_files(
+            &git,
+            "Two\n\nPull Request: #13",
+            &[one],
+            &[("a.txt", "a\n"), ("b.txt", "b\n")],
+        );
+        let commits = [(one, root, 12, "One"), (two, one, 13, "Two")]
+            .into_iter()
+            .map(|(oid, parent_oid, number, title)| {
+                let mut commit = prepared_commit_factory("x", title);
+                commit.oid = oid;
+                commit.parent_oid = parent_oid;
+                commit.pull_request_number = Some(number);
+                commit
+            })
+            .collect::<Vec<_>>();
+
+        let diff = combined_diff(&git, &config, &commits).unwrap();
+        let one_at = diff.find("==== [1/2] #12: One ====\n").unwrap();
+        let two_at = diff.find("==== [2/2] #13: Two ====\n").unwrap();
+        assert!(one_at < two_at);
+        // Each section has the changes of its own commit only
+        let (first, second) = diff.split_at(two_at);
+        assert!(first.contains("+++ b/a.txt\n@@ -0,0 +1 @@\n+a\n"));
+        assert!(!first.contains("b.txt"));
+        assert!(second.contains("+++ b/b.txt\n@@ -0,0 +1 @@\n+b\n"));
+        assert!(!second.contains("a.txt"));
+    }
+}
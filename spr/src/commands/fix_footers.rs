@@ -0,0 +1,561 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{collections::HashSet, iter::zip};
+
+use crate::{
+    error::Result,
+    forge::Forge,
+    git::PreparedCommit,
+    github::{PullRequest, PullRequestState, PullRequestUpdate},
+    message::{
+        build_github_body, build_pr_stack_message, hides_pr_stack,
+        replace_marked_pr_stack, take_marked_pr_stack,
+        with_pull_request_description, MessageSection, PRStackDetails,
+    },
+    output::{output, write_commit_title},
+    rate_limit::{check_rate_limit, ApiOperation},
+};
+
+pub async fn fix_footers(
+    git: &crate::git::Git,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let prepared_commits: Vec<_> = git
+        .get_prepared_commits(config)?
+        .into_iter()
+        .filter(|commit| commit.pull_request_number.is_some())
+        .collect();
+    if prepared_commits.is_empty() {
+        output(
+            "👋",
+            "No commit on this branch has a Pull Request. Good bye!",
+        )?;
+        return Ok(());
+    }
+
+    if prepared_commits.len() > 1 {
+        check_rate_limit(
+            config,
+            ApiOperation::FixFooters,
+            prepared_commits.len(),
+        )
+        .await?;
+    }
+
+    let pull_requests = gh
+        .get_pull_requests(
+            prepared_commits.iter().map(|pc| pc.pull_request_number),
+        )
+        .await
+        .into_iter()
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+
+    let updates =
+        pr_stack_updates(git, config, &prepared_commits, &pull_requests)?;
+
+    let mut updated = 0;
+    for ((commit, pull_request), update) in
+        zip(zip(&prepared_commits, &pull_requests), updates)
+    {
+        write_commit_title(commit)?;
+
+        if pull_request.state != PullRequestState::Open {
+            output("⏭️", "Pull Request is closed - leaving it alone")?;
+        } else if let Some(update) = update {
+            gh.update_pull_request(pull_request.number, &update).await?;
+            output(
+                "✍",
+                &format!(
+                    "Updated the PR Stack of Pull Request #{}",
+                    pull_request.number
+                ),
+            )?;
+            updated += 1;
+        } else {
+            output("✅", "PR Stack is up to date")?;
+        }
+    }
+
+    if updated == 0 {
+        output(
+            "👍",
+            "All PR Stacks match the stack already - nothing to do",
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Bring the PR Stacks of the Pull Requests of `commits` (the commits of the
+/// local branch with a Pull Request, bottom first) up to date, except for the
+/// ones in `published`, which were just updated. Pull Requests whose PR Stack
+/// is correct already are not touched. This is how `spr diff` keeps the PR
+/// Stacks of the rest of the stack right, e.g. after a Pull Request below was
+/// closed, or the commits were reordered.
+pub(crate) async fn refresh_pr_stacks(
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    config: &crate::config::Config,
+    commits: &[PreparedCommit],
+    published: &[u64],
+) -> Result<()> {
+    let unpublished = |number: &u64| !published.contains(number);
+    if !commits
+        .iter()
+        .filter_map(|commit| commit.pull_request_number.as_ref())
+        .any(unpublished)
+    {
+        return Ok(());
+    }
+
+    let pull_requests = gh
+        .get_pull_requests(commits.iter().map(|pc| pc.pull_request_number))
+        .await
+        .into_iter()
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+
+    let updates = pr_stack_updates(git, config, commits, &pull_requests)?;
+    for (pull_request, update) in zip(&pull_requests, updates) {
+        if let (Some(update), true) =
+            (update, unpublished(&pull_request.number))
+        {
+            gh.update_pull_request(pull_request.number, &update).await?;
+            output(
+                "✍",
+                &format!(
+                    "Updated the outdated PR Stack of Pull Request #{}",
+                    pull_request.number
+                ),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// For each of the given Pull Requests (those of `commits`, in the same
+/// order), the update that brings its PR Stack up to date, or `None` if it is
+/// up to date already or the Pull Request is closed.
+fn pr_stack_updates(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    commits: &[PreparedCommit],
+    pull_requests: &[PullRequest],
+) -> Result<Vec<Option<PullRequestUpdate>>> {
+    let mut details = PRStackDetails {
+        merged: merged_pull_requests(pull_requests),
+        ..Default::default()
+    };
+    if config.pr_stack_format.diffstat {
+        for commit in commits {
+            if let Some(number) = commit.pull_request_number {
+                details.diffstats.insert(
+                    number,
+                    git.diff_stat(commit.parent_oid, commit.oid)?,
+                );
+            }
+        }
+    }
+
+    Ok(zip(pull_requests, pr_stacks(pull_requests))
+        .map(|(pull_request, stack)| {
+            if pull_request.state == PullRequestState::Open {
+                pr_stack_update(pull_request, &stack, &details, config)
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
+/// The numbers of the given Pull Requests that are merged.
+pub(crate) fn merged_pull_requests(
+    pull_requests: &[PullRequest],
+) -> HashSet<u64> {
+    pull_requests
+        .iter()
+        .filter(|pull_request| {
+            pull_request.state == PullRequestState::Closed
+                && pull_request.merge_commit.is_some()
+        })
+        .map(|pull_request| pull_request.number)
+        .collect()
+}
+
+/// The PR Stack for each of the given Pull Requests, which are in the order
+/// of the commits on the local branch. A Pull Request based on master starts a
+/// new stack (as one submitted with `--cherry-pick` does).
+pub(crate) fn pr_stacks(pull_requests: &[PullRequest]) -> Vec<Vec<u64>> {
+    let mut stack = Vec::new();
+
+    pull_requests
+        .iter()
+        .map(|pull_request| {
+            if pull_request.base.is_master_branch() {
+                stack.clear();
+            }
+            stack.insert(0, pull_request.number);
+            stack.clone()
+        })
+        .collect()
+}
+
+/// The update that makes the PR Stack in the body of the Pull Request list the
+/// given stack (with what `details` says about it), or `None` if it does so
+/// already. A PR Stack between comment markers is replaced where it is,
+/// leaving the rest of the body exactly as it was.
+fn pr_stack_update(
+    pull_request: &PullRequest,
+    stack: &[u64],
+    details: &PRStackDetails,
+    config: &crate::config::Config,
+) -> Option<PullRequestUpdate> {
+    let pr_stack = build_pr_stack_message(stack, details, config);
+    let current = pull_request.sections.get(&MessageSection::PRStack);
+    if current.map(|s| s.trim()) == Some(pr_stack.trim()) {
+        return None;
+    }
+    // A stack of one Pull Request that is not shown
+    let hidden = hides_pr_stack(&pr_stack, config);
+    if hidden && current.is_none() {
+        return None;
+    }
+
+    let body = pull_request.body.as_deref().unwrap_or_default();
+    if config.pr_stack_format.comment_markers
+        && !hidden
+        && take_marked_pr_stack(body).1.is_some()
+    {
+        return Some(PullRequestUpdate {
+            body: Some(replace_marked_pr_stack(body, &pr_stack)),
+            ..Default::default()
+        });
+    }
+
+    let mut sections =
+        with_pull_request_description(&pull_request.sections, Some(body));
+    sections.insert(MessageSection::PRStack, pr_stack);
+
+    Some(PullRequestUpdate {
+        body: Some(build_github_body(&sections, config)),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::github::tests::pull_request_factory;
+
+    #[test]
+    fn test_pr_stacks() {
+        let config = config_factory();
+        let pull_requests = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "master"),
+            pull_request_factory(
+                &config,
+                2,
+                "spr/foo/two",
+                "spr/foo/master.two",
+            ),
+            // Cherry-picked, so not stacked on the ones below
+            pull_request_factory(&config, 3, "spr/foo/three", "master"),
+            pull_request_factory(
+                &config,
+                4,
+                "spr/foo/four",
+                "spr/foo/master.four",
+            ),
+        ];
+
+        assert_eq!(
+            pr_stacks(&pull_requests),
+            vec![vec![1], vec![2, 1], vec![3], vec![4, 3]]
+        );
+    }
+
+    #[test]
+    fn test_pr_stack_update_merged() {
+        let mut config = config_factory();
+        let mut merged =
+            pull_request_factory(&config, 1, "spr/foo/one", "master");
+        merged.state = PullRequestState::Closed;
+        merged.merge_commit = Some(git2::Oid::zero());
+        let mut closed =
+            pull_request_factory(&config, 3, "spr/foo/three", "master");
+        closed.state = PullRequestState::Closed;
+        let pull_request = pull_request_factory(
+            &config,
+            2,
+            "spr/foo/two",
+            "spr/foo/master.two",
+        );
+        let details = PRStackDetails {
+            merged: merged_pull_requests(&[
+                merged,
+                pull_request.clone(),
+                closed,
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(details.merged, HashSet::from([1]));
+
+        let body = |config: &crate::config::Config| {
+            pr_stack_update(&pull_request, &[2, 1], &details, config)
+                .unwrap()
+                .body
+                .unwrap()
+        };
+
+        // By default, the merged Pull Request is dropped
+        assert!(!body(&config).contains("/pull/1"));
+
+        config.pr_stack_format.footer_keep_merged = true;
+        let body = body(&config);
+        assert!(
+            body.contains("https://github.com/acme/codez/pull/1 (merged)\n")
+        );
+        assert_eq!(
+            crate::utils::parse_pr_stack_entries(&body),
+            vec![(2, false), (1, true)]
+        );
+    }
+
+    #[test]
+    fn test_pr_stack_update_needed() {
+        let config = config_factory();
+        let mut pull_request = pull_request_factory(
+            &config,
+            2,
+            "spr/foo/two",
+            "spr/foo/master.two",
+        );
+        pull_request
+            .sections
+            .insert(MessageSection::Summary, "Summary".to_string());
+        pull_request.sections.insert(
+            MessageSection::PRStack,
+            build_pr_stack_message(&[2], &Default::default(), &config),
+        );
+
+        let update = pr_stack_update(
+            &pull_request,
+            &[2, 1],
+            &Default::default(),
+            &config,
+        )
+        .unwrap();
+
+        let body = update.body.unwrap();
+        assert!(body.starts_with("Summary"));
+        assert!(body.contains(&build_pr_stack_message(
+            &[2, 1],
+            &Default::default(),
+            &config
+        )));
+        assert!(update.title.is_none());
+        assert!(update.base.is_none());
+
+        // A description of the Pull Request's own keeps its markers
+        pull_request.body = Some(format!(
+            "{}\nSummary\n{}\n",
+            crate::message::DESCRIPTION_START_MARKER,
+            crate::message::DESCRIPTION_END_MARKER
+        ));
+        let update = pr_stack_update(
+            &pull_request,
+            &[2, 1],
+            &Default::default(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            crate::message::marked_description(&update.body.unwrap()),
+            Some("Summary")
+        );
+    }
+
+    #[test]
+    fn test_pr_stack_update_missing_stack() {
+        let config = config_factory();
+        let pull_request =
+            pull_request_factory(&config, 1, "spr/foo/one", "master");
+
+        assert!(pr_stack_update(
+            &pull_request,
+            &[1],
+            &Default::default(),
+            &config
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn test_pr_stack_update_hidden_single_pr_footer() {
+        let mut config = config_factory();
+        config.pr_stack_format.hide_single_pr_footer = true;
+        let mut pull_request =
+            pull_request_factory(&config, 1, "spr/foo/one", "master");
+
+        // The footer is not shown, so there is nothing to add
+        assert!(pr_stack_update(
+            &pull_request,
+            &[1],
+            &Default::default(),
+            &config
+        )
+        .is_none());
+
+        // A footer from before is taken out
+        pull_request.sections.insert(
+            MessageSection::PRStack,
+            build_pr_stack_message(&[2, 1], &Default::default(), &config),
+        );
+        let update =
+            pr_stack_update(&pull_request, &[1], &Default::default(), &config)
+                .unwrap();
+        assert!(!update.body.unwrap().contains("PR Stack:"));
+    }
+
+    #[test]
+    fn test_pr_stack_update_between_comment_markers() {
+        let mut config = config_factory();
+        config.pr_stack_format.comment_markers = true;
+        let mut pull_request = pull_request_factory(
+            &config,
+            2,
+            "spr/foo/two",
+            "spr/foo/master.two",
+        );
+        let old = build_pr_stack_message(&[2], &Default::default(), &config);
+        let body = format!(
+            "Prose *a bot* reformatted\n\n{}\n",
+            crate::message::replace_marked_pr_stack("", &old)
+        );
+        pull_request.body = Some(body.clone());
+        pull_request
+            .sections
+            .insert(MessageSection::PRStack, old.trim().to_string());
+
+        let new = build_pr_stack_message(&[2, 1], &Default::default(), &config);
+        let update = pr_stack_update(
+            &pull_request,
+            &[2, 1],
+            &Default::default(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(update.body.unwrap(), body.replace(&old, &new));
+    }
+
+    #[test]
+    fn test_pr_stack_update_already_correct() {
+        let config = config_factory();
+        let mut pull_request = pull_request_factory(
+            &config,
+            2,
+            "spr/foo/two",
+            "spr/foo/master.two",
+        );
+        // Parsing the body from GitHub trims the section
+        pull_request.sections.insert(
+            MessageSection::PRStack,
+            build_pr_stack_message(&[2, 1], &Default::default(), &config)
+                .trim()
+                .to_string(),
+        );
+
+        assert!(pr_stack_update(
+            &pull_request,
+            &[2, 1],
+            &Default::default(),
+            &config
+        )
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_pr_stacks_corrects_untouched_pull_request() {
+        use crate::forge::tests::{Call, MockForge};
+
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = config_factory();
+        let stack = |numbers: &[u64]| {
+            build_pr_stack_message(numbers, &Default::default(), &config)
+                .trim()
+                .to_string()
+        };
+
+        let mut pull_requests = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "master"),
+            pull_request_factory(
+                &config,
+                2,
+                "spr/foo/two",
+                "spr/foo/master.two",
+            ),
+            pull_request_factory(
+                &config,
+                3,
+                "spr/foo/three",
+                "spr/foo/master.three",
+            ),
+        ];
+        pull_requests[0]
+            .sections
+            .insert(MessageSection::PRStack, stack(&[1]));
+        // Still lists a Pull Request that is no longer in the stack
+        pull_requests[1]
+            .sections
+            .insert(MessageSection::PRStack, stack(&[2, 9, 1]));
+        let forge = MockForge::new(10, pull_requests);
+
+        let commits: Vec<_> = [Some(1), None, Some(2), Some(3)]
+            .into_iter()
+            .enumerate()
+            .map(|(index, number)| {
+                let mut commit = crate::git::tests::prepared_commit_factory(
+                    &index.to_string(),
+                    "Change",
+                );
+                commit.pull_request_number = number;
+                commit
+            })
+            .collect();
+
+        // #3 was just submitted, so only #2 needs fixing
+        refresh_pr_stacks(&git, &forge, &config, &commits, &[3])
+            .await
+            .unwrap();
+        let calls = forge.calls();
+        assert_eq!(
+            calls[..3],
+            [
+                Call::GetPullRequest(1),
+                Call::GetPullRequest(2),
+                Call::GetPullRequest(3),
+            ]
+        );
+        assert!(matches!(
+            &calls[3..],
+            [Call::UpdatePullRequest(2, update)]
+                if update.body.as_ref().unwrap().contains(&stack(&[2, 1]))
+        ));
+
+        // Nothing to look at if all of them were just submitted
+        let forge = MockForge::new(10, Vec::new());
+        refresh_pr_stacks(&git, &forge, &config, &commits, &[1, 2, 3])
+            .await
+            .unwrap();
+        assert!(forge.calls().is_empty());
+    }
+}
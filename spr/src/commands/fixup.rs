@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use indoc::formatdoc;
+
+use crate::{
+    commands::diff::{diff, DiffOptions},
+    error::{add_error, Error, Result, ResultExt},
+    git::find_pull_request_commit,
+    output::{output, write_commit_title},
+    utils::run_command,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct FixupOptions {
+    /// Number of the Pull Request whose commit the changes go into
+    pull_request: u64,
+
+    /// Message to be used for the commits updating the Pull Requests (e.g.
+    /// 'review comments')
+    #[clap(long, short = 'm')]
+    message: Option<String>,
+}
+
+pub async fn fixup(
+    opts: FixupOptions,
+    git: &crate::git::Git,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    if git.check_no_uncommitted_changes().is_ok() {
+        return Err(Error::new("There are no changes to fix up with"));
+    }
+
+    let prepared_commits = git.get_prepared_commits(config)?;
+    let target = find_pull_request_commit(&prepared_commits, opts.pull_request)
+        .map(|index| &prepared_commits[index])
+        .ok_or_else(|| {
+            Error::new(format!(
+                "No commit on this branch belongs to Pull Request #{}",
+                opts.pull_request
+            ))
+        })?;
+
+    write_commit_title(target)?;
+
+    // Commit the staged changes, or all changes to tracked files if nothing
+    // is staged, as a fixup for the target commit...
+    let mut cmd = tokio::process::Command::new("git");
+    cmd.arg("commit")
+        .arg("--quiet")
+        .arg("--no-verify")
+        .arg(format!("--fixup={}", target.oid));
+    if !git.has_staged_changes()? {
+        cmd.arg("--all");
+    }
+    run_command(&mut cmd)
+        .await
+        .reword("Creating the fixup commit failed".to_string())?;
+
+    // ...and squash it into the target commit straight away.
+    let mut cmd = tokio::process::Command::new("git");
+    cmd.env("GIT_SEQUENCE_EDITOR", "true")
+        .arg("rebase")
+        .arg("--quiet")
+        .arg("--interactive")
+        .arg("--autosquash")
+        .arg("--autostash")
+        .arg(format!("{}", target.parent_oid));
+    if run_command(&mut cmd).await.is_err() {
+        // Put everything back the way it was: abort the rebase, and undo the
+        // fixup commit, leaving its changes staged. The reset is attempted
+        // even if aborting failed, so that the fixup commit does not stay.
+        let mut result = run_command(
+            tokio::process::Command::new("git")
+                .arg("rebase")
+                .arg("--abort"),
+        )
+        .await
+        .reword("Aborting the rebase failed".to_string());
+        add_error(
+            &mut result,
+            run_command(
+                tokio::process::Command::new("git")
+                    .arg("reset")
+                    .arg("--quiet")
+                    .arg("--soft")
+                    .arg("HEAD~1"),
+            )
+            .await
+            .reword("Undoing the fixup commit failed".to_string()),
+        );
+
+        if let Err(mut error) = result {
+            error.push(format!(
+                "The changes could not be squashed into commit {}, and \
+                 putting things back the way they were failed, too. Check \
+                 `git status` and `git log` before going on.",
+                target.short_id,
+            ));
+            return Err(error);
+        }
+
+        return Err(Error::new(formatdoc!(
+            "The changes could not be squashed into commit {}, probably \
+             because of conflicts with the commits on top of it. Nothing was \
+             changed; the changes are staged.",
+            target.short_id,
+        )));
+    }
+
+    output(
+        "🩹",
+        &format!("Squashed the changes into commit {}", target.short_id),
+    )?;
+
+    // Commits below the target commit are unchanged, so only the Pull Request
+    // of the target commit and the ones on top of it need updating.
+    diff(
+        DiffOptions::from_pull_request(opts.pull_request, opts.message),
+        git,
+        gh,
+        config,
+    )
+    .await
+    .map(|_| ())
+}
@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::HashSet;
+
+use crate::{
+    commands::{
+        fix_footers::pr_stacks,
+        list::{search_pull_requests, stacks, ListState, ListedPullRequest},
+    },
+    error::Result,
+    forge::Forge,
+    github::PullRequest,
+    output::output,
+};
+
+/// How `spr graph` draws the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// A tree of the Pull Requests under the branches they are based on
+    Ascii,
+    /// A Graphviz graph, for `dot`
+    Dot,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct GraphOptions {
+    /// Draw the stack as a text tree, or as a Graphviz graph
+    #[clap(long, value_enum, default_value = "ascii")]
+    format: GraphFormat,
+
+    /// Also draw your other open stacks that are based on the same branch
+    #[clap(long)]
+    siblings: bool,
+}
+
+/// A Pull Request in the graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphNode {
+    pub number: u64,
+    pub title: String,
+    /// The Pull Request it is stacked on, if it is in the graph
+    pub below: Option<u64>,
+    /// The branch it is based on on GitHub, which is what it hangs off in the
+    /// graph unless it is stacked on another Pull Request
+    pub base: String,
+}
+
+/// The nodes for the Pull Requests of the checked-out branch (bottom of the
+/// stack first), each stacked on the one below it, unless it is based on
+/// master.
+pub fn stack_nodes(pull_requests: &[PullRequest]) -> Vec<GraphNode> {
+    pr_stacks(pull_requests)
+        .into_iter()
+        .zip(pull_requests)
+        .map(|(stack, pull_request)| GraphNode {
+            number: pull_request.number,
+            title: pull_request.title.clone(),
+            below: stack.get(1).copied(),
+            base: pull_request.base.branch_name().to_string(),
+        })
+        .collect()
+}
+
+/// The nodes for the stacks among `pull_requests` that none of `nodes` belong
+/// to and that are based on the same branch as one of the stacks of `nodes`.
+/// Which Pull Request is stacked on which is what their PR Stack sections
+/// say.
+pub fn sibling_nodes(
+    pull_requests: &[ListedPullRequest],
+    nodes: &[GraphNode],
+) -> Vec<GraphNode> {
+    let drawn: HashSet<u64> = nodes.iter().map(|node| node.number).collect();
+    let bases: HashSet<&str> = nodes
+        .iter()
+        .filter(|node| node.below.is_none())
+        .map(|node| node.base.as_str())
+        .collect();
+
+    let mut siblings = Vec::new();
+    for stack in stacks(pull_requests) {
+        let stack: Vec<&ListedPullRequest> = stack
+            .into_iter()
+            .map(|index| &pull_requests[index])
+            .collect();
+        if stack.iter().any(|pr| drawn.contains(&pr.number)) {
+            continue;
+        }
+
+        let numbers: HashSet<u64> = stack.iter().map(|pr| pr.number).collect();
+        let stack_nodes: Vec<GraphNode> = stack
+            .iter()
+            .map(|pr| GraphNode {
+                number: pr.number,
+                title: pr.title.clone(),
+                // The PR Stack lists the Pull Request itself first, then the
+                // ones below it
+                below: match &pr.pr_stack[..] {
+                    [first, below, ..] if *first == pr.number => {
+                        Some(*below).filter(|below| numbers.contains(below))
+                    }
+                    _ => None,
+                },
+                base: pr.base.clone(),
+            })
+            .collect();
+        if stack_nodes
+            .iter()
+            .any(|node| node.below.is_none() && bases.contains(&*node.base))
+        {
+            siblings.extend(stack_nodes);
+        }
+    }
+
+    siblings
+}
+
+fn label(node: &GraphNode) -> String {
+    format!("#{} {}", node.number, node.title)
+}
+
+/// The branches the Pull Requests that are not stacked on another one are
+/// based on, in the order they first come up.
+fn roots(nodes: &[GraphNode]) -> Vec<&str> {
+    let mut roots: Vec<&str> = Vec::new();
+    for node in nodes.iter().filter(|node| node.below.is_none()) {
+        if !roots.contains(&node.base.as_str()) {
+            roots.push(&node.base);
+        }
+    }
+    roots
+}
+
+/// The lines of a tree of the Pull Requests, with the branches they are based
+/// on at the top, like this:
+///
+/// ```text
+/// master
+/// ├── #1 First change
+/// │   └── #2 Second change
+/// └── #3 Other change
+/// ```
+pub fn render_ascii(nodes: &[GraphNode]) -> Vec<String> {
+    fn render_children<'a>(
+        nodes: &'a [GraphNode],
+        children: Vec<&'a GraphNode>,
+        indent: &str,
+        lines: &mut Vec<String>,
+    ) {
+        let count = children.len();
+        for (index, child) in children.into_iter().enumerate() {
+            let last = index + 1 == count;
+            lines.push(format!(
+                "{}{}{}",
+                indent,
+                if last { "└── " } else { "├── " },
+                label(child)
+            ));
+            let grandchildren = nodes
+                .iter()
+                .filter(|node| node.below == Some(child.number))
+                .collect();
+            let indent =
+                format!("{}{}", indent, if last { "    " } else { "│   " });
+            render_children(nodes, grandchildren, &indent, lines);
+        }
+    }
+
+    let mut lines = Vec::new();
+    for root in roots(nodes) {
+        lines.push(root.to_string());
+        let children = nodes
+            .iter()
+            .filter(|node| node.below.is_none() && node.base == root)
+            .collect();
+        render_children(nodes, children, "", &mut lines);
+    }
+
+    lines
+}
+
+/// A string in double quotes, for DOT.
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A Graphviz graph with a node for each Pull Request and each branch they
+/// are based on, and an edge from each Pull Request to what it is based on.
+pub fn render_dot(nodes: &[GraphNode]) -> String {
+    let pr_id = |number: u64| format!("pr{}", number);
+    let branch_id = |branch: &str| quoted(&format!("branch:{}", branch));
+
+    let mut dot = String::from(
+        "digraph stack {\n    rankdir=BT;\n    node [shape=box];\n",
+    );
+    for root in roots(nodes) {
+        dot.push_str(&format!(
+            "    {} [label={}, shape=ellipse];\n",
+            branch_id(root),
+            quoted(root)
+        ));
+    }
+    for node in nodes {
+        dot.push_str(&format!(
+            "    {} [label={}];\n",
+            pr_id(node.number),
+            quoted(&label(node))
+        ));
+    }
+    for node in nodes {
+        let target = match node.below {
+            Some(below) => pr_id(below),
+            None => branch_id(&node.base),
+        };
+        dot.push_str(&format!("    {} -> {};\n", pr_id(node.number), target));
+    }
+    dot.push_str("}\n");
+
+    dot
+}
+
+/// Print the Pull Requests of this branch (and with `--siblings`, your other
+/// stacks on the same base) as a tree or as a Graphviz graph.
+pub async fn graph(
+    opts: GraphOptions,
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    graphql_client: reqwest::Client,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let prepared_commits = git.get_prepared_commits(config)?;
+    let mut pull_requests: Vec<PullRequest> = Vec::new();
+    for pull_request in gh
+        .get_pull_requests(
+            prepared_commits.iter().map(|pc| pc.pull_request_number),
+        )
+        .await
+    {
+        // A Pull Request that more than one commit belongs to is drawn once
+        if let Some(pull_request) = pull_request? {
+            if !pull_requests
+                .iter()
+                .any(|pr| pr.number == pull_request.number)
+            {
+                pull_requests.push(pull_request);
+            }
+        }
+    }
+
+    let mut nodes = stack_nodes(&pull_requests);
+    if opts.siblings {
+        let listed =
+            search_pull_requests(&graphql_client, config, ListState::Open)
+                .await?;
+        nodes.extend(sibling_nodes(&listed, &nodes));
+    }
+    if nodes.is_empty() {
+        output(
+            "👋",
+            "No commit on this branch has a Pull Request - nothing to draw",
+        )?;
+        return Ok(());
+    }
+
+    match opts.format {
+        GraphFormat::Ascii => {
+            for line in render_ascii(&nodes) {
+                println!("{}", line);
+            }
+        }
+        GraphFormat::Dot => print!("{}", render_dot(&nodes)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::github::tests::pull_request_factory;
+
+    fn node(number: u64, below: Option<u64>, base: &str) -> GraphNode {
+        GraphNode {
+            number,
+            title: format!("Change {}", number),
+            below,
+            base: base.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_stack_nodes() {
+        let config = config_factory();
+        let pull_requests = [
+            pull_request_factory(&config, 1, "spr/foo/one", "master"),
+            pull_request_factory(&config, 2, "spr/foo/two", "spr/foo/one"),
+            pull_request_factory(
+                &config,
+                3,
+                "spr/foo/three",
+                "spr/foo/master.three",
+            ),
+            // Cherry-picked, so it starts a stack of its own
+            pull_request_factory(&config, 4, "spr/foo/four", "master"),
+        ];
+
+        assert_eq!(
+            stack_nodes(&pull_requests)
+                .into_iter()
+                .map(|node| (node.number, node.below))
+                .collect::<Vec<_>>(),
+            vec![(1, None), (2, Some(1)), (3, Some(2)), (4, None)]
+        );
+    }
+
+    #[test]
+    fn test_sibling_nodes() {
+        let listed =
+            |number: u64, pr_stack: &[u64], base: &str| ListedPullRequest {
+                number,
+                title: format!("Change {}", number),
+                url: format!("https://x/{}", number),
+                decision: crate::commands::list::ReviewDecision::Pending,
+                updated_at: chrono::Utc::now(),
+                pr_stack: pr_stack.to_vec(),
+                base: base.to_string(),
+            };
+        let nodes = [node(1, None, "master"), node(2, Some(1), "spr/foo/one")];
+        let pull_requests = [
+            // The stack of the branch itself
+            listed(1, &[1], "master"),
+            listed(2, &[2, 1], "spr/foo/one"),
+            // A sibling stack on master
+            listed(5, &[5], "master"),
+            listed(6, &[6, 5], "spr/foo/master.six"),
+            // A stack on another branch
+            listed(8, &[8], "release"),
+        ];
+
+        assert_eq!(
+            sibling_nodes(&pull_requests, &nodes),
+            vec![
+                node(5, None, "master"),
+                node(6, Some(5), "spr/foo/master.six")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_ascii() {
+        let nodes = [
+            node(1, None, "master"),
+            node(2, Some(1), "spr/foo/one"),
+            node(3, Some(2), "spr/foo/two"),
+            node(5, None, "master"),
+            node(8, None, "release"),
+        ];
+
+        assert_eq!(
+            render_ascii(&nodes),
+            vec![
+                "master",
+                "├── #1 Change 1",
+                "│   └── #2 Change 2",
+                "│       └── #3 Change 3",
+                "└── #5 Change 5",
+                "release",
+                "└── #8 Change 8",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_dot() {
+        let mut nodes = vec![
+            node(1, None, "master"),
+            node(2, Some(1), "spr/foo/one"),
+            node(5, None, "master"),
+        ];
+        nodes[1].title = r#"Say "hi" \o/"#.to_string();
+        let dot = render_dot(&nodes);
+
+        assert!(dot.starts_with("digraph stack {\n"));
+        assert!(dot.ends_with("}\n"));
+        // A node per Pull Request (and per branch they are based on)
+        for number in [1, 2, 5] {
+            assert_eq!(
+                dot.matches(&format!("    pr{} [label=", number)).count(),
+                1
+            );
+        }
+        assert_eq!(
+            dot.matches(r#""branch:master" [label="master", shape=ellipse];"#)
+                .count(),
+            1
+        );
+        assert!(dot.contains(r##"pr2 [label="#2 Say \"hi\" \\o/"];"##));
+        // An edge per base relationship
+        assert_eq!(dot.matches(" -> ").count(), 3);
+        assert!(dot.contains("    pr1 -> \"branch:master\";\n"));
+        assert!(dot.contains("    pr2 -> pr1;\n"));
+        assert!(dot.contains("    pr5 -> \"branch:master\";\n"));
+    }
+}
@@ -0,0 +1,474 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::path::{Path, PathBuf};
+
+use git2::Oid;
+
+use crate::{
+    commands::export::PULL_REQUEST_HEADER,
+    error::{Error, Result, ResultExt},
+    message::{build_commit_message, parse_message, MessageSection},
+    output::output,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct ImportOptions {
+    /// Directory with one patch file per commit (as written by `spr export`),
+    /// or an mbox file with all patches
+    source: PathBuf,
+
+    /// Name of the branch to be created. Defaults to `import`
+    #[clap(long)]
+    branch_name: Option<String>,
+
+    /// If given, create new branch but do not check out
+    #[clap(long)]
+    no_checkout: bool,
+}
+
+/// One commit of a patch series.
+#[derive(Debug)]
+pub struct Patch {
+    /// Where the patch came from, for messages about it
+    pub name: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub date: Option<git2::Time>,
+    pub message: String,
+    /// The Pull Request named in the patch's headers
+    pub pull_request_number: Option<u64>,
+    pub diff: String,
+}
+
+/// Split the contents of an mbox file into the emails in it.
+pub fn split_mbox(text: &str) -> Vec<&str> {
+    let starts: Vec<_> = lazy_regex::regex!(r"(?m)^From [0-9a-f]{40} ")
+        .find_iter(text)
+        .map(|m| m.start())
+        .collect();
+
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).copied().chain([text.len()]))
+        .map(|(&start, end)| &text[start..end])
+        .collect()
+}
+
+/// Parse a patch email, as written by `spr export` or `git format-patch`.
+pub fn parse_patch(
+    name: String,
+    text: &str,
+    config: &crate::config::Config,
+) -> Result<Patch> {
+    let (headers, rest) = text
+        .split_once("\n\n")
+        .ok_or_else(|| Error::new(format!("{} is not a patch", name)))?;
+
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in headers.lines() {
+        if line.starts_with([' ', '\t']) {
+            // Continuation of a folded header
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if line.starts_with("From ") {
+            // The mbox separator line
+            continue;
+        } else if let Some((key, value)) = line.split_once(':') {
+            fields.push((key.trim().into(), value.trim().into()));
+        }
+    }
+    let header = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    };
+
+    let (author_name, author_email) = header("from")
+        .and_then(|from| {
+            lazy_regex::regex_captures!(r"^(.*?)\s*<([^>]*)>$", from)
+        })
+        .map(|(_, name, email)| (name.to_string(), email.to_string()))
+        .ok_or_else(|| Error::new(format!("{} has no author", name)))?;
+    let date = header("date")
+        .and_then(|date| chrono::DateTime::parse_from_rfc2822(date).ok())
+        .map(|date| {
+            git2::Time::new(
+                date.timestamp(),
+                date.offset().local_minus_utc() / 60,
+            )
+        });
+    let subject = header("subject").unwrap_or_default();
+    let subject =
+        lazy_regex::regex!(r"^\[PATCH[^\]]*\]\s*").replace(subject, "");
+
+    // The message ends where the diffstat starts, the diff at the signature
+    let diff_start = if rest.starts_with("diff --git ") {
+        Some(0)
+    } else {
+        rest.find("\ndiff --git ").map(|index| index + 1)
+    };
+    let body_end = if rest.starts_with("---\n") {
+        0
+    } else {
+        rest.find("\n---\n")
+            .map(|index| index + 1)
+            .or(diff_start)
+            .unwrap_or(rest.len())
+    };
+    let diff = match diff_start {
+        Some(start) => {
+            let diff = &rest[start..];
+            let signature =
+                lazy_regex::regex!(r"\n-- ?\n(libgit2 )?\d[^\n]*\n*$");
+            match signature.find(diff) {
+                Some(m) => &diff[..m.start() + 1],
+                None => diff,
+            }
+        }
+        None => "",
+    };
+
+    let pull_request_number = header(PULL_REQUEST_HEADER)
+        .and_then(|value| config.parse_pull_request_field(value));
+
+    let mut message = parse_message(
+        &format!("{}\n\n{}", subject, &rest[..body_end]),
+        MessageSection::Title,
+    );
+    if let Some(number) = pull_request_number {
+        message.insert(
+            MessageSection::PullRequest,
+            config.pull_request_url(number),
+        );
+    }
+
+    Ok(Patch {
+        name,
+        author_name,
+        author_email,
+        date,
+        message: build_commit_message(&message),
+        pull_request_number,
+        diff: diff.to_string(),
+    })
+}
+
+/// Read the patches from a directory of patch files (in the order of their
+/// names), or from an mbox file.
+fn read_patches(
+    source: &Path,
+    config: &crate::config::Config,
+) -> Result<Vec<Patch>> {
+    let read = |path: &Path| {
+        std::fs::read_to_string(path)
+            .reword(format!("Could not read {}", path.display()))
+    };
+
+    if source.is_dir() {
+        let mut files = std::fs::read_dir(source)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.retain(|path| path.extension().is_some_and(|ext| ext == "patch"));
+        files.sort();
+
+        files
+            .iter()
+            .map(|path| {
+                let name = path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned();
+                parse_patch(name, &read(path)?, config)
+            })
+            .collect()
+    } else {
+        let text = read(source)?;
+        split_mbox(&text)
+            .into_iter()
+            .enumerate()
+            .map(|(index, email)| {
+                let name =
+                    format!("Patch {} in {}", index + 1, source.display());
+                parse_patch(name, email, config)
+            })
+            .collect()
+    }
+}
+
+/// Create a commit for each patch, starting on top of `base_oid`, without
+/// updating any reference. Returns the last commit.
+pub fn apply_patches(
+    git: &crate::git::Git,
+    base_oid: Oid,
+    patches: &[Patch],
+) -> Result<Oid> {
+    let repo = git.repo();
+    let mut parent = repo.find_commit(base_oid)?;
+
+    for patch in patches {
+        let tree = if patch.diff.is_empty() {
+            parent.tree()?
+        } else {
+            let diff = git2::Diff::from_buffer(patch.diff.as_bytes()).reword(
+                format!("{} does not contain a valid diff", patch.name),
+            )?;
+            let mut index = repo
+                .apply_to_tree(&parent.tree()?, &diff, None)
+                .reword(format!(
+                    "{} does not apply cleanly - nothing was imported",
+                    patch.name
+                ))?;
+            repo.find_tree(index.write_tree_to(&repo)?)?
+        };
+
+        let author = match &patch.date {
+            Some(date) => git2::Signature::new(
+                &patch.author_name,
+                &patch.author_email,
+                date,
+            )?,
+            None => {
+                git2::Signature::now(&patch.author_name, &patch.author_email)?
+            }
+        };
+        let committer = repo.signature().or_else(|_| {
+            git2::Signature::now(&patch.author_name, &patch.author_email)
+        })?;
+
+        let oid = repo.commit(
+            None,
+            &author,
+            &committer,
+            &patch.message,
+            &tree,
+            &[&parent],
+        )?;
+        parent = repo.find_commit(oid)?;
+    }
+
+    Ok(parent.id())
+}
+
+pub async fn import(
+    opts: ImportOptions,
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let patches = read_patches(&opts.source, config)?;
+    if patches.is_empty() {
+        return Err(Error::new(format!(
+            "No patches found in {}",
+            opts.source.display()
+        )));
+    }
+
+    let base_oid = git.resolve_reference(config.master_ref.local())?;
+    let head_oid = apply_patches(git, base_oid, &patches)?;
+
+    for patch in &patches {
+        match patch.pull_request_number {
+            Some(number) => output(
+                "📥",
+                &format!("Applied {} (Pull Request #{})", patch.name, number),
+            )?,
+            None => output("📥", &format!("Applied {}", patch.name))?,
+        }
+    }
+
+    let branch_name = match opts.branch_name {
+        Some(name) => name,
+        None => git.get_unused_branch_name("import")?,
+    };
+
+    let repo = git.repo();
+    let head_commit = repo.find_commit(head_oid)?;
+    repo.branch(&branch_name, &head_commit, true)?;
+    output("🌱", &format!("Created new branch: {}", &branch_name))?;
+
+    if !opts.no_checkout {
+        repo.checkout_tree(head_commit.as_object(), None)?;
+        repo.set_head(&format!("refs/heads/{}", branch_name))?;
+        output("✅", "Checked out")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::export::format_patches;
+    use crate::config::tests::config_factory;
+    use crate::git::tests::{create_commit_with_files, test_repo};
+
+    const PATCH: &str = indoc::indoc!(
+        "
+        From 62969ccdf61f966d2d761db068c765b65f021e8b Mon Sep 17 00:00:00 2001
+        From: Test <test@example.com>
+        Date: Wed, 14 Oct 2026 10:50:52 +0200
+        Subject: [PATCH 1/2] One
+        X-Spr-Pull-Request: https://github.com/acme/codez/pull/12
+
+        Summary of one
+        ---
+         a.txt | 1 +
+         1 file changed, 1 insertion(+)
+         create mode 100644 a.txt
+
+        diff --git a/a.txt b/a.txt
+        new file mode 100644
+        index 0000000..7898192
+        --- /dev/null
+        +++ b/a.txt
+        @@ -0,0 +1 @@
+        +a
+        --
+        libgit2 1.6.4
+
+        "
+    );
+
+    #[test]
+    fn test_parse_patch() {
+        let config = config_factory();
+        let patch = parse_patch("one.patch".into(), PATCH, &config).unwrap();
+
+        assert_eq!(patch.author_name, "Test");
+        assert_eq!(patch.author_email, "test@example.com");
+        assert_eq!(patch.date.unwrap().offset_minutes(), 120);
+        assert_eq!(patch.pull_request_number, Some(12));
+        assert_eq!(
+            patch.message,
+            "One\n\nSummary of one\n\n\
+             Pull Request: https://github.com/acme/codez/pull/12\n"
+        );
+        assert!(patch.diff.starts_with("diff --git a/a.txt b/a.txt\n"));
+        assert!(patch.diff.ends_with("\n+a\n"));
+    }
+
+    #[test]
+    fn test_parse_patch_pull_request_numbers() {
+        let config = config_factory();
+        let with_header = |value: &str| {
+            let text =
+                PATCH.replace("https://github.com/acme/codez/pull/12", value);
+            parse_patch("patch".into(), &text, &config)
+                .unwrap()
+                .pull_request_number
+        };
+
+        assert_eq!(with_header("#34"), Some(34));
+        assert_eq!(with_header("https://github.com/acme/other/pull/56"), None);
+
+        let text = PATCH.replace(
+            "X-Spr-Pull-Request: https://github.com/acme/codez/pull/12\n",
+            "",
+        );
+        let patch = parse_patch("patch".into(), &text, &config).unwrap();
+        assert_eq!(patch.pull_request_number, None);
+        assert_eq!(patch.message, "One\n\nSummary of one\n");
+    }
+
+    #[test]
+    fn test_split_mbox() {
+        let mbox =
+            format!("{}{}", PATCH, PATCH.replace("1/2] One", "2/2] Two"));
+        let emails = split_mbox(&mbox);
+
+        assert_eq!(emails.len(), 2);
+        assert_eq!(emails[0], PATCH);
+        assert!(emails[1].contains("Subject: [PATCH 2/2] Two\n"));
+        assert!(split_mbox("").is_empty());
+    }
+
+    #[test]
+    fn test_import_exported_stack() {
+        let config = config_factory();
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+
+        let one = create_commit_with_files(
+            &git,
+            "One\n\nPull Request: https://github.com/acme/codez/pull/12\n",
+            &[root],
+            &[("a.txt", "a\n")],
+        );
+        let two = create_commit_with_files(
+            &git,
+            "Two\n",
+            &[one],
+            &[("a.txt", "a\nmore\n"), ("b.txt", "b\n")],
+        );
+        let three = create_commit_with_files(
+            &git,
+            "Three\n\nPull Request: #13\n",
+            &[two],
+            &[("b.txt", "b\n")],
+        );
+        let originals = [one, two, three]
+            .iter()
+            .map(|&oid| git.prepare_commit(&config, oid).unwrap())
+            .collect::<Vec<_>>();
+
+        let patches = format_patches(&git, &config, &originals)
+            .unwrap()
+            .iter()
+            .map(|text| parse_patch("patch".into(), text, &config).unwrap())
+            .collect::<Vec<_>>();
+        let head = apply_patches(&git, root, &patches).unwrap();
+
+        let mut imported = Vec::new();
+        let mut oid = head;
+        while oid != root {
+            let commit = git.prepare_commit(&config, oid).unwrap();
+            oid = commit.parent_oid;
+            imported.insert(0, commit);
+        }
+
+        assert_eq!(
+            imported
+                .iter()
+                .map(|commit| commit.pull_request_number)
+                .collect::<Vec<_>>(),
+            vec![Some(12), None, Some(13)]
+        );
+        for (original, imported) in originals.iter().zip(&imported) {
+            assert_eq!(
+                git.get_tree_oid_for_commit(original.oid).unwrap(),
+                git.get_tree_oid_for_commit(imported.oid).unwrap()
+            );
+            assert_eq!(original.message, imported.message);
+        }
+    }
+
+    #[test]
+    fn test_apply_conflict_names_patch() {
+        let config = config_factory();
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+
+        // The patch changes a file that does not exist on the base
+        let text = PATCH
+            .replace("new file mode 100644\n", "")
+            .replace("--- /dev/null", "--- a/a.txt")
+            .replace("@@ -0,0 +1 @@\n+a", "@@ -1 +1 @@\n-x\n+a");
+        let patch =
+            parse_patch("0001-one.patch".into(), &text, &config).unwrap();
+
+        let error = apply_patches(&git, root, &[patch]).unwrap_err();
+        assert!(error
+            .messages()
+            .last()
+            .unwrap()
+            .starts_with("0001-one.patch does not apply"));
+    }
+}
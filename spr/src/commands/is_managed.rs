@@ -0,0 +1,92 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::{config::ProfileConfig, error::Result, git::PreparedCommit};
+
+/// Whether the repository is set up for spr, i.e. has a branch prefix
+/// configured (which `spr init` always sets).
+pub fn has_spr_config(git_config: &ProfileConfig) -> bool {
+    git_config.get_string("spr.branchPrefix").is_ok()
+}
+
+/// Whether spr manages the branch: the repository is set up for spr, and at
+/// least one of the commits up to HEAD refers to a Pull Request.
+pub fn is_managed(has_spr_config: bool, commits: &[PreparedCommit]) -> bool {
+    has_spr_config
+        && commits
+            .iter()
+            .any(|commit| commit.pull_request_number.is_some())
+}
+
+/// Whether spr manages the checked-out branch of a repository that is set up
+/// for spr. This looks at the local repository only.
+pub async fn check(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Result<bool> {
+    let prepared_commits = git.get_prepared_commits(config)?;
+
+    Ok(is_managed(true, &prepared_commits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::prepared_commit_factory;
+
+    fn commits(pull_requests: &[Option<u64>]) -> Vec<PreparedCommit> {
+        pull_requests
+            .iter()
+            .enumerate()
+            .map(|(index, &number)| {
+                let mut commit =
+                    prepared_commit_factory(&index.to_string(), "Commit");
+                commit.pull_request_number = number;
+                commit
+            })
+            .collect()
+    }
+
+    fn git_config(
+        entries: &[(&str, &str)],
+    ) -> (tempfile::TempDir, ProfileConfig) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config =
+            git2::Config::open(&dir.path().join("config")).unwrap();
+        for (name, value) in entries {
+            config.set_str(name, value).unwrap();
+        }
+
+        (dir, ProfileConfig::new(config, None).unwrap())
+    }
+
+    #[test]
+    fn test_has_spr_config() {
+        let (_dir, config) = git_config(&[("spr.branchPrefix", "spr/foo/")]);
+        assert!(has_spr_config(&config));
+
+        // Other settings alone (such as a token in the global configuration)
+        // do not make a repository set up for spr
+        let (_dir, config) = git_config(&[("spr.githubAuthToken", "token")]);
+        assert!(!has_spr_config(&config));
+        let (_dir, config) = git_config(&[]);
+        assert!(!has_spr_config(&config));
+    }
+
+    #[test]
+    fn test_is_managed() {
+        assert!(is_managed(true, &commits(&[Some(1)])));
+        assert!(is_managed(true, &commits(&[None, Some(2), None])));
+
+        // No commit refers to a Pull Request, or there are no commits
+        assert!(!is_managed(true, &commits(&[None, None])));
+        assert!(!is_managed(true, &[]));
+
+        // Pull Request trailers mean nothing without spr set up
+        assert!(!is_managed(false, &commits(&[Some(1)])));
+    }
+}
@@ -9,71 +9,709 @@ use indoc::formatdoc;
 use std::{io::Write, process::Stdio, time::Duration};
 
 use crate::{
+    config::LandAuthor,
     error::{Error, Result, ResultExt},
-    github::{PullRequestState, PullRequestUpdate, ReviewStatus},
-    message::build_github_body_for_merging,
+    forge::{Forge, MergeMethod},
+    git::PreparedCommit,
+    github::{
+        ChecksState, CommitIdentity, GitHubBranch, MergeRequest, PullRequest,
+        PullRequestChecks, PullRequestState, PullRequestUpdate, ReviewStatus,
+    },
+    interrupt,
+    land_progress::LandProgress,
+    message::{build_github_body_for_merging, MessageSection},
     output::{output, write_commit_title},
-    utils::run_command,
+    rate_limit::{check_rate_limit, ApiOperation},
+    utils::{expand_template, run_command},
 };
 
-#[derive(Debug, clap::Parser)]
+#[derive(Debug, Default, clap::Parser)]
 pub struct LandOptions {
     /// Merge a Pull Request that was created or updated with spr diff
     /// --cherry-pick
     #[clap(long)]
     cherry_pick: bool,
+
+    /// Land the Pull Requests of all commits in the branch, starting with the
+    /// bottom one, not just the one of the HEAD commit
+    #[clap(long, short = 'a')]
+    all: bool,
+
+    /// Print which Pull Requests would be landed, in which order, and which
+    /// branches would be deleted, without actually landing anything
+    #[clap(long)]
+    plan: bool,
+
+    /// Land the Pull Requests of all commits in the branch, like --all, but
+    /// without first checking that all of them can be landed: land as many as
+    /// possible from the bottom up, stop at the first one that cannot be
+    /// landed, and report which were landed and which were not
+    #[clap(long)]
+    keep_going: bool,
+
+    /// Before merging a Pull Request, wait for its checks to finish, and do
+    /// not merge it if any of them fail
+    #[clap(long)]
+    wait_for_checks: bool,
+
+    /// Merge into the given branch on GitHub instead of master, e.g. a
+    /// release branch. The Pull Requests must be based on that branch (or on
+    /// base branches created by spr)
+    #[clap(long, value_name = "BRANCH")]
+    into: Option<String>,
+
+    /// Delete the branches of the Pull Requests on GitHub after landing them
+    /// (instead of spr.landDeleteRemoteBranches)
+    #[clap(long, overrides_with = "no-delete-remote")]
+    delete_remote: bool,
+
+    /// Keep the branches of the Pull Requests on GitHub after landing them
+    #[clap(long, overrides_with = "delete-remote")]
+    no_delete_remote: bool,
+
+    /// Delete the local branches of the same names as the branches of the
+    /// Pull Requests after landing them (instead of
+    /// spr.landDeleteLocalBranches)
+    #[clap(long, overrides_with = "no-delete-local")]
+    delete_local: bool,
+
+    /// Keep the local branches after landing
+    #[clap(long, overrides_with = "delete-local")]
+    no_delete_local: bool,
+
+    /// When squashing, title the commit "<PR title> (#<number>)" and use the
+    /// Pull Request's description as its message, whatever the local commit
+    /// or spr.landMessageTemplate say (instead of
+    /// spr.landSquashTitleFromPr)
+    #[clap(long)]
+    squash_title_from_pr: bool,
 }
 
-pub async fn land(
-    opts: LandOptions,
+/// Options for landing as the library asks (see [`crate::api::land`]).
+impl From<&crate::api::LandOptions> for LandOptions {
+    fn from(options: &crate::api::LandOptions) -> Self {
+        Self {
+            all: options.all,
+            cherry_pick: options.cherry_pick,
+            keep_going: options.keep_going,
+            wait_for_checks: options.wait_for_checks,
+            plan: options.plan,
+            ..Default::default()
+        }
+    }
+}
+
+impl LandOptions {
+    fn branch_deletion(
+        &self,
+        config: &crate::config::Config,
+    ) -> BranchDeletion {
+        let choose = |yes: bool, no: bool, default: bool| {
+            if yes {
+                true
+            } else if no {
+                false
+            } else {
+                default
+            }
+        };
+        BranchDeletion {
+            remote: choose(
+                self.delete_remote,
+                self.no_delete_remote,
+                config.land_delete_remote_branches,
+            ),
+            local: choose(
+                self.delete_local,
+                self.no_delete_local,
+                config.land_delete_local_branches,
+            ),
+        }
+    }
+}
+
+/// Which branches `spr land` deletes after landing a Pull Request: those on
+/// GitHub, the local ones of the same names, or both, or neither.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchDeletion {
+    pub remote: bool,
+    pub local: bool,
+}
+
+/// What `spr land` does for one Pull Request.
+#[derive(Debug, Clone)]
+pub struct LandStep {
+    pub pull_request_number: u64,
+    pub title: String,
+    pub merge_method: MergeMethod,
+    /// Whether a squash commit is titled after the Pull Request (see
+    /// [`squash_title`])
+    pub squash_title_from_pr: bool,
+    pub target: GitHubBranch,
+    /// Branches on GitHub that get deleted after landing
+    pub delete_remote_branches: Vec<GitHubBranch>,
+    /// Local branches that get deleted after landing (if they exist)
+    pub delete_local_branches: Vec<String>,
+}
+
+/// The branch that `spr land` merges into: master, or the one given with
+/// `--into`, which must not be one of the branches spr creates.
+pub fn land_target(
+    into: Option<&str>,
+    config: &crate::config::Config,
+) -> Result<GitHubBranch> {
+    let branch_name = match into {
+        None => return Ok(config.master_ref.clone()),
+        Some(branch_name) => branch_name,
+    };
+
+    if !git2::Reference::is_valid_name(&format!("refs/heads/{branch_name}")) {
+        return Err(Error::new(format!(
+            "Cannot land into '{branch_name}': not a valid branch name"
+        )));
+    }
+    if branch_name.starts_with(&config.branch_prefix) {
+        return Err(Error::new(format!(
+            "Cannot land into '{branch_name}': this is a branch spr created \
+             for a Pull Request"
+        )));
+    }
+
+    Ok(config.new_github_branch(branch_name))
+}
+
+/// Check that the branch to land into exists on GitHub, as far as the last
+/// fetch knows.
+fn check_target_exists(
     git: &crate::git::Git,
-    gh: &mut crate::github::GitHub,
+    target: &GitHubBranch,
     config: &crate::config::Config,
 ) -> Result<()> {
-    git.check_no_uncommitted_changes()?;
-    let mut prepared_commits = git.get_prepared_commits(config)?;
+    git.resolve_reference(target.local())
+        .map(|_| ())
+        .reword(format!(
+            "Cannot land into '{}': there is no such branch on {}",
+            target.branch_name(),
+            config.remote_name
+        ))
+}
 
-    let based_on_unlanded_commits = prepared_commits.len() > 1;
+/// Check that the Pull Requests to be landed into `target` fit it: each one
+/// must be based either on `target` itself, or on a base branch created by
+/// spr, which gets replaced by `target` when landing. A Pull Request based on
+/// some other branch (like master, when landing into a release branch) would
+/// bring along the changes of that branch.
+fn check_bases(
+    pull_requests: &[PullRequest],
+    target: &GitHubBranch,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let mut error = None::<Error>;
+    for pull_request in pull_requests {
+        let base = pull_request.base.branch_name();
+        if base == target.branch_name()
+            || base.starts_with(&config.branch_prefix)
+        {
+            continue;
+        }
 
-    if based_on_unlanded_commits && !opts.cherry_pick {
-        return Err(Error::new(formatdoc!(
-            "Cannot land a commit whose parent is not on {master}. To land \
-             this commit, rebase it so that it is a direct child of {master}.
-             Alternatively, if you used the `--cherry-pick` option with `spr \
-             diff`, then you can pass it to `spr land`, too.",
-            master = &config.master_ref.branch_name(),
-        )));
+        let message = format!(
+            "Pull Request #{} is based on '{}', not on '{}'.",
+            pull_request.number,
+            base,
+            target.branch_name()
+        );
+        match &mut error {
+            Some(error) => error.push(message),
+            None => error = Some(Error::new(message)),
+        }
     }
 
-    let prepared_commit = match prepared_commits.last_mut() {
-        Some(c) => c,
-        None => {
-            output("👋", "Branch is empty - nothing to do. Good bye!")?;
-            return Ok(());
+    match error {
+        None => Ok(()),
+        Some(mut error) => {
+            error.push(format!(
+                "Create the Pull Requests on top of '{}' (for example with \
+                 `spr diff` on a branch tracking it), or land them without \
+                 --into.",
+                target.branch_name()
+            ));
+            Err(error)
+        }
+    }
+}
+
+/// Work out which Pull Requests `spr land` lands, and in which order.
+///
+/// `pull_requests` are the Pull Requests of the commits to be landed, in the
+/// order of the commits in the branch (bottom first). This order is kept: a
+/// Pull Request can only be landed once everything it is based on has landed.
+/// All of them are merged into `target`, and their branches are deleted as
+/// `deletion` says.
+pub fn plan_land(
+    pull_requests: &[PullRequest],
+    target: &GitHubBranch,
+    deletion: BranchDeletion,
+) -> Vec<LandStep> {
+    pull_requests
+        .iter()
+        .map(|pull_request| {
+            let mut branches = vec![pull_request.head.clone()];
+            if !pull_request.base.is_master_branch()
+                && pull_request.base.on_github() != target.on_github()
+            {
+                branches.push(pull_request.base.clone());
+            }
+
+            LandStep {
+                pull_request_number: pull_request.number,
+                title: pull_request.title.clone(),
+                merge_method: MergeMethod::Squash,
+                squash_title_from_pr: false,
+                target: target.clone(),
+                delete_local_branches: if deletion.local {
+                    branches
+                        .iter()
+                        .map(|branch| branch.branch_name().to_string())
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+                delete_remote_branches: if deletion.remote {
+                    branches
+                } else {
+                    Vec::new()
+                },
+            }
+        })
+        .collect()
+}
+
+/// Use the merge method that the `Merge-Method` trailer of a commit asks for
+/// to land its Pull Request, in place of the default. Fails, before anything
+/// is landed, if a trailer has an invalid value.
+pub fn apply_merge_method_trailers(
+    plan: &mut [LandStep],
+    prepared_commits: &[PreparedCommit],
+) -> Result<()> {
+    for commit in prepared_commits {
+        let merge_method =
+            crate::message::merge_method_trailer(&commit.message)
+                .context(format!("Cannot land commit {}", commit.short_id))?;
+        let (merge_method, number) =
+            match (merge_method, commit.pull_request_number) {
+                (Some(merge_method), Some(number)) => (merge_method, number),
+                _ => continue,
+            };
+        for step in plan.iter_mut() {
+            if step.pull_request_number == number {
+                step.merge_method = merge_method;
+            }
         }
+    }
+
+    Ok(())
+}
+
+pub fn print_land_plan(plan: &[LandStep]) -> Result<()> {
+    for (index, step) in plan.iter().enumerate() {
+        output(
+            "📋",
+            &format!(
+                "{}. #{} {}: {} into {}, then {}",
+                index + 1,
+                step.pull_request_number,
+                step.title,
+                merge_method_label(step.merge_method),
+                step.target.branch_name(),
+                describe_deletion(step),
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// What happens to the branches of a Pull Request after landing it, for
+/// `spr land --plan`.
+fn describe_deletion(step: &LandStep) -> String {
+    let remote = step
+        .delete_remote_branches
+        .iter()
+        .map(GitHubBranch::branch_name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let local = step.delete_local_branches.join(", ");
+    match (remote.is_empty(), local.is_empty()) {
+        (true, true) => "keep its branches".to_string(),
+        (false, true) => format!("delete {} on GitHub", remote),
+        (true, false) => format!("delete local {}", local),
+        (false, false) => {
+            format!("delete {} on GitHub and local {}", remote, local)
+        }
+    }
+}
+
+/// The placeholders of `spr.landMessageTemplate`.
+const LAND_MESSAGE_PLACEHOLDERS: [&str; 5] =
+    ["pr_title", "pr_body", "pr_number", "pr_url", "pr_stack"];
+
+/// Check that `spr.landMessageTemplate` only uses known placeholders, so that
+/// a typo is noticed before anything is landed.
+pub fn validate_land_message_template(template: &str) -> Result<()> {
+    let values = LAND_MESSAGE_PLACEHOLDERS.map(|name| (name, ""));
+    expand_template(template, &values)
+        .context("Invalid spr.landMessageTemplate".to_string())?;
+    Ok(())
+}
+
+/// The title of a squash commit as GitHub makes it: the Pull Request's title
+/// followed by its number, unless the title already ends with that.
+pub fn squash_title(pull_request: &PullRequest) -> String {
+    let title = pull_request.title.trim();
+    let suffix = format!("(#{})", pull_request.number);
+    if title.ends_with(&suffix) {
+        title.to_string()
+    } else {
+        format!("{} {}", title, suffix)
+    }
+}
+
+/// The title and message of the commit that landing the Pull Request creates:
+/// the Pull Request's title and description, or `spr.landMessageTemplate`
+/// expanded for it. The first line of the expanded template is the title, and
+/// the rest the message. With `squash_title_from_pr`, a squash commit gets
+/// the [`squash_title`] and the description, and no template.
+fn land_commit_message(
+    pull_request: &PullRequest,
+    merge_method: MergeMethod,
+    squash_title_from_pr: bool,
+    config: &crate::config::Config,
+) -> Result<(String, String)> {
+    let body = build_github_body_for_merging(&pull_request.sections);
+    if squash_title_from_pr && matches!(merge_method, MergeMethod::Squash) {
+        return Ok((squash_title(pull_request), body));
+    }
+    let template = match &config.land_message_template {
+        Some(template) => template,
+        None => return Ok((pull_request.title.clone(), body)),
     };
 
-    write_commit_title(prepared_commit)?;
+    let number = pull_request.number.to_string();
+    let url = config.pull_request_url(pull_request.number);
+    let stack = pull_request
+        .sections
+        .get(&MessageSection::PRStack)
+        .map(|stack| stack.trim())
+        .unwrap_or_default();
+    let values: Vec<(&str, &str)> = LAND_MESSAGE_PLACEHOLDERS
+        .iter()
+        .zip([
+            pull_request.title.as_str(),
+            body.trim(),
+            &number,
+            &url,
+            stack,
+        ])
+        .map(|(name, value)| (*name, value))
+        .collect();
+    let expanded = expand_template(template, &values)
+        .context("Invalid spr.landMessageTemplate".to_string())?;
 
-    let pull_request_number =
-        if let Some(number) = prepared_commit.pull_request_number {
-            output("#️⃣ ", &format!("Pull Request #{}", number))?;
-            number
+    let (title, message) = expanded
+        .trim()
+        .split_once('\n')
+        .unwrap_or((expanded.trim(), ""));
+    let message = message.trim();
+    Ok((
+        title.trim().to_string(),
+        if message.is_empty() {
+            String::new()
         } else {
-            return Err(Error::new(
-                "This commit does not refer to a Pull Request.",
-            ));
+            format!("{}\n", message)
+        },
+    ))
+}
+
+/// The placeholders that `spr.landTrailer` values can use.
+const LAND_TRAILER_PLACEHOLDERS: [&str; 3] =
+    ["pr_number", "pr_url", "reviewer"];
+
+/// Check that a `spr.landTrailer` value is a single line that only uses known
+/// placeholders.
+pub fn validate_land_trailer(template: &str) -> Result<()> {
+    if template.contains('\n') {
+        return Err(Error::new("Invalid spr.landTrailer: must be one line"));
+    }
+    let values = LAND_TRAILER_PLACEHOLDERS.map(|name| (name, ""));
+    expand_template(template, &values)
+        .context("Invalid spr.landTrailer".to_string())?;
+    Ok(())
+}
+
+/// The trailers for the commit that landing the Pull Request creates, from
+/// `spr.landTrailer`. A trailer with `{reviewer}` is repeated for each user
+/// who approved the Pull Request (in alphabetical order), and left out if
+/// nobody did.
+pub fn land_trailers(
+    pull_request: &PullRequest,
+    config: &crate::config::Config,
+) -> Result<Vec<String>> {
+    let number = pull_request.number.to_string();
+    let url = config.pull_request_url(pull_request.number);
+    let mut approvers: Vec<&str> = pull_request
+        .reviewers
+        .iter()
+        .filter(|(_, status)| **status == ReviewStatus::Approved)
+        .map(|(login, _)| login.as_str())
+        .collect();
+    approvers.sort_unstable();
+
+    let mut trailers = Vec::new();
+    for template in &config.land_trailers {
+        let reviewers = if template.contains("{reviewer}") {
+            approvers.clone()
+        } else {
+            vec![""]
         };
+        for reviewer in reviewers {
+            let values: Vec<(&str, &str)> = LAND_TRAILER_PLACEHOLDERS
+                .iter()
+                .zip([number.as_str(), url.as_str(), reviewer])
+                .map(|(name, value)| (*name, value))
+                .collect();
+            trailers.push(
+                expand_template(template, &values)
+                    .context("Invalid spr.landTrailer".to_string())?
+                    .trim()
+                    .to_string(),
+            );
+        }
+    }
 
-    // Load Pull Request information
-    let pull_request = gh.clone().get_pull_request(pull_request_number).await?;
+    Ok(trailers)
+}
 
+/// Put together the request for merging the Pull Request, whose head must be
+/// `head_oid`. GitHub's merge API does not let us choose the author of the
+/// commit it creates, so `author` (if given) is credited with a
+/// `Co-authored-by` trailer in the commit message instead. The trailers of
+/// `spr.landTrailer` go before it, and the `Co-authored-by` trailers of the
+/// commits of the Pull Request branch (`co_authors`, see
+/// `Git::co_author_trailers`) after it.
+pub fn build_merge_request(
+    pull_request: &PullRequest,
+    step: &LandStep,
+    head_oid: git2::Oid,
+    author: Option<&CommitIdentity>,
+    co_authors: &[String],
+    config: &crate::config::Config,
+) -> Result<MergeRequest> {
+    let (commit_title, mut commit_message) = land_commit_message(
+        pull_request,
+        step.merge_method,
+        step.squash_title_from_pr,
+        config,
+    )?;
+    let mut trailers = land_trailers(pull_request, config)?;
+    if let Some(author) = author {
+        trailers.push(format!(
+            "Co-authored-by: {} <{}>",
+            author.name, author.email
+        ));
+    }
+    for co_author in co_authors {
+        if !trailers.contains(co_author) {
+            trailers.push(co_author.clone());
+        }
+    }
+    if !trailers.is_empty() {
+        let message = commit_message.trim_end();
+        commit_message = format!(
+            "{}{}{}\n",
+            message,
+            if message.is_empty() { "" } else { "\n\n" },
+            trailers.join("\n")
+        );
+    }
+
+    Ok(MergeRequest {
+        commit_title,
+        commit_message,
+        sha: format!("{}", head_oid),
+        merge_method: step.merge_method,
+    })
+}
+
+/// Who to credit for landing the Pull Request, as per `spr.landAuthor`.
+fn merge_author(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    pull_request: &PullRequest,
+) -> Result<Option<CommitIdentity>> {
+    match config.land_author {
+        LandAuthor::GitHub => Ok(None),
+        LandAuthor::PullRequestAuthor => Ok(pull_request.author.clone()),
+        LandAuthor::CurrentUser => Ok(Some(git.user_identity()?)),
+    }
+}
+
+/// The outcome of landing a stack with `spr land --keep-going`.
+#[derive(Debug)]
+pub struct LandReport {
+    pub landed: Vec<u64>,
+    /// The Pull Request that could not be landed, and why
+    pub blocked: Option<(u64, Error)>,
+    /// Pull Requests on top of the blocked one, which were not attempted
+    pub not_attempted: Vec<u64>,
+}
+
+impl LandReport {
+    /// Put together the report from the land plan and the results of landing
+    /// its steps in order. Landing stops at the first failure, so there are no
+    /// results for the steps after that.
+    pub fn new(plan: &[LandStep], results: Vec<Result<()>>) -> Self {
+        let mut report = LandReport {
+            landed: Vec::new(),
+            blocked: None,
+            not_attempted: Vec::new(),
+        };
+        let mut results = results.into_iter();
+
+        for step in plan {
+            let number = step.pull_request_number;
+            if report.blocked.is_some() {
+                report.not_attempted.push(number);
+                continue;
+            }
+
+            match results.next() {
+                Some(Ok(())) => report.landed.push(number),
+                Some(Err(error)) => report.blocked = Some((number, error)),
+                None => report.not_attempted.push(number),
+            }
+        }
+
+        report
+    }
+
+    pub fn print(&self) -> Result<()> {
+        let list = |numbers: &[u64]| {
+            numbers
+                .iter()
+                .map(|number| format!("#{}", number))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        if !self.landed.is_empty() {
+            output("🛬", &format!("Landed: {}", list(&self.landed)))?;
+        }
+        if let Some((number, error)) = &self.blocked {
+            output("🚧", &format!("Could not land #{}:", number))?;
+            for message in error.messages() {
+                output("  ", message)?;
+            }
+        }
+        if !self.not_attempted.is_empty() {
+            output(
+                "⏸️",
+                &format!("Not attempted: {}", list(&self.not_attempted)),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What keeps a Pull Request from landing as far as the branch protection
+/// rules go: the reviews they require, going by GitHub's review decision, and
+/// (if we asked GitHub about them) the checks they require to pass.
+pub fn missing_for_landing(
+    pull_request: &PullRequest,
+    checks: Option<&PullRequestChecks>,
+) -> Vec<String> {
+    let reviewers = |status: ReviewStatus| {
+        let mut reviewers = pull_request
+            .reviewers
+            .iter()
+            .filter(|(_, reviewer_status)| **reviewer_status == status)
+            .map(|(reviewer, _)| reviewer.as_str())
+            .collect::<Vec<_>>();
+        reviewers.sort();
+        reviewers.join(", ")
+    };
+
+    let mut missing = Vec::new();
+    match pull_request.review_status {
+        // Either no review is required, or the required ones were given
+        None | Some(ReviewStatus::Approved) => (),
+        Some(ReviewStatus::Rejected) => {
+            let reviewers = reviewers(ReviewStatus::Rejected);
+            missing.push(if reviewers.is_empty() {
+                "changes were requested".to_string()
+            } else {
+                format!("changes were requested by {}", reviewers)
+            });
+        }
+        Some(ReviewStatus::Requested) => {
+            let reviewers = reviewers(ReviewStatus::Requested);
+            missing.push(if reviewers.is_empty() {
+                "a required approving review is missing".to_string()
+            } else {
+                format!(
+                    "a required approving review is missing (waiting for {})",
+                    reviewers
+                )
+            });
+        }
+    }
+    for check in checks.iter().flat_map(|checks| &checks.unmet_required) {
+        missing.push(format!("required check {} has not passed", check));
+    }
+
+    missing
+}
+
+/// Fail if the branch protection rules keep the Pull Request from landing
+/// (see `missing_for_landing`), listing what is missing.
+fn check_ready_to_land(
+    pull_request: &PullRequest,
+    checks: Option<&PullRequestChecks>,
+) -> Result<()> {
+    let missing = missing_for_landing(pull_request, checks);
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut error = Error::new(format!(
+        "This Pull Request is not ready to land: {}.",
+        missing.join("; ")
+    ));
+    if checks.is_some_and(|checks| !checks.unmet_required.is_empty()) {
+        error.push(
+            "To wait for the checks to finish, use spr land --wait-for-checks."
+                .to_string(),
+        );
+    }
+
+    Err(error)
+}
+
+/// Check the things about a Pull Request that prevent landing it, which we
+/// can tell without trying.
+fn check_landable(
+    pull_request: &PullRequest,
+    config: &crate::config::Config,
+) -> Result<()> {
     if pull_request.state != PullRequestState::Open {
         return Err(Error::new(formatdoc!(
             "This Pull Request is already closed!",
         )));
     }
 
+    check_ready_to_land(pull_request, None)?;
+
     if config.require_approval
         && pull_request.review_status != Some(ReviewStatus::Approved)
     {
@@ -82,22 +720,376 @@ pub async fn land(
         ));
     }
 
+    Ok(())
+}
+
+/// What to do after asking GitHub about the checks of a Pull Request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChecksDecision {
+    /// All checks passed (or there are none): go ahead and merge
+    Proceed,
+    /// Checks are still running: ask again later
+    Wait,
+    /// Do not merge, for the given reason
+    Abort(String),
+}
+
+/// Decide what to do given the checks of the Pull Request we are about to
+/// merge, `elapsed` time after we started waiting for them.
+pub fn decide_checks(
+    checks: &PullRequestChecks,
+    head_oid: git2::Oid,
+    elapsed: Duration,
+    timeout: Option<Duration>,
+) -> ChecksDecision {
+    // Right after we pushed, GitHub may still report the checks of the
+    // previous head commit. Those don't count.
+    let state = if checks.head_oid == head_oid {
+        checks.state
+    } else {
+        ChecksState::Pending
+    };
+
+    match state {
+        ChecksState::NoChecks | ChecksState::Success => ChecksDecision::Proceed,
+        ChecksState::Failure => {
+            let mut reason = "Checks failed".to_string();
+            if !checks.failed.is_empty() {
+                reason.push_str(": ");
+                reason.push_str(&checks.failed.join(", "));
+            }
+            ChecksDecision::Abort(reason)
+        }
+        ChecksState::Pending => match timeout {
+            Some(timeout) if elapsed >= timeout => {
+                ChecksDecision::Abort(format!(
+                    "Checks did not finish within {} seconds",
+                    timeout.as_secs()
+                ))
+            }
+            _ => ChecksDecision::Wait,
+        },
+    }
+}
+
+/// Poll the checks of the Pull Request until they pass, fail, or we run out of
+/// time.
+async fn wait_for_checks(
+    gh: &impl Forge,
+    config: &crate::config::Config,
+    pull_request_number: u64,
+    head_oid: git2::Oid,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut waiting = false;
+
+    loop {
+        let checks = gh.get_pull_request_checks(pull_request_number).await?;
+        match decide_checks(
+            &checks,
+            head_oid,
+            start.elapsed(),
+            config.checks_timeout,
+        ) {
+            ChecksDecision::Proceed => {
+                if waiting {
+                    output("✅", "Checks passed")?;
+                }
+                return Ok(());
+            }
+            ChecksDecision::Abort(reason) => {
+                return Err(Error::new(format!(
+                    "{} - not landing Pull Request #{}",
+                    reason, pull_request_number
+                )));
+            }
+            ChecksDecision::Wait => {
+                if !waiting {
+                    output("⏳", "Waiting for checks to finish...")?;
+                    waiting = true;
+                }
+                tokio::time::sleep(config.checks_poll_interval).await;
+            }
+        }
+    }
+}
+
+fn merge_method_label(merge_method: MergeMethod) -> &'static str {
+    match merge_method {
+        MergeMethod::Merge => "merge",
+        MergeMethod::Rebase => "rebase",
+        MergeMethod::Squash => "squash",
+    }
+}
+
+/// What `spr land` did, when it did not fail.
+#[derive(Debug)]
+pub enum LandOutcome {
+    /// The branch is empty, or all its Pull Requests were landed already
+    NothingToLand,
+    /// With `--plan`: what landing would do, none of which was done
+    Planned(Vec<LandStep>),
+    /// The Pull Requests that were landed. With `--keep-going`, that may have
+    /// stopped at one that could not be landed.
+    Landed(LandReport),
+}
+
+pub async fn land(
+    opts: LandOptions,
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    config: &crate::config::Config,
+) -> Result<LandOutcome> {
+    git.check_no_uncommitted_changes()?;
+    let mut prepared_commits = git.get_prepared_commits(config)?;
+
+    if prepared_commits.is_empty() {
+        output("👋", "Branch is empty - nothing to do. Good bye!")?;
+        return Ok(LandOutcome::NothingToLand);
+    }
+
+    let all = opts.all || opts.keep_going;
+    let target = land_target(opts.into.as_deref(), config)?;
+
+    if prepared_commits.len() > 1 && !opts.cherry_pick && !all {
+        return Err(Error::new(formatdoc!(
+            "Cannot land a commit whose parent is not on {master}. To land \
+             this commit, rebase it so that it is a direct child of {master}.
+             Alternatively, if you used the `--cherry-pick` option with `spr \
+             diff`, then you can pass it to `spr land`, too. Or use `spr land \
+             --all` to land all commits of this branch.",
+            master = &config.master_ref.branch_name(),
+        )));
+    }
+
+    if !all {
+        // Only the HEAD commit gets landed
+        prepared_commits.drain(0..prepared_commits.len() - 1);
+    }
+
+    let numbers = prepared_commits
+        .iter()
+        .map(|prepared_commit| {
+            prepared_commit.pull_request_number.ok_or_else(|| {
+                Error::new(format!(
+                    "Commit {} does not refer to a Pull Request.",
+                    prepared_commit.short_id
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut pull_requests = gh
+        .get_pull_requests(numbers.into_iter().map(Some))
+        .await
+        .into_iter()
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+
+    // Pick up where an interrupted `spr land --all` left off, skipping the
+    // Pull Requests at the bottom that it landed
+    let git_dir = git.repo().path().to_path_buf();
+    let mut progress = LandProgress::load(&git_dir)?;
+    let landed_already = if all {
+        progress.landed_already(&pull_requests)
+    } else {
+        0
+    };
+    if landed_already > 0 {
+        let landed = pull_requests.drain(..landed_already).collect::<Vec<_>>();
+        output(
+            "⏩",
+            &format!(
+                "Resuming the interrupted spr land: {} landed already",
+                landed
+                    .iter()
+                    .map(|pull_request| format!("#{}", pull_request.number))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )?;
+        if !opts.plan {
+            drop_landed_commits(git, config, &target, &landed).await?;
+        }
+        if pull_requests.is_empty() {
+            progress.remove()?;
+            output("👋", "Everything was landed already. Good bye!")?;
+            return Ok(LandOutcome::NothingToLand);
+        }
+    }
+
+    if opts.into.is_some() {
+        crate::git::Git::fetch_from_remote(&[&target], &config.remote_name)
+            .await
+            .context(format!(
+                "Cannot land into '{}': could not fetch it from {}",
+                target.branch_name(),
+                config.remote_name
+            ))?;
+        check_target_exists(git, &target, config)?;
+        check_bases(&pull_requests, &target, config)?;
+    }
+
+    let mut plan =
+        plan_land(&pull_requests, &target, opts.branch_deletion(config));
+    apply_merge_method_trailers(&mut plan, &prepared_commits)?;
+    for step in plan.iter_mut() {
+        step.squash_title_from_pr =
+            opts.squash_title_from_pr || config.land_squash_title_from_pr;
+    }
+
+    if opts.plan {
+        print_land_plan(&plan)?;
+        return Ok(LandOutcome::Planned(plan));
+    }
+
+    if plan.len() > 1 {
+        check_rate_limit(config, ApiOperation::Land, plan.len()).await?;
+    }
+    if all {
+        progress.start(
+            &plan
+                .iter()
+                .map(|step| step.pull_request_number)
+                .collect::<Vec<_>>(),
+        )?;
+    }
+
+    if all && !opts.keep_going {
+        // Don't start landing a stack that we can tell we won't get through
+        for pull_request in pull_requests.iter() {
+            check_landable(pull_request, config).map_err(|mut error| {
+                error.push(format!(
+                    "Pull Request #{} cannot be landed, so nothing was \
+                     landed.",
+                    pull_request.number
+                ));
+                error
+            })?;
+        }
+    }
+
+    let mut results = Vec::new();
+    for (step_index, step) in plan.iter().enumerate() {
+        // Landing a commit rebases the rest of the branch, so we pick up the
+        // current state of the branch before landing each one. Once the first
+        // one has landed into a branch other than master, the rest of the
+        // branch sits on top of that one.
+        let mut prepared_commits = if step_index > 0 && opts.into.is_some() {
+            git.get_prepared_commits_from(config, target.local())?
+        } else {
+            git.get_prepared_commits(config)?
+        };
+        let index = if all { 0 } else { prepared_commits.len() - 1 };
+
+        let result = land_impl(
+            git,
+            gh,
+            config,
+            &mut prepared_commits,
+            index,
+            step,
+            opts.wait_for_checks,
+        )
+        .await;
+        if all && result.is_ok() {
+            progress.mark_landed(step.pull_request_number)?;
+        }
+        if !opts.keep_going {
+            result?;
+            results.push(Ok(()));
+            continue;
+        }
+
+        // We never skip a Pull Request that could not be landed, as the ones
+        // on top of it depend on it.
+        let failed = result.is_err();
+        results.push(result);
+        if failed {
+            break;
+        }
+    }
+
+    let report = LandReport::new(&plan, results);
+    if opts.keep_going {
+        report.print()?;
+    }
+    if all && report.blocked.is_none() {
+        progress.remove()?;
+    }
+
+    Ok(LandOutcome::Landed(report))
+}
+
+/// Take the commits of Pull Requests that were landed already off the
+/// branch, by rebasing it onto the merge commit of the last one of them.
+async fn drop_landed_commits(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    target: &GitHubBranch,
+    landed: &[PullRequest],
+) -> Result<()> {
+    let merge_commit = match landed.last().and_then(|pr| pr.merge_commit) {
+        Some(merge_commit) => merge_commit,
+        None => return Ok(()),
+    };
+    crate::git::Git::fetch_from_remote(&[target], &config.remote_name).await?;
+    git.fetch_commits_from_remote(&[merge_commit], &config.remote_name)
+        .await?;
+
+    let mut prepared_commits = git.get_prepared_commits(config)?;
+    git.rebase_commits(&mut prepared_commits[..], merge_commit)
+        .context(
+            "The automatic rebase failed - please rebase manually!".to_string(),
+        )
+}
+
+async fn land_impl(
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    config: &crate::config::Config,
+    prepared_commits: &mut [PreparedCommit],
+    commit_index: usize,
+    step: &LandStep,
+    wait_for_checks_first: bool,
+) -> Result<()> {
+    let based_on_unlanded_commits = commit_index > 0;
+    let prepared_commit = &prepared_commits[commit_index];
+
+    write_commit_title(prepared_commit)?;
+
+    if prepared_commit.pull_request_number != Some(step.pull_request_number) {
+        return Err(Error::new(format!(
+            "Expected commit {} to refer to Pull Request #{}.",
+            prepared_commit.short_id, step.pull_request_number
+        )));
+    }
+    let pull_request_number = step.pull_request_number;
+    output("#️⃣ ", &format!("Pull Request #{}", pull_request_number))?;
+
+    // Load Pull Request information
+    let pull_request = gh.get_pull_request(pull_request_number).await?;
+
+    check_landable(&pull_request, config)?;
+    let author = merge_author(git, config, &pull_request)?;
+
     output("🛫", "Getting started...")?;
 
-    // Fetch current master from GitHub.
+    // Fetch the current state of the branch we merge into (master, unless
+    // landing with --into) from GitHub.
+    let target = &step.target;
     run_command(
         tokio::process::Command::new("git")
             .arg("fetch")
             .arg("--no-write-fetch-head")
             .arg("--")
             .arg(&config.remote_name)
-            .arg(config.master_ref.on_github()),
+            .arg(target.on_github()),
     )
     .await
     .reword("git fetch failed".to_string())?;
 
-    let current_master = git.resolve_reference(config.master_ref.local())?;
-    let base_is_master = pull_request.base.is_master_branch();
+    let current_master = git.resolve_reference(target.local())?;
+    let base_is_target = pull_request.base.on_github() == target.on_github();
     let index = git.cherrypick(prepared_commit.oid, current_master)?;
 
     if index.has_conflicts() {
@@ -105,7 +1097,7 @@ pub async fn land(
             "This commit cannot be applied on top of the '{master}' branch.
              Please rebase this commit on top of current \
              '{remote}/{master}'.{unlanded}",
-            master = &config.master_ref.branch_name(),
+            master = target.branch_name(),
             remote = &config.remote_name,
             unlanded = if based_on_unlanded_commits {
                 " You may also have to land commits that this commit depends on first."
@@ -148,13 +1140,14 @@ pub async fn land(
     // cherry-picked the commit onto master.
     let mut pr_head_oid = pull_request.head_oid;
 
-    if !base_is_master {
+    if !base_is_target {
         // The base of the Pull Request on GitHub is not set to master. This
         // means the Pull Request uses a base branch. We tested above that
         // merging the Pull Request branch into the master branch produces the
         // intended result (the same as cherry-picking the local commit onto
         // master), so what we want to do is actually merge the Pull Request as
         // it is into master. Hence, we change the base to the master branch.
+        // (Here and below, "master" is the branch given with --into, if any.)
         //
         // Before we do that, there is one more edge case to look out for: if
         // the base branch contains changes that have since been landed on
@@ -231,7 +1224,7 @@ pub async fn land(
         gh.update_pull_request(
             pull_request_number,
             &PullRequestUpdate {
-                base: Some(config.master_ref.branch_name().to_string()),
+                base: Some(target.branch_name().to_string()),
                 ..Default::default()
             },
         )
@@ -256,7 +1249,7 @@ pub async fn land(
             )));
         }
 
-        if mergeability.base.is_master_branch()
+        if mergeability.base.on_github() == target.on_github()
             && mergeability.mergeable.is_some()
         {
             if mergeability.mergeable != Some(true) {
@@ -296,6 +1289,18 @@ pub async fn land(
         tokio::time::sleep(Duration::from_secs(1)).await;
     };
 
+    let result = match result {
+        Ok(()) if wait_for_checks_first => {
+            wait_for_checks(gh, config, pull_request_number, pr_head_oid).await
+        }
+        Ok(()) => {
+            let checks =
+                gh.get_pull_request_checks(pull_request_number).await?;
+            check_ready_to_land(&pull_request, Some(&checks))
+        }
+        result => result,
+    };
+
     let result = match result {
         Ok(()) => {
             // We have checked that merging the Pull Request branch into the master
@@ -303,26 +1308,36 @@ pub async fn land(
             // used a base branch with this Pull Request or not. We have made sure the
             // target of the Pull Request is set to the master branch. So let GitHub do
             // the merge now!
-            octocrab::instance()
-                .pulls(&config.owner, &config.repo)
-                .merge(pull_request_number)
-                .method(octocrab::params::pulls::MergeMethod::Squash)
-                .title(pull_request.title)
-                .message(build_github_body_for_merging(&pull_request.sections))
-                .sha(format!("{}", pr_head_oid))
-                .send()
-                .await
-                .convert()
-                .and_then(|merge| {
-                    if merge.merged {
-                        Ok(merge)
-                    } else {
-                        Err(Error::new(formatdoc!(
-                            "GitHub Pull Request merge failed: {}",
-                            merge.message.unwrap_or_default()
-                        )))
-                    }
-                })
+            // The commits of the Pull Request branch credit the authors of
+            // the commits cherry-picked from someone else, and the ones
+            // `spr diff` was asked to credit
+            match git
+                .co_author_trailers(pr_head_oid, current_master)
+                .and_then(|co_authors| {
+                    build_merge_request(
+                        &pull_request,
+                        step,
+                        pr_head_oid,
+                        author.as_ref(),
+                        &co_authors,
+                        config,
+                    )
+                }) {
+                Ok(merge_request) => gh
+                    .merge_pull_request(pull_request_number, &merge_request)
+                    .await
+                    .and_then(|merge| {
+                        if merge.merged {
+                            Ok(merge)
+                        } else {
+                            Err(Error::new(formatdoc!(
+                                "GitHub Pull Request merge failed: {}",
+                                merge.message.unwrap_or_default()
+                            )))
+                        }
+                    }),
+                Err(error) => Err(error),
+            }
         }
         Err(err) => Err(err),
     };
@@ -334,7 +1349,7 @@ pub async fn land(
 
             // If we changed the target branch of the Pull Request earlier, then
             // undo this change now.
-            if !base_is_master {
+            if !base_is_target {
                 let result = gh
                     .update_pull_request(
                         pull_request_number,
@@ -355,36 +1370,28 @@ pub async fn land(
         }
     };
 
+    interrupt::record_completed(format!(
+        "Landed Pull Request #{}",
+        pull_request_number
+    ));
     output("🛬", "Landed!")?;
 
-    let mut remove_old_branch_child_process =
-        tokio::process::Command::new("git")
-            .arg("push")
-            .arg("--no-verify")
-            .arg("--delete")
-            .arg("--")
-            .arg(&config.remote_name)
-            .arg(pull_request.head.on_github())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()?;
-
-    let remove_old_base_branch_child_process = if base_is_master {
-        None
-    } else {
-        Some(
+    let remove_old_branch_child_processes = step
+        .delete_remote_branches
+        .iter()
+        .map(|branch| {
             tokio::process::Command::new("git")
                 .arg("push")
                 .arg("--no-verify")
                 .arg("--delete")
                 .arg("--")
                 .arg(&config.remote_name)
-                .arg(pull_request.base.on_github())
+                .arg(branch.on_github())
                 .stdout(Stdio::null())
                 .stderr(Stdio::null())
-                .spawn()?,
-        )
-    };
+                .spawn()
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
 
     // Rebase us on top of the now-landed commit
     if let Some(sha) = merge.sha {
@@ -397,7 +1404,7 @@ pub async fn land(
                 .arg("--no-write-fetch-head")
                 .arg("--")
                 .arg(&config.remote_name)
-                .arg(config.master_ref.on_github())
+                .arg(target.on_github())
                 .arg(&sha)
                 .stdout(Stdio::null())
                 .stderr(Stdio::piped())
@@ -422,10 +1429,963 @@ pub async fn land(
     // Wait for the "git push" to delete the old Pull Request branch to finish,
     // but ignore the result. GitHub may be configured to delete the branch
     // automatically, in which case it's gone already and this command fails.
-    remove_old_branch_child_process.wait().await?;
-    if let Some(mut proc) = remove_old_base_branch_child_process {
+    for mut proc in remove_old_branch_child_processes {
         proc.wait().await?;
     }
 
+    delete_local_branches(git, &step.delete_local_branches)?;
+
+    Ok(())
+}
+
+/// Delete those of the given local branches that exist, except the one that
+/// is checked out.
+fn delete_local_branches(
+    git: &crate::git::Git,
+    branch_names: &[String],
+) -> Result<()> {
+    if branch_names.is_empty() {
+        return Ok(());
+    }
+    let existing = git.local_branch_names()?;
+    let checked_out = git.checked_out_branch();
+    for name in branch_names {
+        if !existing.contains(name) {
+            continue;
+        }
+        if checked_out.as_ref() == Some(name) {
+            output(
+                "⚠️",
+                &format!("Keeping local branch {}, which is checked out", name),
+            )?;
+            continue;
+        }
+        git.delete_local_branch(name)?;
+        output("🗑️", &format!("Deleted local branch {}", name))?;
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::github::tests::pull_request_factory;
+    use clap::Parser;
+
+    const REMOTE_ONLY: BranchDeletion = BranchDeletion {
+        remote: true,
+        local: false,
+    };
+
+    #[test]
+    fn test_plan_land_stack() {
+        let config = config_factory();
+        let pull_requests = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "master"),
+            pull_request_factory(
+                &config,
+                2,
+                "spr/foo/two",
+                "spr/foo/master.two",
+            ),
+            pull_request_factory(
+                &config,
+                3,
+                "spr/foo/three",
+                "spr/foo/master.three",
+            ),
+        ];
+
+        let plan = plan_land(&pull_requests, &config.master_ref, REMOTE_ONLY);
+
+        assert_eq!(
+            plan.iter()
+                .map(|step| step.pull_request_number)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert!(plan
+            .iter()
+            .all(|step| step.merge_method == MergeMethod::Squash
+                && step.target.is_master_branch()));
+        assert_eq!(
+            plan.iter()
+                .map(|step| step
+                    .delete_remote_branches
+                    .iter()
+                    .map(GitHubBranch::branch_name)
+                    .collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            vec![
+                vec!["spr/foo/one"],
+                vec!["spr/foo/two", "spr/foo/master.two"],
+                vec!["spr/foo/three", "spr/foo/master.three"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_merge_method_trailers() {
+        let config = config_factory();
+        let pull_requests = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "master"),
+            pull_request_factory(
+                &config,
+                2,
+                "spr/foo/two",
+                "spr/foo/master.two",
+            ),
+        ];
+        let commit = |id: &str, message: &str, number: u64| {
+            let mut commit =
+                crate::git::tests::prepared_commit_factory(id, message);
+            commit.pull_request_number = Some(number);
+            commit
+        };
+
+        let mut plan =
+            plan_land(&pull_requests, &config.master_ref, REMOTE_ONLY);
+        apply_merge_method_trailers(
+            &mut plan,
+            &[
+                commit("a", "Cleanup\n\nMerge-Method: squash", 1),
+                commit("b", "Feature\n\nLots of it\n\nMerge-Method: merge", 2),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            plan.iter()
+                .map(|step| step.merge_method)
+                .collect::<Vec<_>>(),
+            vec![MergeMethod::Squash, MergeMethod::Merge]
+        );
+
+        // Without a trailer, the default stays
+        let mut plan =
+            plan_land(&pull_requests, &config.master_ref, REMOTE_ONLY);
+        apply_merge_method_trailers(
+            &mut plan,
+            &[commit("a", "Cleanup", 1), commit("b", "Feature", 2)],
+        )
+        .unwrap();
+        assert!(plan
+            .iter()
+            .all(|step| step.merge_method == MergeMethod::Squash));
+
+        assert!(apply_merge_method_trailers(
+            &mut plan,
+            &[commit("a", "Cleanup\n\nMerge-Method: octopus", 1)],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_plan_land_into() {
+        let config = config_factory();
+        let target = config.new_github_branch("release-1.2");
+        let pull_requests = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "release-1.2"),
+            pull_request_factory(
+                &config,
+                2,
+                "spr/foo/two",
+                "spr/foo/master.two",
+            ),
+        ];
+
+        let plan = plan_land(&pull_requests, &target, REMOTE_ONLY);
+
+        assert!(plan
+            .iter()
+            .all(|step| step.target.branch_name() == "release-1.2"));
+        // The branch landed into is never deleted
+        assert_eq!(
+            plan.iter()
+                .map(|step| step
+                    .delete_remote_branches
+                    .iter()
+                    .map(GitHubBranch::branch_name)
+                    .collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            vec![
+                vec!["spr/foo/one"],
+                vec!["spr/foo/two", "spr/foo/master.two"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_land_branch_deletion() {
+        let config = config_factory();
+        let pull_requests = vec![pull_request_factory(
+            &config,
+            2,
+            "spr/foo/two",
+            "spr/foo/master.two",
+        )];
+        let deletions = |remote, local| {
+            let step = plan_land(
+                &pull_requests,
+                &config.master_ref,
+                BranchDeletion { remote, local },
+            )
+            .remove(0);
+            (
+                step.delete_remote_branches
+                    .iter()
+                    .map(|branch| branch.on_github().to_string())
+                    .collect::<Vec<_>>(),
+                step.delete_local_branches,
+            )
+        };
+        let both = vec![
+            "refs/heads/spr/foo/two".to_string(),
+            "refs/heads/spr/foo/master.two".to_string(),
+        ];
+        let both_local =
+            vec!["spr/foo/two".to_string(), "spr/foo/master.two".to_string()];
+
+        assert_eq!(deletions(false, false), (vec![], vec![]));
+        assert_eq!(deletions(true, false), (both.clone(), vec![]));
+        assert_eq!(deletions(false, true), (vec![], both_local.clone()));
+        assert_eq!(deletions(true, true), (both, both_local));
+    }
+
+    #[test]
+    fn test_branch_deletion_options() {
+        let mut config = config_factory();
+        let deletion = |args: &[&str], config: &crate::config::Config| {
+            LandOptions::parse_from(
+                std::iter::once("land").chain(args.iter().copied()),
+            )
+            .branch_deletion(config)
+        };
+
+        // Both branches are kept, unless configured otherwise...
+        assert_eq!(deletion(&[], &config), BranchDeletion::default());
+        config.land_delete_remote_branches = true;
+        assert_eq!(deletion(&[], &config), REMOTE_ONLY);
+        // ...or asked for on the command line
+        assert_eq!(
+            deletion(&["--no-delete-remote", "--delete-local"], &config),
+            BranchDeletion {
+                remote: false,
+                local: true
+            }
+        );
+        // The last of two opposite flags wins
+        assert_eq!(
+            deletion(&["--no-delete-remote", "--delete-remote"], &config),
+            REMOTE_ONLY
+        );
+    }
+
+    #[test]
+    fn test_delete_local_branches() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let oid = git.head().unwrap();
+        for name in ["spr/foo/one", "spr/foo/two"] {
+            git.repo()
+                .reference(&format!("refs/heads/{name}"), oid, true, "test")
+                .unwrap();
+        }
+        git.repo().set_head("refs/heads/spr/foo/two").unwrap();
+
+        delete_local_branches(
+            &git,
+            &[
+                "spr/foo/one".to_string(),
+                "spr/foo/two".to_string(),
+                "spr/foo/gone".to_string(),
+            ],
+        )
+        .unwrap();
+
+        // The checked-out branch is kept
+        assert_eq!(
+            git.local_branch_names().unwrap(),
+            vec!["master".to_string(), "spr/foo/two".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_land_target() {
+        let config = config_factory();
+
+        assert!(land_target(None, &config).unwrap().is_master_branch());
+        let target = land_target(Some("release/1.2"), &config).unwrap();
+        assert_eq!(target.on_github(), "refs/heads/release/1.2");
+        assert_eq!(target.local(), "refs/remotes/origin/release/1.2");
+
+        assert!(land_target(Some("release 1.2"), &config).is_err());
+        assert!(land_target(Some(""), &config).is_err());
+        assert!(land_target(Some("spr/foo/one"), &config).is_err());
+    }
+
+    #[test]
+    fn test_check_target_exists() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        let config = config_factory();
+        let oid = git.head().unwrap();
+        git.repo()
+            .reference("refs/remotes/origin/release", oid, true, "test")
+            .unwrap();
+
+        assert!(check_target_exists(
+            &git,
+            &config.new_github_branch("release"),
+            &config
+        )
+        .is_ok());
+        let error = check_target_exists(
+            &git,
+            &config.new_github_branch("relaese"),
+            &config,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &[
+                "Cannot land into 'relaese': there is no such branch on origin"
+                    .to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_bases() {
+        let config = config_factory();
+        let target = config.new_github_branch("release");
+
+        // Based on the target, or stacked with spr's base branches
+        let consistent = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "release"),
+            pull_request_factory(
+                &config,
+                2,
+                "spr/foo/two",
+                "spr/foo/release.two",
+            ),
+        ];
+        assert!(check_bases(&consistent, &target, &config).is_ok());
+
+        // The bottom Pull Request was created against master
+        let inconsistent = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "master"),
+            pull_request_factory(
+                &config,
+                2,
+                "spr/foo/two",
+                "spr/foo/master.two",
+            ),
+            pull_request_factory(&config, 3, "spr/foo/three", "develop"),
+        ];
+        let error = check_bases(&inconsistent, &target, &config).unwrap_err();
+        assert_eq!(
+            &error.messages()[..2],
+            &[
+                "Pull Request #1 is based on 'master', not on 'release'."
+                    .to_string(),
+                "Pull Request #3 is based on 'develop', not on 'release'."
+                    .to_string(),
+            ]
+        );
+
+        // Landing into master needs the bases to be master, as usual
+        assert!(
+            check_bases(&consistent[..1], &config.master_ref, &config).is_err()
+        );
+    }
+
+    #[test]
+    fn test_land_report_stops_at_first_blocker() {
+        let config = config_factory();
+        let pull_requests = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "master"),
+            pull_request_factory(&config, 2, "spr/foo/two", "master"),
+            pull_request_factory(&config, 3, "spr/foo/three", "master"),
+        ];
+        let plan = plan_land(
+            &pull_requests,
+            &config.master_ref,
+            BranchDeletion::default(),
+        );
+
+        let report = LandReport::new(
+            &plan,
+            vec![Ok(()), Err(Error::new("Required checks have not passed"))],
+        );
+
+        assert_eq!(report.landed, vec![1]);
+        let (blocked, error) = report.blocked.unwrap();
+        assert_eq!(blocked, 2);
+        assert_eq!(
+            error.messages(),
+            &["Required checks have not passed".to_string()]
+        );
+        assert_eq!(report.not_attempted, vec![3]);
+    }
+
+    #[test]
+    fn test_land_report_all_landed() {
+        let config = config_factory();
+        let pull_requests = vec![
+            pull_request_factory(&config, 1, "spr/foo/one", "master"),
+            pull_request_factory(&config, 2, "spr/foo/two", "master"),
+            pull_request_factory(&config, 3, "spr/foo/three", "master"),
+        ];
+        let plan = plan_land(
+            &pull_requests,
+            &config.master_ref,
+            BranchDeletion::default(),
+        );
+
+        let report = LandReport::new(&plan, vec![Ok(()), Ok(()), Ok(())]);
+
+        assert_eq!(report.landed, vec![1, 2, 3]);
+        assert!(report.blocked.is_none());
+        assert!(report.not_attempted.is_empty());
+    }
+
+    #[test]
+    fn test_check_landable() {
+        let mut config = config_factory();
+        let mut pull_request =
+            pull_request_factory(&config, 1, "spr/foo/one", "master");
+        assert!(check_landable(&pull_request, &config).is_ok());
+
+        config.require_approval = true;
+        assert!(check_landable(&pull_request, &config).is_err());
+
+        pull_request.review_status = Some(ReviewStatus::Approved);
+        assert!(check_landable(&pull_request, &config).is_ok());
+
+        pull_request.state = PullRequestState::Closed;
+        assert!(check_landable(&pull_request, &config).is_err());
+    }
+
+    #[test]
+    fn test_missing_for_landing() {
+        let config = config_factory();
+        let mut pull_request =
+            pull_request_factory(&config, 1, "spr/foo/one", "master");
+        pull_request.reviewers = [
+            ("carol", ReviewStatus::Requested),
+            ("bob", ReviewStatus::Rejected),
+            ("alice", ReviewStatus::Requested),
+        ]
+        .into_iter()
+        .map(|(reviewer, status)| (reviewer.to_string(), status))
+        .collect();
+
+        // No review decision means that no review is required
+        pull_request.review_status = None;
+        assert!(missing_for_landing(&pull_request, None).is_empty());
+        pull_request.review_status = Some(ReviewStatus::Approved);
+        assert!(missing_for_landing(&pull_request, None).is_empty());
+        assert!(check_ready_to_land(&pull_request, None).is_ok());
+
+        pull_request.review_status = Some(ReviewStatus::Requested);
+        assert_eq!(
+            missing_for_landing(&pull_request, None),
+            vec![
+                "a required approving review is missing (waiting for \
+                  alice, carol)"
+            ]
+        );
+        pull_request.review_status = Some(ReviewStatus::Rejected);
+        assert_eq!(
+            missing_for_landing(&pull_request, None),
+            vec!["changes were requested by bob"]
+        );
+        assert!(check_ready_to_land(&pull_request, None).is_err());
+
+        // Required checks count only once we know them
+        let mut checks = checks(&"1".repeat(40), ChecksState::Pending);
+        checks.unmet_required = vec!["build".to_string(), "lint".to_string()];
+        pull_request.review_status = Some(ReviewStatus::Approved);
+        assert_eq!(
+            missing_for_landing(&pull_request, Some(&checks)),
+            vec![
+                "required check build has not passed",
+                "required check lint has not passed"
+            ]
+        );
+        let error =
+            check_ready_to_land(&pull_request, Some(&checks)).unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &[
+                "This Pull Request is not ready to land: required check \
+                 build has not passed; required check lint has not passed."
+                    .to_string(),
+                "To wait for the checks to finish, use spr land \
+                 --wait-for-checks."
+                    .to_string(),
+            ]
+        );
+
+        // Optional checks that have not passed do not matter
+        checks.unmet_required.clear();
+        assert!(check_ready_to_land(&pull_request, Some(&checks)).is_ok());
+    }
+
+    #[test]
+    fn test_land_commit_message_template() {
+        let mut config = config_factory();
+        let mut pull_request =
+            pull_request_factory(&config, 12, "spr/foo/one", "master");
+        pull_request.title = "Fix the frobnicator".to_string();
+        pull_request.sections = crate::message::parse_message(
+            "Summary text\n\nStack:\nhttps://github.com/acme/codez/pull/12",
+            crate::message::MessageSection::Summary,
+        );
+        pull_request.sections.insert(
+            MessageSection::PRStack,
+            "https://github.com/acme/codez/pull/12\n".to_string(),
+        );
+
+        // Without a template, the Pull Request's title and description
+        assert_eq!(
+            land_commit_message(
+                &pull_request,
+                MergeMethod::Squash,
+                false,
+                &config
+            )
+            .unwrap()
+            .0,
+            "Fix the frobnicator"
+        );
+
+        config.land_message_template = Some(
+            "{pr_title} (#{pr_number})\n\n{pr_body}\n\nStack:\n{pr_stack}\n"
+                .to_string(),
+        );
+        let (title, message) = land_commit_message(
+            &pull_request,
+            MergeMethod::Squash,
+            false,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(title, "Fix the frobnicator (#12)");
+        assert!(message.starts_with("Summary text\n\nStack:\n"));
+        assert!(message.ends_with("https://github.com/acme/codez/pull/12\n"));
+
+        // A template of just a title leaves the message empty
+        config.land_message_template = Some("Land {pr_url}".to_string());
+        assert_eq!(
+            land_commit_message(
+                &pull_request,
+                MergeMethod::Squash,
+                false,
+                &config
+            )
+            .unwrap(),
+            (
+                "Land https://github.com/acme/codez/pull/12".to_string(),
+                String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn test_squash_title_from_pr() {
+        let mut config = config_factory();
+        config.land_message_template = Some("{pr_url}".to_string());
+        let mut pull_request =
+            pull_request_factory(&config, 12, "spr/foo/one", "master");
+        // The PR was retitled after the commit was submitted, which the
+        // commit (and so the branch's prepared commit) does not know
+        pull_request.title = "Fix the frobnicator for good".to_string();
+        pull_request.sections = crate::message::parse_message(
+            "Fix the frobnicator\n\nSummary text",
+            crate::message::MessageSection::Title,
+        );
+
+        assert_eq!(
+            land_commit_message(
+                &pull_request,
+                MergeMethod::Squash,
+                true,
+                &config
+            )
+            .unwrap(),
+            (
+                "Fix the frobnicator for good (#12)".to_string(),
+                "Summary text\n".to_string()
+            )
+        );
+
+        // A title that already ends with the number does not get it twice
+        pull_request.title = "Fix the frobnicator (#12)".to_string();
+        assert_eq!(squash_title(&pull_request), "Fix the frobnicator (#12)");
+
+        // Other merge methods, or without the option, are as before
+        assert_eq!(
+            land_commit_message(
+                &pull_request,
+                MergeMethod::Merge,
+                true,
+                &config
+            )
+            .unwrap()
+            .0,
+            "https://github.com/acme/codez/pull/12"
+        );
+        assert_eq!(
+            land_commit_message(
+                &pull_request,
+                MergeMethod::Squash,
+                false,
+                &config
+            )
+            .unwrap()
+            .0,
+            "https://github.com/acme/codez/pull/12"
+        );
+    }
+
+    #[test]
+    fn test_land_message_template_unknown_placeholder() {
+        assert!(
+            validate_land_message_template("{pr_title}\n\n{pr_body}").is_ok()
+        );
+        assert!(validate_land_message_template("{pr_titel}").is_err());
+
+        let mut config = config_factory();
+        config.land_message_template = Some("{pr_author}".to_string());
+        let pull_request =
+            pull_request_factory(&config, 12, "spr/foo/one", "master");
+        let error = land_commit_message(
+            &pull_request,
+            MergeMethod::Squash,
+            false,
+            &config,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &vec![
+                "Unknown placeholder {pr_author} in template (known ones are \
+                 {pr_title}, {pr_body}, {pr_number}, {pr_url}, {pr_stack})"
+                    .to_string(),
+                "Invalid spr.landMessageTemplate".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_merge_request_author() {
+        let (_dir, git) = crate::git::tests::test_repo();
+        {
+            let repo = git.repo();
+            let mut git_config = repo.config().unwrap();
+            git_config.set_str("user.name", "Maintainer").unwrap();
+            git_config
+                .set_str("user.email", "maintainer@acme.com")
+                .unwrap();
+        }
+        let mut config = config_factory();
+        let mut pull_request =
+            pull_request_factory(&config, 1, "spr/foo/one", "master");
+        pull_request.sections = crate::message::parse_message(
+            "Summary text",
+            crate::message::MessageSection::Summary,
+        );
+        pull_request.author = Some(CommitIdentity {
+            name: "Pat Author".to_string(),
+            email: "pat@acme.com".to_string(),
+        });
+        let plan = plan_land(
+            &[pull_request.clone()],
+            &config.master_ref,
+            BranchDeletion::default(),
+        );
+        let request = |config: &crate::config::Config| {
+            let author = merge_author(&git, config, &pull_request).unwrap();
+            build_merge_request(
+                &pull_request,
+                &plan[0],
+                git2::Oid::zero(),
+                author.as_ref(),
+                &[],
+                config,
+            )
+            .unwrap()
+        };
+
+        let merge_request = request(&config);
+        assert_eq!(merge_request.commit_title, "PR 1");
+        assert_eq!(merge_request.commit_message, "Summary text\n");
+        assert_eq!(merge_request.sha, git2::Oid::zero().to_string());
+
+        config.land_author = LandAuthor::PullRequestAuthor;
+        assert_eq!(
+            request(&config).commit_message,
+            "Summary text\n\nCo-authored-by: Pat Author <pat@acme.com>\n"
+        );
+
+        config.land_author = LandAuthor::CurrentUser;
+        assert_eq!(
+            request(&config).commit_message,
+            "Summary text\n\n\
+             Co-authored-by: Maintainer <maintainer@acme.com>\n"
+        );
+
+        // The configured trailers go before Co-authored-by
+        config.land_trailers = vec!["PR-URL: {pr_url}".to_string()];
+        assert_eq!(
+            request(&config).commit_message,
+            "Summary text\n\n\
+             PR-URL: https://github.com/acme/codez/pull/1\n\
+             Co-authored-by: Maintainer <maintainer@acme.com>\n"
+        );
+    }
+
+    #[test]
+    fn test_build_merge_request_co_authors() {
+        use crate::git::tests::create_commit;
+
+        let (_dir, git) = crate::git::tests::test_repo();
+        let mut config = config_factory();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let base = create_commit(
+            &git,
+            "[spr] changes to master this commit is based on\n\n\
+             Co-authored-by: Base Author <base@acme.com>",
+            &[master],
+        );
+        let first = create_commit(
+            &git,
+            "[spr] initial version\n\nCreated using spr 1.3.6\n\n\
+             Co-authored-by: Pat Author <pat@acme.com>",
+            &[master],
+        );
+        let head = create_commit(
+            &git,
+            "[spr] changes introduced through rebase\n\n\
+             Created using spr 1.3.6\n\n\
+             Co-authored-by: Pat Author <pat@acme.com>\n\
+             co-authored-by: Sam Other <sam@acme.com>",
+            &[first, base],
+        );
+
+        // The trailers of the Pull Request branch's own commits, each once
+        let co_authors = git.co_author_trailers(head, master).unwrap();
+        assert_eq!(
+            co_authors,
+            vec![
+                "Co-authored-by: Pat Author <pat@acme.com>",
+                "co-authored-by: Sam Other <sam@acme.com>",
+            ]
+        );
+
+        let mut pull_request =
+            pull_request_factory(&config, 1, "spr/foo/one", "master");
+        pull_request.sections = crate::message::parse_message(
+            "Summary text",
+            crate::message::MessageSection::Summary,
+        );
+        pull_request.author = Some(CommitIdentity {
+            name: "Pat Author".to_string(),
+            email: "pat@acme.com".to_string(),
+        });
+        config.land_author = LandAuthor::PullRequestAuthor;
+        let plan = plan_land(
+            &[pull_request.clone()],
+            &config.master_ref,
+            BranchDeletion::default(),
+        );
+        let merge_request = build_merge_request(
+            &pull_request,
+            &plan[0],
+            head,
+            pull_request.author.as_ref(),
+            &co_authors,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            merge_request.commit_message,
+            "Summary text\n\n\
+             Co-authored-by: Pat Author <pat@acme.com>\n\
+             co-authored-by: Sam Other <sam@acme.com>\n"
+        );
+    }
+
+    #[test]
+    fn test_land_trailers() {
+        let mut config = config_factory();
+        config.land_trailers = vec![
+            "Reviewed-by: {reviewer}".to_string(),
+            "PR-URL: {pr_url}".to_string(),
+            "PR-Number: #{pr_number}".to_string(),
+        ];
+        let mut pull_request =
+            pull_request_factory(&config, 12, "spr/foo/one", "master");
+        pull_request.reviewers = [
+            ("carol", ReviewStatus::Approved),
+            ("bob", ReviewStatus::Rejected),
+            ("alice", ReviewStatus::Approved),
+            ("dave", ReviewStatus::Requested),
+        ]
+        .into_iter()
+        .map(|(login, status)| (login.to_string(), status))
+        .collect();
+
+        assert_eq!(
+            land_trailers(&pull_request, &config).unwrap(),
+            vec![
+                "Reviewed-by: alice",
+                "Reviewed-by: carol",
+                "PR-URL: https://github.com/acme/codez/pull/12",
+                "PR-Number: #12",
+            ]
+        );
+
+        // Without approvals, the reviewer trailers are left out
+        pull_request.reviewers.clear();
+        assert_eq!(
+            land_trailers(&pull_request, &config).unwrap(),
+            vec![
+                "PR-URL: https://github.com/acme/codez/pull/12",
+                "PR-Number: #12",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_land_trailer() {
+        assert!(validate_land_trailer("Reviewed-by: {reviewer}").is_ok());
+        assert!(validate_land_trailer("PR-URL: {pr_url}").is_ok());
+        assert!(validate_land_trailer("Approved-by: {approver}").is_err());
+        assert!(validate_land_trailer("One: 1\nTwo: 2").is_err());
+    }
+
+    fn checks(head: &str, state: ChecksState) -> PullRequestChecks {
+        PullRequestChecks {
+            head_oid: git2::Oid::from_str(head).unwrap(),
+            state,
+            failed: Vec::new(),
+            unmet_required: Vec::new(),
+        }
+    }
+
+    /// Run the decisions for a sequence of polls, a minute apart, until one
+    /// of them is not to wait.
+    fn decide_sequence(
+        polls: &[PullRequestChecks],
+        timeout: Option<Duration>,
+    ) -> Vec<ChecksDecision> {
+        let head = git2::Oid::from_str("1234").unwrap();
+        let mut decisions = Vec::new();
+        for (minutes, poll) in polls.iter().enumerate() {
+            let elapsed = Duration::from_secs(60 * minutes as u64);
+            let decision = decide_checks(poll, head, elapsed, timeout);
+            let done = decision != ChecksDecision::Wait;
+            decisions.push(decision);
+            if done {
+                break;
+            }
+        }
+        decisions
+    }
+
+    #[test]
+    fn test_decide_checks_waits_until_success() {
+        let timeout = Some(Duration::from_secs(30 * 60));
+        assert_eq!(
+            decide_sequence(
+                &[
+                    checks("1234", ChecksState::Pending),
+                    checks("1234", ChecksState::Pending),
+                    checks("1234", ChecksState::Success),
+                    checks("1234", ChecksState::Failure),
+                ],
+                timeout
+            ),
+            vec![
+                ChecksDecision::Wait,
+                ChecksDecision::Wait,
+                ChecksDecision::Proceed
+            ]
+        );
+        assert_eq!(
+            decide_sequence(&[checks("1234", ChecksState::NoChecks)], timeout),
+            vec![ChecksDecision::Proceed]
+        );
+    }
+
+    #[test]
+    fn test_decide_checks_aborts_on_failure() {
+        let mut failed = checks("1234", ChecksState::Failure);
+        failed.failed = vec!["build".to_string(), "lint".to_string()];
+
+        assert_eq!(
+            decide_sequence(
+                &[checks("1234", ChecksState::Pending), failed],
+                None
+            ),
+            vec![
+                ChecksDecision::Wait,
+                ChecksDecision::Abort("Checks failed: build, lint".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decide_checks_times_out() {
+        assert_eq!(
+            decide_sequence(
+                &[
+                    checks("1234", ChecksState::Pending),
+                    checks("1234", ChecksState::Pending),
+                    checks("1234", ChecksState::Pending),
+                ],
+                Some(Duration::from_secs(2 * 60))
+            ),
+            vec![
+                ChecksDecision::Wait,
+                ChecksDecision::Wait,
+                ChecksDecision::Abort(
+                    "Checks did not finish within 120 seconds".to_string()
+                )
+            ]
+        );
+
+        // Without a timeout, we keep waiting
+        assert_eq!(
+            decide_sequence(
+                &vec![checks("1234", ChecksState::Pending); 3],
+                None
+            )
+            .last(),
+            Some(&ChecksDecision::Wait)
+        );
+    }
+
+    #[test]
+    fn test_decide_checks_ignores_previous_head() {
+        // GitHub has not caught up with our push yet, so what it reports is
+        // about another commit
+        assert_eq!(
+            decide_sequence(
+                &[
+                    checks("abcd", ChecksState::Success),
+                    checks("abcd", ChecksState::Failure),
+                    checks("1234", ChecksState::Success),
+                ],
+                None
+            ),
+            vec![
+                ChecksDecision::Wait,
+                ChecksDecision::Wait,
+                ChecksDecision::Proceed
+            ]
+        );
+    }
+}
@@ -7,11 +7,14 @@
 
 use crate::error::Error;
 use crate::error::Result;
+use crate::github::{fetch_all_pages, send_graphql_request, Page, MAX_PAGES};
+use crate::message::MessageSection;
 use graphql_client::{GraphQLQuery, Response};
 use reqwest;
 
 #[allow(clippy::upper_case_acronyms)]
 type URI = String;
+type DateTime = chrono::DateTime<chrono::Utc>;
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "src/gql/schema.docs.graphql",
@@ -20,53 +23,287 @@ type URI = String;
 )]
 pub struct SearchQuery;
 
+type PullRequestNode = search_query::SearchQuerySearchNodesOnPullRequest;
+
+/// Which Pull Requests `spr list` shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListState {
+    Open,
+    Closed,
+    Merged,
+    All,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct ListOptions {
+    /// Only list Pull Requests in this state
+    #[clap(long, value_enum, default_value = "open")]
+    state: ListState,
+
+    /// List Pull Requests in any state (same as --state all)
+    #[clap(long, short = 'a')]
+    all: bool,
+
+    /// Only list the stacks whose most recently updated Pull Request has not
+    /// been updated for this many days
+    #[clap(long, value_name = "DAYS")]
+    stale: Option<u32>,
+}
+
+/// The qualifiers for GitHub's search query that select Pull Requests in the
+/// given state.
+fn state_qualifiers(state: ListState) -> &'static str {
+    match state {
+        ListState::Open => " is:open",
+        ListState::Closed => " is:closed is:unmerged",
+        ListState::Merged => " is:merged",
+        ListState::All => "",
+    }
+}
+
+/// What became of a Pull Request, or (while it is open) how far it got with
+/// its review.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReviewDecision {
+    Merged,
+    Closed,
+    Accepted,
+    ChangesRequested,
+    Pending,
+    /// A review decision that GitHub added after this version of spr
+    Other(String),
+}
+
+/// A Pull Request as `spr list` shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedPullRequest {
+    pub number: u64,
+    pub title: String,
+    pub url: String,
+    pub decision: ReviewDecision,
+    /// When anything about the Pull Request last changed on GitHub
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// The Pull Requests in the PR Stack section of its description (this
+    /// one included), or none if it has no such section
+    pub pr_stack: Vec<u64>,
+    /// The name of the branch it is based on
+    pub base: String,
+}
+
+impl From<PullRequestNode> for ListedPullRequest {
+    fn from(pr: PullRequestNode) -> Self {
+        let decision = match (&pr.state, pr.review_decision) {
+            (search_query::PullRequestState::MERGED, _) => {
+                ReviewDecision::Merged
+            }
+            (search_query::PullRequestState::CLOSED, _) => {
+                ReviewDecision::Closed
+            }
+            (_, Some(search_query::PullRequestReviewDecision::APPROVED)) => {
+                ReviewDecision::Accepted
+            }
+            (
+                _,
+                Some(
+                    search_query::PullRequestReviewDecision::CHANGES_REQUESTED,
+                ),
+            ) => ReviewDecision::ChangesRequested,
+            (
+                _,
+                None
+                | Some(search_query::PullRequestReviewDecision::REVIEW_REQUIRED),
+            ) => ReviewDecision::Pending,
+            (_, Some(search_query::PullRequestReviewDecision::Other(d))) => {
+                ReviewDecision::Other(d)
+            }
+        };
+
+        ListedPullRequest {
+            number: pr.number as u64,
+            title: pr.title,
+            url: pr.url,
+            decision,
+            updated_at: pr.updated_at,
+            pr_stack: pr_stack_numbers(&pr.body),
+            base: pr.base_ref_name,
+        }
+    }
+}
+
+/// The Pull Requests in the PR Stack section of a Pull Request description.
+fn pr_stack_numbers(body: &str) -> Vec<u64> {
+    let (body, marked_pr_stack) = crate::message::take_marked_pr_stack(body);
+    let pr_stack = marked_pr_stack.or_else(|| {
+        crate::message::parse_message(&body, MessageSection::Summary)
+            .remove(&MessageSection::PRStack)
+    });
+
+    pr_stack
+        .map(|pr_stack| crate::utils::parse_pr_stack_list(&pr_stack))
+        .unwrap_or_default()
+}
+
+/// The stacks that `pull_requests` make up, as indices into it: Pull Requests
+/// are in the same stack if one is in the PR Stack of the other (or both are
+/// in that of a third). Stacks are in the order of their first Pull Request
+/// in `pull_requests`, and so are the Pull Requests in each stack.
+pub fn stacks(pull_requests: &[ListedPullRequest]) -> Vec<Vec<usize>> {
+    // Union-find over the indices, linked through the Pull Request numbers
+    // they mention
+    let mut parent: Vec<usize> = (0..pull_requests.len()).collect();
+    fn root(parent: &[usize], mut index: usize) -> usize {
+        while parent[index] != index {
+            index = parent[index];
+        }
+        index
+    }
+
+    let mut first_mention = std::collections::HashMap::<u64, usize>::new();
+    for (index, pr) in pull_requests.iter().enumerate() {
+        for &number in std::iter::once(&pr.number).chain(&pr.pr_stack) {
+            match first_mention.get(&number) {
+                Some(&other) => {
+                    let (a, b) = (root(&parent, index), root(&parent, other));
+                    parent[a.max(b)] = a.min(b);
+                }
+                None => {
+                    first_mention.insert(number, index);
+                }
+            }
+        }
+    }
+
+    let mut stacks: Vec<Vec<usize>> = Vec::new();
+    let mut stack_of_root = std::collections::HashMap::<usize, usize>::new();
+    for index in 0..pull_requests.len() {
+        let stack =
+            *stack_of_root
+                .entry(root(&parent, index))
+                .or_insert_with(|| {
+                    stacks.push(Vec::new());
+                    stacks.len() - 1
+                });
+        stacks[stack].push(index);
+    }
+
+    stacks
+}
+
+/// The Pull Requests of the stacks whose most recently updated Pull Request
+/// was last updated more than `days` days before `now`, stack by stack.
+pub fn stale_pull_requests(
+    pull_requests: &[ListedPullRequest],
+    now: chrono::DateTime<chrono::Utc>,
+    days: u32,
+) -> Vec<ListedPullRequest> {
+    let threshold = now - chrono::Duration::days(days.into());
+
+    stacks(pull_requests)
+        .into_iter()
+        .filter(|stack| {
+            stack
+                .iter()
+                .map(|&index| pull_requests[index].updated_at)
+                .max()
+                .is_some_and(|newest| newest < threshold)
+        })
+        .flatten()
+        .map(|index| pull_requests[index].clone())
+        .collect()
+}
+
+/// Your Pull Requests in the repository that are in the given state.
+pub async fn search_pull_requests(
+    graphql_client: &reqwest::Client,
+    config: &crate::config::Config,
+    state: ListState,
+) -> Result<Vec<ListedPullRequest>> {
+    let query = format!(
+        "repo:{}/{} is:pr author:@me archived:false{}",
+        config.owner,
+        config.repo,
+        state_qualifiers(state)
+    );
+
+    let pull_requests = fetch_all_pages(MAX_PAGES, |after| {
+        let variables = search_query::Variables {
+            query: query.clone(),
+            after,
+        };
+        async move {
+            let request_body = SearchQuery::build_query(variables);
+            let res =
+                send_graphql_request(graphql_client, config, &request_body)
+                    .await?;
+            let response_body: Response<search_query::ResponseData> =
+                res.json().await?;
+
+            response_body
+                .data
+                .map(search_page)
+                .ok_or_else(|| Error::new("unexpected error"))
+        }
+    })
+    .await?;
+
+    Ok(pull_requests.into_iter().map(Into::into).collect())
+}
+
 pub async fn list(
+    opts: ListOptions,
     graphql_client: reqwest::Client,
     config: &crate::config::Config,
 ) -> Result<()> {
-    let variables = search_query::Variables {
-        query: format!(
-            "repo:{}/{} is:open is:pr author:@me archived:false",
-            config.owner, config.repo
-        ),
-    };
-    let request_body = SearchQuery::build_query(variables);
-    let res = graphql_client
-        .post("https://api.github.com/graphql")
-        .json(&request_body)
-        .send()
-        .await?;
-    let response_body: Response<search_query::ResponseData> =
-        res.json().await?;
-
-    print_pr_info(response_body).ok_or_else(|| Error::new("unexpected error"))
+    let state = if opts.all { ListState::All } else { opts.state };
+    let mut pull_requests =
+        search_pull_requests(&graphql_client, config, state).await?;
+    if let Some(days) = opts.stale {
+        pull_requests =
+            stale_pull_requests(&pull_requests, chrono::Utc::now(), days);
+    }
+
+    print_pr_info(&pull_requests).ok_or_else(|| Error::new("unexpected error"))
 }
 
-fn print_pr_info(
-    response_body: Response<search_query::ResponseData>,
-) -> Option<()> {
+/// The Pull Requests on one page of search results.
+fn search_page(data: search_query::ResponseData) -> Page<PullRequestNode> {
+    let search = data.search;
+    let items = search
+        .nodes
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|node| match node {
+            search_query::SearchQuerySearchNodes::PullRequest(pr) => Some(pr),
+            _ => None,
+        })
+        .collect();
+
+    Page::new(
+        items,
+        search.page_info.has_next_page,
+        search.page_info.end_cursor,
+    )
+}
+
+fn print_pr_info(pull_requests: &[ListedPullRequest]) -> Option<()> {
     let term = console::Term::stdout();
-    for pr in response_body.data?.search.nodes? {
-        let pr = match pr {
-            Some(crate::commands::list::search_query::SearchQuerySearchNodes::PullRequest(pr)) => pr,
-            _ => continue,
-        };
-        let dummy: String;
-        let decision = match pr.review_decision {
-            Some(search_query::PullRequestReviewDecision::APPROVED) => {
-                console::style("Accepted").green()
+    for pr in pull_requests {
+        let decision = match &pr.decision {
+            ReviewDecision::Merged => {
+                console::style("Merged".to_string()).magenta()
+            }
+            ReviewDecision::Closed => {
+                console::style("Closed".to_string()).dim()
             }
-            Some(
-                search_query::PullRequestReviewDecision::CHANGES_REQUESTED,
-            ) => console::style("Changes Requested").red(),
-            None
-            | Some(search_query::PullRequestReviewDecision::REVIEW_REQUIRED) => {
-                console::style("Pending")
+            ReviewDecision::Accepted => {
+                console::style("Accepted".to_string()).green()
             }
-            Some(search_query::PullRequestReviewDecision::Other(d)) => {
-                dummy = d;
-                console::style(dummy.as_str())
+            ReviewDecision::ChangesRequested => {
+                console::style("Changes Requested".to_string()).red()
             }
+            ReviewDecision::Pending => console::style("Pending".to_string()),
+            ReviewDecision::Other(d) => console::style(d.clone()),
         };
         term.write_line(&format!(
             "{} {} {}",
@@ -78,3 +315,174 @@ fn print_pr_info(
     }
     Some(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(json: &str) -> search_query::ResponseData {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_state_qualifiers() {
+        assert_eq!(state_qualifiers(ListState::Open), " is:open");
+        assert_eq!(
+            state_qualifiers(ListState::Closed),
+            " is:closed is:unmerged"
+        );
+        assert_eq!(state_qualifiers(ListState::Merged), " is:merged");
+        assert_eq!(state_qualifiers(ListState::All), "");
+    }
+
+    #[tokio::test]
+    async fn test_search_pages() {
+        let mut cursors = Vec::new();
+        let pull_requests = fetch_all_pages(MAX_PAGES, |after| {
+            cursors.push(after.clone());
+            let response = match after.as_deref() {
+                None => page(
+                    r#"{"search": {
+                        "pageInfo": {"hasNextPage": true, "endCursor": "c1"},
+                        "nodes": [
+                            {"__typename": "PullRequest", "number": 1,
+                             "title": "One", "url": "https://x/1",
+                             "state": "OPEN", "reviewDecision": null,
+                             "updatedAt": "2024-06-01T12:00:00Z",
+                             "body": "Summary\n\nPR Stack:\nhttps://x/pull/1",
+                             "baseRefName": "master"},
+                            {"__typename": "Issue"}
+                        ]
+                    }}"#,
+                ),
+                Some("c1") => page(
+                    r#"{"search": {
+                        "pageInfo": {"hasNextPage": false, "endCursor": "c2"},
+                        "nodes": [
+                            {"__typename": "PullRequest", "number": 2,
+                             "title": "Two", "url": "https://x/2",
+                             "state": "MERGED", "reviewDecision": "APPROVED",
+                             "updatedAt": "2024-06-02T12:00:00Z",
+                             "body": "", "baseRefName": "spr/foo/master.two"}
+                        ]
+                    }}"#,
+                ),
+                Some(cursor) => panic!("unexpected cursor {}", cursor),
+            };
+            std::future::ready(Ok(search_page(response)))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(cursors, vec![None, Some("c1".to_string())]);
+        assert_eq!(
+            pull_requests.iter().map(|pr| pr.number).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        let listed: Vec<ListedPullRequest> =
+            pull_requests.into_iter().map(Into::into).collect();
+        assert_eq!(
+            listed,
+            vec![
+                ListedPullRequest {
+                    number: 1,
+                    title: "One".to_string(),
+                    url: "https://x/1".to_string(),
+                    decision: ReviewDecision::Pending,
+                    updated_at: date("2024-06-01T12:00:00Z"),
+                    pr_stack: vec![1],
+                    base: "master".to_string(),
+                },
+                ListedPullRequest {
+                    number: 2,
+                    title: "Two".to_string(),
+                    url: "https://x/2".to_string(),
+                    decision: ReviewDecision::Merged,
+                    updated_at: date("2024-06-02T12:00:00Z"),
+                    pr_stack: Vec::new(),
+                    base: "spr/foo/master.two".to_string(),
+                },
+            ]
+        );
+    }
+
+    fn date(text: &str) -> chrono::DateTime<chrono::Utc> {
+        text.parse().unwrap()
+    }
+
+    /// A Pull Request last updated at `updated_at`, in a stack with the given
+    /// ones.
+    fn listed(
+        number: u64,
+        updated_at: &str,
+        pr_stack: &[u64],
+    ) -> ListedPullRequest {
+        ListedPullRequest {
+            number,
+            title: format!("PR {}", number),
+            url: format!("https://x/{}", number),
+            decision: ReviewDecision::Pending,
+            updated_at: date(updated_at),
+            pr_stack: pr_stack.to_vec(),
+            base: "master".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_pr_stack_numbers() {
+        assert_eq!(
+            pr_stack_numbers(
+                "Summary\n\nPR Stack:\n\
+                 https://github.com/acme/codez/pull/3 <-- (current PR)\n\
+                 https://github.com/acme/codez/pull/2\n"
+            ),
+            vec![3, 2]
+        );
+        assert!(pr_stack_numbers("Just a description").is_empty());
+    }
+
+    #[test]
+    fn test_stacks() {
+        let pull_requests = vec![
+            listed(1, "2024-06-01T00:00:00Z", &[2, 1]),
+            listed(5, "2024-06-01T00:00:00Z", &[]),
+            // Linked to #1 through #2, which is not listed itself
+            listed(3, "2024-06-01T00:00:00Z", &[3, 2]),
+            listed(7, "2024-06-01T00:00:00Z", &[8, 7]),
+            listed(8, "2024-06-01T00:00:00Z", &[8, 7]),
+        ];
+
+        assert_eq!(
+            stacks(&pull_requests),
+            vec![vec![0, 2], vec![1], vec![3, 4]]
+        );
+    }
+
+    #[test]
+    fn test_stale_pull_requests() {
+        let now = date("2024-06-30T12:00:00Z");
+        let pull_requests = vec![
+            // A stack whose newest Pull Request is 20 days old
+            listed(1, "2024-05-01T00:00:00Z", &[2, 1]),
+            listed(2, "2024-06-10T12:00:00Z", &[2, 1]),
+            // A stack that is 45 days old
+            listed(3, "2024-05-16T12:00:00Z", &[]),
+            // One that was updated yesterday, though its bottom is old
+            listed(4, "2024-04-01T00:00:00Z", &[5, 4]),
+            listed(5, "2024-06-29T12:00:00Z", &[5, 4]),
+        ];
+        let numbers = |days| {
+            stale_pull_requests(&pull_requests, now, days)
+                .iter()
+                .map(|pr| pr.number)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(numbers(14), vec![1, 2, 3]);
+        assert_eq!(numbers(30), vec![3]);
+        // Exactly 45 days is not older than 45 days
+        assert!(numbers(45).is_empty());
+        assert_eq!(numbers(0), vec![1, 2, 3, 4, 5]);
+    }
+}
@@ -5,11 +5,34 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+pub mod adopt;
 pub mod amend;
 pub mod close;
+pub mod comment;
+pub mod config;
 pub mod diff;
+pub mod edit;
+pub mod export;
+pub mod export_diff;
+pub mod fix_footers;
+pub mod fixup;
 pub mod format;
+pub mod graph;
+pub mod import;
 pub mod init;
+pub mod is_managed;
 pub mod land;
 pub mod list;
+pub mod open;
 pub mod patch;
+pub mod preview;
+pub mod prompt;
+pub mod prune;
+pub mod ready;
+pub mod relink;
+pub mod rename_branches;
+pub mod reviews;
+pub mod undo;
+pub mod verify;
+pub mod version;
+pub mod watch;
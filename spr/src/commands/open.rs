@@ -0,0 +1,82 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::process::{Command, Stdio};
+
+use crate::{
+    error::{Error, Result},
+    output::output,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct OpenOptions {
+    /// Number of the Pull Request to open (defaults to the Pull Request of
+    /// the HEAD commit)
+    pull_request: Option<u64>,
+}
+
+pub async fn open(
+    opts: OpenOptions,
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let pull_request_number = match opts.pull_request {
+        Some(number) => number,
+        None => {
+            let prepared_commits = git.get_prepared_commits(config)?;
+            let commit = prepared_commits.last().ok_or_else(|| {
+                Error::new("Branch is empty - there is no Pull Request to open")
+            })?;
+
+            commit.pull_request_number.ok_or_else(|| {
+                Error::new(
+                    "This commit does not have a Pull Request yet. Run \
+                     `spr diff` to create one.",
+                )
+            })?
+        }
+    };
+
+    let url = config.pull_request_url(pull_request_number);
+    if open_in_browser(&url) {
+        output("🌐", &format!("Opened {}", url))?;
+    } else {
+        // There is no browser we can launch (e.g. on a headless system), so
+        // just tell the user where to go.
+        output("🔗", &url)?;
+    }
+
+    Ok(())
+}
+
+/// Try to open `url` in the user's browser. Returns `false` if no browser
+/// could be launched.
+fn open_in_browser(url: &str) -> bool {
+    let mut cmd = if let Some(browser) = std::env::var_os("BROWSER") {
+        Command::new(browser)
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", "start", ""]);
+        cmd
+    } else if std::env::var_os("DISPLAY").is_some()
+        || std::env::var_os("WAYLAND_DISPLAY").is_some()
+    {
+        Command::new("xdg-open")
+    } else {
+        return false;
+    };
+
+    cmd.arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
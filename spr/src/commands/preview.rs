@@ -0,0 +1,111 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::{
+    error::Result,
+    git::PreparedCommit,
+    output::{output, write_commit_title},
+};
+
+/// What `spr diff` would do with a commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewAction {
+    /// The commit has no Pull Request yet, so one would be created
+    Create,
+    /// The commit refers to this Pull Request, which would be updated
+    Update(u64),
+}
+
+/// Work out for each commit whether submitting it would create a new Pull
+/// Request or update the one it already refers to.
+pub fn classify(prepared_commits: &[PreparedCommit]) -> Vec<PreviewAction> {
+    prepared_commits
+        .iter()
+        .map(|commit| match commit.pull_request_number {
+            Some(number) => PreviewAction::Update(number),
+            None => PreviewAction::Create,
+        })
+        .collect()
+}
+
+pub async fn preview(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let prepared_commits = git.get_prepared_commits(config)?;
+    if prepared_commits.is_empty() {
+        output("👋", "Branch is empty - nothing to do. Good bye!")?;
+        return Ok(());
+    }
+
+    let actions = classify(&prepared_commits);
+    for (commit, action) in prepared_commits.iter().zip(&actions) {
+        write_commit_title(commit)?;
+        match action {
+            PreviewAction::Create => {
+                output("🆕", "Would create a new Pull Request")?
+            }
+            PreviewAction::Update(number) => {
+                output("🔁", &format!("Would update Pull Request #{}", number))?
+            }
+        }
+        for change in git.submodule_changes(commit.parent_oid, commit.oid)? {
+            output("📦", &change.to_string())?;
+        }
+    }
+
+    let created = actions
+        .iter()
+        .filter(|&&action| action == PreviewAction::Create)
+        .count();
+    output(
+        "📋",
+        &format!("{} new, {} to update", created, actions.len() - created),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::git::tests::{create_commit, test_repo};
+
+    #[test]
+    fn test_classify() {
+        let config = config_factory();
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+
+        let messages = [
+            "Tracked\n\nPull Request: https://github.com/acme/codez/pull/12\n",
+            "Untracked\n",
+            "Tracked too\n\nPull Request: #13\n",
+            // A Pull Request in another repository does not count
+            "Elsewhere\n\nPull Request: https://github.com/acme/other/pull/7\n",
+        ];
+        let mut parent = root;
+        let prepared_commits = messages
+            .iter()
+            .map(|message| {
+                parent = create_commit(&git, message, &[parent]);
+                git.prepare_commit(&config, parent).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            classify(&prepared_commits),
+            vec![
+                PreviewAction::Update(12),
+                PreviewAction::Create,
+                PreviewAction::Update(13),
+                PreviewAction::Create,
+            ]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io::Write;
+
+use crate::{error::Result, git::PreparedCommit};
+
+/// Where HEAD is in the stack, as far as the local repository knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackStatus {
+    /// How many commits of the stack are at or below HEAD
+    pub position: usize,
+    /// How many commits the stack has, including the ones above HEAD (while
+    /// a rebase has HEAD in the middle of the branch)
+    pub total: usize,
+    /// How many commits at or below HEAD have no Pull Request yet
+    pub unsubmitted: usize,
+}
+
+impl StackStatus {
+    pub fn new(commits: &[PreparedCommit], total: usize) -> Self {
+        StackStatus {
+            position: commits.len(),
+            total: total.max(commits.len()),
+            unsubmitted: commits
+                .iter()
+                .filter(|commit| commit.pull_request_number.is_none())
+                .count(),
+        }
+    }
+}
+
+/// The status in a few characters, like `spr:3/5✓`: the position of HEAD in
+/// the stack, the size of the stack, and whether every commit up to HEAD has
+/// a Pull Request (✓) or not (✗).
+pub fn format_prompt(status: &StackStatus) -> String {
+    format!(
+        "spr:{}/{}{}",
+        status.position,
+        status.total,
+        if status.unsubmitted == 0 {
+            '✓'
+        } else {
+            '✗'
+        }
+    )
+}
+
+/// Print the status of the stack for a shell prompt. This looks at the local
+/// repository only, and prints nothing if there is no stack.
+pub async fn prompt(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let prepared_commits = git.get_prepared_commits(config)?;
+    if prepared_commits.is_empty() {
+        return Ok(());
+    }
+
+    // In the middle of a rebase, HEAD is somewhere in the branch being rebased
+    let total = match git.rebased_branch() {
+        Some(branch) => {
+            git.count_commits(&branch, config.master_ref.local())?
+        }
+        None => prepared_commits.len(),
+    };
+
+    let status = StackStatus::new(&prepared_commits, total);
+    writeln!(std::io::stdout(), "{}", format_prompt(&status))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::prepared_commit_factory;
+
+    fn commits(pull_requests: &[Option<u64>]) -> Vec<PreparedCommit> {
+        pull_requests
+            .iter()
+            .enumerate()
+            .map(|(index, &number)| {
+                let mut commit =
+                    prepared_commit_factory(&index.to_string(), "Commit");
+                commit.pull_request_number = number;
+                commit
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_format_prompt_in_sync() {
+        let status = StackStatus::new(&commits(&[Some(1), Some(2)]), 2);
+        assert_eq!(format_prompt(&status), "spr:2/2✓");
+    }
+
+    #[test]
+    fn test_format_prompt_unsubmitted() {
+        let status = StackStatus::new(&commits(&[Some(1), None]), 2);
+        assert_eq!(status.unsubmitted, 1);
+        assert_eq!(format_prompt(&status), "spr:2/2✗");
+    }
+
+    #[test]
+    fn test_format_prompt_in_the_middle_of_the_stack() {
+        let status =
+            StackStatus::new(&commits(&[Some(1), Some(2), Some(3)]), 5);
+        assert_eq!(format_prompt(&status), "spr:3/5✓");
+
+        // The stack is never smaller than the part of it below HEAD
+        let status = StackStatus::new(&commits(&[Some(1), Some(2)]), 1);
+        assert_eq!(format_prompt(&status), "spr:2/2✓");
+    }
+}
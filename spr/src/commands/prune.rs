@@ -0,0 +1,172 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::{
+    error::{Error, Result},
+    github::PullRequestState,
+    output::output,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct PruneOptions {
+    /// Delete the branches without asking for confirmation
+    #[clap(long, short = 'y')]
+    yes: bool,
+}
+
+/// The local branches that may be pruned: those named like the branches spr
+/// creates for Pull Requests, except the one that is checked out.
+pub fn prune_candidates<'a>(
+    branch_names: &'a [String],
+    checked_out: Option<&str>,
+    config: &crate::config::Config,
+) -> Vec<&'a str> {
+    branch_names
+        .iter()
+        .map(String::as_str)
+        .filter(|name| name.starts_with(&config.branch_prefix))
+        .filter(|name| Some(*name) != checked_out)
+        .collect()
+}
+
+/// Whether a branch is done with, given the states of the Pull Requests that
+/// use it. A branch no Pull Request uses is left alone, since we cannot tell
+/// what it is for.
+fn is_stale(states: &[PullRequestState]) -> bool {
+    !states.is_empty()
+        && states
+            .iter()
+            .all(|state| *state == PullRequestState::Closed)
+}
+
+pub async fn prune(
+    opts: PruneOptions,
+    git: &crate::git::Git,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let branch_names = git.local_branch_names()?;
+    let checked_out = git.checked_out_branch();
+    let candidates =
+        prune_candidates(&branch_names, checked_out.as_deref(), config);
+
+    let mut stale = Vec::new();
+    for name in candidates {
+        if is_stale(&gh.get_pull_request_states_for_branch(name).await?) {
+            stale.push(name);
+        }
+    }
+
+    if stale.is_empty() {
+        output("👍", "No local branches to prune - nothing to do")?;
+        return Ok(());
+    }
+
+    output(
+        "🧹",
+        "These local branches belong to Pull Requests that are merged or \
+         closed:",
+    )?;
+    for name in &stale {
+        output("  ", name)?;
+    }
+
+    if !opts.yes {
+        if !console::user_attended() {
+            return Err(Error::new(
+                "Not deleting branches without confirmation. Run spr in a \
+                 terminal to confirm, or pass --yes.",
+            ));
+        }
+
+        let confirmed = dialoguer::Confirm::new()
+            .with_prompt("Delete them?")
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            return Err(Error::new("Aborted as per user request".to_string()));
+        }
+    }
+
+    for name in &stale {
+        git.delete_local_branch(name)?;
+    }
+    output("🗑️", &format!("Deleted {} local branch(es)", stale.len()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::git::tests::{create_commit, test_repo};
+
+    #[test]
+    fn test_prune_candidates() {
+        let config = config_factory();
+        let branch_names: Vec<String> = [
+            "master",
+            "spr/foo/one",
+            "spr/foo/master.two",
+            "spr/foo/three",
+            "spr/bar/theirs",
+            "feature/spr/foo/x",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+
+        assert_eq!(
+            prune_candidates(&branch_names, Some("spr/foo/three"), &config),
+            vec!["spr/foo/one", "spr/foo/master.two"]
+        );
+        assert_eq!(
+            prune_candidates(&branch_names, None, &config),
+            vec!["spr/foo/one", "spr/foo/master.two", "spr/foo/three"]
+        );
+    }
+
+    #[test]
+    fn test_prune_candidates_in_repo() {
+        let config = config_factory();
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let oid = create_commit(&git, "Change", &[master]);
+        for name in ["spr/foo/change", "spr/foo/other", "topic"] {
+            git.repo()
+                .reference(&format!("refs/heads/{}", name), oid, true, "test")
+                .unwrap();
+        }
+        git.repo().set_head("refs/heads/spr/foo/change").unwrap();
+
+        let branch_names = git.local_branch_names().unwrap();
+        let checked_out = git.checked_out_branch();
+        assert_eq!(checked_out.as_deref(), Some("spr/foo/change"));
+        assert_eq!(
+            prune_candidates(&branch_names, checked_out.as_deref(), &config),
+            vec!["spr/foo/other"]
+        );
+
+        git.delete_local_branch("spr/foo/other").unwrap();
+        assert!(!git
+            .local_branch_names()
+            .unwrap()
+            .contains(&"spr/foo/other".to_string()));
+    }
+
+    #[test]
+    fn test_is_stale() {
+        use PullRequestState::*;
+
+        assert!(!is_stale(&[]));
+        assert!(is_stale(&[Closed]));
+        assert!(is_stale(&[Closed, Closed]));
+        assert!(!is_stale(&[Closed, Open]));
+        assert!(!is_stale(&[Open]));
+    }
+}
@@ -0,0 +1,212 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::{
+    commands::diff::collect_reviewers,
+    error::Result,
+    forge::Forge,
+    github::{PullRequest, PullRequestRequestReviewers, PullRequestState},
+    output::output,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct ReadyOptions {
+    /// Mark the Pull Requests of the whole branch as ready, not just the one
+    /// of the HEAD commit
+    #[clap(long, short = 'a')]
+    all: bool,
+
+    /// Request review from these users or teams (teams with a leading '#'),
+    /// in addition to the ones configured in spr.defaultReviewers. Can be
+    /// given multiple times, and each value may be a comma-separated list.
+    #[clap(long, short = 'r', value_name = "NAMES")]
+    reviewer: Vec<String>,
+
+    /// Do not request review from the reviewers configured in
+    /// spr.defaultReviewers
+    #[clap(long)]
+    no_default_reviewers: bool,
+}
+
+/// The users and teams to ask for reviews, given their names (teams with a
+/// leading #).
+fn requested_reviewers(names: Vec<String>) -> PullRequestRequestReviewers {
+    let mut reviewers = PullRequestRequestReviewers::default();
+    for name in names {
+        match name.strip_prefix('#') {
+            Some(slug) => reviewers.team_reviewers.push(slug.to_string()),
+            None => reviewers.reviewers.push(name),
+        }
+    }
+    reviewers.sort();
+
+    reviewers
+}
+
+/// Take the draft Pull Requests among `pull_requests` out of draft, and ask
+/// `reviewers` to review all open ones. Pull Requests that are ready already
+/// only get the review requests.
+async fn make_ready(
+    gh: &impl Forge,
+    pull_requests: &[PullRequest],
+    reviewers: &PullRequestRequestReviewers,
+) -> Result<()> {
+    let no_reviewers =
+        reviewers.reviewers.is_empty() && reviewers.team_reviewers.is_empty();
+
+    for pull_request in pull_requests {
+        let number = pull_request.number;
+        if pull_request.state != PullRequestState::Open {
+            output(
+                "⏭️",
+                &format!(
+                    "Pull Request #{} is closed - leaving it alone",
+                    number
+                ),
+            )?;
+            continue;
+        }
+
+        if pull_request.draft {
+            gh.mark_ready_for_review(pull_request).await?;
+            output(
+                "🚀",
+                &format!("Marked Pull Request #{} as ready for review", number),
+            )?;
+        } else if no_reviewers {
+            output(
+                "✅",
+                &format!(
+                    "Pull Request #{} is ready for review already",
+                    number
+                ),
+            )?;
+        }
+
+        if !no_reviewers {
+            gh.request_reviewers(number, reviewers.clone()).await?;
+            output(
+                "👀",
+                &format!("Requested reviews on Pull Request #{}", number),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn ready(
+    opts: ReadyOptions,
+    git: &crate::git::Git,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let mut prepared_commits = git.get_prepared_commits(config)?;
+    if !opts.all && !prepared_commits.is_empty() {
+        // Remove all prepared commits from the vector but the last. So, if
+        // `--all` is not given, we only operate on the HEAD commit.
+        prepared_commits.drain(0..prepared_commits.len() - 1);
+    }
+    let numbers: Vec<u64> = prepared_commits
+        .iter()
+        .filter_map(|commit| commit.pull_request_number)
+        .collect();
+    if numbers.is_empty() {
+        output(
+            "👋",
+            "No commit has a Pull Request - nothing to do. Good bye!",
+        )?;
+        return Ok(());
+    }
+
+    let pull_requests = gh
+        .get_pull_requests(numbers.into_iter().map(Some))
+        .await
+        .into_iter()
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+
+    let default_reviewers = if opts.no_default_reviewers {
+        &[]
+    } else {
+        &config.default_reviewers[..]
+    };
+    let reviewers = requested_reviewers(collect_reviewers(
+        None,
+        &opts.reviewer,
+        false,
+        default_reviewers,
+        &config.reviewer_aliases,
+    ));
+
+    make_ready(gh, &pull_requests, &reviewers).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::tests::config_factory,
+        forge::tests::{Call, MockForge},
+        github::tests::pull_request_factory,
+    };
+
+    #[test]
+    fn test_requested_reviewers() {
+        assert_eq!(
+            requested_reviewers(vec![
+                "zoe".to_string(),
+                "#core".to_string(),
+                "alice".to_string(),
+            ]),
+            PullRequestRequestReviewers {
+                reviewers: vec!["alice".to_string(), "zoe".to_string()],
+                team_reviewers: vec!["core".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_make_ready() {
+        let config = config_factory();
+        let mut pull_requests: Vec<_> = (1..=3)
+            .map(|number| {
+                pull_request_factory(
+                    &config,
+                    number,
+                    &format!("spr/foo/{}", number),
+                    "master",
+                )
+            })
+            .collect();
+        // #2 is ready already, and #3 is a closed draft
+        pull_requests[0].draft = true;
+        pull_requests[2].draft = true;
+        pull_requests[2].state = PullRequestState::Closed;
+        let forge = MockForge::new(10, Vec::new());
+        let reviewers = requested_reviewers(vec!["alice".to_string()]);
+
+        make_ready(&forge, &pull_requests, &reviewers)
+            .await
+            .unwrap();
+        assert_eq!(
+            forge.calls(),
+            vec![
+                Call::MarkReadyForReview(1),
+                Call::RequestReviewers(1, reviewers.clone()),
+                Call::RequestReviewers(2, reviewers),
+            ]
+        );
+
+        // Without reviewers, only the drafts change
+        let forge = MockForge::new(10, Vec::new());
+        make_ready(&forge, &pull_requests, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(forge.calls(), vec![Call::MarkReadyForReview(1)]);
+    }
+}
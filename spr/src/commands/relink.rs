@@ -0,0 +1,178 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    forge::Forge,
+    git::find_pull_request_commit,
+    github::{PullRequestHead, PullRequestState, PullRequestUpdate},
+    output::output,
+    utils::run_command,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct RelinkOptions {
+    /// Number of the Pull Request whose branch was deleted
+    pull_request: u64,
+}
+
+/// Whether `spr relink` has to restore the head branch of the Pull Request
+/// with the given head. Fails if that is impossible or pointless.
+fn needs_relink(number: u64, head: &PullRequestHead) -> Result<bool> {
+    if head.merged {
+        return Err(Error::new(format!(
+            "Pull Request #{} is merged - there is nothing to relink",
+            number
+        )));
+    }
+    if !head.is_headless() {
+        return Ok(false);
+    }
+    if head.repository.is_none() {
+        return Err(Error::new(format!(
+            "The repository of the branch of Pull Request #{} was deleted, so \
+             the branch cannot be restored",
+            number
+        )));
+    }
+
+    Ok(true)
+}
+
+pub async fn relink(
+    opts: RelinkOptions,
+    git: &crate::git::Git,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let number = opts.pull_request;
+    let head = gh.get_pull_request_head(number).await?;
+    if !needs_relink(number, &head)? {
+        output(
+            "👍",
+            &format!(
+                "Pull Request #{} still has its branch {} - nothing to relink",
+                number,
+                head.branch.branch_name()
+            ),
+        )?;
+        return Ok(());
+    }
+
+    output(
+        "🔗",
+        &format!(
+            "Branch {} of Pull Request #{} is gone - restoring it",
+            head.branch.branch_name(),
+            number
+        ),
+    )?;
+
+    // GitHub keeps the last head commit of a Pull Request around after its
+    // branch is deleted, so we can push it back exactly as it was
+    git.fetch_commits_from_remote(&[head.oid], &config.remote_name)
+        .await?;
+    let mut cmd = tokio::process::Command::new("git");
+    cmd.arg("push")
+        .arg("--no-verify")
+        .arg("--")
+        .arg(&config.remote_name)
+        .arg(format!("{}:{}", head.oid, head.branch.on_github()));
+    run_command(&mut cmd)
+        .await
+        .reword("git push failed".to_string())?;
+    git.repo().reference(
+        head.branch.local(),
+        head.oid,
+        true,
+        "spr relink restored branch",
+    )?;
+
+    // GitHub closes a Pull Request when its branch is deleted
+    if head.state == PullRequestState::Closed {
+        gh.update_pull_request(
+            number,
+            &PullRequestUpdate {
+                state: Some(PullRequestState::Open),
+                ..Default::default()
+            },
+        )
+        .await
+        .reword(format!(
+            "Restored the branch, but could not reopen Pull Request #{}",
+            number
+        ))?;
+        output("📖", "Reopened the Pull Request")?;
+    }
+
+    output(
+        "✅",
+        &format!(
+            "Relinked Pull Request #{}: {}",
+            number,
+            config.pull_request_url(number)
+        ),
+    )?;
+
+    let prepared_commits = git.get_prepared_commits(config)?;
+    if find_pull_request_commit(&prepared_commits, number).is_some() {
+        output(
+            "💡",
+            "Run `spr diff` to update it with the local commit, if that has \
+             changed since",
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+
+    fn head_factory(merged: bool, branch_exists: bool) -> PullRequestHead {
+        let config = config_factory();
+        PullRequestHead {
+            state: PullRequestState::Closed,
+            merged,
+            branch: config.new_github_branch("spr/foo/change"),
+            oid: git2::Oid::zero(),
+            repository: Some("acme/codez".to_string()),
+            branch_exists,
+        }
+    }
+
+    #[test]
+    fn test_is_headless() {
+        assert!(head_factory(false, false).is_headless());
+        assert!(!head_factory(false, true).is_headless());
+        // A merged Pull Request does not need its branch anymore
+        assert!(!head_factory(true, false).is_headless());
+    }
+
+    #[test]
+    fn test_needs_relink() {
+        assert!(needs_relink(1, &head_factory(false, false)).unwrap());
+        assert!(!needs_relink(1, &head_factory(false, true)).unwrap());
+        assert!(needs_relink(1, &head_factory(true, false)).is_err());
+    }
+
+    #[test]
+    fn test_needs_relink_repository_deleted() {
+        let mut head = head_factory(false, false);
+        head.repository = None;
+
+        let error = needs_relink(7, &head).unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &vec!["The repository of the branch of Pull Request #7 was \
+                   deleted, so the branch cannot be restored"
+                .to_string()]
+        );
+    }
+}
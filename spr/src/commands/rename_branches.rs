@@ -0,0 +1,538 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::{
+    error::Result,
+    forge::Forge,
+    github::{GitHubBranch, PullRequest, PullRequestState},
+    interrupt,
+    message::MessageSection,
+    output::output,
+    push_cache::PushCache,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct RenameBranchesOptions {
+    /// Only rename the branches whose names start with this prefix (e.g. the
+    /// spr.branchPrefix from before)
+    #[clap(long, value_name = "PREFIX")]
+    from_prefix: Option<String>,
+
+    /// Print which branches would be renamed, and to what, without renaming
+    /// anything
+    #[clap(long)]
+    plan: bool,
+}
+
+/// A branch of a Pull Request that is named after an old naming scheme.
+#[derive(Debug, Clone)]
+pub struct BranchRename {
+    /// The Pull Request the branch is the head (or base) of
+    pub number: u64,
+    pub from: GitHubBranch,
+    pub to: GitHubBranch,
+}
+
+/// A Pull Request of the stack, with the title and author date of its commit,
+/// which its branch names are made from.
+pub struct StackPullRequest<'a> {
+    pub title: &'a str,
+    pub date: Option<DateTime<FixedOffset>>,
+    pub pull_request: &'a PullRequest,
+}
+
+/// The branches of the given open Pull Requests (bottom of the stack first)
+/// to rename, so that they are named as `spr diff` would name them now.
+/// Branches that are named like that already are left as they are, as are the
+/// ones that do not start with `from_prefix`, if given. New names are added to
+/// `ref_names` (the names of the remote-tracking branches), so that they are
+/// not given out twice.
+pub fn branch_renames(
+    config: &crate::config::Config,
+    from_prefix: Option<&str>,
+    stack: &[StackPullRequest],
+    ref_names: &mut HashSet<String>,
+) -> Vec<BranchRename> {
+    let needs_rename = |branch: &GitHubBranch, expected: &str| {
+        let name = branch.branch_name();
+        !branch.is_master_branch()
+            && !is_named(name, expected)
+            && from_prefix.is_none_or(|prefix| name.starts_with(prefix))
+    };
+
+    // A base branch that is the head of another Pull Request of the stack is
+    // named after that one's commit, and renamed as its head
+    let heads: HashSet<&str> = stack
+        .iter()
+        .map(|entry| entry.pull_request.head.on_github())
+        .collect();
+
+    // A branch that is the base of one Pull Request and the head of another
+    // is renamed once, and GitHub updates both
+    let renamed = |renames: &[BranchRename], branch: &GitHubBranch| {
+        renames
+            .iter()
+            .any(|rename| rename.from.on_github() == branch.on_github())
+    };
+
+    let mut renames: Vec<BranchRename> = Vec::new();
+    for entry in stack {
+        let pull_request = entry.pull_request;
+        if pull_request.state != PullRequestState::Open {
+            continue;
+        }
+
+        let date = entry.date.as_ref();
+        let expected_head =
+            config.get_new_branch_name(&HashSet::new(), entry.title, date);
+        if needs_rename(&pull_request.head, &expected_head)
+            && !renamed(&renames, &pull_request.head)
+        {
+            let to = config.new_github_branch(&config.get_new_branch_name(
+                ref_names,
+                entry.title,
+                date,
+            ));
+            ref_names.insert(to.local().to_string());
+            renames.push(BranchRename {
+                number: pull_request.number,
+                from: pull_request.head.clone(),
+                to,
+            });
+        }
+        let expected_base =
+            config.get_base_branch_name(&HashSet::new(), entry.title, date);
+        if needs_rename(&pull_request.base, &expected_base)
+            && !heads.contains(pull_request.base.on_github())
+            && !renamed(&renames, &pull_request.base)
+        {
+            let to = config.new_github_branch(&config.get_base_branch_name(
+                ref_names,
+                entry.title,
+                date,
+            ));
+            ref_names.insert(to.local().to_string());
+            renames.push(BranchRename {
+                number: pull_request.number,
+                from: pull_request.base.clone(),
+                to,
+            });
+        }
+    }
+
+    renames
+}
+
+/// The branch the Pull Request with the given head branch should have now,
+/// if the title of its commit changed since spr named the branch after it
+/// (so that the branch is not named like that, or like that with a numeric
+/// suffix, any more). Branches spr did not name under spr.branchPrefix are
+/// left to `spr rename-branches`. The new name is added to `ref_names`.
+pub fn retitled_branch(
+    config: &crate::config::Config,
+    head: &GitHubBranch,
+    title: &str,
+    date: Option<&DateTime<FixedOffset>>,
+    ref_names: &mut HashSet<String>,
+) -> Option<GitHubBranch> {
+    let name = head.branch_name();
+    if head.is_master_branch() || !name.starts_with(&config.branch_prefix) {
+        return None;
+    }
+
+    let expected = config.get_new_branch_name(&HashSet::new(), title, date);
+    if is_named(name, &expected) {
+        return None;
+    }
+
+    let to = config
+        .new_github_branch(&config.get_new_branch_name(ref_names, title, date));
+    ref_names.insert(to.local().to_string());
+    Some(to)
+}
+
+/// Whether the branch is named `expected`, or `expected` with the numeric
+/// suffix spr adds to keep it from clashing with another branch.
+fn is_named(name: &str, expected: &str) -> bool {
+    name.strip_prefix(expected).is_some_and(|suffix| {
+        suffix.is_empty()
+            || suffix
+                .strip_prefix('-')
+                .is_some_and(|number| number.parse::<u32>().is_ok())
+    })
+}
+
+/// Rename the branch on GitHub, which moves the open Pull Requests with it as
+/// head or base along, and the remote-tracking branch with it.
+pub async fn rename_branch(
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    rename: &BranchRename,
+) -> Result<()> {
+    gh.rename_branch(rename.from.branch_name(), rename.to.branch_name())
+        .await?;
+    interrupt::record_completed(format!(
+        "Renamed branch {} of Pull Request #{} to {}",
+        rename.from.branch_name(),
+        rename.number,
+        rename.to.branch_name()
+    ));
+    rename_tracking_branch(git, rename)
+}
+
+/// Rename the branches of the Pull Requests of this branch to the names they
+/// would get under the current configuration. Renaming branches on GitHub
+/// (rather than pushing new ones, since the head of a Pull Request cannot be
+/// changed) moves the open Pull Requests with them along.
+pub async fn rename_branches(
+    opts: RenameBranchesOptions,
+    git: &crate::git::Git,
+    gh: &impl Forge,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let prepared_commits: Vec<_> = git
+        .get_prepared_commits(config)?
+        .into_iter()
+        .filter(|commit| commit.pull_request_number.is_some())
+        .collect();
+    if prepared_commits.is_empty() {
+        output(
+            "👋",
+            "No commit on this branch has a Pull Request. Good bye!",
+        )?;
+        return Ok(());
+    }
+
+    let pull_requests = gh
+        .get_pull_requests(
+            prepared_commits.iter().map(|pc| pc.pull_request_number),
+        )
+        .await
+        .into_iter()
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut stack = Vec::new();
+    for (commit, pull_request) in prepared_commits.iter().zip(&pull_requests) {
+        stack.push(StackPullRequest {
+            title: commit
+                .message
+                .get(&MessageSection::Title)
+                .map(String::as_str)
+                .unwrap_or_default(),
+            date: match &config.branch_date_format {
+                Some(_) => Some(git.author_date(commit.oid)?),
+                None => None,
+            },
+            pull_request,
+        });
+    }
+
+    let mut ref_names = git.get_all_ref_names()?;
+    let renames = branch_renames(
+        config,
+        opts.from_prefix.as_deref(),
+        &stack,
+        &mut ref_names,
+    );
+    if renames.is_empty() {
+        output(
+            "👍",
+            "The branches of all Pull Requests are named after the current \
+             configuration already - nothing to rename",
+        )?;
+        return Ok(());
+    }
+
+    for rename in &renames {
+        let description = format!(
+            "branch {} of Pull Request #{} to {}",
+            rename.from.branch_name(),
+            rename.number,
+            rename.to.branch_name()
+        );
+        if opts.plan {
+            output("📐", &format!("Would rename {}", description))?;
+            continue;
+        }
+
+        rename_branch(git, gh, rename).await?;
+        output("🏷️", &format!("Renamed {}", description))?;
+    }
+
+    Ok(())
+}
+
+/// Move the remote-tracking branch and the push cache entry of the renamed
+/// branch along, so that spr does not need to fetch it again.
+fn rename_tracking_branch(
+    git: &crate::git::Git,
+    rename: &BranchRename,
+) -> Result<()> {
+    let repo = git.repo();
+    if let Ok(mut reference) = repo.find_reference(rename.from.local()) {
+        reference.rename(rename.to.local(), true, "spr rename-branches")?;
+    }
+
+    let mut cache = PushCache::load(repo.path())?;
+    if let Some(oid) = cache.remove(rename.from.on_github()) {
+        cache.insert(rename.to.on_github(), oid);
+        cache.save()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::github::tests::pull_request_factory;
+
+    fn renames(
+        config: &crate::config::Config,
+        from_prefix: Option<&str>,
+        pull_requests: &[PullRequest],
+    ) -> Vec<(u64, String, String)> {
+        let stack: Vec<_> = pull_requests
+            .iter()
+            .zip(["First change", "Second change", "Third change"])
+            .map(|(pull_request, title)| StackPullRequest {
+                title,
+                date: None,
+                pull_request,
+            })
+            .collect();
+
+        branch_renames(config, from_prefix, &stack, &mut HashSet::new())
+            .into_iter()
+            .map(|rename| {
+                (
+                    rename.number,
+                    rename.from.branch_name().to_string(),
+                    rename.to.branch_name().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_branch_renames() {
+        let config = config_factory();
+        let old = |number, head: &str, base: &str| {
+            pull_request_factory(&config, number, head, base)
+        };
+        let stack = [
+            old(1, "old/foo/first-change", "master"),
+            old(2, "old/foo/second-change", "old/foo/master.second-change"),
+        ];
+
+        assert_eq!(
+            renames(&config, None, &stack),
+            vec![
+                (
+                    1,
+                    "old/foo/first-change".to_string(),
+                    "spr/foo/first-change".to_string()
+                ),
+                (
+                    2,
+                    "old/foo/second-change".to_string(),
+                    "spr/foo/second-change".to_string()
+                ),
+                (
+                    2,
+                    "old/foo/master.second-change".to_string(),
+                    "spr/foo/master.second-change".to_string()
+                ),
+            ]
+        );
+        // Only the branches under the old prefix
+        assert!(renames(&config, Some("older/"), &stack).is_empty());
+        assert_eq!(renames(&config, Some("old/"), &stack).len(), 3);
+
+        // Closed Pull Requests are left alone
+        let mut closed = stack[0].clone();
+        closed.state = PullRequestState::Closed;
+        assert!(renames(&config, None, &[closed]).is_empty());
+
+        // A branch that is the head of one Pull Request and the base of the
+        // next is renamed once
+        let shared = [
+            old(1, "old/foo/first-change", "master"),
+            old(2, "old/foo/second-change", "old/foo/first-change"),
+        ];
+        assert_eq!(
+            renames(&config, None, &shared)
+                .into_iter()
+                .map(|(number, ..)| number)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_retitled_branch() {
+        let config = config_factory();
+        let retitled = |head: &str, title: &str| {
+            retitled_branch(
+                &config,
+                &config.new_github_branch(head),
+                title,
+                None,
+                &mut HashSet::new(),
+            )
+            .map(|branch| branch.branch_name().to_string())
+        };
+
+        assert_eq!(retitled("spr/foo/first-change", "First change"), None);
+        // The suffix that kept the name from clashing with another branch
+        assert_eq!(retitled("spr/foo/first-change-1", "First change"), None);
+        assert_eq!(
+            retitled("spr/foo/first-change", "Better title"),
+            Some("spr/foo/better-title".to_string())
+        );
+        assert_eq!(
+            retitled("spr/foo/first-change-1", "First changes"),
+            Some("spr/foo/first-changes".to_string())
+        );
+        // Not named by spr under the current prefix
+        assert_eq!(retitled("old/foo/first-change", "Better title"), None);
+        assert_eq!(retitled("master", "Better title"), None);
+
+        // The new name does not clash with existing branches
+        let mut ref_names: HashSet<String> =
+            ["refs/remotes/origin/spr/foo/better-title".to_string()]
+                .into_iter()
+                .collect();
+        let to = retitled_branch(
+            &config,
+            &config.new_github_branch("spr/foo/first-change"),
+            "Better title",
+            None,
+            &mut ref_names,
+        )
+        .unwrap();
+        assert_eq!(to.branch_name(), "spr/foo/better-title-1");
+        assert!(ref_names.contains(to.local()));
+    }
+
+    #[test]
+    fn test_branch_renames_date_format() {
+        let mut config = config_factory();
+        config.branch_date_format = Some("%Y%m%d".to_string());
+        let pull_requests = [
+            pull_request_factory(&config, 1, "spr/foo/first-change", "master"),
+            pull_request_factory(
+                &config,
+                2,
+                "spr/foo/20240102-second-change",
+                "spr/foo/master.20240102-second-change",
+            ),
+        ];
+        let date =
+            DateTime::parse_from_rfc3339("2024-01-02T10:00:00+01:00").unwrap();
+        let stack: Vec<_> = pull_requests
+            .iter()
+            .zip(["First change", "Second change"])
+            .map(|(pull_request, title)| StackPullRequest {
+                title,
+                date: Some(date),
+                pull_request,
+            })
+            .collect();
+
+        let renamed: Vec<_> =
+            branch_renames(&config, None, &stack, &mut HashSet::new())
+                .into_iter()
+                .map(|rename| {
+                    (
+                        rename.from.branch_name().to_string(),
+                        rename.to.branch_name().to_string(),
+                    )
+                })
+                .collect();
+        assert_eq!(
+            renamed,
+            vec![(
+                "spr/foo/first-change".to_string(),
+                "spr/foo/20240102-first-change".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_branch_renames_already_migrated() {
+        let config = config_factory();
+        let new = |number, head: &str, base: &str| {
+            pull_request_factory(&config, number, head, base)
+        };
+
+        // Branches named as spr would name them now keep their names
+        let migrated = [
+            new(1, "spr/foo/first-change-1", "master"),
+            new(2, "spr/foo/second-change", "spr/foo/master.second-change"),
+        ];
+        assert!(renames(&config, None, &migrated).is_empty());
+
+        // ...but not the ones under the current prefix that are named after
+        // other settings (or titles)
+        let outdated = [
+            new(1, "spr/foo/first-change", "master"),
+            new(2, "spr/foo/renamed", "spr/foo/master.renamed"),
+        ];
+        assert_eq!(
+            renames(&config, None, &outdated),
+            vec![
+                (
+                    2,
+                    "spr/foo/renamed".to_string(),
+                    "spr/foo/second-change".to_string()
+                ),
+                (
+                    2,
+                    "spr/foo/master.renamed".to_string(),
+                    "spr/foo/master.second-change".to_string()
+                ),
+            ]
+        );
+
+        // Partly migrated: only the rest is renamed
+        let partly = [
+            new(1, "spr/foo/first-change", "master"),
+            new(2, "old/foo/second-change", "master"),
+        ];
+        assert_eq!(
+            renames(&config, None, &partly),
+            vec![(
+                2,
+                "old/foo/second-change".to_string(),
+                "spr/foo/second-change".to_string()
+            )]
+        );
+
+        // New names do not clash with existing branches
+        let stack: Vec<_> = partly
+            .iter()
+            .map(|pull_request| StackPullRequest {
+                title: "First change",
+                date: None,
+                pull_request,
+            })
+            .collect();
+        let mut ref_names: HashSet<String> =
+            ["refs/remotes/origin/spr/foo/first-change".to_string()]
+                .into_iter()
+                .collect();
+        let renamed = branch_renames(&config, None, &stack, &mut ref_names);
+        assert_eq!(renamed[0].to.branch_name(), "spr/foo/first-change-1");
+    }
+}
@@ -0,0 +1,382 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use graphql_client::{GraphQLQuery, Response};
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    github::send_graphql_request,
+    output::output,
+    utils::{run_bounded, sort_names},
+};
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/gql/schema.docs.graphql",
+    query_path = "src/gql/pullrequest_reviews_query.graphql",
+    response_derives = "Debug"
+)]
+pub struct PullRequestReviewsQuery;
+
+type PullRequestNode =
+    pull_request_reviews_query::PullRequestReviewsQueryRepositoryPullRequest;
+
+/// Where a Pull Request stands with its reviewers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ReviewSummary {
+    pub number: u64,
+    /// The users and teams (with a leading #) still asked to review
+    pub requested: Vec<String>,
+    pub approved: Vec<String>,
+    pub changes_requested: Vec<String>,
+    /// "yes", "conflicts" or "unknown", or the state of a Pull Request that is
+    /// not open
+    pub mergeable: String,
+}
+
+/// Aggregate the review data GitHub has on a Pull Request.
+fn summarize(pr: PullRequestNode) -> ReviewSummary {
+    use pull_request_reviews_query::{
+        MergeableState, PullRequestReviewState,
+        PullRequestReviewsQueryRepositoryPullRequestReviewRequestsNodesRequestedReviewer as Reviewer,
+        PullRequestState,
+    };
+
+    let mut requested: Vec<String> = pr
+        .review_requests
+        .into_iter()
+        .flat_map(|requests| requests.nodes)
+        .flatten()
+        .flatten()
+        .filter_map(|request| match request.requested_reviewer? {
+            Reviewer::User(user) => Some(user.login),
+            Reviewer::Team(team) => Some(format!("#{}", team.slug)),
+            _ => None,
+        })
+        .collect();
+
+    let mut approved = Vec::new();
+    let mut changes_requested = Vec::new();
+    for review in pr
+        .latest_opinionated_reviews
+        .into_iter()
+        .flat_map(|reviews| reviews.nodes)
+        .flatten()
+        .flatten()
+    {
+        let login = match review.author {
+            Some(author) => author.login,
+            None => continue,
+        };
+        match review.state {
+            PullRequestReviewState::APPROVED => approved.push(login),
+            PullRequestReviewState::CHANGES_REQUESTED => {
+                changes_requested.push(login)
+            }
+            _ => (),
+        }
+    }
+
+    for names in [&mut requested, &mut approved, &mut changes_requested] {
+        sort_names(names);
+    }
+
+    let mergeable = match (pr.state, pr.mergeable) {
+        (PullRequestState::MERGED, _) => "merged",
+        (PullRequestState::CLOSED, _) => "closed",
+        (_, MergeableState::MERGEABLE) => "yes",
+        (_, MergeableState::CONFLICTING) => "conflicts",
+        (_, _) => "unknown",
+    };
+
+    ReviewSummary {
+        number: pr.number as u64,
+        requested,
+        approved,
+        changes_requested,
+        mergeable: mergeable.to_string(),
+    }
+}
+
+const HEADINGS: [&str; 5] = [
+    "PR",
+    "Requested",
+    "Approved",
+    "Changes requested",
+    "Mergeable",
+];
+
+/// The lines of the table of the given summaries. On a terminal, the columns
+/// are aligned and the headings stand out; otherwise, the fields are
+/// separated by tabs, for other programs to read.
+fn render_table(summaries: &[ReviewSummary], terminal: bool) -> Vec<String> {
+    let list = |names: &[String]| {
+        if names.is_empty() {
+            "-".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    let rows: Vec<[String; 5]> = summaries
+        .iter()
+        .map(|summary| {
+            [
+                format!("#{}", summary.number),
+                list(&summary.requested),
+                list(&summary.approved),
+                list(&summary.changes_requested),
+                summary.mergeable.clone(),
+            ]
+        })
+        .collect();
+
+    if !terminal {
+        return std::iter::once(HEADINGS.join("\t"))
+            .chain(rows.iter().map(|row| row.join("\t")))
+            .collect();
+    }
+
+    let mut widths = HEADINGS.map(console::measure_text_width);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(console::measure_text_width(cell));
+        }
+    }
+    let line = |cells: [String; 5]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| {
+                console::pad_str(cell, width, console::Alignment::Left, None)
+                    .into_owned()
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    std::iter::once(
+        console::style(line(HEADINGS.map(String::from)))
+            .bold()
+            .to_string(),
+    )
+    .chain(rows.into_iter().map(line))
+    .collect()
+}
+
+pub(crate) async fn get_review_summary(
+    graphql_client: &reqwest::Client,
+    config: &crate::config::Config,
+    number: u64,
+) -> Result<ReviewSummary> {
+    let variables = pull_request_reviews_query::Variables {
+        name: config.repo.clone(),
+        owner: config.owner.clone(),
+        number: number as i64,
+    };
+    let request_body = PullRequestReviewsQuery::build_query(variables);
+    let res =
+        send_graphql_request(graphql_client, config, &request_body).await?;
+    let response_body: Response<pull_request_reviews_query::ResponseData> =
+        res.json().await?;
+
+    if let Some(errors) = response_body.errors {
+        let error = Err(Error::new(format!(
+            "fetching the reviews of PR #{number} failed"
+        )));
+        return errors
+            .into_iter()
+            .fold(error, |err, e| err.context(e.to_string()));
+    }
+
+    let pr = response_body
+        .data
+        .ok_or_else(|| Error::new("failed to fetch PR"))?
+        .repository
+        .ok_or_else(|| Error::new("failed to find repository"))?
+        .pull_request
+        .ok_or_else(|| Error::new("failed to find PR"))?;
+
+    Ok(summarize(pr))
+}
+
+pub async fn reviews(
+    git: &crate::git::Git,
+    graphql_client: reqwest::Client,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let numbers: Vec<u64> = git
+        .get_prepared_commits(config)?
+        .iter()
+        .filter_map(|commit| commit.pull_request_number)
+        .collect();
+    if numbers.is_empty() {
+        output(
+            "👋",
+            "No commit on this branch has a Pull Request. Good bye!",
+        )?;
+        return Ok(());
+    }
+
+    // The top of the stack first, as in the PR Stack sections
+    let summaries = run_bounded(
+        config.concurrency,
+        numbers.iter().rev().map(|&number| {
+            let graphql_client = &graphql_client;
+            async move {
+                get_review_summary(graphql_client, config, number)
+                    .await
+                    .context(format!(
+                        "Could not get the reviews of Pull Request #{}",
+                        number
+                    ))
+            }
+        }),
+    )
+    .await
+    .into_iter()
+    .collect::<Result<Vec<_>>>()?;
+
+    let term = console::Term::stdout();
+    for line in render_table(&summaries, term.is_term()) {
+        term.write_line(&line)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pull_request_node(json: serde_json::Value) -> PullRequestNode {
+        let data: pull_request_reviews_query::ResponseData =
+            serde_json::from_value(serde_json::json!({
+                "repository": { "pullRequest": json }
+            }))
+            .unwrap();
+        data.repository.unwrap().pull_request.unwrap()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_summarize() {
+        let pr = pull_request_node(serde_json::json!({
+            "number": 7,
+            "state": "OPEN",
+            "mergeable": "MERGEABLE",
+            "latestOpinionatedReviews": { "nodes": [
+                { "author": { "__typename": "User", "login": "zoe" },
+                  "state": "APPROVED" },
+                { "author": { "__typename": "User", "login": "bob" },
+                  "state": "CHANGES_REQUESTED" },
+                { "author": { "__typename": "User", "login": "Alice" },
+                  "state": "APPROVED" },
+                { "author": null, "state": "APPROVED" },
+                { "author": { "__typename": "User", "login": "carol" },
+                  "state": "DISMISSED" }
+            ] },
+            "reviewRequests": { "nodes": [
+                { "requestedReviewer": { "__typename": "User",
+                                         "login": "dave" } },
+                { "requestedReviewer": { "__typename": "Team",
+                                         "slug": "core" } },
+                { "requestedReviewer": null }
+            ] }
+        }));
+
+        assert_eq!(
+            summarize(pr),
+            ReviewSummary {
+                number: 7,
+                requested: names(&["#core", "dave"]),
+                approved: names(&["Alice", "zoe"]),
+                changes_requested: names(&["bob"]),
+                mergeable: "yes".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_summarize_without_reviews() {
+        let pr = pull_request_node(serde_json::json!({
+            "number": 8,
+            "state": "OPEN",
+            "mergeable": "CONFLICTING",
+            "latestOpinionatedReviews": null,
+            "reviewRequests": { "nodes": [] }
+        }));
+        let summary = summarize(pr);
+        assert!(summary.requested.is_empty());
+        assert!(summary.approved.is_empty());
+        assert_eq!(summary.mergeable, "conflicts");
+
+        let merged = pull_request_node(serde_json::json!({
+            "number": 9,
+            "state": "MERGED",
+            "mergeable": "UNKNOWN",
+            "latestOpinionatedReviews": null,
+            "reviewRequests": null
+        }));
+        assert_eq!(summarize(merged).mergeable, "merged");
+    }
+
+    #[test]
+    fn test_render_table() {
+        let summaries = vec![
+            ReviewSummary {
+                number: 12,
+                requested: names(&["dave"]),
+                approved: names(&["alice", "bob"]),
+                changes_requested: vec![],
+                mergeable: "yes".to_string(),
+            },
+            ReviewSummary {
+                number: 3,
+                requested: vec![],
+                approved: vec![],
+                changes_requested: names(&["carol"]),
+                mergeable: "unknown".to_string(),
+            },
+        ];
+
+        assert_eq!(
+            render_table(&summaries, false),
+            vec![
+                "PR\tRequested\tApproved\tChanges requested\tMergeable",
+                "#12\tdave\talice, bob\t-\tyes",
+                "#3\t-\t-\tcarol\tunknown",
+            ]
+        );
+
+        // Faking a terminal, the headings are bold...
+        crate::output::set_colors_enabled(true);
+        assert!(render_table(&summaries, true)[0].contains('\x1b'));
+        // ...unless colors are turned off
+        crate::output::set_colors_enabled(false);
+        assert!(render_table(&summaries, true)
+            .iter()
+            .all(|line| !line.contains('\x1b')));
+
+        let lines: Vec<String> = render_table(&summaries, true)
+            .iter()
+            .map(|line| console::strip_ansi_codes(line).into_owned())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "PR   Requested  Approved    Changes requested  Mergeable",
+                "#12  dave       alice, bob  -                  yes",
+                "#3   -          -           carol              unknown",
+            ]
+        );
+    }
+}
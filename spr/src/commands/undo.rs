@@ -0,0 +1,52 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::{
+    error::{Error, Result},
+    output::output,
+    undo::Snapshot,
+};
+
+/// The commands that change the local repository only.
+const LOCAL_COMMANDS: &[&str] = &["format", "import"];
+
+pub async fn undo(git: &crate::git::Git) -> Result<()> {
+    let git_dir = git.repo().path().to_path_buf();
+    let snapshot = Snapshot::load(&git_dir)?.ok_or_else(|| {
+        Error::new("There is nothing to undo (or it was undone already)")
+    })?;
+
+    let done = snapshot.restore(git).await?;
+    if done.is_empty() {
+        output(
+            "👍",
+            &format!(
+                "spr {} did not move HEAD or any local branch",
+                snapshot.command
+            ),
+        )?;
+    }
+    for step in done {
+        output("↩️", &step)?;
+    }
+    Snapshot::remove(&git_dir)?;
+
+    if !LOCAL_COMMANDS.contains(&snapshot.command.as_str()) {
+        for step in &snapshot.remote_steps {
+            output("⚠️", &format!("{} (this cannot be undone)", step))?;
+        }
+        output(
+            "⚠️",
+            &format!(
+                "What spr {} changed on GitHub stays as it is",
+                snapshot.command
+            ),
+        )?;
+    }
+
+    Ok(())
+}
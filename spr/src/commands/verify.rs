@@ -0,0 +1,485 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::HashMap;
+
+use crate::{
+    commands::{
+        fix_footers::{merged_pull_requests, pr_stacks},
+        land::missing_for_landing,
+    },
+    error::{Error, Result},
+    forge::Forge,
+    git::PreparedCommit,
+    github::{PullRequest, PullRequestState},
+    message::{listed_pr_stack, MessageSection},
+    output::output,
+    utils::parse_pr_stack_list,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct VerifyOptions {
+    /// Also check that each Pull Request is ready to land: approved as the
+    /// branch protection rules require, with its required checks passed
+    #[clap(long)]
+    ready_to_land: bool,
+}
+
+/// Something about the stack on GitHub that does not match the local branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Drift {
+    /// The commit has no Pull Request
+    MissingPullRequest { commit: String },
+    /// More than one commit belongs to the Pull Request
+    SharedPullRequest { number: u64 },
+    /// The Pull Request of a commit is closed or merged
+    ClosedPullRequest { number: u64 },
+    /// The Pull Request is not based on what the commit below it is
+    BaseMismatch {
+        number: u64,
+        base: String,
+        expected: String,
+    },
+    /// The PR Stack section of the Pull Request lists other Pull Requests
+    /// than the stack has
+    FooterMismatch {
+        number: u64,
+        found: Vec<u64>,
+        expected: Vec<u64>,
+    },
+    /// The Pull Request lacks reviews or checks that the branch protection
+    /// rules require for landing it (with `--ready-to-land`)
+    NotReady { number: u64, missing: Vec<String> },
+}
+
+impl Drift {
+    fn category(&self) -> &'static str {
+        match self {
+            Drift::MissingPullRequest { .. }
+            | Drift::SharedPullRequest { .. }
+            | Drift::ClosedPullRequest { .. } => "Commits and Pull Requests",
+            Drift::BaseMismatch { .. } => "Base branches",
+            Drift::FooterMismatch { .. } => "PR Stack sections",
+            Drift::NotReady { .. } => "Readiness to land",
+        }
+    }
+
+    fn describe(&self) -> String {
+        let list = |numbers: &[u64]| {
+            if numbers.is_empty() {
+                "nothing".to_string()
+            } else {
+                numbers
+                    .iter()
+                    .map(|number| format!("#{}", number))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+
+        match self {
+            Drift::MissingPullRequest { commit } => {
+                format!("Commit {} has no Pull Request", commit)
+            }
+            Drift::SharedPullRequest { number } => format!(
+                "More than one commit belongs to Pull Request #{}",
+                number
+            ),
+            Drift::ClosedPullRequest { number } => {
+                format!("Pull Request #{} is not open", number)
+            }
+            Drift::BaseMismatch {
+                number,
+                base,
+                expected,
+            } => format!(
+                "Pull Request #{} is based on {}, but should be based on {}",
+                number, base, expected
+            ),
+            Drift::FooterMismatch {
+                number,
+                found,
+                expected,
+            } => format!(
+                "The PR Stack of Pull Request #{} lists {}, but the stack is {}",
+                number,
+                list(found),
+                list(expected)
+            ),
+            Drift::NotReady { number, missing } => format!(
+                "Pull Request #{} is not ready to land: {}",
+                number,
+                missing.join("; ")
+            ),
+        }
+    }
+}
+
+/// Whether the Pull Request is based on a branch that fits the commit below
+/// it: master (for the bottom commit, or one submitted with `--cherry-pick`),
+/// the branch of the Pull Request below, or a base branch spr made.
+fn check_base(
+    pull_request: &PullRequest,
+    below: Option<&PullRequest>,
+    bottom: bool,
+    config: &crate::config::Config,
+) -> Option<Drift> {
+    let base = &pull_request.base;
+    let master = config.master_ref.branch_name();
+    let mismatch = |expected: String| {
+        Some(Drift::BaseMismatch {
+            number: pull_request.number,
+            base: base.branch_name().to_string(),
+            expected,
+        })
+    };
+
+    if bottom {
+        return if base.is_master_branch() {
+            None
+        } else {
+            mismatch(master.to_string())
+        };
+    }
+
+    let spr_base_prefix = format!("{}{}.", config.branch_prefix, master);
+    let fits = base.is_master_branch()
+        || base.branch_name().starts_with(&spr_base_prefix)
+        || below.is_some_and(|below| {
+            below.head.branch_name() == base.branch_name()
+        });
+    if fits {
+        return None;
+    }
+
+    mismatch(match below {
+        Some(below) => format!(
+            "{}, {} (of Pull Request #{}) or a base branch made by spr",
+            master,
+            below.head.branch_name(),
+            below.number
+        ),
+        None => format!("{} or a base branch made by spr", master),
+    })
+}
+
+/// Everything about the Pull Requests of the given commits that does not
+/// match the local branch. `pull_requests` has the Pull Request of each
+/// commit, if it has one.
+fn find_drift(
+    commits: &[PreparedCommit],
+    pull_requests: &[Option<PullRequest>],
+    config: &crate::config::Config,
+) -> Vec<Drift> {
+    let mut drift = Vec::new();
+
+    let mut seen = HashMap::new();
+    for (commit, pull_request) in commits.iter().zip(pull_requests) {
+        let pull_request = match pull_request {
+            Some(pull_request) => pull_request,
+            None => {
+                drift.push(Drift::MissingPullRequest {
+                    commit: commit.short_id.clone(),
+                });
+                continue;
+            }
+        };
+        let count = seen.entry(pull_request.number).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            if *count == 2 {
+                drift.push(Drift::SharedPullRequest {
+                    number: pull_request.number,
+                });
+            }
+            continue;
+        }
+        if pull_request.state != PullRequestState::Open {
+            drift.push(Drift::ClosedPullRequest {
+                number: pull_request.number,
+            });
+        }
+    }
+
+    for (index, pull_request) in pull_requests.iter().enumerate() {
+        if let Some(pull_request) = pull_request {
+            let below = index
+                .checked_sub(1)
+                .and_then(|below| pull_requests[below].as_ref());
+            drift.extend(check_base(pull_request, below, index == 0, config));
+        }
+    }
+
+    // The stack each Pull Request should list, counting a Pull Request that
+    // more than one commit belongs to only once
+    let mut existing: Vec<PullRequest> = Vec::new();
+    for pull_request in pull_requests.iter().flatten() {
+        if !existing.iter().any(|pr| pr.number == pull_request.number) {
+            existing.push(pull_request.clone());
+        }
+    }
+    let merged = merged_pull_requests(&existing);
+    for (pull_request, expected) in existing.iter().zip(pr_stacks(&existing)) {
+        let expected = listed_pr_stack(&expected, &merged, config);
+        let found = parse_pr_stack_list(
+            pull_request
+                .sections
+                .get(&MessageSection::PRStack)
+                .map(String::as_str)
+                .unwrap_or_default(),
+        );
+        // Pull Requests submitted with `--no-stack` have no PR Stack
+        let standalone = found.is_empty() && expected.len() == 1;
+        if found != expected && !standalone {
+            drift.push(Drift::FooterMismatch {
+                number: pull_request.number,
+                found,
+                expected,
+            });
+        }
+    }
+
+    drift
+}
+
+pub async fn verify(
+    opts: VerifyOptions,
+    git: &crate::git::Git,
+    gh: &mut crate::github::GitHub,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let prepared_commits = git.get_prepared_commits(config)?;
+    if prepared_commits.is_empty() {
+        output("👋", "Branch is empty - nothing to verify. Good bye!")?;
+        return Ok(());
+    }
+
+    let pull_requests = gh
+        .get_pull_requests(
+            prepared_commits.iter().map(|pc| pc.pull_request_number),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut drift = find_drift(&prepared_commits, &pull_requests, config);
+    if opts.ready_to_land {
+        let mut checked = Vec::new();
+        for pull_request in pull_requests.iter().flatten() {
+            if pull_request.state != PullRequestState::Open
+                || checked.contains(&pull_request.number)
+            {
+                continue;
+            }
+            checked.push(pull_request.number);
+            let checks =
+                gh.get_pull_request_checks(pull_request.number).await?;
+            let missing = missing_for_landing(pull_request, Some(&checks));
+            if !missing.is_empty() {
+                drift.push(Drift::NotReady {
+                    number: pull_request.number,
+                    missing,
+                });
+            }
+        }
+    }
+    if drift.is_empty() {
+        output("✅", "The stack on GitHub matches the local branch")?;
+        return Ok(());
+    }
+
+    let mut categories: Vec<&str> = Vec::new();
+    for item in &drift {
+        if !categories.contains(&item.category()) {
+            categories.push(item.category());
+        }
+    }
+    for category in categories {
+        output("⚠️", &format!("{}:", category))?;
+        for item in drift.iter().filter(|item| item.category() == category) {
+            output("  ", &item.describe())?;
+        }
+    }
+
+    // Updating the Pull Requests does not make them ready to land
+    if drift
+        .iter()
+        .all(|item| matches!(item, Drift::NotReady { .. }))
+    {
+        return Err(Error::new(format!(
+            "Found {} Pull Request(s) that are not ready to land.",
+            drift.len()
+        )));
+    }
+    Err(Error::new(format!(
+        "Found {} problem(s) with the stack. `spr diff --all` brings the Pull \
+         Requests up to date, and `spr fix-footers` repairs just the PR Stack \
+         sections.",
+        drift.len()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::git::tests::prepared_commit_factory;
+    use crate::github::tests::pull_request_factory;
+    use crate::message::build_pr_stack_message;
+
+    fn with_stack(
+        mut pull_request: PullRequest,
+        stack: &[u64],
+        config: &crate::config::Config,
+    ) -> PullRequest {
+        pull_request.sections.insert(
+            MessageSection::PRStack,
+            build_pr_stack_message(stack, &Default::default(), config),
+        );
+        pull_request
+    }
+
+    fn commits(count: usize) -> Vec<PreparedCommit> {
+        (0..count)
+            .map(|index| prepared_commit_factory(&index.to_string(), "Commit"))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_drift_none() {
+        let config = config_factory();
+        let pull_requests = vec![
+            Some(with_stack(
+                pull_request_factory(&config, 1, "spr/foo/one", "master"),
+                &[1],
+                &config,
+            )),
+            Some(with_stack(
+                pull_request_factory(
+                    &config,
+                    2,
+                    "spr/foo/two",
+                    "spr/foo/master.two",
+                ),
+                &[2, 1],
+                &config,
+            )),
+        ];
+
+        assert!(find_drift(&commits(2), &pull_requests, &config).is_empty());
+    }
+
+    #[test]
+    fn test_find_drift_base_mismatch() {
+        let config = config_factory();
+        let pull_requests = vec![
+            // The bottom Pull Request must be based on master
+            Some(with_stack(
+                pull_request_factory(
+                    &config,
+                    1,
+                    "spr/foo/one",
+                    "spr/foo/master.one",
+                ),
+                &[1],
+                &config,
+            )),
+            Some(with_stack(
+                pull_request_factory(&config, 2, "spr/foo/two", "release"),
+                &[2, 1],
+                &config,
+            )),
+        ];
+
+        assert_eq!(
+            find_drift(&commits(2), &pull_requests, &config),
+            vec![
+                Drift::BaseMismatch {
+                    number: 1,
+                    base: "spr/foo/master.one".to_string(),
+                    expected: "master".to_string(),
+                },
+                Drift::BaseMismatch {
+                    number: 2,
+                    base: "release".to_string(),
+                    expected: "master, spr/foo/one (of Pull Request #1) or a \
+                               base branch made by spr"
+                        .to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_drift_based_on_pull_request_below() {
+        let config = config_factory();
+        let pull_requests = vec![
+            Some(pull_request_factory(&config, 1, "spr/foo/one", "master")),
+            Some(with_stack(
+                pull_request_factory(&config, 2, "spr/foo/two", "spr/foo/one"),
+                &[2, 1],
+                &config,
+            )),
+        ];
+
+        assert!(find_drift(&commits(2), &pull_requests, &config).is_empty());
+    }
+
+    #[test]
+    fn test_find_drift_footer_mismatch() {
+        let config = config_factory();
+        let pull_requests = vec![
+            Some(with_stack(
+                pull_request_factory(&config, 1, "spr/foo/one", "master"),
+                &[1],
+                &config,
+            )),
+            Some(with_stack(
+                pull_request_factory(
+                    &config,
+                    2,
+                    "spr/foo/two",
+                    "spr/foo/master.two",
+                ),
+                &[2, 7],
+                &config,
+            )),
+        ];
+
+        assert_eq!(
+            find_drift(&commits(2), &pull_requests, &config),
+            vec![Drift::FooterMismatch {
+                number: 2,
+                found: vec![2, 7],
+                expected: vec![2, 1],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_drift_commits_and_pull_requests() {
+        let config = config_factory();
+        let mut closed = with_stack(
+            pull_request_factory(&config, 3, "spr/foo/three", "master"),
+            &[3],
+            &config,
+        );
+        closed.state = PullRequestState::Closed;
+        let pull_requests = vec![None, Some(closed.clone()), Some(closed)];
+
+        assert_eq!(
+            find_drift(&commits(3), &pull_requests, &config),
+            vec![
+                Drift::MissingPullRequest {
+                    commit: "0".to_string()
+                },
+                Drift::ClosedPullRequest { number: 3 },
+                Drift::SharedPullRequest { number: 3 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use crate::error::Result;
+
+#[derive(Debug, clap::Parser)]
+pub struct VersionOptions {
+    /// Also print the Git commit spr was built from, the build date and the
+    /// target platform
+    #[clap(long, short = 'v')]
+    verbose: bool,
+}
+
+/// What the build script found out about the build.
+const GIT_SHA: &str = env!("SPR_GIT_SHA");
+const BUILD_DATE: &str = env!("SPR_BUILD_DATE");
+const TARGET: &str = env!("SPR_TARGET");
+
+/// The commit sha as embedded by the build script: the full hex sha, followed
+/// by `-dirty` if there were uncommitted changes. Returns `None` if spr was
+/// not built from a Git checkout.
+pub fn parse_commit_sha(embedded: &str) -> Option<&str> {
+    lazy_regex::regex_is_match!(r#"^[0-9a-f]{40}(-dirty)?$"#, embedded)
+        .then_some(embedded)
+}
+
+pub fn version(opts: VersionOptions) -> Result<()> {
+    let term = console::Term::stdout();
+    term.write_line(&format!("spr {}", env!("CARGO_PKG_VERSION")))?;
+
+    if opts.verbose {
+        term.write_line(&format!(
+            "commit: {}",
+            parse_commit_sha(GIT_SHA).unwrap_or("unknown")
+        ))?;
+        term.write_line(&format!("build date: {}", BUILD_DATE))?;
+        term.write_line(&format!("target: {}", TARGET))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commit_sha() {
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(parse_commit_sha(sha), Some(sha));
+        assert_eq!(
+            parse_commit_sha(&format!("{}-dirty", sha)),
+            Some(&format!("{}-dirty", sha)[..])
+        );
+
+        assert_eq!(parse_commit_sha("unknown"), None);
+        assert_eq!(parse_commit_sha("0123456"), None);
+        assert_eq!(parse_commit_sha(&sha.to_uppercase()), None);
+    }
+
+    #[test]
+    fn test_embedded_build_info() {
+        // Either we were built from a Git checkout, or we say we don't know
+        assert!(GIT_SHA == "unknown" || parse_commit_sha(GIT_SHA).is_some());
+        assert!(lazy_regex::regex_is_match!(
+            r#"^\d{4}-\d{2}-\d{2}$"#,
+            BUILD_DATE
+        ));
+        assert!(!TARGET.is_empty());
+    }
+}
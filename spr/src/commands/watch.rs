@@ -0,0 +1,345 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{
+    commands::reviews::{get_review_summary, ReviewSummary},
+    config::WatchEvent,
+    error::Result,
+    forge::Forge,
+    github::ChecksState,
+    output::output,
+    utils::run_bounded,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct WatchOptions {
+    /// How often to ask GitHub about the Pull Requests, in seconds (instead
+    /// of spr.watchInterval)
+    #[clap(long, value_name = "SECONDS")]
+    interval: Option<u64>,
+}
+
+/// What `spr watch` knows about a Pull Request after asking GitHub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PullRequestStatus {
+    review: ReviewSummary,
+    checks: ChecksState,
+}
+
+/// The status of each Pull Request of the stack, by number.
+type Snapshot = BTreeMap<u64, PullRequestStatus>;
+
+/// Descriptions of the changes (among the given kinds of events) from one
+/// snapshot to the next. Pull Requests that were not in the previous snapshot
+/// (because they were just created) have nothing to compare with, so they
+/// are left out.
+fn changes(
+    previous: &Snapshot,
+    current: &Snapshot,
+    events: &[WatchEvent],
+) -> Vec<String> {
+    let added = |before: &[String], now: &[String]| {
+        now.iter()
+            .filter(|name| !before.contains(name))
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut result = Vec::new();
+    for (number, now) in current {
+        let before = match previous.get(number) {
+            Some(before) => before,
+            None => continue,
+        };
+
+        for event in events {
+            let change = match event {
+                WatchEvent::Approved => {
+                    Some(added(&before.review.approved, &now.review.approved))
+                        .filter(|names| !names.is_empty())
+                        .map(|names| format!("approved by {}", names))
+                }
+                WatchEvent::ChangesRequested => Some(added(
+                    &before.review.changes_requested,
+                    &now.review.changes_requested,
+                ))
+                .filter(|names| !names.is_empty())
+                .map(|names| format!("changes requested by {}", names)),
+                WatchEvent::ReviewRequested => {
+                    Some(added(&before.review.requested, &now.review.requested))
+                        .filter(|names| !names.is_empty())
+                        .map(|names| format!("review requested from {}", names))
+                }
+                // Only finished checks are news
+                WatchEvent::Checks if before.checks != now.checks => {
+                    match now.checks {
+                        ChecksState::Success => Some("checks passed".into()),
+                        ChecksState::Failure => Some("checks failed".into()),
+                        ChecksState::Pending | ChecksState::NoChecks => None,
+                    }
+                }
+                // GitHub computes mergeability lazily, so it is unknown for a
+                // while after every push
+                WatchEvent::Mergeable
+                    if before.review.mergeable != now.review.mergeable =>
+                {
+                    match now.review.mergeable.as_str() {
+                        "yes" => Some("can be merged".into()),
+                        "conflicts" => Some("has conflicts".into()),
+                        "merged" => Some("was merged".into()),
+                        "closed" => Some("was closed".into()),
+                        _ => None,
+                    }
+                }
+                WatchEvent::Checks | WatchEvent::Mergeable => None,
+            };
+            if let Some(change) = change {
+                result.push(format!("#{}: {}", number, change));
+            }
+        }
+    }
+
+    result
+}
+
+async fn get_snapshot(
+    git: &crate::git::Git,
+    gh: &crate::github::GitHub,
+    graphql_client: &reqwest::Client,
+    config: &crate::config::Config,
+) -> Result<Snapshot> {
+    let numbers: Vec<u64> = git
+        .get_prepared_commits(config)?
+        .iter()
+        .filter_map(|commit| commit.pull_request_number)
+        .collect();
+
+    run_bounded(
+        config.concurrency,
+        numbers.into_iter().map(|number| async move {
+            let review =
+                get_review_summary(graphql_client, config, number).await?;
+            let checks = gh.get_pull_request_checks(number).await?.state;
+            Ok((number, PullRequestStatus { review, checks }))
+        }),
+    )
+    .await
+    .into_iter()
+    .collect()
+}
+
+/// Tell about the changes, and ring the terminal bell.
+fn notify(changes: &[String]) -> Result<()> {
+    for change in changes {
+        output("🔔", change)?;
+    }
+    let term = console::Term::stdout();
+    if term.is_term() {
+        term.write_str("\x07")?;
+    }
+
+    Ok(())
+}
+
+pub async fn watch(
+    opts: WatchOptions,
+    git: &crate::git::Git,
+    gh: &crate::github::GitHub,
+    graphql_client: reqwest::Client,
+    config: &crate::config::Config,
+) -> Result<()> {
+    let interval = opts
+        .interval
+        .map(|seconds| Duration::from_secs(seconds.max(1)))
+        .unwrap_or(config.watch_interval);
+
+    let mut previous = get_snapshot(git, gh, &graphql_client, config).await?;
+    if previous.is_empty() {
+        output(
+            "👋",
+            "No commit on this branch has a Pull Request. Good bye!",
+        )?;
+        return Ok(());
+    }
+    output(
+        "👀",
+        &format!(
+            "Watching {} Pull Request(s), every {} second(s) - press Ctrl-C \
+             to stop",
+            previous.len(),
+            interval.as_secs()
+        ),
+    )?;
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let current = tokio::select! {
+            _ = &mut ctrl_c => break,
+            _ = tokio::time::sleep(interval) => tokio::select! {
+                _ = &mut ctrl_c => break,
+                current = get_snapshot(git, gh, &graphql_client, config) => current,
+            },
+        };
+
+        match current {
+            Ok(current) => {
+                let changes =
+                    changes(&previous, &current, &config.watch_events);
+                if !changes.is_empty() {
+                    notify(&changes)?;
+                }
+                previous = current;
+            }
+            // Keep watching through network hiccups
+            Err(error) => output(
+                "⚠️",
+                &format!("Could not ask GitHub about the stack: {}", error),
+            )?,
+        }
+    }
+
+    output("👋", "Stopped watching")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn status(
+        number: u64,
+        approved: &[&str],
+        checks: ChecksState,
+        mergeable: &str,
+    ) -> (u64, PullRequestStatus) {
+        (
+            number,
+            PullRequestStatus {
+                review: ReviewSummary {
+                    number,
+                    requested: vec![],
+                    approved: names(approved),
+                    changes_requested: vec![],
+                    mergeable: mergeable.to_string(),
+                },
+                checks,
+            },
+        )
+    }
+
+    #[test]
+    fn test_no_changes() {
+        let snapshot = Snapshot::from([
+            status(1, &["alice"], ChecksState::Success, "yes"),
+            status(2, &[], ChecksState::Pending, "unknown"),
+        ]);
+
+        assert!(changes(&snapshot, &snapshot, &WatchEvent::ALL).is_empty());
+    }
+
+    #[test]
+    fn test_review_changes() {
+        let previous = Snapshot::from([status(
+            1,
+            &["alice"],
+            ChecksState::Pending,
+            "yes",
+        )]);
+        let mut current = Snapshot::from([status(
+            1,
+            &["alice", "bob"],
+            ChecksState::Pending,
+            "yes",
+        )]);
+        let review = &mut current.get_mut(&1).unwrap().review;
+        review.changes_requested = names(&["carol"]);
+        review.requested = names(&["#core"]);
+
+        assert_eq!(
+            changes(&previous, &current, &WatchEvent::ALL),
+            vec![
+                "#1: approved by bob",
+                "#1: changes requested by carol",
+                "#1: review requested from #core",
+            ]
+        );
+        assert_eq!(
+            changes(&previous, &current, &[WatchEvent::Approved]),
+            vec!["#1: approved by bob"]
+        );
+        // A withdrawn approval is no reason to ring the bell
+        assert!(changes(&current, &previous, &WatchEvent::ALL).is_empty());
+    }
+
+    #[test]
+    fn test_checks_changes() {
+        let pending =
+            Snapshot::from([status(1, &[], ChecksState::Pending, "yes")]);
+        let passed =
+            Snapshot::from([status(1, &[], ChecksState::Success, "yes")]);
+        let failed =
+            Snapshot::from([status(1, &[], ChecksState::Failure, "yes")]);
+
+        assert_eq!(
+            changes(&pending, &passed, &WatchEvent::ALL),
+            vec!["#1: checks passed"]
+        );
+        assert_eq!(
+            changes(&pending, &failed, &WatchEvent::ALL),
+            vec!["#1: checks failed"]
+        );
+        // Checks starting again after a push are not news
+        assert!(changes(&passed, &pending, &WatchEvent::ALL).is_empty());
+        assert!(changes(&pending, &passed, &[WatchEvent::Approved]).is_empty());
+    }
+
+    #[test]
+    fn test_mergeable_changes() {
+        let snapshot = |mergeable| {
+            Snapshot::from([status(1, &[], ChecksState::Success, mergeable)])
+        };
+
+        assert_eq!(
+            changes(&snapshot("yes"), &snapshot("conflicts"), &WatchEvent::ALL),
+            vec!["#1: has conflicts"]
+        );
+        assert_eq!(
+            changes(&snapshot("yes"), &snapshot("merged"), &WatchEvent::ALL),
+            vec!["#1: was merged"]
+        );
+        assert_eq!(
+            changes(&snapshot("unknown"), &snapshot("yes"), &WatchEvent::ALL),
+            vec!["#1: can be merged"]
+        );
+        assert!(changes(
+            &snapshot("yes"),
+            &snapshot("unknown"),
+            &WatchEvent::ALL
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_new_pull_requests_are_not_changes() {
+        let previous =
+            Snapshot::from([status(1, &[], ChecksState::Pending, "yes")]);
+        let current = Snapshot::from([
+            status(1, &[], ChecksState::Pending, "yes"),
+            status(2, &["alice"], ChecksState::Success, "yes"),
+        ]);
+
+        assert!(changes(&previous, &current, &WatchEvent::ALL).is_empty());
+    }
+}
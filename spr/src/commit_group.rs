@@ -0,0 +1,389 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Groups of commits that `spr diff` submits as one Pull Request. A commit
+//! with a `Squash-Into-Previous: true` trailer goes into the Pull Request of
+//! the commit below it.
+//!
+//! The Pull Request of a group belongs to its top commit, which gets the
+//! `Pull Request` field, so that the next commit up the stack is based on
+//! it like on any other commit. The title of the Pull Request is that of
+//! the bottom commit of the group, and its description is put together from
+//! the messages of all of them. The commits themselves stay as they are.
+
+use std::ops::Range;
+
+use crate::{
+    error::{Error, Result},
+    git::PreparedCommit,
+    message::{MessageSection, MessageSectionsMap},
+};
+
+/// The trailer that marks a commit as part of the Pull Request below it.
+pub const TRAILER: &str = "Squash-Into-Previous";
+
+/// The sections that each commit of a group has its own of, and that are put
+/// together for the Pull Request. The others are taken from the top commit.
+const OWN_SECTIONS: [MessageSection; 3] = [
+    MessageSection::Title,
+    MessageSection::Summary,
+    MessageSection::TestPlan,
+];
+
+/// The value of the trailer, if this line is one.
+fn trailer_value(line: &str) -> Option<&str> {
+    let (key, value) = line.split_once(':')?;
+    key.trim()
+        .eq_ignore_ascii_case(TRAILER)
+        .then(|| value.trim())
+}
+
+/// Whether the commit goes into the Pull Request of the commit below it.
+pub fn is_continuation(message: &MessageSectionsMap) -> bool {
+    message
+        .iter()
+        .filter(|(section, _)| **section != MessageSection::Title)
+        .flat_map(|(_, text)| text.lines())
+        .filter_map(trailer_value)
+        .any(|value| matches!(&value.to_ascii_lowercase()[..], "true" | "yes"))
+}
+
+/// `text` without the trailer.
+fn strip_trailer(text: &str) -> String {
+    text.lines()
+        .filter(|line| trailer_value(line).is_none())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// The ranges of `commits` (bottom of the stack first) that go into one Pull
+/// Request each.
+pub fn group_ranges(commits: &[PreparedCommit]) -> Result<Vec<Range<usize>>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for (index, commit) in commits.iter().enumerate() {
+        match ranges.last_mut() {
+            Some(range) if is_continuation(&commit.message) => {
+                range.end = index + 1
+            }
+            None if is_continuation(&commit.message) => {
+                return Err(Error::new(format!(
+                    "Commit {} has '{}: true', but there is no commit below \
+                     it in the stack to go with",
+                    commit.short_id, TRAILER
+                )));
+            }
+            _ => ranges.push(index..index + 1),
+        }
+    }
+
+    Ok(ranges)
+}
+
+/// The message of the Pull Request of a group: the title of the bottom
+/// commit, the summaries (with the titles of the commits on top of the
+/// bottom one) and test plans of all of them, and the other sections of the
+/// top commit.
+fn combined_message(members: &[PreparedCommit]) -> MessageSectionsMap {
+    // Later commits win for the sections that are not put together
+    let mut message = MessageSectionsMap::new();
+    for member in members {
+        for (section, text) in &member.message {
+            if !OWN_SECTIONS.contains(section) {
+                message.insert(*section, text.clone());
+            }
+        }
+    }
+
+    let join = |parts: Vec<String>| {
+        parts
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+    let text = |member: &PreparedCommit, section| {
+        member
+            .message
+            .get(&section)
+            .map(|text| strip_trailer(text))
+            .unwrap_or_default()
+    };
+
+    let summary = join(
+        members
+            .iter()
+            .enumerate()
+            .map(|(index, member)| {
+                let summary = text(member, MessageSection::Summary);
+                if index == 0 {
+                    summary
+                } else {
+                    join(vec![text(member, MessageSection::Title), summary])
+                }
+            })
+            .collect(),
+    );
+    let test_plan = join(
+        members
+            .iter()
+            .map(|member| text(member, MessageSection::TestPlan))
+            .collect(),
+    );
+
+    if let Some(title) = members[0].message.get(&MessageSection::Title) {
+        message.insert(MessageSection::Title, title.clone());
+    }
+    for (section, text) in [
+        (MessageSection::Summary, summary),
+        (MessageSection::TestPlan, test_plan),
+    ] {
+        if !text.is_empty() {
+            message.insert(section, text);
+        }
+    }
+
+    message
+}
+
+/// Combine the commits of a group into one commit to submit: it has the tree
+/// of the top commit, the parent of the bottom one, and the combined message,
+/// and the group is kept in its `squashed` field.
+fn fold_group(members: Vec<PreparedCommit>) -> Result<PreparedCommit> {
+    let mut numbers = members
+        .iter()
+        .filter_map(|member| member.pull_request_number);
+    let pull_request_number = numbers.next();
+    if let Some(other) =
+        numbers.find(|&number| Some(number) != pull_request_number)
+    {
+        return Err(Error::new(format!(
+            "The commits from {} to {} go into one Pull Request, but belong \
+             to different ones already (#{} and #{})",
+            members[0].short_id,
+            members[members.len() - 1].short_id,
+            pull_request_number.unwrap(),
+            other
+        )));
+    }
+
+    let top = &members[members.len() - 1];
+    Ok(PreparedCommit {
+        oid: top.oid,
+        short_id: top.short_id.clone(),
+        parent_oid: members[0].parent_oid,
+        pr_stack: top.pr_stack.clone(),
+        message: combined_message(&members),
+        pull_request_number,
+        squashed: members,
+    })
+}
+
+/// Combine each group of `commits` (bottom of the stack first) into one
+/// commit. Commits that are not part of a group stay as they are.
+pub fn fold(mut commits: Vec<PreparedCommit>) -> Result<Vec<PreparedCommit>> {
+    let ranges = group_ranges(&commits)?;
+    let mut folded = Vec::with_capacity(ranges.len());
+    // From the top, so that the ranges still fit what is left of `commits`
+    for range in ranges.into_iter().rev() {
+        let mut members = commits.split_off(range.start);
+        folded.push(if members.len() == 1 {
+            members.pop().unwrap()
+        } else {
+            fold_group(members)?
+        });
+    }
+    folded.reverse();
+
+    Ok(folded)
+}
+
+/// The original commits of `commits`, as they were before `fold`.
+pub fn unfold(commits: Vec<PreparedCommit>) -> Vec<PreparedCommit> {
+    commits
+        .into_iter()
+        .flat_map(|commit| {
+            if commit.squashed.is_empty() {
+                vec![commit]
+            } else {
+                commit.squashed
+            }
+        })
+        .collect()
+}
+
+/// The commits of a group that was submitted, with what spr added to the
+/// message of the group (the `Pull Request` field, the PR Stack and so on)
+/// in the message of the top commit, and taken out of the others.
+pub fn submitted_members(commit: &PreparedCommit) -> Vec<PreparedCommit> {
+    let mut members = commit.squashed.clone();
+    let (top, below) = match members.split_last_mut() {
+        Some(split) => split,
+        None => return vec![commit.clone()],
+    };
+
+    let mut message = commit.message.clone();
+    for section in OWN_SECTIONS {
+        match top.message.remove(&section) {
+            Some(text) => message.insert(section, text),
+            None => message.remove(&section),
+        };
+    }
+    top.message = message;
+    top.pull_request_number = commit.pull_request_number;
+
+    for member in below {
+        member.message.remove(&MessageSection::PullRequest);
+        member.message.remove(&MessageSection::PRStack);
+        member.pull_request_number = None;
+    }
+
+    members
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::prepared_commit_factory;
+
+    fn commits(messages: &[&str]) -> Vec<PreparedCommit> {
+        messages
+            .iter()
+            .enumerate()
+            .map(|(index, message)| {
+                prepared_commit_factory(&index.to_string(), message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_is_continuation() {
+        let continuation = |message| {
+            is_continuation(&prepared_commit_factory("a", message).message)
+        };
+
+        assert!(continuation("Title\n\nSquash-Into-Previous: true"));
+        assert!(continuation(
+            "Title\n\nSummary\n\nTest Plan: none\n\nsquash-into-previous: yes"
+        ));
+        assert!(!continuation("Title\n\nSquash-Into-Previous: false"));
+        assert!(!continuation("Title\n\nSummary"));
+        assert!(!continuation("Squash-Into-Previous: true"));
+    }
+
+    #[test]
+    fn test_group_ranges() {
+        let stack = commits(&[
+            "One",
+            "Two",
+            "Three\n\nSquash-Into-Previous: true",
+            "Four\n\nSquash-Into-Previous: true",
+            "Five",
+            "Six\n\nSquash-Into-Previous: true",
+        ]);
+        assert_eq!(group_ranges(&stack).unwrap(), vec![0..1, 1..4, 4..6]);
+
+        assert_eq!(
+            group_ranges(&commits(&["One", "Two"])).unwrap(),
+            vec![0..1, 1..2]
+        );
+        assert!(group_ranges(&[]).unwrap().is_empty());
+        // Nothing to go with at the bottom of the stack
+        assert!(
+            group_ranges(&commits(&["One\n\nSquash-Into-Previous: true"]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_fold() {
+        let mut stack = commits(&[
+            "One",
+            "Two\n\nFirst part\n\nTest Plan: ran it\n\n\
+             Reviewers: alice",
+            "Three\n\nSecond part\n\nSquash-Into-Previous: true",
+            "Four",
+        ]);
+        for (index, commit) in stack.iter_mut().enumerate() {
+            commit.oid = git2::Oid::from_bytes(&[index as u8 + 1; 20]).unwrap();
+            commit.parent_oid =
+                git2::Oid::from_bytes(&[index as u8; 20]).unwrap();
+        }
+        stack[2].pull_request_number = Some(7);
+
+        let folded = fold(stack.clone()).unwrap();
+        assert_eq!(folded.len(), 3);
+        assert!(folded[0].squashed.is_empty());
+        assert!(folded[2].squashed.is_empty());
+
+        // The tree of the top commit on the parent of the bottom one
+        let group = &folded[1];
+        assert_eq!(group.oid, stack[2].oid);
+        assert_eq!(group.parent_oid, stack[1].parent_oid);
+        assert_eq!(group.pull_request_number, Some(7));
+        assert_eq!(group.message[&MessageSection::Title], "Two");
+        assert_eq!(
+            group.message[&MessageSection::Summary],
+            "First part\n\nThree\n\nSecond part"
+        );
+        assert_eq!(group.message[&MessageSection::TestPlan], "ran it");
+        assert_eq!(group.message[&MessageSection::Reviewers], "alice");
+
+        let unfolded = unfold(folded);
+        assert_eq!(
+            unfolded.iter().map(|c| c.oid).collect::<Vec<_>>(),
+            stack.iter().map(|c| c.oid).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_fold_different_pull_requests() {
+        let mut stack = commits(&["One", "Two\n\nSquash-Into-Previous: true"]);
+        stack[0].pull_request_number = Some(1);
+        assert_eq!(
+            fold(stack.clone()).unwrap()[0].pull_request_number,
+            Some(1)
+        );
+
+        stack[1].pull_request_number = Some(2);
+        assert!(fold(stack).is_err());
+    }
+
+    #[test]
+    fn test_submitted_members() {
+        let mut stack = commits(&[
+            "One\n\nFirst part\n\nPull Request: #1",
+            "Two\n\nSquash-Into-Previous: true",
+        ]);
+        stack[0].pull_request_number = Some(1);
+
+        let mut group = fold(stack).unwrap().pop().unwrap();
+        group
+            .message
+            .insert(MessageSection::PRStack, "* #1".to_string());
+
+        let members = submitted_members(&group);
+        // The top commit gets the Pull Request, and keeps its own message
+        assert_eq!(members[1].pull_request_number, Some(1));
+        assert_eq!(members[1].message[&MessageSection::Title], "Two");
+        assert_eq!(
+            members[1].message[&MessageSection::Summary],
+            "Squash-Into-Previous: true"
+        );
+        assert!(members[1]
+            .message
+            .contains_key(&MessageSection::PullRequest));
+        assert!(members[1].message.contains_key(&MessageSection::PRStack));
+        // ...and the bottom one no longer has it
+        assert_eq!(members[0].pull_request_number, None);
+        assert_eq!(members[0].message[&MessageSection::Summary], "First part");
+        assert!(!members[0]
+            .message
+            .contains_key(&MessageSection::PullRequest));
+    }
+}
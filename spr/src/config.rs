@@ -5,19 +5,521 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Duration};
 
-use crate::{error::Result, github::GitHubBranch, utils::slugify};
+use chrono::{DateTime, FixedOffset};
+
+use crate::{
+    error::{Error, Result},
+    github::GitHubBranch,
+    message::PRStackFormat,
+    utils::{normalize_typography, slugify, slugify_with},
+};
+
+/// Slug used for branch names when the commit title does not contain any
+/// characters that are usable in a branch name, unless configured otherwise.
+const EMPTY_SLUG_FALLBACK: &str = "patch";
+
+/// The slug of branches for commits whose titles do not contain any
+/// characters that are usable in a branch name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EmptySlugFallback {
+    /// This text
+    Literal(String),
+    /// A short hash of the commit title, so that such commits do not all
+    /// get the same branch name (but for a suffix)
+    Hash,
+}
+
+impl Default for EmptySlugFallback {
+    fn default() -> Self {
+        Self::Literal(EMPTY_SLUG_FALLBACK.to_string())
+    }
+}
+
+impl std::str::FromStr for EmptySlugFallback {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("hash") {
+            return Ok(Self::Hash);
+        }
+        // It goes into branch names as it is
+        if s.is_empty() || slugify(s) != s {
+            return Err(crate::error::Error::new(format!(
+                "Invalid value for spr.emptySlugFallback: '{}' (must be \
+                 'hash', or lowercase letters, digits and dashes, like \
+                 'patch')",
+                s
+            )));
+        }
+
+        Ok(Self::Literal(s.to_string()))
+    }
+}
+
+/// What `spr diff` does about an existing Pull Request whose base branch is
+/// neither master nor a base branch spr made, e.g. because someone changed it
+/// on GitHub.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BaseMismatch {
+    /// Base the Pull Request on master (or a new base branch) again
+    #[default]
+    Correct,
+    /// Say so, and leave the Pull Request alone
+    Warn,
+}
+
+impl std::str::FromStr for BaseMismatch {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "correct" => Ok(Self::Correct),
+            "warn" => Ok(Self::Warn),
+            _ => Err(crate::error::Error::new(format!(
+                "Invalid value for spr.baseMismatch: '{}' (must be 'correct' \
+                 or 'warn')",
+                s
+            ))),
+        }
+    }
+}
+
+/// What to do about commits that do not change anything (i.e. that have the same
+/// tree as their parent) when submitting them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyCommits {
+    /// Submit them like any other commit
+    #[default]
+    Allow,
+    /// Leave them out (they stay in the local branch, though)
+    Skip,
+    /// Refuse to submit anything
+    Error,
+}
+
+impl std::str::FromStr for EmptyCommits {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "allow" => Ok(Self::Allow),
+            "skip" => Ok(Self::Skip),
+            "error" => Ok(Self::Error),
+            _ => Err(crate::error::Error::new(format!(
+                "Invalid value for spr.emptyCommits: '{}' (must be 'allow', \
+                 'skip' or 'error')",
+                s
+            ))),
+        }
+    }
+}
+
+/// Who gets credit for the commit that `spr land` has GitHub create.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LandAuthor {
+    /// Whoever GitHub attributes the commit to
+    #[default]
+    GitHub,
+    /// The author of the Pull Request
+    PullRequestAuthor,
+    /// The user running `spr land` (as configured in Git)
+    CurrentUser,
+}
+
+impl std::str::FromStr for LandAuthor {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "github" => Ok(Self::GitHub),
+            "pr-author" => Ok(Self::PullRequestAuthor),
+            "current-user" => Ok(Self::CurrentUser),
+            _ => Err(crate::error::Error::new(format!(
+                "Invalid value for spr.landAuthor: '{}' (must be 'github', \
+                 'pr-author' or 'current-user')",
+                s
+            ))),
+        }
+    }
+}
+
+/// How `spr diff` picks reviewers from `spr.reviewerPool` for a new Pull
+/// Request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReviewerStrategy {
+    /// Everyone in the pool
+    #[default]
+    All,
+    /// The next ones in the pool, in turn
+    RoundRobin,
+}
+
+impl std::str::FromStr for ReviewerStrategy {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "round-robin" => Ok(Self::RoundRobin),
+            _ => Err(crate::error::Error::new(format!(
+                "Invalid value for spr.reviewerStrategy: '{}' (must be 'all' \
+                 or 'round-robin')",
+                s
+            ))),
+        }
+    }
+}
+
+/// Where spr records which Pull Request a commit belongs to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PullRequestTracking {
+    /// In a `Pull Request` trailer in the commit message
+    #[default]
+    Trailer,
+    /// In a Git note on the commit, leaving the commit message alone
+    Notes,
+}
+
+impl std::str::FromStr for PullRequestTracking {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "trailer" => Ok(Self::Trailer),
+            "notes" => Ok(Self::Notes),
+            _ => Err(crate::error::Error::new(format!(
+                "Invalid value for spr.pullRequestTracking: '{}' (must be \
+                 'trailer' or 'notes')",
+                s
+            ))),
+        }
+    }
+}
+
+/// The changes to the Pull Requests of the stack that `spr watch` tells about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// Someone approved a Pull Request
+    Approved,
+    /// Someone requested changes on a Pull Request
+    ChangesRequested,
+    /// Someone was asked to review a Pull Request
+    ReviewRequested,
+    /// The checks of a Pull Request passed or failed
+    Checks,
+    /// A Pull Request became mergeable or got conflicts, or was merged or
+    /// closed
+    Mergeable,
+}
+
+impl WatchEvent {
+    pub const ALL: [WatchEvent; 5] = [
+        WatchEvent::Approved,
+        WatchEvent::ChangesRequested,
+        WatchEvent::ReviewRequested,
+        WatchEvent::Checks,
+        WatchEvent::Mergeable,
+    ];
+}
+
+impl std::str::FromStr for WatchEvent {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "approved" => Ok(Self::Approved),
+            "changes-requested" => Ok(Self::ChangesRequested),
+            "review-requested" => Ok(Self::ReviewRequested),
+            "checks" => Ok(Self::Checks),
+            "mergeable" => Ok(Self::Mergeable),
+            _ => Err(crate::error::Error::new(format!(
+                "Invalid value for spr.watchEvents: '{}' (must be \
+                 'approved', 'changes-requested', 'review-requested', \
+                 'checks' or 'mergeable')",
+                s
+            ))),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Config {
+    pub github_host: String,
     pub owner: String,
     pub repo: String,
     pub remote_name: String,
+    pub fork_owner: Option<String>,
     pub master_ref: GitHubBranch,
     pub branch_prefix: String,
     pub require_approval: bool,
     pub require_test_plan: bool,
+    pub pr_stack_format: PRStackFormat,
+    /// How to clean up the messages the user edits in spr's editor
+    pub message_cleanup: crate::message::MessageCleanup,
+    pub strip_title_emoji: bool,
+    /// Whether to replace curly quotes, dashes and ellipses in commit titles
+    /// with plain characters, for the titles of Pull Requests and the names
+    /// of their branches
+    pub normalize_typography: bool,
+    pub strip_trailers: Vec<String>,
+    pub trailer_sections: Vec<crate::message::TrailerSection>,
+    pub reviewer_aliases: Vec<crate::utils::ReviewerAlias>,
+    pub default_reviewers: Vec<String>,
+    /// The users and teams `spr diff` picks reviewers of new Pull Requests
+    /// from, as `reviewer_strategy` says
+    pub reviewer_pool: Vec<String>,
+    pub reviewer_strategy: ReviewerStrategy,
+    /// How many reviewers the `round-robin` strategy picks for each Pull
+    /// Request
+    pub reviewer_pool_count: usize,
+    /// How many distinct reviewers (counting a team as one) a new Pull
+    /// Request must have, or zero for no minimum
+    pub reviewer_required: usize,
+    pub user_agent: String,
+    pub connect_timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub assignees: Vec<String>,
+    pub assign_self: bool,
+    pub branch_slug_strip_pattern: Option<lazy_regex::Regex>,
+    pub branch_slug_keep_dots: bool,
+    pub empty_commits: EmptyCommits,
+    pub base_mismatch: BaseMismatch,
+    pub empty_slug_fallback: EmptySlugFallback,
+    pub land_author: LandAuthor,
+    /// How many Pull Requests to fetch at the same time
+    pub concurrency: usize,
+    pub github_app: Option<crate::github_app::GitHubApp>,
+    pub confirm_force_push: bool,
+    /// Whether `spr diff` records the base of the stack, as with
+    /// `--set-upstream`
+    pub set_upstream: bool,
+    /// Whether `spr diff --cherry-pick` credits the author of a commit
+    /// written by someone else with a `Co-authored-by` trailer
+    pub cherry_pick_co_author: bool,
+    /// Whether `spr diff` also brings the PR Stacks of the Pull Requests of
+    /// the stack it did not update up to date
+    pub refresh_pr_stacks: bool,
+    /// Whether `spr diff` renames the branch of an existing Pull Request when
+    /// the title of its commit changed, so that it is named after the new one
+    pub sync_branch_names: bool,
+    /// Whether Pull Request descriptions say which Pull Request they depend
+    /// on, with a `Depends on #N` line naming the one below in the stack
+    pub depends_on_line: bool,
+    /// Whether `spr diff` folds `fixup!` and `squash!` commits into their
+    /// targets before submitting, as with `--autosquash`
+    pub autosquash: bool,
+    /// Whether `spr diff` prints what it is about to do with each commit
+    /// before pushing anything, as with `--show-plan`
+    pub show_plan: bool,
+    /// Server-side push options (`git push --push-option`) for pushing the
+    /// branches of Pull Requests
+    pub push_options: Vec<String>,
+    /// Paths (in gitignore syntax) that commits `spr diff` submits must not
+    /// change, e.g. secrets or build artifacts
+    pub submit_denylist: Vec<String>,
+    /// Whether `spr land` deletes the branches of landed Pull Requests on
+    /// GitHub
+    pub land_delete_remote_branches: bool,
+    /// Whether `spr land` deletes the local branches of the same names
+    pub land_delete_local_branches: bool,
+    pub checks_poll_interval: Duration,
+    pub checks_timeout: Option<Duration>,
+    pub pull_request_tracking: PullRequestTracking,
+    pub notes_ref: String,
+    /// Whether operations on many Pull Requests refuse to start if the GitHub
+    /// rate limits may not leave enough requests for them (rather than just
+    /// warning)
+    pub strict_rate_limit: bool,
+    /// Fetch the branches of Pull Requests even if the cache of pushed
+    /// branches says they are unchanged (`spr --refresh`)
+    pub refresh: bool,
+    /// The title (first line) and message of the commit `spr land` creates,
+    /// with placeholders for the Pull Request, instead of its title and
+    /// description
+    pub land_message_template: Option<String>,
+    /// Whether `spr land` titles squash commits "<PR title> (#<number>)",
+    /// with the Pull Request's description as the message
+    pub land_squash_title_from_pr: bool,
+    /// Trailers (with placeholders) that `spr land` adds to the message of
+    /// the commit it creates
+    pub land_trailers: Vec<String>,
+    /// A shell command that `spr diff` runs in a checkout of each commit it
+    /// submits, amending the commit with whatever files the command changes
+    pub commit_hook: Option<String>,
+    /// The project (by number or title) of the owner of the repository that
+    /// `spr diff` adds new Pull Requests to
+    pub project: Option<String>,
+    /// The format (as in `strftime`) of the date of the commit to put in front
+    /// of the names of new branches, if any
+    pub branch_date_format: Option<String>,
+    /// How often `spr watch` asks GitHub about the stack
+    pub watch_interval: Duration,
+    /// The changes `spr watch` notifies about
+    pub watch_events: Vec<WatchEvent>,
+    /// The length (in characters) beyond which `spr diff` warns about a Pull
+    /// Request title, or `None` for no limit
+    pub title_length_limit: Option<usize>,
+    /// The oldest Git that spr agrees to run
+    pub minimum_git_version: crate::git::GitVersion,
+}
+
+/// The owner and name of the repository with the given (HTTPS or SSH) remote
+/// URL, if it is on the given GitHub host.
+pub fn parse_github_remote(
+    github_host: &str,
+    url: &str,
+) -> Option<(String, String)> {
+    let (_, path) = url.split_once(github_host)?;
+    let path = path.strip_prefix([':', '/'])?;
+    let (owner, repo) = path.split_once('/')?;
+    let repo = repo.trim_end_matches('/');
+    let repo = repo.strip_suffix(".git").unwrap_or(repo);
+
+    (!owner.is_empty() && !repo.is_empty())
+        .then(|| (owner.to_string(), repo.to_string()))
+}
+
+/// Check that `format` is a valid `strftime` format for `spr.branchDateFormat`,
+/// which gives dates that can go into branch names.
+pub fn validate_branch_date_format(format: &str) -> Result<()> {
+    use chrono::format::{Item, StrftimeItems};
+
+    let invalid = |reason: &str| {
+        Err(Error::new(format!(
+            "Invalid value for spr.branchDateFormat: '{}' ({})",
+            format, reason
+        )))
+    };
+    if format.is_empty() {
+        return invalid("must not be empty");
+    }
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return invalid("not a valid date format");
+    }
+    let sample = DateTime::parse_from_rfc3339("2024-06-01T12:34:56+02:00")
+        .unwrap()
+        .format(format)
+        .to_string();
+    if !git2::Reference::is_valid_name(&format!("refs/heads/{}-slug", sample)) {
+        return invalid("gives dates that cannot go into branch names");
+    }
+
+    Ok(())
+}
+
+/// The repository given with `--target-owner` and `--target-repo`, for
+/// creating Pull Requests in another repository than the one the remote
+/// points to. The two only make sense together.
+pub fn target_repository(
+    owner: Option<String>,
+    repo: Option<String>,
+) -> Result<Option<(String, String)>> {
+    match (owner, repo) {
+        (Some(owner), Some(repo)) => Ok(Some((owner, repo))),
+        (None, None) => Ok(None),
+        (Some(_), None) | (None, Some(_)) => Err(Error::new(
+            "--target-owner and --target-repo must be given together",
+        )),
+    }
+}
+
+/// The branch the stack is based on, as chosen by [`choose_base_branch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseBranch {
+    pub name: String,
+    /// Whether this is the upstream of the checked-out branch
+    pub from_upstream: bool,
+}
+
+impl BaseBranch {
+    /// What `--base-auto` reports about the chosen base.
+    pub fn describe(&self, remote_name: &str) -> String {
+        if self.from_upstream {
+            format!(
+                "Base branch: {} (the upstream of the current branch)",
+                self.name
+            )
+        } else {
+            format!(
+                "Base branch: {} (the current branch has no upstream on {})",
+                self.name, remote_name
+            )
+        }
+    }
+}
+
+/// Choose the branch to base the stack on: the upstream of the checked-out
+/// branch if there is one (which is only looked up with `--base-auto`),
+/// otherwise `spr.githubMasterBranch`, or the remote's default branch if that
+/// is not set either.
+pub fn choose_base_branch(
+    upstream: Option<String>,
+    configured: Option<String>,
+    remote_default: impl FnOnce() -> Result<String>,
+) -> Result<BaseBranch> {
+    Ok(match (upstream, configured) {
+        (Some(name), _) => BaseBranch {
+            name,
+            from_upstream: true,
+        },
+        (None, Some(name)) => BaseBranch {
+            name,
+            from_upstream: false,
+        },
+        (None, None) => BaseBranch {
+            name: remote_default()?,
+            from_upstream: false,
+        },
+    })
+}
+
+/// How many requests to GitHub (e.g. fetching Pull Requests) we run at the same
+/// time, unless configured otherwise.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// How often `spr land --wait-for-checks` asks GitHub about the checks of a
+/// Pull Request, unless configured otherwise.
+pub const DEFAULT_CHECKS_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long `spr land --wait-for-checks` waits for the checks of a Pull
+/// Request to finish, unless configured otherwise.
+pub const DEFAULT_CHECKS_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// How often `spr watch` asks GitHub about the Pull Requests of the stack,
+/// unless configured otherwise.
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The length of Pull Request titles beyond which `spr diff` warns, unless
+/// configured otherwise. GitHub cuts off longer titles in lists of Pull
+/// Requests and in notifications.
+pub const DEFAULT_TITLE_LENGTH_LIMIT: usize = 72;
+
+/// The notes ref in which spr records the Pull Requests of commits, unless
+/// configured otherwise.
+pub const DEFAULT_NOTES_REF: &str = "refs/notes/spr";
+
+/// The host name of GitHub, unless configured otherwise (e.g. for GitHub
+/// Enterprise).
+pub const DEFAULT_GITHUB_HOST: &str = "github.com";
+
+/// How long to wait for a connection to GitHub, unless configured otherwise.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for GitHub to respond to a request, unless configured
+/// otherwise.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The User-Agent spr sends to GitHub unless configured otherwise, e.g.
+/// `spr/1.3.5 (+https://github.com/getcord/spr)`.
+pub fn default_user_agent() -> String {
+    format!(
+        "spr/{} (+{})",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_REPOSITORY")
+    )
 }
 
 impl Config {
@@ -36,19 +538,109 @@ impl Config {
             &master_branch,
         );
         Self {
+            github_host: DEFAULT_GITHUB_HOST.to_string(),
             owner,
             repo,
             remote_name,
+            fork_owner: None,
             master_ref,
             branch_prefix,
             require_approval,
             require_test_plan,
+            pr_stack_format: Default::default(),
+            message_cleanup: Default::default(),
+            strip_title_emoji: false,
+            normalize_typography: false,
+            strip_trailers: Vec::new(),
+            trailer_sections: Vec::new(),
+            reviewer_aliases: Vec::new(),
+            default_reviewers: Vec::new(),
+            reviewer_pool: Vec::new(),
+            reviewer_strategy: ReviewerStrategy::default(),
+            reviewer_pool_count: 1,
+            reviewer_required: 0,
+            user_agent: default_user_agent(),
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            assignees: Vec::new(),
+            assign_self: false,
+            branch_slug_strip_pattern: None,
+            branch_slug_keep_dots: false,
+            empty_commits: EmptyCommits::Allow,
+            base_mismatch: BaseMismatch::Correct,
+            empty_slug_fallback: Default::default(),
+            land_author: LandAuthor::GitHub,
+            concurrency: DEFAULT_CONCURRENCY,
+            github_app: None,
+            confirm_force_push: false,
+            set_upstream: false,
+            cherry_pick_co_author: true,
+            refresh_pr_stacks: true,
+            sync_branch_names: false,
+            autosquash: false,
+            show_plan: false,
+            depends_on_line: false,
+            push_options: Vec::new(),
+            submit_denylist: Vec::new(),
+            land_delete_remote_branches: false,
+            land_delete_local_branches: false,
+            checks_poll_interval: DEFAULT_CHECKS_POLL_INTERVAL,
+            checks_timeout: Some(DEFAULT_CHECKS_TIMEOUT),
+            pull_request_tracking: PullRequestTracking::Trailer,
+            notes_ref: DEFAULT_NOTES_REF.to_string(),
+            strict_rate_limit: false,
+            refresh: false,
+            land_message_template: None,
+            land_squash_title_from_pr: false,
+            land_trailers: Vec::new(),
+            commit_hook: None,
+            project: None,
+            branch_date_format: None,
+            watch_interval: DEFAULT_WATCH_INTERVAL,
+            watch_events: WatchEvent::ALL.to_vec(),
+            title_length_limit: Some(DEFAULT_TITLE_LENGTH_LIMIT),
+            minimum_git_version: crate::git::MINIMUM_GIT_VERSION,
+        }
+    }
+
+    /// The owner of the GitHub repository with the given (HTTPS or SSH) URL,
+    /// if it is on our GitHub host.
+    pub fn owner_from_remote_url(&self, url: &str) -> Option<String> {
+        parse_github_remote(&self.github_host, url).map(|(owner, _)| owner)
+    }
+
+    /// The head reference for creating a Pull Request from the given branch:
+    /// across forks, GitHub needs to be told whose branch it is.
+    pub fn pull_request_head(&self, branch: &GitHubBranch) -> String {
+        match &self.fork_owner {
+            Some(owner) => format!("{}:{}", owner, branch.branch_name()),
+            None => branch.branch_name().to_string(),
+        }
+    }
+
+    /// The base URL of GitHub's REST API. GitHub Enterprise Server serves it
+    /// under `/api/v3` on its own host.
+    pub fn api_url(&self) -> String {
+        if self.github_host == DEFAULT_GITHUB_HOST {
+            "https://api.github.com/".to_string()
+        } else {
+            format!("https://{}/api/v3/", self.github_host)
+        }
+    }
+
+    /// The URL of GitHub's GraphQL API.
+    pub fn graphql_url(&self) -> String {
+        if self.github_host == DEFAULT_GITHUB_HOST {
+            "https://api.github.com/graphql".to_string()
+        } else {
+            format!("https://{}/api/graphql", self.github_host)
         }
     }
 
     pub fn pull_request_url(&self, number: u64) -> String {
         format!(
-            "https://github.com/{owner}/{repo}/pull/{number}",
+            "https://{host}/{owner}/{repo}/pull/{number}",
+            host = &self.github_host,
             owner = &self.owner,
             repo = &self.repo
         )
@@ -66,39 +658,102 @@ impl Config {
         }
 
         let regex = lazy_regex::regex!(
-            r#"^\s*https?://github.com/([\w\-\.]+)/([\w\-\.]+)/pull/(\d+)([/?#].*)?\s*$"#
+            r#"^\s*https?://([\w\-\.]+)/([\w\-\.]+)/([\w\-\.]+)/pull/(\d+)([/?#].*)?\s*$"#
         );
         let m = regex.captures(text);
         if let Some(caps) = m {
-            if self.owner == caps.get(1).unwrap().as_str()
-                && self.repo == caps.get(2).unwrap().as_str()
+            if self.github_host == caps.get(1).unwrap().as_str()
+                && self.owner == caps.get(2).unwrap().as_str()
+                && self.repo == caps.get(3).unwrap().as_str()
             {
-                return Some(caps.get(3).unwrap().as_str().parse().unwrap());
+                return Some(caps.get(4).unwrap().as_str().parse().unwrap());
             }
         }
 
         None
     }
 
+    /// The name for the branch of a new Pull Request for a commit with the
+    /// given title and author date (the date only matters with
+    /// `spr.branchDateFormat`).
     pub fn get_new_branch_name(
         &self,
         existing_ref_names: &HashSet<String>,
         title: &str,
+        date: Option<&DateTime<FixedOffset>>,
     ) -> String {
-        self.find_unused_branch_name(existing_ref_names, &slugify(title))
+        self.find_unused_branch_name(
+            existing_ref_names,
+            &self.dated_slug(title, date),
+        )
     }
 
     pub fn get_base_branch_name(
         &self,
         existing_ref_names: &HashSet<String>,
         title: &str,
+        date: Option<&DateTime<FixedOffset>>,
     ) -> String {
         self.find_unused_branch_name(
             existing_ref_names,
-            &format!("{}.{}", self.master_ref.branch_name(), &slugify(title)),
+            &format!(
+                "{}.{}",
+                self.master_ref.branch_name(),
+                &self.dated_slug(title, date)
+            ),
         )
     }
 
+    /// The slug, with the date in `spr.branchDateFormat` in front of it, if
+    /// that is configured.
+    fn dated_slug(
+        &self,
+        title: &str,
+        date: Option<&DateTime<FixedOffset>>,
+    ) -> String {
+        match (&self.branch_date_format, date) {
+            (Some(format), Some(date)) => {
+                format!("{}-{}", date.format(format), self.slug(title))
+            }
+            _ => self.slug(title),
+        }
+    }
+
+    /// The slug used for naming branches for a commit with the given title.
+    /// Titles consisting only of characters that cannot go into a branch name
+    /// get a generic slug, so that we can always create a branch.
+    fn slug(&self, title: &str) -> String {
+        // Remove a leading token (like a ticket id) that should only appear in
+        // the Pull Request title, but not in the branch name.
+        let title = self
+            .branch_slug_strip_pattern
+            .as_ref()
+            .and_then(|regex| regex.find(title))
+            .filter(|m| m.start() == 0)
+            .map(|m| &title[m.end()..])
+            .unwrap_or(title);
+
+        let slug = if self.normalize_typography {
+            slugify_with(
+                &normalize_typography(title),
+                self.branch_slug_keep_dots,
+            )
+        } else {
+            slugify_with(title, self.branch_slug_keep_dots)
+        };
+        if !slug.is_empty() {
+            return slug;
+        }
+        match &self.empty_slug_fallback {
+            EmptySlugFallback::Literal(fallback) => fallback.clone(),
+            EmptySlugFallback::Hash => {
+                git2::Oid::hash_object(git2::ObjectType::Blob, title.as_bytes())
+                    .map(|oid| oid.to_string()[..8].to_string())
+                    .unwrap_or_else(|_| EMPTY_SLUG_FALLBACK.to_string())
+            }
+        }
+    }
+
     fn find_unused_branch_name(
         &self,
         existing_ref_names: &HashSet<String>,
@@ -142,12 +797,163 @@ impl Config {
     }
 }
 
+/// The Git configuration spr reads its settings from: the given file only, if
+/// there is one (e.g. from `--config`), or else the usual configuration of the
+/// repository (including the global and system-wide files). Unlike those,
+/// an explicitly given file must exist.
+pub fn open_git_config(
+    repo: &git2::Repository,
+    explicit_path: Option<&std::path::Path>,
+) -> Result<git2::Config> {
+    match explicit_path {
+        Some(path) => {
+            if !path.is_file() {
+                return Err(Error::new(format!(
+                    "Config file {} does not exist",
+                    path.display()
+                )));
+            }
+            Ok(git2::Config::open(path)?)
+        }
+        None => Ok(repo.config()?),
+    }
+}
+
+/// The environment variable naming the profile to use, if not given with
+/// `--profile`.
+pub const PROFILE_ENV_VAR: &str = "SPR_PROFILE";
+
+/// The name of the profile to use: the one given on the command line, or else
+/// the one named in the environment, or else the default profile configured
+/// as `spr.profile`. Without any of them, no profile is used.
+pub fn select_profile(
+    flag: Option<String>,
+    env: Option<String>,
+    git_config: &git2::Config,
+) -> Option<String> {
+    flag.or(env.filter(|name| !name.is_empty()))
+        .or_else(|| git_config.get_string("spr.profile").ok())
+        .filter(|name| !name.is_empty())
+}
+
+/// Reads spr's settings from the Git configuration, within a profile: with a
+/// profile named e.g. `work`, the value of `spr.work.githubAuthToken` takes
+/// precedence over that of `spr.githubAuthToken`, and so on for every setting.
+/// Settings the profile does not have are taken from outside it.
+pub struct ProfileConfig {
+    git_config: git2::Config,
+    profile: Option<String>,
+}
+
+impl ProfileConfig {
+    pub fn new(
+        git_config: git2::Config,
+        profile: Option<String>,
+    ) -> Result<Self> {
+        if let Some(name) = &profile {
+            if !lazy_regex::regex_is_match!(r"^[\w-]+$", name) {
+                return Err(Error::new(format!(
+                    "Invalid profile name '{}'",
+                    name
+                )));
+            }
+
+            let mut entries =
+                git_config.entries(Some(&format!(r"^spr\.{}\.", name)))?;
+            if entries.next().is_none() {
+                return Err(Error::new(format!(
+                    "Unknown profile '{}': there are no spr.{}.* settings in \
+                     the Git configuration",
+                    name, name
+                )));
+            }
+        }
+
+        Ok(Self {
+            git_config,
+            profile,
+        })
+    }
+
+    /// The name of the given setting within the profile.
+    fn profile_name(&self, name: &str) -> Option<String> {
+        let profile = self.profile.as_ref()?;
+        let key = name.strip_prefix("spr.")?;
+        Some(format!("spr.{}.{}", profile, key))
+    }
+
+    fn get<T>(
+        &self,
+        name: &str,
+        get: impl Fn(&git2::Config, &str) -> std::result::Result<T, git2::Error>,
+    ) -> std::result::Result<T, git2::Error> {
+        if let Some(profile_name) = self.profile_name(name) {
+            match get(&self.git_config, &profile_name) {
+                Err(error) if error.code() == git2::ErrorCode::NotFound => (),
+                result => return result,
+            }
+        }
+
+        get(&self.git_config, name)
+    }
+
+    pub fn get_string(
+        &self,
+        name: &str,
+    ) -> std::result::Result<String, git2::Error> {
+        self.get(name, git2::Config::get_string)
+    }
+
+    pub fn get_bool(
+        &self,
+        name: &str,
+    ) -> std::result::Result<bool, git2::Error> {
+        self.get(name, git2::Config::get_bool)
+    }
+
+    pub fn get_i64(&self, name: &str) -> std::result::Result<i64, git2::Error> {
+        self.get(name, git2::Config::get_i64)
+    }
+
+    pub fn get_path(
+        &self,
+        name: &str,
+    ) -> std::result::Result<std::path::PathBuf, git2::Error> {
+        self.get(name, git2::Config::get_path)
+    }
+
+    /// All values of a setting that may be given multiple times. If the
+    /// profile has any, they replace the ones outside it.
+    pub fn get_multivar(&self, name: &str) -> Result<Vec<String>> {
+        let values = |name: &str| -> Result<Vec<String>> {
+            let mut values = Vec::new();
+            if let Ok(mut entries) = self.git_config.multivar(name, None) {
+                while let Some(entry) = entries.next() {
+                    if let Some(value) = entry?.value() {
+                        values.push(value.to_string());
+                    }
+                }
+            }
+            Ok(values)
+        };
+
+        if let Some(profile_name) = self.profile_name(name) {
+            let profile_values = values(&profile_name)?;
+            if !profile_values.is_empty() {
+                return Ok(profile_values);
+            }
+        }
+
+        values(name)
+    }
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
-    fn config_factory() -> Config {
+    pub(crate) fn config_factory() -> Config {
         crate::config::Config::new(
             "acme".into(),
             "codez".into(),
@@ -169,6 +975,201 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_owner_from_remote_url() {
+        let mut config = config_factory();
+
+        for url in [
+            "https://github.com/fork/codez.git",
+            "https://github.com/fork/codez",
+            "git@github.com:fork/codez.git",
+            "ssh://git@github.com/fork/codez.git",
+        ] {
+            assert_eq!(
+                config.owner_from_remote_url(url).as_deref(),
+                Some("fork"),
+                "{}",
+                url
+            );
+        }
+        assert_eq!(
+            config.owner_from_remote_url("git@gitlab.com:fork/codez.git"),
+            None
+        );
+
+        config.github_host = "github.acme.com".to_string();
+        assert_eq!(
+            config
+                .owner_from_remote_url("git@github.acme.com:fork/codez.git")
+                .as_deref(),
+            Some("fork")
+        );
+    }
+
+    #[test]
+    fn test_parse_github_remote() {
+        for url in [
+            "https://github.com/acme/codez.git",
+            "https://github.com/acme/codez/",
+            "git@github.com:acme/codez.git",
+            "ssh://git@github.com/acme/codez",
+        ] {
+            assert_eq!(
+                parse_github_remote("github.com", url),
+                Some(("acme".to_string(), "codez".to_string())),
+                "{}",
+                url
+            );
+        }
+        assert_eq!(
+            parse_github_remote("github.com", "git@gitlab.com:acme/codez.git"),
+            None
+        );
+        assert_eq!(parse_github_remote("github.com", "/srv/git/codez"), None);
+    }
+
+    #[test]
+    fn test_pull_request_head_same_repo() {
+        let config = config_factory();
+        let branch = config.new_github_branch("spr/foo/change");
+
+        assert_eq!(config.pull_request_head(&branch), "spr/foo/change");
+    }
+
+    #[test]
+    fn test_pull_request_head_across_forks() {
+        let mut config = config_factory();
+        config.fork_owner = Some("contributor".to_string());
+        let branch = config.new_github_branch("spr/foo/change");
+
+        assert_eq!(
+            config.pull_request_head(&branch),
+            "contributor:spr/foo/change"
+        );
+    }
+
+    #[test]
+    fn test_pull_request_url_github_host() {
+        let mut gh = config_factory();
+        gh.github_host = "github.acme.com".to_string();
+
+        assert_eq!(
+            &gh.pull_request_url(123),
+            "https://github.acme.com/acme/codez/pull/123"
+        );
+    }
+
+    #[test]
+    fn test_api_urls() {
+        let mut gh = config_factory();
+        assert_eq!(gh.api_url(), "https://api.github.com/");
+        assert_eq!(gh.graphql_url(), "https://api.github.com/graphql");
+
+        gh.github_host = "github.acme.com".to_string();
+        assert_eq!(gh.api_url(), "https://github.acme.com/api/v3/");
+        assert_eq!(gh.graphql_url(), "https://github.acme.com/api/graphql");
+    }
+
+    #[test]
+    fn test_target_repository() {
+        let some = |s: &str| Some(s.to_string());
+
+        assert_eq!(target_repository(None, None).unwrap(), None);
+        assert_eq!(
+            target_repository(some("upstream"), some("codez")).unwrap(),
+            Some(("upstream".to_string(), "codez".to_string()))
+        );
+        for (owner, repo) in [(some("upstream"), None), (None, some("codez"))] {
+            assert_eq!(
+                target_repository(owner, repo).unwrap_err().messages(),
+                &vec![
+                    "--target-owner and --target-repo must be given together"
+                        .to_string()
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_choose_base_branch_with_upstream() {
+        let base = choose_base_branch(
+            Some("release".to_string()),
+            Some("main".to_string()),
+            || panic!("the remote's default branch is not needed"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            base,
+            BaseBranch {
+                name: "release".to_string(),
+                from_upstream: true,
+            }
+        );
+        assert_eq!(
+            base.describe("origin"),
+            "Base branch: release (the upstream of the current branch)"
+        );
+    }
+
+    #[test]
+    fn test_choose_base_branch_without_upstream() {
+        let base = choose_base_branch(None, Some("main".to_string()), || {
+            panic!("the remote's default branch is not needed")
+        })
+        .unwrap();
+        assert_eq!(
+            base,
+            BaseBranch {
+                name: "main".to_string(),
+                from_upstream: false,
+            }
+        );
+        assert_eq!(
+            base.describe("origin"),
+            "Base branch: main (the current branch has no upstream on origin)"
+        );
+
+        let base =
+            choose_base_branch(None, None, || Ok("trunk".to_string())).unwrap();
+        assert_eq!(base.name, "trunk");
+        assert!(!base.from_upstream);
+
+        let error =
+            choose_base_branch(None, None, || Err(Error::new("no default")))
+                .unwrap_err();
+        assert_eq!(error.messages(), &vec!["no default".to_string()]);
+    }
+
+    #[test]
+    fn test_pull_request_url_target_repository() {
+        let (owner, repo) =
+            target_repository(Some("upstream".into()), Some("other".into()))
+                .unwrap()
+                .unwrap();
+        let mut config = config_factory();
+        config.owner = owner;
+        config.repo = repo;
+        // Pushing to a fork of the target repository
+        config.fork_owner =
+            config.owner_from_remote_url("git@github.com:acme/codez.git");
+
+        assert_eq!(
+            config.pull_request_url(5),
+            "https://github.com/upstream/other/pull/5"
+        );
+        assert_eq!(
+            config.parse_pull_request_field(
+                "https://github.com/upstream/other/pull/5"
+            ),
+            Some(5)
+        );
+        assert_eq!(
+            config.pull_request_head(&config.new_github_branch("spr/foo/x")),
+            "acme:spr/foo/x"
+        );
+    }
+
     #[test]
     fn test_parse_pull_request_field_empty() {
         let gh = config_factory();
@@ -229,4 +1230,391 @@ mod tests {
             Some(123)
         );
     }
+
+    #[test]
+    fn test_parse_pull_request_field_url_github_host() {
+        let mut gh = config_factory();
+        gh.github_host = "github.acme.com".to_string();
+
+        assert_eq!(
+            gh.parse_pull_request_field(
+                "https://github.acme.com/acme/codez/pull/123"
+            ),
+            Some(123)
+        );
+        assert_eq!(
+            gh.parse_pull_request_field(
+                "https://github.com/acme/codez/pull/123"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_new_branch_name() {
+        let gh = config_factory();
+        let refs = HashSet::new();
+
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "Hello World", None),
+            "spr/foo/hello-world"
+        );
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "Release foo.lock", None),
+            "spr/foo/release-foolock"
+        );
+    }
+
+    #[test]
+    fn test_get_new_branch_name_empty_slug() {
+        let gh = config_factory();
+        let refs = HashSet::new();
+
+        assert_eq!(gh.get_new_branch_name(&refs, "!!!", None), "spr/foo/patch");
+        assert_eq!(gh.get_new_branch_name(&refs, "", None), "spr/foo/patch");
+        assert_eq!(
+            gh.get_base_branch_name(&refs, "🚀", None),
+            "spr/foo/master.patch"
+        );
+    }
+
+    #[test]
+    fn test_get_new_branch_name_empty_slug_fallback() {
+        let mut gh = config_factory();
+        let refs = HashSet::new();
+
+        gh.empty_slug_fallback = "change".parse().unwrap();
+        assert_eq!(gh.get_new_branch_name(&refs, "?!", None), "spr/foo/change");
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "--- ...", None),
+            "spr/foo/change"
+        );
+
+        gh.empty_slug_fallback = "Hash".parse().unwrap();
+        let hashed = gh.get_new_branch_name(&refs, "?!", None);
+        let slug = hashed.strip_prefix("spr/foo/").unwrap();
+        assert_eq!(slug.len(), 8);
+        assert!(slug.chars().all(|c| c.is_ascii_hexdigit()));
+        // The same title gets the same slug, and another one another
+        assert_eq!(gh.get_new_branch_name(&refs, "?!", None), hashed);
+        assert_ne!(gh.get_new_branch_name(&refs, "!!!", None), hashed);
+        // Titles with a slug keep it
+        assert_eq!(gh.get_new_branch_name(&refs, "Fix", None), "spr/foo/fix");
+
+        assert_eq!(
+            "patch".parse::<EmptySlugFallback>().unwrap(),
+            EmptySlugFallback::default()
+        );
+        assert!("".parse::<EmptySlugFallback>().is_err());
+        assert!("My Patch".parse::<EmptySlugFallback>().is_err());
+    }
+
+    #[test]
+    fn test_get_new_branch_name_taken() {
+        let gh = config_factory();
+        let refs: HashSet<String> = [
+            "refs/remotes/origin/spr/foo/hello".to_string(),
+            "refs/remotes/origin/spr/foo/hello-1".to_string(),
+        ]
+        .into();
+
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "Hello", None),
+            "spr/foo/hello-2"
+        );
+    }
+
+    #[test]
+    fn test_get_new_branch_name_strip_pattern() {
+        let mut gh = config_factory();
+        gh.branch_slug_strip_pattern =
+            Some(lazy_regex::Regex::new(r"[A-Z]+-\d+:").unwrap());
+        let refs = HashSet::new();
+
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "PROJ-123: Add feature", None),
+            "spr/foo/add-feature"
+        );
+        // Only a leading match is removed
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "Fix PROJ-123: crash", None),
+            "spr/foo/fix-proj-123-crash"
+        );
+        // If nothing is left, we fall back on the default slug
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "PROJ-123:", None),
+            "spr/foo/patch"
+        );
+        assert_eq!(
+            gh.get_base_branch_name(&refs, "PROJ-123: Add feature", None),
+            "spr/foo/master.add-feature"
+        );
+    }
+
+    #[test]
+    fn test_get_new_branch_name_keep_dots() {
+        let mut gh = config_factory();
+        let refs = HashSet::new();
+
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "Bump to 1.2.3", None),
+            "spr/foo/bump-to-123"
+        );
+
+        gh.branch_slug_keep_dots = true;
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "Bump to 1.2.3", None),
+            "spr/foo/bump-to-1.2.3"
+        );
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "Release foo.lock", None),
+            "spr/foo/release-foo-lock"
+        );
+    }
+
+    #[test]
+    fn test_get_new_branch_name_normalize_typography() {
+        let mut gh = config_factory();
+        let refs = HashSet::new();
+        let title =
+            "Handle \u{201C}quoted\u{201D} names\u{2014}properly\u{2026}";
+
+        assert_eq!(
+            gh.get_new_branch_name(&refs, title, None),
+            "spr/foo/handle-quoted-namesproperly"
+        );
+        gh.normalize_typography = true;
+        assert_eq!(
+            gh.get_new_branch_name(&refs, title, None),
+            "spr/foo/handle-quoted-names-properly"
+        );
+    }
+
+    #[test]
+    fn test_get_new_branch_name_date_prefix() {
+        let mut gh = config_factory();
+        let refs = HashSet::new();
+        let date =
+            DateTime::parse_from_rfc3339("2024-06-01T23:30:00-02:00").unwrap();
+
+        // Without spr.branchDateFormat, the date makes no difference
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "My slug", Some(&date)),
+            "spr/foo/my-slug"
+        );
+
+        gh.branch_date_format = Some("%Y-%m-%d".into());
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "My slug", Some(&date)),
+            "spr/foo/2024-06-01-my-slug"
+        );
+        gh.branch_date_format = Some("%Y%m%d%H%M".into());
+        assert_eq!(
+            gh.get_new_branch_name(&refs, "My slug", Some(&date)),
+            "spr/foo/202406012330-my-slug"
+        );
+        assert_eq!(
+            gh.get_base_branch_name(&refs, "My slug", Some(&date)),
+            "spr/foo/master.202406012330-my-slug"
+        );
+    }
+
+    #[test]
+    fn test_validate_branch_date_format() {
+        assert!(validate_branch_date_format("%Y-%m-%d").is_ok());
+        assert!(validate_branch_date_format("%y%m%d").is_ok());
+
+        assert!(validate_branch_date_format("").is_err());
+        assert!(validate_branch_date_format("%Y-%Q").is_err());
+        // Colons and spaces cannot go into branch names
+        assert!(validate_branch_date_format("%H:%M").is_err());
+        assert!(validate_branch_date_format("%Y %m").is_err());
+    }
+
+    #[test]
+    fn test_open_git_config_explicit_path() {
+        let (dir, git) = crate::git::tests::test_repo();
+        git.repo()
+            .config()
+            .unwrap()
+            .set_str("spr.githubRepository", "acme/discovered")
+            .unwrap();
+        let path = dir.path().join("spr.config");
+        std::fs::write(&path, "[spr]\n\tbranchPrefix = spr/explicit/\n")
+            .unwrap();
+
+        let config = open_git_config(&git.repo(), Some(&path)).unwrap();
+        assert_eq!(
+            config.get_string("spr.branchPrefix").unwrap(),
+            "spr/explicit/"
+        );
+        // Nothing is read from the repository's configuration
+        assert!(config.get_string("spr.githubRepository").is_err());
+
+        let config = open_git_config(&git.repo(), None).unwrap();
+        assert_eq!(
+            config.get_string("spr.githubRepository").unwrap(),
+            "acme/discovered"
+        );
+    }
+
+    #[test]
+    fn test_open_git_config_missing_file() {
+        let (dir, git) = crate::git::tests::test_repo();
+        let path = dir.path().join("missing.config");
+
+        let error = match open_git_config(&git.repo(), Some(&path)) {
+            Ok(_) => panic!("a missing config file must be an error"),
+            Err(error) => error,
+        };
+        assert_eq!(
+            error.messages(),
+            &[format!("Config file {} does not exist", path.display())]
+        );
+    }
+
+    fn profile_git_config(dir: &tempfile::TempDir) -> git2::Config {
+        let path = dir.path().join("spr.config");
+        std::fs::write(
+            &path,
+            indoc::indoc!(
+                "
+                [spr]
+                    githubAuthToken = default-token
+                    branchPrefix = spr/me/
+                    trailerSection = Jira=Ticket
+                [spr \"work\"]
+                    githubAuthToken = work-token
+                    githubHost = github.example.com
+                    trailerSection = Risk=Risk
+                    trailerSection = Jira=Ticket
+                [spr \"personal\"]
+                    githubAuthToken = personal-token
+                "
+            ),
+        )
+        .unwrap();
+
+        git2::Config::open(&path).unwrap()
+    }
+
+    #[test]
+    fn test_profile_config_selects_profile() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let work =
+            ProfileConfig::new(profile_git_config(&dir), Some("work".into()))
+                .unwrap();
+        assert_eq!(
+            work.get_string("spr.githubAuthToken").unwrap(),
+            "work-token"
+        );
+        assert_eq!(
+            work.get_string("spr.githubHost").unwrap(),
+            "github.example.com"
+        );
+        // Taken from outside the profile
+        assert_eq!(work.get_string("spr.branchPrefix").unwrap(), "spr/me/");
+        assert_eq!(
+            work.get_multivar("spr.trailerSection").unwrap(),
+            vec!["Risk=Risk", "Jira=Ticket"]
+        );
+
+        let personal = ProfileConfig::new(
+            profile_git_config(&dir),
+            Some("personal".into()),
+        )
+        .unwrap();
+        assert_eq!(
+            personal.get_string("spr.githubAuthToken").unwrap(),
+            "personal-token"
+        );
+        assert!(personal.get_string("spr.githubHost").is_err());
+        assert_eq!(
+            personal.get_multivar("spr.trailerSection").unwrap(),
+            vec!["Jira=Ticket"]
+        );
+
+        let none = ProfileConfig::new(profile_git_config(&dir), None).unwrap();
+        assert_eq!(
+            none.get_string("spr.githubAuthToken").unwrap(),
+            "default-token"
+        );
+    }
+
+    #[test]
+    fn test_profile_config_unknown_profile() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for name in ["home", "wo", "work.githubHost"] {
+            assert!(ProfileConfig::new(
+                profile_git_config(&dir),
+                Some(name.into())
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut git_config = profile_git_config(&dir);
+        assert_eq!(select_profile(None, None, &git_config), None);
+
+        git_config.set_str("spr.profile", "personal").unwrap();
+        let select = |flag: Option<&str>, env: Option<&str>| {
+            select_profile(
+                flag.map(Into::into),
+                env.map(Into::into),
+                &git_config,
+            )
+        };
+        assert_eq!(select(None, None).as_deref(), Some("personal"));
+        assert_eq!(select(None, Some("work")).as_deref(), Some("work"));
+        assert_eq!(select(Some("home"), Some("work")).as_deref(), Some("home"));
+        // An empty environment variable counts as not set
+        assert_eq!(select(None, Some("")).as_deref(), Some("personal"));
+    }
+
+    #[test]
+    fn test_open_git_config_includes() {
+        let (dir, git) = crate::git::tests::test_repo();
+        let included = dir.path().join("included.config");
+        std::fs::write(&included, "[spr]\n\tgithubAuthToken = included\n")
+            .unwrap();
+        let conditional = dir.path().join("conditional.config");
+        std::fs::write(&conditional, "[spr]\n\tgithubHost = example.com\n")
+            .unwrap();
+        // Matches any repository in the directory (and so this one)
+        let gitdir = format!("{}/", dir.path().display());
+
+        let repo_config = git.repo().path().join("config");
+        let mut text = std::fs::read_to_string(&repo_config).unwrap();
+        text.push_str(&format!(
+            "[include]\n\tpath = {}\n[includeIf \"gitdir:{}\"]\n\tpath = {}\n",
+            included.display(),
+            gitdir,
+            conditional.display()
+        ));
+        std::fs::write(&repo_config, text).unwrap();
+
+        let config = open_git_config(&git.repo(), None).unwrap();
+        assert_eq!(
+            config.get_string("spr.githubAuthToken").unwrap(),
+            "included"
+        );
+        assert_eq!(config.get_string("spr.githubHost").unwrap(), "example.com");
+
+        let explicit = dir.path().join("spr.config");
+        std::fs::write(
+            &explicit,
+            format!("[include]\n\tpath = {}\n", included.display()),
+        )
+        .unwrap();
+        let config = open_git_config(&git.repo(), Some(&explicit)).unwrap();
+        assert_eq!(
+            config.get_string("spr.githubAuthToken").unwrap(),
+            "included"
+        );
+    }
 }
@@ -0,0 +1,365 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! The API of the service hosting the repository (the "forge") that spr
+//! submits Pull Requests to. GitHub (see [`crate::github::GitHub`]) is the only
+//! implementation so far, but commands that go through this trait work with any
+//! host that can provide it.
+
+use crate::{
+    error::Result,
+    github::{
+        MergeRequest, Project, PullRequest, PullRequestChecks,
+        PullRequestMergeability, PullRequestRequestReviewers,
+        PullRequestUpdate,
+    },
+    message::MessageSectionsMap,
+};
+
+/// How a Pull Request is merged into its base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMethod {
+    /// With a merge commit
+    Merge,
+    /// As a single commit with all its changes
+    Squash,
+    /// By rebasing its commits onto the base
+    Rebase,
+}
+
+/// What came of asking to merge a Pull Request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeOutcome {
+    pub merged: bool,
+    /// The commit that merging created on the base branch
+    pub sha: Option<String>,
+    /// Why the Pull Request was not merged, if the forge said
+    pub message: Option<String>,
+}
+
+// spr awaits these futures on the thread that creates them, so they do not need
+// to be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait Forge {
+    /// Fetch the given Pull Requests, running at most `spr.concurrency` queries
+    /// at the same time. The results are in the order of `numbers`, and there
+    /// is `None` for every `None` in `numbers`.
+    async fn get_pull_requests(
+        &self,
+        numbers: impl IntoIterator<Item = Option<u64>>,
+    ) -> Vec<Result<Option<PullRequest>>>;
+
+    async fn get_pull_request(&self, number: u64) -> Result<PullRequest>;
+
+    /// Create a Pull Request with the title and body of `message`, and return
+    /// its number.
+    async fn create_pull_request(
+        &self,
+        message: &MessageSectionsMap,
+        base_ref_name: String,
+        head_ref_name: String,
+        draft: bool,
+    ) -> Result<u64>;
+
+    async fn update_pull_request(
+        &self,
+        number: u64,
+        updates: &PullRequestUpdate,
+    ) -> Result<()>;
+
+    async fn request_reviewers(
+        &self,
+        number: u64,
+        reviewers: PullRequestRequestReviewers,
+    ) -> Result<()>;
+
+    async fn add_assignees(
+        &self,
+        number: u64,
+        assignees: Vec<String>,
+    ) -> Result<()>;
+
+    async fn add_labels(&self, number: u64, labels: Vec<String>) -> Result<()>;
+
+    async fn merge_pull_request(
+        &self,
+        number: u64,
+        request: &MergeRequest,
+    ) -> Result<MergeOutcome>;
+
+    /// Whether the Pull Request can be merged into its base, as far as the
+    /// forge has worked that out yet.
+    async fn get_pull_request_mergeability(
+        &self,
+        number: u64,
+    ) -> Result<PullRequestMergeability>;
+
+    /// The state of the checks on the head commit of the Pull Request.
+    async fn get_pull_request_checks(
+        &self,
+        number: u64,
+    ) -> Result<PullRequestChecks>;
+
+    /// The login of the user spr acts as.
+    async fn current_user_login(&self) -> Result<String>;
+
+    /// Take the draft Pull Request out of draft, so that it is ready for
+    /// review.
+    async fn mark_ready_for_review(
+        &self,
+        pull_request: &PullRequest,
+    ) -> Result<()>;
+
+    /// The project of the owner of the repository with the given number or
+    /// title (see [`crate::github::resolve_project`]).
+    async fn find_project(&self, name: &str) -> Result<Project>;
+
+    /// Add the Pull Request to the project as an item.
+    async fn add_to_project(
+        &self,
+        project: &Project,
+        pull_request: &PullRequest,
+    ) -> Result<()>;
+
+    /// Rename a branch of the repository. The open Pull Requests with the
+    /// branch as their head or base get the new name, too.
+    async fn rename_branch(&self, from: &str, to: &str) -> Result<()>;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{cell::RefCell, collections::HashMap};
+
+    use super::*;
+    use crate::{error::Error, message::build_github_title};
+
+    /// A call made to a [`MockForge`].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Call {
+        GetPullRequest(u64),
+        CreatePullRequest {
+            title: Option<String>,
+            base: String,
+            head: String,
+            draft: bool,
+        },
+        UpdatePullRequest(u64, PullRequestUpdate),
+        RequestReviewers(u64, PullRequestRequestReviewers),
+        AddAssignees(u64, Vec<String>),
+        AddLabels(u64, Vec<String>),
+        MergePullRequest(u64),
+        GetPullRequestMergeability(u64),
+        GetPullRequestChecks(u64),
+        CurrentUserLogin,
+        MarkReadyForReview(u64),
+        FindProject(String),
+        /// The number of the project, and of the Pull Request
+        AddToProject(u64, u64),
+        RenameBranch(String, String),
+    }
+
+    /// A forge that records the calls made to it. It knows the Pull Requests
+    /// it is given, and creates new ones with numbers counting up from
+    /// `next_number`.
+    #[derive(Default)]
+    pub struct MockForge {
+        pub pull_requests: RefCell<HashMap<u64, PullRequest>>,
+        pub next_number: RefCell<u64>,
+        pub calls: RefCell<Vec<Call>>,
+        /// The projects of the owner of the repository
+        pub projects: Vec<Project>,
+    }
+
+    impl MockForge {
+        pub fn new(next_number: u64, pull_requests: Vec<PullRequest>) -> Self {
+            Self {
+                pull_requests: RefCell::new(
+                    pull_requests
+                        .into_iter()
+                        .map(|pr| (pr.number, pr))
+                        .collect(),
+                ),
+                next_number: RefCell::new(next_number),
+                calls: Default::default(),
+                projects: Vec::new(),
+            }
+        }
+
+        pub fn calls(&self) -> Vec<Call> {
+            self.calls.borrow().clone()
+        }
+
+        fn record(&self, call: Call) {
+            self.calls.borrow_mut().push(call);
+        }
+    }
+
+    impl Forge for MockForge {
+        async fn get_pull_requests(
+            &self,
+            numbers: impl IntoIterator<Item = Option<u64>>,
+        ) -> Vec<Result<Option<PullRequest>>> {
+            let mut result = Vec::new();
+            for number in numbers {
+                result.push(match number {
+                    Some(number) => {
+                        self.get_pull_request(number).await.map(Some)
+                    }
+                    None => Ok(None),
+                });
+            }
+            result
+        }
+
+        async fn get_pull_request(&self, number: u64) -> Result<PullRequest> {
+            self.record(Call::GetPullRequest(number));
+            self.pull_requests
+                .borrow()
+                .get(&number)
+                .cloned()
+                .ok_or_else(|| {
+                    Error::new(format!("No Pull Request #{}", number))
+                })
+        }
+
+        async fn create_pull_request(
+            &self,
+            message: &MessageSectionsMap,
+            base_ref_name: String,
+            head_ref_name: String,
+            draft: bool,
+        ) -> Result<u64> {
+            let config = crate::config::tests::config_factory();
+            let title = build_github_title(message, &config);
+            self.record(Call::CreatePullRequest {
+                title: title.clone(),
+                base: base_ref_name.clone(),
+                head: head_ref_name.clone(),
+                draft,
+            });
+
+            let number = self.next_number.replace_with(|number| *number + 1);
+            let mut pull_request = crate::github::tests::pull_request_factory(
+                &config,
+                number,
+                &head_ref_name,
+                &base_ref_name,
+            );
+            pull_request.title = title.unwrap_or_default();
+            self.pull_requests.borrow_mut().insert(number, pull_request);
+
+            Ok(number)
+        }
+
+        async fn update_pull_request(
+            &self,
+            number: u64,
+            updates: &PullRequestUpdate,
+        ) -> Result<()> {
+            self.record(Call::UpdatePullRequest(number, updates.clone()));
+            Ok(())
+        }
+
+        async fn request_reviewers(
+            &self,
+            number: u64,
+            reviewers: PullRequestRequestReviewers,
+        ) -> Result<()> {
+            self.record(Call::RequestReviewers(number, reviewers));
+            Ok(())
+        }
+
+        async fn add_assignees(
+            &self,
+            number: u64,
+            assignees: Vec<String>,
+        ) -> Result<()> {
+            self.record(Call::AddAssignees(number, assignees));
+            Ok(())
+        }
+
+        async fn add_labels(
+            &self,
+            number: u64,
+            labels: Vec<String>,
+        ) -> Result<()> {
+            self.record(Call::AddLabels(number, labels));
+            Ok(())
+        }
+
+        async fn merge_pull_request(
+            &self,
+            number: u64,
+            _request: &MergeRequest,
+        ) -> Result<MergeOutcome> {
+            self.record(Call::MergePullRequest(number));
+            Err(Error::new("MockForge does not merge Pull Requests"))
+        }
+
+        async fn get_pull_request_mergeability(
+            &self,
+            number: u64,
+        ) -> Result<PullRequestMergeability> {
+            self.record(Call::GetPullRequestMergeability(number));
+            let pull_request =
+                self.pull_requests.borrow().get(&number).cloned();
+            let pull_request = pull_request.ok_or_else(|| {
+                Error::new(format!("No Pull Request #{}", number))
+            })?;
+            Ok(PullRequestMergeability {
+                base: pull_request.base,
+                head_oid: pull_request.head_oid,
+                mergeable: Some(true),
+                merge_commit: None,
+            })
+        }
+
+        async fn get_pull_request_checks(
+            &self,
+            number: u64,
+        ) -> Result<PullRequestChecks> {
+            self.record(Call::GetPullRequestChecks(number));
+            Err(Error::new("MockForge does not run checks"))
+        }
+
+        async fn current_user_login(&self) -> Result<String> {
+            self.record(Call::CurrentUserLogin);
+            Ok("me".to_string())
+        }
+
+        async fn mark_ready_for_review(
+            &self,
+            pull_request: &PullRequest,
+        ) -> Result<()> {
+            self.record(Call::MarkReadyForReview(pull_request.number));
+            Ok(())
+        }
+
+        async fn find_project(&self, name: &str) -> Result<Project> {
+            self.record(Call::FindProject(name.to_string()));
+            crate::github::resolve_project(self.projects.clone(), name, "acme")
+        }
+
+        async fn add_to_project(
+            &self,
+            project: &Project,
+            pull_request: &PullRequest,
+        ) -> Result<()> {
+            self.record(Call::AddToProject(
+                project.number,
+                pull_request.number,
+            ));
+            Ok(())
+        }
+
+        async fn rename_branch(&self, from: &str, to: &str) -> Result<()> {
+            self.record(Call::RenameBranch(from.to_string(), to.to_string()));
+            Ok(())
+        }
+    }
+}
@@ -5,19 +5,304 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use std::collections::{HashSet, VecDeque};
+use std::{
+    collections::{HashSet, VecDeque},
+    path::Path,
+    process::Stdio,
+};
 
 use crate::{
     config::Config,
     error::{Error, Result, ResultExt},
-    github::GitHubBranch,
+    github::{CommitIdentity, GitHubBranch},
     message::{
         build_commit_message, parse_message, MessageSection, MessageSectionsMap,
     },
-    utils::{parse_pr_stack_list, run_command},
+    path_filter::PathFilter,
+    utils::{parse_pr_stack_entries, run_command},
 };
 use git2::Oid;
 
+/// A version of Git, as `git --version` prints it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GitVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+/// The oldest Git that spr works with: `git worktree add --detach` (for
+/// spr.commitHook) needs Git 2.5, and `git push --atomic` Git 2.4.
+pub const MINIMUM_GIT_VERSION: GitVersion = GitVersion {
+    major: 2,
+    minor: 5,
+    patch: 0,
+};
+
+impl std::fmt::Display for GitVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl std::str::FromStr for GitVersion {
+    type Err = crate::error::Error;
+
+    /// Parse a version like `2.39.2`, or the output of `git --version`
+    /// (`git version 2.39.3 (Apple Git-145)`, `git version
+    /// 2.45.1.windows.1`). A missing minor or patch version counts as 0.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::new(format!("Invalid Git version: '{}'", s));
+        let version = s.trim();
+        let version = version.strip_prefix("git version").unwrap_or(version);
+        let mut numbers = version
+            .split_whitespace()
+            .next()
+            .ok_or_else(invalid)?
+            .split('.')
+            .map(|part| {
+                let digits = part
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(part, |end| &part[..end]);
+                digits.parse::<u32>().ok()
+            });
+
+        let major = numbers.next().flatten().ok_or_else(invalid)?;
+        let mut next = || numbers.next().flatten().unwrap_or(0);
+        Ok(GitVersion {
+            major,
+            minor: next(),
+            patch: next(),
+        })
+    }
+}
+
+/// The version of the `git` that spr runs.
+pub fn installed_git_version() -> Result<GitVersion> {
+    let output = std::process::Command::new("git")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::new("git --version failed"));
+    }
+
+    String::from_utf8_lossy(&output.stdout).parse()
+}
+
+/// Fail if the installed Git is older than `minimum`. If spr cannot tell
+/// which version it is, it only warns.
+pub fn check_git_version(minimum: GitVersion) -> Result<()> {
+    let installed = match installed_git_version() {
+        Ok(installed) => installed,
+        Err(error) => {
+            return crate::output::output(
+                "⚠️",
+                &format!("Could not find out the version of Git: {}", error),
+            )
+        }
+    };
+    if installed < minimum {
+        return Err(Error::new(format!(
+            "spr needs Git {} or newer, but the installed Git is {}. Please \
+             upgrade Git (or lower spr.minimumGitVersion, at your own risk).",
+            minimum, installed
+        )));
+    }
+
+    Ok(())
+}
+
+/// The number of added and deleted lines of a change, as `git diff --numstat`
+/// counts them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffStat {
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+/// A change of the commit that a submodule points to. Such an entry in a tree
+/// (a "gitlink") has no content of its own to diff, so it is shown the way
+/// `git diff --submodule=short` shows it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubmoduleChange {
+    pub path: String,
+    /// The commit before the change, or `None` if the submodule was added
+    pub old: Option<Oid>,
+    /// The commit after the change, or `None` if the submodule was removed
+    pub new: Option<Oid>,
+}
+
+impl SubmoduleChange {
+    /// The submodule change of the given delta, or `None` if neither side of
+    /// it is a gitlink.
+    fn from_delta(delta: &git2::DiffDelta) -> Option<Self> {
+        let side = |file: git2::DiffFile| {
+            (file.mode() == git2::FileMode::Commit).then(|| file.id())
+        };
+        let old = side(delta.old_file());
+        let new = side(delta.new_file());
+        if old.is_none() && new.is_none() {
+            return None;
+        }
+
+        let path = delta
+            .new_file()
+            .path()
+            .or_else(|| delta.old_file().path())?;
+        Some(Self {
+            path: path.to_string_lossy().into_owned(),
+            old,
+            new,
+        })
+    }
+}
+
+impl std::fmt::Display for SubmoduleChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let short = |oid: Option<Oid>| {
+            let oid = oid.unwrap_or_else(Oid::zero).to_string();
+            oid[..7].to_string()
+        };
+        write!(
+            f,
+            "Submodule {}: {}..{}",
+            self.path,
+            short(self.old),
+            short(self.new)
+        )
+    }
+}
+
+/// Whether the commit with the given message was made by spr, for a Pull
+/// Request branch or a base branch.
+pub fn is_created_by_spr(message: &str) -> bool {
+    message.contains("\n\nCreated using spr ")
+}
+
+/// The name of the default branch of the given remote, for when it is not
+/// configured in `spr.githubMasterBranch`.
+///
+/// This is the branch `refs/remotes/<remote>/HEAD` points to, which `git clone`
+/// (or `git remote set-head`) sets up. Without it, we go with `master`, if the
+/// remote has such a branch.
+pub fn remote_default_branch(
+    repo: &git2::Repository,
+    remote_name: &str,
+) -> Result<String> {
+    let prefix = format!("refs/remotes/{}/", remote_name);
+
+    if let Ok(head) = repo.find_reference(&format!("{}HEAD", prefix)) {
+        let branch = head.symbolic_target().and_then(|target| {
+            // A freshly created, empty remote has a HEAD pointing to a branch
+            // that does not exist yet.
+            repo.find_reference(target).ok()?;
+            target.strip_prefix(&prefix).map(String::from)
+        });
+        if let Some(branch) = branch {
+            return Ok(branch);
+        }
+    } else if repo.find_reference(&format!("{}master", prefix)).is_ok() {
+        return Ok("master".to_string());
+    }
+
+    Err(Error::new(format!(
+        "Remote '{}' has no default branch; push an initial commit or set \
+         spr.githubMasterBranch in the Git config",
+        remote_name
+    )))
+}
+
+/// The name of the branch on the given remote that the checked-out branch
+/// tracks (`@{upstream}`), for `--base-auto`. `None` if nothing is checked out,
+/// or the branch has no upstream, or its upstream is not on that remote.
+pub fn upstream_branch(
+    repo: &git2::Repository,
+    remote_name: &str,
+) -> Option<String> {
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    let upstream = repo.branch_upstream_name(head.name()?).ok()?;
+    let prefix = format!("refs/remotes/{}/", remote_name);
+
+    upstream.as_str()?.strip_prefix(&prefix).map(String::from)
+}
+
+/// A remote of the local repository that points to a repository on GitHub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHubRemote {
+    pub name: String,
+    pub owner: String,
+    pub repo: String,
+}
+
+impl std::fmt::Display for GitHubRemote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}/{})", self.name, self.owner, self.repo)
+    }
+}
+
+/// The remotes of the repository that point to repositories on the given
+/// GitHub host, in the order Git lists them.
+pub fn github_remotes(
+    repo: &git2::Repository,
+    github_host: &str,
+) -> Result<Vec<GitHubRemote>> {
+    let names = repo.remotes()?;
+    let remotes = names
+        .iter()
+        .flatten()
+        .filter_map(|name| {
+            let remote = repo.find_remote(name).ok()?;
+            let (owner, repo) =
+                crate::config::parse_github_remote(github_host, remote.url()?)?;
+            Some(GitHubRemote {
+                name: name.to_string(),
+                owner,
+                repo,
+            })
+        })
+        .collect();
+
+    Ok(remotes)
+}
+
+/// The remote to use, when `spr.githubRemoteName` does not say, out of the
+/// given GitHub remotes: the only one, if there is just one. If there are
+/// several, the user picks one, or, if nobody is there to ask, this fails
+/// listing them.
+pub fn choose_github_remote(
+    mut remotes: Vec<GitHubRemote>,
+    interactive: bool,
+) -> Result<Option<GitHubRemote>> {
+    if remotes.len() < 2 {
+        return Ok(remotes.pop());
+    }
+
+    let candidates: Vec<String> =
+        remotes.iter().map(|remote| remote.to_string()).collect();
+    if !interactive {
+        return Err(Error::new(format!(
+            "More than one remote points to GitHub: {}. Set \
+             spr.githubRemoteName to the one to use.",
+            candidates.join(", ")
+        )));
+    }
+
+    let index = dialoguer::Select::new()
+        .with_prompt(
+            "More than one remote points to GitHub - which one should spr \
+             use? (Set spr.githubRemoteName to not be asked again.)",
+        )
+        .items(&candidates)
+        .default(0)
+        .interact()?;
+
+    Ok(Some(remotes.swap_remove(index)))
+}
+
 #[derive(Debug, Clone)]
 pub struct PreparedCommit {
     pub oid: Oid,
@@ -26,12 +311,82 @@ pub struct PreparedCommit {
     pub pr_stack: Option<Vec<u64>>,
     pub message: MessageSectionsMap,
     pub pull_request_number: Option<u64>,
+    /// For a group of commits submitted as one Pull Request, the commits of
+    /// the group, bottom first (see `commit_group`). Empty otherwise.
+    pub squashed: Vec<PreparedCommit>,
+}
+
+/// The position of the commit belonging to the given Pull Request in `commits`.
+pub fn find_pull_request_commit(
+    commits: &[PreparedCommit],
+    pull_request_number: u64,
+) -> Option<usize> {
+    commits.iter().position(|commit| {
+        commit.pull_request_number == Some(pull_request_number)
+    })
+}
+
+/// Copy the note in `notes_ref` on commit `from`, if there is one, to the
+/// commit `to`, which replaces it.
+fn copy_note(
+    repo: &git2::Repository,
+    notes_ref: &str,
+    from: Oid,
+    to: Oid,
+) -> Result<()> {
+    if let Ok(note) = repo.find_note(Some(notes_ref), from) {
+        repo.note(
+            &note.author(),
+            &note.committer(),
+            Some(notes_ref),
+            to,
+            note.message().unwrap_or_default(),
+            true,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Run git with the given arguments in `dir`.
+async fn run_git(dir: &Path, args: &[&str]) -> Result<()> {
+    let mut command = tokio::process::Command::new("git");
+    command.arg("-C").arg(dir).args(args);
+
+    run_command(&mut command)
+        .await
+        .reword(format!("git {} failed", args.join(" ")))
+}
+
+/// Run git with the given arguments in `dir`, and return what it prints.
+async fn git_output(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stderr(Stdio::inherit())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(Error::new(format!("git {} failed", args.join(" "))));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The signature for notes spr writes: the user's, or a stand-in if there is
+/// no user configured in Git, since that is no reason to fail.
+fn note_signature(repo: &git2::Repository) -> Result<git2::Signature<'static>> {
+    Ok(repo
+        .signature()
+        .or_else(|_| git2::Signature::now("spr", "spr@localhost"))?)
 }
 
 #[derive(Clone)]
 pub struct Git {
     repo: std::sync::Arc<std::sync::Mutex<git2::Repository>>,
     hooks: std::sync::Arc<std::sync::Mutex<git2_ext::hooks::Hooks>>,
+    notes_ref: String,
 }
 
 impl Git {
@@ -41,14 +396,22 @@ impl Git {
                 git2_ext::hooks::Hooks::with_repo(&repo).unwrap(),
             )),
             repo: std::sync::Arc::new(std::sync::Mutex::new(repo)),
+            notes_ref: crate::config::DEFAULT_NOTES_REF.to_string(),
         }
     }
 
-    pub fn repo(&self) -> std::sync::MutexGuard<git2::Repository> {
+    /// Use the given notes ref for the notes recording the Pull Requests of
+    /// commits (see `spr.pullRequestTracking`).
+    pub fn with_notes_ref(mut self, notes_ref: &str) -> Self {
+        self.notes_ref = notes_ref.to_string();
+        self
+    }
+
+    pub fn repo(&self) -> std::sync::MutexGuard<'_, git2::Repository> {
         self.repo.lock().expect("poisoned mutex")
     }
 
-    fn hooks(&self) -> std::sync::MutexGuard<git2_ext::hooks::Hooks> {
+    fn hooks(&self) -> std::sync::MutexGuard<'_, git2_ext::hooks::Hooks> {
         self.hooks.lock().expect("poisoned mutex")
     }
 
@@ -57,21 +420,218 @@ impl Git {
         let mut walk = repo.revwalk()?;
         walk.set_sorting(git2::Sort::TOPOLOGICAL.union(git2::Sort::REVERSE))?;
         walk.push_head()?;
+        walk.hide_ref(master_ref).reword(format!(
+            "{} does not exist; push an initial commit to the remote, or run \
+             `git fetch` if it has one",
+            master_ref
+        ))?;
+
+        Ok(walk.collect::<std::result::Result<Vec<Oid>, _>>()?)
+    }
+
+    /// The number of commits on `tip_ref` that are not on `master_ref`.
+    pub fn count_commits(
+        &self,
+        tip_ref: &str,
+        master_ref: &str,
+    ) -> Result<usize> {
+        let repo = self.repo();
+        let mut walk = repo.revwalk()?;
+        walk.push_ref(tip_ref)?;
         walk.hide_ref(master_ref)?;
 
+        Ok(walk.count())
+    }
+
+    /// The `Co-authored-by` trailers of the commits of the Pull Request branch
+    /// at `head_oid` that are not on `target_oid`, oldest first and each one
+    /// once. Only first parents are followed, which leaves out the commits
+    /// merged in from the base branch.
+    pub fn co_author_trailers(
+        &self,
+        head_oid: Oid,
+        target_oid: Oid,
+    ) -> Result<Vec<String>> {
+        let repo = self.repo();
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL.union(git2::Sort::REVERSE))?;
+        walk.simplify_first_parent()?;
+        walk.push(head_oid)?;
+        walk.hide(target_oid)?;
+
+        let mut trailers = Vec::new();
+        for oid in walk {
+            let commit = repo.find_commit(oid?)?;
+            for line in commit.message().unwrap_or_default().lines() {
+                let line = line.trim();
+                if lazy_regex::regex_is_match!(r#"^co-authored-by\s*:"#i, line)
+                    && !trailers.iter().any(|trailer| trailer == line)
+                {
+                    trailers.push(line.to_string());
+                }
+            }
+        }
+
+        Ok(trailers)
+    }
+
+    /// The branch being rebased, if a `git rebase` is in progress.
+    pub fn rebased_branch(&self) -> Option<String> {
+        let path = self.repo().path().to_path_buf();
+        ["rebase-merge", "rebase-apply"].iter().find_map(|dir| {
+            let name =
+                std::fs::read_to_string(path.join(dir).join("head-name"))
+                    .ok()?;
+            let name = name.trim();
+            name.starts_with("refs/heads/").then(|| name.to_string())
+        })
+    }
+
+    /// The commits on top of `bottom` up to HEAD (as `git log bottom..HEAD`
+    /// lists them, but bottom first). Fails if `bottom` is not an ancestor of
+    /// HEAD.
+    pub fn get_commit_oids_from(&self, bottom: &str) -> Result<Vec<Oid>> {
+        let repo = self.repo();
+        let bottom_oid = repo
+            .revparse_single(bottom)
+            .and_then(|object| object.peel_to_commit())
+            .reword(format!("{} does not name a commit", bottom))?
+            .id();
+        let head_oid = repo.head()?.peel_to_commit()?.id();
+        if head_oid != bottom_oid
+            && !repo.graph_descendant_of(head_oid, bottom_oid)?
+        {
+            return Err(Error::new(format!(
+                "{} is not an ancestor of HEAD, so it cannot be the bottom of \
+                 the stack",
+                bottom
+            )));
+        }
+
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL.union(git2::Sort::REVERSE))?;
+        walk.push(head_oid)?;
+        walk.hide(bottom_oid)?;
+
         Ok(walk.collect::<std::result::Result<Vec<Oid>, _>>()?)
     }
 
+    /// The commit with the given ID (or an unambiguous abbreviation of it),
+    /// which must exist in the local repository. Nothing is fetched.
+    pub fn find_local_commit(&self, sha: &str) -> Result<Oid> {
+        let sha = sha.to_ascii_lowercase();
+        if sha.len() < 4 || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::new(format!("'{}' is not a commit ID", sha)));
+        }
+
+        self.repo()
+            .revparse_single(&sha)
+            .and_then(|object| object.peel_to_commit())
+            .ok()
+            .map(|commit| commit.id())
+            // Not a branch or tag that happens to look like a commit ID
+            .filter(|oid| oid.to_string().starts_with(&sha))
+            .ok_or_else(|| {
+                Error::new(format!(
+                    "Commit {} does not exist in the local repository; fetch \
+                     it first",
+                    sha
+                ))
+            })
+    }
+
+    /// Whether there is nothing to submit on top of `base` (a revision, like
+    /// `refs/remotes/origin/master` or a commit ID): HEAD is `base`, or a
+    /// commit that `base` contains already.
+    pub fn is_empty_range(&self, base: &str) -> Result<bool> {
+        let head_oid = self.head()?;
+        let repo = self.repo();
+        let base_oid = repo
+            .revparse_single(base)
+            .and_then(|object| object.peel_to_commit())
+            .reword(format!("{} does not name a commit", base))?
+            .id();
+
+        Ok(head_oid == base_oid
+            || repo.graph_descendant_of(base_oid, head_oid)?)
+    }
+
     pub fn get_prepared_commits(
         &self,
         config: &Config,
     ) -> Result<Vec<PreparedCommit>> {
-        self.get_commit_oids(config.master_ref.local())?
-            .into_iter()
+        let oids = self.get_commit_oids(config.master_ref.local())?;
+        self.check_no_merge_commits(&oids, config)?;
+        oids.into_iter()
             .map(|oid| self.prepare_commit(config, oid))
             .collect()
     }
 
+    /// The commits on top of `bottom` up to HEAD, as
+    /// [`Git::get_commit_oids_from`] finds them.
+    pub fn get_prepared_commits_from(
+        &self,
+        config: &Config,
+        bottom: &str,
+    ) -> Result<Vec<PreparedCommit>> {
+        let oids = self.get_commit_oids_from(bottom)?;
+        self.check_no_merge_commits(&oids, config)?;
+        oids.into_iter()
+            .map(|oid| self.prepare_commit(config, oid))
+            .collect()
+    }
+
+    /// The merge commits (the ones with more than one parent) among the
+    /// given commits.
+    pub fn merge_commits(&self, oids: &[Oid]) -> Result<Vec<Oid>> {
+        let repo = self.repo();
+        let mut result = Vec::new();
+        for &oid in oids {
+            if repo.find_commit(oid)?.parent_count() > 1 {
+                result.push(oid);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Fail, naming them, if there are merge commits among the given
+    /// commits. Each commit becomes a Pull Request based on its parent, so
+    /// spr cannot submit a stack containing a merge commit.
+    fn check_no_merge_commits(
+        &self,
+        oids: &[Oid],
+        config: &Config,
+    ) -> Result<()> {
+        let merge_commits = self.merge_commits(oids)?;
+        if merge_commits.is_empty() {
+            return Ok(());
+        }
+
+        let mut error = Error::new(format!(
+            "The stack contains {} merge commit(s), which spr cannot turn \
+             into Pull Requests:",
+            merge_commits.len()
+        ));
+        for oid in merge_commits {
+            error.push(self.describe_commit(oid)?);
+        }
+        error.push(format!(
+            "Flatten the branch first, for example with `git rebase {}`",
+            config
+                .master_ref
+                .local()
+                .strip_prefix("refs/remotes/")
+                .unwrap_or(config.master_ref.local())
+        ));
+
+        Err(error)
+    }
+
+    pub fn merge_base(&self, one: Oid, two: Oid) -> Result<Oid> {
+        Ok(self.repo().merge_base(one, two)?)
+    }
+
     pub fn rewrite_commit_messages(
         &self,
         commits: &mut [PreparedCommit],
@@ -117,6 +677,12 @@ impl Git {
                     &repo,
                     &[(prepared_commit.oid, new_oid)],
                 );
+                copy_note(
+                    &repo,
+                    &self.notes_ref,
+                    prepared_commit.oid,
+                    new_oid,
+                )?;
                 prepared_commit.oid = new_oid;
                 parent_oid = Some(new_oid);
             } else {
@@ -145,8 +711,11 @@ impl Git {
 
         let commit = repo.find_commit(prepared_commit.oid)?;
         let message = build_commit_message(&prepared_commit.message);
+        let parent_oid = parent_oid.unwrap_or(prepared_commit.parent_oid);
 
-        if Some(&message[..]) == commit.message() {
+        if Some(&message[..]) == commit.message()
+            && commit.parent_id(0)? == parent_oid
+        {
             return Ok(prepared_commit.oid);
         }
 
@@ -156,12 +725,11 @@ impl Git {
             &commit.committer(),
             &message[..],
             &commit.tree()?,
-            &[&repo.find_commit(
-                parent_oid.unwrap_or(prepared_commit.parent_oid),
-            )?],
+            &[&repo.find_commit(parent_oid)?],
         )?;
 
         hooks.run_post_rewrite_rebase(&repo, &[(prepared_commit.oid, new_oid)]);
+        copy_note(&repo, &self.notes_ref, prepared_commit.oid, new_oid)?;
 
         prepared_commit.oid = new_oid;
 
@@ -173,6 +741,53 @@ impl Git {
         Ok(new_oid)
     }
 
+    /// Recreate `commits` (a linear chain of commits) on top of
+    /// `new_parent_oid`, keeping their messages and trees as they are, and
+    /// point HEAD at the result. This is for moving commits onto a rewritten
+    /// version of their parent that has the same tree.
+    pub fn reparent_commits(
+        &self,
+        commits: &mut [PreparedCommit],
+        mut new_parent_oid: Oid,
+    ) -> Result<()> {
+        if commits.is_empty() {
+            return Ok(());
+        }
+        let repo = self.repo();
+        let hooks = self.hooks();
+
+        for prepared_commit in commits.iter_mut() {
+            let commit = repo.find_commit(prepared_commit.oid)?;
+            if commit.parent_id(0)? == new_parent_oid {
+                new_parent_oid = prepared_commit.oid;
+                continue;
+            }
+
+            let new_oid = repo.commit(
+                None,
+                &commit.author(),
+                &commit.committer(),
+                String::from_utf8_lossy(commit.message_bytes()).as_ref(),
+                &commit.tree()?,
+                &[&repo.find_commit(new_parent_oid)?],
+            )?;
+            hooks.run_post_rewrite_rebase(
+                &repo,
+                &[(prepared_commit.oid, new_oid)],
+            );
+            copy_note(&repo, &self.notes_ref, prepared_commit.oid, new_oid)?;
+            prepared_commit.oid = new_oid;
+            prepared_commit.parent_oid = new_parent_oid;
+            new_parent_oid = new_oid;
+        }
+
+        repo.find_reference("HEAD")?
+            .resolve()?
+            .set_target(new_parent_oid, "spr moved commits")?;
+
+        Ok(())
+    }
+
     pub fn rebase_commits(
         &self,
         commits: &mut [PreparedCommit],
@@ -223,6 +838,12 @@ impl Git {
                 &repo,
                 &[(prepared_commit.oid, new_parent_oid)],
             );
+            copy_note(
+                &repo,
+                &self.notes_ref,
+                prepared_commit.oid,
+                new_parent_oid,
+            )?;
         }
 
         let new_oid = new_parent_oid;
@@ -257,6 +878,232 @@ impl Git {
         Ok(())
     }
 
+    /// Run the shell command `hook` in a checkout of each of `commits` (a
+    /// linear chain of commits, bottom first), and amend the commit with the
+    /// changes the command makes to the files, if any. The commits on top of
+    /// an amended one are recreated on top of it, including `on_top`, the
+    /// commits above `commits` on the branch. Fails, naming the commit, if
+    /// the command fails.
+    pub async fn run_commit_hook(
+        &self,
+        hook: &str,
+        commits: &mut [PreparedCommit],
+        on_top: &mut [PreparedCommit],
+    ) -> Result<()> {
+        let first_parent_oid = match commits.first() {
+            Some(first) => first.parent_oid,
+            None => return Ok(()),
+        };
+        let (repo_dir, worktree) = {
+            let repo = self.repo();
+            let repo_dir = repo.workdir().unwrap_or(repo.path()).to_path_buf();
+            (repo_dir, repo.path().join("spr-hook-worktree"))
+        };
+        if worktree.exists() {
+            // Left over from a run of spr that was interrupted
+            std::fs::remove_dir_all(&worktree)?;
+            run_git(&repo_dir, &["worktree", "prune"]).await?;
+        }
+        let worktree_arg = worktree.to_string_lossy().into_owned();
+        run_git(
+            &repo_dir,
+            &[
+                "worktree",
+                "add",
+                "--detach",
+                &worktree_arg,
+                &first_parent_oid.to_string(),
+            ],
+        )
+        .await
+        .reword(
+            "Could not create a checkout to run spr.commitHook in".into(),
+        )?;
+
+        let result = self.run_commit_hook_in(&worktree, hook, commits).await;
+        run_git(&repo_dir, &["worktree", "remove", "--force", &worktree_arg])
+            .await
+            .ok();
+        let new_top = match result? {
+            Some(new_top) => new_top,
+            // No commit was amended, so nothing else changes either
+            None => return Ok(()),
+        };
+
+        let new_top = self.recreate_commits(on_top, new_top)?;
+        let repo = self.repo();
+        let mut reference = repo.head()?.resolve()?;
+        repo.checkout_tree(repo.find_commit(new_top)?.as_object(), None)
+            .map_err(Error::from)
+            .reword(
+                "Could not check out the commits amended by spr.commitHook"
+                    .into(),
+            )?;
+        reference.set_target(new_top, "spr ran spr.commitHook")?;
+
+        Ok(())
+    }
+
+    /// Run `hook` for each commit in the checkout at `worktree`, recreating
+    /// the commits that it changes (and the ones on top of them). Returns the
+    /// new top commit if any commit was amended.
+    async fn run_commit_hook_in(
+        &self,
+        worktree: &Path,
+        hook: &str,
+        commits: &mut [PreparedCommit],
+    ) -> Result<Option<Oid>> {
+        let mut new_parent_oid = commits[0].parent_oid;
+        let mut amended = false;
+
+        for prepared_commit in commits.iter_mut() {
+            let error_context = || {
+                format!(
+                    "spr.commitHook failed on commit {} ({})",
+                    prepared_commit.short_id,
+                    prepared_commit
+                        .message
+                        .get(&MessageSection::Title)
+                        .map(String::as_str)
+                        .unwrap_or_default()
+                )
+            };
+
+            // The commit as it is, or moved onto its amended parent
+            let tree_oid = if amended {
+                self.cherrypick_tree(prepared_commit.oid, new_parent_oid)
+                    .context(error_context())?
+            } else {
+                self.get_tree_oid_for_commit(prepared_commit.oid)?
+            };
+            let checkout = if amended {
+                self.recreate_commit(
+                    prepared_commit.oid,
+                    tree_oid,
+                    new_parent_oid,
+                )?
+            } else {
+                prepared_commit.oid
+            };
+            run_git(
+                worktree,
+                &["checkout", "--detach", "--force", &checkout.to_string()],
+            )
+            .await
+            .context(error_context())?;
+
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(hook)
+                .current_dir(worktree)
+                .stdin(Stdio::null())
+                .status()
+                .await?;
+            if !status.success() {
+                return Err(Error::new(error_context()));
+            }
+
+            run_git(worktree, &["add", "--all"])
+                .await
+                .context(error_context())?;
+            let hook_tree_oid = Oid::from_str(
+                git_output(worktree, &["write-tree"])
+                    .await
+                    .context(error_context())?
+                    .trim(),
+            )?;
+
+            let new_oid = if hook_tree_oid == tree_oid {
+                checkout
+            } else {
+                amended = true;
+                self.recreate_commit(
+                    prepared_commit.oid,
+                    hook_tree_oid,
+                    new_parent_oid,
+                )?
+            };
+            if new_oid != prepared_commit.oid {
+                self.record_rewrite(prepared_commit.oid, new_oid)?;
+                prepared_commit.oid = new_oid;
+                prepared_commit.parent_oid = new_parent_oid;
+            }
+            new_parent_oid = new_oid;
+        }
+
+        Ok(amended.then_some(new_parent_oid))
+    }
+
+    /// The tree of the commit `oid` cherry-picked onto `base_oid`.
+    fn cherrypick_tree(&self, oid: Oid, base_oid: Oid) -> Result<Oid> {
+        let index = self.cherrypick(oid, base_oid)?;
+        if index.has_conflicts() {
+            return Err(Error::new(
+                "Moving the commit onto its amended parent caused conflicts",
+            ));
+        }
+        self.write_index(index)
+    }
+
+    /// A copy of the commit `oid` with the given tree and parent.
+    fn recreate_commit(
+        &self,
+        oid: Oid,
+        tree_oid: Oid,
+        parent_oid: Oid,
+    ) -> Result<Oid> {
+        let repo = self.repo();
+        let commit = repo.find_commit(oid)?;
+
+        let new_oid = repo.commit(
+            None,
+            &commit.author(),
+            &commit.committer(),
+            String::from_utf8_lossy(commit.message_bytes()).as_ref(),
+            &repo.find_tree(tree_oid)?,
+            &[&repo.find_commit(parent_oid)?],
+        )?;
+
+        Ok(new_oid)
+    }
+
+    /// Tell the post-rewrite hook, and the Pull Request note, that the commit
+    /// `old` is now `new`.
+    fn record_rewrite(&self, old: Oid, new: Oid) -> Result<()> {
+        let repo = self.repo();
+        self.hooks().run_post_rewrite_rebase(&repo, &[(old, new)]);
+        copy_note(&repo, &self.notes_ref, old, new)
+    }
+
+    /// Recreate `commits` on top of `new_parent_oid` (moving their changes
+    /// onto it), and return the new top commit.
+    fn recreate_commits(
+        &self,
+        commits: &mut [PreparedCommit],
+        mut new_parent_oid: Oid,
+    ) -> Result<Oid> {
+        for prepared_commit in commits.iter_mut() {
+            let tree_oid = self
+                .cherrypick_tree(prepared_commit.oid, new_parent_oid)
+                .context(format!(
+                    "Could not move commit {} onto the commits amended by \
+                     spr.commitHook",
+                    prepared_commit.short_id
+                ))?;
+            let new_oid = self.recreate_commit(
+                prepared_commit.oid,
+                tree_oid,
+                new_parent_oid,
+            )?;
+            self.record_rewrite(prepared_commit.oid, new_oid)?;
+            prepared_commit.oid = new_oid;
+            prepared_commit.parent_oid = new_parent_oid;
+            new_parent_oid = new_oid;
+        }
+
+        Ok(new_parent_oid)
+    }
+
     pub fn head(&self) -> Result<Oid> {
         let oid = self
             .repo()
@@ -373,6 +1220,11 @@ impl Git {
             message.remove(&MessageSection::PullRequest);
         }
 
+        // A commit tracked with a Git note has no trailer. The trailer wins,
+        // though, if a commit has both.
+        let pull_request_number =
+            pull_request_number.or_else(|| self.pull_request_note(config, oid));
+
         Ok(PreparedCommit {
             oid,
             short_id,
@@ -380,23 +1232,92 @@ impl Git {
             pr_stack: None,
             message,
             pull_request_number,
+            squashed: Vec::new(),
         })
     }
 
-    pub fn parse_pr_stack_from_commit(&self, oid: Oid) -> Result<Vec<u64>> {
+    /// The Pull Request that the note on the given commit names, if it has
+    /// one.
+    pub fn pull_request_note(&self, config: &Config, oid: Oid) -> Option<u64> {
+        let repo = self.repo();
+        let note = repo.find_note(Some(&self.notes_ref), oid).ok()?;
+        let number = config.parse_pull_request_field(note.message()?.trim());
+        number
+    }
+
+    /// Record in a note on the given commit that it belongs to the given Pull
+    /// Request, replacing any note there is.
+    pub fn set_pull_request_note(
+        &self,
+        config: &Config,
+        oid: Oid,
+        number: u64,
+    ) -> Result<()> {
+        let repo = self.repo();
+        let signature = note_signature(&repo)?;
+        repo.note(
+            &signature,
+            &signature,
+            Some(&self.notes_ref),
+            oid,
+            &format!("{}\n", config.pull_request_url(number)),
+            true,
+        )?;
+
+        Ok(())
+    }
+
+    /// Remove the note on the given commit that names its Pull Request, if
+    /// there is one.
+    pub fn remove_pull_request_note(&self, oid: Oid) -> Result<()> {
+        let repo = self.repo();
+        if repo.find_note(Some(&self.notes_ref), oid).is_err() {
+            return Ok(());
+        }
+        let signature = note_signature(&repo)?;
+        repo.note_delete(oid, Some(&self.notes_ref), &signature, &signature)?;
+
+        Ok(())
+    }
+
+    /// The Pull Requests that the PR Stack in the message of the given commit
+    /// lists, each with whether it is marked as merged.
+    pub fn parse_pr_stack_from_commit(
+        &self,
+        oid: Oid,
+    ) -> Result<Vec<(u64, bool)>> {
         let repo = self.repo();
         let commit = repo.find_commit(oid)?;
         let message =
-            String::from_utf8_lossy(&commit.message_bytes()).into_owned();
+            String::from_utf8_lossy(commit.message_bytes()).into_owned();
         let message = parse_message(&message, MessageSection::Title);
         let pr_stack = message.get(&MessageSection::PRStack);
         drop(commit);
         drop(repo);
-        Ok(parse_pr_stack_list(
+        Ok(parse_pr_stack_entries(
             pr_stack.map(String::as_str).unwrap_or(""),
         ))
     }
 
+    /// Create (or move) local branches named like the given branches on
+    /// GitHub, pointing at the given commits.
+    pub fn create_local_branches(
+        &self,
+        branches: &[(Oid, &GitHubBranch)],
+    ) -> Result<()> {
+        let repo = self.repo();
+        for (oid, branch) in branches {
+            repo.reference(
+                branch.on_github(),
+                *oid,
+                true,
+                "spr prepared branch locally",
+            )?;
+        }
+
+        Ok(())
+    }
+
     pub fn get_all_ref_names(&self) -> Result<HashSet<String>> {
         let result: std::result::Result<HashSet<_>, _> = self
             .repo()
@@ -408,16 +1329,53 @@ impl Git {
         Ok(result?)
     }
 
+    /// The names of all local branches.
+    pub fn local_branch_names(&self) -> Result<Vec<String>> {
+        let repo = self.repo();
+        let mut names = Vec::new();
+        for branch in repo.branches(Some(git2::BranchType::Local))? {
+            if let Some(name) = branch?.0.name()? {
+                names.push(name.to_string());
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// The name of the local branch that is checked out, if any.
+    pub fn checked_out_branch(&self) -> Option<String> {
+        let repo = self.repo();
+        let head = repo.head().ok()?;
+        if head.is_branch() {
+            head.shorthand().map(String::from)
+        } else {
+            None
+        }
+    }
+
+    pub fn delete_local_branch(&self, name: &str) -> Result<()> {
+        let repo = self.repo();
+        repo.find_branch(name, git2::BranchType::Local)?.delete()?;
+
+        Ok(())
+    }
+
     pub fn get_pr_patch_branch_name(&self, pr_number: u64) -> Result<String> {
+        self.get_unused_branch_name(&format!("PR-{}", pr_number))
+    }
+
+    /// The given branch name, or, if a branch of that name exists already,
+    /// the name with the lowest numeric suffix (`<name>-1`, `<name>-2`, ...)
+    /// that is not taken.
+    pub fn get_unused_branch_name(&self, default_name: &str) -> Result<String> {
         let ref_names = self.get_all_ref_names()?;
-        let default_name = format!("PR-{}", pr_number);
         if !ref_names.contains(&format!("refs/heads/{}", default_name)) {
-            return Ok(default_name);
+            return Ok(default_name.to_string());
         }
 
         let mut count = 1;
         loop {
-            let name = format!("PR-{}-{}", pr_number, count);
+            let name = format!("{}-{}", default_name, count);
             if !ref_names.contains(&format!("refs/heads/{}", name)) {
                 return Ok(name);
             }
@@ -433,6 +1391,22 @@ impl Git {
         Ok(repo.cherrypick_commit(&commit, &base_commit, 0, None)?)
     }
 
+    /// Like `cherrypick`, for the changes of all commits from `parent_oid`
+    /// (which is not included) up to `oid`.
+    pub fn cherrypick_range(
+        &self,
+        parent_oid: Oid,
+        oid: Oid,
+        base_oid: Oid,
+    ) -> Result<git2::Index> {
+        let repo = self.repo();
+        let ancestor = repo.find_commit(parent_oid)?.tree()?;
+        let ours = repo.find_commit(base_oid)?.tree()?;
+        let theirs = repo.find_commit(oid)?.tree()?;
+
+        Ok(repo.merge_trees(&ancestor, &ours, &theirs, None)?)
+    }
+
     pub fn write_index(&self, mut index: git2::Index) -> Result<Oid> {
         Ok(index.write_tree_to(&self.repo())?)
     }
@@ -443,6 +1417,20 @@ impl Git {
         Ok(tree_oid)
     }
 
+    /// When the commit was authored, in the author's time zone.
+    pub fn author_date(
+        &self,
+        oid: Oid,
+    ) -> Result<chrono::DateTime<chrono::FixedOffset>> {
+        let time = self.repo().find_commit(oid)?.author().when();
+        chrono::FixedOffset::east_opt(time.offset_minutes() * 60)
+            .and_then(|offset| {
+                chrono::TimeZone::timestamp_opt(&offset, time.seconds(), 0)
+                    .single()
+            })
+            .ok_or_else(|| Error::new("The commit has an invalid author date"))
+    }
+
     pub fn find_master_base(
         &self,
         commit_oid: Oid,
@@ -544,6 +1532,169 @@ impl Git {
         Ok(oid)
     }
 
+    /// Whether the given commit is empty, i.e. has the same tree as its
+    /// (first) parent, or an empty tree if it is a root commit.
+    pub fn is_empty_commit(&self, oid: Oid) -> Result<bool> {
+        let repo = self.repo();
+        let commit = repo.find_commit(oid)?;
+        if commit.parent_count() == 0 {
+            // A root commit is empty if its tree is
+            return Ok(commit.tree()?.is_empty());
+        }
+        let parent = commit.parent(0)?;
+
+        Ok(commit.tree_id() == parent.tree_id())
+    }
+
+    /// The commits someone other than spr added to a Pull Request branch since
+    /// spr last pushed to it: starting from `head` (where the branch is on
+    /// GitHub), the commits that spr did not create, up to the ones that spr
+    /// created or that are on master (whose commits get merged into Pull
+    /// Request branches when they are rebased).
+    pub fn foreign_commits(&self, head: Oid, master: Oid) -> Result<Vec<Oid>> {
+        let repo = self.repo();
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([head]);
+
+        while let Some(oid) = queue.pop_front() {
+            if !seen.insert(oid)
+                || oid == master
+                || repo.graph_descendant_of(master, oid)?
+            {
+                continue;
+            }
+
+            let commit = repo.find_commit(oid)?;
+            if is_created_by_spr(&String::from_utf8_lossy(
+                commit.message_bytes(),
+            )) {
+                continue;
+            }
+
+            result.push(oid);
+            queue.extend(commit.parent_ids());
+        }
+
+        Ok(result)
+    }
+
+    /// The short id and the first line of the message of the given commit.
+    pub fn describe_commit(&self, oid: Oid) -> Result<String> {
+        let repo = self.repo();
+        let commit = repo.find_commit(oid)?;
+        let short_id = commit.as_object().short_id()?;
+
+        Ok(format!(
+            "{} {}",
+            short_id.as_str().unwrap_or_default(),
+            commit.summary().unwrap_or_default()
+        ))
+    }
+
+    /// The paths of the files that the given commit changes (compared to its
+    /// first parent), except the ones that `filter` excludes.
+    pub fn get_changed_files(
+        &self,
+        oid: Oid,
+        filter: &PathFilter,
+    ) -> Result<Vec<String>> {
+        let repo = self.repo();
+        let commit = repo.find_commit(oid)?;
+        let parent_tree = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let diff = repo.diff_tree_to_tree(
+            parent_tree.as_ref(),
+            Some(&commit.tree()?),
+            None,
+        )?;
+
+        Ok(diff
+            .deltas()
+            .filter_map(|delta| {
+                delta.new_file().path().or_else(|| delta.old_file().path())
+            })
+            .map(|path| path.to_string_lossy().into_owned())
+            .filter(|path| !filter.is_excluded(path))
+            .collect())
+    }
+
+    /// The lines added and deleted going from `base_oid` to `oid`. Moving a
+    /// submodule to another commit changes no lines.
+    pub fn diff_stat(&self, base_oid: Oid, oid: Oid) -> Result<DiffStat> {
+        let repo = self.repo();
+        let base_tree = repo.find_commit(base_oid)?.tree()?;
+        let tree = repo.find_commit(oid)?.tree()?;
+        let diff =
+            repo.diff_tree_to_tree(Some(&base_tree), Some(&tree), None)?;
+
+        let mut diffstat = DiffStat::default();
+        for (index, delta) in diff.deltas().enumerate() {
+            if SubmoduleChange::from_delta(&delta).is_some() {
+                continue;
+            }
+            if let Some(patch) = git2::Patch::from_diff(&diff, index)? {
+                let (_, additions, deletions) = patch.line_stats()?;
+                diffstat.additions += additions;
+                diffstat.deletions += deletions;
+            }
+        }
+
+        Ok(diffstat)
+    }
+
+    /// The submodules that point to other commits in `oid` than in
+    /// `base_oid`.
+    pub fn submodule_changes(
+        &self,
+        base_oid: Oid,
+        oid: Oid,
+    ) -> Result<Vec<SubmoduleChange>> {
+        let repo = self.repo();
+        let base_tree = repo.find_commit(base_oid)?.tree()?;
+        let tree = repo.find_commit(oid)?.tree()?;
+        let diff =
+            repo.diff_tree_to_tree(Some(&base_tree), Some(&tree), None)?;
+
+        Ok(diff
+            .deltas()
+            .filter_map(|delta| SubmoduleChange::from_delta(&delta))
+            .collect())
+    }
+
+    /// The name and email address of the user, from the Git configuration.
+    pub fn user_identity(&self) -> Result<CommitIdentity> {
+        let signature = self.repo().signature()?;
+
+        Ok(CommitIdentity {
+            name: String::from_utf8_lossy(signature.name_bytes()).into_owned(),
+            email: String::from_utf8_lossy(signature.email_bytes())
+                .into_owned(),
+        })
+    }
+
+    /// The author of the given commit.
+    pub fn commit_author(&self, oid: Oid) -> Result<CommitIdentity> {
+        let repo = self.repo();
+        let commit = repo.find_commit(oid)?;
+        let author = commit.author();
+
+        Ok(CommitIdentity {
+            name: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+            email: String::from_utf8_lossy(author.email_bytes()).into_owned(),
+        })
+    }
+
+    pub fn has_staged_changes(&self) -> Result<bool> {
+        let repo = self.repo();
+        let head_tree = repo.head()?.peel_to_tree()?;
+        let diff = repo.diff_tree_to_index(Some(&head_tree), None, None)?;
+
+        Ok(diff.deltas().len() > 0)
+    }
+
     pub fn check_no_uncommitted_changes(&self) -> Result<()> {
         let mut opts = git2::StatusOptions::new();
         opts.include_ignored(false).include_untracked(false);
@@ -556,3 +1707,919 @@ impl Git {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn prepared_commit_factory(
+        id: &str,
+        message: &str,
+    ) -> PreparedCommit {
+        PreparedCommit {
+            oid: Oid::zero(),
+            short_id: id.to_string(),
+            parent_oid: Oid::zero(),
+            pr_stack: None,
+            message: parse_message(message, MessageSection::Title),
+            pull_request_number: None,
+            squashed: Vec::new(),
+        }
+    }
+
+    /// Create a Git repository in a temporary directory, with a single empty
+    /// commit on `master`.
+    pub(crate) fn test_repo() -> (tempfile::TempDir, Git) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        let git = Git::new(repo);
+        let oid = create_commit(&git, "Initial commit", &[]);
+        git.repo()
+            .reference("refs/heads/master", oid, true, "test")
+            .unwrap();
+        git.repo().set_head("refs/heads/master").unwrap();
+
+        (dir, git)
+    }
+
+    /// Create a commit with an empty tree and the given message and parents,
+    /// without updating any reference.
+    pub(crate) fn create_commit(
+        git: &Git,
+        message: &str,
+        parents: &[Oid],
+    ) -> Oid {
+        create_commit_with_files(git, message, parents, &[])
+    }
+
+    /// Create a commit whose tree consists of exactly the given files (path
+    /// and content), without updating any reference.
+    pub(crate) fn create_commit_with_files(
+        git: &Git,
+        message: &str,
+        parents: &[Oid],
+        files: &[(&str, &str)],
+    ) -> Oid {
+        let repo = git.repo();
+        let signature =
+            git2::Signature::now("Test", "test@example.com").unwrap();
+        let empty_tree = repo.treebuilder(None).unwrap().write().unwrap();
+        let empty_tree = repo.find_tree(empty_tree).unwrap();
+        let mut tree_update = git2::build::TreeUpdateBuilder::new();
+        for (path, content) in files {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            tree_update.upsert(path, blob, git2::FileMode::Blob);
+        }
+        let tree_oid = tree_update.create_updated(&repo, &empty_tree).unwrap();
+        let tree = repo.find_tree(tree_oid).unwrap();
+        let parents: Vec<_> = parents
+            .iter()
+            .map(|oid| repo.find_commit(*oid).unwrap())
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+
+        repo.commit(None, &signature, &signature, message, &tree, &parents)
+            .unwrap()
+    }
+
+    /// Create a commit whose tree is that of `parent` with a submodule at
+    /// `path` pointing to `target` (or without it, if `target` is `None`).
+    fn create_commit_with_submodule(
+        git: &Git,
+        parent: Oid,
+        path: &str,
+        target: Option<Oid>,
+    ) -> Oid {
+        let repo = git.repo();
+        let signature =
+            git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let mut tree_update = git2::build::TreeUpdateBuilder::new();
+        match target {
+            Some(target) => {
+                tree_update.upsert(path, target, git2::FileMode::Commit)
+            }
+            None => tree_update.remove(path),
+        };
+        let tree_oid = tree_update
+            .create_updated(&repo, &parent.tree().unwrap())
+            .unwrap();
+        let tree = repo.find_tree(tree_oid).unwrap();
+
+        repo.commit(None, &signature, &signature, "Bump", &tree, &[&parent])
+            .unwrap()
+    }
+
+    fn version(major: u32, minor: u32, patch: u32) -> GitVersion {
+        GitVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    #[test]
+    fn test_parse_git_version() {
+        for (output, expected) in [
+            ("git version 2.39.2\n", version(2, 39, 2)),
+            ("git version 2.39.3 (Apple Git-145)", version(2, 39, 3)),
+            ("git version 2.45.1.windows.1", version(2, 45, 1)),
+            ("git version 2.43.0.rc1", version(2, 43, 0)),
+            ("git version 2.7.4", version(2, 7, 4)),
+            ("git version 1.8", version(1, 8, 0)),
+            ("2.30", version(2, 30, 0)),
+        ] {
+            assert_eq!(output.parse::<GitVersion>().unwrap(), expected);
+        }
+
+        assert!("".parse::<GitVersion>().is_err());
+        assert!("git version".parse::<GitVersion>().is_err());
+        assert!("not git".parse::<GitVersion>().is_err());
+    }
+
+    #[test]
+    fn test_compare_git_versions() {
+        assert!(version(2, 7, 4) > MINIMUM_GIT_VERSION);
+        assert!(version(2, 10, 0) > version(2, 9, 5));
+        assert!(version(1, 9, 5) < MINIMUM_GIT_VERSION);
+        assert_eq!(MINIMUM_GIT_VERSION.to_string(), "2.5.0");
+        // The one running the tests is new enough
+        assert!(check_git_version(MINIMUM_GIT_VERSION).is_ok());
+        assert!(check_git_version(version(999, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_submodule_changes() {
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let old =
+            Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let new =
+            Oid::from_str("2222222222222222222222222222222222222222").unwrap();
+
+        let added =
+            create_commit_with_submodule(&git, master, "libs/dep", Some(old));
+        let bumped =
+            create_commit_with_submodule(&git, added, "libs/dep", Some(new));
+        let removed =
+            create_commit_with_submodule(&git, bumped, "libs/dep", None);
+
+        let changes = git.submodule_changes(added, bumped).unwrap();
+        assert_eq!(
+            changes,
+            vec![SubmoduleChange {
+                path: "libs/dep".to_string(),
+                old: Some(old),
+                new: Some(new),
+            }]
+        );
+        assert_eq!(
+            changes[0].to_string(),
+            "Submodule libs/dep: 1111111..2222222"
+        );
+
+        assert_eq!(
+            git.submodule_changes(master, added).unwrap()[0].to_string(),
+            "Submodule libs/dep: 0000000..1111111"
+        );
+        assert_eq!(
+            git.submodule_changes(bumped, removed).unwrap()[0].to_string(),
+            "Submodule libs/dep: 2222222..0000000"
+        );
+
+        // Moving the submodule changes no lines
+        assert_eq!(git.diff_stat(added, bumped).unwrap(), DiffStat::default());
+    }
+
+    #[test]
+    fn test_submodule_changes_ignores_files() {
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let oid = create_commit_with_files(
+            &git,
+            "Files",
+            &[master],
+            &[("file.txt", "one\ntwo\n")],
+        );
+
+        assert!(git.submodule_changes(master, oid).unwrap().is_empty());
+        assert_eq!(
+            git.diff_stat(master, oid).unwrap(),
+            DiffStat {
+                additions: 2,
+                deletions: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_commit_oids_from() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit(&git, "One", &[root]);
+        let two = create_commit(&git, "Two", &[one]);
+        let three = create_commit(&git, "Three", &[two]);
+        git.repo()
+            .reference("refs/heads/master", three, true, "test")
+            .unwrap();
+        git.repo()
+            .reference("refs/tags/v1", one, true, "test")
+            .unwrap();
+
+        assert_eq!(git.get_commit_oids_from("v1").unwrap(), vec![two, three]);
+        assert_eq!(
+            git.get_commit_oids_from(&root.to_string()).unwrap(),
+            vec![one, two, three]
+        );
+        assert_eq!(git.get_commit_oids_from("HEAD~1").unwrap(), vec![three]);
+        assert!(git.get_commit_oids_from("HEAD").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_local_commit() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let sha = root.to_string();
+
+        assert_eq!(git.find_local_commit(&sha).unwrap(), root);
+        assert_eq!(git.find_local_commit(&sha[..10]).unwrap(), root);
+        assert_eq!(
+            git.find_local_commit(&sha.to_ascii_uppercase()).unwrap(),
+            root
+        );
+
+        let missing = "0123456789abcdef0123456789abcdef01234567";
+        let error = git.find_local_commit(missing).unwrap_err();
+        assert!(error.to_string().contains("does not exist"));
+        assert!(git.find_local_commit("master").is_err());
+        assert!(git.find_local_commit("HEAD~1").is_err());
+    }
+
+    #[test]
+    fn test_is_empty_range() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        git.repo()
+            .reference("refs/remotes/origin/master", root, true, "test")
+            .unwrap();
+        // HEAD is the base
+        assert!(git.is_empty_range("refs/remotes/origin/master").unwrap());
+        assert!(git.is_empty_range(&root.to_string()).unwrap());
+
+        let one = create_commit(&git, "One", &[root]);
+        git.repo()
+            .reference("refs/heads/master", one, true, "test")
+            .unwrap();
+        assert!(!git.is_empty_range("refs/remotes/origin/master").unwrap());
+
+        // The base moved on past HEAD
+        let two = create_commit(&git, "Two", &[one]);
+        git.repo()
+            .reference("refs/remotes/origin/master", two, true, "test")
+            .unwrap();
+        assert!(git.is_empty_range("refs/remotes/origin/master").unwrap());
+
+        assert!(git.is_empty_range("refs/remotes/origin/nope").is_err());
+    }
+
+    #[test]
+    fn test_rebased_branch_and_count_commits() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit(&git, "One", &[root]);
+        let two = create_commit(&git, "Two", &[one]);
+        git.repo()
+            .reference("refs/heads/feature", two, true, "test")
+            .unwrap();
+        assert_eq!(git.rebased_branch(), None);
+
+        let rebase_dir = git.repo().path().join("rebase-merge");
+        std::fs::create_dir(&rebase_dir).unwrap();
+        std::fs::write(rebase_dir.join("head-name"), "refs/heads/feature\n")
+            .unwrap();
+        assert_eq!(git.rebased_branch().as_deref(), Some("refs/heads/feature"));
+        assert_eq!(
+            git.count_commits("refs/heads/feature", "refs/heads/master")
+                .unwrap(),
+            2
+        );
+
+        // Rebasing a detached HEAD
+        std::fs::write(rebase_dir.join("head-name"), "detached HEAD\n")
+            .unwrap();
+        assert_eq!(git.rebased_branch(), None);
+    }
+
+    #[test]
+    fn test_get_commit_oids_from_not_an_ancestor() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit(&git, "One", &[root]);
+        let other = create_commit(&git, "Other", &[root]);
+        git.repo()
+            .reference("refs/heads/master", one, true, "test")
+            .unwrap();
+        git.repo()
+            .reference("refs/heads/other", other, true, "test")
+            .unwrap();
+
+        assert_eq!(
+            git.get_commit_oids_from("other").unwrap_err().messages(),
+            &vec!["other is not an ancestor of HEAD, so it cannot be the \
+                   bottom of the stack"
+                .to_string()]
+        );
+        assert!(git.get_commit_oids_from("no-such-ref").is_err());
+
+        // Where the stack branched off, for basing its Pull Requests
+        assert_eq!(git.merge_base(one, other).unwrap(), root);
+    }
+
+    #[test]
+    fn test_merge_commits_are_reported() {
+        let (_dir, git) = test_repo();
+        let config = crate::config::tests::config_factory();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit(&git, "One", &[root]);
+        let side = create_commit(&git, "Side", &[root]);
+        let merge = create_commit(&git, "Merge side", &[one, side]);
+        let two = create_commit(&git, "Two", &[merge]);
+        git.repo()
+            .reference("refs/heads/master", two, true, "test")
+            .unwrap();
+
+        let oids = git.get_commit_oids_from(&root.to_string()).unwrap();
+        assert_eq!(git.merge_commits(&oids).unwrap(), vec![merge]);
+        assert!(git.merge_commits(&[one, side, two]).unwrap().is_empty());
+
+        let error = git
+            .get_prepared_commits_from(&config, &root.to_string())
+            .unwrap_err();
+        assert_eq!(error.messages().len(), 3);
+        assert!(error.messages()[0].contains("1 merge commit(s)"));
+        assert_eq!(error.messages()[1], git.describe_commit(merge).unwrap());
+        assert!(error.messages()[1].ends_with("Merge side"));
+        // The stack is based on the remote-tracking branch
+        assert!(error.messages()[2].ends_with("`git rebase origin/master`"));
+
+        // A stack above the merge commit is fine
+        assert_eq!(
+            git.get_prepared_commits_from(&config, &merge.to_string())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_find_pull_request_commit() {
+        let mut commits = vec![
+            prepared_commit_factory("a", "First"),
+            prepared_commit_factory("b", "Second"),
+            prepared_commit_factory("c", "Third"),
+        ];
+        commits[1].pull_request_number = Some(12);
+        commits[2].pull_request_number = Some(13);
+
+        assert_eq!(find_pull_request_commit(&commits, 12), Some(1));
+        assert_eq!(find_pull_request_commit(&commits, 13), Some(2));
+        assert_eq!(find_pull_request_commit(&commits, 14), None);
+    }
+
+    #[test]
+    fn test_is_empty_commit() {
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let empty = create_commit(&git, "Empty", &[master]);
+        let change = create_commit_with_files(
+            &git,
+            "Change",
+            &[empty],
+            &[("a.txt", "a")],
+        );
+
+        assert!(git.is_empty_commit(empty).unwrap());
+        assert!(!git.is_empty_commit(change).unwrap());
+    }
+
+    #[test]
+    fn test_is_empty_commit_root() {
+        let (_dir, git) = test_repo();
+        let empty_root = create_commit(&git, "Empty root", &[]);
+        let root = create_commit_with_files(&git, "Root", &[], &[("a", "a")]);
+
+        assert!(git.is_empty_commit(empty_root).unwrap());
+        assert!(!git.is_empty_commit(root).unwrap());
+    }
+
+    #[test]
+    fn test_reparent_commits() {
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let one =
+            create_commit_with_files(&git, "One", &[master], &[("a", "1")]);
+        let two = create_commit_with_files(&git, "Two", &[one], &[("a", "2")]);
+        let three =
+            create_commit_with_files(&git, "Three", &[two], &[("a", "3")]);
+        git.repo()
+            .reference("refs/heads/master", three, true, "test")
+            .unwrap();
+        // A rewritten version of `one`, e.g. with an updated message
+        let new_one =
+            create_commit_with_files(&git, "One v2", &[master], &[("a", "1")]);
+
+        let mut commits = vec![
+            prepared_commit_factory("two", "Two"),
+            prepared_commit_factory("three", "Three"),
+        ];
+        commits[0].oid = two;
+        commits[0].parent_oid = one;
+        commits[1].oid = three;
+        commits[1].parent_oid = two;
+
+        git.reparent_commits(&mut commits, new_one).unwrap();
+
+        let repo = git.repo();
+        assert_eq!(commits[0].parent_oid, new_one);
+        assert_eq!(commits[1].parent_oid, commits[0].oid);
+        for (commit, old_oid) in commits.iter().zip([two, three]) {
+            let new_commit = repo.find_commit(commit.oid).unwrap();
+            let old_commit = repo.find_commit(old_oid).unwrap();
+            assert_ne!(commit.oid, old_oid);
+            assert_eq!(new_commit.parent_id(0).unwrap(), commit.parent_oid);
+            assert_eq!(new_commit.message(), old_commit.message());
+            assert_eq!(new_commit.tree_id(), old_commit.tree_id());
+        }
+        assert_eq!(
+            repo.head().unwrap().peel_to_commit().unwrap().id(),
+            commits[1].oid
+        );
+        drop(repo);
+
+        // Commits that already sit on the given parent are kept as they are
+        let expected: Vec<_> = commits.iter().map(|c| c.oid).collect();
+        git.reparent_commits(&mut commits, new_one).unwrap();
+        assert_eq!(commits.iter().map(|c| c.oid).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_create_local_branches() {
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let oid = create_commit(&git, "Change", &[master]);
+        let branch = GitHubBranch::new_from_branch_name(
+            "spr/foo/change",
+            "origin",
+            "master",
+        );
+
+        git.create_local_branches(&[(oid, &branch)]).unwrap();
+        assert_eq!(
+            git.resolve_reference("refs/heads/spr/foo/change").unwrap(),
+            oid
+        );
+
+        // Creating the branch again moves it to the new commit
+        let oid2 = create_commit(&git, "Change v2", &[master]);
+        git.create_local_branches(&[(oid2, &branch)]).unwrap();
+        assert_eq!(
+            git.resolve_reference("refs/heads/spr/foo/change").unwrap(),
+            oid2
+        );
+    }
+
+    #[test]
+    fn test_get_changed_files() {
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let parent = create_commit_with_files(
+            &git,
+            "Parent",
+            &[master],
+            &[("src/lib.rs", "old"), ("README.md", "readme")],
+        );
+        let commit = create_commit_with_files(
+            &git,
+            "Commit",
+            &[parent],
+            &[
+                ("src/lib.rs", "new"),
+                ("src/api.pb.rs", "generated"),
+                ("vendor/dep/lib.rs", "vendored"),
+            ],
+        );
+
+        let mut changed_files =
+            git.get_changed_files(commit, &PathFilter::empty()).unwrap();
+        changed_files.sort();
+        assert_eq!(
+            changed_files,
+            vec![
+                "README.md",
+                "src/api.pb.rs",
+                "src/lib.rs",
+                "vendor/dep/lib.rs"
+            ]
+        );
+
+        let filter = PathFilter::parse("*.pb.rs\nvendor/\n").unwrap();
+        let mut changed_files = git.get_changed_files(commit, &filter).unwrap();
+        changed_files.sort();
+        assert_eq!(changed_files, vec!["README.md", "src/lib.rs"]);
+    }
+
+    #[test]
+    fn test_remote_default_branch() {
+        let (_dir, git) = test_repo();
+        let repo = git.repo();
+        let oid = repo.refname_to_id("refs/heads/master").unwrap();
+
+        repo.reference("refs/remotes/origin/master", oid, true, "test")
+            .unwrap();
+        assert_eq!(remote_default_branch(&repo, "origin").unwrap(), "master");
+
+        repo.reference("refs/remotes/origin/main", oid, true, "test")
+            .unwrap();
+        repo.reference_symbolic(
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/main",
+            true,
+            "test",
+        )
+        .unwrap();
+        assert_eq!(remote_default_branch(&repo, "origin").unwrap(), "main");
+    }
+
+    #[test]
+    fn test_upstream_branch() {
+        let (_dir, git) = test_repo();
+        let repo = git.repo();
+        let oid = repo.refname_to_id("refs/heads/master").unwrap();
+        repo.remote("origin", "https://github.com/acme/codez.git")
+            .unwrap();
+        repo.reference("refs/remotes/origin/release-1.2", oid, true, "test")
+            .unwrap();
+
+        // Without an upstream, --base-auto falls back to the default branch
+        assert_eq!(upstream_branch(&repo, "origin"), None);
+
+        let mut config = repo.config().unwrap();
+        config.set_str("branch.master.remote", "origin").unwrap();
+        config
+            .set_str("branch.master.merge", "refs/heads/release-1.2")
+            .unwrap();
+        assert_eq!(
+            upstream_branch(&repo, "origin").as_deref(),
+            Some("release-1.2")
+        );
+
+        // An upstream on another remote is no base for Pull Requests here
+        assert_eq!(upstream_branch(&repo, "fork"), None);
+
+        // Neither is anything, when no branch is checked out
+        repo.set_head_detached(oid).unwrap();
+        assert_eq!(upstream_branch(&repo, "origin"), None);
+    }
+
+    #[test]
+    fn test_github_remotes() {
+        let (_dir, git) = test_repo();
+        let repo = git.repo();
+        repo.remote("origin", "https://github.com/acme/codez.git")
+            .unwrap();
+        repo.remote("fork", "git@github.com:me/codez.git").unwrap();
+        repo.remote("mirror", "https://gitlab.com/acme/codez.git")
+            .unwrap();
+
+        let remotes = github_remotes(&repo, "github.com").unwrap();
+        let names: Vec<_> = remotes.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["fork", "origin"]);
+        assert_eq!(remotes[0].to_string(), "fork (me/codez)");
+    }
+
+    #[test]
+    fn test_choose_github_remote() {
+        let remote = |name: &str, owner: &str| GitHubRemote {
+            name: name.to_string(),
+            owner: owner.to_string(),
+            repo: "codez".to_string(),
+        };
+
+        assert_eq!(choose_github_remote(vec![], false).unwrap(), None);
+        assert_eq!(
+            choose_github_remote(vec![remote("origin", "acme")], false)
+                .unwrap(),
+            Some(remote("origin", "acme"))
+        );
+
+        // Two GitHub remotes are ambiguous, which fails if nobody is asked
+        let error = choose_github_remote(
+            vec![remote("origin", "acme"), remote("fork", "me")],
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &vec!["More than one remote points to GitHub: origin \
+                   (acme/codez), fork (me/codez). Set spr.githubRemoteName \
+                   to the one to use."
+                .to_string()]
+        );
+    }
+
+    #[test]
+    fn test_remote_without_default_branch() {
+        let (_dir, git) = test_repo();
+        let repo = git.repo();
+
+        let error = remote_default_branch(&repo, "origin").unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &vec!["Remote 'origin' has no default branch; push an initial \
+                 commit or set spr.githubMasterBranch in the Git config"
+                .to_string()]
+        );
+
+        // An empty remote's HEAD points to a branch that does not exist yet
+        repo.reference_symbolic(
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/main",
+            true,
+            "test",
+        )
+        .unwrap();
+        assert!(remote_default_branch(&repo, "origin").is_err());
+    }
+
+    #[test]
+    fn test_get_commit_oids_without_master_ref() {
+        let (_dir, git) = test_repo();
+
+        let error = git
+            .get_commit_oids("refs/remotes/origin/master")
+            .unwrap_err();
+        assert!(error.messages()[0]
+            .starts_with("refs/remotes/origin/master does not exist"));
+    }
+
+    #[test]
+    fn test_foreign_commits() {
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let spr_commit = |message: &str, parents: &[Oid]| {
+            create_commit(
+                &git,
+                &format!("{}\n\nCreated using spr 1.3.5", message),
+                parents,
+            )
+        };
+
+        let initial = spr_commit("[spr] initial version", &[master]);
+        assert_eq!(git.foreign_commits(initial, master).unwrap(), vec![]);
+
+        // A teammate pushed two commits on top of ours
+        let theirs = create_commit(&git, "Fix typo", &[initial]);
+        let theirs_too = create_commit(&git, "Fix another typo", &[theirs]);
+        assert_eq!(
+            git.foreign_commits(theirs_too, master).unwrap(),
+            vec![theirs_too, theirs]
+        );
+
+        // Commits from master merged into the branch are expected
+        let new_master =
+            create_commit(&git, "Someone else's change", &[master]);
+        let rebased = spr_commit("[spr] rebase", &[initial, new_master]);
+        let update = create_commit(&git, "Fix typo", &[rebased]);
+        assert_eq!(
+            git.foreign_commits(update, new_master).unwrap(),
+            vec![update]
+        );
+
+        // Once spr pushed on top of them, they are not unexpected anymore
+        let ours = spr_commit("[spr] changes prepared locally", &[theirs_too]);
+        assert_eq!(git.foreign_commits(ours, master).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_pull_request_note() {
+        let config = crate::config::tests::config_factory();
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let oid = create_commit(&git, "Change\n\nSummary", &[master]);
+        assert_eq!(git.pull_request_note(&config, oid), None);
+
+        git.set_pull_request_note(&config, oid, 42).unwrap();
+        assert_eq!(git.pull_request_note(&config, oid), Some(42));
+
+        // The commit belongs to the Pull Request, but its message has no
+        // trailer
+        let prepared_commit = git.prepare_commit(&config, oid).unwrap();
+        assert_eq!(prepared_commit.pull_request_number, Some(42));
+        assert!(!prepared_commit
+            .message
+            .contains_key(&MessageSection::PullRequest));
+
+        git.remove_pull_request_note(oid).unwrap();
+        assert_eq!(git.pull_request_note(&config, oid), None);
+        // Removing a note that is not there is fine
+        git.remove_pull_request_note(oid).unwrap();
+    }
+
+    #[test]
+    fn test_pull_request_note_custom_ref() {
+        let config = crate::config::tests::config_factory();
+        let (_dir, git) = test_repo();
+        let git = git.with_notes_ref("refs/notes/reviews");
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let oid = create_commit(&git, "Change", &[master]);
+
+        git.set_pull_request_note(&config, oid, 7).unwrap();
+
+        let repo = git.repo();
+        assert!(repo.find_note(Some("refs/notes/reviews"), oid).is_ok());
+        assert!(repo
+            .find_note(Some(crate::config::DEFAULT_NOTES_REF), oid)
+            .is_err());
+    }
+
+    #[test]
+    fn test_pull_request_trailer_wins_over_note() {
+        let config = crate::config::tests::config_factory();
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let oid = create_commit(
+            &git,
+            "Change\n\nPull Request: https://github.com/acme/codez/pull/12\n",
+            &[master],
+        );
+        git.set_pull_request_note(&config, oid, 42).unwrap();
+
+        let prepared_commit = git.prepare_commit(&config, oid).unwrap();
+        assert_eq!(prepared_commit.pull_request_number, Some(12));
+    }
+
+    #[test]
+    fn test_pull_request_note_follows_rewrite() {
+        let config = crate::config::tests::config_factory();
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let first = create_commit(&git, "First", &[master]);
+        let second = create_commit(&git, "Second", &[first]);
+        git.repo()
+            .reference("refs/heads/master", second, true, "test")
+            .unwrap();
+        git.set_pull_request_note(&config, first, 1).unwrap();
+        git.set_pull_request_note(&config, second, 2).unwrap();
+
+        let mut commits = vec![
+            git.prepare_commit(&config, first).unwrap(),
+            git.prepare_commit(&config, second).unwrap(),
+        ];
+        commits[0]
+            .message
+            .insert(MessageSection::Summary, "Reworded".to_string());
+        git.rewrite_commit_messages(&mut commits, None).unwrap();
+
+        assert_ne!(commits[0].oid, first);
+        assert_ne!(commits[1].oid, second);
+        assert_eq!(git.pull_request_note(&config, commits[0].oid), Some(1));
+        assert_eq!(git.pull_request_note(&config, commits[1].oid), Some(2));
+    }
+
+    /// A branch of two commits (and one more on top of them), checked out,
+    /// for running spr.commitHook on the two.
+    fn commit_hook_repo() -> (
+        tempfile::TempDir,
+        Git,
+        Vec<PreparedCommit>,
+        Vec<PreparedCommit>,
+    ) {
+        let config = crate::config::tests::config_factory();
+        let (dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let one =
+            create_commit_with_files(&git, "One", &[master], &[("a", "a\n")]);
+        let two = create_commit_with_files(
+            &git,
+            "Two",
+            &[one],
+            &[("a", "a\n"), ("b", "b\n")],
+        );
+        let three = create_commit_with_files(
+            &git,
+            "Three",
+            &[two],
+            &[("a", "a\n"), ("b", "b\n"), ("c", "c\n")],
+        );
+        git.repo()
+            .reference("refs/heads/master", three, true, "test")
+            .unwrap();
+        git.repo()
+            .checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+
+        let commits = vec![
+            git.prepare_commit(&config, one).unwrap(),
+            git.prepare_commit(&config, two).unwrap(),
+        ];
+        let on_top = vec![git.prepare_commit(&config, three).unwrap()];
+        (dir, git, commits, on_top)
+    }
+
+    fn files(git: &Git, oid: Oid) -> Vec<String> {
+        let repo = git.repo();
+        let tree = repo.find_commit(oid).unwrap().tree().unwrap();
+        let names = tree
+            .iter()
+            .map(|entry| entry.name().unwrap().to_string())
+            .collect();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_run_commit_hook_amends_changed_commits() {
+        let (dir, git, mut commits, mut on_top) = commit_hook_repo();
+        let original: Vec<Oid> = commits.iter().map(|c| c.oid).collect();
+
+        // Generates a file listing the files of the commit
+        git.run_commit_hook("ls > generated", &mut commits, &mut on_top)
+            .await
+            .unwrap();
+
+        assert_ne!(commits[0].oid, original[0]);
+        assert_ne!(commits[1].oid, original[1]);
+        assert_eq!(files(&git, commits[0].oid), vec!["a", "generated"]);
+        assert_eq!(files(&git, commits[1].oid), vec!["a", "b", "generated"]);
+        assert_eq!(commits[1].parent_oid, commits[0].oid);
+        // The commit on top keeps its own change, on top of the amended ones
+        assert_eq!(on_top[0].parent_oid, commits[1].oid);
+        assert_eq!(
+            files(&git, on_top[0].oid),
+            vec!["a", "b", "c", "generated"]
+        );
+        assert_eq!(git.head().unwrap(), on_top[0].oid);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("generated")).unwrap(),
+            "a\nb\ngenerated\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_commit_hook_leaves_clean_commits_alone() {
+        let (_dir, git, mut commits, mut on_top) = commit_hook_repo();
+        let original: Vec<Oid> =
+            commits.iter().chain(on_top.iter()).map(|c| c.oid).collect();
+
+        git.run_commit_hook("true", &mut commits, &mut on_top)
+            .await
+            .unwrap();
+
+        let after: Vec<Oid> =
+            commits.iter().chain(on_top.iter()).map(|c| c.oid).collect();
+        assert_eq!(after, original);
+        assert_eq!(git.head().unwrap(), original[2]);
+    }
+
+    #[tokio::test]
+    async fn test_run_commit_hook_fails() {
+        let (_dir, git, mut commits, mut on_top) = commit_hook_repo();
+        let head = git.head().unwrap();
+
+        // Fails on the second commit only
+        let error = git
+            .run_commit_hook("test ! -e b", &mut commits, &mut on_top)
+            .await
+            .unwrap_err();
+
+        let short_id = commits[1].short_id.clone();
+        assert_eq!(
+            error.messages(),
+            &vec![format!(
+                "spr.commitHook failed on commit {} (Two)",
+                short_id
+            )]
+        );
+        assert_eq!(git.head().unwrap(), head);
+    }
+
+    #[test]
+    fn test_prepare_commit_keeps_comment_lines() {
+        // Git cleaned up the message when it was committed, so what looks like
+        // a comment was kept on purpose (e.g. with `commit.cleanup=whitespace`)
+        let config = crate::config::tests::config_factory();
+        let (_dir, git) = test_repo();
+        let master = git.resolve_reference("refs/heads/master").unwrap();
+        let message = "Title\n\n## Heading\n\nSummary\n";
+        let oid = create_commit(&git, message, &[master]);
+
+        let prepared_commit = git.prepare_commit(&config, oid).unwrap();
+        assert_eq!(
+            prepared_commit
+                .message
+                .get(&MessageSection::Summary)
+                .unwrap(),
+            "## Heading\n\nSummary"
+        );
+        assert_eq!(build_commit_message(&prepared_commit.message), message);
+    }
+}
@@ -10,12 +10,26 @@ use serde::Deserialize;
 
 use crate::{
     error::{Error, Result, ResultExt},
+    forge::Forge,
     git::Git,
     message::{
-        build_github_body, parse_message, MessageSection, MessageSectionsMap,
+        build_github_body, build_github_title, parse_message, MessageSection,
+        MessageSectionsMap,
     },
 };
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+
+/// The most pages of results we fetch for a paginated GraphQL query. This is a
+/// safety net against looping forever, should GitHub keep telling us there is
+/// another page.
+pub const MAX_PAGES: usize = 100;
+
+/// The longest we are willing to wait when GitHub asks us to back off (via a
+/// `Retry-After` header) before retrying a request.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
 
 #[derive(Clone)]
 pub struct GitHub {
@@ -27,7 +41,10 @@ pub struct GitHub {
 #[derive(Debug, Clone)]
 pub struct PullRequest {
     pub number: u64,
+    /// The ID of the Pull Request in GitHub's GraphQL API
+    pub node_id: String,
     pub state: PullRequestState,
+    pub draft: bool,
     pub title: String,
     pub body: Option<String>,
     pub sections: MessageSectionsMap,
@@ -38,6 +55,14 @@ pub struct PullRequest {
     pub merge_commit: Option<git2::Oid>,
     pub reviewers: HashMap<String, ReviewStatus>,
     pub review_status: Option<ReviewStatus>,
+    pub author: Option<CommitIdentity>,
+}
+
+/// A name and email address, as recorded for the author of a Git commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitIdentity {
+    pub name: String,
+    pub email: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -47,7 +72,7 @@ pub enum ReviewStatus {
     Rejected,
 }
 
-#[derive(serde::Serialize, Default, Debug)]
+#[derive(serde::Serialize, Default, Debug, Clone, PartialEq, Eq)]
 pub struct PullRequestUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
@@ -71,25 +96,69 @@ impl PullRequestUpdate {
         &mut self,
         pull_request: &PullRequest,
         message: &MessageSectionsMap,
+        config: &crate::config::Config,
     ) {
-        let title = message.get(&MessageSection::Title);
-        if title.is_some() && title != Some(&pull_request.title) {
-            self.title = title.cloned();
+        let title = build_github_title(message, config);
+        if let Some(title) = title {
+            if !same_text(&title, &pull_request.title) {
+                self.title = Some(title);
+            }
         }
 
-        let body = build_github_body(message);
-        if pull_request.body.as_ref() != Some(&body) {
+        let body = build_github_body(message, config);
+        if !same_text(&body, pull_request.body.as_deref().unwrap_or_default()) {
             self.body = Some(body);
         }
     }
 }
 
-#[derive(serde::Serialize, Default, Debug)]
+/// Whether two titles or descriptions say the same, apart from differences
+/// that GitHub introduces or that nobody can see: CRLF instead of LF line
+/// endings (as the web editor saves them), whitespace at the ends of lines and
+/// blank lines at the end.
+pub fn same_text(one: &str, other: &str) -> bool {
+    let normalize = |text: &str| {
+        text.lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_end()
+            .to_string()
+    };
+    normalize(one) == normalize(other)
+}
+
+/// The body of a request to GitHub's merge API.
+#[derive(Debug, serde::Serialize)]
+pub struct MergeRequest {
+    pub commit_title: String,
+    pub commit_message: String,
+    pub sha: String,
+    pub merge_method: crate::forge::MergeMethod,
+}
+
+#[derive(serde::Serialize, Default, Debug, Clone, PartialEq, Eq)]
 pub struct PullRequestRequestReviewers {
     pub reviewers: Vec<String>,
     pub team_reviewers: Vec<String>,
 }
 
+impl PullRequestRequestReviewers {
+    /// Put the users and teams in a stable order, rather than the order they
+    /// were listed in.
+    pub fn sort(&mut self) {
+        crate::utils::sort_names(&mut self.reviewers);
+        crate::utils::sort_names(&mut self.team_reviewers);
+    }
+}
+
+/// A comment on the conversation of a Pull Request (as opposed to a review
+/// comment on a line of code).
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct IssueComment {
+    pub body: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum PullRequestState {
@@ -113,6 +182,124 @@ pub struct PullRequestMergeability {
     pub merge_commit: Option<git2::Oid>,
 }
 
+/// The combined state of the checks and commit statuses on the head commit of
+/// a Pull Request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksState {
+    /// There are no checks or statuses at all
+    NoChecks,
+    Pending,
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone)]
+pub struct PullRequestChecks {
+    pub head_oid: git2::Oid,
+    pub state: ChecksState,
+    /// Names of the checks and statuses that failed
+    pub failed: Vec<String>,
+    /// Names of the checks and statuses that the branch protection rules
+    /// require to pass before merging, but that have not passed (yet)
+    pub unmet_required: Vec<String>,
+}
+
+/// A project (of the kind GitHub calls Projects V2) that Pull Requests can be
+/// added to as items.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Project {
+    pub id: String,
+    pub number: u64,
+    pub title: String,
+    pub closed: bool,
+}
+
+/// The project that `name` refers to among the projects of `owner`: the one
+/// with that number, if `name` is one, or else the open one with that title
+/// (in any case).
+pub fn resolve_project(
+    projects: Vec<Project>,
+    name: &str,
+    owner: &str,
+) -> Result<Project> {
+    let name = name.trim().trim_start_matches('#');
+    let mut matching: Vec<Project> = match name.parse::<u64>() {
+        Ok(number) => projects
+            .into_iter()
+            .filter(|project| project.number == number)
+            .collect(),
+        Err(_) => projects
+            .into_iter()
+            .filter(|project| {
+                !project.closed && project.title.eq_ignore_ascii_case(name)
+            })
+            .collect(),
+    };
+
+    match matching.len() {
+        0 => Err(Error::new(format!(
+            "{} has no open project '{}' (give the title or the number of a \
+             project of the owner of the repository)",
+            owner, name
+        ))),
+        1 => Ok(matching.remove(0)),
+        _ => Err(Error::new(format!(
+            "{} has more than one project '{}' ({}); give its number instead",
+            owner,
+            name,
+            matching
+                .iter()
+                .map(|project| format!("#{}", project.number))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// The head of a Pull Request, as far as GitHub still knows it when the head
+/// branch is gone (which `GitHub::get_pull_request` cannot cope with).
+#[derive(Debug, Clone)]
+pub struct PullRequestHead {
+    pub state: PullRequestState,
+    pub merged: bool,
+    pub branch: GitHubBranch,
+    pub oid: git2::Oid,
+    /// The repository of the head branch (`OWNER/REPO`), or `None` if it was
+    /// deleted
+    pub repository: Option<String>,
+    pub branch_exists: bool,
+}
+
+impl PullRequestHead {
+    /// Whether the Pull Request lost its head branch, so that spr cannot
+    /// update it anymore. Merged Pull Requests do not need one.
+    pub fn is_headless(&self) -> bool {
+        !self.merged && !self.branch_exists
+    }
+}
+
+/// Whether the branch is at the given commit according to the cache of
+/// branches we pushed or fetched (unless `--refresh` was given), and we have
+/// that commit.
+fn known_branch_oid(
+    git: &Git,
+    config: &crate::config::Config,
+    branch: &GitHubBranch,
+    oid: git2::Oid,
+) -> bool {
+    if config.refresh {
+        return false;
+    }
+    let repo = git.repo();
+    // A cache we cannot read just means fetching
+    let cached = crate::push_cache::PushCache::load(repo.path())
+        .ok()
+        .and_then(|cache| cache.get(branch.on_github()));
+
+    crate::push_cache::is_unchanged(cached, oid)
+        && repo.find_commit(oid).is_ok()
+}
+
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "src/gql/schema.docs.graphql",
@@ -130,6 +317,49 @@ type GitObjectID = String;
 )]
 pub struct PullRequestMergeabilityQuery;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/gql/schema.docs.graphql",
+    query_path = "src/gql/pullrequest_checks_query.graphql",
+    response_derives = "Debug"
+)]
+pub struct PullRequestChecksQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/gql/schema.docs.graphql",
+    query_path = "src/gql/mark_ready_for_review_mutation.graphql",
+    response_derives = "Debug"
+)]
+pub struct MarkReadyForReviewMutation;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/gql/schema.docs.graphql",
+    query_path = "src/gql/projects_query.graphql",
+    response_derives = "Debug"
+)]
+pub struct ProjectsQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/gql/schema.docs.graphql",
+    query_path = "src/gql/add_project_item_mutation.graphql",
+    response_derives = "Debug"
+)]
+pub struct AddProjectItemMutation;
+
+/// The request adding the Pull Request with the given node ID to the project.
+pub fn add_project_item_query(
+    project: &Project,
+    pull_request_node_id: &str,
+) -> graphql_client::QueryBody<add_project_item_mutation::Variables> {
+    AddProjectItemMutation::build_query(add_project_item_mutation::Variables {
+        project: project.id.clone(),
+        content: pull_request_node_id.to_string(),
+    })
+}
+
 impl GitHub {
     pub fn new(
         config: crate::config::Config,
@@ -144,6 +374,7 @@ impl GitHub {
     }
 
     pub async fn get_github_user(login: String) -> Result<UserWithName> {
+        crate::timings::count_api_call();
         octocrab::instance()
             .get::<UserWithName, _, _>(format!("users/{}", login), None::<&()>)
             .await
@@ -154,6 +385,7 @@ impl GitHub {
         owner: String,
         team: String,
     ) -> Result<octocrab::models::teams::Team> {
+        crate::timings::count_api_call();
         octocrab::instance()
             .teams(owner)
             .get(team)
@@ -161,7 +393,152 @@ impl GitHub {
             .map_err(Error::from)
     }
 
-    pub async fn get_pull_request(self, number: u64) -> Result<PullRequest> {
+    /// The states of all Pull Requests that have the given branch as their
+    /// head or their base.
+    pub async fn get_pull_request_states_for_branch(
+        &self,
+        branch_name: &str,
+    ) -> Result<Vec<PullRequestState>> {
+        let octocrab = octocrab::instance();
+        let handler =
+            octocrab.pulls(self.config.owner.clone(), self.config.repo.clone());
+        let pulls = || {
+            handler
+                .list()
+                .state(octocrab::params::State::All)
+                .per_page(100)
+        };
+        let as_head = with_request_timeout(
+            &self.config,
+            pulls()
+                .head(format!("{}:{}", self.config.owner, branch_name))
+                .send(),
+        )
+        .await?;
+        let as_base = with_request_timeout(
+            &self.config,
+            pulls().base(branch_name).send(),
+        )
+        .await?;
+
+        Ok(as_head
+            .items
+            .into_iter()
+            .chain(as_base.items)
+            .map(|pr| match pr.state {
+                Some(octocrab::models::IssueState::Open) => {
+                    PullRequestState::Open
+                }
+                _ => PullRequestState::Closed,
+            })
+            .collect())
+    }
+
+    /// The head of the given Pull Request, from GitHub's REST API, which keeps
+    /// reporting it after the head branch was deleted.
+    pub async fn get_pull_request_head(
+        &self,
+        number: u64,
+    ) -> Result<PullRequestHead> {
+        let octocrab = octocrab::instance();
+        let pr = with_request_timeout(
+            &self.config,
+            octocrab
+                .pulls(self.config.owner.clone(), self.config.repo.clone())
+                .get(number),
+        )
+        .await?;
+
+        let branch =
+            self.config.new_github_branch_from_ref(&pr.head.ref_field)?;
+        let repository = pr
+            .head
+            .repo
+            .as_ref()
+            .and_then(|repo| repo.full_name.clone());
+        let branch_exists = match repository
+            .as_ref()
+            .and_then(|repository| repository.split_once('/'))
+        {
+            Some((owner, repo)) => {
+                let reference = octocrab::params::repos::Reference::Branch(
+                    branch.branch_name().to_string(),
+                );
+                let handler = octocrab.repos(owner, repo);
+                with_request_timeout(&self.config, async {
+                    match handler.get_ref(&reference).await {
+                        Ok(_) => Ok(true),
+                        Err(octocrab::Error::GitHub { source, .. })
+                            if source.message == "Not Found" =>
+                        {
+                            Ok(false)
+                        }
+                        Err(error) => Err(error),
+                    }
+                })
+                .await?
+            }
+            None => false,
+        };
+
+        Ok(PullRequestHead {
+            state: match pr.state {
+                Some(octocrab::models::IssueState::Open) => {
+                    PullRequestState::Open
+                }
+                _ => PullRequestState::Closed,
+            },
+            merged: pr.merged_at.is_some(),
+            branch,
+            oid: git2::Oid::from_str(&pr.head.sha)?,
+            repository,
+            branch_exists,
+        })
+    }
+
+    pub async fn add_comment(
+        &self,
+        number: u64,
+        comment: &IssueComment,
+    ) -> Result<()> {
+        #[derive(Deserialize)]
+        struct Ignore {}
+        let _: Ignore = with_request_timeout(
+            &self.config,
+            octocrab::instance().post(
+                format!(
+                    "repos/{}/{}/issues/{}/comments",
+                    self.config.owner, self.config.repo, number
+                ),
+                Some(comment),
+            ),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl Forge for GitHub {
+    async fn get_pull_requests(
+        &self,
+        numbers: impl IntoIterator<Item = Option<u64>>,
+    ) -> Vec<Result<Option<PullRequest>>> {
+        crate::utils::run_bounded(
+            self.config.concurrency,
+            numbers.into_iter().map(|number| async move {
+                match number {
+                    Some(number) => {
+                        self.get_pull_request(number).await.map(Some)
+                    }
+                    None => Ok(None),
+                }
+            }),
+        )
+        .await
+    }
+
+    async fn get_pull_request(&self, number: u64) -> Result<PullRequest> {
         let GitHub {
             config,
             git,
@@ -174,11 +551,8 @@ impl GitHub {
             number: number as i64,
         };
         let request_body = PullRequestQuery::build_query(variables);
-        let res = graphql_client
-            .post("https://api.github.com/graphql")
-            .json(&request_body)
-            .send()
-            .await?;
+        let res =
+            send_graphql_request(graphql_client, config, &request_body).await?;
         let response_body: Response<pull_request_query::ResponseData> =
             res.json().await?;
 
@@ -201,12 +575,49 @@ impl GitHub {
         let base = config.new_github_branch_from_ref(&pr.base_ref_name)?;
         let head = config.new_github_branch_from_ref(&pr.head_ref_name)?;
 
-        Git::fetch_from_remote(&[&head, &base], &config.remote_name).await?;
-
-        let base_oid = git.resolve_reference(base.local())?;
-        let head_oid = git.resolve_reference(head.local())?;
+        let (base_oid, head_oid) = {
+            let on_github = (
+                git2::Oid::from_str(&pr.base_ref_oid)?,
+                git2::Oid::from_str(&pr.head_ref_oid)?,
+            );
+            if known_branch_oid(git, config, &base, on_github.0)
+                && known_branch_oid(git, config, &head, on_github.1)
+            {
+                // We have seen both branches at these commits before, so there
+                // is nothing to fetch
+                on_github
+            } else {
+                Git::fetch_from_remote(&[&head, &base], &config.remote_name)
+                    .await?;
+                let fetched = (
+                    git.resolve_reference(base.local())?,
+                    git.resolve_reference(head.local())?,
+                );
+                crate::push_cache::record(
+                    git.repo().path(),
+                    &[
+                        (base.on_github(), fetched.0),
+                        (head.on_github(), fetched.1),
+                    ],
+                )?;
+                fetched
+            }
+        };
 
-        let mut sections = parse_message(&pr.body, MessageSection::Summary);
+        let (body, marked_pr_stack) =
+            crate::message::take_marked_pr_stack(&pr.body);
+        let body = if config.depends_on_line {
+            crate::message::strip_depends_on_line(&body)
+        } else {
+            body
+        };
+        let mut sections = parse_message(
+            &crate::message::unmark_description(&body),
+            MessageSection::Summary,
+        );
+        if let Some(pr_stack) = marked_pr_stack {
+            sections.insert(MessageSection::PRStack, pr_stack);
+        }
 
         let title = pr.title.trim().to_string();
         sections.insert(
@@ -299,14 +710,46 @@ impl GitHub {
             );
         }
 
+        let author = pr.author.and_then(|author| {
+            type AuthorType =
+                pull_request_query::PullRequestQueryRepositoryPullRequestAuthorOn;
+            match author.on {
+                AuthorType::User(user) => Some(CommitIdentity {
+                    name: user
+                        .name
+                        .filter(|name| !name.is_empty())
+                        .unwrap_or_else(|| author.login.clone()),
+                    // Users who keep their email address private are
+                    // identified by their GitHub noreply address.
+                    email: if user.email.is_empty() {
+                        match user.database_id {
+                            Some(id) => format!(
+                                "{}+{}@users.noreply.github.com",
+                                id, author.login
+                            ),
+                            None => format!(
+                                "{}@users.noreply.github.com",
+                                author.login
+                            ),
+                        }
+                    } else {
+                        user.email
+                    },
+                }),
+                _ => None,
+            }
+        });
+
         Ok::<_, Error>(PullRequest {
             number: pr.number as u64,
+            node_id: pr.id,
             state: match pr.state {
                 pull_request_query::PullRequestState::OPEN => {
                     PullRequestState::Open
                 }
                 _ => PullRequestState::Closed,
             },
+            draft: pr.is_draft,
             title: pr.title,
             body: Some(pr.body),
             sections,
@@ -316,76 +759,130 @@ impl GitHub {
             head_oid,
             reviewers,
             review_status,
+            author,
             merge_commit: pr
                 .merge_commit
                 .and_then(|sha| git2::Oid::from_str(&sha.oid).ok()),
         })
     }
 
-    pub async fn create_pull_request(
+    async fn create_pull_request(
         &self,
         message: &MessageSectionsMap,
         base_ref_name: String,
         head_ref_name: String,
         draft: bool,
     ) -> Result<u64> {
-        let number = octocrab::instance()
-            .pulls(self.config.owner.clone(), self.config.repo.clone())
-            .create(
-                message
-                    .get(&MessageSection::Title)
-                    .unwrap_or(&String::new()),
-                head_ref_name,
-                base_ref_name,
-            )
-            .body(build_github_body(message))
-            .draft(Some(draft))
-            .send()
-            .await?
-            .number;
+        let number = with_request_timeout(
+            &self.config,
+            octocrab::instance()
+                .pulls(self.config.owner.clone(), self.config.repo.clone())
+                .create(
+                    build_github_title(message, &self.config)
+                        .unwrap_or_default(),
+                    head_ref_name,
+                    base_ref_name,
+                )
+                .body(build_github_body(message, &self.config))
+                .draft(Some(draft))
+                .send(),
+        )
+        .await?
+        .number;
 
         Ok(number)
     }
 
-    pub async fn update_pull_request(
+    async fn update_pull_request(
         &self,
         number: u64,
         updates: &PullRequestUpdate,
     ) -> Result<()> {
-        octocrab::instance()
-            .patch::<octocrab::models::pulls::PullRequest, _, _>(
-                format!(
-                    "repos/{}/{}/pulls/{}",
-                    self.config.owner, self.config.repo, number
+        with_request_timeout(
+            &self.config,
+            octocrab::instance()
+                .patch::<octocrab::models::pulls::PullRequest, _, _>(
+                    format!(
+                        "repos/{}/{}/pulls/{}",
+                        self.config.owner, self.config.repo, number
+                    ),
+                    Some(&updates),
                 ),
-                Some(&updates),
-            )
-            .await?;
+        )
+        .await?;
 
         Ok(())
     }
 
-    pub async fn request_reviewers(
+    async fn request_reviewers(
         &self,
         number: u64,
         reviewers: PullRequestRequestReviewers,
     ) -> Result<()> {
         #[derive(Deserialize)]
         struct Ignore {}
-        let _: Ignore = octocrab::instance()
-            .post(
+        let _: Ignore = with_request_timeout(
+            &self.config,
+            octocrab::instance().post(
                 format!(
                     "repos/{}/{}/pulls/{}/requested_reviewers",
                     self.config.owner, self.config.repo, number
                 ),
                 Some(&reviewers),
-            )
-            .await?;
+            ),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn add_assignees(
+        &self,
+        number: u64,
+        assignees: Vec<String>,
+    ) -> Result<()> {
+        #[derive(serde::Serialize)]
+        struct Assignees {
+            assignees: Vec<String>,
+        }
+        #[derive(Deserialize)]
+        struct Ignore {}
+        let _: Ignore = with_request_timeout(
+            &self.config,
+            octocrab::instance().post(
+                format!(
+                    "repos/{}/{}/issues/{}/assignees",
+                    self.config.owner, self.config.repo, number
+                ),
+                Some(&Assignees { assignees }),
+            ),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn add_labels(&self, number: u64, labels: Vec<String>) -> Result<()> {
+        #[derive(serde::Serialize)]
+        struct Labels {
+            labels: Vec<String>,
+        }
+        let _: Vec<octocrab::models::Label> = with_request_timeout(
+            &self.config,
+            octocrab::instance().post(
+                format!(
+                    "repos/{}/{}/issues/{}/labels",
+                    self.config.owner, self.config.repo, number
+                ),
+                Some(&Labels { labels }),
+            ),
+        )
+        .await?;
 
         Ok(())
     }
 
-    pub async fn get_pull_request_mergeability(
+    async fn get_pull_request_mergeability(
         &self,
         number: u64,
     ) -> Result<PullRequestMergeability> {
@@ -395,12 +892,12 @@ impl GitHub {
             number: number as i64,
         };
         let request_body = PullRequestMergeabilityQuery::build_query(variables);
-        let res = self
-            .graphql_client
-            .post("https://api.github.com/graphql")
-            .json(&request_body)
-            .send()
-            .await?;
+        let res = send_graphql_request(
+            &self.graphql_client,
+            &self.config,
+            &request_body,
+        )
+        .await?;
         let response_body: Response<
             pull_request_mergeability_query::ResponseData,
         > = res.json().await?;
@@ -436,6 +933,512 @@ impl GitHub {
             .and_then(|sha| git2::Oid::from_str(&sha.oid).ok()),
         })
     }
+
+    async fn get_pull_request_checks(
+        &self,
+        number: u64,
+    ) -> Result<PullRequestChecks> {
+        use pull_request_checks_query::{
+            CheckConclusionState,
+            PullRequestChecksQueryRepositoryPullRequestCommitsNodesCommitStatusCheckRollupContextsNodes as Context,
+            StatusState,
+        };
+
+        let variables = pull_request_checks_query::Variables {
+            name: self.config.repo.clone(),
+            owner: self.config.owner.clone(),
+            number: number as i64,
+        };
+        let request_body = PullRequestChecksQuery::build_query(variables);
+        let res = send_graphql_request(
+            &self.graphql_client,
+            &self.config,
+            &request_body,
+        )
+        .await?;
+        let response_body: Response<pull_request_checks_query::ResponseData> =
+            res.json().await?;
+
+        if let Some(errors) = response_body.errors {
+            let error =
+                Err(Error::new(format!("querying PR #{number} checks failed")));
+            return errors
+                .into_iter()
+                .fold(error, |err, e| err.context(e.to_string()));
+        }
+
+        let pr = response_body
+            .data
+            .ok_or_else(|| Error::new("failed to fetch PR"))?
+            .repository
+            .ok_or_else(|| Error::new("failed to find repository"))?
+            .pull_request
+            .ok_or_else(|| Error::new("failed to find PR"))?;
+
+        let rollup = pr
+            .commits
+            .nodes
+            .into_iter()
+            .flatten()
+            .flatten()
+            .last()
+            .and_then(|node| node.commit.status_check_rollup);
+
+        let (state, failed, unmet_required) = match rollup {
+            None => (ChecksState::NoChecks, Vec::new(), Vec::new()),
+            Some(rollup) => {
+                let state = match rollup.state {
+                    StatusState::SUCCESS => ChecksState::Success,
+                    StatusState::ERROR | StatusState::FAILURE => {
+                        ChecksState::Failure
+                    }
+                    _ => ChecksState::Pending,
+                };
+                let mut failed = Vec::new();
+                let mut unmet_required = Vec::new();
+                for context in
+                    rollup.contexts.nodes.into_iter().flatten().flatten()
+                {
+                    let (name, is_failed, is_passed, is_required) =
+                        match context {
+                            Context::CheckRun(run) => {
+                                use CheckConclusionState::*;
+                                let conclusion = run.conclusion;
+                                (
+                                    run.name,
+                                    matches!(
+                                        conclusion,
+                                        Some(
+                                            ACTION_REQUIRED
+                                                | CANCELLED
+                                                | FAILURE
+                                                | STARTUP_FAILURE
+                                                | TIMED_OUT
+                                        )
+                                    ),
+                                    matches!(
+                                        conclusion,
+                                        Some(SUCCESS | NEUTRAL | SKIPPED)
+                                    ),
+                                    run.is_required,
+                                )
+                            }
+                            Context::StatusContext(status) => (
+                                status.context,
+                                matches!(
+                                    status.state,
+                                    StatusState::ERROR | StatusState::FAILURE
+                                ),
+                                matches!(status.state, StatusState::SUCCESS),
+                                status.is_required,
+                            ),
+                        };
+                    if is_required && !is_passed {
+                        unmet_required.push(name.clone());
+                    }
+                    if is_failed {
+                        failed.push(name);
+                    }
+                }
+
+                (state, failed, unmet_required)
+            }
+        };
+
+        Ok(PullRequestChecks {
+            head_oid: git2::Oid::from_str(&pr.head_ref_oid)?,
+            state,
+            failed,
+            unmet_required,
+        })
+    }
+
+    async fn rename_branch(&self, from: &str, to: &str) -> Result<()> {
+        #[derive(serde::Serialize)]
+        struct Rename<'a> {
+            new_name: &'a str,
+        }
+        #[derive(Deserialize)]
+        struct Ignore {}
+        let _: Ignore = with_request_timeout(
+            &self.config,
+            octocrab::instance().post(
+                format!(
+                    "repos/{}/{}/branches/{}/rename",
+                    self.config.owner, self.config.repo, from
+                ),
+                Some(&Rename { new_name: to }),
+            ),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn merge_pull_request(
+        &self,
+        number: u64,
+        request: &MergeRequest,
+    ) -> Result<crate::forge::MergeOutcome> {
+        let merge: octocrab::models::pulls::Merge = with_request_timeout(
+            &self.config,
+            octocrab::instance().put(
+                format!(
+                    "repos/{}/{}/pulls/{}/merge",
+                    self.config.owner, self.config.repo, number
+                ),
+                Some(request),
+            ),
+        )
+        .await?;
+
+        Ok(crate::forge::MergeOutcome {
+            merged: merge.merged,
+            sha: merge.sha,
+            message: merge.message,
+        })
+    }
+
+    async fn current_user_login(&self) -> Result<String> {
+        crate::timings::count_api_call();
+        Ok(octocrab::instance().current().user().await?.login)
+    }
+
+    async fn mark_ready_for_review(
+        &self,
+        pull_request: &PullRequest,
+    ) -> Result<()> {
+        let variables = mark_ready_for_review_mutation::Variables {
+            id: pull_request.node_id.clone(),
+        };
+        let request_body = MarkReadyForReviewMutation::build_query(variables);
+        let res = send_graphql_request(
+            &self.graphql_client,
+            &self.config,
+            &request_body,
+        )
+        .await?;
+        let response_body: Response<
+            mark_ready_for_review_mutation::ResponseData,
+        > = res.json().await?;
+
+        if let Some(errors) = response_body.errors {
+            let error = Err(Error::new(format!(
+                "marking PR #{} as ready for review failed",
+                pull_request.number
+            )));
+            return errors
+                .into_iter()
+                .fold(error, |err, e| err.context(e.to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn find_project(&self, name: &str) -> Result<Project> {
+        use projects_query::ProjectsQueryRepositoryOwner as Owner;
+
+        let owner = &self.config.owner;
+        let is_number = name.trim().trim_start_matches('#').parse::<u64>();
+        let variables = projects_query::Variables {
+            owner: owner.clone(),
+            // Titles narrow the search down; numbers are looked for among
+            // all projects
+            query: is_number.is_err().then(|| name.trim().to_string()),
+        };
+        let request_body = ProjectsQuery::build_query(variables);
+        let res = send_graphql_request(
+            &self.graphql_client,
+            &self.config,
+            &request_body,
+        )
+        .await?;
+        let response_body: Response<projects_query::ResponseData> =
+            res.json().await?;
+
+        if let Some(errors) = response_body.errors {
+            let error = Err(Error::new(format!(
+                "querying the projects of {} failed",
+                owner
+            )));
+            return errors
+                .into_iter()
+                .fold(error, |err, e| err.context(e.to_string()));
+        }
+
+        let projects =
+            match response_body.data.and_then(|data| data.repository_owner) {
+                Some(Owner::Organization(owner)) => owner.projects_v2,
+                Some(Owner::User(owner)) => owner.projects_v2,
+                None => {
+                    return Err(Error::new(format!("failed to find {}", owner)))
+                }
+            };
+        let projects = projects
+            .nodes
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|node| Project {
+                id: node.id,
+                number: node.number as u64,
+                title: node.title,
+                closed: node.closed,
+            })
+            .collect();
+
+        resolve_project(projects, name, owner)
+    }
+
+    async fn add_to_project(
+        &self,
+        project: &Project,
+        pull_request: &PullRequest,
+    ) -> Result<()> {
+        let request_body =
+            add_project_item_query(project, &pull_request.node_id);
+        let res = send_graphql_request(
+            &self.graphql_client,
+            &self.config,
+            &request_body,
+        )
+        .await?;
+        let response_body: Response<add_project_item_mutation::ResponseData> =
+            res.json().await?;
+
+        if let Some(errors) = response_body.errors {
+            let error = Err(Error::new(format!(
+                "adding PR #{} to project '{}' failed",
+                pull_request.number, project.title
+            )));
+            return errors
+                .into_iter()
+                .fold(error, |err, e| err.context(e.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Send a request to GitHub's GraphQL API.
+///
+/// When GitHub responds that we hit a (secondary) rate limit and tells us how
+/// long to wait via the `Retry-After` header, we wait that long and then retry
+/// the request once.
+pub async fn send_graphql_request<Q: serde::Serialize + ?Sized>(
+    client: &reqwest::Client,
+    config: &crate::config::Config,
+    body: &Q,
+) -> Result<reqwest::Response> {
+    // When authenticating as a GitHub App, the token changes during the run,
+    // so it is set on each request (over the one in the default headers).
+    let token = match crate::github_app::instance() {
+        Some(auth) => Some(auth.token().await?),
+        None => None,
+    };
+    let url = config.graphql_url();
+    let send = || {
+        crate::timings::count_api_call();
+        let request = client.post(&url).json(body);
+        match &token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+        .send()
+    };
+
+    let response = send().await?;
+    let status = response.status();
+    if status != reqwest::StatusCode::FORBIDDEN
+        && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+    {
+        return Ok(response);
+    }
+
+    let delay = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, SystemTime::now()));
+    let delay = match delay {
+        Some(delay) => delay,
+        None => return Ok(response),
+    };
+
+    if delay > MAX_RETRY_AFTER {
+        return Err(Error::new(format!(
+            "GitHub rate limit exceeded; GitHub asks to retry in {} seconds, \
+             which is longer than the maximum wait of {} seconds",
+            delay.as_secs(),
+            MAX_RETRY_AFTER.as_secs()
+        )));
+    }
+
+    crate::output::output(
+        "⏳",
+        &format!(
+            "GitHub rate limit exceeded, retrying in {} seconds",
+            delay.as_secs()
+        ),
+    )?;
+    tokio::time::sleep(delay).await;
+
+    Ok(send().await?)
+}
+
+/// One page of results of a paginated GraphQL query.
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Where to continue for the next page (`pageInfo.endCursor`), if there
+    /// is one (`pageInfo.hasNextPage`)
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn new(
+        items: Vec<T>,
+        has_next_page: bool,
+        end_cursor: Option<String>,
+    ) -> Self {
+        Self {
+            items,
+            next_cursor: end_cursor.filter(|_| has_next_page),
+        }
+    }
+}
+
+/// Fetch all pages of a paginated GraphQL query and return all their items.
+/// `fetch_page` is called with the cursor to continue after (`None` for the
+/// first page). Fails if there are more than `max_pages` pages.
+pub async fn fetch_all_pages<T, F, Fut>(
+    max_pages: usize,
+    mut fetch_page: F,
+) -> Result<Vec<T>>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: std::future::Future<Output = Result<Page<T>>>,
+{
+    let mut items = Vec::new();
+    let mut cursor = None;
+
+    for _ in 0..max_pages {
+        let page = fetch_page(cursor).await?;
+        items.extend(page.items);
+
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            return Ok(items);
+        }
+    }
+
+    Err(Error::new(format!(
+        "GitHub returned more than {} pages of results - giving up",
+        max_pages
+    )))
+}
+
+/// Make the global octocrab instance authenticate with the given token.
+pub fn initialise_octocrab(
+    config: &crate::config::Config,
+    github_auth_token: &str,
+) -> Result<()> {
+    octocrab::initialise(octocrab_builder(config, github_auth_token)?)?;
+
+    Ok(())
+}
+
+/// The builder for an octocrab instance talking to the REST API of our GitHub
+/// host.
+fn octocrab_builder(
+    config: &crate::config::Config,
+    github_auth_token: &str,
+) -> Result<octocrab::OctocrabBuilder> {
+    Ok(octocrab::Octocrab::builder()
+        .base_url(config.api_url())?
+        .personal_token(github_auth_token.to_string())
+        .add_header(reqwest::header::USER_AGENT, config.user_agent.clone()))
+}
+
+/// Construct the HTTP client used for GitHub's GraphQL API.
+pub fn build_graphql_client(
+    config: &crate::config::Config,
+    github_auth_token: &str,
+) -> Result<reqwest::Client> {
+    Ok(http_client_builder(config)
+        .default_headers(graphql_headers(config, github_auth_token)?)
+        .build()?)
+}
+
+/// A builder for HTTP clients talking to GitHub, with the configured connect
+/// and request timeouts applied.
+pub(crate) fn http_client_builder(
+    config: &crate::config::Config,
+) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder();
+    if let Some(timeout) = config.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = config.request_timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    builder
+}
+
+/// Await a request made through octocrab, failing if it takes longer than the
+/// configured request timeout. (Unlike our GraphQL client, octocrab's HTTP
+/// client cannot be configured with timeouts.)
+pub async fn with_request_timeout<T, E>(
+    config: &crate::config::Config,
+    request: impl std::future::Future<Output = std::result::Result<T, E>>,
+) -> Result<T>
+where
+    E: std::error::Error,
+{
+    crate::timings::count_api_call();
+    let result = match config.request_timeout {
+        Some(timeout) => {
+            tokio::time::timeout(timeout, request).await.map_err(|_| {
+                Error::new(format!(
+                    "Request to GitHub timed out after {} seconds",
+                    timeout.as_secs_f32()
+                ))
+            })?
+        }
+        None => request.await,
+    };
+
+    Ok(result?)
+}
+
+/// The default headers for requests to GitHub's GraphQL API.
+pub fn graphql_headers(
+    config: &crate::config::Config,
+    github_auth_token: &str,
+) -> Result<reqwest::header::HeaderMap> {
+    use reqwest::header;
+
+    let mut headers = header::HeaderMap::new();
+    headers.insert(header::ACCEPT, "application/json".parse()?);
+    headers.insert(header::USER_AGENT, config.user_agent.parse()?);
+    headers.insert(
+        header::AUTHORIZATION,
+        format!("Bearer {}", github_auth_token).parse()?,
+    );
+
+    Ok(headers)
+}
+
+/// Parse the value of a `Retry-After` header, which is either a number of
+/// seconds or an HTTP date. Dates in the past result in a zero delay.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
 }
 
 #[derive(Debug, Clone)]
@@ -506,10 +1509,88 @@ impl GitHubBranch {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
+    pub(crate) fn pull_request_factory(
+        config: &crate::config::Config,
+        number: u64,
+        head: &str,
+        base: &str,
+    ) -> PullRequest {
+        PullRequest {
+            number,
+            node_id: format!("PR_{}", number),
+            state: PullRequestState::Open,
+            draft: false,
+            title: format!("PR {}", number),
+            body: None,
+            sections: Default::default(),
+            base: config.new_github_branch(base),
+            head: config.new_github_branch(head),
+            base_oid: git2::Oid::zero(),
+            head_oid: git2::Oid::zero(),
+            merge_commit: None,
+            reviewers: Default::default(),
+            review_status: None,
+            author: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_request_body() {
+        let request = MergeRequest {
+            commit_title: "Title (#1)".to_string(),
+            commit_message: "Summary\n".to_string(),
+            sha: git2::Oid::zero().to_string(),
+            merge_method: crate::forge::MergeMethod::Squash,
+        };
+
+        // As GitHub's merge API expects it
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["merge_method"],
+            "squash"
+        );
+    }
+
+    #[test]
+    fn test_same_text() {
+        assert!(same_text("Title", "Title"));
+        assert!(same_text("One\nTwo\n", "One\r\nTwo\r\n"));
+        assert!(same_text("One  \nTwo", "One\nTwo\t"));
+        assert!(same_text("One\nTwo\n", "One\nTwo\n\n\n"));
+        assert!(same_text("", "\r\n"));
+
+        assert!(!same_text("One\nTwo", "One Two"));
+        assert!(!same_text("One\n\nTwo", "One\nTwo"));
+        // Indentation matters in Markdown
+        assert!(!same_text("One\n  Two", "One\nTwo"));
+    }
+
+    #[test]
+    fn test_update_message_ignores_line_endings() {
+        let config = crate::config::tests::config_factory();
+        let message = crate::message::parse_message(
+            "Title\n\nSome prose\n\nTest Plan: ran it",
+            MessageSection::Title,
+        );
+        let mut pull_request =
+            pull_request_factory(&config, 1, "spr/foo/title", "master");
+        pull_request.title = "Title".to_string();
+        pull_request.body =
+            Some(build_github_body(&message, &config).replace('\n', " \r\n"));
+
+        let mut update = PullRequestUpdate::default();
+        update.update_message(&pull_request, &message, &config);
+        assert!(update.is_empty());
+
+        pull_request.body = Some("Other prose".to_string());
+        update.update_message(&pull_request, &message, &config);
+        assert_eq!(update.body, Some(build_github_body(&message, &config)));
+        assert!(update.title.is_none());
+    }
+
     #[test]
     fn test_new_from_ref_with_branch_name() {
         let r =
@@ -615,4 +1696,263 @@ mod tests {
         assert_eq!(r.branch_name(), "refs/heads/foo");
         assert!(!r.is_master_branch());
     }
+
+    #[test]
+    fn test_parse_retry_after_seconds() {
+        let now = SystemTime::now();
+        assert_eq!(parse_retry_after("30", now), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("-5", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after_http_date() {
+        let now =
+            httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:45 GMT", now),
+            Some(Duration::from_secs(45))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+    }
+
+    fn project(number: u64, title: &str, closed: bool) -> Project {
+        Project {
+            id: format!("PVT_{}", number),
+            number,
+            title: title.to_string(),
+            closed,
+        }
+    }
+
+    #[test]
+    fn test_resolve_project() {
+        let projects = vec![
+            project(1, "Roadmap", false),
+            project(2, "Bugs", false),
+            project(3, "Bugs", false),
+            project(4, "Q1 planning", true),
+        ];
+        let resolve =
+            |name: &str| resolve_project(projects.clone(), name, "acme");
+
+        assert_eq!(resolve("roadmap").unwrap(), projects[0]);
+        assert_eq!(resolve(" Roadmap ").unwrap(), projects[0]);
+        assert_eq!(resolve("2").unwrap(), projects[1]);
+        assert_eq!(resolve("#3").unwrap(), projects[2]);
+        // Closed projects are only found by their number
+        assert_eq!(resolve("4").unwrap(), projects[3]);
+
+        let error = resolve("Bugs").unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &["acme has more than one project 'Bugs' (#2, #3); give its \
+               number instead"
+                .to_string()]
+        );
+        for name in ["Q1 planning", "Backlog", "5"] {
+            let error = resolve(name).unwrap_err();
+            assert!(error.messages()[0]
+                .starts_with(&format!("acme has no open project '{}'", name)));
+        }
+    }
+
+    #[test]
+    fn test_add_project_item_query() {
+        let query =
+            add_project_item_query(&project(5, "Roadmap", false), "PR_kwDO7");
+
+        assert_eq!(query.operation_name, "AddProjectItemMutation");
+        assert!(query.query.contains("addProjectV2ItemById"));
+        assert_eq!(
+            serde_json::to_value(&query.variables).unwrap(),
+            serde_json::json!({ "project": "PVT_5", "content": "PR_kwDO7" })
+        );
+    }
+
+    #[test]
+    fn test_graphql_headers_user_agent() {
+        let mut config = crate::config::tests::config_factory();
+        let headers = graphql_headers(&config, "token").unwrap();
+        assert_eq!(
+            headers.get(reqwest::header::USER_AGENT).unwrap(),
+            &format!(
+                "spr/{} (+https://github.com/getcord/spr)",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(
+            headers.get(reqwest::header::AUTHORIZATION).unwrap(),
+            "Bearer token"
+        );
+
+        config.user_agent = "acme-spr/1.0".to_string();
+        let headers = graphql_headers(&config, "token").unwrap();
+        assert_eq!(
+            headers.get(reqwest::header::USER_AGENT).unwrap(),
+            "acme-spr/1.0"
+        );
+    }
+
+    /// Accept connections on a local port, but never respond to requests.
+    fn unresponsive_server() -> std::net::SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let connections: Vec<_> = listener.incoming().collect();
+            drop(connections);
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_graphql_client_request_timeout() {
+        let mut config = crate::config::tests::config_factory();
+        config.request_timeout = Some(Duration::from_millis(100));
+        let client = build_graphql_client(&config, "token").unwrap();
+        let addr = unresponsive_server();
+
+        let error = client
+            .post(format!("http://{}/graphql", addr))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(error.is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_octocrab_github_host() {
+        let mut config = crate::config::tests::config_factory();
+        config.github_host = "github.acme.com".to_string();
+        let octocrab =
+            octocrab_builder(&config, "token").unwrap().build().unwrap();
+
+        assert_eq!(
+            octocrab.absolute_url("repos/acme/codez").unwrap().as_str(),
+            "https://github.acme.com/api/v3/repos/acme/codez"
+        );
+    }
+
+    #[test]
+    fn test_http_client_builder_timeouts() {
+        let mut config = crate::config::tests::config_factory();
+        config.connect_timeout = Some(Duration::from_secs(3));
+        config.request_timeout = Some(Duration::from_secs(7));
+        let builder = format!("{:?}", http_client_builder(&config));
+        assert!(builder.contains("connect_timeout: 3s"), "{}", builder);
+        assert!(builder.contains("timeout: 7s"), "{}", builder);
+
+        config.connect_timeout = None;
+        config.request_timeout = None;
+        let builder = format!("{:?}", http_client_builder(&config));
+        assert!(!builder.contains("timeout"), "{}", builder);
+    }
+
+    #[tokio::test]
+    async fn test_with_request_timeout() {
+        let mut config = crate::config::tests::config_factory();
+        config.request_timeout = Some(Duration::from_millis(100));
+
+        let result = with_request_timeout(
+            &config,
+            std::future::pending::<std::result::Result<(), std::fmt::Error>>(),
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().messages(),
+            &["Request to GitHub timed out after 0.1 seconds".to_string()]
+        );
+
+        let result = with_request_timeout(
+            &config,
+            std::future::ready(Ok::<_, std::fmt::Error>(42)),
+        )
+        .await;
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_pages() {
+        let mut cursors = Vec::new();
+        let items = fetch_all_pages(MAX_PAGES, |cursor| {
+            cursors.push(cursor.clone());
+            std::future::ready(Ok(match cursor.as_deref() {
+                None => Page::new(vec![1, 2], true, Some("c1".into())),
+                Some("c1") => Page::new(vec![3], false, Some("c2".into())),
+                Some(cursor) => panic!("unexpected cursor {}", cursor),
+            }))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(items, vec![1, 2, 3]);
+        assert_eq!(cursors, vec![None, Some("c1".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_pages_max_pages() {
+        let mut pages = 0;
+        let result = fetch_all_pages(5, |_| {
+            pages += 1;
+            // A misbehaving server that always claims there is another page
+            std::future::ready(Ok(Page::new(
+                vec![pages],
+                true,
+                Some("again".into()),
+            )))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(pages, 5);
+    }
+
+    #[test]
+    fn test_sort_requested_reviewers() {
+        let names = |names: &[&str]| -> Vec<String> {
+            names.iter().map(|s| s.to_string()).collect()
+        };
+        let mut one = PullRequestRequestReviewers {
+            reviewers: names(&["zoe", "Alice", "bob"]),
+            team_reviewers: names(&["web", "Core"]),
+        };
+        let mut two = PullRequestRequestReviewers {
+            reviewers: names(&["bob", "zoe", "Alice"]),
+            team_reviewers: names(&["Core", "web"]),
+        };
+        one.sort();
+        two.sort();
+
+        assert_eq!(one, two);
+        assert_eq!(one.reviewers, names(&["Alice", "bob", "zoe"]));
+        assert_eq!(one.team_reviewers, names(&["Core", "web"]));
+    }
+
+    #[test]
+    fn test_known_branch_oid() {
+        let mut config = crate::config::tests::config_factory();
+        let (_dir, git) = crate::git::tests::test_repo();
+        let oid = git.resolve_reference("refs/heads/master").unwrap();
+        let branch = config.new_github_branch("spr/foo/one");
+
+        // Not in the cache yet
+        assert!(!known_branch_oid(&git, &config, &branch, oid));
+
+        crate::push_cache::record(
+            git.repo().path(),
+            &[(branch.on_github(), oid)],
+        )
+        .unwrap();
+        assert!(known_branch_oid(&git, &config, &branch, oid));
+        // GitHub has a commit on the branch that we have not seen
+        assert!(!known_branch_oid(&git, &config, &branch, git2::Oid::zero()));
+
+        config.refresh = true;
+        assert!(!known_branch_oid(&git, &config, &branch, oid));
+    }
 }
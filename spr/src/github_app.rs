@@ -0,0 +1,380 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::{
+    path::PathBuf,
+    sync::{OnceLock, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use crate::error::{Error, Result, ResultExt};
+
+/// How long before its expiry we replace an installation token, so that a
+/// request started with it does not fail because it expires on the way.
+pub const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// GitHub does not accept JSON Web Tokens that are valid for more than ten
+/// minutes. Ours is valid for nine, and from a minute in the past, to allow
+/// for some clock drift between us and GitHub.
+const JWT_VALIDITY: Duration = Duration::from_secs(9 * 60);
+const JWT_CLOCK_DRIFT: Duration = Duration::from_secs(60);
+
+/// The GitHub App (and its installation for the repository) to authenticate
+/// as, instead of using a personal access token.
+#[derive(Clone, Debug)]
+pub struct GitHubApp {
+    pub app_id: u64,
+    pub installation_id: u64,
+    /// Path of the file with the App's private key (in PEM format)
+    pub private_key_path: PathBuf,
+}
+
+/// The claims of the JSON Web Token that authenticates us as the GitHub App,
+/// for requesting installation tokens.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct JwtClaims {
+    /// The App id
+    pub iss: u64,
+    /// Issued at (seconds since the Unix epoch)
+    pub iat: u64,
+    /// Expires at (seconds since the Unix epoch)
+    pub exp: u64,
+}
+
+impl JwtClaims {
+    pub fn new(app_id: u64, now: SystemTime) -> Self {
+        let now = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Self {
+            iss: app_id,
+            iat: now.saturating_sub(JWT_CLOCK_DRIFT.as_secs()),
+            exp: now + JWT_VALIDITY.as_secs(),
+        }
+    }
+}
+
+/// Turns the claims into a signed JSON Web Token.
+pub trait JwtSigner: Send + Sync {
+    fn sign(&self, claims: &JwtClaims) -> Result<String>;
+}
+
+/// Signs JSON Web Tokens with the App's RSA private key, as GitHub requires.
+pub struct RsaJwtSigner {
+    key: jsonwebtoken::EncodingKey,
+}
+
+impl RsaJwtSigner {
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        Ok(Self {
+            key: jsonwebtoken::EncodingKey::from_rsa_pem(pem)
+                .reword("Invalid GitHub App private key".to_string())?,
+        })
+    }
+}
+
+impl JwtSigner for RsaJwtSigner {
+    fn sign(&self, claims: &JwtClaims) -> Result<String> {
+        Ok(jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+            claims,
+            &self.key,
+        )?)
+    }
+}
+
+/// A token for making API requests as an installation of the GitHub App.
+#[derive(Clone, Debug)]
+pub struct InstallationToken {
+    pub token: String,
+    pub expires_at: SystemTime,
+}
+
+/// Whether we need a new installation token, because we do not have one yet,
+/// or the one we have is about to expire.
+pub fn needs_refresh(
+    token: Option<&InstallationToken>,
+    now: SystemTime,
+) -> bool {
+    match token {
+        Some(token) => now + REFRESH_MARGIN >= token.expires_at,
+        None => true,
+    }
+}
+
+/// How long until the given token needs to be replaced.
+pub fn refresh_delay(token: &InstallationToken, now: SystemTime) -> Duration {
+    token
+        .expires_at
+        .duration_since(now + REFRESH_MARGIN)
+        .unwrap_or_default()
+}
+
+#[derive(serde::Deserialize)]
+struct AccessTokenResponse {
+    token: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Provides installation tokens for the GitHub App, requesting a new one
+/// whenever the current one is about to expire.
+pub struct InstallationAuth {
+    app_id: u64,
+    installation_id: u64,
+    signer: Box<dyn JwtSigner>,
+    config: crate::config::Config,
+    client: reqwest::Client,
+    token: RwLock<Option<InstallationToken>>,
+}
+
+impl InstallationAuth {
+    pub fn new(
+        app: &GitHubApp,
+        signer: Box<dyn JwtSigner>,
+        config: &crate::config::Config,
+    ) -> Result<Self> {
+        Ok(Self {
+            app_id: app.app_id,
+            installation_id: app.installation_id,
+            signer,
+            config: config.clone(),
+            client: crate::github::http_client_builder(config).build()?,
+            token: RwLock::new(None),
+        })
+    }
+
+    /// Set up authentication as the given App, reading its private key.
+    pub fn from_private_key_file(
+        app: &GitHubApp,
+        config: &crate::config::Config,
+    ) -> Result<Self> {
+        let pem = std::fs::read(&app.private_key_path).reword(format!(
+            "Could not read the GitHub App private key from {}",
+            app.private_key_path.display()
+        ))?;
+
+        Self::new(app, Box::new(RsaJwtSigner::from_pem(&pem)?), config)
+    }
+
+    /// The JSON Web Token authenticating us as the App.
+    fn jwt(&self, now: SystemTime) -> Result<String> {
+        self.signer.sign(&JwtClaims::new(self.app_id, now))
+    }
+
+    /// A current installation token. If the one we have is about to expire,
+    /// a new one is requested, and the global octocrab instance is switched
+    /// over to it.
+    pub async fn token(&self) -> Result<String> {
+        let now = SystemTime::now();
+        if let Some(token) = self.current_token(now) {
+            return Ok(token.token);
+        }
+
+        let token = self.request_token(now).await?;
+        crate::github::initialise_octocrab(&self.config, &token.token)?;
+        *self.token.write().unwrap() = Some(token.clone());
+
+        Ok(token.token)
+    }
+
+    /// The token we have, unless it needs to be replaced.
+    fn current_token(&self, now: SystemTime) -> Option<InstallationToken> {
+        let token = self.token.read().unwrap();
+        if needs_refresh(token.as_ref(), now) {
+            None
+        } else {
+            token.clone()
+        }
+    }
+
+    async fn request_token(
+        &self,
+        now: SystemTime,
+    ) -> Result<InstallationToken> {
+        let response = self
+            .client
+            .post(format!(
+                "{}app/installations/{}/access_tokens",
+                self.config.api_url(),
+                self.installation_id
+            ))
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .header(reqwest::header::USER_AGENT, &self.config.user_agent)
+            .bearer_auth(self.jwt(now)?)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::new(format!(
+                "Requesting a GitHub App installation token failed: {}",
+                status
+            )));
+        }
+
+        let response: AccessTokenResponse = response.json().await?;
+        Ok(InstallationToken {
+            token: response.token,
+            expires_at: response.expires_at.into(),
+        })
+    }
+
+    /// Keep replacing the installation token before it expires, so that
+    /// requests made through octocrab (which does not ask us for the token)
+    /// keep working in long runs.
+    pub async fn keep_fresh(&'static self) {
+        loop {
+            let delay = match self.token.read().unwrap().as_ref() {
+                Some(token) => refresh_delay(token, SystemTime::now()),
+                None => Duration::ZERO,
+            };
+            tokio::time::sleep(delay).await;
+
+            if self.token().await.is_err() {
+                // Try again in a bit; meanwhile, requests fail with an
+                // authentication error.
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+        }
+    }
+}
+
+static INSTANCE: OnceLock<InstallationAuth> = OnceLock::new();
+
+/// Make the given App authentication the one used for all requests to GitHub.
+pub fn initialise(auth: InstallationAuth) -> &'static InstallationAuth {
+    if INSTANCE.set(auth).is_err() {
+        panic!("GitHub App authentication is already initialised");
+    }
+
+    INSTANCE.get().unwrap()
+}
+
+/// The App authentication to use, if spr is configured to authenticate as a
+/// GitHub App.
+pub fn instance() -> Option<&'static InstallationAuth> {
+    INSTANCE.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Signs nothing, but remembers the claims it was asked to sign.
+    #[derive(Default)]
+    struct RecordingSigner {
+        claims: std::sync::Arc<Mutex<Vec<JwtClaims>>>,
+    }
+
+    impl JwtSigner for RecordingSigner {
+        fn sign(&self, claims: &JwtClaims) -> Result<String> {
+            let mut recorded = self.claims.lock().unwrap();
+            recorded.push(claims.clone());
+            Ok(format!("jwt-{}", recorded.len()))
+        }
+    }
+
+    fn app() -> GitHubApp {
+        GitHubApp {
+            app_id: 1234,
+            installation_id: 5678,
+            private_key_path: PathBuf::from("key.pem"),
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_jwt_claims() {
+        assert_eq!(
+            JwtClaims::new(1234, at(1_000_000)),
+            JwtClaims {
+                iss: 1234,
+                iat: 1_000_000 - 60,
+                exp: 1_000_000 + 9 * 60,
+            }
+        );
+    }
+
+    #[test]
+    fn test_jwt_signs_claims_for_app() {
+        let signer = RecordingSigner::default();
+        let claims = signer.claims.clone();
+        let auth = InstallationAuth::new(
+            &app(),
+            Box::new(signer),
+            &crate::config::tests::config_factory(),
+        )
+        .unwrap();
+
+        assert_eq!(auth.jwt(at(1_000_000)).unwrap(), "jwt-1");
+        assert_eq!(
+            claims.lock().unwrap().as_slice(),
+            &[JwtClaims::new(1234, at(1_000_000))]
+        );
+    }
+
+    #[test]
+    fn test_rsa_signer_rejects_invalid_key() {
+        assert!(RsaJwtSigner::from_pem(b"not a key").is_err());
+    }
+
+    #[test]
+    fn test_needs_refresh() {
+        let token = InstallationToken {
+            token: "token".to_string(),
+            expires_at: at(3600),
+        };
+
+        assert!(needs_refresh(None, at(0)));
+        assert!(!needs_refresh(Some(&token), at(0)));
+        assert!(!needs_refresh(Some(&token), at(3600 - 5 * 60 - 1)));
+        assert!(needs_refresh(Some(&token), at(3600 - 5 * 60)));
+        assert!(needs_refresh(Some(&token), at(3600)));
+        assert!(needs_refresh(Some(&token), at(7200)));
+    }
+
+    #[test]
+    fn test_refresh_delay() {
+        let token = InstallationToken {
+            token: "token".to_string(),
+            expires_at: at(3600),
+        };
+
+        assert_eq!(
+            refresh_delay(&token, at(0)),
+            Duration::from_secs(3600 - 5 * 60)
+        );
+        assert_eq!(refresh_delay(&token, at(3600)), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_token_reuses_current_token() {
+        let auth = InstallationAuth::new(
+            &app(),
+            Box::new(RecordingSigner::default()),
+            &crate::config::tests::config_factory(),
+        )
+        .unwrap();
+        *auth.token.write().unwrap() = Some(InstallationToken {
+            token: "current".to_string(),
+            expires_at: SystemTime::now() + Duration::from_secs(3600),
+        });
+
+        // No request is made to GitHub, since the token is still good
+        assert_eq!(auth.token().await.unwrap(), "current");
+        assert!(auth.current_token(SystemTime::now()).is_some());
+        assert!(auth
+            .current_token(SystemTime::now() + Duration::from_secs(3600))
+            .is_none());
+    }
+}
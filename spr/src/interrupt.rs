@@ -0,0 +1,269 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Cleaning up after Ctrl-C. A command interrupted halfway through may leave
+//! a `git rebase` in progress, or HEAD somewhere else than where it was. The
+//! cleanup aborts the rebase (if the command started it) and puts HEAD back.
+//! What was pushed to GitHub or changed there cannot be undone, so the
+//! commands record those steps as they complete them, and the cleanup lists
+//! them.
+
+use std::sync::Mutex;
+
+use git2::Oid;
+
+use crate::{
+    error::{Error, Result},
+    output::output,
+    utils::run_command,
+};
+
+/// The steps of the running command that changed things on GitHub, as they
+/// were completed.
+static COMPLETED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Note that the running command completed a step that cannot be undone (such
+/// as pushing a branch), to report it if the command is interrupted.
+pub fn record_completed(step: impl Into<String>) {
+    COMPLETED.lock().expect("poisoned mutex").push(step.into());
+}
+
+/// The steps the running command completed that cannot be undone, so far.
+pub fn completed() -> Vec<String> {
+    COMPLETED.lock().expect("poisoned mutex").clone()
+}
+
+fn take_completed() -> Vec<String> {
+    std::mem::take(&mut *COMPLETED.lock().expect("poisoned mutex"))
+}
+
+/// Where HEAD was when the command started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// The checked-out branch (e.g. `refs/heads/feature`), or `None` if HEAD
+    /// was detached
+    pub branch: Option<String>,
+    pub oid: Oid,
+}
+
+impl Checkpoint {
+    /// Where HEAD is now, or `None` if it does not point at a commit yet.
+    pub fn capture(git: &crate::git::Git) -> Option<Self> {
+        let repo = git.repo();
+        let head = repo.head().ok()?;
+        let oid = head.peel_to_commit().ok()?.id();
+        let branch = head
+            .is_branch()
+            .then(|| head.name().map(String::from))
+            .flatten();
+
+        Some(Checkpoint { branch, oid })
+    }
+
+    /// Check out the branch (at the commit) that was checked out at the
+    /// checkpoint again. Returns what was done.
+    pub async fn restore(&self, git: &crate::git::Git) -> Result<Vec<String>> {
+        let mut done = Vec::new();
+
+        if Checkpoint::capture(git).as_ref() != Some(self) {
+            let repo = git.repo();
+            let commit = repo.find_commit(self.oid)?;
+            repo.checkout_tree(commit.as_object(), None)?;
+            match &self.branch {
+                Some(branch) => {
+                    repo.reference(branch, self.oid, true, "spr interrupted")?;
+                    repo.set_head(branch)?;
+                }
+                None => repo.set_head_detached(self.oid)?,
+            }
+            done.push(format!(
+                "Put HEAD back on {} at {}",
+                self.branch
+                    .as_deref()
+                    .map(|branch| branch.trim_start_matches("refs/heads/"))
+                    .unwrap_or("the detached commit"),
+                commit.as_object().short_id()?.as_str().unwrap_or_default()
+            ));
+        }
+
+        Ok(done)
+    }
+}
+
+/// Whether a `git rebase` is in progress.
+pub fn rebase_in_progress(git: &crate::git::Git) -> bool {
+    let repo = git.repo();
+    ["rebase-merge", "rebase-apply"]
+        .iter()
+        .any(|dir| repo.path().join(dir).exists())
+}
+
+/// Clean up after the command was interrupted: abort the `git rebase` it left
+/// in progress, unless one was in progress before it started already, and
+/// restore the checkpoint. Returns what was done.
+async fn clean_up(
+    git: &crate::git::Git,
+    checkpoint: Option<&Checkpoint>,
+    rebasing_before: bool,
+) -> Result<Vec<String>> {
+    let mut done = Vec::new();
+
+    if !rebasing_before && rebase_in_progress(git) {
+        let workdir = git.repo().workdir().map(|path| path.to_path_buf());
+        let mut cmd = tokio::process::Command::new("git");
+        if let Some(workdir) = &workdir {
+            cmd.current_dir(workdir);
+        }
+        run_command(cmd.arg("rebase").arg("--abort")).await?;
+        done.push("Aborted the rebase in progress".to_string());
+    }
+    if let Some(checkpoint) = checkpoint {
+        done.extend(checkpoint.restore(git).await?);
+    }
+
+    Ok(done)
+}
+
+/// Run `operation`, and if Ctrl-C interrupts it, restore the checkpoint taken
+/// before it started and report what was and was not completed.
+pub async fn run_interruptible<T>(
+    git: &crate::git::Git,
+    operation: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    take_completed();
+    let checkpoint = Checkpoint::capture(git);
+    let rebasing_before = rebase_in_progress(git);
+
+    tokio::select! {
+        result = operation => result,
+        _ = tokio::signal::ctrl_c() => {
+            output("✋", "Interrupted - cleaning up")?;
+            let restored =
+                clean_up(git, checkpoint.as_ref(), rebasing_before).await;
+            for step in completed() {
+                output("✔️", &format!("{} (this cannot be undone)", step))?;
+            }
+
+            let mut error = Error::new("Interrupted");
+            match restored {
+                Ok(restored) => {
+                    for step in restored {
+                        output("↩️", &step)?;
+                    }
+                }
+                Err(mut restore_error) => {
+                    restore_error.push(
+                        "Could not restore the repository - check `git \
+                         status`"
+                            .to_string(),
+                    );
+                    error = restore_error;
+                }
+            }
+
+            Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::{create_commit_with_files, test_repo};
+
+    fn git_command(dir: &std::path::Path, args: &[&str]) -> bool {
+        std::process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=Test", "-c", "user.email=test@test"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status
+            .success()
+    }
+
+    #[tokio::test]
+    async fn test_clean_up_aborts_rebase() {
+        let (dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        // Two commits that conflict with each other, on two branches
+        let ours =
+            create_commit_with_files(&git, "Ours", &[root], &[("a", "ours")]);
+        let theirs = create_commit_with_files(
+            &git,
+            "Theirs",
+            &[root],
+            &[("a", "theirs")],
+        );
+        {
+            let repo = git.repo();
+            repo.reference("refs/heads/master", ours, true, "test")
+                .unwrap();
+            repo.reference("refs/heads/other", theirs, true, "test")
+                .unwrap();
+            repo.checkout_head(Some(
+                git2::build::CheckoutBuilder::new().force(),
+            ))
+            .unwrap();
+        }
+        let checkpoint = Checkpoint::capture(&git).unwrap();
+        assert_eq!(checkpoint.branch.as_deref(), Some("refs/heads/master"));
+
+        // The rebase stops at the conflict
+        assert!(!git_command(dir.path(), &["rebase", "other"]));
+        assert!(dir.path().join(".git/rebase-merge").exists());
+        assert_ne!(Checkpoint::capture(&git).as_ref(), Some(&checkpoint));
+
+        // A command started while the rebase was in progress leaves it be
+        let during = Checkpoint::capture(&git).unwrap();
+        assert!(clean_up(&git, Some(&during), true)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(dir.path().join(".git/rebase-merge").exists());
+
+        let done = clean_up(&git, Some(&checkpoint), false).await.unwrap();
+        assert_eq!(done, vec!["Aborted the rebase in progress".to_string()]);
+        assert!(!dir.path().join(".git/rebase-merge").exists());
+        assert_eq!(Checkpoint::capture(&git), Some(checkpoint));
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("a")).unwrap(),
+            "ours"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_moves_head_back() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let checkpoint = Checkpoint::capture(&git).unwrap();
+
+        // As if the branch was rewritten halfway
+        let moved =
+            create_commit_with_files(&git, "Moved", &[root], &[("a", "a")]);
+        git.repo()
+            .reference("refs/heads/master", moved, true, "test")
+            .unwrap();
+
+        let done = checkpoint.restore(&git).await.unwrap();
+        assert_eq!(done.len(), 1);
+        assert!(done[0].starts_with("Put HEAD back on master at "));
+        assert_eq!(git.resolve_reference("refs/heads/master").unwrap(), root);
+
+        // Nothing to do the second time
+        assert!(checkpoint.restore(&git).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_record_completed() {
+        take_completed();
+        record_completed("Pushed branch spr/foo/one");
+        assert_eq!(completed(), vec!["Pushed branch spr/foo/one"]);
+        assert_eq!(take_completed(), vec!["Pushed branch spr/foo/one"]);
+        assert!(take_completed().is_empty());
+    }
+}
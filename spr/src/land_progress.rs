@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A record, kept in the Git directory, of how far `spr land --all` got
+//! through a stack: the Pull Requests it set out to land, and the ones it
+//! landed. If the command dies halfway (say the network goes away after two
+//! of four), the next `spr land --all` skips the ones GitHub says are merged
+//! and goes on with the first one that is not.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    github::{PullRequest, PullRequestState},
+};
+
+/// Where in the Git directory the record lives.
+const PROGRESS_PATH: &str = "spr/land-progress";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LandProgress {
+    path: PathBuf,
+    /// The Pull Requests being landed, bottom of the stack first
+    planned: Vec<u64>,
+    /// The ones among them that were landed
+    landed: Vec<u64>,
+}
+
+/// The Pull Requests planned and landed, from the lines `planned N` and
+/// `landed N`.
+fn parse(text: &str) -> Result<(Vec<u64>, Vec<u64>)> {
+    let mut planned = Vec::new();
+    let mut landed = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let invalid = || Error::new(format!("Invalid line '{}'", line));
+        let (key, number) = line.split_once(' ').ok_or_else(invalid)?;
+        let number = number.parse().map_err(|_| invalid())?;
+        match key {
+            "planned" => planned.push(number),
+            "landed" => landed.push(number),
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok((planned, landed))
+}
+
+impl LandProgress {
+    /// Read the record of the repository with the given Git directory. A
+    /// missing record is an empty one.
+    pub fn load(git_dir: &Path) -> Result<Self> {
+        let path = git_dir.join(PROGRESS_PATH);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                String::new()
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let (planned, landed) = parse(&text)
+            .context(format!("Could not read {}; delete it", path.display()))?;
+
+        Ok(LandProgress {
+            path,
+            planned,
+            landed,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text: String = self
+            .planned
+            .iter()
+            .map(|number| format!("planned {}\n", number))
+            .chain(
+                self.landed
+                    .iter()
+                    .map(|number| format!("landed {}\n", number)),
+            )
+            .collect();
+        std::fs::write(&self.path, text)?;
+
+        Ok(())
+    }
+
+    /// Forget the record, once the whole stack is landed.
+    pub fn remove(&self) -> Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                Err(error.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Start landing the given Pull Requests, unless they are the rest of the
+    /// ones being landed already.
+    pub fn start(&mut self, planned: &[u64]) -> Result<()> {
+        if !planned.iter().all(|number| self.planned.contains(number)) {
+            self.planned = planned.to_vec();
+            self.landed.clear();
+        }
+
+        self.save()
+    }
+
+    pub fn mark_landed(&mut self, number: u64) -> Result<()> {
+        self.landed.push(number);
+
+        self.save()
+    }
+
+    pub fn landed(&self) -> &[u64] {
+        &self.landed
+    }
+
+    /// How many of `pull_requests` (bottom of the stack first) were landed
+    /// already, before running `spr land` again: the ones at the bottom that
+    /// were being landed and are merged. Whether a Pull Request is merged is
+    /// what GitHub says; having been marked as landed is not enough, and
+    /// neither is being merged if it was not being landed.
+    pub fn landed_already(&self, pull_requests: &[PullRequest]) -> usize {
+        pull_requests
+            .iter()
+            .take_while(|pull_request| {
+                self.planned.contains(&pull_request.number)
+                    && pull_request.state == PullRequestState::Closed
+                    && pull_request.merge_commit.is_some()
+            })
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_factory;
+    use crate::github::tests::pull_request_factory;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut progress = LandProgress::load(dir.path()).unwrap();
+        assert!(progress.landed().is_empty());
+        progress.start(&[1, 2, 3]).unwrap();
+        progress.mark_landed(1).unwrap();
+        assert_eq!(LandProgress::load(dir.path()).unwrap(), progress);
+        assert_eq!(progress.landed(), &[1]);
+
+        // Going on with the rest keeps what was landed
+        progress.start(&[2, 3]).unwrap();
+        assert_eq!(progress.landed(), &[1]);
+        // Something else starts afresh
+        progress.start(&[4]).unwrap();
+        assert!(progress.landed().is_empty());
+
+        progress.remove().unwrap();
+        assert!(!dir.path().join(PROGRESS_PATH).exists());
+        progress.remove().unwrap();
+
+        std::fs::write(dir.path().join(PROGRESS_PATH), "landed one\n").unwrap();
+        assert!(LandProgress::load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_landed_already() {
+        let config = config_factory();
+        let dir = tempfile::tempdir().unwrap();
+        let merged = |number: u64| {
+            let mut pull_request =
+                pull_request_factory(&config, number, "spr/foo/x", "master");
+            pull_request.state = PullRequestState::Closed;
+            pull_request.merge_commit =
+                Some(git2::Oid::from_bytes(&[number as u8; 20]).unwrap());
+            pull_request
+        };
+        let open = |number: u64| {
+            pull_request_factory(&config, number, "spr/foo/x", "master")
+        };
+
+        let mut progress = LandProgress::load(dir.path()).unwrap();
+        progress.start(&[1, 2, 3, 4]).unwrap();
+        progress.mark_landed(1).unwrap();
+
+        // The landed one, and the one merged before it could be marked as
+        // landed
+        assert_eq!(
+            progress.landed_already(&[merged(1), merged(2), open(3), open(4)]),
+            2
+        );
+        // Landing resumes with the first one that is not merged
+        assert_eq!(
+            progress.landed_already(&[merged(1), open(2), merged(3), open(4)]),
+            1
+        );
+
+        // What GitHub says counts, not what was marked
+        assert_eq!(
+            progress.landed_already(&[open(1), open(2), open(3), open(4)]),
+            0
+        );
+        let mut closed = merged(1);
+        closed.merge_commit = None;
+        assert_eq!(progress.landed_already(&[closed, open(2)]), 0);
+
+        // A Pull Request that was not being landed is not skipped
+        assert_eq!(progress.landed_already(&[merged(5), open(4)]), 0);
+        progress.remove().unwrap();
+        let progress = LandProgress::load(dir.path()).unwrap();
+        assert_eq!(progress.landed_already(&[merged(1), open(2)]), 0);
+    }
+}
@@ -5,11 +5,27 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+pub mod api;
+pub mod autosquash;
+pub mod code_owners;
 pub mod commands;
+pub mod commit_group;
 pub mod config;
 pub mod error;
+pub mod forge;
 pub mod git;
 pub mod github;
+pub mod github_app;
+pub mod interrupt;
+pub mod land_progress;
 pub mod message;
 pub mod output;
+pub mod path_filter;
+pub mod pr_config;
+pub mod push_cache;
+pub mod rate_limit;
+pub mod reviewer_rotation;
+pub mod timings;
+pub mod undo;
+pub mod upstream;
 pub mod utils;
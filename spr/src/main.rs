@@ -10,10 +10,9 @@
 //! stacked to allow for a series of code reviews of interdependent code.
 
 use clap::{Parser, Subcommand};
-use reqwest::{self, header};
 use spr::{
     commands,
-    error::{Error, Result},
+    error::{Error, Result, ResultExt},
     output::output,
 };
 
@@ -38,12 +37,60 @@ pub struct Cli {
     #[clap(long)]
     github_repository: Option<String>,
 
+    /// Owner of the repository to create Pull Requests in, if not the one
+    /// given by the GitHub repository setting (requires --target-repo)
+    #[clap(long, value_name = "OWNER", conflicts_with = "github-repository")]
+    target_owner: Option<String>,
+
+    /// Name of the repository to create Pull Requests in, if not the one given
+    /// by the GitHub repository setting (requires --target-owner)
+    #[clap(long, value_name = "REPO", conflicts_with = "github-repository")]
+    target_repo: Option<String>,
+
     /// prefix to be used for branches created for pull requests (if not given
     /// taken from git config spr.branchPrefix, defaulting to
     /// 'spr/<GITHUB_USERNAME>/')
     #[clap(long)]
     branch_prefix: Option<String>,
 
+    /// Read the spr configuration from this file only (in Git config format),
+    /// instead of the Git configuration of the repository
+    #[clap(long, value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
+
+    /// Use the settings of this profile (`spr.<PROFILE>.*` in the Git config)
+    /// in preference to the others (if not given taken from the environment
+    /// variable SPR_PROFILE, or git config spr.profile)
+    #[clap(long)]
+    profile: Option<String>,
+
+    /// Base the stack on the upstream of the checked-out branch
+    /// (`@{upstream}`), if it has one on the GitHub remote, instead of the
+    /// configured master branch
+    #[clap(long)]
+    base_auto: bool,
+
+    /// Refuse to start operations on many Pull Requests if the GitHub API rate
+    /// limits may not leave enough requests for them, instead of warning
+    #[clap(long)]
+    strict_rate_limit: bool,
+
+    /// Fetch the branches of Pull Requests from GitHub, even if spr pushed or
+    /// fetched them at the commits GitHub reports before
+    #[clap(long)]
+    refresh: bool,
+
+    /// Do not color the output, even on a terminal (like setting the NO_COLOR
+    /// environment variable)
+    #[clap(long)]
+    no_color: bool,
+
+    /// At the end, print how long each phase of the command took (fetching,
+    /// rebasing, pushing and updating Pull Requests) and how many requests it
+    /// sent to the GitHub API
+    #[clap(long)]
+    timings: bool,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -56,7 +103,7 @@ enum Commands {
 
     /// Create a new or update an existing Pull Request on GitHub from the
     /// current HEAD commit
-    Diff(commands::diff::DiffOptions),
+    Diff(Box<commands::diff::DiffOptions>),
 
     /// Reformat commit message
     Format(commands::format::FormatOptions),
@@ -67,14 +114,112 @@ enum Commands {
     /// Update local commit message with content on GitHub
     Amend(commands::amend::AmendOptions),
 
-    /// List open Pull Requests on GitHub and their review decision
-    List,
+    /// List your Pull Requests on GitHub (by default the open ones) and their
+    /// review decision
+    List(commands::list::ListOptions),
+
+    /// Draw the Pull Requests of this branch (and with --siblings, your other
+    /// stacks on the same base) as a tree, or as a Graphviz graph
+    Graph(commands::graph::GraphOptions),
+
+    /// Show for each Pull Request of this branch who is asked to review it,
+    /// who approved it or requested changes, and whether it can be merged
+    Reviews,
+
+    /// Keep asking GitHub about the Pull Requests of this branch, and ring the
+    /// bell when one is approved, gets changes requested, finishes its checks
+    /// or becomes mergeable (until Ctrl-C)
+    Watch(commands::watch::WatchOptions),
 
     /// Create a new branch with the contents of an existing Pull Request
     Patch(commands::patch::PatchOptions),
 
     /// Close a Pull request
     Close(commands::close::CloseOptions),
+
+    /// Mark the draft Pull Request of the HEAD commit (or with --all, of the
+    /// whole branch) as ready for review, and request reviews on it
+    Ready(commands::ready::ReadyOptions),
+
+    /// Associate the HEAD commit with an existing Pull Request that was not
+    /// created by spr, so that spr can manage it from now on
+    Adopt(commands::adopt::AdoptOptions),
+
+    /// Delete local spr branches whose Pull Requests are merged or closed
+    /// (except the checked-out one), after listing them and asking to confirm
+    Prune(commands::prune::PruneOptions),
+
+    /// Restore the deleted branch of a Pull Request (and reopen it, if GitHub
+    /// closed it for that), so that spr can update it again
+    Relink(commands::relink::RelinkOptions),
+
+    /// Rename the branches of the Pull Requests of this branch that are named
+    /// after an older configuration (e.g. another spr.branchPrefix) to the
+    /// names spr would give them now
+    RenameBranches(commands::rename_branches::RenameBranchesOptions),
+
+    /// Post a comment on a Pull Request
+    Comment(commands::comment::CommentOptions),
+
+    /// Squash the uncommitted changes into the commit of a Pull Request further
+    /// down the stack, and update that Pull Request and the ones on top of it
+    Fixup(commands::fixup::FixupOptions),
+
+    /// Edit the commit message of a Pull Request further down the stack in
+    /// your editor, and update that Pull Request and the ones on top of it
+    Edit(commands::edit::EditOptions),
+
+    /// Open a Pull Request (by default the one of the HEAD commit) in the web
+    /// browser
+    Open(commands::open::OpenOptions),
+
+    /// Rewrite the PR Stack section of all Pull Requests on this branch to
+    /// match the current stack, without changing any code
+    FixFooters,
+
+    /// Check that the Pull Requests of this branch match its commits (each
+    /// commit has an open Pull Request with the right base and PR Stack), and
+    /// exit with an error if not
+    Verify(commands::verify::VerifyOptions),
+
+    /// Show which commits on this branch would get a new Pull Request and
+    /// which would update an existing one, without changing anything
+    Preview,
+
+    /// Print a short status of the stack for shell prompts, like `spr:3/5✓`
+    /// (the position of HEAD, the size of the stack, and whether every commit
+    /// up to HEAD has a Pull Request), using only the local repository
+    Prompt,
+
+    /// Exit with 0 if spr manages the checked-out branch (the repository is
+    /// set up for spr, and at least one commit up to HEAD has a Pull Request),
+    /// and with 1 if not, using only the local repository
+    IsManaged,
+
+    /// Write the commits on this branch as a patch series (with their Pull
+    /// Requests in extra headers), for offline review or sending by email
+    Export(commands::export::ExportOptions),
+
+    /// Write the changes of the whole stack as one diff, in a section for
+    /// each Pull Request (with its number and title), for attaching to a
+    /// review
+    ExportDiff(commands::export_diff::ExportDiffOptions),
+
+    /// Create a new branch from a patch series (as written by `spr export`)
+    /// applied onto master, keeping the commits linked to their Pull Requests
+    Import(commands::import::ImportOptions),
+
+    /// Put HEAD and the local branches back where they were before the last
+    /// spr command that changed things, and list what it changed on GitHub,
+    /// which cannot be undone
+    Undo,
+
+    /// Print the version of spr (with --verbose, including build details)
+    Version(commands::version::VersionOptions),
+
+    /// Get, set or list spr's settings in the Git configuration, checking
+    /// the values spr would read
+    Config(commands::config::ConfigOptions),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -85,8 +230,11 @@ pub enum OptionsError {
     InvalidRepository(String),
 }
 
-pub async fn spr() -> Result<()> {
-    let cli = Cli::parse();
+pub async fn spr(cli: Cli) -> Result<Outcome> {
+    if let Commands::Diff(opts) = &cli.command {
+        // Keep stdout free for the URLs
+        spr::output::set_output_to_stderr(opts.print_urls);
+    }
 
     if let Some(path) = &cli.cd {
         if let Err(err) = std::env::set_current_dir(path) {
@@ -96,19 +244,82 @@ pub async fn spr() -> Result<()> {
     }
 
     if let Commands::Init = cli.command {
-        return commands::init::init().await;
+        return commands::init::init().await.map(|()| Outcome::Done);
+    }
+    if let Commands::Version(opts) = cli.command {
+        return commands::version::version(opts).map(|()| Outcome::Done);
     }
 
     let repo = git2::Repository::discover(std::env::current_dir()?)?;
 
-    let git_config = repo.config()?;
+    let git_config =
+        spr::config::open_git_config(&repo, cli.config.as_deref())?;
+    let profile = spr::config::select_profile(
+        cli.profile,
+        std::env::var(spr::config::PROFILE_ENV_VAR).ok(),
+        &git_config,
+    );
+    let git_config = spr::config::ProfileConfig::new(git_config, profile)?;
 
-    let github_repository = match cli.github_repository {
-        Some(v) => Ok(v),
-        None => git_config.get_string("spr.githubRepository"),
-    }?;
+    if matches!(cli.command, Commands::IsManaged)
+        && !commands::is_managed::has_spr_config(&git_config)
+    {
+        std::process::exit(1);
+    }
+
+    if let Commands::Config(opts) = cli.command {
+        return commands::config::config(
+            opts,
+            &repo,
+            cli.config.as_deref(),
+            &git_config,
+        )
+        .map(|()| Outcome::Done);
+    }
 
-    let (github_owner, github_repo) = {
+    let github_host = git_config
+        .get_string("spr.githubHost")
+        .unwrap_or_else(|_| spr::config::DEFAULT_GITHUB_HOST.to_string());
+
+    // Which remote to push to, and (unless configured) which repository on
+    // GitHub it is
+    let (github_remote_name, remote_repository) =
+        match git_config.get_string("spr.githubRemoteName") {
+            Ok(name) => {
+                let repository = spr::git::github_remotes(&repo, &github_host)?
+                    .into_iter()
+                    .find(|remote| remote.name == name)
+                    .map(|remote| format!("{}/{}", remote.owner, remote.repo));
+                (name, repository)
+            }
+            Err(_) => match spr::git::choose_github_remote(
+                spr::git::github_remotes(&repo, &github_host)?,
+                console::user_attended()
+                    && !matches!(
+                        cli.command,
+                        Commands::Prompt | Commands::IsManaged
+                    ),
+            )? {
+                Some(remote) => (
+                    remote.name,
+                    Some(format!("{}/{}", remote.owner, remote.repo)),
+                ),
+                None => ("origin".to_string(), None),
+            },
+        };
+
+    let target =
+        spr::config::target_repository(cli.target_owner, cli.target_repo)?;
+    let (github_owner, github_repo) = if let Some(target) = target {
+        target
+    } else {
+        let github_repository = match cli.github_repository {
+            Some(v) => v,
+            None => match git_config.get_string("spr.githubRepository") {
+                Ok(v) => v,
+                Err(error) => remote_repository.ok_or(error)?,
+            },
+        };
         let captures = lazy_regex::regex!(r#"^([\w\-\.]+)/([\w\-\.]+)$"#)
             .captures(&github_repository)
             .ok_or_else(|| {
@@ -120,12 +331,21 @@ pub async fn spr() -> Result<()> {
         )
     };
 
-    let github_remote_name = git_config
-        .get_string("spr.githubRemoteName")
-        .unwrap_or_else(|_| "origin".to_string());
-    let github_master_branch = git_config
-        .get_string("spr.githubMasterBranch")
-        .unwrap_or_else(|_| "master".to_string());
+    // With --base-auto, the upstream of the checked-out branch (if any) takes
+    // the place of the master branch
+    let upstream = cli
+        .base_auto
+        .then(|| spr::git::upstream_branch(&repo, &github_remote_name))
+        .flatten();
+    let base_branch = spr::config::choose_base_branch(
+        upstream,
+        git_config.get_string("spr.githubMasterBranch").ok(),
+        || spr::git::remote_default_branch(&repo, &github_remote_name),
+    )?;
+    if cli.base_auto {
+        output("🌿", &base_branch.describe(&github_remote_name))?;
+    }
+    let github_master_branch = base_branch.name;
     let branch_prefix = git_config.get_string("spr.branchPrefix")?;
     let require_approval = git_config
         .get_bool("spr.requireApproval")
@@ -136,7 +356,7 @@ pub async fn spr() -> Result<()> {
         .ok()
         .unwrap_or(true);
 
-    let config = spr::config::Config::new(
+    let mut config = spr::config::Config::new(
         github_owner,
         github_repo,
         github_remote_name,
@@ -145,36 +365,324 @@ pub async fn spr() -> Result<()> {
         require_approval,
         require_test_plan,
     );
+    config.pr_stack_format.limit = git_config
+        .get_i64("spr.prStackLimit")
+        .ok()
+        .and_then(|limit| usize::try_from(limit).ok())
+        .filter(|&limit| limit > 0);
+    config.pr_stack_format.heading =
+        git_config.get_string("spr.prStackHeading").ok();
+    if let Ok(marker) = git_config.get_string("spr.prStackCurrentMarker") {
+        config.pr_stack_format.current_marker = marker;
+    }
+    config.pr_stack_format.diffstat = git_config
+        .get_bool("spr.prStackDiffstat")
+        .ok()
+        .unwrap_or(false);
+    config.pr_stack_format.comment_markers = git_config
+        .get_bool("spr.prStackCommentMarkers")
+        .ok()
+        .unwrap_or(false);
+    config.pr_stack_format.hide_single_pr_footer = git_config
+        .get_bool("spr.hideSinglePrFooter")
+        .ok()
+        .unwrap_or(false);
+    config.pr_stack_format.footer_keep_merged = git_config
+        .get_bool("spr.footerKeepMerged")
+        .ok()
+        .unwrap_or(false);
+    config.strip_title_emoji = git_config
+        .get_bool("spr.stripTitleEmoji")
+        .ok()
+        .unwrap_or(false);
+    config.normalize_typography = git_config
+        .get_bool("spr.normalizeTypography")
+        .ok()
+        .unwrap_or(false);
+    config.strip_trailers = git_config
+        .get_string("spr.stripTrailers")
+        .map(|keys| spr::utils::parse_name_list(&keys))
+        .unwrap_or_default();
+    // Given once per trailer, as `KEY=HEADING`
+    for value in git_config.get_multivar("spr.trailerSection")? {
+        config.trailer_sections.push(value.parse()?);
+    }
+    config.default_reviewers = git_config
+        .get_string("spr.defaultReviewers")
+        .map(|reviewers| spr::utils::parse_name_list(&reviewers))
+        .unwrap_or_default();
+    config.reviewer_pool = git_config
+        .get_string("spr.reviewerPool")
+        .map(|reviewers| spr::utils::parse_name_list(&reviewers))
+        .unwrap_or_default();
+    if let Ok(strategy) = git_config.get_string("spr.reviewerStrategy") {
+        config.reviewer_strategy = strategy.parse()?;
+    }
+    if let Ok(count) = git_config.get_i64("spr.reviewerPoolCount") {
+        config.reviewer_pool_count = count.max(1) as usize;
+    }
+    if let Ok(count) = git_config.get_i64("spr.reviewerRequired") {
+        config.reviewer_required = count.max(0) as usize;
+    }
+    // Given once per alias, as `NAME=REVIEWERS`
+    for value in git_config.get_multivar("spr.reviewerAlias")? {
+        config.reviewer_aliases.push(value.parse()?);
+    }
+    config.push_options = git_config.get_multivar("spr.pushOption")?;
+    config.submit_denylist = git_config.get_multivar("spr.submitDenylist")?;
+
+    config.assignees = git_config
+        .get_string("spr.assignees")
+        .map(|assignees| spr::utils::parse_name_list(&assignees))
+        .unwrap_or_default();
+    config.assign_self =
+        git_config.get_bool("spr.assignSelf").ok().unwrap_or(false);
+    config.branch_slug_keep_dots = git_config
+        .get_bool("spr.branchSlugKeepDots")
+        .ok()
+        .unwrap_or(false);
+    if let Ok(pattern) = git_config.get_string("spr.branchSlugStripPattern") {
+        config.branch_slug_strip_pattern =
+            Some(lazy_regex::Regex::new(&pattern).map_err(|error| {
+                Error::new(format!(
+                    "spr.branchSlugStripPattern is not a valid regular \
+                     expression: {}",
+                    error
+                ))
+            })?);
+    }
+    if let Ok(format) = git_config.get_string("spr.branchDateFormat") {
+        spr::config::validate_branch_date_format(&format)?;
+        config.branch_date_format = Some(format);
+    }
+    if let Ok(empty_commits) = git_config.get_string("spr.emptyCommits") {
+        config.empty_commits = empty_commits.parse()?;
+    }
+    if let Ok(base_mismatch) = git_config.get_string("spr.baseMismatch") {
+        config.base_mismatch = base_mismatch.parse()?;
+    }
+    if let Ok(fallback) = git_config.get_string("spr.emptySlugFallback") {
+        config.empty_slug_fallback = fallback.parse()?;
+    }
+    if let Ok(tracking) = git_config.get_string("spr.pullRequestTracking") {
+        config.pull_request_tracking = tracking.parse()?;
+    }
+    if let Ok(notes_ref) = git_config.get_string("spr.notesRef") {
+        config.notes_ref = notes_ref;
+    }
+    if let Ok(land_author) = git_config.get_string("spr.landAuthor") {
+        config.land_author = land_author.parse()?;
+    }
+    if let Ok(template) = git_config.get_string("spr.landMessageTemplate") {
+        commands::land::validate_land_message_template(&template)?;
+        config.land_message_template = Some(template);
+    }
+    // Given once per trailer
+    for value in git_config.get_multivar("spr.landTrailer")? {
+        commands::land::validate_land_trailer(&value)?;
+        config.land_trailers.push(value);
+    }
+    if let Ok(hook) = git_config.get_string("spr.commitHook") {
+        config.commit_hook = Some(hook).filter(|hook| !hook.trim().is_empty());
+    }
+    if let Ok(project) = git_config.get_string("spr.project") {
+        config.project = Some(project).filter(|name| !name.trim().is_empty());
+    }
+    if let Ok(concurrency) = git_config.get_i64("spr.concurrency") {
+        // Anything less than one means running one request at a time
+        config.concurrency = usize::try_from(concurrency).unwrap_or(1).max(1);
+    }
+    if let (Ok(app_id), Ok(installation_id), Ok(private_key_path)) = (
+        git_config.get_i64("spr.githubAppId"),
+        git_config.get_i64("spr.githubAppInstallationId"),
+        git_config.get_path("spr.githubAppPrivateKey"),
+    ) {
+        config.github_app = Some(spr::github_app::GitHubApp {
+            app_id: github_app_id("spr.githubAppId", app_id)?,
+            installation_id: github_app_id(
+                "spr.githubAppInstallationId",
+                installation_id,
+            )?,
+            private_key_path,
+        });
+    }
+    config.github_host = github_host;
+    if let Ok(user_agent) = git_config.get_string("spr.userAgent") {
+        config.user_agent = user_agent;
+    }
 
-    let git = spr::git::Git::new(repo);
+    if let Ok(seconds) = git_config.get_i64("spr.connectTimeout") {
+        config.connect_timeout = timeout_from_seconds(seconds);
+    }
+    if let Ok(seconds) = git_config.get_i64("spr.requestTimeout") {
+        config.request_timeout = timeout_from_seconds(seconds);
+    }
+    config.strict_rate_limit = cli.strict_rate_limit;
+    config.refresh = cli.refresh;
+    config.land_delete_remote_branches = git_config
+        .get_bool("spr.landDeleteRemoteBranches")
+        .ok()
+        .unwrap_or(false);
+    config.land_squash_title_from_pr = git_config
+        .get_bool("spr.landSquashTitleFromPr")
+        .ok()
+        .unwrap_or(false);
+    config.land_delete_local_branches = git_config
+        .get_bool("spr.landDeleteLocalBranches")
+        .ok()
+        .unwrap_or(false);
+    config.confirm_force_push = git_config
+        .get_bool("spr.confirmForcePush")
+        .ok()
+        .unwrap_or(false);
+    config.set_upstream =
+        git_config.get_bool("spr.setUpstream").ok().unwrap_or(false);
+    config.show_plan =
+        git_config.get_bool("spr.showPlan").ok().unwrap_or(false);
+    config.cherry_pick_co_author = git_config
+        .get_bool("spr.cherryPickCoAuthor")
+        .ok()
+        .unwrap_or(true);
+    config.refresh_pr_stacks = git_config
+        .get_bool("spr.refreshPrStacks")
+        .ok()
+        .unwrap_or(true);
+    config.sync_branch_names = git_config
+        .get_bool("spr.syncBranchNames")
+        .ok()
+        .unwrap_or(false);
+    config.autosquash =
+        git_config.get_bool("spr.autosquash").ok().unwrap_or(false);
+    config.depends_on_line = git_config
+        .get_bool("spr.dependsOnLine")
+        .ok()
+        .unwrap_or(false);
+    if let Ok(seconds) = git_config.get_i64("spr.checksPollInterval") {
+        // Polling more than once a second would only burn through the rate
+        // limit
+        config.checks_poll_interval =
+            std::time::Duration::from_secs(seconds.max(1) as u64);
+    }
+    if let Ok(seconds) = git_config.get_i64("spr.watchInterval") {
+        config.watch_interval =
+            std::time::Duration::from_secs(seconds.max(1) as u64);
+    }
+    if let Ok(events) = git_config.get_string("spr.watchEvents") {
+        config.watch_events = spr::utils::parse_name_list(&events)
+            .iter()
+            .map(|event| event.parse())
+            .collect::<Result<_>>()?;
+    }
+    if let Ok(seconds) = git_config.get_i64("spr.checksTimeout") {
+        config.checks_timeout = timeout_from_seconds(seconds);
+    }
+    if let Ok(limit) = git_config.get_i64("spr.titleLengthLimit") {
+        config.title_length_limit =
+            usize::try_from(limit).ok().filter(|&limit| limit > 0);
+    }
+    // Clean up edited messages as `git commit` does
+    if let Ok(mode) = git_config.get_string("commit.cleanup") {
+        config.message_cleanup.mode = mode.parse()?;
+    }
+    if let Ok(comment_char) = git_config.get_string("core.commentChar") {
+        config.message_cleanup.comment_char =
+            spr::message::MessageCleanup::parse_comment_char(&comment_char)?;
+    }
+    if let Ok(version) = git_config.get_string("spr.minimumGitVersion") {
+        config.minimum_git_version = version
+            .parse::<spr::git::GitVersion>()
+            .context("Invalid value for spr.minimumGitVersion".to_string())?;
+    }
 
-    if let Commands::Format(opts) = cli.command {
-        return commands::format::format(opts, &git, &config).await;
+    // When pushing to a fork, Pull Requests are created across forks
+    let remote_url = repo
+        .find_remote(&config.remote_name)
+        .ok()
+        .and_then(|remote| remote.url().map(String::from));
+    config.fork_owner = remote_url
+        .and_then(|url| config.owner_from_remote_url(&url))
+        .filter(|owner| !owner.eq_ignore_ascii_case(&config.owner));
+
+    let git = spr::git::Git::new(repo).with_notes_ref(&config.notes_ref);
+
+    // Remember where the branches were, for `spr undo`
+    let snapshot = match undoable_command(&cli.command) {
+        Some(command) => spr::undo::Snapshot::capture(&git, command)?,
+        None => None,
+    };
+    let git_dir = git.repo().path().to_path_buf();
+    if let Some(snapshot) = &snapshot {
+        snapshot.save(&git_dir)?;
     }
 
-    let github_auth_token = match cli.github_auth_token {
-        Some(v) => Ok(v),
-        None => git_config.get_string("spr.githubAuthToken"),
-    }?;
+    if let Commands::Format(opts) = cli.command {
+        let result = commands::format::format(opts, &git, &config).await;
+        finish_snapshot(snapshot, &git, &git_dir)?;
+        return result.map(|()| Outcome::Done);
+    }
+    if let Commands::Open(opts) = cli.command {
+        return commands::open::open(opts, &git, &config)
+            .await
+            .map(|()| Outcome::Done);
+    }
+    if let Commands::Preview = cli.command {
+        return commands::preview::preview(&git, &config)
+            .await
+            .map(|()| Outcome::Done);
+    }
+    if let Commands::Prompt = cli.command {
+        return commands::prompt::prompt(&git, &config)
+            .await
+            .map(|()| Outcome::Done);
+    }
+    if let Commands::IsManaged = cli.command {
+        if !commands::is_managed::check(&git, &config).await? {
+            std::process::exit(1);
+        }
+        return Ok(Outcome::Done);
+    }
+    if let Commands::Export(opts) = cli.command {
+        return commands::export::export(opts, &git, &config)
+            .await
+            .map(|()| Outcome::Done);
+    }
+    if let Commands::ExportDiff(opts) = cli.command {
+        return commands::export_diff::export_diff(opts, &git, &config)
+            .await
+            .map(|()| Outcome::Done);
+    }
+    if let Commands::Import(opts) = cli.command {
+        let result = commands::import::import(opts, &git, &config).await;
+        finish_snapshot(snapshot, &git, &git_dir)?;
+        return result.map(|()| Outcome::Done);
+    }
+    if let Commands::Undo = cli.command {
+        return commands::undo::undo(&git).await.map(|()| Outcome::Done);
+    }
 
-    octocrab::initialise(
-        octocrab::Octocrab::builder().personal_token(github_auth_token.clone()),
-    )?;
+    // The commands from here on run git, so rather stop now than fail halfway
+    // through on an old one
+    spr::git::check_git_version(config.minimum_git_version)?;
+
+    let github_auth_token = match (&config.github_app, cli.github_auth_token) {
+        (_, Some(v)) => v,
+        (Some(app), None) => {
+            let auth = spr::github_app::initialise(
+                spr::github_app::InstallationAuth::from_private_key_file(
+                    app, &config,
+                )?,
+            );
+            let token = auth.token().await?;
+            tokio::spawn(auth.keep_fresh());
+            token
+        }
+        (None, None) => git_config.get_string("spr.githubAuthToken")?,
+    };
 
-    let mut headers = header::HeaderMap::new();
-    headers.insert(header::ACCEPT, "application/json".parse()?);
-    headers.insert(
-        header::USER_AGENT,
-        format!("spr/{}", env!("CARGO_PKG_VERSION")).try_into()?,
-    );
-    headers.insert(
-        header::AUTHORIZATION,
-        format!("Bearer {}", github_auth_token).parse()?,
-    );
+    spr::github::initialise_octocrab(&config, &github_auth_token)?;
 
-    let graphql_client = reqwest::Client::builder()
-        .default_headers(headers)
-        .build()?;
+    let graphql_client =
+        spr::github::build_graphql_client(&config, &github_auth_token)?;
 
     let mut gh = spr::github::GitHub::new(
         config.clone(),
@@ -182,38 +690,239 @@ pub async fn spr() -> Result<()> {
         graphql_client.clone(),
     );
 
-    match cli.command {
-        Commands::Diff(opts) => {
-            commands::diff::diff(opts, &git, &mut gh, &config).await?
-        }
-        Commands::Land(opts) => {
-            commands::land::land(opts, &git, &mut gh, &config).await?
-        }
-        Commands::Amend(opts) => {
-            commands::amend::amend(opts, &git, &mut gh, &config).await?
-        }
-        Commands::List => commands::list::list(graphql_client, &config).await?,
-        Commands::Patch(opts) => {
-            commands::patch::patch(opts, &git, &mut gh, &config).await?
-        }
-        Commands::Close(opts) => {
-            commands::close::close(opts, &git, &mut gh, &config).await?
-        }
-        // The following commands are executed above and return from this
-        // function before it reaches this match.
-        Commands::Init | Commands::Format(_) => (),
-    };
+    if let Commands::Watch(opts) = cli.command {
+        // Watching ends with Ctrl-C by design, so there is nothing to clean up
+        return commands::watch::watch(
+            opts,
+            &git,
+            &gh,
+            graphql_client,
+            &config,
+        )
+        .await
+        .map(|()| Outcome::Done);
+    }
 
-    Ok::<_, Error>(())
+    // Clean up after Ctrl-C in the middle of the commands that change things
+    let result = spr::interrupt::run_interruptible(&git, async {
+        match cli.command {
+            Commands::Diff(opts) => {
+                let outcome =
+                    spr::api::submit(&git, &gh, &config, *opts).await?;
+                if outcome == spr::api::DiffOutcome::NothingToSubmit {
+                    return Ok(Outcome::NothingToSubmit);
+                }
+            }
+            Commands::Land(opts) => {
+                let outcome = spr::api::land(&git, &gh, &config, opts).await?;
+                if let spr::api::LandOutcome::Landed(report) = outcome {
+                    // The report says which Pull Request could not be landed
+                    if report.blocked.is_some() {
+                        return Err(Error::empty());
+                    }
+                }
+            }
+            Commands::Amend(opts) => {
+                commands::amend::amend(opts, &git, &mut gh, &config).await?
+            }
+            Commands::List(opts) => {
+                commands::list::list(opts, graphql_client, &config).await?
+            }
+            Commands::Reviews => {
+                commands::reviews::reviews(&git, graphql_client, &config)
+                    .await?
+            }
+            Commands::Graph(opts) => {
+                commands::graph::graph(opts, &git, &gh, graphql_client, &config)
+                    .await?
+            }
+            Commands::Patch(opts) => {
+                commands::patch::patch(opts, &git, &mut gh, &config).await?
+            }
+            Commands::Close(opts) => {
+                commands::close::close(opts, &git, &mut gh, &config).await?
+            }
+            Commands::Ready(opts) => {
+                commands::ready::ready(opts, &git, &mut gh, &config).await?
+            }
+            Commands::Fixup(opts) => {
+                commands::fixup::fixup(opts, &git, &mut gh, &config).await?
+            }
+            Commands::Edit(opts) => {
+                commands::edit::edit(opts, &git, &mut gh, &config).await?
+            }
+            Commands::Adopt(opts) => {
+                commands::adopt::adopt(opts, &git, &mut gh, &config).await?
+            }
+            Commands::Prune(opts) => {
+                commands::prune::prune(opts, &git, &mut gh, &config).await?
+            }
+            Commands::Relink(opts) => {
+                commands::relink::relink(opts, &git, &mut gh, &config).await?
+            }
+            Commands::RenameBranches(opts) => {
+                commands::rename_branches::rename_branches(
+                    opts, &git, &gh, &config,
+                )
+                .await?
+            }
+            Commands::Comment(opts) => {
+                commands::comment::comment(opts, &mut gh, &config).await?
+            }
+            Commands::FixFooters => {
+                commands::fix_footers::fix_footers(&git, &mut gh, &config)
+                    .await?
+            }
+            Commands::Verify(opts) => {
+                commands::verify::verify(opts, &git, &mut gh, &config).await?
+            }
+            // The following commands are executed above and return from this
+            // function before it reaches this match.
+            Commands::Init
+            | Commands::Version(_)
+            | Commands::Format(_)
+            | Commands::Open(_)
+            | Commands::Preview
+            | Commands::Prompt
+            | Commands::IsManaged
+            | Commands::Export(_)
+            | Commands::ExportDiff(_)
+            | Commands::Import(_)
+            | Commands::Undo
+            | Commands::Config(_)
+            | Commands::Watch(_) => (),
+        };
+
+        Ok::<_, Error>(Outcome::Done)
+    })
+    .await;
+
+    finish_snapshot(snapshot, &git, &git_dir)?;
+
+    result
+}
+
+/// Note in the snapshot for `spr undo` where the command left the branches
+/// and what it changed on GitHub, whether it succeeded or not.
+fn finish_snapshot(
+    snapshot: Option<spr::undo::Snapshot>,
+    git: &spr::git::Git,
+    git_dir: &std::path::Path,
+) -> Result<()> {
+    if let Some(mut snapshot) = snapshot {
+        snapshot.record_after(git)?;
+        snapshot.remote_steps = spr::interrupt::completed();
+        snapshot.save(git_dir)?;
+    }
+
+    Ok(())
+}
+
+/// The name of the command, if it changes the repository (or GitHub), so
+/// that `spr undo` can reverse it.
+fn undoable_command(command: &Commands) -> Option<&'static str> {
+    Some(match command {
+        Commands::Diff(_) => "diff",
+        Commands::Format(_) => "format",
+        Commands::Land(_) => "land",
+        Commands::Amend(_) => "amend",
+        Commands::Patch(_) => "patch",
+        Commands::Close(_) => "close",
+        Commands::Ready(_) => "ready",
+        Commands::Adopt(_) => "adopt",
+        Commands::Prune(_) => "prune",
+        Commands::Relink(_) => "relink",
+        Commands::RenameBranches(_) => "rename-branches",
+        Commands::Comment(_) => "comment",
+        Commands::Fixup(_) => "fixup",
+        Commands::Edit(_) => "edit",
+        Commands::FixFooters => "fix-footers",
+        Commands::Import(_) => "import",
+        Commands::Init
+        | Commands::List(_)
+        | Commands::Graph(_)
+        | Commands::Reviews
+        | Commands::Watch(_)
+        | Commands::Open(_)
+        | Commands::Verify(_)
+        | Commands::Preview
+        | Commands::Prompt
+        | Commands::IsManaged
+        | Commands::Export(_)
+        | Commands::ExportDiff(_)
+        | Commands::Undo
+        | Commands::Version(_)
+        | Commands::Config(_) => return None,
+    })
+}
+
+/// How a command that did not fail ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// `spr diff` found no commits to submit
+    NothingToSubmit,
+}
+
+/// The exit code of `spr diff` when there are no commits to submit, so that
+/// scripts can tell that apart from success and from failure (exit code 1).
+const NOTHING_TO_SUBMIT_EXIT_CODE: i32 = 3;
+
+/// Timeouts are configured in seconds, where zero (or a negative number) means
+/// no timeout.
+fn timeout_from_seconds(seconds: i64) -> Option<std::time::Duration> {
+    u64::try_from(seconds)
+        .ok()
+        .filter(|&seconds| seconds > 0)
+        .map(std::time::Duration::from_secs)
+}
+
+/// A GitHub App or installation id, which GitHub never makes negative.
+fn github_app_id(key: &str, value: i64) -> Result<u64> {
+    u64::try_from(value).map_err(|_| {
+        Error::new(format!(
+            "Invalid value for {}: '{}' (must not be negative)",
+            key, value
+        ))
+    })
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    if let Err(error) = spr().await {
-        for message in error.messages() {
-            output("🛑", message)?;
+    let started = std::time::Instant::now();
+    let cli = Cli::parse();
+    if cli.timings {
+        spr::timings::enable();
+    }
+    if spr::output::colors_disabled(
+        cli.no_color,
+        std::env::var_os("NO_COLOR").as_deref(),
+    ) {
+        spr::output::set_colors_enabled(false);
+    }
+    // Outside of a repository set up for spr, `spr prompt` just prints nothing,
+    // and `spr is-managed` just exits with 1
+    let prompt = matches!(cli.command, Commands::Prompt);
+    let is_managed = matches!(cli.command, Commands::IsManaged);
+    let result = spr(cli).await;
+    if let Some(timings) = spr::timings::take() {
+        for line in timings.summary(started.elapsed()) {
+            output("⏱️", &line)?;
+        }
+    }
+    match result {
+        Ok(Outcome::Done) => (),
+        Ok(Outcome::NothingToSubmit) => {
+            std::process::exit(NOTHING_TO_SUBMIT_EXIT_CODE)
+        }
+        Err(_) if prompt => (),
+        Err(_) if is_managed => std::process::exit(1),
+        Err(error) => {
+            for message in error.messages() {
+                output("🛑", message)?;
+            }
+            std::process::exit(1);
         }
-        std::process::exit(1);
     }
 
     Ok(())
@@ -5,8 +5,11 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+use std::collections::{HashMap, HashSet};
+
 use crate::{
     error::{Error, Result},
+    git::DiffStat,
     output::output,
 };
 
@@ -54,6 +57,148 @@ pub fn message_section_by_label(label: &str) -> Option<MessageSection> {
     }
 }
 
+/// The line above the diff that `git commit --verbose` (or `commit.verbose`)
+/// puts into the commit message template, after the comment character. It
+/// and everything below it is not part of the message.
+const SCISSORS_LINE: &str =
+    " ------------------------ >8 ------------------------";
+
+/// How `git commit` cleans up a message the user edited (`commit.cleanup`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CleanupMode {
+    /// Leave the message as it is
+    Verbatim,
+    /// Drop trailing whitespace and leading, trailing and repeated blank
+    /// lines
+    Whitespace,
+    /// Like `Whitespace`, and drop comment lines too
+    #[default]
+    Strip,
+    /// Like `Whitespace`, and drop everything from the scissors line on
+    Scissors,
+}
+
+impl std::str::FromStr for CleanupMode {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "verbatim" => Ok(Self::Verbatim),
+            "whitespace" => Ok(Self::Whitespace),
+            // What Git does by default when the message was edited
+            "strip" | "default" => Ok(Self::Strip),
+            "scissors" => Ok(Self::Scissors),
+            _ => Err(Error::new(format!(
+                "Invalid value for commit.cleanup: '{}' (must be 'verbatim', \
+                 'whitespace', 'strip', 'scissors' or 'default')",
+                s
+            ))),
+        }
+    }
+}
+
+/// How to clean up the messages the user edits in spr's editor, as configured
+/// for `git commit` with `commit.cleanup` and `core.commentChar`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageCleanup {
+    pub mode: CleanupMode,
+    /// The character that starts comment lines
+    pub comment_char: char,
+}
+
+impl Default for MessageCleanup {
+    fn default() -> Self {
+        Self {
+            mode: CleanupMode::default(),
+            comment_char: '#',
+        }
+    }
+}
+
+impl MessageCleanup {
+    /// The comment character given by `core.commentChar`. With `auto`, Git
+    /// picks one that no line of the message starts with; spr sticks to `#`.
+    pub fn parse_comment_char(value: &str) -> Result<char> {
+        let mut chars = value.chars();
+        match (value, chars.next(), chars.next()) {
+            ("auto", _, _) => Ok('#'),
+            (_, Some(c), None) if !c.is_whitespace() => Ok(c),
+            _ => Err(Error::new(format!(
+                "Invalid value for core.commentChar: '{}' (must be a single \
+                 character or 'auto')",
+                value
+            ))),
+        }
+    }
+
+    fn scissors_line(&self) -> String {
+        format!("{}{}", self.comment_char, SCISSORS_LINE)
+    }
+
+    /// The text to put into the editor: `message`, followed by the `hint`
+    /// lines as comments. Unless comments are stripped, the hint goes below
+    /// a scissors line, so that the cleanup drops it all the same.
+    pub fn template(&self, message: &str, hint: &[&str]) -> String {
+        let mut template = format!("{}\n", message.trim_end());
+        if self.mode != CleanupMode::Strip {
+            template.push_str(&format!(
+                "{}\n{} Do not modify or remove the line above.\n{} \
+                 Everything below it will be ignored.\n",
+                self.scissors_line(),
+                self.comment_char,
+                self.comment_char
+            ));
+        }
+        for line in hint {
+            template.push_str(&format!("{} {}\n", self.comment_char, line));
+        }
+
+        template
+    }
+
+    /// Clean up a message the user edited the way `git commit` does: drop
+    /// everything from the scissors line on, and then, depending on the
+    /// mode, comment lines, trailing whitespace and leading, trailing and
+    /// repeated blank lines.
+    pub fn clean_up(&self, msg: &str) -> String {
+        let scissors_line = self.scissors_line();
+        let mut text = String::new();
+        for line in msg.lines() {
+            if line == scissors_line {
+                break;
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        if self.mode == CleanupMode::Verbatim {
+            return text;
+        }
+
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            if self.mode == CleanupMode::Strip
+                && line.starts_with(self.comment_char)
+            {
+                continue;
+            }
+            let line = line.trim_end();
+            if line.is_empty() && matches!(lines.last(), None | Some(&"")) {
+                continue;
+            }
+            lines.push(line);
+        }
+        if lines.last() == Some(&"") {
+            lines.pop();
+        }
+
+        if lines.is_empty() {
+            String::new()
+        } else {
+            format!("{}\n", lines.join("\n"))
+        }
+    }
+}
+
 pub fn parse_message(
     msg: &str,
     top_section: MessageSection,
@@ -64,6 +209,8 @@ pub fn parse_message(
     let mut lines_in_section = Vec::<&str>::new();
     let mut sections =
         std::collections::BTreeMap::<MessageSection, String>::new();
+    // Lines like `reviewers: ...` in a ```spr block do not start sections
+    let mut in_pr_config_block = false;
 
     for (lineno, line) in msg
         .trim()
@@ -71,7 +218,11 @@ pub fn parse_message(
         .map(|line| line.trim_end())
         .enumerate()
     {
-        if let Some(caps) = regex.captures(line) {
+        if in_pr_config_block {
+            in_pr_config_block = line != crate::pr_config::BLOCK_END;
+        } else if line == crate::pr_config::BLOCK_START {
+            in_pr_config_block = true;
+        } else if let Some(caps) = regex.captures(line) {
             let label = caps.get(1).unwrap().as_str();
             let payload = caps.get(2).unwrap().as_str();
 
@@ -165,26 +316,148 @@ pub fn build_message(
     result
 }
 
-pub(crate) fn build_pr_stack_message(
-    prs: &Vec<u64>,
-    owner: &str,
-    repo: &str,
-) -> String {
+/// The text following the URL of the current Pull Request in the "PR Stack"
+/// section, unless configured otherwise.
+pub const DEFAULT_CURRENT_PR_MARKER: &str = "<-- (current PR)";
+
+/// Settings that control how the "PR Stack" section is rendered.
+#[derive(Clone, Debug)]
+pub struct PRStackFormat {
+    /// Maximum number of Pull Requests listed individually. Further Pull
+    /// Requests are collapsed into an "…and N more" block. The current Pull
+    /// Request is always listed.
+    pub limit: Option<usize>,
+    /// A line (e.g. a Markdown heading) put above the list of Pull Requests
+    pub heading: Option<String>,
+    /// The text following the URL of the current Pull Request
+    pub current_marker: String,
+    /// Whether to show the lines each Pull Request adds and deletes
+    pub diffstat: bool,
+    /// Whether to put the section between HTML comments in Pull Request
+    /// descriptions, by which spr finds it again however else the
+    /// description was edited
+    pub comment_markers: bool,
+    /// Whether to leave the section out of the descriptions of Pull Requests
+    /// that are the only one in their stack. Commit messages still have it,
+    /// because commits stacked on top later take their stack from there.
+    pub hide_single_pr_footer: bool,
+    /// Whether Pull Requests of the stack that are merged already stay in
+    /// it, marked as merged, rather than being left out
+    pub footer_keep_merged: bool,
+}
+
+impl Default for PRStackFormat {
+    fn default() -> Self {
+        Self {
+            limit: None,
+            heading: None,
+            current_marker: DEFAULT_CURRENT_PR_MARKER.to_string(),
+            diffstat: false,
+            comment_markers: false,
+            hide_single_pr_footer: false,
+            footer_keep_merged: false,
+        }
+    }
+}
+
+/// The size of a Pull Request as shown in the PR Stack, e.g. `(+120 −34)`.
+pub fn format_diffstat(diffstat: &DiffStat) -> String {
+    format!("(+{} \u{2212}{})", diffstat.additions, diffstat.deletions)
+}
+
+/// The text following the URL (and size) of a merged Pull Request that the
+/// PR Stack keeps listing.
+pub const MERGED_PR_MARKER: &str = "(merged)";
+
+/// The Pull Requests that the PR Stack of a stack of `prs` lists: those in
+/// `merged` are left out, unless `spr.footerKeepMerged` keeps them. The first
+/// one, which is the Pull Request whose PR Stack it is, is always listed.
+pub(crate) fn listed_pr_stack(
+    prs: &[u64],
+    merged: &HashSet<u64>,
+    config: &crate::config::Config,
+) -> Vec<u64> {
     prs.iter()
         .enumerate()
-        .map(|(index, &pr)| {
-            if index == 0 {
-                format!(
-                    "https://github.com/{}/{}/pull/{} <-- (current PR)\n",
-                    owner, repo, pr
-                )
-            } else {
-                format!("https://github.com/{}/{}/pull/{}\n", owner, repo, pr)
-            }
+        .filter(|(index, pr)| {
+            *index == 0
+                || config.pr_stack_format.footer_keep_merged
+                || !merged.contains(pr)
         })
+        .map(|(_, pr)| *pr)
         .collect()
 }
 
+/// What the PR Stack shows about the Pull Requests it lists, besides their
+/// URLs.
+#[derive(Clone, Debug, Default)]
+pub struct PRStackDetails {
+    /// The sizes of the Pull Requests, for those that have one
+    pub diffstats: HashMap<u64, DiffStat>,
+    /// The Pull Requests that are merged already: they are marked with
+    /// [`MERGED_PR_MARKER`], or left out (see [`listed_pr_stack`])
+    pub merged: HashSet<u64>,
+}
+
+/// The PR Stack section listing the given Pull Requests, with what `details`
+/// says about them.
+pub(crate) fn build_pr_stack_message(
+    prs: &[u64],
+    details: &PRStackDetails,
+    config: &crate::config::Config,
+) -> String {
+    let format = &config.pr_stack_format;
+    let merged = &details.merged;
+    let prs = &listed_pr_stack(prs, merged, config)[..];
+    let line = |index: usize, pr: u64| {
+        let mut line = config.pull_request_url(pr);
+        if let Some(diffstat) = details.diffstats.get(&pr) {
+            line.push(' ');
+            line.push_str(&format_diffstat(diffstat));
+        }
+        if format.footer_keep_merged && merged.contains(&pr) {
+            line.push(' ');
+            line.push_str(MERGED_PR_MARKER);
+        }
+        if index == 0 && !format.current_marker.is_empty() {
+            line.push(' ');
+            line.push_str(&format.current_marker);
+        }
+        line.push('\n');
+        line
+    };
+
+    let mut result = String::new();
+    if let (Some(heading), false) = (&format.heading, prs.is_empty()) {
+        result.push_str(&format!("{}\n\n", heading));
+    }
+
+    let shown = format.limit.unwrap_or(prs.len()).max(1).min(prs.len());
+    result.extend(
+        prs[..shown]
+            .iter()
+            .enumerate()
+            .map(|(index, &pr)| line(index, pr)),
+    );
+
+    // The collapsed Pull Requests are still listed (inside an HTML details
+    // element, so GitHub hides them until expanded), because commits stacked
+    // on top of this one take their stack from this list.
+    let collapsed = &prs[shown..];
+    if !collapsed.is_empty() {
+        result.push_str(&format!(
+            "<details><summary>…and {} more</summary>\n\n",
+            collapsed.len()
+        ));
+        for (index, &pr) in collapsed.iter().enumerate() {
+            result.push_str(&line(shown + index, pr));
+        }
+        result.push_str("</details>\n");
+    }
+
+    result
+}
+
 pub fn build_commit_message(section_texts: &MessageSectionsMap) -> String {
     build_message(
         section_texts,
@@ -200,15 +473,566 @@ pub fn build_commit_message(section_texts: &MessageSectionsMap) -> String {
     )
 }
 
-pub fn build_github_body(section_texts: &MessageSectionsMap) -> String {
-    build_message(
-        section_texts,
-        &[
-            MessageSection::Summary,
-            MessageSection::TestPlan,
-            MessageSection::PRStack,
-        ],
-    )
+/// The title for the Pull Request on GitHub, derived from the commit message.
+pub fn build_github_title(
+    section_texts: &MessageSectionsMap,
+    config: &crate::config::Config,
+) -> Option<String> {
+    let title = section_texts.get(&MessageSection::Title)?;
+    let title = if config.strip_title_emoji {
+        crate::utils::strip_leading_emoji(title)
+    } else {
+        title
+    };
+    if config.normalize_typography {
+        Some(crate::utils::normalize_typography(title))
+    } else {
+        Some(title.to_string())
+    }
+}
+
+/// A trailer (e.g. `Test-Plan`) that is rendered as a section of its own,
+/// under the given heading, in Pull Request descriptions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrailerSection {
+    pub key: String,
+    pub heading: String,
+}
+
+impl std::str::FromStr for TrailerSection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some((key, heading))
+                if !key.trim().is_empty() && !heading.trim().is_empty() =>
+            {
+                Ok(Self {
+                    key: key.trim().to_string(),
+                    heading: heading.trim().to_string(),
+                })
+            }
+            _ => Err(Error::new(format!(
+                "Invalid value for spr.trailerSection: '{}' (must be \
+                 'KEY=HEADING', e.g. 'Test-Plan=## Test Plan')",
+                s
+            ))),
+        }
+    }
+}
+
+/// Whether the given PR Stack section is left out of Pull Request
+/// descriptions, as it lists only the one Pull Request and
+/// `spr.hideSinglePrFooter` is set.
+pub fn hides_pr_stack(pr_stack: &str, config: &crate::config::Config) -> bool {
+    config.pr_stack_format.hide_single_pr_footer
+        && crate::utils::parse_pr_stack_list(pr_stack).len() <= 1
+}
+
+pub fn build_github_body(
+    section_texts: &MessageSectionsMap,
+    config: &crate::config::Config,
+) -> String {
+    let without_pr_stack;
+    let section_texts = match section_texts.get(&MessageSection::PRStack) {
+        Some(pr_stack) if hides_pr_stack(pr_stack, config) => {
+            without_pr_stack = {
+                let mut sections = section_texts.clone();
+                sections.remove(&MessageSection::PRStack);
+                sections
+            };
+            &without_pr_stack
+        }
+        _ => section_texts,
+    };
+    let depends_on = section_texts
+        .get(&MessageSection::PRStack)
+        .filter(|_| config.depends_on_line)
+        .and_then(|pr_stack| depends_on_line(pr_stack));
+
+    if config.pr_stack_format.comment_markers {
+        if let Some(pr_stack) = section_texts.get(&MessageSection::PRStack) {
+            let mut description = section_texts.clone();
+            description.remove(&MessageSection::PRStack);
+            return replace_marked_pr_stack(
+                &build_unmarked_github_body(
+                    &description,
+                    depends_on.as_deref(),
+                    config,
+                ),
+                pr_stack,
+            );
+        }
+    }
+
+    build_unmarked_github_body(section_texts, depends_on.as_deref(), config)
+}
+
+/// The `Depends on #N` line naming the Pull Request below this one in the
+/// stack, given its PR Stack section, or `None` for the bottom one. With
+/// `spr.dependsOnLine` it goes into Pull Request descriptions, for tools
+/// that look for the phrase.
+pub fn depends_on_line(pr_stack: &str) -> Option<String> {
+    let below = *crate::utils::parse_pr_stack_list(pr_stack).get(1)?;
+
+    Some(format!("Depends on #{}", below))
+}
+
+/// `text` without the `Depends on #N` line, as spr puts it into Pull Request
+/// descriptions.
+pub fn strip_depends_on_line(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut lines = text.split_inclusive('\n').peekable();
+    while let Some(line) = lines.next() {
+        if lazy_regex::regex_is_match!(r#"^Depends on #\d+\s*$"#, line) {
+            // Along with the blank line that separates it from the PR Stack
+            if lines.peek().is_some_and(|next| next.trim().is_empty()) {
+                lines.next();
+            }
+            continue;
+        }
+        stripped.push_str(line);
+    }
+
+    stripped
+}
+
+/// The HTML comments that the PR Stack section is put between in Pull Request
+/// descriptions with `spr.prStackCommentMarkers`. GitHub does not show them.
+pub const PR_STACK_START_MARKER: &str = "<!-- spr:pr-stack:start -->";
+pub const PR_STACK_END_MARKER: &str = "<!-- spr:pr-stack:end -->";
+
+/// Where the marked PR Stack section is in `body`: the ranges of the whole
+/// section (markers included) and of what is between the markers.
+fn find_marked_pr_stack(
+    body: &str,
+) -> Option<(std::ops::Range<usize>, std::ops::Range<usize>)> {
+    let start = body.find(PR_STACK_START_MARKER)?;
+    let inner_start = start + PR_STACK_START_MARKER.len();
+    let inner_end =
+        inner_start + body[inner_start..].find(PR_STACK_END_MARKER)?;
+
+    Some((
+        start..inner_end + PR_STACK_END_MARKER.len(),
+        inner_start..inner_end,
+    ))
+}
+
+/// Replace the marked PR Stack section of a Pull Request description with
+/// one listing `pr_stack`, leaving the rest of the description as it is. A
+/// description without one gets it added at the end.
+pub fn replace_marked_pr_stack(body: &str, pr_stack: &str) -> String {
+    let section_texts = MessageSectionsMap::from([(
+        MessageSection::PRStack,
+        pr_stack.to_string(),
+    )]);
+    let section = format!(
+        "{}\n{}{}",
+        PR_STACK_START_MARKER,
+        build_message(&section_texts, &[MessageSection::PRStack]),
+        PR_STACK_END_MARKER
+    );
+
+    match find_marked_pr_stack(body) {
+        Some((range, _)) => {
+            format!("{}{}{}", &body[..range.start], section, &body[range.end..])
+        }
+        None if body.trim().is_empty() => format!("{}\n", section),
+        None => format!("{}\n\n{}\n", body.trim_end(), section),
+    }
+}
+
+/// Take the marked PR Stack section out of a Pull Request description:
+/// returns the rest of the description and the list of Pull Requests in the
+/// section, if there is one.
+pub fn take_marked_pr_stack(body: &str) -> (String, Option<String>) {
+    let (range, inner) = match find_marked_pr_stack(body) {
+        Some(ranges) => ranges,
+        None => return (body.to_string(), None),
+    };
+
+    let pr_stack = parse_message(&body[inner], MessageSection::PRStack)
+        .remove(&MessageSection::PRStack)
+        .unwrap_or_default();
+    let before = body[..range.start].trim_end();
+    let after = body[range.end..].trim_start();
+    let rest = match (before.is_empty(), after.is_empty()) {
+        (true, _) => after.to_string(),
+        (false, true) => format!("{}\n", before),
+        (false, false) => format!("{}\n\n{}", before, after),
+    };
+
+    (rest, Some(pr_stack))
+}
+
+/// The HTML comments that a description given with `spr diff --body-file` or
+/// `--edit-body` is put between in the Pull Request description, so that
+/// updating the Pull Request leaves it alone.
+pub const DESCRIPTION_START_MARKER: &str = "<!-- spr:description:start -->";
+pub const DESCRIPTION_END_MARKER: &str = "<!-- spr:description:end -->";
+
+/// The description between the markers in the body of a Pull Request, if it
+/// has one of its own.
+pub fn marked_description(body: &str) -> Option<&str> {
+    let start =
+        body.find(DESCRIPTION_START_MARKER)? + DESCRIPTION_START_MARKER.len();
+    let end = start + body[start..].find(DESCRIPTION_END_MARKER)?;
+
+    Some(body[start..end].trim())
+}
+
+/// The body of a Pull Request without the markers of its own description (but
+/// with the description itself).
+pub fn unmark_description(body: &str) -> String {
+    body.lines()
+        .filter(|line| {
+            let line = line.trim();
+            line != DESCRIPTION_START_MARKER && line != DESCRIPTION_END_MARKER
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The message to create a Pull Request from, when its description is given
+/// with `--body-file` or `--edit-body`: `description` (verbatim, between
+/// markers) takes the place of the summary and test plan of the commit
+/// message. The sections spr manages, like the PR Stack, stay.
+pub fn with_description(
+    message: &MessageSectionsMap,
+    description: &str,
+) -> MessageSectionsMap {
+    let mut message = message.clone();
+    message.remove(&MessageSection::TestPlan);
+    message.insert(
+        MessageSection::Summary,
+        format!(
+            "{}\n{}\n{}",
+            DESCRIPTION_START_MARKER,
+            description.trim(),
+            DESCRIPTION_END_MARKER
+        ),
+    );
+    message
+}
+
+/// The message to update the Pull Request whose description is `body` from:
+/// if the Pull Request has a description of its own, that one is kept instead
+/// of the summary and test plan of `message`.
+pub fn with_pull_request_description(
+    message: &MessageSectionsMap,
+    body: Option<&str>,
+) -> MessageSectionsMap {
+    match body.and_then(marked_description) {
+        Some(description) => with_description(message, description),
+        None => message.clone(),
+    }
+}
+
+/// The description of a Pull Request, with the PR Stack section (if any) in
+/// the place of an ordinary section.
+fn build_unmarked_github_body(
+    section_texts: &MessageSectionsMap,
+    depends_on: Option<&str>,
+    config: &crate::config::Config,
+) -> String {
+    let mut section_texts = section_texts.clone();
+    crate::pr_config::strip_from_message(&mut section_texts);
+    let closing_lines = issue_closing_lines(&section_texts);
+    let trailer_sections =
+        take_trailer_sections(&mut section_texts, &config.trailer_sections);
+    let strip_keys = stripped_trailer_keys(config);
+    section_texts.retain(|_, text| {
+        let (kept, stripped) = partition_trailers(text, &strip_keys);
+        if stripped.is_empty() {
+            return true;
+        }
+        // Drop sections that consisted of stripped trailers only
+        *text = kept;
+        !text.is_empty()
+    });
+    restore_issue_closing_lines(&mut section_texts, &closing_lines);
+
+    if trailer_sections.is_empty() && depends_on.is_none() {
+        return build_message(
+            &section_texts,
+            &[
+                MessageSection::Summary,
+                MessageSection::TestPlan,
+                MessageSection::PRStack,
+            ],
+        );
+    }
+
+    // The sections made from trailers, and the Depends On line, go between
+    // the description and the PR Stack
+    let mut parts = vec![build_message(
+        &section_texts,
+        &[MessageSection::Summary, MessageSection::TestPlan],
+    )];
+    parts.extend(
+        trailer_sections
+            .into_iter()
+            .chain(depends_on.map(String::from))
+            .map(|section| format!("{}\n", section)),
+    );
+    parts.push(build_message(&section_texts, &[MessageSection::PRStack]));
+    parts.retain(|part| !part.is_empty());
+
+    parts.join("\n")
+}
+
+/// The lines of the description (summary and test plan) that make GitHub close
+/// an issue when the Pull Request is merged, like `Closes #123` or
+/// `Fixes: acme/codez#45`.
+fn issue_closing_lines(section_texts: &MessageSectionsMap) -> Vec<String> {
+    [MessageSection::Summary, MessageSection::TestPlan]
+        .iter()
+        .filter_map(|section| section_texts.get(section))
+        .flat_map(|text| text.lines())
+        .filter(|line| {
+            lazy_regex::regex_is_match!(
+                r#"^\s*(close[sd]?|fix(e[sd])?|resolve[sd]?)\s*:?\s+([\w.-]+/[\w.-]+)?#\d+"#i,
+                line
+            )
+        })
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+/// Put the issue-closing lines that stripping trailers (or rendering them as
+/// sections of their own) removed back at the end of the summary, verbatim,
+/// so that GitHub still closes the issues.
+fn restore_issue_closing_lines(
+    section_texts: &mut MessageSectionsMap,
+    closing_lines: &[String],
+) {
+    let missing: Vec<&str> = closing_lines
+        .iter()
+        .filter(|line| {
+            ![MessageSection::Summary, MessageSection::TestPlan]
+                .iter()
+                .filter_map(|section| section_texts.get(section))
+                .any(|text| text.lines().any(|l| l.trim() == line.as_str()))
+        })
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+
+    append_to_message_section(
+        section_texts.entry(MessageSection::Summary),
+        &missing.join("\n"),
+    );
+}
+
+/// Take the trailers that are rendered as sections of their own out of the
+/// message, and return those sections (heading and content), in the order in
+/// which they are configured. Sections of the message that consisted of such
+/// trailers only are removed.
+fn take_trailer_sections(
+    section_texts: &mut MessageSectionsMap,
+    trailer_sections: &[TrailerSection],
+) -> Vec<String> {
+    let mut result = Vec::new();
+    for trailer_section in trailer_sections {
+        let keys = [trailer_section.key.clone()];
+        let mut values = Vec::new();
+        section_texts.retain(|_, text| {
+            let (kept, stripped) = partition_trailers(text, &keys);
+            if stripped.is_empty() {
+                return true;
+            }
+            values.push(trailer_value(&stripped));
+            *text = kept;
+            !text.is_empty()
+        });
+
+        if !values.is_empty() {
+            result.push(format!(
+                "{}\n\n{}",
+                trailer_section.heading,
+                values.join("\n\n")
+            ));
+        }
+    }
+
+    result
+}
+
+/// The value of a trailer, given its lines: what follows the key on the first
+/// line, and the (de-indented) continuation lines.
+fn trailer_value(lines: &str) -> String {
+    lines
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            if index == 0 {
+                line.split_once(':').map_or("", |(_, value)| value).trim()
+            } else {
+                line.trim()
+            }
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The trailer that sets how `spr land` merges the Pull Request of a commit,
+/// in place of the default (squash).
+pub const MERGE_METHOD_TRAILER: &str = "Merge-Method";
+
+/// The keys of the trailers that are left out of Pull Request descriptions:
+/// those in `spr.stripTrailers`, and the ones spr itself reads.
+fn stripped_trailer_keys(config: &crate::config::Config) -> Vec<String> {
+    config
+        .strip_trailers
+        .iter()
+        .cloned()
+        .chain(std::iter::once(MERGE_METHOD_TRAILER.to_string()))
+        .collect()
+}
+
+/// The merge method that the `Merge-Method` trailer of the commit message
+/// asks for, if it has one. Fails if the trailer has some other value than
+/// `squash`, `merge` or `rebase`.
+pub fn merge_method_trailer(
+    section_texts: &MessageSectionsMap,
+) -> Result<Option<crate::forge::MergeMethod>> {
+    use crate::forge::MergeMethod;
+
+    let keys = [MERGE_METHOD_TRAILER.to_string()];
+    let value = section_texts.values().find_map(|text| {
+        let (_, stripped) = partition_trailers(text, &keys);
+        stripped
+            .lines()
+            .next()
+            .map(|line| trailer_value(line).to_ascii_lowercase())
+    });
+
+    match value.as_deref() {
+        None => Ok(None),
+        Some("squash") => Ok(Some(MergeMethod::Squash)),
+        Some("merge") => Ok(Some(MergeMethod::Merge)),
+        Some("rebase") => Ok(Some(MergeMethod::Rebase)),
+        Some(other) => Err(Error::new(format!(
+            "Invalid {} trailer '{}' (must be squash, merge or rebase)",
+            MERGE_METHOD_TRAILER, other
+        ))),
+    }
+}
+
+/// Remove the trailers with the given keys (compared case-insensitively) from
+/// the trailer block of `text`, along with their continuation lines.
+///
+/// The trailer block is the last paragraph of the text, if it consists of
+/// trailers (`Key: value` lines, where the key has no spaces) and indented
+/// continuation lines only. Everything else is left alone, so prose that
+/// happens to contain a colon is never touched.
+pub fn strip_trailers(text: &str, keys: &[String]) -> String {
+    partition_trailers(text, keys).0
+}
+
+/// Split `text` into what is left after stripping the trailers with the given
+/// keys (see `strip_trailers`), and the stripped trailer lines.
+fn partition_trailers(text: &str, keys: &[String]) -> (String, String) {
+    let lines: Vec<&str> = text.lines().collect();
+    let block_start = lines
+        .iter()
+        .rposition(|line| line.trim().is_empty())
+        .map(|index| index + 1)
+        .unwrap_or(0);
+    let block = &lines[block_start..];
+
+    let trailer_key = |line: &str| {
+        lazy_regex::regex_captures!(r#"^\s*([\w-]+)\s*:"#, line)
+            .map(|(_, key)| key.to_string())
+    };
+    let is_continuation =
+        |line: &str| line.starts_with(|c: char| c.is_whitespace());
+    let is_trailer_block = block
+        .first()
+        .is_some_and(|line| trailer_key(line).is_some())
+        && block
+            .iter()
+            .all(|line| trailer_key(line).is_some() || is_continuation(line));
+    if !is_trailer_block {
+        return (text.to_string(), String::new());
+    }
+
+    let mut kept = Vec::new();
+    let mut stripped = Vec::new();
+    let mut stripping = false;
+    for line in block {
+        if let Some(key) = trailer_key(line) {
+            stripping = keys
+                .iter()
+                .any(|strip| strip.trim().eq_ignore_ascii_case(&key));
+        }
+        if stripping {
+            stripped.push(*line);
+        } else {
+            kept.push(*line);
+        }
+    }
+
+    let mut result: Vec<&str> = lines[..block_start].to_vec();
+    if kept.is_empty() {
+        while result.last().is_some_and(|line| line.trim().is_empty()) {
+            result.pop();
+        }
+    } else {
+        result.extend(kept);
+    }
+
+    (result.join("\n"), stripped.join("\n"))
+}
+
+/// Put the trailers that are stripped from Pull Request descriptions (or
+/// rendered as sections of their own) back into a commit message taken from
+/// GitHub (as `spr amend` does), copying them from the local commit message
+/// they were taken from.
+pub fn restore_stripped_trailers(
+    local: &MessageSectionsMap,
+    from_github: &mut MessageSectionsMap,
+    config: &crate::config::Config,
+) {
+    let keys: Vec<String> = stripped_trailer_keys(config)
+        .into_iter()
+        .chain(config.trailer_sections.iter().map(|ts| ts.key.clone()))
+        .collect();
+
+    // The sections rendered from trailers come back from GitHub as part of
+    // another section; the trailers replace them.
+    let rendered =
+        take_trailer_sections(&mut local.clone(), &config.trailer_sections);
+    from_github.retain(|_, text| {
+        let was_empty = text.is_empty();
+        for section in &rendered {
+            if let Some(index) = text.find(section.as_str()) {
+                text.replace_range(index..index + section.len(), "");
+                *text = text.trim().to_string();
+            }
+        }
+        was_empty || !text.is_empty()
+    });
+
+    for (section, text) in local {
+        let (_, stripped) = partition_trailers(text, &keys);
+        if stripped.is_empty() {
+            continue;
+        }
+
+        let text = from_github.entry(*section).or_default();
+        if text.contains(&stripped) {
+            continue;
+        }
+        if text.is_empty() {
+            *text = stripped;
+        } else {
+            *text = format!("{}\n\n{}", text, stripped);
+        }
+    }
 }
 
 pub fn build_github_body_for_merging(
@@ -226,6 +1050,31 @@ pub fn build_github_body_for_merging(
     )
 }
 
+/// Whether the commit is marked as work in progress, and thus should not be
+/// submitted as a Pull Request. A commit is marked this way by a title starting
+/// with `[WIP]` or `fixup!`, or by a `Skip-PR: true` line.
+pub fn is_work_in_progress(section_texts: &MessageSectionsMap) -> bool {
+    let title = section_texts
+        .get(&MessageSection::Title)
+        .map(|t| t.trim_start())
+        .unwrap_or("");
+    if title.to_ascii_lowercase().starts_with("[wip]")
+        || title.starts_with("fixup!")
+    {
+        return true;
+    }
+
+    section_texts
+        .values()
+        .flat_map(|text| text.lines())
+        .any(|line| {
+            lazy_regex::regex_is_match!(
+                r#"^\s*skip-pr\s*:\s*(true|yes|1)\s*$"#i,
+                line
+            )
+        })
+}
+
 pub fn validate_commit_message(
     message: &MessageSectionsMap,
     config: &crate::config::Config,
@@ -249,16 +1098,157 @@ pub fn validate_commit_message(
     Ok(())
 }
 
+/// The length of `title` in characters, if it is longer than `limit`.
+pub fn title_too_long(title: &str, limit: usize) -> Option<usize> {
+    let length = title.chars().count();
+    (length > limit).then_some(length)
+}
+
+/// Warn if the Pull Request title made from the commit message is longer than
+/// `spr.titleLengthLimit`, or fail if `strict` (`spr diff --strict-title`).
+pub fn check_title_length(
+    message: &MessageSectionsMap,
+    config: &crate::config::Config,
+    strict: bool,
+) -> Result<()> {
+    let title = build_github_title(message, config).unwrap_or_default();
+    let limit = match config.title_length_limit {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    let length = match title_too_long(&title, limit) {
+        Some(length) => length,
+        None => return Ok(()),
+    };
+
+    let message = format!(
+        "The title is {} characters long, and GitHub cuts off titles longer \
+         than {} in places. Consider rewording the commit.",
+        length, limit
+    );
+    if strict {
+        output("💔", &message)?;
+        return Err(Error::empty());
+    }
+    output("⚠️", &message)?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
     #[test]
-    fn test_parse_empty() {
+    fn test_clean_up_strip() {
+        let cleanup = MessageCleanup::default();
         assert_eq!(
-            parse_message("", MessageSection::Title),
-            [(MessageSection::Title, "".to_string())].into()
+            cleanup.clean_up(
+                "\n\nTitle  \n# Please enter the commit message\n\n\n\
+                 Summary\t\n#comment\n\nTest Plan: ran it\n\n\n"
+            ),
+            "Title\n\nSummary\n\nTest Plan: ran it\n"
+        );
+        assert_eq!(cleanup.clean_up("# Only comments\n#\n"), "");
+        // Only lines starting with '#' are comments
+        assert_eq!(
+            cleanup.clean_up("Fix #123\n\n  # indented\n"),
+            "Fix #123\n\n  # indented\n"
+        );
+
+        let cleanup = MessageCleanup {
+            comment_char: ';',
+            ..Default::default()
+        };
+        assert_eq!(
+            cleanup.clean_up("Title\n; comment\n\n## Heading\n"),
+            "Title\n\n## Heading\n"
+        );
+    }
+
+    #[test]
+    fn test_clean_up_verbose() {
+        assert_eq!(
+            MessageCleanup::default().clean_up(
+                "Title\n\nSummary\n\n\
+                 # ------------------------ >8 ------------------------\n\
+                 diff --git a/file b/file\n\
+                 +added line\n"
+            ),
+            "Title\n\nSummary\n"
+        );
+    }
+
+    #[test]
+    fn test_clean_up_modes() {
+        let text = "\nTitle  \n\n\n## Heading\n\nSummary\n\n\
+                    # ------------------------ >8 ------------------------\n\
+                    # Hint\n";
+        let clean_up = |mode| {
+            MessageCleanup {
+                mode,
+                ..Default::default()
+            }
+            .clean_up(text)
+        };
+        assert_eq!(
+            clean_up(CleanupMode::Verbatim),
+            "\nTitle  \n\n\n## Heading\n\nSummary\n\n"
+        );
+        assert_eq!(
+            clean_up(CleanupMode::Whitespace),
+            "Title\n\n## Heading\n\nSummary\n"
+        );
+        assert_eq!(
+            clean_up(CleanupMode::Scissors),
+            "Title\n\n## Heading\n\nSummary\n"
+        );
+        assert_eq!(clean_up(CleanupMode::Strip), "Title\n\nSummary\n");
+
+        assert_eq!(
+            "default".parse::<CleanupMode>().unwrap(),
+            CleanupMode::Strip
+        );
+        assert_eq!(
+            "Whitespace".parse::<CleanupMode>().unwrap(),
+            CleanupMode::Whitespace
+        );
+        assert!("none".parse::<CleanupMode>().is_err());
+
+        assert_eq!(MessageCleanup::parse_comment_char(";").unwrap(), ';');
+        assert_eq!(MessageCleanup::parse_comment_char("auto").unwrap(), '#');
+        assert!(MessageCleanup::parse_comment_char("//").is_err());
+    }
+
+    #[test]
+    fn test_message_round_trip_keeps_heading() {
+        // Under `commit.cleanup=whitespace`, a Markdown heading in the body
+        // stays, and rebuilding the message gives back what was committed
+        let cleanup = MessageCleanup {
+            mode: CleanupMode::Whitespace,
+            ..Default::default()
+        };
+        let text = "Title\n\n## Heading\n\nSummary\n\nTest Plan: ran it\n";
+        let message =
+            parse_message(&cleanup.clean_up(text), MessageSection::Title);
+        assert_eq!(
+            message.get(&MessageSection::Summary).unwrap(),
+            "## Heading\n\nSummary"
+        );
+        assert_eq!(
+            cleanup.clean_up(
+                &cleanup.template(&build_commit_message(&message), &["Hint"])
+            ),
+            text
+        );
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(
+            parse_message("", MessageSection::Title),
+            [(MessageSection::Title, "".to_string())].into()
         );
     }
 
@@ -344,15 +1334,38 @@ Reviewer:    a, b, c"#,
         );
     }
 
+    fn stack_config(
+        owner: &str,
+        repo: &str,
+        format: PRStackFormat,
+    ) -> crate::config::Config {
+        let mut config = crate::config::tests::config_factory();
+        config.owner = owner.to_string();
+        config.repo = repo.to_string();
+        config.pr_stack_format = format;
+        config
+    }
+
     #[test]
     fn test_build_pr_stack_message_empty() {
-        assert_eq!(build_pr_stack_message(&vec![], "owner", "repo"), "");
+        assert_eq!(
+            build_pr_stack_message(
+                &[],
+                &Default::default(),
+                &stack_config("owner", "repo", Default::default())
+            ),
+            ""
+        );
     }
 
     #[test]
     fn test_build_pr_stack_message_single_pr() {
         assert_eq!(
-            build_pr_stack_message(&vec![42], "owner", "repo"),
+            build_pr_stack_message(
+                &[42],
+                &Default::default(),
+                &stack_config("owner", "repo", Default::default())
+            ),
             "https://github.com/owner/repo/pull/42 <-- (current PR)\n"
         );
     }
@@ -360,7 +1373,11 @@ Reviewer:    a, b, c"#,
     #[test]
     fn test_build_pr_stack_message_multiple_prs() {
         assert_eq!(
-            build_pr_stack_message(&vec![1, 2, 3], "owner", "repo"),
+            build_pr_stack_message(
+                &[1, 2, 3],
+                &Default::default(),
+                &stack_config("owner", "repo", Default::default())
+            ),
             "https://github.com/owner/repo/pull/1 <-- (current PR)\n\
              https://github.com/owner/repo/pull/2\n\
              https://github.com/owner/repo/pull/3\n"
@@ -371,12 +1388,752 @@ Reviewer:    a, b, c"#,
     fn test_build_pr_stack_message_different_owner_repo() {
         assert_eq!(
             build_pr_stack_message(
-                &vec![10, 20],
-                "different-owner",
-                "different-repo"
+                &[10, 20],
+                &Default::default(),
+                &stack_config(
+                    "different-owner",
+                    "different-repo",
+                    Default::default()
+                )
             ),
             "https://github.com/different-owner/different-repo/pull/10 <-- (current PR)\n\
              https://github.com/different-owner/different-repo/pull/20\n"
         );
     }
+
+    #[test]
+    fn test_build_pr_stack_message_collapsed() {
+        let format = PRStackFormat {
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            build_pr_stack_message(
+                &[5, 4, 3, 2, 1],
+                &Default::default(),
+                &stack_config("owner", "repo", format)
+            ),
+            "https://github.com/owner/repo/pull/5 <-- (current PR)\n\
+             https://github.com/owner/repo/pull/4\n\
+             <details><summary>…and 3 more</summary>\n\
+             \n\
+             https://github.com/owner/repo/pull/3\n\
+             https://github.com/owner/repo/pull/2\n\
+             https://github.com/owner/repo/pull/1\n\
+             </details>\n"
+        );
+    }
+
+    #[test]
+    fn test_build_pr_stack_message_collapsed_keeps_current_pr() {
+        let format = PRStackFormat {
+            limit: Some(0),
+            ..Default::default()
+        };
+        let text = build_pr_stack_message(
+            &[3, 2, 1],
+            &Default::default(),
+            &stack_config("owner", "repo", format),
+        );
+        assert!(text.starts_with(
+            "https://github.com/owner/repo/pull/3 <-- (current PR)\n"
+        ));
+        assert!(text.contains("…and 2 more"));
+        assert_eq!(crate::utils::parse_pr_stack_list(&text), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_build_pr_stack_message_below_limit() {
+        let format = PRStackFormat {
+            limit: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            build_pr_stack_message(
+                &[2, 1],
+                &Default::default(),
+                &stack_config("owner", "repo", format)
+            ),
+            build_pr_stack_message(
+                &[2, 1],
+                &Default::default(),
+                &stack_config("owner", "repo", Default::default())
+            ),
+        );
+    }
+
+    #[test]
+    fn test_build_pr_stack_message_custom_heading_and_marker() {
+        let format = PRStackFormat {
+            heading: Some("### Stack".to_string()),
+            current_marker: "👈 this PR".to_string(),
+            ..Default::default()
+        };
+        let config = stack_config("owner", "repo", format);
+        let text =
+            build_pr_stack_message(&[3, 2, 1], &Default::default(), &config);
+
+        assert_eq!(
+            text,
+            "### Stack\n\
+             \n\
+             https://github.com/owner/repo/pull/3 👈 this PR\n\
+             https://github.com/owner/repo/pull/2\n\
+             https://github.com/owner/repo/pull/1\n"
+        );
+        assert_eq!(crate::utils::parse_pr_stack_list(&text), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_format_diffstat() {
+        assert_eq!(
+            format_diffstat(&DiffStat {
+                additions: 120,
+                deletions: 34
+            }),
+            "(+120 \u{2212}34)"
+        );
+        assert_eq!(format_diffstat(&DiffStat::default()), "(+0 \u{2212}0)");
+    }
+
+    #[test]
+    fn test_build_pr_stack_message_diffstats() {
+        let config = stack_config("owner", "repo", Default::default());
+        let details = PRStackDetails {
+            diffstats: HashMap::from([
+                (
+                    3,
+                    DiffStat {
+                        additions: 120,
+                        deletions: 34,
+                    },
+                ),
+                (
+                    1,
+                    DiffStat {
+                        additions: 5,
+                        deletions: 0,
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let text = build_pr_stack_message(&[3, 2, 1], &details, &config);
+        assert_eq!(
+            text,
+            "https://github.com/owner/repo/pull/3 (+120 \u{2212}34) \
+             <-- (current PR)\n\
+             https://github.com/owner/repo/pull/2\n\
+             https://github.com/owner/repo/pull/1 (+5 \u{2212}0)\n"
+        );
+        // The numbers do not get in the way of reading the stack back
+        assert_eq!(crate::utils::parse_pr_stack_list(&text), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_pr_stack_round_trip_with_custom_format() {
+        let format = PRStackFormat {
+            heading: Some("Stack 2/2".to_string()),
+            current_marker: "(this one)".to_string(),
+            limit: Some(1),
+            diffstat: false,
+            comment_markers: false,
+            hide_single_pr_footer: false,
+            footer_keep_merged: false,
+        };
+        let config = stack_config("owner", "repo", format);
+
+        let mut sections = MessageSectionsMap::new();
+        sections.insert(MessageSection::Summary, "Summary".to_string());
+        sections.insert(
+            MessageSection::PRStack,
+            build_pr_stack_message(&[2, 1], &Default::default(), &config),
+        );
+        let body = build_github_body(&sections, &config);
+        let parsed = parse_message(&body, MessageSection::Summary);
+
+        assert_eq!(
+            crate::utils::parse_pr_stack_list(
+                &parsed[&MessageSection::PRStack]
+            ),
+            vec![2, 1]
+        );
+    }
+
+    #[test]
+    fn test_build_pr_stack_message_no_marker() {
+        let format = PRStackFormat {
+            current_marker: String::new(),
+            ..Default::default()
+        };
+        assert_eq!(
+            build_pr_stack_message(
+                &[1],
+                &Default::default(),
+                &stack_config("owner", "repo", format)
+            ),
+            "https://github.com/owner/repo/pull/1\n"
+        );
+    }
+
+    #[test]
+    fn test_build_github_title_keeps_branch_slug_prefix() {
+        let mut config = crate::config::tests::config_factory();
+        config.branch_slug_strip_pattern =
+            Some(lazy_regex::Regex::new(r"[A-Z]+-\d+:").unwrap());
+        let message =
+            parse_message("PROJ-123: Add feature", MessageSection::Title);
+
+        assert_eq!(
+            build_github_title(&message, &config).as_deref(),
+            Some("PROJ-123: Add feature")
+        );
+    }
+
+    #[test]
+    fn test_build_github_title_normalize_typography() {
+        let mut config = crate::config::tests::config_factory();
+        let message = parse_message(
+            "Don\u{2019}t \u{201C}panic\u{201D} \u{2014} yet\u{2026}",
+            MessageSection::Title,
+        );
+
+        assert_eq!(
+            build_github_title(&message, &config).as_deref(),
+            Some("Don\u{2019}t \u{201C}panic\u{201D} \u{2014} yet\u{2026}")
+        );
+        config.normalize_typography = true;
+        assert_eq!(
+            build_github_title(&message, &config).as_deref(),
+            Some("Don't \"panic\" - yet")
+        );
+    }
+
+    #[test]
+    fn test_is_work_in_progress() {
+        let wip = |msg| {
+            is_work_in_progress(&parse_message(msg, MessageSection::Title))
+        };
+
+        assert!(wip("[WIP] Scratch"));
+        assert!(wip("[wip]Scratch"));
+        assert!(wip("fixup! Add feature"));
+        assert!(wip("Scratch\n\nSome notes\n\nSkip-PR: true"));
+        assert!(wip("Scratch\n\nTest Plan: none\nskip-pr: yes"));
+
+        assert!(!wip("Add feature"));
+        assert!(!wip("Add WIP support"));
+        assert!(!wip("Add feature\n\nSkip-PR: false"));
+        assert!(!wip("Add feature\n\nDocument the Skip-PR: true trailer"));
+    }
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_strip_trailers() {
+        let keys = keys(&["Ticket", "reviewer", " Test-Plan "]);
+
+        assert_eq!(
+            strip_trailers(
+                "Some prose.\n\nTicket: ABC-123\nChange-Id: I1234\n  \
+                 ticket: ABC-124\nReviewer: pat\nTest-Plan: ran it\n  \
+                 and it passed",
+                &keys
+            ),
+            "Some prose.\n\nChange-Id: I1234"
+        );
+        // Nothing left of the trailer block, so the blank line goes, too
+        assert_eq!(
+            strip_trailers("Some prose.\n\nTicket: ABC-123", &keys),
+            "Some prose."
+        );
+        assert_eq!(strip_trailers("Ticket: ABC-123", &keys), "");
+    }
+
+    #[test]
+    fn test_strip_trailers_leaves_prose_alone() {
+        let keys = keys(&["Ticket"]);
+
+        // Not the last paragraph
+        let text = "Ticket: ABC-123\n\nMore prose.";
+        assert_eq!(strip_trailers(text, &keys), text);
+        // The last paragraph is not all trailers
+        let text = "Prose.\n\nThis fixes it.\nTicket: ABC-123";
+        assert_eq!(strip_trailers(text, &keys), text);
+        // A key with spaces is no trailer
+        let text = "Prose.\n\nSee the Ticket: ABC-123";
+        assert_eq!(strip_trailers(text, &keys), text);
+        // Nothing configured
+        let text = "Prose.\n\nTicket: ABC-123";
+        assert_eq!(strip_trailers(text, &[]), text);
+    }
+
+    #[test]
+    fn test_build_github_body_strips_trailers() {
+        let mut config = crate::config::tests::config_factory();
+        config.strip_trailers = keys(&["Ticket"]);
+
+        let message = parse_message(
+            "Title\n\nSummary: prose\n\nTicket: ABC-1\n\n\
+             Test Plan: ran it\n\nTicket: ABC-2\nSigned-off-by: Pat",
+            MessageSection::Title,
+        );
+
+        assert_eq!(
+            build_github_body(&message, &config),
+            "prose\n\nTest Plan:\nran it\n\nSigned-off-by: Pat\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_method_trailer() {
+        use crate::forge::MergeMethod;
+        let method = |text: &str| {
+            merge_method_trailer(&parse_message(text, MessageSection::Title))
+        };
+
+        assert_eq!(
+            method("Title\n\nProse\n\nMerge-Method: merge").unwrap(),
+            Some(MergeMethod::Merge)
+        );
+        assert_eq!(
+            method("Title\n\nTest Plan: ran it\n\nmerge-method: Rebase")
+                .unwrap(),
+            Some(MergeMethod::Rebase)
+        );
+        assert_eq!(method("Title\n\nProse").unwrap(), None);
+        // Only the trailer block counts
+        assert_eq!(
+            method("Title\n\nMerge-Method: merge\n\nProse").unwrap(),
+            None
+        );
+        assert!(method("Title\n\nProse\n\nMerge-Method: fast-forward").is_err());
+    }
+
+    #[test]
+    fn test_build_github_body_strips_merge_method_trailer() {
+        let config = crate::config::tests::config_factory();
+        let message = parse_message(
+            "Title\n\nprose\n\nTest Plan: ran it\n\nMerge-Method: merge",
+            MessageSection::Title,
+        );
+
+        assert_eq!(
+            build_github_body(&message, &config),
+            "prose\n\nTest Plan: ran it\n"
+        );
+    }
+
+    fn marker_config() -> crate::config::Config {
+        let mut config = crate::config::tests::config_factory();
+        config.pr_stack_format.comment_markers = true;
+        config
+    }
+
+    #[test]
+    fn test_build_github_body_with_comment_markers() {
+        let config = marker_config();
+        let mut message =
+            parse_message("Title\n\nSome prose", MessageSection::Title);
+        message.insert(
+            MessageSection::PRStack,
+            build_pr_stack_message(&[2, 1], &Default::default(), &config),
+        );
+
+        let body = build_github_body(&message, &config);
+        assert_eq!(
+            body,
+            format!(
+                "Some prose\n\n{}\nPR Stack:\n{}\n{}\n",
+                PR_STACK_START_MARKER,
+                message[&MessageSection::PRStack],
+                PR_STACK_END_MARKER
+            )
+        );
+
+        // The section is found again, even after a bot added text around it
+        let edited = format!("{}\n_Checked by a bot_\n", body);
+        let (rest, pr_stack) = take_marked_pr_stack(&edited);
+        assert_eq!(rest, "Some prose\n\n_Checked by a bot_\n");
+        assert_eq!(
+            crate::utils::parse_pr_stack_list(&pr_stack.unwrap()),
+            vec![2, 1]
+        );
+    }
+
+    #[test]
+    fn test_build_github_body_hide_single_pr_footer() {
+        let mut config = crate::config::tests::config_factory();
+        config.pr_stack_format.hide_single_pr_footer = true;
+        let body = |config: &crate::config::Config, stack: &[u64]| {
+            let mut message =
+                parse_message("Title\n\nSome prose", MessageSection::Title);
+            message.insert(
+                MessageSection::PRStack,
+                build_pr_stack_message(stack, &Default::default(), config),
+            );
+            build_github_body(&message, config)
+        };
+
+        assert_eq!(body(&config, &[1]), "Some prose\n");
+        assert_eq!(
+            body(&config, &[2, 1]),
+            format!(
+                "Some prose\n\nPR Stack:\n{}\n",
+                build_pr_stack_message(&[2, 1], &Default::default(), &config)
+            )
+        );
+
+        // Not even between comment markers
+        config.pr_stack_format.comment_markers = true;
+        assert_eq!(body(&config, &[1]), "Some prose\n");
+        assert!(body(&config, &[2, 1]).contains(PR_STACK_START_MARKER));
+
+        config.pr_stack_format.hide_single_pr_footer = false;
+        assert!(body(&config, &[1]).contains("PR Stack:\n"));
+    }
+
+    #[test]
+    fn test_build_github_body_depends_on_line() {
+        let mut config = crate::config::tests::config_factory();
+        config.depends_on_line = true;
+        // #1 is the bottom of the stack, and #3 the top
+        let body = |config: &crate::config::Config, stack: &[u64]| {
+            let mut message =
+                parse_message("Title\n\nSome prose", MessageSection::Title);
+            message.insert(
+                MessageSection::PRStack,
+                build_pr_stack_message(stack, &Default::default(), config),
+            );
+            build_github_body(&message, config)
+        };
+
+        let top = body(&config, &[3, 2, 1]);
+        assert!(top.starts_with("Some prose\n\nDepends on #2\n\nPR Stack:\n"));
+        assert!(body(&config, &[2, 1]).contains("\nDepends on #1\n"));
+        assert!(!body(&config, &[1]).contains("Depends on"));
+
+        // It does not find its way back into the commit message
+        assert_eq!(
+            parse_message(
+                &strip_depends_on_line(&top),
+                MessageSection::Summary
+            )[&MessageSection::Summary],
+            "Some prose"
+        );
+
+        // Not without the setting
+        config.depends_on_line = false;
+        assert!(!body(&config, &[3, 2, 1]).contains("Depends on"));
+
+        // Nor does the PR Stack lose its place with comment markers
+        config.depends_on_line = true;
+        config.pr_stack_format.comment_markers = true;
+        let marked = body(&config, &[3, 2, 1]);
+        assert!(marked.starts_with(&format!(
+            "Some prose\n\nDepends on #2\n\n{}",
+            PR_STACK_START_MARKER
+        )));
+    }
+
+    #[test]
+    fn test_replace_marked_pr_stack() {
+        let config = marker_config();
+        let old = build_pr_stack_message(&[1], &Default::default(), &config);
+        let new = build_pr_stack_message(&[2, 1], &Default::default(), &config);
+        let body = format!(
+            "**Edited by a bot**\n\nSome prose\n\n{}\nPR Stack:\n{}\n\
+             {}\n\nMore from the bot\n",
+            PR_STACK_START_MARKER, old, PR_STACK_END_MARKER
+        );
+
+        let replaced = replace_marked_pr_stack(&body, &new);
+        assert_eq!(
+            replaced,
+            body.replace(&old, &new),
+            "only the list between the markers changes"
+        );
+        assert_eq!(replaced.matches(PR_STACK_START_MARKER).count(), 1);
+        assert_eq!(
+            crate::utils::parse_pr_stack_list(
+                &take_marked_pr_stack(&replaced).1.unwrap()
+            ),
+            vec![2, 1]
+        );
+    }
+
+    #[test]
+    fn test_replace_marked_pr_stack_inserts_when_absent() {
+        let config = marker_config();
+        let pr_stack =
+            build_pr_stack_message(&[1], &Default::default(), &config);
+
+        let inserted = replace_marked_pr_stack("Some prose\n", &pr_stack);
+        assert!(inserted.starts_with("Some prose\n\n<!--"));
+        assert!(inserted.ends_with(&format!("{}\n", PR_STACK_END_MARKER)));
+        assert_eq!(
+            take_marked_pr_stack(&inserted),
+            (
+                "Some prose\n".to_string(),
+                Some(pr_stack.trim().to_string())
+            )
+        );
+
+        assert!(replace_marked_pr_stack("", &pr_stack)
+            .starts_with(PR_STACK_START_MARKER));
+        // Without markers, there is nothing to take out
+        assert_eq!(
+            take_marked_pr_stack("Some prose\n"),
+            ("Some prose\n".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn test_with_description() {
+        let message = parse_message(
+            "Title\n\nCommit summary\n\nTest Plan: ran it\n\n\
+             Reviewers: alice",
+            MessageSection::Title,
+        );
+
+        let github_message = with_description(&message, "\nMy description\n");
+        assert_eq!(
+            github_message.get(&MessageSection::Summary).unwrap(),
+            &format!(
+                "{}\nMy description\n{}",
+                DESCRIPTION_START_MARKER, DESCRIPTION_END_MARKER
+            )
+        );
+        assert!(!github_message.contains_key(&MessageSection::TestPlan));
+        assert_eq!(
+            github_message.get(&MessageSection::Title),
+            message.get(&MessageSection::Title)
+        );
+        assert_eq!(
+            github_message.get(&MessageSection::Reviewers),
+            message.get(&MessageSection::Reviewers)
+        );
+    }
+
+    #[test]
+    fn test_marked_description() {
+        let body = format!(
+            "{}\nMy *own*\n\nTest Plan: none\n{}\n\nStack:\n- #1\n",
+            DESCRIPTION_START_MARKER, DESCRIPTION_END_MARKER
+        );
+
+        assert_eq!(
+            marked_description(&body),
+            Some("My *own*\n\nTest Plan: none")
+        );
+        assert_eq!(
+            unmark_description(&body),
+            "My *own*\n\nTest Plan: none\n\nStack:\n- #1"
+        );
+        assert_eq!(marked_description("Summary"), None);
+        assert_eq!(
+            marked_description(DESCRIPTION_START_MARKER),
+            None,
+            "the end marker is missing"
+        );
+
+        // Updating from the commit message keeps the description
+        let message = parse_message(
+            "Title\n\nCommit summary\n\nTest Plan: ran it",
+            MessageSection::Title,
+        );
+        let kept = with_pull_request_description(&message, Some(&body));
+        assert_eq!(
+            kept,
+            with_description(&message, "My *own*\n\nTest Plan: none")
+        );
+        assert_eq!(
+            with_pull_request_description(&message, Some("Old")),
+            message
+        );
+        assert_eq!(with_pull_request_description(&message, None), message);
+    }
+
+    #[test]
+    fn test_build_github_body_keeps_issue_closing_lines() {
+        let message = parse_message(
+            "Title\n\nSome prose\n\nCloses #123\nFixes #7\n\n\
+             Test Plan: ran it\n\nresolves acme/codez#9",
+            MessageSection::Title,
+        );
+        assert_eq!(
+            build_github_body(
+                &message,
+                &crate::config::tests::config_factory()
+            ),
+            "Some prose\n\nCloses #123\nFixes #7\n\nTest Plan:\nran it\n\n\
+             resolves acme/codez#9\n"
+        );
+    }
+
+    #[test]
+    fn test_build_github_body_keeps_stripped_issue_closing_trailers() {
+        let mut config = test_plan_config();
+        config.strip_trailers = keys(&["Fixes", "Closes", "Ticket"]);
+
+        let message = parse_message(
+            "Title\n\nSome prose\n\nTicket: ABC-1\nCloses: #123\n\
+             Fixes: #7\nTest-Plan: cargo test",
+            MessageSection::Title,
+        );
+        assert_eq!(
+            build_github_body(&message, &config),
+            "Some prose\n\nCloses: #123\nFixes: #7\n\n## Test Plan\n\n\
+             cargo test\n"
+        );
+    }
+
+    #[test]
+    fn test_restore_stripped_trailers() {
+        let mut config = crate::config::tests::config_factory();
+        config.strip_trailers = keys(&["Ticket"]);
+        let local = parse_message(
+            "Title\n\nOld prose\n\nTicket: ABC-1",
+            MessageSection::Title,
+        );
+        let mut from_github =
+            parse_message("Title\n\nNew prose", MessageSection::Title);
+
+        restore_stripped_trailers(&local, &mut from_github, &config);
+        assert_eq!(
+            from_github[&MessageSection::Summary],
+            "New prose\n\nTicket: ABC-1"
+        );
+
+        // Doing it again changes nothing
+        restore_stripped_trailers(&local, &mut from_github, &config);
+        assert_eq!(
+            from_github[&MessageSection::Summary],
+            "New prose\n\nTicket: ABC-1"
+        );
+    }
+
+    fn test_plan_config() -> crate::config::Config {
+        let mut config = crate::config::tests::config_factory();
+        config.trailer_sections = vec!["Test-Plan = ## Test Plan"
+            .parse::<TrailerSection>()
+            .unwrap()];
+        config
+    }
+
+    #[test]
+    fn test_parse_trailer_section() {
+        assert_eq!(
+            "Test-Plan=## Test Plan".parse::<TrailerSection>().unwrap(),
+            TrailerSection {
+                key: "Test-Plan".to_string(),
+                heading: "## Test Plan".to_string(),
+            }
+        );
+        assert!("Test-Plan".parse::<TrailerSection>().is_err());
+        assert!("=## Test Plan".parse::<TrailerSection>().is_err());
+    }
+
+    #[test]
+    fn test_trailer_section_single_line() {
+        let message = parse_message(
+            "Title\n\nSome prose\n\nTest-Plan: cargo test\nTicket: ABC-1",
+            MessageSection::Title,
+        );
+
+        assert_eq!(
+            build_github_body(&message, &test_plan_config()),
+            "Some prose\n\nTicket: ABC-1\n\n## Test Plan\n\ncargo test\n"
+        );
+    }
+
+    #[test]
+    fn test_trailer_section_multi_line() {
+        let mut message = parse_message(
+            "Title\n\nSome prose\n\nTest-Plan: ran the tests\n  \
+             and tried it out:\n    spr diff --all",
+            MessageSection::Title,
+        );
+        message.insert(MessageSection::PRStack, "#2\n#1".to_string());
+
+        assert_eq!(
+            build_github_body(&message, &test_plan_config()),
+            "Some prose\n\n\
+             ## Test Plan\n\n\
+             ran the tests\nand tried it out:\nspr diff --all\n\n\
+             PR Stack:\n#2\n#1\n"
+        );
+
+        // Without the trailer, the body is built as usual
+        let message =
+            parse_message("Title\n\nSome prose", MessageSection::Title);
+        assert_eq!(
+            build_github_body(&message, &test_plan_config()),
+            "Some prose\n"
+        );
+    }
+
+    #[test]
+    fn test_restore_trailer_section() {
+        let config = test_plan_config();
+        let local = parse_message(
+            "Title\n\nSome prose\n\nTest-Plan: cargo test",
+            MessageSection::Title,
+        );
+        let body = build_github_body(&local, &config);
+        let mut from_github = parse_message(&body, MessageSection::Summary);
+        from_github.insert(MessageSection::Title, "Title".to_string());
+
+        restore_stripped_trailers(&local, &mut from_github, &config);
+        assert_eq!(from_github, local);
+    }
+
+    #[test]
+    fn test_build_github_body_without_pr_config_block() {
+        let config = crate::config::tests::config_factory();
+        let message = parse_message(
+            "Title\n\nSummary\n\n```spr\nreviewers: [alice]\n```\n\n\
+             Test Plan: ran it",
+            MessageSection::Title,
+        );
+        // The reviewers of the block are not the Reviewers section
+        assert!(!message.contains_key(&MessageSection::Reviewers));
+
+        assert_eq!(
+            build_github_body(&message, &config),
+            "Summary\n\nTest Plan: ran it\n"
+        );
+        // The commit message keeps the block
+        assert!(build_commit_message(&message).contains("reviewers: [alice]"));
+    }
+
+    #[test]
+    fn test_title_too_long() {
+        let title =
+            "Add a configurable limit for the length of Pull Request titles";
+        assert_eq!(title_too_long(title, 72), None);
+        assert_eq!(title_too_long(title, title.len()), None);
+        assert_eq!(title_too_long(title, 20), Some(title.len()));
+        // Characters count, not bytes
+        assert_eq!(title_too_long("Ünïcödé", 7), None);
+    }
+
+    #[test]
+    fn test_check_title_length() {
+        let mut config = crate::config::tests::config_factory();
+        let long = parse_message(&"x".repeat(80), MessageSection::Title);
+        let short = parse_message("Fix the build", MessageSection::Title);
+
+        // Too long titles are only warned about...
+        assert!(check_title_length(&long, &config, false).is_ok());
+        assert!(check_title_length(&short, &config, true).is_ok());
+        // ...unless --strict-title is given
+        assert!(check_title_length(&long, &config, true).is_err());
+
+        config.title_length_limit = None;
+        assert!(check_title_length(&long, &config, true).is_ok());
+    }
 }
@@ -5,10 +5,59 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use crate::{error::Result, git::PreparedCommit, message::MessageSection};
 
+static OUTPUT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+static OUTPUT_SILENCED: AtomicBool = AtomicBool::new(false);
+
+/// Send all output to stderr instead of stdout, so that stdout is left for
+/// output meant for other programs (like `spr diff --print-urls`).
+pub fn set_output_to_stderr(to_stderr: bool) {
+    OUTPUT_TO_STDERR.store(to_stderr, Ordering::Relaxed);
+}
+
+/// Drop all output, for tools that use spr as a library (see [`crate::api`])
+/// and show the results in their own way.
+pub fn set_output_silenced(silenced: bool) {
+    OUTPUT_SILENCED.store(silenced, Ordering::Relaxed);
+}
+
+fn silenced() -> bool {
+    OUTPUT_SILENCED.load(Ordering::Relaxed)
+}
+
+/// Whether colors are turned off, by `--no-color` or by setting the NO_COLOR
+/// environment variable to anything but the empty string (as
+/// https://no-color.org asks).
+pub fn colors_disabled(
+    no_color_flag: bool,
+    no_color_env: Option<&std::ffi::OsStr>,
+) -> bool {
+    no_color_flag || no_color_env.is_some_and(|value| !value.is_empty())
+}
+
+/// Turn colors and other styling on or off for all output, overriding the
+/// detection of whether stdout and stderr are terminals.
+pub fn set_colors_enabled(enabled: bool) {
+    console::set_colors_enabled(enabled);
+    console::set_colors_enabled_stderr(enabled);
+}
+
+fn term() -> console::Term {
+    if OUTPUT_TO_STDERR.load(Ordering::Relaxed) {
+        console::Term::stderr()
+    } else {
+        console::Term::stdout()
+    }
+}
+
 pub fn output(icon: &str, text: &str) -> Result<()> {
-    let term = console::Term::stdout();
+    if silenced() {
+        return Ok(());
+    }
+    let term = term();
 
     let bullet = format!("  {}  ", icon);
     let indent = console::measure_text_width(&bullet);
@@ -24,8 +73,34 @@ pub fn output(icon: &str, text: &str) -> Result<()> {
     Ok(())
 }
 
+/// Print the lines of a diff (see [`crate::utils::unified_diff`]) as they
+/// are, without wrapping them, with removed lines in red and added ones in
+/// green.
+pub fn output_diff(lines: &[String]) -> Result<()> {
+    if silenced() {
+        return Ok(());
+    }
+    let term = term();
+    for line in lines {
+        let styled = if line.starts_with("@@") {
+            console::style(line).cyan()
+        } else if line.starts_with('-') {
+            console::style(line).red()
+        } else if line.starts_with('+') {
+            console::style(line).green()
+        } else {
+            console::style(line)
+        };
+        term.write_line(&format!("      {}", styled))?;
+    }
+    Ok(())
+}
+
 pub fn write_commit_title(prepared_commit: &PreparedCommit) -> Result<()> {
-    let term = console::Term::stdout();
+    if silenced() {
+        return Ok(());
+    }
+    let term = term();
     term.write_line(&format!(
         "{} {}",
         console::style(&prepared_commit.short_id).italic(),
@@ -40,3 +115,17 @@ pub fn write_commit_title(prepared_commit: &PreparedCommit) -> Result<()> {
     ))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors_disabled() {
+        assert!(!colors_disabled(false, None));
+        assert!(colors_disabled(true, None));
+        assert!(colors_disabled(false, Some("1".as_ref())));
+        // An empty NO_COLOR counts as not set
+        assert!(!colors_disabled(false, Some("".as_ref())));
+    }
+}
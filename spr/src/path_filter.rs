@@ -0,0 +1,130 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::error::{Error, Result};
+
+/// The file, relative to the root of the repository, listing the paths (in
+/// gitignore syntax) that spr leaves out when looking at the files a commit
+/// changes, e.g. generated or vendored code.
+pub const IGNORE_FILE: &str = ".spr/ignore";
+
+/// Decides which changed files are considered when making decisions based on
+/// the files a commit changes, like whom to ask for review.
+pub struct PathFilter {
+    gitignore: Gitignore,
+}
+
+impl PathFilter {
+    /// A filter that excludes nothing.
+    pub fn empty() -> Self {
+        Self {
+            gitignore: Gitignore::empty(),
+        }
+    }
+
+    /// Build a filter from the contents of an ignore file.
+    pub fn parse(content: &str) -> Result<Self> {
+        Self::from_patterns(content.lines(), IGNORE_FILE)
+    }
+
+    /// Build a filter from the given patterns (in gitignore syntax), which
+    /// come from `source` (for error messages).
+    pub fn from_patterns<'a>(
+        patterns: impl IntoIterator<Item = &'a str>,
+        source: &str,
+    ) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in patterns {
+            builder.add_line(None, pattern).map_err(|error| {
+                Error::new(format!("Invalid line in {}: {}", source, error))
+            })?;
+        }
+
+        Ok(Self {
+            gitignore: builder.build()?,
+        })
+    }
+
+    /// Load the ignore file from the working directory of the repository. If
+    /// there is none, nothing is excluded.
+    pub fn load(git: &crate::git::Git) -> Result<Self> {
+        let path = match git.repo().workdir() {
+            Some(workdir) => workdir.join(IGNORE_FILE),
+            None => return Ok(Self::empty()),
+        };
+
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Self::empty())
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Whether the file at the given path (relative to the root of the
+    /// repository) is to be left out.
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.gitignore
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let filter = PathFilter::parse(indoc::indoc!(
+            "
+            # Generated code
+            *.pb.rs
+            /vendor/
+            "
+        ))
+        .unwrap();
+
+        assert!(filter.is_excluded("api.pb.rs"));
+        assert!(filter.is_excluded("src/api.pb.rs"));
+        assert!(filter.is_excluded("vendor/foo/lib.rs"));
+        assert!(!filter.is_excluded("src/vendor/lib.rs"));
+        assert!(!filter.is_excluded("src/lib.rs"));
+    }
+
+    #[test]
+    fn test_from_patterns() {
+        let filter =
+            PathFilter::from_patterns([".env", "*.pem", "target/"], "test")
+                .unwrap();
+
+        assert!(filter.is_excluded(".env"));
+        assert!(filter.is_excluded("config/.env"));
+        assert!(filter.is_excluded("certs/server.pem"));
+        assert!(filter.is_excluded("target/debug/spr"));
+        assert!(!filter.is_excluded("src/env.rs"));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(!PathFilter::empty().is_excluded("src/lib.rs"));
+        assert!(!PathFilter::parse("").unwrap().is_excluded("src/lib.rs"));
+    }
+
+    #[test]
+    fn test_load() {
+        let (dir, git) = crate::git::tests::test_repo();
+        assert!(!PathFilter::load(&git).unwrap().is_excluded("gen/api.rs"));
+
+        std::fs::create_dir(dir.path().join(".spr")).unwrap();
+        std::fs::write(dir.path().join(IGNORE_FILE), "gen/\n").unwrap();
+        assert!(PathFilter::load(&git).unwrap().is_excluded("gen/api.rs"));
+    }
+}
@@ -0,0 +1,369 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Settings for the Pull Request of a commit, kept in its commit message in a
+//! fenced block like this one:
+//!
+//! ````text
+//! ```spr
+//! reviewers: [alice, "#core"]
+//! labels:
+//!   - bug
+//!   - needs-backport
+//! draft: true
+//! ```
+//! ````
+//!
+//! The block is written in a small subset of YAML: one `key: value` per line,
+//! where values are plain or quoted strings, or lists (`[a, b]`, or one
+//! `- item` per line). spr applies the settings when it creates the Pull
+//! Request, and leaves the block out of the Pull Request description.
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    message::{MessageSection, MessageSectionsMap},
+};
+
+/// The line that opens the block.
+pub(crate) const BLOCK_START: &str = "```spr";
+/// The line that closes the block.
+pub(crate) const BLOCK_END: &str = "```";
+
+/// The sections of a commit message that the block may be in.
+const SECTIONS: [MessageSection; 2] =
+    [MessageSection::Summary, MessageSection::TestPlan];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PullRequestConfig {
+    /// Users and teams (with a leading #) to request reviews from, in
+    /// addition to the ones in the Reviewers section
+    pub reviewers: Vec<String>,
+    pub labels: Vec<String>,
+    /// Whether to create the Pull Request as a draft, if the block says
+    pub draft: Option<bool>,
+}
+
+/// The byte range of the block in `text` (fences included), and its content.
+fn find_block(text: &str) -> Option<(std::ops::Range<usize>, &str)> {
+    let mut offset = 0;
+    let mut start = None;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        match start {
+            None if trimmed == BLOCK_START => {
+                start = Some((offset, offset + line.len()));
+            }
+            Some((block_start, content_start)) if trimmed == BLOCK_END => {
+                return Some((
+                    block_start..offset + line.len(),
+                    &text[content_start..offset],
+                ));
+            }
+            _ => (),
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+/// `text` without the block, and without the blank lines it leaves behind.
+pub fn strip_block(text: &str) -> String {
+    match find_block(text) {
+        None => text.to_string(),
+        Some((range, _)) => {
+            let before = text[..range.start].trim_end();
+            let after = text[range.end..].trim_start_matches(['\n', '\r']);
+            match (before.is_empty(), after.is_empty()) {
+                (true, _) => after.to_string(),
+                (false, true) => before.to_string(),
+                (false, false) => format!("{}\n\n{}", before, after),
+            }
+        }
+    }
+}
+
+/// Remove the block from the sections of the message that may contain it.
+/// Sections that consisted of the block only are removed.
+pub fn strip_from_message(section_texts: &mut MessageSectionsMap) {
+    for section in SECTIONS {
+        if let Some(text) = section_texts.get_mut(&section) {
+            *text = strip_block(text);
+            if text.is_empty() {
+                section_texts.remove(&section);
+            }
+        }
+    }
+}
+
+/// Put the block of the local commit message back into a commit message
+/// taken from GitHub (as `spr amend` does), where it was left out.
+pub fn restore_block(
+    local: &MessageSectionsMap,
+    from_github: &mut MessageSectionsMap,
+) {
+    let block = SECTIONS
+        .iter()
+        .filter_map(|section| local.get(section))
+        .find_map(|text| find_block(text).map(|(range, _)| &text[range]));
+    let already_there = SECTIONS
+        .iter()
+        .filter_map(|section| from_github.get(section))
+        .any(|text| find_block(text).is_some());
+    let block = match block {
+        Some(block) if !already_there => block.trim_end(),
+        _ => return,
+    };
+
+    let summary = from_github.entry(MessageSection::Summary).or_default();
+    if summary.is_empty() {
+        *summary = block.to_string();
+    } else {
+        *summary = format!("{}\n\n{}", summary, block);
+    }
+}
+
+/// The settings of the block in the commit message, if there is one.
+pub fn from_message(
+    section_texts: &MessageSectionsMap,
+) -> Result<Option<PullRequestConfig>> {
+    SECTIONS
+        .iter()
+        .filter_map(|section| section_texts.get(section))
+        .find_map(|text| find_block(text))
+        .map(|(_, content)| {
+            parse(content).context(
+                "Invalid ```spr block in the commit message".to_string(),
+            )
+        })
+        .transpose()
+}
+
+/// Remove a trailing comment (` # ...`) from a line, unless the `#` is in
+/// quotes (like in `"#core"`).
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => {
+                return &line[..index];
+            }
+            None => (),
+        }
+        previous = c;
+    }
+
+    line
+}
+
+fn parse_scalar(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(unquoted) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return unquoted.to_string();
+        }
+    }
+
+    value.to_string()
+}
+
+/// The items of a list value: `[a, b]`, or a plain `a, b`.
+fn parse_inline_list(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .unwrap_or(value);
+
+    value
+        .split(',')
+        .map(parse_scalar)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match &parse_scalar(value).to_ascii_lowercase()[..] {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        other => Err(Error::new(format!(
+            "Invalid value for {}: '{}' (must be true or false)",
+            key, other
+        ))),
+    }
+}
+
+/// Parse the content of the block.
+pub fn parse(content: &str) -> Result<PullRequestConfig> {
+    // The keys with their (inline) values and the items of block lists
+    let mut entries: Vec<(String, String, Vec<String>)> = Vec::new();
+    for line in content.lines() {
+        let line = strip_comment(line).trim_end();
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(item) = line.trim_start().strip_prefix("- ") {
+            match entries.last_mut() {
+                Some((_, value, items)) if value.is_empty() => {
+                    items.push(parse_scalar(item))
+                }
+                _ => {
+                    return Err(Error::new(format!(
+                        "List item without a key: '{}'",
+                        line.trim()
+                    )))
+                }
+            }
+        } else if let Some((key, value)) = line.split_once(':') {
+            entries.push((
+                key.trim().to_string(),
+                value.trim().to_string(),
+                Vec::new(),
+            ));
+        } else {
+            return Err(Error::new(format!(
+                "Expected 'key: value', found '{}'",
+                line.trim()
+            )));
+        }
+    }
+
+    let mut config = PullRequestConfig::default();
+    for (key, value, items) in entries {
+        let list = || {
+            if value.is_empty() {
+                items.clone()
+            } else {
+                parse_inline_list(&value)
+            }
+        };
+        match &key[..] {
+            "reviewers" => config.reviewers.extend(list()),
+            "labels" => config.labels.extend(list()),
+            "draft" => config.draft = Some(parse_bool(&key, &value)?),
+            _ => {
+                return Err(Error::new(format!(
+                    "Unknown setting '{}' (must be reviewers, labels or \
+                     draft)",
+                    key
+                )))
+            }
+        }
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::parse_message;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = parse(
+            "# Who should look at this\n\
+             reviewers: [alice, \"#core\"]  # the team, too\n\
+             labels:\n  - bug\n  - 'needs backport'\n\
+             draft: yes\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            PullRequestConfig {
+                reviewers: names(&["alice", "#core"]),
+                labels: names(&["bug", "needs backport"]),
+                draft: Some(true),
+            }
+        );
+        assert_eq!(parse("").unwrap(), PullRequestConfig::default());
+        assert_eq!(parse("labels: a, b").unwrap().labels, names(&["a", "b"]));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse("reviewer: alice").is_err());
+        assert!(parse("draft: maybe").is_err());
+        assert!(parse("- bug").is_err());
+        assert!(parse("labels: [bug]\n  - other").is_err());
+        assert!(parse("just some text").is_err());
+    }
+
+    #[test]
+    fn test_from_message() {
+        let message = parse_message(
+            "Title\n\nSummary\n\n```spr\nlabels: [bug]\n```\n\n\
+             Test Plan: ran it",
+            MessageSection::Title,
+        );
+        assert_eq!(
+            from_message(&message).unwrap().unwrap().labels,
+            names(&["bug"])
+        );
+
+        let message = parse_message("Title\n\nSummary", MessageSection::Title);
+        assert_eq!(from_message(&message).unwrap(), None);
+
+        // Other fenced blocks are left alone
+        let message = parse_message(
+            "Title\n\n```yaml\nlabels: [bug]\n```",
+            MessageSection::Title,
+        );
+        assert_eq!(from_message(&message).unwrap(), None);
+
+        let message = parse_message(
+            "Title\n\n```spr\nlables: [bug]\n```",
+            MessageSection::Title,
+        );
+        assert!(from_message(&message).is_err());
+    }
+
+    #[test]
+    fn test_strip_block() {
+        assert_eq!(
+            strip_block("Before\n\n```spr\ndraft: true\n```\n\nAfter"),
+            "Before\n\nAfter"
+        );
+        assert_eq!(strip_block("```spr\ndraft: true\n```\n\nAfter"), "After");
+        assert_eq!(strip_block("Before\n```spr\ndraft: true\n```"), "Before");
+        // An unterminated block is not a block
+        assert_eq!(
+            strip_block("Before\n```spr\ndraft: true"),
+            "Before\n```spr\ndraft: true"
+        );
+    }
+
+    #[test]
+    fn test_restore_block() {
+        let local = parse_message(
+            "Title\n\nSummary\n\n```spr\nlabels: [bug]\n```",
+            MessageSection::Title,
+        );
+        let mut from_github = local.clone();
+        strip_from_message(&mut from_github);
+        assert!(from_message(&from_github).unwrap().is_none());
+
+        restore_block(&local, &mut from_github);
+        assert_eq!(from_github, local);
+        // Nothing is added twice
+        restore_block(&local, &mut from_github);
+        assert_eq!(from_github, local);
+    }
+}
@@ -0,0 +1,159 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A record, kept in the Git directory, of the commit each branch on GitHub
+//! was at when spr last pushed or fetched it. When GitHub reports a Pull
+//! Request's branches at the recorded commits, spr knows them already and
+//! does not need to fetch them to decide whether anything has changed.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use git2::Oid;
+
+use crate::error::{Error, Result, ResultExt};
+
+/// Where in the Git directory the cache lives.
+const CACHE_PATH: &str = "spr/branch-shas";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushCache {
+    path: PathBuf,
+    /// The commit of each branch, by its name on GitHub
+    /// (`refs/heads/...`)
+    entries: BTreeMap<String, Oid>,
+}
+
+impl PushCache {
+    /// Read the cache of the repository with the given Git directory. A
+    /// missing cache is an empty one.
+    pub fn load(git_dir: &Path) -> Result<Self> {
+        let path = git_dir.join(CACHE_PATH);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                String::new()
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let entries = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (oid, branch) = line.split_once(' ').ok_or_else(|| {
+                    Error::new(format!("Invalid line '{}'", line))
+                })?;
+                Ok((branch.to_string(), Oid::from_str(oid)?))
+            })
+            .collect::<Result<_>>()
+            .context(format!(
+                "Could not read {}; delete it, or run spr with --refresh",
+                path.display()
+            ))?;
+
+        Ok(PushCache { path, entries })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text: String = self
+            .entries
+            .iter()
+            .map(|(branch, oid)| format!("{} {}\n", oid, branch))
+            .collect();
+        std::fs::write(&self.path, text)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, branch: &str) -> Option<Oid> {
+        self.entries.get(branch).copied()
+    }
+
+    pub fn insert(&mut self, branch: &str, oid: Oid) {
+        self.entries.insert(branch.to_string(), oid);
+    }
+
+    pub fn remove(&mut self, branch: &str) -> Option<Oid> {
+        self.entries.remove(branch)
+    }
+}
+
+/// Whether a branch that GitHub reports at `on_github` is still where the
+/// cache says it was, so that it does not need fetching.
+pub fn is_unchanged(cached: Option<Oid>, on_github: Oid) -> bool {
+    cached == Some(on_github)
+}
+
+/// Record in the cache of the repository with the given Git directory that
+/// the given branches (by their names on GitHub) are at the given commits.
+pub fn record(git_dir: &Path, branches: &[(&str, Oid)]) -> Result<()> {
+    if branches.is_empty() {
+        return Ok(());
+    }
+    let mut cache = PushCache::load(git_dir)?;
+    for (branch, oid) in branches {
+        cache.insert(branch, *oid);
+    }
+
+    cache.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oid(n: u8) -> Oid {
+        Oid::from_bytes(&[n; 20]).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let cache = PushCache::load(dir.path()).unwrap();
+        assert_eq!(cache.get("refs/heads/spr/foo/one"), None);
+
+        record(
+            dir.path(),
+            &[
+                ("refs/heads/spr/foo/one", oid(1)),
+                ("refs/heads/master", oid(2)),
+            ],
+        )
+        .unwrap();
+        record(dir.path(), &[("refs/heads/spr/foo/one", oid(3))]).unwrap();
+
+        let cache = PushCache::load(dir.path()).unwrap();
+        assert_eq!(cache.get("refs/heads/spr/foo/one"), Some(oid(3)));
+        assert_eq!(cache.get("refs/heads/master"), Some(oid(2)));
+        assert_eq!(cache.get("refs/heads/spr/foo/two"), None);
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("spr")).unwrap();
+        std::fs::write(dir.path().join(CACHE_PATH), "not a cache\n").unwrap();
+
+        assert!(PushCache::load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_is_unchanged() {
+        assert!(is_unchanged(Some(oid(1)), oid(1)));
+        // Someone else pushed to the branch since
+        assert!(!is_unchanged(Some(oid(1)), oid(2)));
+        // spr has not seen the branch yet
+        assert!(!is_unchanged(None, oid(1)));
+    }
+}
@@ -0,0 +1,317 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A check before operations on many Pull Requests that the GitHub API rate
+//! limits leave enough requests for them, so that they do not fail half-way.
+
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    github::with_request_timeout,
+    output::output,
+};
+
+/// An operation whose number of API requests we estimate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiOperation {
+    Diff,
+    Land,
+    FixFooters,
+}
+
+/// Numbers of requests to the two APIs of GitHub, which are rate-limited
+/// separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ApiCalls {
+    pub rest: u64,
+    pub graphql: u64,
+}
+
+impl ApiOperation {
+    /// The most requests the operation makes for each Pull Request.
+    fn calls_per_pull_request(self) -> ApiCalls {
+        match self {
+            // Fetching the Pull Request (and fetching it again after creating
+            // it), and creating, updating, requesting reviewers and assigning
+            ApiOperation::Diff => ApiCalls {
+                rest: 4,
+                graphql: 2,
+            },
+            // Fetching the Pull Request twice, checking its mergeability and
+            // checks, and retargeting and merging it (and undoing the
+            // retargeting if that fails)
+            ApiOperation::Land => ApiCalls {
+                rest: 3,
+                graphql: 4,
+            },
+            // Fetching and updating the Pull Request
+            ApiOperation::FixFooters => ApiCalls {
+                rest: 1,
+                graphql: 1,
+            },
+        }
+    }
+}
+
+/// The most requests `operation` makes for the given number of Pull Requests.
+pub fn estimate_api_calls(
+    operation: ApiOperation,
+    pull_requests: usize,
+) -> ApiCalls {
+    let per_pull_request = operation.calls_per_pull_request();
+    let pull_requests = pull_requests as u64;
+    ApiCalls {
+        rest: per_pull_request.rest * pull_requests,
+        graphql: per_pull_request.graphql * pull_requests,
+    }
+}
+
+/// The rate limit of one of GitHub's APIs, as reported by its `rate_limit`
+/// endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// When the limit resets (seconds since the Unix epoch)
+    pub reset: u64,
+}
+
+impl RateLimit {
+    /// How many requests short of `needed` the remaining budget is.
+    fn shortfall(&self, needed: u64) -> Option<u64> {
+        needed
+            .checked_sub(self.remaining)
+            .filter(|&shortfall| shortfall > 0)
+    }
+
+    /// The time until the limit resets, rounded up to whole minutes.
+    fn minutes_until_reset(&self, now: SystemTime) -> u64 {
+        let reset = SystemTime::UNIX_EPOCH + Duration::from_secs(self.reset);
+        let seconds = reset.duration_since(now).unwrap_or_default().as_secs();
+        seconds.div_ceil(60)
+    }
+
+    fn describe(&self, api: &str, now: SystemTime) -> String {
+        format!(
+            "{}: {} of {} requests left, resets in {} minute(s)",
+            api,
+            self.remaining,
+            self.limit,
+            self.minutes_until_reset(now)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimits {
+    pub core: RateLimit,
+    pub graphql: RateLimit,
+}
+
+/// The APIs whose remaining budget is too small for `needed`, with the
+/// shortfall for each.
+fn insufficient(
+    limits: &RateLimits,
+    needed: ApiCalls,
+) -> Vec<(&'static str, u64)> {
+    [
+        ("REST API", limits.core.shortfall(needed.rest)),
+        ("GraphQL API", limits.graphql.shortfall(needed.graphql)),
+    ]
+    .into_iter()
+    .filter_map(|(api, shortfall)| shortfall.map(|shortfall| (api, shortfall)))
+    .collect()
+}
+
+pub async fn get_rate_limits(
+    config: &crate::config::Config,
+) -> Result<RateLimits> {
+    #[derive(Deserialize)]
+    struct Response {
+        resources: RateLimits,
+    }
+
+    // Asking for the rate limits does not count against them
+    let response: Response = with_request_timeout(
+        config,
+        octocrab::instance().get("rate_limit", None::<&()>),
+    )
+    .await?;
+
+    Ok(response.resources)
+}
+
+/// Before running `operation` on the given number of Pull Requests, check
+/// that the rate limits leave enough requests for it. If they do not, warn, or
+/// fail if `spr --strict-rate-limit` was given.
+pub async fn check_rate_limit(
+    config: &crate::config::Config,
+    operation: ApiOperation,
+    pull_requests: usize,
+) -> Result<()> {
+    let limits = match get_rate_limits(config).await {
+        Ok(limits) => limits,
+        // Not knowing the rate limits is no reason not to try
+        Err(_) if !config.strict_rate_limit => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    let needed = estimate_api_calls(operation, pull_requests);
+    let insufficient = insufficient(&limits, needed);
+    if insufficient.is_empty() {
+        return Ok(());
+    }
+
+    let now = SystemTime::now();
+    output(
+        "⏳",
+        &format!(
+            "This needs up to {} REST and {} GraphQL requests to GitHub, \
+             which may exceed the rate limits:",
+            needed.rest, needed.graphql
+        ),
+    )?;
+    output("  ", &limits.core.describe("REST API", now))?;
+    output("  ", &limits.graphql.describe("GraphQL API", now))?;
+
+    if config.strict_rate_limit {
+        return Err(Error::new(format!(
+            "Not starting, since the {} would run out. Try again once the \
+             limits have reset, or without --strict-rate-limit.",
+            insufficient
+                .iter()
+                .map(|(api, shortfall)| {
+                    format!("{} ({} requests short)", api, shortfall)
+                })
+                .collect::<Vec<_>>()
+                .join(" and ")
+        )));
+    }
+
+    output(
+        "⚠️",
+        "Going ahead anyway - if spr stops half-way, run it again once the \
+         limits have reset",
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(remaining: u64) -> RateLimit {
+        RateLimit {
+            limit: 5000,
+            remaining,
+            reset: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_estimate_api_calls() {
+        assert_eq!(
+            estimate_api_calls(ApiOperation::Diff, 3),
+            ApiCalls {
+                rest: 12,
+                graphql: 6
+            }
+        );
+        assert_eq!(
+            estimate_api_calls(ApiOperation::Land, 2),
+            ApiCalls {
+                rest: 6,
+                graphql: 8
+            }
+        );
+        assert_eq!(
+            estimate_api_calls(ApiOperation::FixFooters, 5),
+            ApiCalls {
+                rest: 5,
+                graphql: 5
+            }
+        );
+        assert_eq!(
+            estimate_api_calls(ApiOperation::Diff, 0),
+            ApiCalls::default()
+        );
+    }
+
+    #[test]
+    fn test_insufficient() {
+        let limits = RateLimits {
+            core: limit(10),
+            graphql: limit(100),
+        };
+
+        assert!(insufficient(
+            &limits,
+            estimate_api_calls(ApiOperation::Diff, 2)
+        )
+        .is_empty());
+        // Exactly enough is enough
+        assert!(insufficient(
+            &limits,
+            ApiCalls {
+                rest: 10,
+                graphql: 100
+            }
+        )
+        .is_empty());
+        assert_eq!(
+            insufficient(&limits, estimate_api_calls(ApiOperation::Diff, 3)),
+            vec![("REST API", 2)]
+        );
+        assert_eq!(
+            insufficient(
+                &limits,
+                ApiCalls {
+                    rest: 11,
+                    graphql: 101
+                }
+            ),
+            vec![("REST API", 1), ("GraphQL API", 1)]
+        );
+    }
+
+    #[test]
+    fn test_describe_rate_limit() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_699_999_000);
+        assert_eq!(
+            limit(42).describe("REST API", now),
+            "REST API: 42 of 5000 requests left, resets in 17 minute(s)"
+        );
+
+        // A reset in the past (because of clock skew) is a reset now
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        assert_eq!(limit(42).minutes_until_reset(later), 0);
+    }
+
+    #[test]
+    fn test_parse_rate_limits() {
+        let limits: RateLimits = serde_json::from_value(serde_json::json!({
+            "core": {
+                "limit": 5000, "used": 1, "remaining": 4999,
+                "reset": 1691591363
+            },
+            "graphql": {
+                "limit": 5000, "used": 7, "remaining": 4993,
+                "reset": 1691593228
+            },
+            "search": {
+                "limit": 30, "used": 0, "remaining": 30, "reset": 1691591091
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(limits.core.remaining, 4999);
+        assert_eq!(limits.graphql.remaining, 4993);
+        assert_eq!(limits.graphql.reset, 1691593228);
+    }
+}
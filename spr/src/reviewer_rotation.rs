@@ -0,0 +1,127 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Picking reviewers from the pool in `spr.reviewerPool` in turn, for
+//! `spr.reviewerStrategy` (or `spr diff --reviewer-strategy`) `round-robin`.
+//! Where in the pool the next pick starts is kept in the Git directory, so
+//! that one submit after the other asks the next reviewer in the pool.
+
+use std::path::Path;
+
+use crate::error::{Error, Result, ResultExt};
+
+/// Where in the Git directory the position in the pool lives.
+const ROTATION_PATH: &str = "spr/reviewer-rotation";
+
+/// The `count` reviewers of `pool` from `index` on, starting over at the
+/// beginning of the pool after its end (but never picking anyone twice).
+pub fn pick(pool: &[String], index: usize, count: usize) -> Vec<String> {
+    if pool.is_empty() {
+        return Vec::new();
+    }
+
+    (0..count.min(pool.len()))
+        .map(|offset| pool[(index + offset) % pool.len()].clone())
+        .collect()
+}
+
+/// The position in the pool that the next pick starts at.
+pub fn load_index(git_dir: &Path) -> Result<usize> {
+    let path = git_dir.join(ROTATION_PATH);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(0)
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    text.trim()
+        .parse()
+        .map_err(|_| Error::new(format!("Invalid position '{}'", text.trim())))
+        .context(format!("Could not read {}; delete it", path.display()))
+}
+
+fn save_index(git_dir: &Path, index: usize) -> Result<()> {
+    let path = git_dir.join(ROTATION_PATH);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, format!("{}\n", index))?;
+
+    Ok(())
+}
+
+/// Pick the next `count` reviewers of `pool`, and move on past them for the
+/// next pick.
+pub fn next_reviewers(
+    git_dir: &Path,
+    pool: &[String],
+    count: usize,
+) -> Result<Vec<String>> {
+    if pool.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let index = load_index(git_dir)? % pool.len();
+    let reviewers = pick(pool, index, count);
+    save_index(git_dir, (index + reviewers.len()) % pool.len())?;
+
+    Ok(reviewers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pick() {
+        let pool = names(&["alice", "bob", "carol"]);
+
+        assert_eq!(pick(&pool, 0, 1), names(&["alice"]));
+        assert_eq!(pick(&pool, 2, 1), names(&["carol"]));
+        assert_eq!(pick(&pool, 2, 2), names(&["carol", "alice"]));
+        // The index wraps around, and nobody is picked twice
+        assert_eq!(pick(&pool, 4, 1), names(&["bob"]));
+        assert_eq!(pick(&pool, 1, 5), names(&["bob", "carol", "alice"]));
+        assert!(pick(&[], 3, 1).is_empty());
+    }
+
+    #[test]
+    fn test_next_reviewers_advances() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = names(&["alice", "bob", "carol"]);
+        let next = || next_reviewers(dir.path(), &pool, 1).unwrap();
+
+        assert_eq!(next(), names(&["alice"]));
+        assert_eq!(load_index(dir.path()).unwrap(), 1);
+        assert_eq!(next(), names(&["bob"]));
+        assert_eq!(next(), names(&["carol"]));
+        assert_eq!(load_index(dir.path()).unwrap(), 0);
+        assert_eq!(next(), names(&["alice"]));
+
+        // Two at a time
+        assert_eq!(
+            next_reviewers(dir.path(), &pool, 2).unwrap(),
+            names(&["bob", "carol"])
+        );
+        assert_eq!(next(), names(&["alice"]));
+    }
+
+    #[test]
+    fn test_load_index_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("spr")).unwrap();
+        std::fs::write(dir.path().join(ROTATION_PATH), "second\n").unwrap();
+
+        assert!(load_index(dir.path()).is_err());
+    }
+}
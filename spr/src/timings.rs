@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! How long the phases of a command took, and how many requests it sent to
+//! the GitHub API, for `--timings`. Nothing is collected unless [`enable`]
+//! was called.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A part of the work of a command that is timed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    /// Getting branches and Pull Requests from GitHub
+    Fetch,
+    /// Rewriting local commits
+    Rebase,
+    /// Pushing branches to GitHub
+    Push,
+    /// Creating and updating Pull Requests
+    ApiUpdates,
+}
+
+impl Phase {
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Fetch => "fetch",
+            Phase::Rebase => "rebase",
+            Phase::Push => "push",
+            Phase::ApiUpdates => "API updates",
+        }
+    }
+}
+
+/// What one phase took, over all the times the command was in it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTiming {
+    pub duration: Duration,
+    /// How many times the command went into the phase
+    pub runs: usize,
+    /// The requests to the GitHub API sent during the phase
+    pub api_calls: usize,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Timings {
+    phases: BTreeMap<Phase, PhaseTiming>,
+    /// The phases running now, innermost last
+    running: Vec<Phase>,
+    api_calls: usize,
+}
+
+impl Timings {
+    pub fn start(&mut self, phase: Phase) {
+        self.running.push(phase);
+    }
+
+    /// Note that the given phase, started last, is over after `duration`.
+    pub fn finish(&mut self, phase: Phase, duration: Duration) {
+        if let Some(index) = self.running.iter().rposition(|&p| p == phase) {
+            self.running.remove(index);
+        }
+        let timing = self.phases.entry(phase).or_default();
+        timing.duration += duration;
+        timing.runs += 1;
+    }
+
+    /// Count a request to the GitHub API, towards the innermost phase
+    /// running, if any.
+    pub fn count_api_call(&mut self) {
+        self.api_calls += 1;
+        if let Some(phase) = self.running.last() {
+            self.phases.entry(*phase).or_default().api_calls += 1;
+        }
+    }
+
+    pub fn phase(&self, phase: Phase) -> PhaseTiming {
+        self.phases.get(&phase).copied().unwrap_or_default()
+    }
+
+    /// The requests to the GitHub API, in any phase or none.
+    pub fn api_calls(&self) -> usize {
+        self.api_calls
+    }
+
+    /// A line for each phase the command went into, and one for the whole
+    /// command, which took `total`.
+    pub fn summary(&self, total: Duration) -> Vec<String> {
+        let requests = |count: usize| match count {
+            1 => "1 API call".to_string(),
+            n => format!("{} API calls", n),
+        };
+
+        self.phases
+            .iter()
+            .map(|(phase, timing)| {
+                let mut line = format!(
+                    "{}: {:.2}s",
+                    phase.label(),
+                    timing.duration.as_secs_f64()
+                );
+                if timing.api_calls > 0 {
+                    line.push_str(&format!(", {}", requests(timing.api_calls)));
+                }
+                line
+            })
+            .chain(std::iter::once(format!(
+                "total: {:.2}s, {}",
+                total.as_secs_f64(),
+                requests(self.api_calls)
+            )))
+            .collect()
+    }
+}
+
+static TIMINGS: Mutex<Option<Timings>> = Mutex::new(None);
+
+fn with_timings(f: impl FnOnce(&mut Timings)) {
+    if let Some(timings) = TIMINGS.lock().expect("poisoned mutex").as_mut() {
+        f(timings);
+    }
+}
+
+/// Start collecting timings.
+pub fn enable() {
+    *TIMINGS.lock().expect("poisoned mutex") = Some(Timings::default());
+}
+
+/// The timings collected so far, if collecting them was enabled.
+pub fn take() -> Option<Timings> {
+    TIMINGS.lock().expect("poisoned mutex").take()
+}
+
+/// Run `future` as (part of) the given phase.
+pub async fn time<F: Future>(phase: Phase, future: F) -> F::Output {
+    with_timings(|timings| timings.start(phase));
+    let start = Instant::now();
+    let output = future.await;
+    with_timings(|timings| timings.finish(phase, start.elapsed()));
+    output
+}
+
+/// Run `f` as (part of) the given phase.
+pub fn time_sync<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    with_timings(|timings| timings.start(phase));
+    let start = Instant::now();
+    let output = f();
+    with_timings(|timings| timings.finish(phase, start.elapsed()));
+    output
+}
+
+/// Count a request to the GitHub API.
+pub fn count_api_call() {
+    with_timings(Timings::count_api_call);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let mut timings = Timings::default();
+        let ms = Duration::from_millis;
+
+        timings.count_api_call();
+        timings.start(Phase::Fetch);
+        timings.count_api_call();
+        timings.count_api_call();
+        timings.finish(Phase::Fetch, ms(1500));
+        timings.start(Phase::ApiUpdates);
+        timings.count_api_call();
+        // A phase inside another gets the requests made while it runs
+        timings.start(Phase::Fetch);
+        timings.count_api_call();
+        timings.finish(Phase::Fetch, ms(250));
+        timings.count_api_call();
+        timings.finish(Phase::ApiUpdates, ms(2000));
+
+        assert_eq!(
+            timings.phase(Phase::Fetch),
+            PhaseTiming {
+                duration: ms(1750),
+                runs: 2,
+                api_calls: 3,
+            }
+        );
+        assert_eq!(
+            timings.phase(Phase::ApiUpdates),
+            PhaseTiming {
+                duration: ms(2000),
+                runs: 1,
+                api_calls: 2,
+            }
+        );
+        assert_eq!(timings.phase(Phase::Push), PhaseTiming::default());
+        assert_eq!(timings.api_calls(), 6);
+
+        assert_eq!(
+            timings.summary(ms(4000)),
+            vec![
+                "fetch: 1.75s, 3 API calls",
+                "API updates: 2.00s, 2 API calls",
+                "total: 4.00s, 6 API calls",
+            ]
+        );
+    }
+}
@@ -0,0 +1,503 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A snapshot, kept in the Git directory, of where HEAD and the local
+//! branches were before and after the last spr command that changes things,
+//! so that `spr undo` can put back the ones the command moved. What the
+//! command changed on GitHub cannot be undone, so the snapshot lists the steps
+//! the command recorded as completed (see
+//! [`crate::interrupt::record_completed`]) to report them.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use git2::Oid;
+
+use crate::{
+    error::{Error, Result, ResultExt},
+    interrupt::{rebase_in_progress, Checkpoint},
+};
+
+/// Where in the Git directory the snapshot lives.
+const SNAPSHOT_PATH: &str = "spr/undo";
+
+/// Where HEAD and the local branches are at some point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refs {
+    pub head: Checkpoint,
+    /// The commit of each local branch, by its full name (`refs/heads/...`)
+    pub branches: BTreeMap<String, Oid>,
+}
+
+impl Refs {
+    /// Where HEAD and the local branches are now, or `None` if HEAD does not
+    /// point at a commit yet.
+    pub fn capture(git: &crate::git::Git) -> Result<Option<Self>> {
+        let head = match Checkpoint::capture(git) {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let branches = local_branches(&git.repo())?;
+
+        Ok(Some(Refs { head, branches }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The spr command the snapshot was taken for (e.g. `diff`)
+    pub command: String,
+    /// Where HEAD and the local branches were before the command
+    pub before: Refs,
+    /// Where the command left them, or `None` if it did not get that far
+    pub after: Option<Refs>,
+    /// The steps the command completed that changed things on GitHub
+    pub remote_steps: Vec<String>,
+}
+
+fn short_id(repo: &git2::Repository, oid: Oid) -> Result<String> {
+    Ok(repo
+        .find_object(oid, None)?
+        .short_id()?
+        .as_str()
+        .unwrap_or_default()
+        .to_string())
+}
+
+fn branch_name(name: &str) -> &str {
+    name.trim_start_matches("refs/heads/")
+}
+
+fn local_branches(repo: &git2::Repository) -> Result<BTreeMap<String, Oid>> {
+    let mut branches = BTreeMap::new();
+    for branch in repo.branches(Some(git2::BranchType::Local))? {
+        let reference = branch?.0.into_reference();
+        if let (Some(name), Some(oid)) = (reference.name(), reference.target())
+        {
+            branches.insert(name.to_string(), oid);
+        }
+    }
+
+    Ok(branches)
+}
+
+impl Snapshot {
+    /// Where HEAD and the local branches are now, before running `command`,
+    /// or `None` if HEAD does not point at a commit yet.
+    pub fn capture(
+        git: &crate::git::Git,
+        command: &str,
+    ) -> Result<Option<Self>> {
+        Ok(Refs::capture(git)?.map(|before| Snapshot {
+            command: command.to_string(),
+            before,
+            after: None,
+            remote_steps: Vec::new(),
+        }))
+    }
+
+    /// Note where the command left HEAD and the local branches, once it is
+    /// done (whether it succeeded or not).
+    pub fn record_after(&mut self, git: &crate::git::Git) -> Result<()> {
+        self.after = Refs::capture(git)?;
+
+        Ok(())
+    }
+
+    /// Read the snapshot of the repository with the given Git directory, or
+    /// `None` if there is none.
+    pub fn load(git_dir: &Path) -> Result<Option<Self>> {
+        let path = git_dir.join(SNAPSHOT_PATH);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        Snapshot::parse(&text)
+            .map(Some)
+            .context(format!("Could not read {}; delete it", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut command = None;
+        // Before and after the command
+        let mut heads = [None, None];
+        let mut branches = [BTreeMap::new(), BTreeMap::new()];
+        let mut remote_steps = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let invalid = || Error::new(format!("Invalid line '{}'", line));
+            let (key, value) = line.split_once(' ').ok_or_else(invalid)?;
+            let (index, key) = match key.strip_prefix("after-") {
+                Some(key) => (1, key),
+                None => (0, key),
+            };
+            match key {
+                "command" if index == 0 => command = Some(value.to_string()),
+                "head" => {
+                    let (oid, branch) = match value.split_once(' ') {
+                        Some((oid, branch)) => (oid, Some(branch.to_string())),
+                        None => (value, None),
+                    };
+                    heads[index] = Some(Checkpoint {
+                        branch,
+                        oid: Oid::from_str(oid)?,
+                    });
+                }
+                "branch" => {
+                    let (oid, branch) =
+                        value.split_once(' ').ok_or_else(invalid)?;
+                    branches[index]
+                        .insert(branch.to_string(), Oid::from_str(oid)?);
+                }
+                "remote" if index == 0 => remote_steps.push(value.to_string()),
+                _ => return Err(invalid()),
+            }
+        }
+
+        let [before_head, after_head] = heads;
+        let [before_branches, after_branches] = branches;
+        Ok(Snapshot {
+            command: command.ok_or_else(|| Error::new("No command"))?,
+            before: Refs {
+                head: before_head.ok_or_else(|| Error::new("No HEAD"))?,
+                branches: before_branches,
+            },
+            after: after_head.map(|head| Refs {
+                head,
+                branches: after_branches,
+            }),
+            remote_steps,
+        })
+    }
+
+    pub fn save(&self, git_dir: &Path) -> Result<()> {
+        let path = git_dir.join(SNAPSHOT_PATH);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut text = format!("command {}\n", self.command);
+        let refs = std::iter::once(("", &self.before))
+            .chain(self.after.iter().map(|after| ("after-", after)));
+        for (prefix, refs) in refs {
+            match &refs.head.branch {
+                Some(branch) => text.push_str(&format!(
+                    "{}head {} {}\n",
+                    prefix, refs.head.oid, branch
+                )),
+                None => text
+                    .push_str(&format!("{}head {}\n", prefix, refs.head.oid)),
+            }
+            for (branch, oid) in &refs.branches {
+                text.push_str(&format!(
+                    "{}branch {} {}\n",
+                    prefix, oid, branch
+                ));
+            }
+        }
+        for step in &self.remote_steps {
+            // Each step is one line
+            text.push_str(&format!("remote {}\n", step.replace('\n', " ")));
+        }
+        std::fs::write(&path, text)?;
+
+        Ok(())
+    }
+
+    /// Delete the snapshot of the repository with the given Git directory,
+    /// once it has been restored.
+    pub fn remove(git_dir: &Path) -> Result<()> {
+        match std::fs::remove_file(git_dir.join(SNAPSHOT_PATH)) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                Err(error.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Put back HEAD and the local branches that the command moved: check out
+    /// what was checked out, reset the branches it moved, recreate the ones
+    /// it deleted and delete the ones it created. Branches the command did
+    /// not touch stay as they are. Refuses if HEAD or any of those branches
+    /// changed since the command. Returns what was done.
+    pub async fn restore(&self, git: &crate::git::Git) -> Result<Vec<String>> {
+        let after = self.after.as_ref().ok_or_else(|| {
+            Error::new(format!(
+                "spr {} did not record where it left the branches, so it \
+                 cannot be undone",
+                self.command
+            ))
+        })?;
+        if rebase_in_progress(git) {
+            return Err(Error::new(
+                "A rebase is in progress; finish or abort it first",
+            ));
+        }
+
+        let before = &self.before;
+        let moved: BTreeSet<&String> = before
+            .branches
+            .keys()
+            .chain(after.branches.keys())
+            .filter(|branch| {
+                before.branches.get(*branch) != after.branches.get(*branch)
+            })
+            .collect();
+
+        let current = local_branches(&git.repo())?;
+        let mut changed: Vec<&str> = moved
+            .iter()
+            .filter(|branch| {
+                current.get(**branch) != after.branches.get(**branch)
+            })
+            .map(|branch| branch_name(branch))
+            .collect();
+        if Checkpoint::capture(git).as_ref() != Some(&after.head) {
+            changed.insert(0, "HEAD");
+        }
+        if !changed.is_empty() {
+            return Err(Error::new(format!(
+                "{} changed since spr {}, so it cannot be undone",
+                changed.join(", "),
+                self.command
+            )));
+        }
+
+        let mut done = if before.head != after.head {
+            before.head.restore(git).await?
+        } else {
+            Vec::new()
+        };
+
+        let repo = git.repo();
+        for branch in moved {
+            if before.head.branch.as_ref() == Some(branch) {
+                continue;
+            }
+            match (before.branches.get(branch), after.branches.get(branch)) {
+                (Some(&oid), Some(_)) => {
+                    repo.reference(branch, oid, true, "spr undo")?;
+                    done.push(format!(
+                        "Reset branch {} to {}",
+                        branch_name(branch),
+                        short_id(&repo, oid)?
+                    ));
+                }
+                (Some(&oid), None) => {
+                    repo.reference(branch, oid, true, "spr undo")?;
+                    done.push(format!(
+                        "Recreated branch {} at {}",
+                        branch_name(branch),
+                        short_id(&repo, oid)?
+                    ));
+                }
+                (None, Some(&oid)) => {
+                    repo.find_reference(branch)?.delete()?;
+                    done.push(format!(
+                        "Deleted branch {} (it was at {})",
+                        branch_name(branch),
+                        short_id(&repo, oid)?
+                    ));
+                }
+                (None, None) => (),
+            }
+        }
+
+        Ok(done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::{create_commit_with_files, test_repo};
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Snapshot::load(dir.path()).unwrap(), None);
+
+        let oid = |n: u8| Oid::from_bytes(&[n; 20]).unwrap();
+        let mut snapshot = Snapshot {
+            command: "diff".to_string(),
+            before: Refs {
+                head: Checkpoint {
+                    branch: Some("refs/heads/feature".to_string()),
+                    oid: oid(1),
+                },
+                branches: [
+                    ("refs/heads/feature".to_string(), oid(1)),
+                    ("refs/heads/master".to_string(), oid(2)),
+                ]
+                .into_iter()
+                .collect(),
+            },
+            after: None,
+            remote_steps: vec![
+                "Pushed branch spr/foo/one".to_string(),
+                "Created Pull Request #12".to_string(),
+            ],
+        };
+        snapshot.save(dir.path()).unwrap();
+        assert_eq!(
+            Snapshot::load(dir.path()).unwrap().as_ref(),
+            Some(&snapshot)
+        );
+
+        // After the command, with a detached HEAD
+        snapshot.after = Some(Refs {
+            head: Checkpoint {
+                branch: None,
+                oid: oid(3),
+            },
+            branches: [("refs/heads/master".to_string(), oid(3))]
+                .into_iter()
+                .collect(),
+        });
+        snapshot.save(dir.path()).unwrap();
+        assert_eq!(Snapshot::load(dir.path()).unwrap(), Some(snapshot));
+
+        Snapshot::remove(dir.path()).unwrap();
+        assert_eq!(Snapshot::load(dir.path()).unwrap(), None);
+        Snapshot::remove(dir.path()).unwrap();
+
+        std::fs::write(dir.path().join(SNAPSHOT_PATH), "what\n").unwrap();
+        assert!(Snapshot::load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_capture() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let other =
+            create_commit_with_files(&git, "Other", &[root], &[("a", "a")]);
+        git.repo()
+            .reference("refs/heads/other", other, true, "test")
+            .unwrap();
+
+        let snapshot = Snapshot::capture(&git, "land").unwrap().unwrap();
+        assert_eq!(snapshot.command, "land");
+        assert_eq!(
+            snapshot.before.head,
+            Checkpoint {
+                branch: Some("refs/heads/master".to_string()),
+                oid: root,
+            }
+        );
+        assert_eq!(
+            snapshot.before.branches.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("refs/heads/master".to_string(), root),
+                ("refs/heads/other".to_string(), other),
+            ]
+        );
+        assert_eq!(snapshot.after, None);
+        assert!(snapshot.remote_steps.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit_with_files(&git, "One", &[root], &[("a", "1")]);
+        let two = create_commit_with_files(&git, "Two", &[root], &[("a", "2")]);
+        {
+            let repo = git.repo();
+            repo.reference("refs/heads/moved", one, true, "test")
+                .unwrap();
+            repo.reference("refs/heads/deleted", one, true, "test")
+                .unwrap();
+        }
+        let mut snapshot = Snapshot::capture(&git, "diff").unwrap().unwrap();
+        // Nothing to go by until the command is done
+        assert!(snapshot.restore(&git).await.is_err());
+
+        // As if the command rewrote HEAD's branch and another one, deleted
+        // one and created one
+        {
+            let repo = git.repo();
+            repo.reference("refs/heads/master", two, true, "test")
+                .unwrap();
+            repo.reference("refs/heads/moved", two, true, "test")
+                .unwrap();
+            repo.find_reference("refs/heads/deleted")
+                .unwrap()
+                .delete()
+                .unwrap();
+            repo.reference("refs/heads/created", two, true, "test")
+                .unwrap();
+        }
+        snapshot.record_after(&git).unwrap();
+
+        let done = snapshot.restore(&git).await.unwrap();
+        assert_eq!(done.len(), 4);
+        assert!(done[0].starts_with("Put HEAD back on master at "));
+        assert!(done[1].starts_with("Deleted branch created (it was at "));
+        assert!(done[2].starts_with("Recreated branch deleted at "));
+        assert!(done[3].starts_with("Reset branch moved to "));
+        assert_eq!(
+            Refs::capture(&git).unwrap().as_ref(),
+            Some(&snapshot.before)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_keeps_branches_created_since() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit_with_files(&git, "One", &[root], &[("a", "1")]);
+        let mut snapshot = Snapshot::capture(&git, "diff").unwrap().unwrap();
+        git.repo()
+            .reference("refs/heads/master", one, true, "test")
+            .unwrap();
+        snapshot.record_after(&git).unwrap();
+
+        // The user creates a branch after the command
+        git.repo()
+            .reference("refs/heads/mine", one, true, "test")
+            .unwrap();
+
+        let done = snapshot.restore(&git).await.unwrap();
+        assert_eq!(done.len(), 1);
+        assert!(done[0].starts_with("Put HEAD back on master at "));
+        assert_eq!(git.resolve_reference("refs/heads/master").unwrap(), root);
+        assert_eq!(git.resolve_reference("refs/heads/mine").unwrap(), one);
+    }
+
+    #[tokio::test]
+    async fn test_restore_refuses_after_changes() {
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+        let one = create_commit_with_files(&git, "One", &[root], &[("a", "1")]);
+        let two = create_commit_with_files(&git, "Two", &[root], &[("a", "2")]);
+        git.repo()
+            .reference("refs/heads/other", root, true, "test")
+            .unwrap();
+        let mut snapshot = Snapshot::capture(&git, "diff").unwrap().unwrap();
+        git.repo()
+            .reference("refs/heads/other", one, true, "test")
+            .unwrap();
+        snapshot.record_after(&git).unwrap();
+
+        // The user moves the branch the command moved
+        git.repo()
+            .reference("refs/heads/other", two, true, "test")
+            .unwrap();
+
+        let error = snapshot.restore(&git).await.unwrap_err();
+        assert_eq!(
+            error.messages(),
+            &["other changed since spr diff, so it cannot be undone"]
+        );
+        assert_eq!(git.resolve_reference("refs/heads/other").unwrap(), two);
+    }
+}
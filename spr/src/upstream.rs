@@ -0,0 +1,143 @@
+/*
+ * Copyright (c) Radical HQ Limited
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A record, kept in the Git directory, of the base that the stack of each
+//! local branch was submitted on top of, as `spr diff --set-upstream` found
+//! it. `spr diff` compares it with where the base is now to tell when the
+//! stack needs a rebase, even after switching the default branch, for
+//! example.
+
+use std::{collections::BTreeMap, path::Path};
+
+use git2::Oid;
+
+use crate::error::{Error, Result, ResultExt};
+
+/// Where in the Git directory the record lives.
+const UPSTREAMS_PATH: &str = "spr/upstreams";
+
+/// The base of a stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    /// The full name of the reference the stack is based on (e.g.
+    /// `refs/remotes/origin/master`)
+    pub base_ref: String,
+    /// The commit that the reference was at
+    pub oid: Oid,
+}
+
+/// The upstreams of all branches, by local branch name.
+fn load_all(git_dir: &Path) -> Result<BTreeMap<String, Upstream>> {
+    let path = git_dir.join(UPSTREAMS_PATH);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            String::new()
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.split(' ');
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(branch), Some(oid), Some(base_ref), None) => Ok((
+                    branch.to_string(),
+                    Upstream {
+                        base_ref: base_ref.to_string(),
+                        oid: Oid::from_str(oid)?,
+                    },
+                )),
+                _ => Err(Error::new(format!("Invalid line '{}'", line))),
+            }
+        })
+        .collect::<Result<_>>()
+        .context(format!(
+            "Could not read {}; delete it, and run spr diff --set-upstream \
+             again",
+            path.display()
+        ))
+}
+
+/// The recorded upstream of the given local branch, if there is one.
+pub fn load(git_dir: &Path, branch: &str) -> Result<Option<Upstream>> {
+    Ok(load_all(git_dir)?.remove(branch))
+}
+
+/// Record the upstream of the given local branch, replacing the one recorded
+/// before.
+pub fn record(git_dir: &Path, branch: &str, upstream: &Upstream) -> Result<()> {
+    let mut upstreams = load_all(git_dir)?;
+    upstreams.insert(branch.to_string(), upstream.clone());
+
+    let path = git_dir.join(UPSTREAMS_PATH);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let text: String = upstreams
+        .iter()
+        .map(|(branch, upstream)| {
+            format!("{} {} {}\n", branch, upstream.oid, upstream.base_ref)
+        })
+        .collect();
+    std::fs::write(&path, text)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(base_ref: &str, n: u8) -> Upstream {
+        Upstream {
+            base_ref: base_ref.to_string(),
+            oid: Oid::from_bytes(&[n; 20]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(load(dir.path(), "feature").unwrap(), None);
+
+        record(
+            dir.path(),
+            "feature",
+            &upstream("refs/remotes/origin/master", 1),
+        )
+        .unwrap();
+        record(dir.path(), "other", &upstream("refs/tags/v1.2", 2)).unwrap();
+        record(
+            dir.path(),
+            "feature",
+            &upstream("refs/remotes/origin/main", 3),
+        )
+        .unwrap();
+
+        assert_eq!(
+            load(dir.path(), "feature").unwrap(),
+            Some(upstream("refs/remotes/origin/main", 3))
+        );
+        assert_eq!(
+            load(dir.path(), "other").unwrap(),
+            Some(upstream("refs/tags/v1.2", 2))
+        );
+        assert_eq!(load(dir.path(), "master").unwrap(), None);
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("spr")).unwrap();
+        std::fs::write(dir.path().join(UPSTREAMS_PATH), "feature 1234\n")
+            .unwrap();
+
+        assert!(load(dir.path(), "feature").is_err());
+    }
+}
@@ -7,18 +7,37 @@
 
 use crate::{
     error::{Error, Result},
-    message,
+    git, message,
 };
 
+use futures::StreamExt;
 use git2::Oid;
-use std::{io::Write, process::Stdio};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io::Write,
+    process::Stdio,
+};
 use unicode_normalization::UnicodeNormalization;
 
 pub fn slugify(s: &str) -> String {
-    s.trim()
+    slugify_with(s, false)
+}
+
+/// Like `slugify`, but optionally keeping dots, so that e.g. version numbers
+/// in `s` survive. Runs of dots are collapsed and dots at either end are
+/// removed, like dashes.
+pub fn slugify_with(s: &str, keep_dots: bool) -> String {
+    let slug: String = s
+        .trim()
         .nfd()
         .map(|c| if c.is_whitespace() { '-' } else { c })
-        .filter(|c| c.is_ascii_alphanumeric() || c == &'_' || c == &'-')
+        .filter(|c| {
+            c.is_ascii_alphanumeric()
+                || c == &'_'
+                || c == &'-'
+                || (keep_dots && c == &'.')
+        })
         .map(|c| char::to_ascii_lowercase(&c))
         .scan(None, |last_char, char| {
             if char == '-' && last_char == &Some('-') {
@@ -29,9 +48,112 @@ pub fn slugify(s: &str) -> String {
             }
         })
         .flatten()
+        .collect();
+
+    sanitize_ref_component(&slug)
+}
+
+/// Turn `text` into something that can be used as a single slash-separated
+/// component of a Git ref name.
+///
+/// Git refuses ref names that contain `..`, that have components starting
+/// with a dot or ending with `.lock`, and a number of special characters (see
+/// `git check-ref-format`). On top of that, branches are stored as files in
+/// `.git/refs` and thus must not use names reserved on Windows (like `con` or
+/// `nul`). This function strips or replaces all of those. The result may be
+/// empty, in which case it is up to the caller to choose a fallback.
+pub fn sanitize_ref_component(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        let c = if c.is_whitespace() { '-' } else { c };
+        if c.is_control() || "~^:?*[\\/<>\"|@{}".contains(c) {
+            continue;
+        }
+        // Collapse runs of dots and dashes
+        if (c == '.' || c == '-') && result.ends_with(c) {
+            continue;
+        }
+        result.push(c);
+    }
+
+    loop {
+        let trimmed = result.trim_matches(|c| c == '.' || c == '-');
+        if let Some(stripped) = trimmed.strip_suffix(".lock") {
+            result = format!("{}-lock", stripped);
+        } else if trimmed.len() != result.len() {
+            result = trimmed.to_string();
+        } else {
+            break;
+        }
+    }
+
+    let device_name = result.split('.').next().unwrap_or("");
+    let is_reserved = matches!(
+        &device_name.to_ascii_lowercase()[..],
+        "con" | "prn" | "aux" | "nul"
+    ) || lazy_regex::regex_is_match!(
+        r#"^(?i)(com|lpt)[0-9]$"#,
+        device_name
+    );
+    if is_reserved {
+        result.insert(0, '_');
+    }
+
+    result
+}
+
+/// Replace the typographic characters that text pasted from documents tends
+/// to have with plain ones: curly quotes become straight quotes, dashes
+/// (en, em and the like) become hyphens, non-breaking spaces become spaces,
+/// and ellipses are dropped. `slugify` would drop all of these, running the
+/// words on either side of a dash together.
+pub fn normalize_typography(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => Some('\''),
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' => Some('"'),
+            '\u{2010}'..='\u{2015}' | '\u{2212}' => Some('-'),
+            '\u{00A0}' | '\u{202F}' => Some(' '),
+            '\u{2026}' => None,
+            c => Some(c),
+        })
         .collect()
 }
 
+/// Remove a leading emoji (like `🚀`) or gitmoji shortcode (like `:rocket:`)
+/// and the whitespace following it from `text`. Emoji further into the text
+/// are left alone. If there is nothing but the emoji, `text` is returned
+/// unchanged.
+pub fn strip_leading_emoji(text: &str) -> &str {
+    let rest = if let Some(shortcode) =
+        lazy_regex::regex_find!(r#"^:[a-z0-9_+\-]+:(\s|$)"#, text)
+    {
+        &text[shortcode.len()..]
+    } else {
+        text.trim_start_matches(is_emoji_char)
+    };
+
+    let rest = rest.trim_start();
+    if rest.is_empty() {
+        text
+    } else {
+        rest
+    }
+}
+
+fn is_emoji_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1F000}'..='\u{1FAFF}'
+            | '\u{2600}'..='\u{27BF}'
+            | '\u{2B00}'..='\u{2BFF}'
+            | '\u{2300}'..='\u{23FF}'
+            | '\u{FE0F}'
+            | '\u{200D}'
+            | '\u{20E3}'
+    )
+}
+
 pub fn parse_name_list(text: &str) -> Vec<String> {
     lazy_regex::regex!(r#"\(.*?\)"#)
         .replace_all(text, ",")
@@ -42,6 +164,77 @@ pub fn parse_name_list(text: &str) -> Vec<String> {
         .collect()
 }
 
+/// A name that stands for a list of reviewers (users, or teams with a leading
+/// `#`), as configured in `spr.reviewerAlias`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReviewerAlias {
+    pub name: String,
+    pub reviewers: Vec<String>,
+}
+
+impl std::str::FromStr for ReviewerAlias {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some((name, reviewers))
+                if !name.trim().is_empty()
+                    && !parse_name_list(reviewers).is_empty() =>
+            {
+                Ok(Self {
+                    name: name.trim().to_string(),
+                    reviewers: parse_name_list(reviewers),
+                })
+            }
+            _ => Err(Error::new(format!(
+                "Invalid value for spr.reviewerAlias: '{}' (must be \
+                 'NAME=REVIEWERS', e.g. 'frontend=#frontend, alice, bob')",
+                s
+            ))),
+        }
+    }
+}
+
+/// Replace each name in `names` that is an alias (compared
+/// case-insensitively) with the reviewers it stands for. Other names are kept
+/// as they are. Aliases are not expanded recursively.
+pub fn expand_reviewer_aliases(
+    names: Vec<String>,
+    aliases: &[ReviewerAlias],
+) -> Vec<String> {
+    names
+        .into_iter()
+        .flat_map(|name| {
+            // Later definitions override earlier ones, as usual in Git config
+            match aliases
+                .iter()
+                .rev()
+                .find(|alias| alias.name.eq_ignore_ascii_case(&name))
+            {
+                Some(alias) => alias.reviewers.clone(),
+                None => vec![name],
+            }
+        })
+        .collect()
+}
+
+/// Remove repeated names from `names`, keeping the first occurrence. Names are
+/// compared case-insensitively, as GitHub does for logins and team slugs.
+pub fn dedup_names(names: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    names
+        .into_iter()
+        .filter(|name| seen.insert(name.to_lowercase()))
+        .collect()
+}
+
+/// Sort `names` case-insensitively, so that the same names come out in the
+/// same order however they were given. Names that differ only in case are
+/// ordered by their exact spelling.
+pub fn sort_names(names: &mut [String]) {
+    names.sort_by_cached_key(|name| (name.to_lowercase(), name.clone()));
+}
+
 /*
  * Given a PR stack string that looks like:
  *
@@ -51,19 +244,116 @@ pub fn parse_name_list(text: &str) -> Vec<String> {
  * https://github.com/mk1123/spr/pull/3
  * ```
  *
- * Returns a vector of PR numbers.
+ * Returns a vector of PR numbers. The URLs may also be the targets of
+ * Markdown links (like `[#1](https://github.com/mk1123/spr/pull/1)`), as
+ * other tools rewriting the PR stack sometimes turn them into those.
+ *
+ * Since anyone can edit Pull Request descriptions, the text may be anything.
+ * Lines too long to be an entry are skipped without looking at them, and
+ * at most `MAX_PR_STACK_ENTRIES` numbers are taken.
  */
 pub fn parse_pr_stack_list(text: &str) -> Vec<u64> {
+    parse_pr_stack_entries(text)
+        .into_iter()
+        .map(|(number, _)| number)
+        .collect()
+}
+
+/// The Pull Requests that a PR Stack section lists, like
+/// [`parse_pr_stack_list`], each with whether it is marked as merged (see
+/// [`message::MERGED_PR_MARKER`]).
+pub fn parse_pr_stack_entries(text: &str) -> Vec<(u64, bool)> {
     text.lines()
+        .filter(|line| line.len() <= MAX_PR_STACK_LINE_LENGTH)
         .filter_map(|line| {
-            line.split_whitespace()
-                .next()
-                .and_then(|url| url.split('/').last())
-                .and_then(|num| num.parse().ok())
+            let (url, rest) = match lazy_regex::regex_captures!(
+                r#"^\s*\[[^\]]*\]\(([^)\s]+)\)(.*)$"#,
+                line
+            ) {
+                Some((_, url, rest)) => (url, rest),
+                None => {
+                    let line = line.trim_start();
+                    line.split_once(char::is_whitespace).unwrap_or((line, ""))
+                }
+            };
+
+            // Only take Pull Request URLs, so that other lines (like a
+            // configured heading) are never mistaken for one
+            let number = lazy_regex::regex_captures!(r#"/pull/(\d+)$"#, url)
+                .and_then(|(_, num)| num.parse().ok())?;
+            let merged = rest
+                .split_whitespace()
+                .any(|word| word == message::MERGED_PR_MARKER);
+            Some((number, merged))
         })
+        .take(MAX_PR_STACK_ENTRIES)
         .collect()
 }
 
+/// The longest line of a PR Stack section that can be an entry: a URL with a
+/// Markdown link around it and some text after it is much shorter.
+const MAX_PR_STACK_LINE_LENGTH: usize = 1024;
+
+/// The most Pull Requests read from a PR Stack section.
+pub const MAX_PR_STACK_ENTRIES: usize = 1000;
+
+/// Replace each `{name}` in `template` with the value of that name in
+/// `values`. `{{` and `}}` stand for literal braces. A placeholder that
+/// `values` has no value for is an error.
+pub fn expand_template(
+    template: &str,
+    values: &[(&str, &str)],
+) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(index) = rest.find(['{', '}']) {
+        result.push_str(&rest[..index]);
+        let brace = &rest[index..index + 1];
+        rest = &rest[index + 1..];
+
+        if rest.starts_with(brace) {
+            // An escaped brace
+            result.push_str(brace);
+            rest = &rest[1..];
+            continue;
+        }
+        if brace == "}" {
+            return Err(Error::new(
+                "Unmatched '}' in template (use '}}' for a literal brace)",
+            ));
+        }
+
+        let end = rest.find('}').ok_or_else(|| {
+            Error::new(
+                "Unterminated placeholder in template (use '{{' for a \
+                 literal brace)",
+            )
+        })?;
+        let name = &rest[..end];
+        let value = values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                Error::new(format!(
+                    "Unknown placeholder {{{}}} in template (known ones are \
+                     {})",
+                    name,
+                    values
+                        .iter()
+                        .map(|(key, _)| format!("{{{}}}", key))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })?;
+        result.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
 pub fn remove_all_parens(text: &str) -> String {
     lazy_regex::regex!(r#"[()]"#).replace_all(text, "").into()
 }
@@ -84,29 +374,265 @@ pub async fn run_command(cmd: &mut tokio::process::Command) -> Result<()> {
     Ok(())
 }
 
+/// Run the command like [`run_command`], with `input` written to its standard
+/// input, which is closed after it. The input is written while the output of
+/// the command is read, so that a command that writes a lot before it has
+/// read all of its input does not get stuck.
+pub async fn run_command_with_stdin(
+    cmd: &mut tokio::process::Command,
+    input: &[u8],
+) -> Result<()> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let write = async move {
+        let written =
+            tokio::io::AsyncWriteExt::write_all(&mut stdin, input).await;
+        // Closing stdin tells the command that there is no more input
+        drop(stdin);
+        match written {
+            // The command not reading all of its input is up to the command
+            Err(error) if error.kind() == std::io::ErrorKind::BrokenPipe => {
+                Ok(())
+            }
+            written => written,
+        }
+    };
+    let (written, cmd_output) = tokio::join!(write, child.wait_with_output());
+    let cmd_output = cmd_output?;
+
+    if !cmd_output.status.success() {
+        console::Term::stderr().write_all(&cmd_output.stderr)?;
+        return Err(Error::new("command failed"));
+    }
+    written?;
+
+    Ok(())
+}
+
+/// Run the given futures with at most `concurrency` of them in flight at any
+/// time, and return their results in the order of `futures`.
+pub async fn run_bounded<T>(
+    concurrency: usize,
+    futures: impl IntoIterator<Item = impl Future<Output = T>>,
+) -> Vec<T> {
+    futures::stream::iter(futures)
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
 pub fn get_pr_stack(
     git: &crate::git::Git,
     config: &crate::config::Config,
     pull_request_number: u64,
+    oid: Oid,
     parent_oid: Oid,
     cherry_pick: bool,
     directly_based_on_master: bool,
 ) -> Result<String> {
-    if cherry_pick || directly_based_on_master {
-        Ok(message::build_pr_stack_message(
-            &vec![pull_request_number],
-            &config.owner,
-            &config.repo,
-        ))
+    let standalone = cherry_pick || directly_based_on_master;
+    let parsed_stack = if standalone {
+        Vec::new()
     } else {
-        let mut pr_stack = git.parse_pr_stack_from_commit(parent_oid)?;
+        git.parse_pr_stack_from_commit(parent_oid)?
+    };
+    // Pull Requests the stack below keeps as merged stay so
+    let merged: HashSet<u64> = parsed_stack
+        .iter()
+        .filter(|(_, merged)| *merged)
+        .map(|(number, _)| *number)
+        .collect();
+
+    build_stack_from_parsed(
+        parsed_stack.into_iter().map(|(number, _)| number).collect(),
+        &merged,
+        pull_request_number,
+        standalone,
+        config,
+        |pr_stack| get_stack_diffstats(git, config, pr_stack, oid, parent_oid),
+    )
+}
+
+/// The PR Stack section of the given Pull Request, given the stack of the
+/// commit below it as parsed already (from its message). A `standalone` Pull
+/// Request (cherry-picked, or based on master) starts a stack of its own.
+/// The Pull Requests in `merged` are merged already. `diffstats` is only
+/// asked for the sizes of the Pull Requests in the stack if the PR Stack shows
+/// them.
+pub fn build_stack_from_parsed(
+    parsed_stack: Vec<u64>,
+    merged: &HashSet<u64>,
+    pull_request_number: u64,
+    standalone: bool,
+    config: &crate::config::Config,
+    diffstats: impl FnOnce(&[u64]) -> Result<HashMap<u64, git::DiffStat>>,
+) -> Result<String> {
+    let pr_stack = if standalone {
+        vec![pull_request_number]
+    } else {
+        let mut pr_stack = parsed_stack;
         pr_stack.insert(0, pull_request_number);
-        Ok(message::build_pr_stack_message(
-            &pr_stack,
-            &config.owner,
-            &config.repo,
-        ))
+        pr_stack
+    };
+
+    let details = message::PRStackDetails {
+        diffstats: if config.pr_stack_format.diffstat {
+            diffstats(&pr_stack)?
+        } else {
+            HashMap::new()
+        },
+        merged: merged.clone(),
+    };
+
+    Ok(message::build_pr_stack_message(&pr_stack, &details, config))
+}
+
+/// The lines added and deleted by the commit of each Pull Request in the
+/// stack, i.e. `oid` for the first one, and the commits below it for the rest,
+/// each compared to its parent. Walking down stops at the first commit that
+/// does not belong to the stack, so Pull Requests further down it (e.g.
+/// because they are landed already) get no numbers.
+fn get_stack_diffstats(
+    git: &crate::git::Git,
+    config: &crate::config::Config,
+    pr_stack: &[u64],
+    oid: Oid,
+    parent_oid: Oid,
+) -> Result<HashMap<u64, git::DiffStat>> {
+    let mut diffstats = HashMap::new();
+    diffstats.insert(pr_stack[0], git.diff_stat(parent_oid, oid)?);
+
+    let mut oid = parent_oid;
+    // A merge or root commit cannot be the commit of a Pull Request
+    while let Ok(commit) = git.prepare_commit(config, oid) {
+        match commit.pull_request_number {
+            Some(number)
+                if pr_stack.contains(&number)
+                    && !diffstats.contains_key(&number) =>
+            {
+                diffstats
+                    .insert(number, git.diff_stat(commit.parent_oid, oid)?);
+                oid = commit.parent_oid;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(diffstats)
+}
+
+/// A line in the difference between two texts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// The lines of `old` and `new`, in order, marked as being in both, only in
+/// `old` or only in `new`. The lines in both are a longest common subsequence,
+/// and removed lines come before the added lines that replace them.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len()
+            || (i < old.len() && common[i + 1][j] >= common[i][j + 1])
+        {
+            lines.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// The difference between `old` and `new` as a unified diff (like `diff -u`
+/// prints, without the file names): hunks of changed lines, each with up to
+/// `context` unchanged lines around it and a `@@ -l,n +l,n @@` header. Empty
+/// if the texts have the same lines.
+pub fn unified_diff(old: &str, new: &str, context: usize) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let lines = diff_lines(&old, &new);
+
+    let changes: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+        .map(|(index, _)| index)
+        .collect();
+
+    // Changes that are close enough for their context to touch go into the
+    // same hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &index in &changes {
+        let start = index.saturating_sub(context);
+        let end = (index + context + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = Vec::new();
+    for (start, end) in hunks {
+        let count = |range: &[DiffLine], in_old: bool| {
+            range
+                .iter()
+                .filter(|line| match line {
+                    DiffLine::Same(_) => true,
+                    DiffLine::Removed(_) => in_old,
+                    DiffLine::Added(_) => !in_old,
+                })
+                .count()
+        };
+        let old_before = count(&lines[..start], true);
+        let new_before = count(&lines[..start], false);
+        let old_count = count(&lines[start..end], true);
+        let new_count = count(&lines[start..end], false);
+        // An empty range is given by the line before it, as diff does
+        let first_line =
+            |before: usize, count: usize| before + usize::from(count > 0);
+        diff.push(format!(
+            "@@ -{},{} +{},{} @@",
+            first_line(old_before, old_count),
+            old_count,
+            first_line(new_before, new_count),
+            new_count
+        ));
+        diff.extend(lines[start..end].iter().map(|line| match line {
+            DiffLine::Same(text) => format!(" {}", text),
+            DiffLine::Removed(text) => format!("-{}", text),
+            DiffLine::Added(text) => format!("+{}", text),
+        }));
     }
+
+    diff
 }
 
 #[cfg(test)]
@@ -129,6 +655,52 @@ mod tests {
         assert_eq!(slugify("ĥêlļō ŵöřľď"), "hello-world".to_string());
     }
 
+    fn aliases() -> Vec<ReviewerAlias> {
+        vec![
+            "frontend = #frontend, alice, bob".parse().unwrap(),
+            "oncall=carol".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_parse_reviewer_alias() {
+        assert_eq!(
+            aliases()[0],
+            ReviewerAlias {
+                name: "frontend".to_string(),
+                reviewers: vec![
+                    "#frontend".to_string(),
+                    "alice".to_string(),
+                    "bob".to_string()
+                ],
+            }
+        );
+        assert!("frontend".parse::<ReviewerAlias>().is_err());
+        assert!("frontend = ".parse::<ReviewerAlias>().is_err());
+        assert!(" = alice".parse::<ReviewerAlias>().is_err());
+    }
+
+    #[test]
+    fn test_expand_reviewer_aliases() {
+        let names = |names: &[&str]| -> Vec<String> {
+            names.iter().map(|s| s.to_string()).collect()
+        };
+
+        assert_eq!(
+            expand_reviewer_aliases(
+                names(&["Frontend", "dave", "oncall"]),
+                &aliases()
+            ),
+            names(&["#frontend", "alice", "bob", "dave", "carol"])
+        );
+        // Names that are not aliases are left alone
+        assert_eq!(
+            expand_reviewer_aliases(names(&["#backend", "erin"]), &aliases()),
+            names(&["#backend", "erin"])
+        );
+        assert!(expand_reviewer_aliases(Vec::new(), &aliases()).is_empty());
+    }
+
     #[test]
     fn test_parse_name_list_empty() {
         assert!(parse_name_list("").is_empty());
@@ -214,4 +786,527 @@ mod tests {
             vec![1, 2, 3]
         );
     }
+
+    #[test]
+    fn test_parse_pr_stack_list_adversarial_input() {
+        // A megabyte-long line, before and after an entry
+        let long = format!("https://x/pull/{}", "1".repeat(1 << 20));
+        assert_eq!(
+            parse_pr_stack_list(&format!(
+                "{}\nhttps://github.com/mk1123/spr/pull/7\n{}",
+                long, long
+            )),
+            vec![7]
+        );
+        // Deeply nested brackets and parentheses
+        let nested = format!(
+            "{}https://github.com/mk1123/spr/pull/1{}",
+            "[(".repeat(400),
+            ")]".repeat(400)
+        );
+        assert!(parse_pr_stack_list(&nested).is_empty());
+        // Numbers too large for a Pull Request, and multi-byte characters
+        assert!(parse_pr_stack_list(
+            "https://github.com/mk1123/spr/pull/99999999999999999999999\n\
+             [ü](https://github.com/mk1123/spr/pull/ü)\n\
+             [\u{1F600}](\u{1F600}/pull/\u{1F600}"
+        )
+        .is_empty());
+        // Not more than so many entries
+        let many = "https://github.com/mk1123/spr/pull/1\n".repeat(5000);
+        assert_eq!(parse_pr_stack_list(&many).len(), MAX_PR_STACK_ENTRIES);
+    }
+
+    #[test]
+    fn test_parse_pr_stack_list_markdown_links() {
+        assert_eq!(
+            parse_pr_stack_list(
+                "[#1](https://github.com/mk1123/spr/pull/1) <-- (current PR)\n\
+                 https://github.com/mk1123/spr/pull/2\n\
+                 [PR 3](https://github.com/mk1123/spr/pull/3)\n\
+                 https://github.com/mk1123/spr/pull/4 (some extra text)"
+            ),
+            vec![1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_expand_template() {
+        let values = [("pr_title", "Fix it"), ("pr_number", "12")];
+
+        assert_eq!(
+            expand_template("{pr_title} (#{pr_number})", &values).unwrap(),
+            "Fix it (#12)"
+        );
+        assert_eq!(
+            expand_template("{pr_number}{pr_number}", &values).unwrap(),
+            "1212"
+        );
+        assert_eq!(
+            expand_template("{{pr_title}} is {pr_title}", &values).unwrap(),
+            "{pr_title} is Fix it"
+        );
+        assert_eq!(
+            expand_template("No placeholders", &values).unwrap(),
+            "No placeholders"
+        );
+    }
+
+    #[test]
+    fn test_expand_template_errors() {
+        let values = [("pr_title", "Fix it"), ("pr_number", "12")];
+
+        assert_eq!(
+            expand_template("{pr_titel}", &values)
+                .unwrap_err()
+                .messages(),
+            &vec!["Unknown placeholder {pr_titel} in template (known ones \
+                   are {pr_title}, {pr_number})"
+                .to_string()]
+        );
+        assert!(expand_template("{pr_title", &values).is_err());
+        assert!(expand_template("pr_title}", &values).is_err());
+    }
+
+    #[test]
+    fn test_slugify_never_yields_invalid_refs() {
+        assert_eq!(slugify("Update foo.lock"), "update-foolock");
+        assert_eq!(
+            slugify("-- leading and trailing --"),
+            "leading-and-trailing"
+        );
+        assert_eq!(slugify("!!!"), "");
+        assert_eq!(slugify("..."), "");
+        assert_eq!(slugify("CON"), "_con");
+        assert_eq!(slugify("lpt1"), "_lpt1");
+        assert_eq!(slugify("console"), "console");
+    }
+
+    #[test]
+    fn test_slugify_keeping_dots() {
+        assert_eq!(slugify("Bump to 1.2.3"), "bump-to-123");
+        assert_eq!(slugify_with("Bump to 1.2.3", false), "bump-to-123");
+        assert_eq!(slugify_with("Bump to 1.2.3", true), "bump-to-1.2.3");
+        assert_eq!(slugify_with("1..2...3", true), "1.2.3");
+        assert_eq!(slugify_with(".hidden. thing.", true), "hidden.-thing");
+        assert_eq!(slugify_with("Update foo.lock", true), "update-foo-lock");
+        assert_eq!(slugify_with("...", true), "");
+    }
+
+    #[test]
+    fn test_sanitize_ref_component() {
+        assert_eq!(sanitize_ref_component("foo.lock"), "foo-lock");
+        assert_eq!(sanitize_ref_component("foo.lock."), "foo-lock");
+        assert_eq!(sanitize_ref_component("foo..bar"), "foo.bar");
+        assert_eq!(sanitize_ref_component("."), "");
+        assert_eq!(sanitize_ref_component(".."), "");
+        assert_eq!(sanitize_ref_component(".hidden"), "hidden");
+        assert_eq!(sanitize_ref_component("a@{b}"), "ab");
+        assert_eq!(sanitize_ref_component("a/b:c?d*e"), "abcde");
+        assert_eq!(sanitize_ref_component("x<y>\"|z"), "xyz");
+        assert_eq!(sanitize_ref_component("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_ref_component("v1.2.3"), "v1.2.3");
+    }
+
+    #[test]
+    fn test_normalize_typography() {
+        assert_eq!(
+            normalize_typography("Don\u{2019}t \u{201C}fix\u{201D} it"),
+            "Don't \"fix\" it"
+        );
+        assert_eq!(
+            normalize_typography("Parser\u{2014}again, 1\u{2013}2"),
+            "Parser-again, 1-2"
+        );
+        assert_eq!(normalize_typography("Wait for it\u{2026}"), "Wait for it");
+        assert_eq!(
+            normalize_typography("Plain 'text' - as is"),
+            "Plain 'text' - as is"
+        );
+
+        // Without normalizing, the words around the dash run together
+        let title = "Use \u{201C}smart\u{201D} quotes\u{2014}everywhere";
+        assert_eq!(slugify(title), "use-smart-quoteseverywhere");
+        assert_eq!(
+            slugify(&normalize_typography(title)),
+            "use-smart-quotes-everywhere"
+        );
+    }
+
+    #[test]
+    fn test_strip_leading_emoji_gitmoji() {
+        assert_eq!(strip_leading_emoji(":rocket: Add feature"), "Add feature");
+        assert_eq!(
+            strip_leading_emoji(":sparkles:  Add feature"),
+            "Add feature"
+        );
+        assert_eq!(strip_leading_emoji(":rocket:"), ":rocket:");
+        assert_eq!(strip_leading_emoji(":rocket:Add"), ":rocket:Add");
+        assert_eq!(strip_leading_emoji("Fix: things"), "Fix: things");
+    }
+
+    #[test]
+    fn test_strip_leading_emoji_unicode() {
+        assert_eq!(strip_leading_emoji("🚀 Add feature"), "Add feature");
+        assert_eq!(strip_leading_emoji("🚀Add feature"), "Add feature");
+        assert_eq!(strip_leading_emoji("⚡️ Speed up"), "Speed up");
+        assert_eq!(strip_leading_emoji("👩‍💻 Refactor"), "Refactor");
+        assert_eq!(strip_leading_emoji("🚀"), "🚀");
+    }
+
+    #[test]
+    fn test_strip_leading_emoji_none() {
+        assert_eq!(strip_leading_emoji("Add feature"), "Add feature");
+        assert_eq!(
+            strip_leading_emoji("Add feature 🚀 :rocket:"),
+            "Add feature 🚀 :rocket:"
+        );
+        assert_eq!(strip_leading_emoji(""), "");
+    }
+
+    #[tokio::test]
+    async fn test_run_command_with_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("echoed");
+
+        run_command_with_stdin(
+            tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg("cat > \"$0\"")
+                .arg(&path),
+            b"hello\nworld\n",
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\nworld\n");
+
+        // Far more than fits in a pipe, echoed to stderr while it is written
+        let input = vec![b'x'; 1 << 20];
+        run_command_with_stdin(
+            tokio::process::Command::new("sh").arg("-c").arg("cat >&2"),
+            &input,
+        )
+        .await
+        .unwrap();
+
+        assert!(run_command_with_stdin(
+            tokio::process::Command::new("sh").arg("-c").arg("exit 1"),
+            b"ignored",
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_bounded() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let results = run_bounded(
+            3,
+            (0..10u64).map(|i| {
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(now, Ordering::SeqCst);
+                    // Let later futures finish first, to check the order of
+                    // the results
+                    tokio::time::sleep(std::time::Duration::from_millis(
+                        10 - i,
+                    ))
+                    .await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    i
+                }
+            }),
+        )
+        .await;
+
+        assert_eq!(results, (0..10).collect::<Vec<_>>());
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_bounded_zero_concurrency() {
+        let results =
+            run_bounded(0, (0..3).map(|i| async move { i * 2 })).await;
+        assert_eq!(results, vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_get_pr_stack_diffstats() {
+        use crate::git::tests::{create_commit_with_files, test_repo};
+
+        let mut config = crate::config::tests::config_factory();
+        config.pr_stack_format.diffstat = true;
+        let (_dir, git) = test_repo();
+        let root = git.resolve_reference("refs/heads/master").unwrap();
+
+        let landed = create_commit_with_files(
+            &git,
+            "Landed\n\nPull Request: #1\n",
+            &[root],
+            &[("a.txt", "a\n")],
+        );
+        let one = create_commit_with_files(
+            &git,
+            "One\n\nPull Request: #2\n",
+            &[landed],
+            &[("a.txt", "a\nb\nc\n")],
+        );
+        let two = create_commit_with_files(
+            &git,
+            "Two\n\n\
+             PR Stack:\n\
+             https://github.com/acme/codez/pull/3\n\
+             https://github.com/acme/codez/pull/2\n\
+             https://github.com/acme/codez/pull/1\n\n\
+             Pull Request: #3\n",
+            &[one],
+            &[("a.txt", "a\nc\n"), ("b.txt", "x\ny\n")],
+        );
+        let three = create_commit_with_files(
+            &git,
+            "Three\n",
+            &[two],
+            &[("a.txt", "a\nc\n"), ("b.txt", "x\ny\n"), ("c.txt", "z\n")],
+        );
+        // As `git diff --numstat` counts them
+        assert_eq!(
+            git.diff_stat(one, two).unwrap(),
+            git::DiffStat {
+                additions: 2,
+                deletions: 1
+            }
+        );
+
+        let stack =
+            get_pr_stack(&git, &config, 4, three, two, false, false).unwrap();
+        assert_eq!(
+            stack,
+            "https://github.com/acme/codez/pull/4 (+1 \u{2212}0) \
+             <-- (current PR)\n\
+             https://github.com/acme/codez/pull/3 (+2 \u{2212}1)\n\
+             https://github.com/acme/codez/pull/2 (+2 \u{2212}0)\n\
+             https://github.com/acme/codez/pull/1 (+1 \u{2212}0)\n"
+        );
+    }
+
+    #[test]
+    fn test_build_stack_from_parsed() {
+        let mut config = crate::config::tests::config_factory();
+        let no_diffstats = |_: &[u64]| -> Result<HashMap<u64, git::DiffStat>> {
+            panic!("diffstats are not shown")
+        };
+
+        assert_eq!(
+            build_stack_from_parsed(
+                vec![2, 1],
+                &HashSet::new(),
+                3,
+                false,
+                &config,
+                no_diffstats
+            )
+            .unwrap(),
+            message::build_pr_stack_message(
+                &[3, 2, 1],
+                &Default::default(),
+                &config
+            )
+        );
+        // A standalone Pull Request ignores the stack below it
+        assert_eq!(
+            build_stack_from_parsed(
+                vec![2, 1],
+                &HashSet::new(),
+                3,
+                true,
+                &config,
+                no_diffstats
+            )
+            .unwrap(),
+            message::build_pr_stack_message(&[3], &Default::default(), &config)
+        );
+
+        config.pr_stack_format.diffstat = true;
+        let diffstats = HashMap::from([(
+            3,
+            git::DiffStat {
+                additions: 5,
+                deletions: 2,
+            },
+        )]);
+        assert_eq!(
+            build_stack_from_parsed(
+                vec![1],
+                &HashSet::new(),
+                3,
+                false,
+                &config,
+                |stack| {
+                    assert_eq!(stack, &[3, 1]);
+                    Ok(diffstats.clone())
+                }
+            )
+            .unwrap(),
+            message::build_pr_stack_message(
+                &[3, 1],
+                &message::PRStackDetails {
+                    diffstats,
+                    ..Default::default()
+                },
+                &config
+            )
+        );
+    }
+
+    #[test]
+    fn test_pr_stack_keeps_merged() {
+        let mut config = crate::config::tests::config_factory();
+        config.pr_stack_format.footer_keep_merged = true;
+        let no_diffstats = |_: &[u64]| -> Result<HashMap<u64, git::DiffStat>> {
+            panic!("diffstats are not shown")
+        };
+        let merged_two = message::PRStackDetails {
+            merged: HashSet::from([2]),
+            ..Default::default()
+        };
+
+        let text =
+            message::build_pr_stack_message(&[3, 2, 1], &merged_two, &config);
+        assert_eq!(
+            text,
+            "https://github.com/acme/codez/pull/3 <-- (current PR)\n\
+             https://github.com/acme/codez/pull/2 (merged)\n\
+             https://github.com/acme/codez/pull/1\n"
+        );
+        assert_eq!(
+            parse_pr_stack_entries(&text),
+            vec![(3, false), (2, true), (1, false)]
+        );
+        assert_eq!(parse_pr_stack_list(&text), vec![3, 2, 1]);
+
+        // The commit on top keeps the merged Pull Request, still marked
+        let merged = parse_pr_stack_entries(&text)
+            .into_iter()
+            .filter(|(_, merged)| *merged)
+            .map(|(number, _)| number)
+            .collect();
+        assert_eq!(
+            build_stack_from_parsed(
+                parse_pr_stack_list(&text),
+                &merged,
+                4,
+                false,
+                &config,
+                no_diffstats
+            )
+            .unwrap(),
+            "https://github.com/acme/codez/pull/4 <-- (current PR)\n\
+             https://github.com/acme/codez/pull/3\n\
+             https://github.com/acme/codez/pull/2 (merged)\n\
+             https://github.com/acme/codez/pull/1\n"
+        );
+
+        // Otherwise merged Pull Requests are left out
+        config.pr_stack_format.footer_keep_merged = false;
+        assert_eq!(
+            message::build_pr_stack_message(&[3, 2, 1], &merged_two, &config,),
+            "https://github.com/acme/codez/pull/3 <-- (current PR)\n\
+             https://github.com/acme/codez/pull/1\n"
+        );
+        assert_eq!(
+            build_stack_from_parsed(
+                parse_pr_stack_list(&text),
+                &merged,
+                4,
+                false,
+                &config,
+                no_diffstats
+            )
+            .unwrap(),
+            "https://github.com/acme/codez/pull/4 <-- (current PR)\n\
+             https://github.com/acme/codez/pull/3\n\
+             https://github.com/acme/codez/pull/1\n"
+        );
+    }
+
+    #[test]
+    fn test_sort_names() {
+        let expected = vec!["#backend", "alice", "Bob", "carol"];
+        for shuffled in [
+            vec!["carol", "Bob", "#backend", "alice"],
+            vec!["alice", "carol", "#backend", "Bob"],
+            vec!["Bob", "alice", "carol", "#backend"],
+        ] {
+            let mut names: Vec<String> =
+                shuffled.into_iter().map(String::from).collect();
+            sort_names(&mut names);
+            assert_eq!(names, expected);
+        }
+
+        // Names that differ only in case still end up in a fixed order
+        let mut names = vec!["bob".to_string(), "Bob".to_string()];
+        sort_names(&mut names);
+        assert_eq!(names, vec!["Bob", "bob"]);
+    }
+
+    #[test]
+    fn test_dedup_then_sort_names_keeps_first_casing() {
+        let mut names = dedup_names(
+            ["Carol", "alice", "carol", "ALICE", "bob"]
+                .into_iter()
+                .map(String::from),
+        );
+        sort_names(&mut names);
+        assert_eq!(names, vec!["alice", "bob", "Carol"]);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        assert!(unified_diff("a\nb\n", "a\nb\n", 3).is_empty());
+        // Line endings do not matter
+        assert!(unified_diff("a\r\nb\r\n", "a\nb", 3).is_empty());
+
+        assert_eq!(
+            unified_diff(
+                "Summary\n\nOld line\nKept\n",
+                "Summary\n\nNew line\nKept\nAdded\n",
+                1
+            ),
+            vec![
+                "@@ -2,3 +2,4 @@",
+                " ",
+                "-Old line",
+                "+New line",
+                " Kept",
+                "+Added",
+            ]
+        );
+
+        // Changes far apart go into separate hunks
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let new = "0\n1\n2\n3\n4\n5\n6\n7\n";
+        assert_eq!(
+            unified_diff(old, new, 1),
+            vec!["@@ -1,1 +1,2 @@", "+0", " 1", "@@ -7,2 +8,1 @@", " 7", "-8",]
+        );
+
+        // Without context
+        assert_eq!(
+            unified_diff("", "Only\n", 3),
+            vec!["@@ -0,0 +1,1 @@", "+Only"]
+        );
+        assert_eq!(
+            unified_diff("Gone\n", "", 3),
+            vec!["@@ -1,1 +0,0 @@", "-Gone"]
+        );
+    }
 }